@@ -195,6 +195,8 @@ impl_CallFromGuest!(0 => P0, 1 => P1, 2 => P2, 3 => P3, 4 => P4, 5 => P5);
 impl_CallFromGuest!(0 => P0, 1 => P1, 2 => P2, 3 => P3, 4 => P4, 5 => P5, 6 => P6);
 impl_CallFromGuest!(0 => P0, 1 => P1, 2 => P2, 3 => P3, 4 => P4, 5 => P5, 6 => P6, 7 => P7);
 impl_CallFromGuest!(0 => P0, 1 => P1, 2 => P2, 3 => P3, 4 => P4, 5 => P5, 6 => P6, 7 => P7, 8 => P8);
+impl_CallFromGuest!(0 => P0, 1 => P1, 2 => P2, 3 => P3, 4 => P4, 5 => P5, 6 => P6, 7 => P7, 8 => P8, 9 => P9);
+impl_CallFromGuest!(0 => P0, 1 => P1, 2 => P2, 3 => P3, 4 => P4, 5 => P5, 6 => P6, 7 => P7, 8 => P8, 9 => P9, 10 => P10);
 
 /// This trait represents a guest or host function that can be called from host
 /// code, but using the guest ABI. See [CallFromGuest], which this is the
@@ -285,7 +287,7 @@ fn read_next_arg<T: GuestArg>(reg_offset: &mut usize, regs: &[u32], mem: &Mem) -
     // After the fourth register is used, the arguments go on the stack.
     // In some cases the argument is split over both registers and the stack.
 
-    let mut fake_regs = [0u32; 4]; // Rust doesn't allow [0u32; Trait::T] alas.
+    let mut fake_regs = [0u32; 6]; // Rust doesn't allow [0u32; Trait::T] alas.
     let fake_regs = &mut fake_regs[0..T::REG_COUNT];
 
     for fake_reg in fake_regs.iter_mut() {
@@ -331,7 +333,7 @@ pub fn write_next_arg<T: GuestArg>(
     // After the fourth register is used, the arguments go on the stack.
     // In some cases the argument is split over both registers and the stack.
 
-    let mut fake_regs = [0u32; 4]; // Rust doesn't allow [0u32; Trait::T] alas.
+    let mut fake_regs = [0u32; 6]; // Rust doesn't allow [0u32; Trait::T] alas.
     let fake_regs = &mut fake_regs[0..T::REG_COUNT];
     arg.to_regs(fake_regs);
 
@@ -357,6 +359,14 @@ impl VAList {
     pub fn next<T: GuestArg>(&mut self, env: &mut Environment) -> T {
         read_next_arg(&mut self.reg_offset, env.cpu.regs_mut(), &env.mem)
     }
+
+    /// Get the 32-bit word at `index` words after the current position,
+    /// without advancing. This is useful for functions that need random access
+    /// to their arguments, like `printf()` with positional arguments.
+    pub fn peek_word(&self, env: &Environment, index: usize) -> u32 {
+        let mut reg_offset = self.reg_offset + index;
+        read_next_arg(&mut reg_offset, env.cpu.regs(), &env.mem)
+    }
 }
 
 macro_rules! impl_GuestArg_with {
@@ -6,8 +6,8 @@
 //! Audio file decoding and OpenAL bindings.
 //!
 //! The audio file decoding support is an abstraction over various libraries
-//! (currently [caf] and [hound]), usage of which should be confined to this
-//! module.
+//! (currently [caf], [hound] and [symphonia]), usage of which should be
+//! confined to this module.
 //!
 //! Resources:
 //! - [Apple Core Audio Format Specification 1.0](https://developer.apple.com/library/archive/documentation/MusicAudio/Reference/CAFSpec/CAF_intro/CAF_intro.html)
@@ -46,6 +46,103 @@ pub struct AudioFile(AudioFileInner);
 enum AudioFileInner {
     Wave(hound::WavReader<Cursor<Vec<u8>>>),
     Caf(caf::CafPacketReader<Cursor<Vec<u8>>>),
+    /// Compressed formats (AAC, MP3) are decoded in full when opened, and then
+    /// presented as 16-bit little-endian linear PCM. This means the rest of
+    /// touchHLE never has to deal with variable-size packets.
+    Decoded(DecodedAudio),
+}
+
+struct DecodedAudio {
+    sample_rate: u32,
+    channels: u32,
+    /// Interleaved samples.
+    samples: Vec<i16>,
+}
+
+/// Decode a compressed audio file (AAC in an MPEG-4 container, or MP3) to PCM.
+/// `extension` is used as a hint for guessing the container format.
+fn decode_with_symphonia(bytes: Vec<u8>, extension: Option<&str>) -> Result<DecodedAudio, ()> {
+    use symphonia::core::audio::SampleBuffer;
+    use symphonia::core::codecs::{DecoderOptions, CODEC_TYPE_NULL};
+    use symphonia::core::errors::Error;
+    use symphonia::core::formats::FormatOptions;
+    use symphonia::core::io::MediaSourceStream;
+    use symphonia::core::meta::MetadataOptions;
+    use symphonia::core::probe::Hint;
+
+    let stream = MediaSourceStream::new(Box::new(Cursor::new(bytes)), Default::default());
+    let mut hint = Hint::new();
+    if let Some(extension) = extension {
+        hint.with_extension(extension);
+    }
+    let mut reader = symphonia::default::get_probe()
+        .format(
+            &hint,
+            stream,
+            &FormatOptions::default(),
+            &MetadataOptions::default(),
+        )
+        .map_err(|_| ())?
+        .format;
+
+    let track = reader
+        .tracks()
+        .iter()
+        .find(|track| track.codec_params.codec != CODEC_TYPE_NULL)
+        .ok_or(())?;
+    let track_id = track.id;
+    let mut sample_rate = track.codec_params.sample_rate;
+    let mut channels = track.codec_params.channels.map(|c| c.count() as u32);
+    let mut decoder = symphonia::default::get_codecs()
+        .make(&track.codec_params, &DecoderOptions::default())
+        .map_err(|_| ())?;
+
+    let mut samples = Vec::new();
+    loop {
+        let packet = match reader.next_packet() {
+            Ok(packet) => packet,
+            Err(Error::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(_) => return Err(()),
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+        let decoded = match decoder.decode(&packet) {
+            Ok(decoded) => decoded,
+            // A corrupt frame shouldn't prevent the rest from being played.
+            Err(Error::DecodeError(e)) => {
+                log!("Warning: skipping undecodable audio packet: {}", e);
+                continue;
+            }
+            Err(_) => return Err(()),
+        };
+        let spec = *decoded.spec();
+        let sample_rate = *sample_rate.get_or_insert(spec.rate);
+        let channels = *channels.get_or_insert(spec.channels.count() as u32);
+        if spec.rate != sample_rate || spec.channels.count() as u32 != channels {
+            // TODO: resample or remix instead
+            log!(
+                "Warning: skipping audio packet with format {} Hz, {} channels (expected {} Hz, {} channels)",
+                spec.rate,
+                spec.channels.count(),
+                sample_rate,
+                channels
+            );
+            continue;
+        }
+        let mut buffer = SampleBuffer::<i16>::new(decoded.capacity() as u64, spec);
+        buffer.copy_interleaved_ref(decoded);
+        samples.extend_from_slice(buffer.samples());
+    }
+
+    let (Some(sample_rate), Some(channels)) = (sample_rate, channels) else {
+        return Err(());
+    };
+    Ok(DecodedAudio {
+        sample_rate,
+        channels,
+        samples,
+    })
 }
 
 impl AudioFile {
@@ -66,6 +163,21 @@ impl AudioFile {
         } else if caf::CafPacketReader::new(Cursor::new(&bytes), vec![]).is_ok() {
             let reader = caf::CafPacketReader::new(Cursor::new(bytes), vec![]).unwrap();
             Ok(AudioFile(AudioFileInner::Caf(reader)))
+        } else if let Ok(decoded) = decode_with_symphonia(
+            bytes,
+            path.as_ref()
+                .file_name()
+                .and_then(|name| name.rsplit_once('.'))
+                .map(|(_, extension)| extension),
+        ) {
+            log_dbg!(
+                "Decoded compressed audio file {:?}: {} Hz, {} channel(s), {} samples",
+                path.as_ref(),
+                decoded.sample_rate,
+                decoded.channels,
+                decoded.samples.len()
+            );
+            Ok(AudioFile(AudioFileInner::Decoded(decoded)))
         } else {
             // We may eventually want to return an error here, this is just more
             // useful currently.
@@ -142,6 +254,21 @@ impl AudioFile {
                     bits_per_channel,
                 }
             }
+            AudioFileInner::Decoded(DecodedAudio {
+                sample_rate,
+                channels,
+                ..
+            }) => AudioDescription {
+                sample_rate: sample_rate.into(),
+                format: AudioFormat::LinearPcm {
+                    is_float: false,
+                    is_little_endian: true,
+                },
+                bytes_per_packet: channels * 2,
+                frames_per_packet: 1,
+                channels_per_frame: channels,
+                bits_per_channel: 16,
+            },
         }
     }
 
@@ -169,12 +296,15 @@ impl AudioFile {
                 // variable size not implemented
                 u64::from(self.packet_size_fixed()) * self.packet_count()
             }
+            AudioFileInner::Decoded(ref decoded) => {
+                u64::try_from(decoded.samples.len()).unwrap() * self.bytes_per_sample()
+            }
         }
     }
 
     pub fn packet_count(&self) -> u64 {
         match self.0 {
-            AudioFileInner::Wave(_) => {
+            AudioFileInner::Wave(_) | AudioFileInner::Decoded(_) => {
                 // never variable-size
                 self.byte_count() / u64::from(self.packet_size_fixed())
             }
@@ -252,6 +382,21 @@ impl AudioFile {
                 }
                 Ok(byte_offset)
             }
+            AudioFileInner::Decoded(ref decoded) => {
+                assert!(offset % 2 == 0);
+                assert!(buffer.len() % 2 == 0);
+
+                let start = usize::try_from(offset / 2)
+                    .unwrap()
+                    .min(decoded.samples.len());
+                let samples = &decoded.samples[start..];
+                let mut byte_offset = 0;
+                for (sample, bytes) in samples.iter().zip(buffer.chunks_exact_mut(2)) {
+                    bytes.copy_from_slice(&sample.to_le_bytes());
+                    byte_offset += 2;
+                }
+                Ok(byte_offset)
+            }
         }
     }
 }
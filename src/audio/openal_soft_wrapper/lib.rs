@@ -85,7 +85,15 @@ use al_types::*;
 
 pub const AL_NO_ERROR: ALenum = 0;
 
+pub const AL_POSITION: ALenum = 0x1004;
+pub const AL_DIRECTION: ALenum = 0x1005;
+pub const AL_VELOCITY: ALenum = 0x1006;
+
+pub const AL_BUFFER: ALenum = 0x1009;
+pub const AL_GAIN: ALenum = 0x100A;
+
 pub const AL_MAX_GAIN: ALenum = 0x100E;
+pub const AL_ORIENTATION: ALenum = 0x100F;
 
 pub const AL_SOURCE_STATE: ALenum = 0x1010;
 
@@ -97,24 +105,63 @@ pub const AL_STOPPED: ALenum = 0x1014;
 pub const AL_BUFFERS_QUEUED: ALenum = 0x1015;
 pub const AL_BUFFERS_PROCESSED: ALenum = 0x1016;
 
+pub const AL_REFERENCE_DISTANCE: ALenum = 0x1020;
+pub const AL_ROLLOFF_FACTOR: ALenum = 0x1021;
+pub const AL_MAX_DISTANCE: ALenum = 0x1023;
+
 pub const AL_FORMAT_MONO8: ALenum = 0x1100;
 pub const AL_FORMAT_MONO16: ALenum = 0x1101;
 pub const AL_FORMAT_STEREO8: ALenum = 0x1102;
 pub const AL_FORMAT_STEREO16: ALenum = 0x1103;
 
+pub const AL_DISTANCE_MODEL: ALenum = 0xD000;
+pub const AL_INVERSE_DISTANCE: ALenum = 0xD001;
+pub const AL_INVERSE_DISTANCE_CLAMPED: ALenum = 0xD002;
+pub const AL_LINEAR_DISTANCE: ALenum = 0xD003;
+pub const AL_LINEAR_DISTANCE_CLAMPED: ALenum = 0xD004;
+pub const AL_EXPONENT_DISTANCE: ALenum = 0xD005;
+pub const AL_EXPONENT_DISTANCE_CLAMPED: ALenum = 0xD006;
+
 extern "C" {
     pub fn alGetError() -> ALenum;
 
+    pub fn alGetInteger(param: ALenum) -> ALint;
+
+    pub fn alDistanceModel(distanceModel: ALenum);
+
+    pub fn alListenerf(param: ALenum, value: ALfloat);
+    pub fn alListener3f(param: ALenum, value1: ALfloat, value2: ALfloat, value3: ALfloat);
+    pub fn alListenerfv(param: ALenum, values: *const ALfloat);
+    pub fn alGetListenerf(param: ALenum, value: *mut ALfloat);
+    pub fn alGetListenerfv(param: ALenum, values: *mut ALfloat);
+
     pub fn alGenSources(n: ALsizei, sources: *mut ALuint);
     pub fn alDeleteSources(n: ALsizei, sources: *const ALuint);
 
     pub fn alSourcef(source: ALuint, param: ALenum, value: ALfloat);
+    pub fn alSource3f(
+        source: ALuint,
+        param: ALenum,
+        value1: ALfloat,
+        value2: ALfloat,
+        value3: ALfloat,
+    );
+    pub fn alSourcefv(source: ALuint, param: ALenum, values: *const ALfloat);
     pub fn alSourcei(source: ALuint, param: ALenum, value: ALint);
     pub fn alGetSourcef(source: ALuint, param: ALenum, value: *mut ALfloat);
+    pub fn alGetSourcefv(source: ALuint, param: ALenum, values: *mut ALfloat);
+    pub fn alGetSource3f(
+        source: ALuint,
+        param: ALenum,
+        value1: *mut ALfloat,
+        value2: *mut ALfloat,
+        value3: *mut ALfloat,
+    );
     pub fn alGetSourcei(source: ALuint, param: ALenum, value: *mut ALint);
 
     pub fn alSourcePlay(source: ALuint);
     pub fn alSourceStop(source: ALuint);
+    pub fn alSourcePause(source: ALuint);
 
     pub fn alSourceQueueBuffers(source: ALuint, nb: ALsizei, buffers: *const ALuint);
     pub fn alSourceUnqueueBuffers(source: ALuint, nb: ALsizei, buffers: *mut ALuint);
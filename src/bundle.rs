@@ -68,6 +68,19 @@ impl Bundle {
         self.plist["CFBundleIdentifier"].as_string().unwrap()
     }
 
+    /// The app's `Info.plist` contents.
+    pub fn info_plist(&self) -> &Dictionary {
+        &self.plist
+    }
+
+    /// The localization used if none of the user's preferred languages are
+    /// available, e.g. `English` or `en`.
+    pub fn development_region(&self) -> Option<&str> {
+        self.plist
+            .get("CFBundleDevelopmentRegion")
+            .and_then(|region| region.as_string())
+    }
+
     pub fn display_name(&self) -> &str {
         self.plist["CFBundleDisplayName"].as_string().unwrap()
     }
@@ -95,6 +108,19 @@ impl Bundle {
         }
     }
 
+    /// Paths of the fonts the app bundles for its own use (`UIAppFonts`).
+    pub fn app_font_paths(&self) -> Vec<GuestPathBuf> {
+        let Some(fonts) = self.plist.get("UIAppFonts") else {
+            return Vec::new();
+        };
+        fonts
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|font| self.path.join(font.as_string().unwrap()))
+            .collect()
+    }
+
     pub fn main_nib_file_path(&self) -> GuestPathBuf {
         // FIXME: There might not be a main nib file, or it might be localised
         // and have multiple paths. This method should definitely be removed
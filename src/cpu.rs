@@ -127,6 +127,41 @@ pub enum CpuState {
     Svc(u32),
 }
 
+/// Record of a single instruction executed by [Cpu::step_traced].
+pub struct TraceEntry {
+    /// Address of the instruction, with the Thumb bit set if it's Thumb code.
+    pub pc: GuestFunction,
+    pub disassembly: String,
+    /// Registers (other than PC) changed by the instruction, as tuples of
+    /// register number, old value and new value.
+    pub changed_regs: Vec<(usize, u32, u32)>,
+    /// Old and new value of CPSR, if it was changed by the instruction.
+    pub changed_cpsr: Option<(u32, u32)>,
+}
+impl std::fmt::Display for TraceEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:#010x}{}: {}",
+            self.pc.addr_without_thumb_bit(),
+            if self.pc.is_thumb() { " (T)" } else { "" },
+            self.disassembly
+        )?;
+        for &(reg_idx, old, new) in &self.changed_regs {
+            match reg_idx {
+                Cpu::SP => write!(f, "; SP: ")?,
+                Cpu::LR => write!(f, "; LR: ")?,
+                _ => write!(f, "; R{}: ", reg_idx)?,
+            }
+            write!(f, "{:#x} -> {:#x}", old, new)?;
+        }
+        if let Some((old, new)) = self.changed_cpsr {
+            write!(f, "; CPSR: {:#x} -> {:#x}", old, new)?;
+        }
+        Ok(())
+    }
+}
+
 impl Cpu {
     /// The register number of the stack pointer.
     pub const SP: usize = 13;
@@ -161,21 +196,32 @@ impl Cpu {
     }
 
     pub fn dump_regs(&self) {
+        eprint!("{}", self.format_regs());
+    }
+
+    /// Describe the registers in the format used by [Self::dump_regs].
+    pub fn format_regs(&self) -> String {
+        use std::fmt::Write;
+
+        let mut out = String::new();
         let regs = self.regs();
         for row in 0..4 {
             for col in 0..4 {
                 let reg_idx = row * 4 + col;
                 match reg_idx {
-                    Self::SP => eprint!("\t SP: "),
-                    Self::LR => eprint!("\t LR: "),
-                    Self::PC => eprint!("\t PC: "),
-                    _ if reg_idx <= 9 => eprint!("\t R{}: ", reg_idx),
-                    _ => eprint!("\tR{}: ", reg_idx),
-                };
-                eprint!("{:#010x}", regs[reg_idx]);
+                    Self::SP => write!(out, "\t SP: "),
+                    Self::LR => write!(out, "\t LR: "),
+                    Self::PC => write!(out, "\t PC: "),
+                    _ if reg_idx <= 9 => write!(out, "\t R{}: ", reg_idx),
+                    _ => write!(out, "\tR{}: ", reg_idx),
+                }
+                .unwrap();
+                write!(out, "{:#010x}", regs[reg_idx]).unwrap();
             }
-            eprintln!();
+            writeln!(out).unwrap();
         }
+        writeln!(out, "\tCPSR: {:#010x}", self.cpsr()).unwrap();
+        out
     }
 
     pub fn cpsr(&self) -> u32 {
@@ -255,4 +301,88 @@ impl Cpu {
             svc => CpuState::Svc(svc as u32),
         }
     }
+
+    /// Execute exactly one instruction. Like [Self::run], check the return
+    /// value!
+    #[must_use]
+    pub fn step(&mut self, mem: &mut Mem) -> CpuState {
+        let res = unsafe {
+            touchHLE_DynarmicWrapper_step(
+                self.dynarmic_wrapper,
+                mem as *mut Mem as *mut touchHLE_Mem,
+            )
+        };
+        match res {
+            -1 => CpuState::Normal,
+            -2 => {
+                panic!("Memory error during CPU execution!");
+            }
+            _ if res < -2 => panic!("Unexpected CPU execution result"),
+            svc => CpuState::Svc(svc as u32),
+        }
+    }
+
+    /// Like [Self::step], but also returns a record of the executed
+    /// instruction and its effect on the registers. This is slow, so it should
+    /// only be used for debugging.
+    #[must_use]
+    pub fn step_traced(&mut self, mem: &mut Mem) -> (CpuState, TraceEntry) {
+        let pc = self.pc_with_thumb_bit();
+        let disassembly = Self::disassemble(mem, pc);
+        let old_regs = *self.regs();
+        let old_cpsr = self.cpsr();
+
+        let state = self.step(mem);
+
+        let new_regs = self.regs();
+        let changed_regs = (0..Self::PC)
+            .filter(|&i| old_regs[i] != new_regs[i])
+            .map(|i| (i, old_regs[i], new_regs[i]))
+            .collect();
+        let new_cpsr = self.cpsr();
+        let changed_cpsr = (old_cpsr != new_cpsr).then_some((old_cpsr, new_cpsr));
+        (
+            state,
+            TraceEntry {
+                pc,
+                disassembly,
+                changed_regs,
+                changed_cpsr,
+            },
+        )
+    }
+
+    /// Disassemble the instruction at some address. This is only meant for
+    /// debugging output.
+    pub fn disassemble(mem: &Mem, pc: GuestFunction) -> String {
+        let addr = pc.addr_without_thumb_bit();
+        let (instruction, thumb) = if pc.is_thumb() {
+            let halfword: u16 = mem.read(Ptr::from_bits(addr));
+            // The disassembler only understands 16-bit Thumb instructions.
+            // Any halfword starting with 0b11101, 0b11110 or 0b11111 is the
+            // first half of a 32-bit instruction.
+            if halfword >> 11 >= 0b11101 {
+                let second_halfword: u16 = mem.read(Ptr::from_bits(addr + 2));
+                return format!(
+                    "<32-bit Thumb instruction {:04x} {:04x}>",
+                    halfword, second_halfword
+                );
+            }
+            (halfword.into(), true)
+        } else {
+            (mem.read(Ptr::from_bits(addr)), false)
+        };
+
+        let mut buf = [0u8; 128];
+        unsafe {
+            touchHLE_DynarmicWrapper_disassemble(
+                instruction,
+                thumb,
+                buf.as_mut_ptr().cast(),
+                buf.len(),
+            );
+        }
+        let len = buf.iter().position(|&c| c == b'\0').unwrap();
+        String::from_utf8_lossy(&buf[..len]).into_owned()
+    }
 }
@@ -3,12 +3,16 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at https://mozilla.org/MPL/2.0/.
  */
+#include <algorithm>
 #include <cstdint>
 #include <cstdio>
+#include <cstring>
+#include <string>
 
 #include "dynarmic/interface/A32/a32.h"
 #include "dynarmic/interface/A32/config.h"
 #include "dynarmic/interface/A32/context.h"
+#include "dynarmic/interface/A32/disassembler.h"
 
 namespace touchHLE::cpu {
 
@@ -177,6 +181,24 @@ public:
     *ticks = env.ticks_remaining;
     return res;
   }
+
+  std::int32_t step(touchHLE_Mem *mem) {
+    env.mem = mem;
+    env.ticks_remaining = 1;
+    Dynarmic::HaltReason hr = cpu->Step();
+    std::int32_t res;
+    if (Dynarmic::Has(hr, Dynarmic::HaltReason::MemoryAbort)) {
+      res = -2;
+    } else if (Dynarmic::Has(hr, HaltReasonSvc)) {
+      res = std::int32_t(env.halting_svc);
+    } else {
+      // Dynarmic::HaltReason::Step, possibly along with other reasons we
+      // don't care about.
+      res = -1;
+    }
+    env.mem = nullptr;
+    return res;
+  }
 };
 
 extern "C" {
@@ -219,6 +241,27 @@ std::int32_t touchHLE_DynarmicWrapper_run(DynarmicWrapper *cpu,
   return cpu->run(mem, ticks);
 }
 
+std::int32_t touchHLE_DynarmicWrapper_step(DynarmicWrapper *cpu,
+                                           touchHLE_Mem *mem) {
+  return cpu->step(mem);
+}
+
+// Writes a null-terminated string to the buffer, truncating it if necessary.
+// Returns the length of the untruncated string.
+std::size_t touchHLE_DynarmicWrapper_disassemble(std::uint32_t instruction,
+                                                 bool thumb, char *buf,
+                                                 std::size_t buf_size) {
+  std::string disassembly =
+      thumb ? Dynarmic::A32::DisassembleThumb16(std::uint16_t(instruction))
+            : Dynarmic::A32::DisassembleArm(instruction);
+  if (buf_size > 0) {
+    std::size_t len = std::min(disassembly.size(), buf_size - 1);
+    std::memcpy(buf, disassembly.data(), len);
+    buf[len] = '\0';
+  }
+  return disassembly.size();
+}
+
 void *touchHLE_DynarmicWrapper_Context_new() {
   return (void *)new Dynarmic::A32::Context();
 }
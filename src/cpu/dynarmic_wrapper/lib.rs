@@ -46,6 +46,16 @@ extern "C" {
         mem: *mut touchHLE_Mem,
         ticks: *mut u64,
     ) -> i32;
+    pub fn touchHLE_DynarmicWrapper_step(
+        cpu: *mut touchHLE_DynarmicWrapper,
+        mem: *mut touchHLE_Mem,
+    ) -> i32;
+    pub fn touchHLE_DynarmicWrapper_disassemble(
+        instruction: u32,
+        thumb: bool,
+        buf: *mut std::ffi::c_char,
+        buf_size: usize,
+    ) -> usize;
 
     pub fn touchHLE_DynarmicWrapper_Context_new() -> *mut Dynarmic_A32_Context;
     pub fn touchHLE_DynarmicWrapper_Context_delete(context: *mut Dynarmic_A32_Context);
@@ -23,13 +23,14 @@
 mod constant_lists;
 mod function_lists;
 
-use crate::abi::{CallFromGuest, GuestFunction};
-use crate::cpu::Cpu;
+use crate::abi::{CallFromGuest, CallFromHost, GuestFunction};
+use crate::cpu::{Cpu, CpuState};
 use crate::frameworks::foundation::ns_string;
 use crate::mach_o::MachO;
-use crate::mem::{ConstVoidPtr, GuestUSize, Mem, MutPtr, Ptr};
+use crate::mem::{ConstPtr, ConstVoidPtr, GuestUSize, Mem, MutPtr, Ptr};
 use crate::objc::ObjC;
 use crate::Environment;
+use std::collections::{BTreeMap, HashMap};
 
 type HostFunction = &'static dyn CallFromGuest;
 
@@ -126,6 +127,31 @@ pub fn search_lists<T>(
         .map(|&(_, ref f)| f)
 }
 
+/// What to do once a breakpoint has been hit. See
+/// [Dyld::set_breakpoint_handler].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BreakpointAction {
+    /// Resume execution normally.
+    Continue,
+    /// Execute a single instruction, then call the handler again.
+    SingleStep,
+    /// Stop the emulator.
+    Abort,
+}
+
+/// Callback for inspecting the emulator state when a breakpoint is hit. See
+/// [Dyld::set_breakpoint_handler].
+pub type BreakpointHandler = Box<dyn FnMut(&mut Environment) -> BreakpointAction>;
+
+/// Symbols that couldn't be linked, for reporting to the user. The value is
+/// [true] if every reference to the symbol is a weak import.
+type MissingSymbols = BTreeMap<String, bool>;
+
+fn add_missing_symbol(missing_symbols: &mut MissingSymbols, bin: &MachO, symbol: &str) {
+    let is_weak = bin.weak_imports.contains(symbol);
+    *missing_symbols.entry(symbol.to_string()).or_insert(true) &= is_weak;
+}
+
 fn encode_a32_svc(imm: u32) -> u32 {
     assert!(imm & 0xff000000 == 0);
     imm | 0xef000000
@@ -144,6 +170,13 @@ pub struct Dyld {
     linked_host_functions: Vec<HostFunction>,
     return_to_host_routine: Option<GuestFunction>,
     constants_to_link_later: Vec<(MutPtr<ConstVoidPtr>, &'static HostConstant)>,
+    /// Guest functions created by [Self::create_proc_address], so they can be
+    /// reused.
+    proc_addresses: HashMap<&'static str, GuestFunction>,
+    /// Original instructions overwritten by breakpoints, keyed by address (with
+    /// the Thumb bit set if needed). Thumb instructions are zero-extended.
+    breakpoints: HashMap<u32, u32>,
+    breakpoint_handler: Option<BreakpointHandler>,
 }
 
 impl Dyld {
@@ -152,7 +185,7 @@ impl Dyld {
     /// We reserve this SVC ID for the special return-to-host routine.
     pub const SVC_RETURN_TO_HOST: u32 = 1;
     /// We reserve this SVC ID for breakpoints.
-    const SVC_BREAKPOINT: u32 = 2;
+    pub const SVC_BREAKPOINT: u32 = 2;
     /// The range of SVC IDs `SVC_LINKED_FUNCTIONS_BASE..` is used to reference
     /// [Self::linked_host_functions] entries.
     const SVC_LINKED_FUNCTIONS_BASE: u32 = Self::SVC_BREAKPOINT + 1;
@@ -165,6 +198,9 @@ impl Dyld {
             linked_host_functions: Vec::new(),
             return_to_host_routine: None,
             constants_to_link_later: Vec::new(),
+            proc_addresses: HashMap::new(),
+            breakpoints: HashMap::new(),
+            breakpoint_handler: None,
         }
     }
 
@@ -192,20 +228,64 @@ impl Dyld {
             Some(ptr)
         };
 
-        // Currently assuming only the app binary contains Objective-C things.
+        // Any of the binaries can contain Objective-C things. They are
+        // registered in reverse order so that libraries come before the app
+        // binary that depends on them.
 
-        objc.register_bin_selectors(&bins[0], mem);
+        for bin in bins.iter().rev() {
+            objc.register_bin_selectors(bin, mem);
+        }
         objc.register_host_selectors(mem);
 
+        // Symbols that have neither a host implementation nor a definition in
+        // one of the loaded binaries. These are reported all at once, rather
+        // than one at a time as the app crashes on them.
+        let mut missing_symbols = MissingSymbols::new();
+
         for bin in bins {
-            self.setup_lazy_linking(bin, mem);
+            self.do_local_relocations(bin, mem);
+            self.setup_lazy_linking(bin, bins, mem, &mut missing_symbols);
             // Must happen before `register_bin_classes`, else superclass
             // pointers will be wrong.
-            self.do_non_lazy_linking(bin, bins, mem, objc);
+            self.do_non_lazy_linking(bin, bins, mem, objc, &mut missing_symbols);
+        }
+
+        let (weak, strong): (Vec<_>, Vec<_>) = missing_symbols
+            .iter()
+            .partition(|&(_symbol, &is_weak)| is_weak);
+        if !strong.is_empty() {
+            let mut list = String::new();
+            for (symbol, _) in &strong {
+                list.push_str("\n- ");
+                list.push_str(symbol);
+            }
+            log!(
+                "Warning: {} symbol(s) referenced by the app are not implemented by touchHLE or any loaded library. The app may crash if it uses them:{}",
+                strong.len(),
+                list
+            );
+        }
+        if !weak.is_empty() {
+            let mut list = String::new();
+            for (symbol, _) in &weak {
+                list.push_str("\n- ");
+                list.push_str(symbol);
+            }
+            log!(
+                "{} weakly-imported symbol(s) referenced by the app are not implemented by touchHLE or any loaded library. They have been linked to NULL, which the app should be able to handle:{}",
+                weak.len(),
+                list
+            );
         }
 
-        objc.register_bin_classes(&bins[0], mem);
-        objc.register_bin_categories(&bins[0], mem);
+        // All classes must be registered before any categories, since a
+        // category can extend a class from another binary.
+        for bin in bins.iter().rev() {
+            objc.register_bin_classes(bin, mem);
+        }
+        for bin in bins.iter().rev() {
+            objc.register_bin_categories(bin, mem);
+        }
     }
 
     /// Set up lazy-linking stubs for a loaded binary.
@@ -220,12 +300,31 @@ impl Dyld {
     ///
     /// These stubs already exist in the binary, but they need to be rewritten
     /// so that they will invoke our dynamic linker.
-    fn setup_lazy_linking(&self, bin: &MachO, mem: &mut Mem) {
+    ///
+    /// Any symbols that can't be linked are added to `missing_symbols`.
+    fn setup_lazy_linking(
+        &self,
+        bin: &MachO,
+        bins: &[MachO],
+        mem: &mut Mem,
+        missing_symbols: &mut MissingSymbols,
+    ) {
         let Some(stubs) = bin.get_section("__symbol_stub4").or_else(|| bin.get_section("__picsymbolstub4")) else {
             return;
         };
 
-        let entry_size = stubs.dyld_indirect_symbol_info.as_ref().unwrap().entry_size;
+        let info = stubs.dyld_indirect_symbol_info.as_ref().unwrap();
+        let entry_size = info.entry_size;
+
+        for symbol in info.indirect_undef_symbols.iter().flatten() {
+            if search_lists(function_lists::FUNCTION_LISTS, symbol).is_none()
+                && !bins
+                    .iter()
+                    .any(|other_bin| other_bin.exported_symbols.contains_key(symbol))
+            {
+                add_missing_symbol(missing_symbols, bin, symbol);
+            }
+        }
 
         // two or three A32 instructions (PIC stub needs one more) followed by
         // the address or offset of the corresponding __la_symbol_ptr
@@ -263,27 +362,49 @@ impl Dyld {
     /// These are usually constants, Objective-C classes, or vtable pointers.
     /// Since the linking must be done upfront, we can't in general delay errors
     /// about missing implementations until the point of use. For that reason,
-    /// this will spit out a warning to stderr for everything missing, so that
-    /// there's at least some indication about why the emulator might crash.
+    /// everything missing is added to `missing_symbols`, so that a warning can
+    /// be printed and there's at least some indication about why the emulator
+    /// might crash.
     ///
     /// `bin` is the binary to link non-lazy symbols for, `bins` is the set of
     /// binaries symbols may be looked up in.
-    fn do_non_lazy_linking(&mut self, bin: &MachO, bins: &[MachO], mem: &mut Mem, objc: &mut ObjC) {
+    fn do_non_lazy_linking(
+        &mut self,
+        bin: &MachO,
+        bins: &[MachO],
+        mem: &mut Mem,
+        objc: &mut ObjC,
+        missing_symbols: &mut MissingSymbols,
+    ) {
         for &(ptr_ptr, ref name) in &bin.external_relocations {
-            let ptr = if let Some(name) = name.strip_prefix("_OBJC_CLASS_$_") {
+            // Symbols from the loaded binaries are checked first, so that a
+            // class defined in one binary and used by another (e.g. as a
+            // superclass) is linked to the real class rather than to a
+            // placeholder or a host class.
+            let ptr = if let Some(&addr) = bins
+                .iter()
+                .find_map(|other_bin| other_bin.exported_symbols.get(name))
+            {
+                Ptr::from_bits(addr)
+            } else if let Some(name) = name.strip_prefix("_OBJC_CLASS_$_") {
                 objc.link_class(name, /* is_metaclass: */ false, mem)
             } else if let Some(name) = name.strip_prefix("_OBJC_METACLASS_$_") {
                 objc.link_class(name, /* is_metaclass: */ true, mem)
             } else if name == "___CFConstantStringClassReference" {
                 ns_string::handle_constant_string(mem, objc, Ptr::from_bits(ptr_ptr))
             } else {
-                // TODO: look up symbol, write pointer
-                log!(
-                    "Warning: unhandled external relocation {:?} at {:#x} in \"{}\"",
+                log_dbg!(
+                    "Unhandled external relocation {:?} at {:#x} in \"{}\"",
                     name,
                     ptr_ptr,
                     bin.name
                 );
+                add_missing_symbol(missing_symbols, bin, name);
+                if bin.weak_imports.contains(name) {
+                    // Lets the app check if the symbol is available.
+                    let ptr_ptr: MutPtr<ConstVoidPtr> = Ptr::from_bits(ptr_ptr);
+                    mem.write(ptr_ptr, Ptr::null());
+                }
                 continue;
             };
             mem.write(Ptr::from_bits(ptr_ptr), ptr)
@@ -319,15 +440,36 @@ impl Dyld {
                 continue;
             }
 
-            log!(
-                "Warning: unhandled non-lazy symbol {:?} at {:?} in \"{}\"",
+            log_dbg!(
+                "Unhandled non-lazy symbol {:?} at {:?} in \"{}\"",
                 symbol,
                 ptr_ptr,
                 bin.name
             );
+            add_missing_symbol(missing_symbols, bin, symbol);
+            if bin.weak_imports.contains(symbol) {
+                // Lets the app check if the symbol is available.
+                mem.write(ptr_ptr, Ptr::null());
+            }
+        }
+    }
+
+    /// Rebase internal pointers (e.g. function pointers in static tables,
+    /// vtables) for a loaded binary, if it wasn't loaded at its preferred
+    /// address.
+    fn do_local_relocations(&self, bin: &MachO, mem: &mut Mem) {
+        let slide = bin.load_slide;
+        if slide == 0 {
+            return;
         }
 
-        // FIXME: there's probably internal relocations to deal with too.
+        log_dbg!(
+            "Rebasing {} internal pointers in \"{}\" by {:#x}",
+            bin.local_relocations.len(),
+            bin.name,
+            slide
+        );
+        rebase_pointers(mem, &bin.local_relocations, slide);
     }
 
     /// Do linking that can only be done once there is a full [Environment].
@@ -354,6 +496,51 @@ impl Dyld {
         }
     }
 
+    /// Get a binary's static initializers (e.g. C++ static constructors), in
+    /// the order they must be called.
+    fn mod_init_funcs(bin: &MachO, mem: &Mem) -> Vec<GuestFunction> {
+        let Some(mod_init_func) = bin.get_section("__mod_init_func") else { return Vec::new(); };
+
+        assert!(mod_init_func.size % 4 == 0);
+        let base: ConstPtr<GuestFunction> = Ptr::from_bits(mod_init_func.addr);
+        (0..(mod_init_func.size / 4))
+            .map(|i| mem.read(base + i))
+            .collect()
+    }
+
+    /// Send `+load` to Objective-C classes and call static initializers for
+    /// every binary, libraries first, as Apple's dyld does before the app's
+    /// entry point is reached. Must be called after all other linking.
+    pub fn run_initializers(env: &mut Environment) {
+        for bin_idx in (0..env.bins.len()).rev() {
+            let bin = &env.bins[bin_idx];
+            let class_methods = env.objc.bin_class_load_methods(bin, &env.mem);
+            let category_methods = env.objc.bin_category_load_methods(bin, &env.mem);
+            if !class_methods.is_empty() || !category_methods.is_empty() {
+                log_dbg!("Sending +load for {:?}", bin.name);
+            }
+            // Classes' own methods come before those from categories. The
+            // methods are called directly: sending a message would send
+            // +initialize first. Categories also aren't applied to their
+            // classes yet.
+            for (class, load_sel, imp) in class_methods.into_iter().chain(category_methods) {
+                let _: () = imp.call_from_host(env, (class, load_sel));
+            }
+
+            let funcs = Self::mod_init_funcs(&env.bins[bin_idx], &env.mem);
+            if !funcs.is_empty() {
+                log_dbg!(
+                    "Calling static initializers for {:?}",
+                    env.bins[bin_idx].name
+                );
+                for func in funcs {
+                    func.call(env);
+                }
+            }
+        }
+        log_dbg!("Static initialization done");
+    }
+
     /// Return a host function that can be called to handle an SVC instruction
     /// encountered during CPU emulation. If `None` is returned, the execution
     /// needs to resume at `svc_pc`.
@@ -367,8 +554,8 @@ impl Dyld {
     ) -> Option<HostFunction> {
         match svc {
             Self::SVC_LAZY_LINK => self.do_lazy_link(bins, mem, cpu, svc_pc),
-            Self::SVC_RETURN_TO_HOST => unreachable!(), // don't handle here
-            Self::SVC_BREAKPOINT => panic!("Breakpoint"),
+            // don't handle these here
+            Self::SVC_RETURN_TO_HOST | Self::SVC_BREAKPOINT => unreachable!(),
             Self::SVC_LINKED_FUNCTIONS_BASE.. => {
                 let f = self
                     .linked_host_functions
@@ -465,8 +652,9 @@ impl Dyld {
 
     /// Creates a guest function that will call a host function with the name
     /// `symbol`. This can be used to implement "get proc address" functions.
-    /// Note that no attempt is made to deduplicate or deallocate these, so
-    /// excessive use would create a memory leak.
+    /// Repeated calls for the same symbol return the same guest function. These
+    /// are never deallocated, so they live for the lifetime of the program, but
+    /// the memory used is bounded by the number of distinct symbols.
     ///
     /// The name must be the mangled symbol name. Returns [Err] if there's no
     /// such function.
@@ -476,8 +664,30 @@ impl Dyld {
         cpu: &mut Cpu,
         symbol: &str,
     ) -> Result<GuestFunction, ()> {
-        let &f = search_lists(function_lists::FUNCTION_LISTS, symbol).ok_or(())?;
+        if let Some(&function) = self.proc_addresses.get(symbol) {
+            return Ok(function);
+        }
+
+        let &(symbol, f) = function_lists::FUNCTION_LISTS
+            .iter()
+            .flat_map(|&n| n)
+            .find(|&(sym, _)| *sym == symbol)
+            .ok_or(())?;
+
+        let function = self.create_guest_function(mem, cpu, f);
+        self.proc_addresses.insert(symbol, function);
+        Ok(function)
+    }
 
+    /// Creates a guest function that will call a host function. Unlike
+    /// [Self::create_proc_address], this doesn't reuse anything, so the caller
+    /// should avoid creating more than one for the same host function.
+    pub fn create_guest_function(
+        &mut self,
+        mem: &mut Mem,
+        cpu: &mut Cpu,
+        f: HostFunction,
+    ) -> GuestFunction {
         // Allocate an SVC ID for this host function
         let idx: u32 = self.linked_host_functions.len().try_into().unwrap();
         let svc = idx + Self::SVC_LINKED_FUNCTIONS_BASE;
@@ -492,9 +702,7 @@ impl Dyld {
         // Just in case
         cpu.invalidate_cache_range(function_ptr.to_bits(), 4);
 
-        Ok(GuestFunction::from_addr_with_thumb_bit(
-            function_ptr.to_bits(),
-        ))
+        GuestFunction::from_addr_with_thumb_bit(function_ptr.to_bits())
     }
 
     /// Sets a primitive breakpoint at an instruction address by overwriting it
@@ -502,18 +710,353 @@ impl Dyld {
     ///
     /// This should be called after initial linking so the instructions don't
     /// get overwritten by that. **Do not call this after CPU execution has
-    /// begun**, it does not clear the instruction cache!
+    /// begun**, it does not clear the instruction cache! Use
+    /// [Self::set_breakpoint_live] in that case.
     pub fn set_breakpoint(&mut self, mem: &mut Mem, at: u32) {
+        self.write_breakpoint(mem, at);
+    }
+
+    /// Like [Self::set_breakpoint], but also clears the instruction cache, so
+    /// it can be used after CPU execution has begun.
+    pub fn set_breakpoint_live(&mut self, mem: &mut Mem, cpu: &mut Cpu, at: u32) {
+        if let Some((addr, size)) = self.write_breakpoint(mem, at) {
+            cpu.invalidate_cache_range(addr, size);
+        }
+    }
+
+    /// Removes a breakpoint set with [Self::set_breakpoint] or
+    /// [Self::set_breakpoint_live], restoring the original instruction.
+    /// Returns [false] if there was no breakpoint at that address.
+    pub fn clear_breakpoint(&mut self, mem: &mut Mem, cpu: &mut Cpu, at: u32) -> bool {
+        let Some(original) = self.breakpoints.remove(&at) else {
+            return false;
+        };
         let at = GuestFunction::from_addr_with_thumb_bit(at);
-        if at.is_thumb() {
-            let ptr: MutPtr<u16> = Ptr::from_bits(at.addr_without_thumb_bit());
+        let addr = at.addr_without_thumb_bit();
+        let size = if at.is_thumb() {
+            mem.write(Ptr::from_bits(addr), original as u16);
+            2
+        } else {
+            mem.write(Ptr::from_bits(addr), original);
+            4
+        };
+        cpu.invalidate_cache_range(addr, size);
+        true
+    }
+
+    /// Set the function that will be called when a breakpoint is hit. The
+    /// handler can inspect and modify the CPU state and memory, and decides how
+    /// execution continues. If there is no handler, the default behaviour is to
+    /// print the registers and a stack trace, then continue.
+    pub fn set_breakpoint_handler(&mut self, handler: BreakpointHandler) {
+        self.breakpoint_handler = Some(handler);
+    }
+
+    /// Handle a breakpoint SVC encountered during CPU execution, by calling the
+    /// breakpoint handler and acting on what it returns.
+    ///
+    /// When this returns, execution can resume normally. If [Some] is
+    /// returned, an SVC instruction was executed while stepping, and the
+    /// caller must handle it as if it had been encountered normally.
+    pub fn handle_breakpoint(env: &mut Environment) -> Option<u32> {
+        // The PC is pointing at the instruction after the SVC, but the handler
+        // should see the state from before the breakpointed instruction.
+        let pc = env.cpu.pc_with_thumb_bit();
+        let svc_size = if pc.is_thumb() { 2 } else { 4 };
+        env.cpu.regs_mut()[Cpu::PC] = pc.addr_without_thumb_bit() - svc_size;
+
+        let mut handler = env.dyld.breakpoint_handler.take();
+        let res = loop {
+            let action = match handler {
+                Some(ref mut handler) => handler(env),
+                None => Self::default_breakpoint_handler(env),
+            };
+            if action == BreakpointAction::Abort {
+                panic!("Aborting at breakpoint");
+            }
+
+            // Execute the instruction that was replaced by the breakpoint (or
+            // whatever instruction the handler has moved the PC to).
+            let at = env.cpu.pc_with_thumb_bit().addr_with_thumb_bit();
+            let had_breakpoint = env.dyld.clear_breakpoint(&mut env.mem, &mut env.cpu, at);
+            let state = env.cpu.step(&mut env.mem);
+            if had_breakpoint {
+                env.dyld.set_breakpoint_live(&mut env.mem, &mut env.cpu, at);
+            }
+
+            if let CpuState::Svc(svc) = state {
+                if action == BreakpointAction::SingleStep {
+                    log!("Single-stepping stopped at SVC #{}", svc);
+                }
+                break Some(svc);
+            }
+            if action == BreakpointAction::Continue {
+                break None;
+            }
+        };
+        // The handler might have been replaced by itself.
+        if env.dyld.breakpoint_handler.is_none() {
+            env.dyld.breakpoint_handler = handler;
+        }
+        res
+    }
+
+    fn default_breakpoint_handler(env: &mut Environment) -> BreakpointAction {
+        log!(
+            "Breakpoint hit at {:?} on thread {}. Registers:",
+            env.cpu.pc_with_thumb_bit(),
+            env.current_thread
+        );
+        env.cpu.dump_regs();
+        log!("Stack trace:");
+        env.stack_trace();
+        BreakpointAction::Continue
+    }
+
+    /// Overwrite an instruction with a breakpoint, saving the original. Returns
+    /// the address and size of the overwritten instruction, or [None] if there
+    /// is already a breakpoint there.
+    fn write_breakpoint(&mut self, mem: &mut Mem, at: u32) -> Option<(u32, GuestUSize)> {
+        if self.breakpoints.contains_key(&at) {
+            return None;
+        }
+        let thumb_at = GuestFunction::from_addr_with_thumb_bit(at);
+        let addr = thumb_at.addr_without_thumb_bit();
+        if thumb_at.is_thumb() {
+            let ptr: MutPtr<u16> = Ptr::from_bits(addr);
+            self.breakpoints.insert(at, mem.read(ptr).into());
             mem.write(
                 ptr,
                 encode_t32_svc(Self::SVC_BREAKPOINT.try_into().unwrap()),
             );
+            Some((addr, 2))
         } else {
-            let ptr: MutPtr<u32> = Ptr::from_bits(at.addr_without_thumb_bit());
+            let ptr: MutPtr<u32> = Ptr::from_bits(addr);
+            self.breakpoints.insert(at, mem.read(ptr));
             mem.write(ptr, encode_a32_svc(Self::SVC_BREAKPOINT));
+            Some((addr, 4))
+        }
+    }
+}
+
+/// Add `slide` to each pointer at the given addresses, which are the
+/// addresses in the binary before it was slid.
+pub(crate) fn rebase_pointers(mem: &mut Mem, addrs: &[u32], slide: u32) {
+    for &addr in addrs {
+        let ptr: MutPtr<u32> = Ptr::from_bits(addr.wrapping_add(slide));
+        let value = mem.read(ptr);
+        mem.write(ptr, value.wrapping_add(slide));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mach_o::Section;
+    use crate::objc::{nil, Class};
+    use std::collections::HashSet;
+
+    fn alloc_words(mem: &mut Mem, words: &[u32]) -> u32 {
+        let ptr: MutPtr<u32> = mem.alloc(words.len() as GuestUSize * 4).cast();
+        for (i, &word) in words.iter().enumerate() {
+            mem.write(ptr + i as GuestUSize, word);
         }
+        ptr.to_bits()
+    }
+
+    /// Create a binary defining a single Objective-C class, with an optional
+    /// superclass from another binary. Returns the binary and the class's
+    /// address.
+    fn bin_with_class(mem: &mut Mem, class_name: &str, superclass: Option<&str>) -> (MachO, u32) {
+        let name = mem.alloc_and_write_cstr(class_name.as_bytes()).to_bits();
+        // class_rw_t: flags, instance start, instance size, reserved, name,
+        // followed by various lists that are all empty.
+        let data = alloc_words(mem, &[0, 4, 4, 0, name, 0, 0, 0, 0, 0]);
+        // class_t: isa, superclass, cache, vtable, data. The superclass gets
+        // filled in by the linker.
+        let metaclass = alloc_words(mem, &[0, 0, 0, 0, data]);
+        let class = alloc_words(mem, &[metaclass, 0, 0, 0, data]);
+        let class_list = alloc_words(mem, &[class]);
+
+        let mut external_relocations = Vec::new();
+        if let Some(superclass) = superclass {
+            external_relocations.push((class + 4, format!("_OBJC_CLASS_$_{}", superclass)));
+            external_relocations.push((metaclass + 4, format!("_OBJC_METACLASS_$_{}", superclass)));
+        }
+
+        let bin = MachO {
+            name: format!("{}.dylib", class_name),
+            dynamic_libraries: Vec::new(),
+            sections: vec![Section {
+                name: "__objc_classlist".to_string(),
+                addr: class_list,
+                size: 4,
+                dyld_indirect_symbol_info: None,
+            }],
+            exported_symbols: HashMap::from([
+                (format!("_OBJC_CLASS_$_{}", class_name), class),
+                (format!("_OBJC_METACLASS_$_{}", class_name), metaclass),
+            ]),
+            defined_symbols: Vec::new(),
+            external_relocations,
+            local_relocations: Vec::new(),
+            weak_imports: HashSet::new(),
+            load_slide: 0,
+        };
+        (bin, class)
+    }
+
+    #[test]
+    fn subclass_in_other_binary() {
+        let mut mem = Mem::new();
+        let mut objc = ObjC::new();
+        let mut dyld = Dyld::new();
+
+        let (app, app_class) = bin_with_class(&mut mem, "AppClass", Some("LibraryClass"));
+        let (library, library_class) = bin_with_class(&mut mem, "LibraryClass", None);
+        let bins = [app, library];
+
+        let mut missing_symbols = MissingSymbols::new();
+        for bin in &bins {
+            dyld.do_non_lazy_linking(bin, &bins, &mut mem, &mut objc, &mut missing_symbols);
+        }
+        assert!(missing_symbols.is_empty());
+        for bin in bins.iter().rev() {
+            objc.register_bin_classes(bin, &mut mem);
+        }
+
+        let app_class: Class = Ptr::from_bits(app_class);
+        let library_class: Class = Ptr::from_bits(library_class);
+        assert_eq!(objc.get_known_class("AppClass", &mut mem), app_class);
+        assert_eq!(
+            objc.get_known_class("LibraryClass", &mut mem),
+            library_class
+        );
+        assert_eq!(objc.get_class_name(app_class), "AppClass");
+        assert!(objc.class_is_subclass_of(app_class, library_class));
+        assert!(!objc.class_is_subclass_of(library_class, app_class));
+
+        let app_metaclass = ObjC::read_isa(app_class, &mem);
+        let library_metaclass = ObjC::read_isa(library_class, &mem);
+        assert!(app_metaclass != nil);
+        assert!(objc.class_is_subclass_of(app_metaclass, library_metaclass));
+    }
+
+    #[test]
+    fn missing_weak_import() {
+        let mut mem = Mem::new();
+        let mut objc = ObjC::new();
+        let mut dyld = Dyld::new();
+
+        let weak_ptr = alloc_words(&mut mem, &[0xdeadbeef]);
+        let strong_ptr = alloc_words(&mut mem, &[0xdeadbeef]);
+        let bin = MachO {
+            name: "App".to_string(),
+            dynamic_libraries: Vec::new(),
+            sections: Vec::new(),
+            exported_symbols: HashMap::new(),
+            defined_symbols: Vec::new(),
+            external_relocations: vec![
+                (weak_ptr, "_NewFeatureSymbol".to_string()),
+                (strong_ptr, "_UnimplementedSymbol".to_string()),
+            ],
+            local_relocations: Vec::new(),
+            weak_imports: HashSet::from(["_NewFeatureSymbol".to_string()]),
+            load_slide: 0,
+        };
+        let bins = [bin];
+
+        let mut missing_symbols = MissingSymbols::new();
+        dyld.do_non_lazy_linking(&bins[0], &bins, &mut mem, &mut objc, &mut missing_symbols);
+
+        let weak_ptr: ConstPtr<ConstVoidPtr> = Ptr::from_bits(weak_ptr);
+        assert!(mem.read(weak_ptr).is_null());
+        let strong_ptr: ConstPtr<u32> = Ptr::from_bits(strong_ptr);
+        assert_eq!(mem.read(strong_ptr), 0xdeadbeef);
+        assert_eq!(
+            missing_symbols,
+            MissingSymbols::from([
+                ("_NewFeatureSymbol".to_string(), true),
+                ("_UnimplementedSymbol".to_string(), false),
+            ])
+        );
+    }
+
+    /// Give a class from [bin_with_class] its own `+load` method.
+    fn add_load_method(mem: &mut Mem, class: u32, imp: u32) {
+        let name = mem.alloc_and_write_cstr(b"load").to_bits();
+        let types = mem.alloc_and_write_cstr(b"v8@0:4").to_bits();
+        // method_list_t: entry size, count, followed by a method_t.
+        let methods = alloc_words(mem, &[12, 1, name, types, imp]);
+
+        // Replace the class_rw_t the metaclass shares with the class.
+        let class: ConstPtr<u32> = Ptr::from_bits(class);
+        let metaclass = mem.read(class);
+        let data: ConstPtr<u32> = Ptr::from_bits(mem.read(class + 4));
+        let name = mem.read(data + 4);
+        let data = alloc_words(mem, &[0, 4, 4, 0, name, methods, 0, 0, 0, 0]);
+        let metaclass_data: MutPtr<u32> = Ptr::from_bits(metaclass + 16);
+        mem.write(metaclass_data, data);
+    }
+
+    #[test]
+    fn initializers() {
+        let mut mem = Mem::new();
+        let mut objc = ObjC::new();
+        let mut dyld = Dyld::new();
+
+        // Put a subclass and its superclass in one binary, with the subclass
+        // listed first, and another subclass without its own +load.
+        let (sub, sub_class) = bin_with_class(&mut mem, "SubClass", Some("BaseClass"));
+        let (base, base_class) = bin_with_class(&mut mem, "BaseClass", None);
+        let (other, other_class) = bin_with_class(&mut mem, "OtherClass", Some("BaseClass"));
+        add_load_method(&mut mem, sub_class, 0x1000);
+        add_load_method(&mut mem, base_class, 0x2000);
+
+        let class_list = alloc_words(&mut mem, &[sub_class, other_class, base_class]);
+        let init_funcs = alloc_words(&mut mem, &[0x3001, 0x4000]);
+        let mut bin = sub;
+        bin.sections = vec![
+            Section {
+                name: "__objc_classlist".to_string(),
+                addr: class_list,
+                size: 12,
+                dyld_indirect_symbol_info: None,
+            },
+            Section {
+                name: "__mod_init_func".to_string(),
+                addr: init_funcs,
+                size: 8,
+                dyld_indirect_symbol_info: None,
+            },
+        ];
+        for other_bin in [base, other] {
+            bin.exported_symbols.extend(other_bin.exported_symbols);
+            bin.external_relocations
+                .extend(other_bin.external_relocations);
+        }
+        let bins = [bin];
+
+        let mut missing_symbols = MissingSymbols::new();
+        dyld.do_non_lazy_linking(&bins[0], &bins, &mut mem, &mut objc, &mut missing_symbols);
+        assert!(missing_symbols.is_empty());
+        objc.register_bin_classes(&bins[0], &mut mem);
+
+        let methods: Vec<(Class, u32)> = objc
+            .bin_class_load_methods(&bins[0], &mem)
+            .into_iter()
+            .map(|(class, _, imp)| (class, imp.addr_with_thumb_bit()))
+            .collect();
+        let expected: Vec<(Class, u32)> = vec![
+            (Ptr::from_bits(base_class), 0x2000),
+            (Ptr::from_bits(sub_class), 0x1000),
+        ];
+        assert_eq!(methods, expected);
+
+        let funcs: Vec<u32> = Dyld::mod_init_funcs(&bins[0], &mem)
+            .into_iter()
+            .map(|func| func.addr_with_thumb_bit())
+            .collect();
+        assert_eq!(funcs, vec![0x3001, 0x4000]);
     }
 }
@@ -6,15 +6,21 @@
 //! Separate module just for the constant lists, since this will probably be a
 //! very long and frequently-updated list.
 
-use crate::frameworks::{core_foundation, core_graphics, foundation, opengles};
+use crate::frameworks::{
+    core_animation, core_foundation, core_graphics, foundation, opengles, uikit,
+};
 use crate::libc;
 
 /// All the lists of constants that the linker should search through.
 pub const CONSTANT_LISTS: &[super::ConstantExports] = &[
     libc::ctype::CONSTANTS,
+    core_animation::ca_layer::CONSTANTS,
     core_foundation::cf_allocator::CONSTANTS,
     core_foundation::cf_run_loop::CONSTANTS,
     core_graphics::cg_color_space::CONSTANTS,
+    core_graphics::cg_geometry::CONSTANTS,
+    foundation::ns_file_manager::CONSTANTS,
     foundation::ns_run_loop::CONSTANTS,
     opengles::eagl::CONSTANTS,
+    uikit::ui_application::CONSTANTS,
 ];
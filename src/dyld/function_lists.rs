@@ -7,7 +7,8 @@
 //! very long and frequently-updated list.
 
 use crate::frameworks::{
-    audio_toolbox, core_foundation, core_graphics, foundation, openal, opengles, uikit,
+    audio_toolbox, core_animation, core_foundation, core_graphics, foundation, openal, opengles,
+    uikit,
 };
 use crate::libc;
 
@@ -16,6 +17,7 @@ pub const FUNCTION_LISTS: &[super::FunctionExports] = &[
     libc::ctype::FUNCTIONS,
     libc::cxxabi::FUNCTIONS,
     libc::dlfcn::FUNCTIONS,
+    libc::errno::FUNCTIONS,
     libc::keymgr::FUNCTIONS,
     libc::mach_thread_info::FUNCTIONS,
     libc::mach_time::FUNCTIONS,
@@ -23,23 +25,35 @@ pub const FUNCTION_LISTS: &[super::FunctionExports] = &[
     libc::pthread::key::FUNCTIONS,
     libc::pthread::mutex::FUNCTIONS,
     libc::pthread::once::FUNCTIONS,
+    libc::pthread::rwlock::FUNCTIONS,
     libc::pthread::thread::FUNCTIONS,
     libc::stdio::FUNCTIONS,
     libc::stdio::printf::FUNCTIONS,
+    libc::stdio::scanf::FUNCTIONS,
     libc::stdlib::FUNCTIONS,
     libc::string::FUNCTIONS,
     libc::time::FUNCTIONS,
+    libc::unistd::FUNCTIONS,
     crate::objc::FUNCTIONS,
     audio_toolbox::audio_file::FUNCTIONS,
     audio_toolbox::audio_queue::FUNCTIONS,
+    core_animation::ca_base::FUNCTIONS,
     core_foundation::cf_bundle::FUNCTIONS,
+    core_foundation::cf_data::FUNCTIONS,
+    core_foundation::cf_date::FUNCTIONS,
     core_foundation::cf_run_loop::FUNCTIONS,
     core_foundation::cf_type::FUNCTIONS,
     core_foundation::cf_url::FUNCTIONS,
+    core_graphics::cg_affine_transform::FUNCTIONS,
     core_graphics::cg_bitmap_context::FUNCTIONS,
+    core_graphics::cg_color::FUNCTIONS,
     core_graphics::cg_color_space::FUNCTIONS,
     core_graphics::cg_context::FUNCTIONS,
+    core_graphics::cg_data_provider::FUNCTIONS,
+    core_graphics::cg_geometry::FUNCTIONS,
+    core_graphics::cg_image::FUNCTIONS,
     foundation::ns_file_manager::FUNCTIONS,
+    foundation::ns_thread::FUNCTIONS,
     openal::FUNCTIONS,
     opengles::FUNCTIONS,
     uikit::ui_application::FUNCTIONS,
@@ -75,6 +75,27 @@ impl Font {
         Self::from_file("touchHLE_fonts/NotoSansJP-Bold.otf")
     }
 
+    /// Load a font from TrueType or OpenType data, e.g. a font bundled with
+    /// an app. Returns [None] if the data can't be parsed.
+    pub fn from_bytes(bytes: Vec<u8>) -> Option<Font> {
+        rusttype::Font::try_from_vec(bytes).map(|font| Font { font })
+    }
+
+    /// Distance from the baseline to the top of the tallest glyphs.
+    pub fn ascent(&self, font_size: f32) -> f32 {
+        self.font.v_metrics(scale(font_size)).ascent
+    }
+    /// Distance from the baseline to the bottom of the lowest glyphs. This is
+    /// negative.
+    pub fn descent(&self, font_size: f32) -> f32 {
+        self.font.v_metrics(scale(font_size)).descent
+    }
+    /// Distance between the baselines of consecutive lines of text.
+    pub fn line_height(&self, font_size: f32) -> f32 {
+        let (line_height, line_gap) = self.line_height_and_gap(font_size);
+        line_height + line_gap
+    }
+
     fn line_height_and_gap(&self, font_size: f32) -> (f32, f32) {
         let v_metrics = self.font.v_metrics(scale(font_size));
         (v_metrics.ascent - v_metrics.descent, v_metrics.line_gap)
@@ -95,7 +116,7 @@ impl Font {
     }
 
     /// Break text into lines with known widths.
-    fn break_lines<'a>(
+    pub fn break_lines<'a>(
         &self,
         font_size: f32,
         text: &'a str,
@@ -188,7 +209,8 @@ impl Font {
             .iter()
             .fold(0f32, |widest, &(line_width, _line)| widest.max(line_width));
         let (line_height, line_gap) = self.line_height_and_gap(font_size);
-        let height = line_height * (lines.len() as f32) + line_gap * ((lines.len() - 1) as f32);
+        let height =
+            line_height * (lines.len() as f32) + line_gap * (lines.len().saturating_sub(1) as f32);
 
         (width, height)
     }
@@ -207,7 +229,7 @@ impl Font {
         let lines = self.break_lines(font_size, text, wrap);
 
         let (line_height, line_gap) = self.line_height_and_gap(font_size);
-        let mut line_y = line_height * ((lines.len() - 1) as f32)
+        let mut line_y = line_height * (lines.len().saturating_sub(1) as f32)
             + line_gap * (lines.len().saturating_sub(2) as f32)
             - self.font.v_metrics(scale(font_size)).descent;
 
@@ -241,4 +263,172 @@ impl Font {
             line_y -= line_height + line_gap;
         }
     }
+
+    /// Draw a single line of text without any layout, with the start of its
+    /// baseline at the origin and y pointing up. Calls the provided callback
+    /// for each pixel, providing the coverage (a value between 0.0 and 1.0).
+    /// Returns the horizontal advance of the text.
+    pub fn draw_line<F: FnMut((i32, i32), f32)>(
+        &self,
+        font_size: f32,
+        text: &str,
+        mut put_pixel: F,
+    ) -> f32 {
+        let mut advance = 0.0;
+        for glyph in self
+            .font
+            .layout(text, scale(font_size), Point { x: 0.0, y: 0.0 })
+        {
+            advance = glyph.position().x + glyph.unpositioned().h_metrics().advance_width;
+            let Some(glyph_bounds) = glyph.pixel_bounding_box() else {
+                continue;
+            };
+            glyph.draw(|x, y, coverage| {
+                let (x, y) = (x as i32, y as i32);
+                // y needs to be flipped to point up
+                put_pixel(
+                    (glyph_bounds.min.x + x, -(glyph_bounds.min.y + y) - 1),
+                    coverage,
+                )
+            });
+        }
+        advance
+    }
+}
+
+/// The names of a font, as found in its `name` table.
+#[derive(Debug, PartialEq, Eq)]
+pub struct FontNames {
+    /// e.g. `Liberation Sans`
+    pub family: String,
+    /// e.g. `LiberationSans-Bold`. This is what `+[UIFont fontWithName:size:]`
+    /// expects.
+    pub postscript: String,
+}
+
+/// Read the family and PostScript names from TrueType or OpenType font data.
+///
+/// Resources:
+/// - Apple's [`name` table documentation](https://developer.apple.com/fonts/TrueType-Reference-Manual/RM06/Chap6name.html)
+pub fn read_font_names(bytes: &[u8]) -> Option<FontNames> {
+    let u16_at = |offset: usize| -> Option<u16> {
+        let bytes = bytes.get(offset..offset.checked_add(2)?)?;
+        Some(u16::from_be_bytes(bytes.try_into().unwrap()))
+    };
+    let u32_at = |offset: usize| -> Option<u32> {
+        let bytes = bytes.get(offset..offset.checked_add(4)?)?;
+        Some(u32::from_be_bytes(bytes.try_into().unwrap()))
+    };
+
+    let table_count = u16_at(4)?;
+    let table_record = (0..usize::from(table_count))
+        .map(|i| 12 + i * 16)
+        .find(|&record| bytes.get(record..record + 4) == Some(&b"name"[..]))?;
+    let table = u32_at(table_record + 8)? as usize;
+    let name_count = u16_at(table + 2)?;
+    let strings = table + usize::from(u16_at(table + 4)?);
+
+    let mut family = None;
+    let mut postscript = None;
+    for i in 0..usize::from(name_count) {
+        let record = table + 6 + i * 12;
+        let platform_id = u16_at(record)?;
+        let name_id = u16_at(record + 6)?;
+        let length = usize::from(u16_at(record + 8)?);
+        let offset = strings + usize::from(u16_at(record + 10)?);
+        let slot = match name_id {
+            1 => &mut family,
+            6 => &mut postscript,
+            _ => continue,
+        };
+        if slot.is_some() {
+            continue;
+        }
+        let data = bytes.get(offset..offset + length)?;
+        *slot = match platform_id {
+            // Unicode and Windows platform names are UTF-16BE.
+            0 | 3 => {
+                let code_units: Vec<u16> = data
+                    .chunks_exact(2)
+                    .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+                    .collect();
+                String::from_utf16(&code_units).ok()
+            }
+            // Macintosh platform names are in Mac OS Roman, but PostScript and
+            // family names are in practice ASCII.
+            1 => std::str::from_utf8(data)
+                .ok()
+                .filter(|name| name.is_ascii())
+                .map(String::from),
+            _ => None,
+        };
+    }
+
+    Some(FontNames {
+        family: family?,
+        postscript: postscript?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bundled_font_names_and_metrics() {
+        let path = "touchHLE_fonts/LiberationSans-Regular.ttf";
+        let bytes = std::fs::read(path).unwrap();
+        assert_eq!(
+            read_font_names(&bytes),
+            Some(FontNames {
+                family: "Liberation Sans".to_string(),
+                postscript: "LiberationSans".to_string(),
+            })
+        );
+        assert_eq!(read_font_names(&bytes[..100]), None);
+
+        let font = Font::from_bytes(bytes).unwrap();
+        let (ascent, descent) = (font.ascent(16.0), font.descent(16.0));
+        assert!(ascent > 10.0 && ascent < 20.0);
+        assert!(descent < 0.0 && descent > -10.0);
+        assert!(font.line_height(16.0) >= ascent - descent);
+        assert!((font.ascent(32.0) - ascent * 2.0).abs() < 0.001);
+    }
+
+    #[test]
+    fn measure_and_draw_text() {
+        let font = Font::sans_regular();
+
+        // Advance widths of "Hello, world" in Liberation Sans add up to about
+        // 5.2em, and the ink is slightly narrower than that.
+        let (width, height) = font.calculate_text_size(16.0, "Hello, world", None);
+        assert!((85.0..95.0).contains(&width), "{}", width);
+        assert_eq!(height, font.ascent(16.0) - font.descent(16.0));
+
+        // Wrapping at the space gives two lines.
+        let (wrapped_width, wrapped_height) =
+            font.calculate_text_size(16.0, "Hello, world", Some((60.0, WrapMode::Word)));
+        assert!(wrapped_width <= 60.0);
+        assert!((wrapped_height - (height + font.line_height(16.0))).abs() < 0.01);
+
+        assert_eq!(font.calculate_text_size(16.0, "", None), (0.0, 0.0));
+
+        let mut covered = 0;
+        let mut max_x = 0;
+        font.draw(
+            16.0,
+            "Hello, world",
+            (0.0, 0.0),
+            None,
+            TextAlignment::Left,
+            |(x, _y), coverage| {
+                if coverage > 0.0 {
+                    covered += 1;
+                    max_x = max_x.max(x);
+                }
+            },
+        );
+        assert!(covered > 100);
+        assert!((max_x as f32 - width).abs() <= 2.0);
+    }
 }
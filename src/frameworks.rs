@@ -35,6 +35,8 @@ pub mod uikit;
 #[derive(Default)]
 pub struct State {
     audio_toolbox: audio_toolbox::State,
+    core_animation: core_animation::State,
+    core_graphics: core_graphics::State,
     foundation: foundation::State,
     openal: openal::State,
     opengles: opengles::State,
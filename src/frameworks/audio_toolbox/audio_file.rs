@@ -42,6 +42,8 @@ unsafe impl SafeRead for OpaqueAudioFileID {}
 type AudioFileID = MutPtr<OpaqueAudioFileID>;
 
 const kAudioFileFileNotFoundError: OSStatus = -43;
+const kAudioFileEndOfFileError: OSStatus = -39;
+const kAudioFileUnsupportedPropertyError: OSStatus = fourcc(b"pty?") as _;
 const kAudioFileBadPropertySizeError: OSStatus = fourcc(b"!siz") as _;
 
 type AudioFilePermissions = i8;
@@ -56,6 +58,16 @@ const kAudioFilePropertyDataFormat: AudioFilePropertyID = fourcc(b"dfmt");
 const kAudioFilePropertyAudioDataByteCount: AudioFilePropertyID = fourcc(b"bcnt");
 const kAudioFilePropertyAudioDataPacketCount: AudioFilePropertyID = fourcc(b"pcnt");
 const kAudioFilePropertyPacketSizeUpperBound: AudioFilePropertyID = fourcc(b"pkub");
+const kAudioFilePropertyMagicCookieData: AudioFilePropertyID = fourcc(b"mgic");
+const kAudioFilePropertyPacketTableInfo: AudioFilePropertyID = fourcc(b"pnfo");
+
+#[repr(C, packed)]
+struct AudioFilePacketTableInfo {
+    number_valid_frames: i64,
+    priming_frames: i32,
+    remainder_frames: i32,
+}
+unsafe impl SafeRead for AudioFilePacketTableInfo {}
 
 fn AudioFileOpenURL(
     env: &mut Environment,
@@ -93,6 +105,49 @@ fn AudioFileOpenURL(
     0 // success
 }
 
+/// Size of a property's data, or [None] if the property is not supported.
+fn property_size(property_id: AudioFilePropertyID) -> Option<GuestUSize> {
+    Some(match property_id {
+        kAudioFilePropertyDataFormat => guest_size_of::<AudioStreamBasicDescription>(),
+        kAudioFilePropertyAudioDataByteCount => guest_size_of::<u64>(),
+        kAudioFilePropertyAudioDataPacketCount => guest_size_of::<u64>(),
+        kAudioFilePropertyPacketSizeUpperBound => guest_size_of::<u32>(),
+        // None of the formats we support have a magic cookie: compressed
+        // formats are presented as already decoded.
+        kAudioFilePropertyMagicCookieData => 0,
+        kAudioFilePropertyPacketTableInfo => guest_size_of::<AudioFilePacketTableInfo>(),
+        _ => return None,
+    })
+}
+
+fn AudioFileGetPropertyInfo(
+    env: &mut Environment,
+    in_audio_file: AudioFileID,
+    in_property_id: AudioFilePropertyID,
+    out_data_size: MutPtr<u32>,
+    is_writable: MutPtr<u32>,
+) -> OSStatus {
+    assert!(State::get(&mut env.framework_state)
+        .audio_files
+        .contains_key(&in_audio_file));
+
+    let Some(size) = property_size(in_property_id) else {
+        log!(
+            "Warning: AudioFileGetPropertyInfo() for unimplemented property ID: {}",
+            debug_fourcc(in_property_id)
+        );
+        return kAudioFileUnsupportedPropertyError;
+    };
+    if !out_data_size.is_null() {
+        env.mem.write(out_data_size, size);
+    }
+    if !is_writable.is_null() {
+        env.mem.write(is_writable, 0); // writing TODO
+    }
+
+    0 // success
+}
+
 fn AudioFileGetProperty(
     env: &mut Environment,
     in_audio_file: AudioFileID,
@@ -100,15 +155,11 @@ fn AudioFileGetProperty(
     io_data_size: MutPtr<u32>,
     out_property_data: MutVoidPtr,
 ) -> OSStatus {
-    let required_size: GuestUSize = match in_property_id {
-        kAudioFilePropertyDataFormat => guest_size_of::<AudioStreamBasicDescription>(),
-        kAudioFilePropertyAudioDataByteCount => guest_size_of::<u64>(),
-        kAudioFilePropertyAudioDataPacketCount => guest_size_of::<u64>(),
-        kAudioFilePropertyPacketSizeUpperBound => guest_size_of::<u32>(),
-        _ => unimplemented!(
+    let Some(required_size) = property_size(in_property_id) else {
+        unimplemented!(
             "Unimplemented property ID: {}",
             debug_fourcc(in_property_id)
-        ),
+        );
     };
     if env.mem.read(io_data_size) != required_size {
         log!("Warning: AudioFileGetProperty() failed");
@@ -183,6 +234,22 @@ fn AudioFileGetProperty(
             env.mem
                 .write(out_property_data.cast(), packet_size_upper_bound);
         }
+        kAudioFilePropertyMagicCookieData => (),
+        kAudioFilePropertyPacketTableInfo => {
+            // Only fixed-size packets (and no encoder delay or padding) are
+            // currently supported, so every frame is valid.
+            let frames_per_packet = host_object.audio_file.audio_description().frames_per_packet;
+            let number_valid_frames =
+                host_object.audio_file.packet_count() * u64::from(frames_per_packet);
+            env.mem.write(
+                out_property_data.cast(),
+                AudioFilePacketTableInfo {
+                    number_valid_frames: number_valid_frames.try_into().unwrap(),
+                    priming_frames: 0,
+                    remainder_frames: 0,
+                },
+            );
+        }
         _ => unreachable!(),
     }
 
@@ -209,10 +276,13 @@ fn AudioFileReadBytes(
         .audio_file
         .read_bytes(in_starting_byte.try_into().unwrap(), buffer_slice)
         .unwrap(); // TODO: handle seek error?
-    assert!((bytes_read as u64) == (bytes_to_read as u64)); // TODO: return eofErr
     env.mem.write(io_num_bytes, bytes_read.try_into().unwrap());
 
-    0 // success
+    if (bytes_read as u64) < (bytes_to_read as u64) {
+        kAudioFileEndOfFileError
+    } else {
+        0 // success
+    }
 }
 
 fn AudioFileReadPackets(
@@ -234,8 +304,12 @@ fn AudioFileReadPackets(
         .get_mut(&in_audio_file)
         .unwrap();
     let packet_size = host_object.audio_file.packet_size_fixed();
+    let packet_count = host_object.audio_file.packet_count();
 
+    // Reading past the end of the file just gives fewer packets.
     let packets_to_read = env.mem.read(io_num_packets);
+    let packets_remaining = packet_count.saturating_sub(in_starting_packet.try_into().unwrap());
+    let packets_to_read = packets_to_read.min(packets_remaining.try_into().unwrap_or(u32::MAX));
 
     let starting_byte = i64::from(packet_size)
         .checked_mul(in_starting_packet)
@@ -274,6 +348,7 @@ fn AudioFileClose(env: &mut Environment, in_audio_file: AudioFileID) -> OSStatus
 
 pub const FUNCTIONS: FunctionExports = &[
     export_c_func!(AudioFileOpenURL(_, _, _, _)),
+    export_c_func!(AudioFileGetPropertyInfo(_, _, _, _)),
     export_c_func!(AudioFileGetProperty(_, _, _, _)),
     export_c_func!(AudioFileReadBytes(_, _, _, _, _)),
     export_c_func!(AudioFileReadPackets(_, _, _, _, _, _, _)),
@@ -15,8 +15,9 @@ use crate::audio::openal::al_types::*;
 use crate::audio::openal::alc_types::*;
 use crate::dyld::{export_c_func, FunctionExports};
 use crate::frameworks::core_audio_types::{
-    kAudioFormatAppleIMA4, kAudioFormatFlagIsBigEndian, kAudioFormatFlagIsFloat,
-    kAudioFormatFlagIsPacked, kAudioFormatLinearPCM, AudioStreamBasicDescription,
+    debug_fourcc, fourcc, kAudioFormatAppleIMA4, kAudioFormatFlagIsBigEndian,
+    kAudioFormatFlagIsFloat, kAudioFormatFlagIsPacked, kAudioFormatLinearPCM,
+    AudioStreamBasicDescription,
 };
 use crate::frameworks::core_foundation::cf_run_loop::{
     kCFRunLoopCommonModes, CFRunLoopMode, CFRunLoopRef,
@@ -24,7 +25,9 @@ use crate::frameworks::core_foundation::cf_run_loop::{
 use crate::frameworks::foundation::ns_run_loop;
 use crate::frameworks::foundation::ns_string::get_static_str;
 use crate::frameworks::mac_types::OSStatus;
-use crate::mem::{ConstPtr, ConstVoidPtr, GuestUSize, Mem, MutPtr, MutVoidPtr, Ptr, SafeRead};
+use crate::mem::{
+    guest_size_of, ConstPtr, ConstVoidPtr, GuestUSize, Mem, MutPtr, MutVoidPtr, Ptr, SafeRead,
+};
 use crate::objc::msg;
 use crate::Environment;
 use std::collections::{HashMap, VecDeque};
@@ -82,6 +85,9 @@ struct AudioQueueHostObject {
     /// Weak reference
     run_loop: CFRunLoopRef,
     volume: f32,
+    /// Set by the app for formats whose decoder needs it. None of the formats
+    /// we currently decode use it, so it is only stored.
+    magic_cookie: Vec<u8>,
     buffers: Vec<AudioQueueBufferRef>,
     /// There is also a queue of OpenAL buffers, which must be kept in sync:
     /// the nth item in this queue must also be the nth item in the OpenAL
@@ -89,11 +95,23 @@ struct AudioQueueHostObject {
     buffer_queue: VecDeque<AudioQueueBufferRef>,
     /// Tracks whether this audio queue has been started, so we can restart the
     /// OpenAL source if it automatically stops due to running out of data.
-    is_running: bool,
+    playback_state: PlaybackState,
     al_source: Option<ALuint>,
     al_unused_buffers: Vec<ALuint>,
 }
 
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum PlaybackState {
+    /// Not started, or stopped.
+    Stopped,
+    Running,
+    /// Paused with `AudioQueuePause()`, can be resumed with `AudioQueueStart()`.
+    Paused,
+    /// Stopped with `AudioQueueStop()` but not immediately: the queue keeps
+    /// playing until the buffers already enqueued have been played.
+    Stopping,
+}
+
 #[repr(C, packed)]
 pub struct OpaqueAudioQueue {
     _filler: u8,
@@ -126,6 +144,16 @@ const kAudioQueueParam_Volume: AudioQueueParameterID = 1;
 
 type AudioQueueParameterValue = f32;
 
+/// Usually a FourCC.
+type AudioQueuePropertyID = u32;
+const kAudioQueueProperty_IsRunning: AudioQueuePropertyID = fourcc(b"aqrn");
+const kAudioQueueProperty_StreamDescription: AudioQueuePropertyID = fourcc(b"aqft");
+const kAudioQueueProperty_MagicCookie: AudioQueuePropertyID = fourcc(b"aqmc");
+
+const kAudioQueueErr_InvalidProperty: OSStatus = -66684;
+const kAudioQueueErr_InvalidPropertySize: OSStatus = -66683;
+const kAudioQueueErr_InvalidParameter: OSStatus = -66682;
+
 fn AudioQueueNewOutput(
     env: &mut Environment,
     in_format: ConstPtr<AudioStreamBasicDescription>,
@@ -156,9 +184,10 @@ fn AudioQueueNewOutput(
         callback_user_data: in_user_data,
         run_loop: in_callback_run_loop,
         volume: 1.0,
+        magic_cookie: Vec::new(),
         buffers: Vec::new(),
         buffer_queue: VecDeque::new(),
-        is_running: false,
+        playback_state: PlaybackState::Stopped,
         al_source: None,
         al_unused_buffers: Vec::new(),
     };
@@ -184,13 +213,43 @@ fn AudioQueueNewOutput(
     0 // success
 }
 
+fn AudioQueueGetParameter(
+    env: &mut Environment,
+    in_aq: AudioQueueRef,
+    in_param_id: AudioQueueParameterID,
+    out_value: MutPtr<AudioQueueParameterValue>,
+) -> OSStatus {
+    if in_param_id != kAudioQueueParam_Volume {
+        log!(
+            "Warning: AudioQueueGetParameter() for unimplemented parameter {}",
+            in_param_id
+        );
+        return kAudioQueueErr_InvalidParameter;
+    }
+
+    let host_object = State::get(&mut env.framework_state)
+        .audio_queues
+        .get(&in_aq)
+        .unwrap();
+    let volume = host_object.volume;
+    env.mem.write(out_value, volume);
+
+    0 // success
+}
+
 fn AudioQueueSetParameter(
     env: &mut Environment,
     in_aq: AudioQueueRef,
     in_param_id: AudioQueueParameterID,
     in_value: AudioQueueParameterValue,
 ) -> OSStatus {
-    assert!(in_param_id == kAudioQueueParam_Volume); // others unimplemented
+    if in_param_id != kAudioQueueParam_Volume {
+        log!(
+            "Warning: AudioQueueSetParameter() for unimplemented parameter {}",
+            in_param_id
+        );
+        return kAudioQueueErr_InvalidParameter;
+    }
 
     let state = State::get(&mut env.framework_state);
     let host_object = state.audio_queues.get_mut(&in_aq).unwrap();
@@ -207,6 +266,123 @@ fn AudioQueueSetParameter(
     0 // success
 }
 
+/// Size of a property's data, or [None] if the property is not supported.
+fn property_size(
+    host_object: &AudioQueueHostObject,
+    property_id: AudioQueuePropertyID,
+) -> Option<GuestUSize> {
+    match property_id {
+        kAudioQueueProperty_IsRunning => Some(guest_size_of::<u32>()),
+        kAudioQueueProperty_StreamDescription => {
+            Some(guest_size_of::<AudioStreamBasicDescription>())
+        }
+        kAudioQueueProperty_MagicCookie => Some(host_object.magic_cookie.len() as GuestUSize),
+        _ => None,
+    }
+}
+
+fn AudioQueueGetPropertySize(
+    env: &mut Environment,
+    in_aq: AudioQueueRef,
+    in_id: AudioQueuePropertyID,
+    out_data_size: MutPtr<u32>,
+) -> OSStatus {
+    let host_object = State::get(&mut env.framework_state)
+        .audio_queues
+        .get(&in_aq)
+        .unwrap();
+    let Some(size) = property_size(host_object, in_id) else {
+        log!(
+            "Warning: AudioQueueGetPropertySize() for unimplemented property {}",
+            debug_fourcc(in_id)
+        );
+        return kAudioQueueErr_InvalidProperty;
+    };
+    env.mem.write(out_data_size, size);
+    0 // success
+}
+
+fn AudioQueueGetProperty(
+    env: &mut Environment,
+    in_aq: AudioQueueRef,
+    in_id: AudioQueuePropertyID,
+    out_data: MutVoidPtr,
+    io_data_size: MutPtr<u32>,
+) -> OSStatus {
+    let host_object = State::get(&mut env.framework_state)
+        .audio_queues
+        .get(&in_aq)
+        .unwrap();
+    let Some(size) = property_size(host_object, in_id) else {
+        log!(
+            "Warning: AudioQueueGetProperty() for unimplemented property {}",
+            debug_fourcc(in_id)
+        );
+        return kAudioQueueErr_InvalidProperty;
+    };
+    if env.mem.read(io_data_size) < size {
+        log!(
+            "Warning: AudioQueueGetProperty() for property {} failed: buffer too small",
+            debug_fourcc(in_id)
+        );
+        return kAudioQueueErr_InvalidPropertySize;
+    }
+
+    match in_id {
+        kAudioQueueProperty_IsRunning => {
+            // Pausing doesn't stop the queue, and an asynchronous stop only
+            // takes effect once the queue has finished playing.
+            let is_running = host_object.playback_state != PlaybackState::Stopped;
+            env.mem.write(out_data.cast(), u32::from(is_running));
+        }
+        kAudioQueueProperty_StreamDescription => {
+            let format = host_object.format;
+            env.mem.write(out_data.cast(), format);
+        }
+        kAudioQueueProperty_MagicCookie => {
+            let cookie = host_object.magic_cookie.clone();
+            env.mem
+                .bytes_at_mut(out_data.cast(), size)
+                .copy_from_slice(&cookie);
+        }
+        _ => unreachable!(),
+    }
+    env.mem.write(io_data_size, size);
+
+    0 // success
+}
+
+fn AudioQueueSetProperty(
+    env: &mut Environment,
+    in_aq: AudioQueueRef,
+    in_id: AudioQueuePropertyID,
+    in_data: ConstVoidPtr,
+    in_data_size: u32,
+) -> OSStatus {
+    if in_id != kAudioQueueProperty_MagicCookie {
+        // The other properties we know about are read-only.
+        log!(
+            "Warning: AudioQueueSetProperty() for unimplemented property {}",
+            debug_fourcc(in_id)
+        );
+        return kAudioQueueErr_InvalidProperty;
+    }
+
+    let cookie = env.mem.bytes_at(in_data.cast(), in_data_size).to_vec();
+    log_dbg!(
+        "Audio queue {:?} got a {}-byte magic cookie",
+        in_aq,
+        cookie.len()
+    );
+    State::get(&mut env.framework_state)
+        .audio_queues
+        .get_mut(&in_aq)
+        .unwrap()
+        .magic_cookie = cookie;
+
+    0 // success
+}
+
 fn AudioQueueAllocateBuffer(
     env: &mut Environment,
     in_aq: AudioQueueRef,
@@ -408,6 +584,24 @@ fn prime_audio_queue(
     context_manager
 }
 
+/// Throw away all buffers enqueued on an audio queue without playing them.
+/// The OpenAL source is stopped if there is one, so a current context is
+/// needed.
+fn discard_queued_buffers(host_object: &mut AudioQueueHostObject) {
+    host_object.buffer_queue.clear();
+    let Some(al_source) = host_object.al_source else {
+        return;
+    };
+    unsafe {
+        al::alSourceStop(al_source);
+        assert!(al::alGetError() == 0);
+    }
+    // All buffers on a stopped source count as processed.
+    unqueue_buffers(al_source, |al_buffer| {
+        host_object.al_unused_buffers.push(al_buffer)
+    });
+}
+
 fn unqueue_buffers<F: FnMut(ALuint)>(al_source: ALuint, mut callback: F) {
     loop {
         let mut al_buffers_processed = 0;
@@ -462,7 +656,6 @@ pub fn handle_audio_queue(env: &mut Environment, in_aq: AudioQueueRef) {
     let &mut AudioQueueHostObject {
         callback_proc,
         callback_user_data,
-        is_running,
         ..
     } = host_object;
 
@@ -482,7 +675,22 @@ pub fn handle_audio_queue(env: &mut Environment, in_aq: AudioQueueRef) {
 
     let _context_manager = prime_audio_queue(env, in_aq, Some(context_manager));
 
-    if is_running {
+    let host_object = State::get(&mut env.framework_state)
+        .audio_queues
+        .get_mut(&in_aq)
+        .unwrap();
+
+    // An asynchronous stop completes once everything has been played.
+    if host_object.playback_state == PlaybackState::Stopping && host_object.buffer_queue.is_empty()
+    {
+        log_dbg!("Audio queue {:?} has finished stopping", in_aq);
+        host_object.playback_state = PlaybackState::Stopped;
+    }
+
+    if matches!(
+        host_object.playback_state,
+        PlaybackState::Running | PlaybackState::Stopping
+    ) {
         unsafe {
             let mut al_source_state = 0;
             al::alGetSourcei(al_source, al::AL_SOURCE_STATE, &mut al_source_state);
@@ -525,8 +733,14 @@ fn AudioQueueStart(
         .get_mut(&in_aq)
         .unwrap();
 
-    host_object.is_running = true;
+    log_dbg!(
+        "Starting audio queue {:?} (was {:?})",
+        in_aq,
+        host_object.playback_state
+    );
+    host_object.playback_state = PlaybackState::Running;
 
+    // If the queue was paused, this resumes playback where it left off.
     if is_supported_audio_format(&host_object.format) {
         let al_source = host_object.al_source.unwrap();
         unsafe { al::alSourcePlay(al_source) };
@@ -536,23 +750,89 @@ fn AudioQueueStart(
     0 // success
 }
 
+fn AudioQueuePause(env: &mut Environment, in_aq: AudioQueueRef) -> OSStatus {
+    let state = State::get(&mut env.framework_state);
+
+    let _context_manager = state.make_al_context_current();
+
+    let host_object = state.audio_queues.get_mut(&in_aq).unwrap();
+    if host_object.playback_state != PlaybackState::Running {
+        log_dbg!(
+            "Ignoring pause of audio queue {:?} ({:?})",
+            in_aq,
+            host_object.playback_state
+        );
+        return 0; // success
+    }
+    log_dbg!("Pausing audio queue {:?}", in_aq);
+    host_object.playback_state = PlaybackState::Paused;
+
+    if let Some(al_source) = host_object.al_source {
+        unsafe { al::alSourcePause(al_source) };
+        assert!(unsafe { al::alGetError() } == 0);
+    }
+
+    0 // success
+}
+
 fn AudioQueueStop(env: &mut Environment, in_aq: AudioQueueRef, in_immediate: bool) -> OSStatus {
     let state = State::get(&mut env.framework_state);
 
     let _context_manager = state.make_al_context_current();
 
     // This happens in Super Monkey Ball. TODO: figure out why.
-    let Some(mut host_object) = state.audio_queues.get_mut(&in_aq) else {
+    let Some(host_object) = state.audio_queues.get_mut(&in_aq) else {
         log!("Tolerating stopping of unknown audio queue {:?}", in_aq);
         return 0; // success
     };
-    host_object.is_running = false;
 
-    if in_immediate {
-        if let Some(al_source) = host_object.al_source {
-            unsafe { al::alSourceStop(al_source) };
-            assert!(unsafe { al::alGetError() } == 0);
-        }
+    log_dbg!(
+        "Stopping audio queue {:?} (was {:?}, immediate: {})",
+        in_aq,
+        host_object.playback_state,
+        in_immediate
+    );
+
+    if in_immediate || host_object.playback_state != PlaybackState::Running {
+        // A paused queue has nothing to drain into, so it stops at once too.
+        host_object.playback_state = PlaybackState::Stopped;
+        discard_queued_buffers(host_object);
+    } else {
+        // Let the run loop play whatever is left, see handle_audio_queue().
+        host_object.playback_state = PlaybackState::Stopping;
+    }
+
+    0 // success
+}
+
+/// Our decoding has no internal state or latency, so there is never anything
+/// left to flush, but this still has to exist.
+fn AudioQueueFlush(env: &mut Environment, in_aq: AudioQueueRef) -> OSStatus {
+    assert!(State::get(&mut env.framework_state)
+        .audio_queues
+        .contains_key(&in_aq));
+    log_dbg!("AudioQueueFlush({:?})", in_aq);
+    0 // success
+}
+
+fn AudioQueueReset(env: &mut Environment, in_aq: AudioQueueRef) -> OSStatus {
+    let state = State::get(&mut env.framework_state);
+
+    let _context_manager = state.make_al_context_current();
+
+    let host_object = state.audio_queues.get_mut(&in_aq).unwrap();
+
+    log_dbg!(
+        "Resetting audio queue {:?} ({:?})",
+        in_aq,
+        host_object.playback_state
+    );
+
+    // The queue keeps its playback state: a running queue carries on playing
+    // once new buffers are enqueued (see handle_audio_queue()).
+    discard_queued_buffers(host_object);
+    if host_object.playback_state == PlaybackState::Stopping {
+        host_object.playback_state = PlaybackState::Stopped;
     }
 
     0 // success
@@ -607,11 +887,18 @@ fn AudioQueueDispose(env: &mut Environment, in_aq: AudioQueueRef, in_immediate:
 
 pub const FUNCTIONS: FunctionExports = &[
     export_c_func!(AudioQueueNewOutput(_, _, _, _, _, _, _)),
+    export_c_func!(AudioQueueGetParameter(_, _, _)),
     export_c_func!(AudioQueueSetParameter(_, _, _)),
+    export_c_func!(AudioQueueGetPropertySize(_, _, _)),
+    export_c_func!(AudioQueueGetProperty(_, _, _, _)),
+    export_c_func!(AudioQueueSetProperty(_, _, _, _)),
     export_c_func!(AudioQueueAllocateBuffer(_, _, _)),
     export_c_func!(AudioQueueEnqueueBuffer(_, _, _, _)),
     export_c_func!(AudioQueuePrime(_, _, _)),
     export_c_func!(AudioQueueStart(_, _)),
+    export_c_func!(AudioQueuePause(_)),
     export_c_func!(AudioQueueStop(_, _)),
+    export_c_func!(AudioQueueFlush(_)),
+    export_c_func!(AudioQueueReset(_)),
     export_c_func!(AudioQueueDispose(_, _)),
 ];
@@ -5,5 +5,13 @@
  */
 //! The Core Animation framework.
 
+pub mod ca_base;
+pub mod ca_display_link;
 pub mod ca_eagl_layer;
 pub mod ca_layer;
+pub mod composition;
+
+#[derive(Default)]
+pub struct State {
+    composition: composition::State,
+}
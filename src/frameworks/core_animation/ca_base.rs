@@ -0,0 +1,25 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! `CABase.h`
+
+use crate::dyld::{export_c_func, FunctionExports};
+use crate::frameworks::core_foundation::cf_date::CFTimeInterval;
+use crate::Environment;
+use std::time::Instant;
+
+/// Convert an [Instant] to the time base used by [CACurrentMediaTime], which
+/// is the same as `mach_absolute_time()`'s, but in seconds.
+pub fn media_time_from_instant(env: &Environment, instant: Instant) -> CFTimeInterval {
+    instant
+        .saturating_duration_since(env.startup_time)
+        .as_secs_f64()
+}
+
+fn CACurrentMediaTime(env: &mut Environment) -> CFTimeInterval {
+    media_time_from_instant(env, Instant::now())
+}
+
+pub const FUNCTIONS: FunctionExports = &[export_c_func!(CACurrentMediaTime())];
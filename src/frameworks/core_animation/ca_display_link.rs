@@ -0,0 +1,196 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! `CADisplayLink`.
+//!
+//! The emulated display refreshes at a fixed 60Hz, starting from the same
+//! point as [super::ca_base::CACurrentMediaTime]'s time base. Display links
+//! fire at most once per refresh, from the run loop they were added to.
+
+use super::ca_base::media_time_from_instant;
+use crate::frameworks::core_foundation::cf_date::CFTimeInterval;
+use crate::frameworks::foundation::ns_run_loop::{self, NSRunLoopMode};
+use crate::frameworks::foundation::NSInteger;
+use crate::objc::{
+    autorelease, id, msg_class, msg_send, nil, objc_classes, release, retain, ClassExports,
+    HostObject, SEL,
+};
+use crate::Environment;
+use std::time::Instant;
+
+/// The refresh rate of the emulated display, in Hz.
+const REFRESH_RATE: u32 = 60;
+
+struct CADisplayLinkHostObject {
+    /// Strong reference
+    target: id,
+    selector: SEL,
+    frame_interval: NSInteger,
+    paused: bool,
+    invalidated: bool,
+    /// Index of the earliest display refresh the link may next fire on.
+    next_refresh: u64,
+    timestamp: CFTimeInterval,
+    /// Weak reference
+    run_loop: id,
+}
+impl HostObject for CADisplayLinkHostObject {}
+
+pub const CLASSES: ClassExports = objc_classes! {
+
+(env, this, _cmd);
+
+@implementation CADisplayLink: NSObject
+
++ (id)displayLinkWithTarget:(id)target
+                   selector:(SEL)selector {
+    retain(env, target);
+
+    let host_object = Box::new(CADisplayLinkHostObject {
+        target,
+        selector,
+        frame_interval: 1,
+        paused: false,
+        invalidated: false,
+        next_refresh: 0,
+        timestamp: 0.0,
+        run_loop: nil,
+    });
+    let new = env.objc.alloc_object(this, host_object, &mut env.mem);
+
+    log_dbg!(
+        "New display link {:?}, target [{:?} {}]",
+        new,
+        target,
+        selector.as_str(&env.mem),
+    );
+
+    autorelease(env, new)
+}
+
+- (())dealloc {
+    let &CADisplayLinkHostObject { target, .. } = env.objc.borrow(this);
+    release(env, target);
+    env.objc.dealloc_object(this, &mut env.mem)
+}
+
+- (())addToRunLoop:(id)run_loop // NSRunLoop*
+           forMode:(NSRunLoopMode)mode {
+    let host_object = env.objc.borrow_mut::<CADisplayLinkHostObject>(this);
+    if host_object.invalidated {
+        return;
+    }
+    // Run loop modes aren't distinguished, so adding the link for another mode
+    // doesn't change anything.
+    if host_object.run_loop == run_loop {
+        return;
+    }
+    if host_object.run_loop != nil {
+        log!(
+            "TODO: adding display link {:?} to a second run loop {:?}, ignoring",
+            this,
+            run_loop
+        );
+        return;
+    }
+    host_object.run_loop = run_loop;
+
+    log_dbg!("Adding display link {:?} to run loop {:?}", this, run_loop);
+
+    ns_run_loop::add_display_link(env, run_loop, this, mode);
+}
+
+- (())invalidate {
+    let host_object = env.objc.borrow_mut::<CADisplayLinkHostObject>(this);
+    if host_object.invalidated {
+        return;
+    }
+    host_object.invalidated = true;
+    let run_loop = std::mem::replace(&mut host_object.run_loop, nil);
+    let target = std::mem::replace(&mut host_object.target, nil);
+
+    log_dbg!("Invalidating display link {:?}", this);
+
+    release(env, target);
+    // This must be last, because the run loop might own the only reference
+    // to the display link.
+    if run_loop != nil {
+        ns_run_loop::remove_display_link(env, run_loop, this);
+    }
+}
+
+- (NSInteger)frameInterval {
+    env.objc.borrow::<CADisplayLinkHostObject>(this).frame_interval
+}
+- (())setFrameInterval:(NSInteger)frame_interval {
+    // Apple's documentation says values less than 1 are ignored.
+    if frame_interval >= 1 {
+        env.objc.borrow_mut::<CADisplayLinkHostObject>(this).frame_interval = frame_interval;
+    }
+}
+
+- (bool)isPaused {
+    env.objc.borrow::<CADisplayLinkHostObject>(this).paused
+}
+- (())setPaused:(bool)paused {
+    env.objc.borrow_mut::<CADisplayLinkHostObject>(this).paused = paused;
+}
+
+- (CFTimeInterval)timestamp {
+    env.objc.borrow::<CADisplayLinkHostObject>(this).timestamp
+}
+- (CFTimeInterval)duration {
+    1.0 / REFRESH_RATE as CFTimeInterval
+}
+
+@end
+
+};
+
+/// For use by `NSRunLoop`: check if a display link is due to fire and fire it
+/// if necessary. Returns [true] if it fired.
+pub fn handle_display_link(env: &mut Environment, link: id) -> bool {
+    let &CADisplayLinkHostObject {
+        target,
+        selector,
+        frame_interval,
+        paused,
+        invalidated,
+        next_refresh,
+        ..
+    } = env.objc.borrow(link);
+
+    if paused || invalidated {
+        return false;
+    }
+
+    let now = media_time_from_instant(env, Instant::now());
+    let refresh = (now * REFRESH_RATE as f64).floor() as u64;
+    if refresh < next_refresh {
+        return false;
+    }
+
+    // Like with NSTimer, missed refreshes are not caught up on.
+    let host_object = env.objc.borrow_mut::<CADisplayLinkHostObject>(link);
+    host_object.next_refresh = refresh + u64::try_from(frame_interval).unwrap();
+    host_object.timestamp = refresh as f64 / REFRESH_RATE as f64;
+
+    log_dbg!(
+        "Display link {:?} fired, sending {:?} message to {:?}",
+        link,
+        selector.as_str(&env.mem),
+        target
+    );
+
+    // The display link may be invalidated by its target.
+    retain(env, link);
+    let pool: id = msg_class![env; NSAutoreleasePool new];
+    // Signature should be `- (void)displayLinkDidFire:(CADisplayLink *)link`.
+    let _: () = msg_send(env, (target, selector, link));
+    release(env, pool);
+    release(env, link);
+
+    true
+}
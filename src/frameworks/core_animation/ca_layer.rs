@@ -5,12 +5,119 @@
  */
 //! `CALayer`.
 
-use crate::objc::{id, msg, nil, objc_classes, release, ClassExports, HostObject};
+use crate::dyld::{ConstantExports, HostConstant};
+use crate::frameworks::core_graphics::cg_bitmap_context::{
+    CGBitmapContextCreate, CGBitmapContextCreateImage,
+};
+use crate::frameworks::core_graphics::cg_color_space::{
+    create_with_name, kCGColorSpaceGenericRGB, CGColorSpaceRelease,
+};
+use crate::frameworks::core_graphics::cg_context::{
+    CGContextRef, CGContextRelease, CGContextScaleCTM, CGContextTranslateCTM,
+};
+use crate::frameworks::core_graphics::cg_image::{
+    kCGImageAlphaPremultipliedLast, CGImageRef, CGImageRelease, CGImageRetain,
+};
+use crate::frameworks::core_graphics::{CGFloat, CGPoint, CGRect, CGSize};
+use crate::frameworks::foundation::ns_array;
+use crate::frameworks::foundation::ns_string::{get_static_str, to_rust_string};
+use crate::frameworks::foundation::NSUInteger;
+use crate::mem::Ptr;
+use crate::objc::{
+    autorelease, id, msg, nil, objc_classes, release, retain, ClassExports, HostObject, ObjC,
+};
+use crate::Environment;
+
+pub const kCAGravityCenter: &str = "center";
+pub const kCAGravityTop: &str = "top";
+pub const kCAGravityBottom: &str = "bottom";
+pub const kCAGravityLeft: &str = "left";
+pub const kCAGravityRight: &str = "right";
+pub const kCAGravityTopLeft: &str = "topLeft";
+pub const kCAGravityTopRight: &str = "topRight";
+pub const kCAGravityBottomLeft: &str = "bottomLeft";
+pub const kCAGravityBottomRight: &str = "bottomRight";
+pub const kCAGravityResize: &str = "resize";
+pub const kCAGravityResizeAspect: &str = "resizeAspect";
+pub const kCAGravityResizeAspectFill: &str = "resizeAspectFill";
+
+const GRAVITIES: &[&str] = &[
+    kCAGravityCenter,
+    kCAGravityTop,
+    kCAGravityBottom,
+    kCAGravityLeft,
+    kCAGravityRight,
+    kCAGravityTopLeft,
+    kCAGravityTopRight,
+    kCAGravityBottomLeft,
+    kCAGravityBottomRight,
+    kCAGravityResize,
+    kCAGravityResizeAspect,
+    kCAGravityResizeAspectFill,
+];
+
+pub const CONSTANTS: ConstantExports = &[
+    (
+        "_kCAGravityCenter",
+        HostConstant::NSString(kCAGravityCenter),
+    ),
+    ("_kCAGravityTop", HostConstant::NSString(kCAGravityTop)),
+    (
+        "_kCAGravityBottom",
+        HostConstant::NSString(kCAGravityBottom),
+    ),
+    ("_kCAGravityLeft", HostConstant::NSString(kCAGravityLeft)),
+    ("_kCAGravityRight", HostConstant::NSString(kCAGravityRight)),
+    (
+        "_kCAGravityTopLeft",
+        HostConstant::NSString(kCAGravityTopLeft),
+    ),
+    (
+        "_kCAGravityTopRight",
+        HostConstant::NSString(kCAGravityTopRight),
+    ),
+    (
+        "_kCAGravityBottomLeft",
+        HostConstant::NSString(kCAGravityBottomLeft),
+    ),
+    (
+        "_kCAGravityBottomRight",
+        HostConstant::NSString(kCAGravityBottomRight),
+    ),
+    (
+        "_kCAGravityResize",
+        HostConstant::NSString(kCAGravityResize),
+    ),
+    (
+        "_kCAGravityResizeAspect",
+        HostConstant::NSString(kCAGravityResizeAspect),
+    ),
+    (
+        "_kCAGravityResizeAspectFill",
+        HostConstant::NSString(kCAGravityResizeAspectFill),
+    ),
+];
 
 pub(super) struct CALayerHostObject {
     /// Possibly nil, usually a UIView. This is a weak reference.
     delegate: id,
     opaque: bool,
+    pub(super) bounds: CGRect,
+    pub(super) position: CGPoint,
+    pub(super) anchor_point: CGPoint,
+    /// Strong references, in back-to-front order.
+    pub(super) sublayers: Vec<id>,
+    /// Possibly nil. This is a weak reference.
+    superlayer: id,
+    /// `CGImageRef`, possibly nil. This is a strong reference.
+    pub(super) contents: CGImageRef,
+    pub(super) contents_scale: CGFloat,
+    /// One of the `kCAGravity` constants.
+    pub(super) contents_gravity: &'static str,
+    pub(super) hidden: bool,
+    pub(super) opacity: f32,
+    needs_display: bool,
+    needs_layout: bool,
     /// For CAEAGLLayer only
     pub(super) drawable_properties: id,
 }
@@ -26,6 +133,21 @@ pub const CLASSES: ClassExports = objc_classes! {
     let host_object = Box::new(CALayerHostObject {
         delegate: nil,
         opaque: false,
+        bounds: CGRect {
+            origin: CGPoint { x: 0.0, y: 0.0 },
+            size: CGSize { width: 0.0, height: 0.0 },
+        },
+        position: CGPoint { x: 0.0, y: 0.0 },
+        anchor_point: CGPoint { x: 0.5, y: 0.5 },
+        sublayers: Vec::new(),
+        superlayer: nil,
+        contents: nil,
+        contents_scale: 1.0,
+        contents_gravity: kCAGravityResize,
+        hidden: false,
+        opacity: 1.0,
+        needs_display: false,
+        needs_layout: false,
         drawable_properties: nil,
     });
     env.objc.alloc_object(this, host_object, &mut env.mem)
@@ -37,10 +159,22 @@ pub const CLASSES: ClassExports = objc_classes! {
 }
 
 - (())dealloc {
-    let &CALayerHostObject { drawable_properties, .. } = env.objc.borrow(this);
+    let host_object = env.objc.borrow_mut::<CALayerHostObject>(this);
+    let sublayers = std::mem::take(&mut host_object.sublayers);
+    let &mut CALayerHostObject {
+        contents,
+        drawable_properties,
+        ..
+    } = host_object;
+    for sublayer in sublayers {
+        env.objc.borrow_mut::<CALayerHostObject>(sublayer).superlayer = nil;
+        release(env, sublayer);
+    }
+    CGImageRelease(env, contents);
     if drawable_properties != nil {
         release(env, drawable_properties);
     }
+    env.objc.dealloc_object(this, &mut env.mem)
 }
 
 - (id)delegate {
@@ -57,8 +191,380 @@ pub const CLASSES: ClassExports = objc_classes! {
     env.objc.borrow_mut::<CALayerHostObject>(this).opaque = opaque;
 }
 
+- (CGRect)bounds {
+    env.objc.borrow::<CALayerHostObject>(this).bounds
+}
+- (())setBounds:(CGRect)bounds {
+    let host_object = env.objc.borrow_mut::<CALayerHostObject>(this);
+    if host_object.bounds.size != bounds.size {
+        host_object.needs_layout = true;
+    }
+    host_object.bounds = bounds;
+}
+
+- (CGPoint)position {
+    env.objc.borrow::<CALayerHostObject>(this).position
+}
+- (())setPosition:(CGPoint)position {
+    env.objc.borrow_mut::<CALayerHostObject>(this).position = position;
+}
+
+- (CGPoint)anchorPoint {
+    env.objc.borrow::<CALayerHostObject>(this).anchor_point
+}
+- (())setAnchorPoint:(CGPoint)anchor_point {
+    env.objc.borrow_mut::<CALayerHostObject>(this).anchor_point = anchor_point;
+}
+
+- (CGRect)frame {
+    let &CALayerHostObject {
+        bounds,
+        position,
+        anchor_point,
+        ..
+    } = env.objc.borrow(this);
+    CGRect {
+        origin: CGPoint {
+            x: position.x - bounds.size.width * anchor_point.x,
+            y: position.y - bounds.size.height * anchor_point.y,
+        },
+        size: bounds.size,
+    }
+}
+- (())setFrame:(CGRect)frame {
+    let host_object = env.objc.borrow_mut::<CALayerHostObject>(this);
+    let anchor_point = host_object.anchor_point;
+    if host_object.bounds.size != frame.size {
+        host_object.needs_layout = true;
+    }
+    host_object.bounds.size = frame.size;
+    host_object.position = CGPoint {
+        x: frame.origin.x + frame.size.width * anchor_point.x,
+        y: frame.origin.y + frame.size.height * anchor_point.y,
+    };
+}
+
+- (id)superlayer {
+    env.objc.borrow::<CALayerHostObject>(this).superlayer
+}
+
+- (id)sublayers {
+    let sublayers = env.objc.borrow::<CALayerHostObject>(this).sublayers.clone();
+    for &sublayer in &sublayers {
+        retain(env, sublayer);
+    }
+    let array = ns_array::from_vec(env, sublayers);
+    autorelease(env, array)
+}
+
+- (())addSublayer:(id)layer {
+    let count = env.objc.borrow::<CALayerHostObject>(this).sublayers.len();
+    insert_sublayer(env, this, layer, count);
+}
+- (())insertSublayer:(id)layer atIndex:(NSUInteger)index {
+    insert_sublayer(env, this, layer, index as usize);
+}
+- (())insertSublayer:(id)layer above:(id)sibling {
+    let index = sublayer_index(&env.objc, this, sibling);
+    insert_sublayer(env, this, layer, index + 1);
+}
+- (())insertSublayer:(id)layer below:(id)sibling {
+    let index = sublayer_index(&env.objc, this, sibling);
+    insert_sublayer(env, this, layer, index);
+}
+
+- (())removeFromSuperlayer {
+    let superlayer = env.objc.borrow::<CALayerHostObject>(this).superlayer;
+    if superlayer == nil {
+        return;
+    }
+    let index = sublayer_index(&env.objc, superlayer, this);
+    let superlayer_host_object = env.objc.borrow_mut::<CALayerHostObject>(superlayer);
+    superlayer_host_object.sublayers.remove(index);
+    superlayer_host_object.needs_layout = true;
+    env.objc.borrow_mut::<CALayerHostObject>(this).superlayer = nil;
+    release(env, this);
+}
+
+- (CGPoint)convertPoint:(CGPoint)point fromLayer:(id)layer {
+    let from = origin_in_root(&env.objc, layer);
+    let to = origin_in_root(&env.objc, this);
+    CGPoint {
+        x: point.x + from.x - to.x,
+        y: point.y + from.y - to.y,
+    }
+}
+- (CGPoint)convertPoint:(CGPoint)point toLayer:(id)layer {
+    let from = origin_in_root(&env.objc, this);
+    let to = origin_in_root(&env.objc, layer);
+    CGPoint {
+        x: point.x + from.x - to.x,
+        y: point.y + from.y - to.y,
+    }
+}
+- (CGRect)convertRect:(CGRect)rect fromLayer:(id)layer {
+    let origin: CGPoint = msg![env; this convertPoint:(rect.origin) fromLayer:layer];
+    CGRect { origin, size: rect.size }
+}
+- (CGRect)convertRect:(CGRect)rect toLayer:(id)layer {
+    let origin: CGPoint = msg![env; this convertPoint:(rect.origin) toLayer:layer];
+    CGRect { origin, size: rect.size }
+}
+
+- (bool)containsPoint:(CGPoint)point {
+    let bounds = env.objc.borrow::<CALayerHostObject>(this).bounds;
+    rect_contains_point(bounds, point)
+}
+
+- (bool)isHidden {
+    env.objc.borrow::<CALayerHostObject>(this).hidden
+}
+- (())setHidden:(bool)hidden {
+    env.objc.borrow_mut::<CALayerHostObject>(this).hidden = hidden;
+}
+
+- (f32)opacity {
+    env.objc.borrow::<CALayerHostObject>(this).opacity
+}
+- (())setOpacity:(f32)opacity {
+    env.objc.borrow_mut::<CALayerHostObject>(this).opacity = opacity;
+}
+
+- (bool)needsLayout {
+    env.objc.borrow::<CALayerHostObject>(this).needs_layout
+}
+- (())setNeedsLayout {
+    env.objc.borrow_mut::<CALayerHostObject>(this).needs_layout = true;
+}
+- (())layoutIfNeeded {
+    let host_object = env.objc.borrow_mut::<CALayerHostObject>(this);
+    if std::mem::take(&mut host_object.needs_layout) {
+        () = msg![env; this layoutSublayers];
+    }
+    let sublayers = env.objc.borrow::<CALayerHostObject>(this).sublayers.clone();
+    for sublayer in sublayers {
+        () = msg![env; sublayer layoutIfNeeded];
+    }
+}
+- (())layoutSublayers {
+    let delegate = env.objc.borrow::<CALayerHostObject>(this).delegate;
+    if delegate != nil && responds_to_selector(env, delegate, "layoutSublayersOfLayer:") {
+        () = msg![env; delegate layoutSublayersOfLayer:this];
+    }
+}
+
+- (bool)needsDisplay {
+    env.objc.borrow::<CALayerHostObject>(this).needs_display
+}
+- (())setNeedsDisplay {
+    env.objc.borrow_mut::<CALayerHostObject>(this).needs_display = true;
+}
+- (())setNeedsDisplayInRect:(CGRect)_rect {
+    // TODO: only redraw the part that needs it
+    env.objc.borrow_mut::<CALayerHostObject>(this).needs_display = true;
+}
+- (())displayIfNeeded {
+    let host_object = env.objc.borrow_mut::<CALayerHostObject>(this);
+    if std::mem::take(&mut host_object.needs_display) {
+        () = msg![env; this display];
+    }
+}
+- (())display {
+    let delegate = env.objc.borrow::<CALayerHostObject>(this).delegate;
+    if delegate != nil && responds_to_selector(env, delegate, "displayLayer:") {
+        () = msg![env; delegate displayLayer:this];
+        return;
+    }
+
+    let bounds = env.objc.borrow::<CALayerHostObject>(this).bounds;
+    let width = bounds.size.width.ceil().max(0.0) as u32;
+    let height = bounds.size.height.ceil().max(0.0) as u32;
+    if width == 0 || height == 0 {
+        () = msg![env; this setContents:nil];
+        return;
+    }
+
+    let color_space = create_with_name(&mut env.objc, &mut env.mem, kCGColorSpaceGenericRGB);
+    let context = CGBitmapContextCreate(
+        env,
+        Ptr::null(),
+        width,
+        height,
+        8,
+        0,
+        color_space,
+        kCGImageAlphaPremultipliedLast,
+    );
+    CGColorSpaceRelease(env, color_space);
+    // Layers use the same co-ordinate system as UIKit, with the origin at the
+    // top-left, so the context must be flipped.
+    CGContextTranslateCTM(env, context, -bounds.origin.x, height as CGFloat + bounds.origin.y);
+    CGContextScaleCTM(env, context, 1.0, -1.0);
+
+    () = msg![env; this drawInContext:context];
+
+    let image = CGBitmapContextCreateImage(env, context);
+    CGContextRelease(env, context);
+    () = msg![env; this setContents:image];
+    CGImageRelease(env, image);
+}
+- (())drawInContext:(CGContextRef)context {
+    let delegate = env.objc.borrow::<CALayerHostObject>(this).delegate;
+    if delegate != nil && responds_to_selector(env, delegate, "drawLayer:inContext:") {
+        () = msg![env; delegate drawLayer:this inContext:context];
+    }
+}
+
+- (id)contents {
+    env.objc.borrow::<CALayerHostObject>(this).contents
+}
+- (())setContents:(id)contents { // CGImageRef
+    let contents = CGImageRetain(env, contents);
+    let host_object = env.objc.borrow_mut::<CALayerHostObject>(this);
+    let old_contents = std::mem::replace(&mut host_object.contents, contents);
+    CGImageRelease(env, old_contents);
+}
+
+- (CGFloat)contentsScale {
+    env.objc.borrow::<CALayerHostObject>(this).contents_scale
+}
+- (())setContentsScale:(CGFloat)scale {
+    env.objc.borrow_mut::<CALayerHostObject>(this).contents_scale = scale;
+}
+
+- (id)contentsGravity {
+    let gravity = env.objc.borrow::<CALayerHostObject>(this).contents_gravity;
+    get_static_str(env, gravity)
+}
+- (())setContentsGravity:(id)gravity { // NSString*
+    let gravity = to_rust_string(env, gravity);
+    let Some(&gravity) = GRAVITIES.iter().find(|&&known| known == gravity) else {
+        log!("Warning: ignoring unknown contents gravity {:?}", gravity);
+        return;
+    };
+    env.objc.borrow_mut::<CALayerHostObject>(this).contents_gravity = gravity;
+}
+
 // TODO
 
 @end
 
 };
+
+/// Shared implementation of the `addSublayer:` and `insertSublayer:` method
+/// family.
+fn insert_sublayer(env: &mut Environment, this: id, layer: id, index: usize) {
+    // Retain first in case removing it from its superlayer would free it.
+    retain(env, layer);
+    () = msg![env; layer removeFromSuperlayer];
+
+    let host_object = env.objc.borrow_mut::<CALayerHostObject>(this);
+    let index = index.min(host_object.sublayers.len());
+    host_object.sublayers.insert(index, layer);
+    host_object.needs_layout = true;
+    env.objc.borrow_mut::<CALayerHostObject>(layer).superlayer = this;
+}
+
+fn sublayer_index(objc: &ObjC, this: id, sublayer: id) -> usize {
+    objc.borrow::<CALayerHostObject>(this)
+        .sublayers
+        .iter()
+        .position(|&l| l == sublayer)
+        .unwrap()
+}
+
+/// Check whether an object (typically the layer's delegate) implements a
+/// method.
+fn responds_to_selector(env: &mut Environment, object: id, selector: &str) -> bool {
+    let Some(sel) = env.objc.lookup_selector(selector) else {
+        return false;
+    };
+    let class = ObjC::read_isa(object, &env.mem);
+    env.objc.class_has_method(class, sel)
+}
+
+/// Get the position of the origin of a layer's co-ordinate space (the one its
+/// bounds are in) within its superlayer's co-ordinate space.
+pub(super) fn origin_in_superlayer(
+    bounds: CGRect,
+    position: CGPoint,
+    anchor_point: CGPoint,
+) -> CGPoint {
+    CGPoint {
+        x: position.x - bounds.size.width * anchor_point.x - bounds.origin.x,
+        y: position.y - bounds.size.height * anchor_point.y - bounds.origin.y,
+    }
+}
+
+/// Get the position of the origin of a layer's co-ordinate space within the
+/// co-ordinate space of the root of its layer tree. A `nil` layer is treated
+/// as being the root.
+fn origin_in_root(objc: &ObjC, layer: id) -> CGPoint {
+    let mut origin = CGPoint { x: 0.0, y: 0.0 };
+    let mut layer = layer;
+    while layer != nil {
+        let &CALayerHostObject {
+            bounds,
+            position,
+            anchor_point,
+            superlayer,
+            ..
+        } = objc.borrow(layer);
+        if superlayer == nil {
+            break;
+        }
+        let offset = origin_in_superlayer(bounds, position, anchor_point);
+        origin.x += offset.x;
+        origin.y += offset.y;
+        layer = superlayer;
+    }
+    origin
+}
+
+fn rect_contains_point(rect: CGRect, point: CGPoint) -> bool {
+    let CGRect { origin, size } = rect;
+    let CGPoint { x, y } = point;
+    (origin.x..origin.x + size.width).contains(&x)
+        && (origin.y..origin.y + size.height).contains(&y)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rect(x: CGFloat, y: CGFloat, width: CGFloat, height: CGFloat) -> CGRect {
+        CGRect {
+            origin: CGPoint { x, y },
+            size: CGSize { width, height },
+        }
+    }
+
+    #[test]
+    fn layer_origin() {
+        // A 100×50 layer with the default anchor point in the middle, placed
+        // so that its frame's top-left corner is at (10, 20).
+        let origin = origin_in_superlayer(
+            rect(0.0, 0.0, 100.0, 50.0),
+            CGPoint { x: 60.0, y: 45.0 },
+            CGPoint { x: 0.5, y: 0.5 },
+        );
+        assert_eq!((origin.x, origin.y), (10.0, 20.0));
+
+        // Scrolling the bounds moves the co-ordinate space the other way.
+        let origin = origin_in_superlayer(
+            rect(5.0, -5.0, 100.0, 50.0),
+            CGPoint { x: 10.0, y: 20.0 },
+            CGPoint { x: 0.0, y: 0.0 },
+        );
+        assert_eq!((origin.x, origin.y), (5.0, 25.0));
+    }
+
+    #[test]
+    fn contains_point() {
+        let bounds = rect(10.0, 20.0, 30.0, 40.0);
+        assert!(rect_contains_point(bounds, CGPoint { x: 10.0, y: 20.0 }));
+        assert!(rect_contains_point(bounds, CGPoint { x: 39.5, y: 59.5 }));
+        assert!(!rect_contains_point(bounds, CGPoint { x: 40.0, y: 30.0 }));
+        assert!(!rect_contains_point(bounds, CGPoint { x: 20.0, y: 19.9 }));
+    }
+}
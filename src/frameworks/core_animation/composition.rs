@@ -0,0 +1,438 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! Compositing of layer trees for display.
+//!
+//! This is a simple software compositor: the contents of each layer are drawn
+//! back-to-front into a buffer, which the window then displays. Apps that draw
+//! with OpenGL ES present their frames directly instead (see
+//! `presentRenderbuffer:` on `EAGLContext`), so layer trees containing a
+//! `CAEAGLLayer` are not composited.
+
+use super::ca_layer::{
+    kCAGravityBottom, kCAGravityBottomLeft, kCAGravityBottomRight, kCAGravityLeft,
+    kCAGravityResize, kCAGravityResizeAspect, kCAGravityResizeAspectFill, kCAGravityRight,
+    kCAGravityTop, kCAGravityTopLeft, kCAGravityTopRight, origin_in_superlayer, CALayerHostObject,
+};
+use crate::frameworks::core_graphics::cg_bitmap_context::rasterize_image;
+use crate::frameworks::core_graphics::cg_image;
+use crate::frameworks::core_graphics::{CGAffineTransform, CGFloat, CGPoint, CGRect, CGSize};
+use crate::image::Image;
+use crate::objc::{id, msg, nil, Class, ObjC};
+use crate::Environment;
+use std::time::{Duration, Instant};
+
+#[derive(Default)]
+pub struct State {
+    last_composition: Option<Instant>,
+}
+
+/// The real device's display refreshes at 60Hz, so there's no point in
+/// compositing more often than that.
+const COMPOSITION_INTERVAL: Duration = Duration::from_nanos(1_000_000_000 / 60);
+
+/// For use by UIKit: lay out and redraw the layer trees with the given root
+/// layers as needed, then composite them (back-to-front) and display the
+/// result, if enough time has passed since the last time this was done.
+pub fn recomposite_if_necessary(env: &mut Environment, root_layers: &[id]) {
+    if root_layers.is_empty() {
+        return;
+    }
+
+    let now = Instant::now();
+    let state = &mut env.framework_state.core_animation.composition;
+    if state
+        .last_composition
+        .is_some_and(|last| now < last + COMPOSITION_INTERVAL)
+    {
+        return;
+    }
+    state.last_composition = Some(now);
+
+    for &layer in root_layers {
+        () = msg![env; layer layoutIfNeeded];
+    }
+
+    let eagl_layer_class = env.objc.get_known_class("CAEAGLLayer", &mut env.mem);
+    for &layer in root_layers {
+        if contains_layer_of_class(env, layer, eagl_layer_class) {
+            return;
+        }
+    }
+
+    for &layer in root_layers {
+        display_if_needed(env, layer);
+    }
+
+    let (width, height) = env.window.size_unrotated_unscaled();
+    let mut framebuffer = Framebuffer::new((width, height));
+    for &layer in root_layers {
+        composite_layer(
+            &env.objc,
+            layer,
+            CGPoint { x: 0.0, y: 0.0 },
+            1.0,
+            &mut framebuffer,
+        );
+    }
+    env.window.present_image(&framebuffer.into_image());
+}
+
+fn contains_layer_of_class(env: &mut Environment, layer: id, class: Class) -> bool {
+    if msg![env; layer isKindOfClass:class] {
+        return true;
+    }
+    let sublayers = env
+        .objc
+        .borrow::<CALayerHostObject>(layer)
+        .sublayers
+        .clone();
+    sublayers
+        .into_iter()
+        .any(|sublayer| contains_layer_of_class(env, sublayer, class))
+}
+
+fn display_if_needed(env: &mut Environment, layer: id) {
+    () = msg![env; layer displayIfNeeded];
+    let sublayers = env
+        .objc
+        .borrow::<CALayerHostObject>(layer)
+        .sublayers
+        .clone();
+    for sublayer in sublayers {
+        display_if_needed(env, sublayer);
+    }
+}
+
+/// Draw a layer and its sublayers. `origin` is the position of the origin of
+/// the superlayer's co-ordinate space within the framebuffer, and `opacity`
+/// is the superlayer's accumulated opacity.
+fn composite_layer(
+    objc: &ObjC,
+    layer: id,
+    origin: CGPoint,
+    opacity: f32,
+    framebuffer: &mut Framebuffer,
+) {
+    let host_object = objc.borrow::<CALayerHostObject>(layer);
+    let &CALayerHostObject {
+        bounds,
+        position,
+        anchor_point,
+        contents,
+        contents_scale,
+        contents_gravity,
+        hidden,
+        ..
+    } = host_object;
+
+    let opacity = opacity * host_object.opacity.clamp(0.0, 1.0);
+    if hidden || opacity == 0.0 {
+        return;
+    }
+
+    let (sublayers_origin, frame_origin) = layer_origins(origin, bounds, position, anchor_point);
+
+    if contents != nil {
+        let image = cg_image::borrow_image(objc, contents);
+        let (width, height) = image.dimensions();
+        if width != 0 && height != 0 {
+            let contents_size = CGSize {
+                width: width as CGFloat / contents_scale,
+                height: height as CGFloat / contents_scale,
+            };
+            let mut rect = contents_rect(contents_gravity, bounds.size, contents_size);
+            rect.origin.x += frame_origin.x;
+            rect.origin.y += frame_origin.y;
+            framebuffer.draw_image(image, rect, opacity);
+        }
+    }
+
+    for &sublayer in &host_object.sublayers {
+        composite_layer(objc, sublayer, sublayers_origin, opacity, framebuffer);
+    }
+}
+
+/// Get the positions within the framebuffer of the origin of a layer's
+/// co-ordinate space (which its sublayers are positioned relative to) and of
+/// its top-left corner (where its contents are drawn), given the position of
+/// the superlayer's origin.
+fn layer_origins(
+    origin: CGPoint,
+    bounds: CGRect,
+    position: CGPoint,
+    anchor_point: CGPoint,
+) -> (CGPoint, CGPoint) {
+    let offset = origin_in_superlayer(bounds, position, anchor_point);
+    let sublayers_origin = CGPoint {
+        x: origin.x + offset.x,
+        y: origin.y + offset.y,
+    };
+    let frame_origin = CGPoint {
+        x: sublayers_origin.x + bounds.origin.x,
+        y: sublayers_origin.y + bounds.origin.y,
+    };
+    (sublayers_origin, frame_origin)
+}
+
+/// Work out where a layer's contents should be drawn, relative to the layer's
+/// top-left corner, according to its `contentsGravity`.
+fn contents_rect(gravity: &str, bounds_size: CGSize, contents_size: CGSize) -> CGRect {
+    let size = match gravity {
+        kCAGravityResize => bounds_size,
+        kCAGravityResizeAspect | kCAGravityResizeAspectFill => {
+            let scale_x = bounds_size.width / contents_size.width;
+            let scale_y = bounds_size.height / contents_size.height;
+            let scale = if gravity == kCAGravityResizeAspect {
+                scale_x.min(scale_y)
+            } else {
+                scale_x.max(scale_y)
+            };
+            CGSize {
+                width: contents_size.width * scale,
+                height: contents_size.height * scale,
+            }
+        }
+        _ => contents_size,
+    };
+
+    let x = match gravity {
+        kCAGravityLeft | kCAGravityTopLeft | kCAGravityBottomLeft => 0.0,
+        kCAGravityRight | kCAGravityTopRight | kCAGravityBottomRight => {
+            bounds_size.width - size.width
+        }
+        _ => (bounds_size.width - size.width) / 2.0,
+    };
+    // Core Animation's gravity names assume the y axis points up, but UIKit's
+    // layers have it pointing down, so the "bottom" is at the top of the
+    // screen. UIKit compensates for this when mapping content modes.
+    let y = match gravity {
+        kCAGravityBottom | kCAGravityBottomLeft | kCAGravityBottomRight => 0.0,
+        kCAGravityTop | kCAGravityTopLeft | kCAGravityTopRight => bounds_size.height - size.height,
+        _ => (bounds_size.height - size.height) / 2.0,
+    };
+
+    CGRect {
+        origin: CGPoint { x, y },
+        size,
+    }
+}
+
+/// RGBA8 buffer that layers are composited into, initially opaque black.
+struct Framebuffer {
+    pixels: Vec<u8>,
+    size: (u32, u32),
+}
+impl Framebuffer {
+    fn new(size: (u32, u32)) -> Framebuffer {
+        Framebuffer {
+            pixels: [0, 0, 0, 255].repeat(size.0 as usize * size.1 as usize),
+            size,
+        }
+    }
+
+    fn draw_image(&mut self, image: &Image, rect: CGRect, opacity: f32) {
+        let image_size = image.dimensions();
+        let image_pixels = image.pixels();
+        let (width, height) = self.size;
+        // The framebuffer's first row is at the top, but Core Graphics puts
+        // the first row of an image at the maximum y of the rect, so flip the
+        // rect and use a transform that flips it back.
+        let flip = CGAffineTransform {
+            d: -1.0,
+            ty: height as CGFloat,
+            ..CGAffineTransform::IDENTITY
+        };
+        let flipped_rect = CGRect {
+            origin: CGPoint {
+                x: rect.origin.x,
+                y: height as CGFloat - rect.origin.y - rect.size.height,
+            },
+            size: rect.size,
+        };
+        rasterize_image(
+            flipped_rect,
+            image_size,
+            flip,
+            self.size,
+            |(x, y), (image_x, image_y)| {
+                let src_idx = (image_y as usize * image_size.0 as usize + image_x as usize) * 4;
+                let dst_idx = (y as usize * width as usize + x as usize) * 4;
+                let src = &image_pixels[src_idx..src_idx + 4];
+                let dst = &mut self.pixels[dst_idx..dst_idx + 4];
+                let mut src: [u8; 4] = src.try_into().unwrap();
+                if opacity < 1.0 {
+                    src[3] = (src[3] as f32 * opacity).round() as u8;
+                }
+                blend_source_over(src, dst.try_into().unwrap());
+            },
+        );
+    }
+
+    fn into_image(self) -> Image {
+        Image::from_pixels(self.pixels, self.size)
+    }
+}
+
+/// Blend a non-premultiplied RGBA8 pixel over another.
+fn blend_source_over(src: [u8; 4], dst: &mut [u8; 4]) {
+    let src_alpha = src[3] as u32;
+    for (dst, &src) in dst[..3].iter_mut().zip(&src) {
+        *dst = ((src as u32 * src_alpha + *dst as u32 * (255 - src_alpha) + 127) / 255) as u8;
+    }
+    dst[3] = (src_alpha + (dst[3] as u32 * (255 - src_alpha) + 127) / 255) as u8;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rect(x: CGFloat, y: CGFloat, width: CGFloat, height: CGFloat) -> CGRect {
+        CGRect {
+            origin: CGPoint { x, y },
+            size: CGSize { width, height },
+        }
+    }
+
+    fn size(width: CGFloat, height: CGFloat) -> CGSize {
+        CGSize { width, height }
+    }
+
+    fn assert_rect_eq(a: CGRect, b: CGRect) {
+        assert_eq!(
+            (a.origin.x, a.origin.y, a.size.width, a.size.height),
+            (b.origin.x, b.origin.y, b.size.width, b.size.height)
+        );
+    }
+
+    #[test]
+    fn gravity() {
+        let bounds = size(4.0, 4.0);
+        let contents = size(2.0, 1.0);
+        assert_rect_eq(
+            contents_rect(kCAGravityResize, bounds, contents),
+            rect(0.0, 0.0, 4.0, 4.0),
+        );
+        assert_rect_eq(
+            contents_rect(kCAGravityResizeAspect, bounds, contents),
+            rect(0.0, 1.0, 4.0, 2.0),
+        );
+        assert_rect_eq(
+            contents_rect(kCAGravityResizeAspectFill, bounds, contents),
+            rect(-2.0, 0.0, 8.0, 4.0),
+        );
+        assert_rect_eq(
+            contents_rect(super::super::ca_layer::kCAGravityCenter, bounds, contents),
+            rect(1.0, 1.5, 2.0, 1.0),
+        );
+        assert_rect_eq(
+            contents_rect(kCAGravityBottomRight, bounds, contents),
+            rect(2.0, 0.0, 2.0, 1.0),
+        );
+        assert_rect_eq(
+            contents_rect(kCAGravityTopLeft, bounds, contents),
+            rect(0.0, 3.0, 2.0, 1.0),
+        );
+    }
+
+    fn point(x: CGFloat, y: CGFloat) -> CGPoint {
+        CGPoint { x, y }
+    }
+
+    #[test]
+    fn layer_positions() {
+        // A 4×2 layer centered on (5, 5) within a superlayer whose origin is at
+        // (10, 0) in the framebuffer.
+        let bounds = rect(0.0, 0.0, 4.0, 2.0);
+        let (sublayers_origin, frame_origin) =
+            layer_origins(point(10.0, 0.0), bounds, point(5.0, 5.0), point(0.5, 0.5));
+        assert_eq!((sublayers_origin.x, sublayers_origin.y), (13.0, 4.0));
+        assert_eq!((frame_origin.x, frame_origin.y), (13.0, 4.0));
+
+        // Changing the bounds origin scrolls the sublayers, but the layer's
+        // own contents stay put.
+        let scrolled = rect(1.0, 3.0, 4.0, 2.0);
+        let (sublayers_origin, frame_origin) =
+            layer_origins(point(10.0, 0.0), scrolled, point(5.0, 5.0), point(0.5, 0.5));
+        assert_eq!((sublayers_origin.x, sublayers_origin.y), (12.0, 1.0));
+        assert_eq!((frame_origin.x, frame_origin.y), (13.0, 4.0));
+    }
+
+    #[test]
+    fn later_layers_are_drawn_on_top() {
+        let red = Image::from_pixels(vec![255, 0, 0, 255], (1, 1));
+        let blue = Image::from_pixels(vec![0, 0, 255, 255], (1, 1));
+        let mut framebuffer = Framebuffer::new((3, 1));
+        framebuffer.draw_image(&red, rect(0.0, 0.0, 2.0, 1.0), 1.0);
+        framebuffer.draw_image(&blue, rect(1.0, 0.0, 2.0, 1.0), 1.0);
+
+        let expected: Vec<u8> = [[255, 0, 0, 255], [0, 0, 255, 255], [0, 0, 255, 255]].concat();
+        assert_eq!(framebuffer.into_image().pixels(), expected);
+    }
+
+    #[test]
+    fn layers_are_clipped_to_framebuffer() {
+        // A 2×2 image, with a distinct color for each pixel, hanging off the
+        // top-left corner of the framebuffer. Only its bottom-right pixel is
+        // visible.
+        let image = Image::from_pixels(
+            [
+                [10, 0, 0, 255],
+                [20, 0, 0, 255],
+                [30, 0, 0, 255],
+                [40, 0, 0, 255],
+            ]
+            .concat(),
+            (2, 2),
+        );
+        let mut framebuffer = Framebuffer::new((2, 2));
+        framebuffer.draw_image(&image, rect(-1.0, -1.0, 2.0, 2.0), 1.0);
+        // Nothing is drawn for a layer that is entirely off-screen.
+        framebuffer.draw_image(&image, rect(5.0, 0.0, 2.0, 2.0), 1.0);
+        framebuffer.draw_image(&image, rect(0.0, -3.0, 2.0, 2.0), 1.0);
+
+        let black = [0, 0, 0, 255];
+        let expected: Vec<u8> = [[40, 0, 0, 255], black, black, black].concat();
+        assert_eq!(framebuffer.into_image().pixels(), expected);
+    }
+
+    #[test]
+    fn composite_image_view_in_window() {
+        // A 2×1 image with an opaque red pixel and a half-transparent white
+        // pixel, shown by an aspect-fit image view occupying the middle of a
+        // 4×4 window.
+        let image = Image::from_pixels(vec![255, 0, 0, 255, 255, 255, 255, 128], (2, 1));
+        let mut framebuffer = Framebuffer::new((4, 4));
+        let mut rect = contents_rect(kCAGravityResizeAspect, size(4.0, 2.0), size(2.0, 1.0));
+        rect.origin.y += 1.0;
+        framebuffer.draw_image(&image, rect, 1.0);
+
+        let black = [0, 0, 0, 255];
+        let red = [255, 0, 0, 255];
+        let grey = [128, 128, 128, 255];
+        let expected: Vec<u8> = [
+            [black, black, black, black],
+            [red, red, grey, grey],
+            [red, red, grey, grey],
+            [black, black, black, black],
+        ]
+        .concat()
+        .concat();
+        assert_eq!(framebuffer.into_image().pixels(), expected);
+    }
+
+    #[test]
+    fn composite_back_to_front_with_opacity() {
+        let red = Image::from_pixels(vec![255, 0, 0, 255], (1, 1));
+        let white = Image::from_pixels(vec![255, 255, 255, 255], (1, 1));
+        let mut framebuffer = Framebuffer::new((3, 1));
+        framebuffer.draw_image(&red, rect(0.0, 0.0, 2.0, 1.0), 1.0);
+        framebuffer.draw_image(&white, rect(1.0, 0.0, 2.0, 1.0), 0.5);
+
+        let expected: Vec<u8> =
+            [[255, 0, 0, 255], [255, 128, 128, 255], [128, 128, 128, 255]].concat();
+        assert_eq!(framebuffer.into_image().pixels(), expected);
+    }
+}
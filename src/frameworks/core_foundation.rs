@@ -18,6 +18,8 @@
 
 pub mod cf_allocator;
 pub mod cf_bundle;
+pub mod cf_data;
+pub mod cf_date;
 pub mod cf_run_loop;
 pub mod cf_string;
 pub mod cf_type;
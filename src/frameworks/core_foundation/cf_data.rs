@@ -0,0 +1,33 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! `CFData`.
+//!
+//! This is toll-free bridged to `NSData` in Apple's implementation. Here it is
+//! the same type.
+
+use super::CFIndex;
+use crate::dyld::{export_c_func, FunctionExports};
+use crate::frameworks::foundation::NSUInteger;
+use crate::mem::ConstPtr;
+use crate::objc::msg;
+use crate::Environment;
+
+pub type CFDataRef = super::CFTypeRef;
+
+fn CFDataGetLength(env: &mut Environment, data: CFDataRef) -> CFIndex {
+    let length: NSUInteger = msg![env; data length];
+    length.try_into().unwrap()
+}
+
+fn CFDataGetBytePtr(env: &mut Environment, data: CFDataRef) -> ConstPtr<u8> {
+    let bytes: ConstPtr<u8> = msg![env; data bytes];
+    bytes
+}
+
+pub const FUNCTIONS: FunctionExports = &[
+    export_c_func!(CFDataGetLength(_)),
+    export_c_func!(CFDataGetBytePtr(_)),
+];
@@ -0,0 +1,20 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! `CFDate` and related time functions.
+
+use crate::dyld::{export_c_func, FunctionExports};
+use crate::frameworks::foundation::ns_date;
+use crate::Environment;
+
+/// Seconds since the reference date (2001-01-01 00:00:00 UTC).
+pub type CFAbsoluteTime = CFTimeInterval;
+pub type CFTimeInterval = f64;
+
+fn CFAbsoluteTimeGetCurrent(env: &mut Environment) -> CFAbsoluteTime {
+    ns_date::now_since_reference_date(env)
+}
+
+pub const FUNCTIONS: FunctionExports = &[export_c_func!(CFAbsoluteTimeGetCurrent())];
@@ -8,12 +8,22 @@
 //! This is not even toll-free bridged to `NSRunLoop` in Apple's implementation,
 //! but here it is the same type.
 
+use super::cf_allocator::CFAllocatorRef;
+use super::cf_date::{CFAbsoluteTime, CFTimeInterval};
+use super::CFIndex;
+use crate::abi::{CallFromHost, GuestFunction};
 use crate::dyld::{export_c_func, ConstantExports, FunctionExports, HostConstant};
-use crate::objc::msg_class;
+use crate::frameworks::foundation::{ns_date, ns_timer};
+use crate::mem::{ConstPtr, ConstVoidPtr, MutVoidPtr, SafeRead};
+use crate::objc::{msg, msg_class};
 use crate::Environment;
 
 pub type CFRunLoopRef = super::CFTypeRef;
 pub type CFRunLoopMode = super::cf_string::CFStringRef;
+/// Toll-free bridged with `NSTimer*`.
+pub type CFRunLoopTimerRef = super::CFTypeRef;
+/// `void (*callout)(CFRunLoopTimerRef timer, void *info)`
+type CFRunLoopTimerCallBack = GuestFunction;
 
 fn CFRunLoopGetCurrent(env: &mut Environment) -> CFRunLoopRef {
     msg_class![env; NSRunLoop currentRunLoop]
@@ -23,6 +33,72 @@ fn CFRunLoopGetMain(env: &mut Environment) -> CFRunLoopRef {
     msg_class![env; NSRunLoop mainRunLoop]
 }
 
+#[repr(C, packed)]
+struct CFRunLoopTimerContext {
+    version: CFIndex,
+    info: MutVoidPtr,
+    /// `const void *(*retain)(const void *info)`
+    retain: GuestFunction,
+    /// `void (*release)(const void *info)`
+    release: GuestFunction,
+    /// `CFStringRef (*copyDescription)(const void *info)`
+    copy_description: GuestFunction,
+}
+unsafe impl SafeRead for CFRunLoopTimerContext {}
+
+fn CFRunLoopTimerCreate(
+    env: &mut Environment,
+    allocator: CFAllocatorRef,
+    fire_date: CFAbsoluteTime,
+    interval: CFTimeInterval,
+    _flags: u32,
+    _order: CFIndex,
+    callout: CFRunLoopTimerCallBack,
+    context: ConstPtr<CFRunLoopTimerContext>,
+) -> CFRunLoopTimerRef {
+    if !allocator.is_null() {
+        // There's no support for multiple allocators, but the timer doesn't
+        // need to be allocated any differently.
+        log_dbg!(
+            "CFRunLoopTimerCreate() with allocator {:?}, ignoring",
+            allocator
+        );
+    }
+
+    let (info, release_info) = if context.is_null() {
+        (MutVoidPtr::null(), None)
+    } else {
+        let CFRunLoopTimerContext {
+            version,
+            info,
+            retain,
+            release,
+            ..
+        } = env.mem.read(context);
+        assert!(version == 0);
+        let info = if retain.addr_with_thumb_bit() != 0 {
+            let info: ConstVoidPtr = retain.call_from_host(env, (info.cast_const(),));
+            info.cast_mut()
+        } else {
+            info
+        };
+        let release_info = (release.addr_with_thumb_bit() != 0).then_some(release);
+        (info, release_info)
+    };
+
+    let fire_in = fire_date - ns_date::now_since_reference_date(env);
+    ns_timer::new_with_callback(env, fire_in, interval, callout, info, release_info)
+}
+
+fn CFRunLoopAddTimer(
+    env: &mut Environment,
+    run_loop: CFRunLoopRef,
+    timer: CFRunLoopTimerRef,
+    mode: CFRunLoopMode,
+) {
+    msg![env; run_loop addTimer:timer forMode:mode]
+}
+
 pub const kCFRunLoopCommonModes: &str = "kCFRunLoopCommonModes";
 pub const kCFRunLoopDefaultMode: &str = "kCFRunLoopDefaultMode";
 
@@ -40,4 +116,6 @@ pub const CONSTANTS: ConstantExports = &[
 pub const FUNCTIONS: FunctionExports = &[
     export_c_func!(CFRunLoopGetCurrent()),
     export_c_func!(CFRunLoopGetMain()),
+    export_c_func!(CFRunLoopTimerCreate(_, _, _, _, _, _, _)),
+    export_c_func!(CFRunLoopAddTimer(_, _, _)),
 ];
@@ -5,12 +5,22 @@
  */
 //! The Core Graphics framework.
 
+pub mod cg_affine_transform;
 pub mod cg_bitmap_context;
+pub mod cg_color;
 pub mod cg_color_space;
 pub mod cg_context;
-mod cg_geometry;
+pub mod cg_data_provider;
+pub mod cg_geometry;
 pub mod cg_image;
+mod cg_path;
 
 pub type CGFloat = f32;
 
+#[derive(Default)]
+pub struct State {
+    cg_context: cg_context::State,
+}
+
+pub use cg_affine_transform::CGAffineTransform;
 pub use cg_geometry::{CGPoint, CGRect, CGSize};
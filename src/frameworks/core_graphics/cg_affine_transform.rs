@@ -0,0 +1,285 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! `CGAffineTransform.h`
+
+use super::{CGFloat, CGPoint, CGRect, CGSize};
+use crate::abi::{impl_GuestRet_for_large_struct, GuestArg};
+use crate::dyld::{export_c_func, FunctionExports};
+use crate::mem::SafeRead;
+use crate::Environment;
+
+/// A 2D affine transformation matrix. Apple's convention treats points as row
+/// vectors, so a point `(x, y)` is transformed to
+/// `(a * x + c * y + tx, b * x + d * y + ty)`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[repr(C, packed)]
+pub struct CGAffineTransform {
+    pub a: CGFloat,
+    pub b: CGFloat,
+    pub c: CGFloat,
+    pub d: CGFloat,
+    pub tx: CGFloat,
+    pub ty: CGFloat,
+}
+unsafe impl SafeRead for CGAffineTransform {}
+impl_GuestRet_for_large_struct!(CGAffineTransform);
+impl GuestArg for CGAffineTransform {
+    const REG_COUNT: usize = 6;
+
+    fn from_regs(regs: &[u32]) -> Self {
+        CGAffineTransform {
+            a: GuestArg::from_regs(&regs[0..1]),
+            b: GuestArg::from_regs(&regs[1..2]),
+            c: GuestArg::from_regs(&regs[2..3]),
+            d: GuestArg::from_regs(&regs[3..4]),
+            tx: GuestArg::from_regs(&regs[4..5]),
+            ty: GuestArg::from_regs(&regs[5..6]),
+        }
+    }
+    fn to_regs(self, regs: &mut [u32]) {
+        self.a.to_regs(&mut regs[0..1]);
+        self.b.to_regs(&mut regs[1..2]);
+        self.c.to_regs(&mut regs[2..3]);
+        self.d.to_regs(&mut regs[3..4]);
+        self.tx.to_regs(&mut regs[4..5]);
+        self.ty.to_regs(&mut regs[5..6]);
+    }
+}
+
+impl CGAffineTransform {
+    pub const IDENTITY: Self = CGAffineTransform {
+        a: 1.0,
+        b: 0.0,
+        c: 0.0,
+        d: 1.0,
+        tx: 0.0,
+        ty: 0.0,
+    };
+
+    pub fn translation(tx: CGFloat, ty: CGFloat) -> Self {
+        CGAffineTransform {
+            tx,
+            ty,
+            ..Self::IDENTITY
+        }
+    }
+
+    pub fn scale(sx: CGFloat, sy: CGFloat) -> Self {
+        CGAffineTransform {
+            a: sx,
+            d: sy,
+            ..Self::IDENTITY
+        }
+    }
+
+    pub fn rotation(angle: CGFloat) -> Self {
+        let (sin, cos) = angle.sin_cos();
+        CGAffineTransform {
+            a: cos,
+            b: sin,
+            c: -sin,
+            d: cos,
+            tx: 0.0,
+            ty: 0.0,
+        }
+    }
+
+    /// Returns a transform that applies `self` and then `other`.
+    pub fn concat(self, other: Self) -> Self {
+        let Self { a, b, c, d, tx, ty } = self;
+        CGAffineTransform {
+            a: a * other.a + b * other.c,
+            b: a * other.b + b * other.d,
+            c: c * other.a + d * other.c,
+            d: c * other.b + d * other.d,
+            tx: tx * other.a + ty * other.c + other.tx,
+            ty: tx * other.b + ty * other.d + other.ty,
+        }
+    }
+
+    /// Returns the inverse transform, or [None] if it is not invertible.
+    pub fn invert(self) -> Option<Self> {
+        let Self { a, b, c, d, tx, ty } = self;
+        let determinant = a * d - b * c;
+        if determinant == 0.0 {
+            return None;
+        }
+        let (a, b, c, d) = (
+            d / determinant,
+            -b / determinant,
+            -c / determinant,
+            a / determinant,
+        );
+        Some(CGAffineTransform {
+            a,
+            b,
+            c,
+            d,
+            tx: -(tx * a + ty * c),
+            ty: -(tx * b + ty * d),
+        })
+    }
+
+    pub fn apply_to_point(self, point: (CGFloat, CGFloat)) -> (CGFloat, CGFloat) {
+        let (x, y) = point;
+        (
+            self.a * x + self.c * y + self.tx,
+            self.b * x + self.d * y + self.ty,
+        )
+    }
+}
+
+fn CGAffineTransformMake(
+    _env: &mut Environment,
+    a: CGFloat,
+    b: CGFloat,
+    c: CGFloat,
+    d: CGFloat,
+    tx: CGFloat,
+    ty: CGFloat,
+) -> CGAffineTransform {
+    CGAffineTransform { a, b, c, d, tx, ty }
+}
+
+fn CGAffineTransformMakeRotation(_env: &mut Environment, angle: CGFloat) -> CGAffineTransform {
+    CGAffineTransform::rotation(angle)
+}
+
+fn CGAffineTransformMakeScale(
+    _env: &mut Environment,
+    sx: CGFloat,
+    sy: CGFloat,
+) -> CGAffineTransform {
+    CGAffineTransform::scale(sx, sy)
+}
+
+fn CGAffineTransformMakeTranslation(
+    _env: &mut Environment,
+    tx: CGFloat,
+    ty: CGFloat,
+) -> CGAffineTransform {
+    CGAffineTransform::translation(tx, ty)
+}
+
+fn CGAffineTransformConcat(
+    _env: &mut Environment,
+    t1: CGAffineTransform,
+    t2: CGAffineTransform,
+) -> CGAffineTransform {
+    t1.concat(t2)
+}
+
+fn CGAffineTransformInvert(_env: &mut Environment, t: CGAffineTransform) -> CGAffineTransform {
+    // Apple's documentation says a non-invertible transform is returned
+    // unchanged.
+    t.invert().unwrap_or(t)
+}
+
+fn CGPointApplyAffineTransform(
+    _env: &mut Environment,
+    point: CGPoint,
+    t: CGAffineTransform,
+) -> CGPoint {
+    let (x, y) = t.apply_to_point((point.x, point.y));
+    CGPoint { x, y }
+}
+
+/// Transforms the rect's corners and returns their bounding box.
+fn apply_to_rect(t: CGAffineTransform, rect: CGRect) -> CGRect {
+    let CGRect {
+        origin: CGPoint { x, y },
+        size: CGSize { width, height },
+    } = rect;
+    let corners = [
+        (x, y),
+        (x + width, y),
+        (x, y + height),
+        (x + width, y + height),
+    ]
+    .map(|corner| t.apply_to_point(corner));
+    let (mut min_x, mut min_y) = corners[0];
+    let (mut max_x, mut max_y) = corners[0];
+    for (x, y) in corners {
+        min_x = min_x.min(x);
+        min_y = min_y.min(y);
+        max_x = max_x.max(x);
+        max_y = max_y.max(y);
+    }
+    CGRect {
+        origin: CGPoint { x: min_x, y: min_y },
+        size: CGSize {
+            width: max_x - min_x,
+            height: max_y - min_y,
+        },
+    }
+}
+
+fn CGRectApplyAffineTransform(
+    _env: &mut Environment,
+    rect: CGRect,
+    t: CGAffineTransform,
+) -> CGRect {
+    apply_to_rect(t, rect)
+}
+
+pub const FUNCTIONS: FunctionExports = &[
+    export_c_func!(CGAffineTransformMake(_, _, _, _, _, _)),
+    export_c_func!(CGAffineTransformMakeRotation(_)),
+    export_c_func!(CGAffineTransformMakeScale(_, _)),
+    export_c_func!(CGAffineTransformMakeTranslation(_, _)),
+    export_c_func!(CGAffineTransformConcat(_, _)),
+    export_c_func!(CGAffineTransformInvert(_)),
+    export_c_func!(CGPointApplyAffineTransform(_, _)),
+    export_c_func!(CGRectApplyAffineTransform(_, _)),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::FRAC_PI_2;
+
+    fn assert_close(actual: (CGFloat, CGFloat), expected: (CGFloat, CGFloat)) {
+        assert!(
+            (actual.0 - expected.0).abs() < 1e-5 && (actual.1 - expected.1).abs() < 1e-5,
+            "{:?} != {:?}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn rotation_then_translation() {
+        // A quarter turn counter-clockwise, then a move to the right.
+        let rotation = CGAffineTransform::rotation(FRAC_PI_2);
+        let translation = CGAffineTransform::translation(10.0, 0.0);
+        let combined = rotation.concat(translation);
+        assert_close(combined.apply_to_point((1.0, 0.0)), (10.0, 1.0));
+        assert_close(combined.apply_to_point((0.0, 2.0)), (8.0, 0.0));
+
+        // The other order moves first, so the translation gets rotated too.
+        let combined = translation.concat(rotation);
+        assert_close(combined.apply_to_point((1.0, 0.0)), (0.0, 11.0));
+
+        let inverse = combined.invert().unwrap();
+        assert_close(inverse.apply_to_point((0.0, 11.0)), (1.0, 0.0));
+        assert_eq!(CGAffineTransform::scale(0.0, 1.0).invert(), None);
+    }
+
+    #[test]
+    fn rect_bounding_box() {
+        let rect = CGRect {
+            origin: CGPoint { x: 1.0, y: 2.0 },
+            size: CGSize {
+                width: 3.0,
+                height: 4.0,
+            },
+        };
+        let transform = CGAffineTransform::scale(-1.0, 2.0);
+        let CGRect { origin, size } = apply_to_rect(transform, rect);
+        assert_close((origin.x, origin.y), (-4.0, 4.0));
+        assert_close((size.width, size.height), (3.0, 8.0));
+    }
+}
@@ -6,14 +6,20 @@
 //! `CGBitmapContext.h`
 
 use super::cg_color_space::{kCGColorSpaceGenericRGB, CGColorSpaceHostObject, CGColorSpaceRef};
-use super::cg_context::{CGContextHostObject, CGContextRef, CGContextSubclass};
+use super::cg_context::{
+    kCGBlendModeClear, kCGBlendModeCopy, kCGBlendModeNormal, kCGTextFill, CGBlendMode,
+    CGContextHostObject, CGContextRef, CGContextSubclass,
+};
 use super::cg_image::{
-    kCGImageAlphaFirst, kCGImageAlphaLast, kCGImageAlphaNone, kCGImageAlphaNoneSkipFirst,
+    self, kCGImageAlphaFirst, kCGImageAlphaLast, kCGImageAlphaNone, kCGImageAlphaNoneSkipFirst,
     kCGImageAlphaNoneSkipLast, kCGImageAlphaOnly, kCGImageAlphaPremultipliedFirst,
-    kCGImageAlphaPremultipliedLast, CGImageAlphaInfo,
+    kCGImageAlphaPremultipliedLast, CGImageAlphaInfo, CGImageHostObject, CGImageRef,
 };
-use super::{CGFloat, CGRect};
+use super::cg_path::Point;
+use super::{CGAffineTransform, CGFloat, CGRect};
 use crate::dyld::{export_c_func, FunctionExports};
+use crate::font::Font;
+use crate::image::Image;
 use crate::mem::{GuestUSize, Mem, MutVoidPtr};
 use crate::objc::ObjC;
 use crate::Environment;
@@ -21,6 +27,9 @@ use crate::Environment;
 #[derive(Copy, Clone)]
 pub(super) struct CGBitmapContextData {
     data: MutVoidPtr,
+    /// Whether `data` was allocated by `CGBitmapContextCreate` and should be
+    /// freed along with the context.
+    owns_data: bool,
     width: GuestUSize,
     height: GuestUSize,
     bits_per_component: GuestUSize,
@@ -29,7 +38,7 @@ pub(super) struct CGBitmapContextData {
     alpha_info: CGImageAlphaInfo,
 }
 
-fn CGBitmapContextCreate(
+pub fn CGBitmapContextCreate(
     env: &mut Environment,
     data: MutVoidPtr,
     width: GuestUSize,
@@ -39,17 +48,55 @@ fn CGBitmapContextCreate(
     color_space: CGColorSpaceRef,
     bitmap_info: u32,
 ) -> CGContextRef {
-    assert!(!data.is_null()); // TODO: support memory allocation
+    create(
+        &mut env.objc,
+        &mut env.mem,
+        data,
+        width,
+        height,
+        bits_per_component,
+        bytes_per_row,
+        color_space,
+        bitmap_info,
+    )
+}
+
+/// [CGBitmapContextCreate] for host code that doesn't have an [Environment].
+pub fn create(
+    objc: &mut ObjC,
+    mem: &mut Mem,
+    data: MutVoidPtr,
+    width: GuestUSize,
+    height: GuestUSize,
+    bits_per_component: GuestUSize,
+    bytes_per_row: GuestUSize,
+    color_space: CGColorSpaceRef,
+    bitmap_info: u32,
+) -> CGContextRef {
     assert!(bits_per_component == 8); // TODO: support other bit depths
-    assert!(components_for_rgb(bitmap_info).is_ok());
+    let components = components_for_rgb(bitmap_info).unwrap();
 
-    let color_space = env.objc.borrow::<CGColorSpaceHostObject>(color_space).name;
+    let color_space = objc.borrow::<CGColorSpaceHostObject>(color_space).name;
     // TODO: support other color spaces
     assert!(color_space == kCGColorSpaceGenericRGB);
 
+    let owns_data = data.is_null();
+    let (data, bytes_per_row) = if owns_data {
+        let bytes_per_row = if bytes_per_row == 0 {
+            width.checked_mul(components).unwrap()
+        } else {
+            bytes_per_row
+        };
+        let data = mem.alloc(height.checked_mul(bytes_per_row).unwrap());
+        (data, bytes_per_row)
+    } else {
+        (data, bytes_per_row)
+    };
+
     let host_object = CGContextHostObject {
         subclass: CGContextSubclass::CGBitmapContext(CGBitmapContextData {
             data,
+            owns_data,
             width,
             height,
             bits_per_component,
@@ -59,12 +106,65 @@ fn CGBitmapContextCreate(
         }),
         // TODO: is this the correct default?
         rgb_fill_color: (0.0, 0.0, 0.0, 0.0),
+        rgb_stroke_color: (0.0, 0.0, 0.0, 1.0),
+        line_width: 1.0,
+        path: Default::default(),
+        ctm: CGAffineTransform::IDENTITY,
+        blend_mode: kCGBlendModeNormal,
+        font: None,
+        text_matrix: CGAffineTransform::IDENTITY,
+        text_drawing_mode: kCGTextFill,
     };
-    let isa = env
-        .objc
-        .get_known_class("_touchHLE_CGContext", &mut env.mem);
-    env.objc
-        .alloc_object(isa, Box::new(host_object), &mut env.mem)
+    let isa = objc.get_known_class("_touchHLE_CGContext", mem);
+    objc.alloc_object(isa, Box::new(host_object), mem)
+}
+
+/// For use by `CGContext`'s destructor.
+pub(super) fn free_data(env: &mut Environment, data: &CGBitmapContextData) {
+    if data.owns_data {
+        env.mem.free(data.data);
+    }
+}
+
+fn bitmap_data(env: &Environment, context: CGContextRef) -> CGBitmapContextData {
+    let &CGContextHostObject {
+        subclass: CGContextSubclass::CGBitmapContext(data),
+        ..
+    } = env.objc.borrow(context);
+    data
+}
+
+pub fn CGBitmapContextCreateImage(env: &mut Environment, context: CGContextRef) -> CGImageRef {
+    let data = bitmap_data(env, context);
+    let pixels = get_pixels(&data, &mut env.mem);
+    let image = to_image(&data, pixels);
+    cg_image::from_image(env, image)
+}
+
+/// Copy the pixels of a bitmap into an [Image], which has no padding and
+/// doesn't use premultiplied alpha.
+fn to_image(data: &CGBitmapContextData, pixels: &[u8]) -> Image {
+    let mut image_pixels = Vec::with_capacity(data.width as usize * data.height as usize * 4);
+    for y in 0..data.height {
+        for x in 0..data.width {
+            let (r, g, b, a) = get_pixel(data, pixels, (x as i32, y as i32)).unwrap();
+            image_pixels.extend([r, g, b, a].map(|c| (c * 255.0).round() as u8));
+        }
+    }
+    Image::from_pixels(image_pixels, (data.width, data.height))
+}
+
+fn CGBitmapContextGetData(env: &mut Environment, context: CGContextRef) -> MutVoidPtr {
+    bitmap_data(env, context).data
+}
+fn CGBitmapContextGetWidth(env: &mut Environment, context: CGContextRef) -> GuestUSize {
+    bitmap_data(env, context).width
+}
+fn CGBitmapContextGetHeight(env: &mut Environment, context: CGContextRef) -> GuestUSize {
+    bitmap_data(env, context).height
+}
+fn CGBitmapContextGetBytesPerRow(env: &mut Environment, context: CGContextRef) -> GuestUSize {
+    bitmap_data(env, context).bytes_per_row
 }
 
 fn components_for_rgb(alpha_info: CGImageAlphaInfo) -> Result<GuestUSize, ()> {
@@ -167,11 +267,80 @@ fn put_pixel(
     }
 }
 
+/// Inverse of [put_pixel]. Returns [None] for pixels outside the bitmap.
+fn get_pixel(
+    data: &CGBitmapContextData,
+    pixels: &[u8],
+    coords: (i32, i32),
+) -> Option<(CGFloat, CGFloat, CGFloat, CGFloat)> {
+    let (x, y) = coords;
+    if x < 0 || y < 0 {
+        return None;
+    }
+    let (x, y) = (x as GuestUSize, y as GuestUSize);
+    if x >= data.width || y >= data.height {
+        return None;
+    }
+    let pixel_size = bytes_per_pixel(data);
+    let first_component_idx = (y * data.bytes_per_row + x * pixel_size) as usize;
+    let component = |i: usize| pixels[first_component_idx + i] as CGFloat / 255.0;
+
+    let unpremultiply = |(r, g, b, a): (CGFloat, CGFloat, CGFloat, CGFloat)| {
+        if a == 0.0 {
+            (0.0, 0.0, 0.0, 0.0)
+        } else {
+            (r / a, g / a, b / a, a)
+        }
+    };
+    Some(match data.alpha_info {
+        kCGImageAlphaNone | kCGImageAlphaNoneSkipLast => {
+            (component(0), component(1), component(2), 1.0)
+        }
+        kCGImageAlphaNoneSkipFirst => (component(1), component(2), component(3), 1.0),
+        kCGImageAlphaPremultipliedLast => {
+            unpremultiply((component(0), component(1), component(2), component(3)))
+        }
+        kCGImageAlphaPremultipliedFirst => {
+            unpremultiply((component(1), component(2), component(3), component(0)))
+        }
+        kCGImageAlphaLast => (component(0), component(1), component(2), component(3)),
+        kCGImageAlphaFirst => (component(1), component(2), component(3), component(0)),
+        kCGImageAlphaOnly => (0.0, 0.0, 0.0, component(0)),
+        _ => unreachable!(), // checked by bytes_per_pixel
+    })
+}
+
+/// Combine a (non-premultiplied) source color with a destination color
+/// according to a blend mode.
+fn blend(
+    mode: CGBlendMode,
+    src: (CGFloat, CGFloat, CGFloat, CGFloat),
+    dst: (CGFloat, CGFloat, CGFloat, CGFloat),
+) -> (CGFloat, CGFloat, CGFloat, CGFloat) {
+    match mode {
+        kCGBlendModeNormal => {
+            let (sr, sg, sb, sa) = src;
+            let (dr, dg, db, da) = dst;
+            let a = sa + da * (1.0 - sa);
+            if a == 0.0 {
+                return (0.0, 0.0, 0.0, 0.0);
+            }
+            let over = |s: CGFloat, d: CGFloat| (s * sa + d * da * (1.0 - sa)) / a;
+            (over(sr, dr), over(sg, dg), over(sb, db), a)
+        }
+        kCGBlendModeClear => (0.0, 0.0, 0.0, 0.0),
+        kCGBlendModeCopy => src,
+        _ => unreachable!(), // checked by CGContextSetBlendMode
+    }
+}
+
 /// Abstract interface for use by host code that wants to draw in a bitmap
 /// context.
 pub struct CGBitmapContextDrawer<'a> {
     bitmap_info: CGBitmapContextData,
     rgb_fill_color: (CGFloat, CGFloat, CGFloat, CGFloat),
+    ctm: CGAffineTransform,
+    blend_mode: CGBlendMode,
     pixels: &'a mut [u8],
 }
 impl CGBitmapContextDrawer<'_> {
@@ -183,6 +352,9 @@ impl CGBitmapContextDrawer<'_> {
         let &CGContextHostObject {
             subclass: CGContextSubclass::CGBitmapContext(bitmap_info),
             rgb_fill_color,
+            ctm,
+            blend_mode,
+            ..
         } = objc.borrow(context);
 
         let pixels = get_pixels(&bitmap_info, mem);
@@ -190,6 +362,8 @@ impl CGBitmapContextDrawer<'_> {
         CGBitmapContextDrawer {
             bitmap_info,
             rgb_fill_color,
+            ctm,
+            blend_mode,
             pixels,
         }
     }
@@ -204,28 +378,305 @@ impl CGBitmapContextDrawer<'_> {
         self.rgb_fill_color
     }
 
+    /// Transform from user space to pixel coordinates. This combines the
+    /// current transformation matrix with the flip from Core Graphics'
+    /// bottom-left origin to the top-left origin of the bitmap's rows.
+    pub fn user_to_pixel_transform(&self) -> CGAffineTransform {
+        let flip = CGAffineTransform {
+            d: -1.0,
+            ty: self.bitmap_info.height as CGFloat,
+            ..CGAffineTransform::IDENTITY
+        };
+        self.ctm.concat(flip)
+    }
+
     pub fn put_pixel(&mut self, coords: (i32, i32), color: (CGFloat, CGFloat, CGFloat, CGFloat)) {
         put_pixel(&self.bitmap_info, self.pixels, coords, color)
     }
+
+    /// Like [Self::put_pixel], but combines the color with the existing pixel
+    /// according to the context's blend mode.
+    pub fn blend_pixel(&mut self, coords: (i32, i32), color: (CGFloat, CGFloat, CGFloat, CGFloat)) {
+        let Some(existing) = get_pixel(&self.bitmap_info, self.pixels, coords) else {
+            return;
+        };
+        let color = blend(self.blend_mode, color, existing);
+        put_pixel(&self.bitmap_info, self.pixels, coords, color)
+    }
 }
 
 /// Implementation of `CGContextFillRect` for `CGBitmapContext`.
 pub(super) fn fill_rect(env: &mut Environment, context: CGContextRef, rect: CGRect) {
+    let CGRect { origin, size } = rect;
+    let (x0, y0) = (origin.x, origin.y);
+    let (x1, y1) = (origin.x + size.width, origin.y + size.height);
+    let color = env
+        .objc
+        .borrow::<CGContextHostObject>(context)
+        .rgb_fill_color;
+    fill_polygons(
+        env,
+        context,
+        &[vec![(x0, y0), (x1, y0), (x1, y1), (x0, y1)]],
+        false,
+        color,
+    );
+}
+
+/// Fill polygons (e.g. from a path) in a `CGBitmapContext` with a solid
+/// color. With `even_odd`, the even-odd rule is used rather than the non-zero
+/// winding rule.
+pub(super) fn fill_polygons(
+    env: &mut Environment,
+    context: CGContextRef,
+    polygons: &[Vec<Point>],
+    even_odd: bool,
+    color: (CGFloat, CGFloat, CGFloat, CGFloat),
+) {
     let mut drawer = CGBitmapContextDrawer::new(&env.objc, &mut env.mem, context);
 
-    // TODO: correct anti-aliasing
-    let x_start = (rect.origin.x.round() as GuestUSize).min(0);
-    let y_start = (rect.origin.y.round() as GuestUSize).min(0);
-    let x_end = ((rect.origin.x + rect.size.width).round() as GuestUSize).max(drawer.width());
-    let y_end = ((rect.origin.y + rect.size.height).round() as GuestUSize).max(drawer.height());
+    let transform = drawer.user_to_pixel_transform();
+    let polygons: Vec<Vec<Point>> = polygons
+        .iter()
+        .map(|polygon| {
+            polygon
+                .iter()
+                .map(|&point| transform.apply_to_point(point))
+                .collect()
+        })
+        .collect();
+
+    // TODO: anti-aliasing
+    let (width, height) = (drawer.width(), drawer.height());
+    super::cg_path::rasterize_polygons(&polygons, even_odd, width, height, |x, y| {
+        drawer.blend_pixel((x, y), color)
+    });
+}
+
+/// Implementation of `CGContextDrawImage` for `CGBitmapContext`.
+pub(super) fn draw_image(
+    env: &mut Environment,
+    context: CGContextRef,
+    rect: CGRect,
+    image: CGImageRef,
+) {
+    let image = &env.objc.borrow::<CGImageHostObject>(image).image;
+    let mut drawer = CGBitmapContextDrawer::new(&env.objc, &mut env.mem, context);
+
+    let (image_width, image_height) = image.dimensions();
+    let image_pixels = image.pixels();
+
+    // TODO: interpolation, anti-aliasing
+    let transform = drawer.user_to_pixel_transform();
+    let (width, height) = (drawer.width(), drawer.height());
+    rasterize_image(
+        rect,
+        (image_width, image_height),
+        transform,
+        (width, height),
+        |coords, (image_x, image_y)| {
+            let idx = ((image_y * image_width + image_x) * 4) as usize;
+            let component = |i: usize| image_pixels[idx + i] as CGFloat / 255.0;
+            let color = (component(0), component(1), component(2), component(3));
+            drawer.blend_pixel(coords, color);
+        },
+    );
+}
+
+/// Implementation of `CGContextShowText` for `CGBitmapContext`. The text is
+/// drawn at the origin of text space, and `color` is [None] for invisible
+/// text. Returns the advance of the text in text space.
+pub(super) fn show_text(
+    objc: &ObjC,
+    mem: &mut Mem,
+    context: CGContextRef,
+    font: &Font,
+    font_size: CGFloat,
+    text: &str,
+    text_matrix: CGAffineTransform,
+    color: Option<(CGFloat, CGFloat, CGFloat, CGFloat)>,
+) -> CGFloat {
+    let mut drawer = CGBitmapContextDrawer::new(objc, mem, context);
+
+    // TODO: Glyphs are rasterized once and then transformed, so scaling,
+    // rotation etc will look bad.
+    let transform = text_matrix.concat(drawer.user_to_pixel_transform());
+    font.draw_line(font_size, text, |(x, y), coverage| {
+        let Some((r, g, b, a)) = color else {
+            return;
+        };
+        let (x, y) = transform.apply_to_point((x as CGFloat + 0.5, y as CGFloat + 0.5));
+        drawer.blend_pixel(
+            (x.floor() as i32, y.floor() as i32),
+            (r, g, b, a * coverage),
+        );
+    })
+}
+
+/// Map an image of size `image_size` onto `rect` in user space, calling
+/// `put_pixel` with the pixel coordinates (within `bitmap_size`) and the
+/// nearest image pixel for each pixel whose center is covered. The first row
+/// of the image is its top, which Core Graphics places at the maximum y of
+/// `rect`.
+pub(crate) fn rasterize_image(
+    rect: CGRect,
+    image_size: (u32, u32),
+    user_to_pixel: CGAffineTransform,
+    bitmap_size: (GuestUSize, GuestUSize),
+    mut put_pixel: impl FnMut((i32, i32), (u32, u32)),
+) {
+    let (image_width, image_height) = image_size;
+    let (width, height) = bitmap_size;
+    let CGRect { origin, size } = rect;
+    if image_width == 0 || image_height == 0 || size.width == 0.0 || size.height == 0.0 {
+        return;
+    }
+    let Some(pixel_to_user) = user_to_pixel.invert() else {
+        return;
+    };
+
+    // Bounding box of the rect in pixel coordinates
+    let corners = [
+        (origin.x, origin.y),
+        (origin.x + size.width, origin.y),
+        (origin.x, origin.y + size.height),
+        (origin.x + size.width, origin.y + size.height),
+    ]
+    .map(|corner| user_to_pixel.apply_to_point(corner));
+    let min_x = corners
+        .iter()
+        .map(|c| c.0)
+        .fold(CGFloat::INFINITY, CGFloat::min);
+    let max_x = corners
+        .iter()
+        .map(|c| c.0)
+        .fold(CGFloat::NEG_INFINITY, CGFloat::max);
+    let min_y = corners
+        .iter()
+        .map(|c| c.1)
+        .fold(CGFloat::INFINITY, CGFloat::min);
+    let max_y = corners
+        .iter()
+        .map(|c| c.1)
+        .fold(CGFloat::NEG_INFINITY, CGFloat::max);
+    let x_start = (min_x - 0.5).ceil().max(0.0) as u32;
+    let x_end = ((max_x - 0.5).ceil().max(0.0) as u32).min(width);
+    let y_start = (min_y - 0.5).ceil().max(0.0) as u32;
+    let y_end = ((max_y - 0.5).ceil().max(0.0) as u32).min(height);
 
-    let color = drawer.rgb_fill_color();
     for y in y_start..y_end {
         for x in x_start..x_end {
-            drawer.put_pixel((x as _, y as _), color)
+            let center = (x as CGFloat + 0.5, y as CGFloat + 0.5);
+            let (user_x, user_y) = pixel_to_user.apply_to_point(center);
+            // Position within the rect, from 0 to 1. A negative width or height
+            // flips the image.
+            let u = (user_x - origin.x) / size.width;
+            let v = (user_y - origin.y) / size.height;
+            if !(0.0..1.0).contains(&u) || !(0.0..1.0).contains(&v) {
+                continue;
+            }
+            let image_x = ((u * image_width as CGFloat) as u32).min(image_width - 1);
+            let image_y = (((1.0 - v) * image_height as CGFloat) as u32).min(image_height - 1);
+            put_pixel((x as i32, y as i32), (image_x, image_y));
         }
     }
 }
 
-pub const FUNCTIONS: FunctionExports =
-    &[export_c_func!(CGBitmapContextCreate(_, _, _, _, _, _, _))];
+pub const FUNCTIONS: FunctionExports = &[
+    export_c_func!(CGBitmapContextCreate(_, _, _, _, _, _, _)),
+    export_c_func!(CGBitmapContextCreateImage(_)),
+    export_c_func!(CGBitmapContextGetData(_)),
+    export_c_func!(CGBitmapContextGetWidth(_)),
+    export_c_func!(CGBitmapContextGetHeight(_)),
+    export_c_func!(CGBitmapContextGetBytesPerRow(_)),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::super::{CGPoint, CGSize};
+    use super::*;
+    use crate::mem::Ptr;
+
+    /// Draw a 2×2 image (with pixels labelled `a` to `d` in reading order)
+    /// into a grid of characters, for easy comparison.
+    fn render(rect: CGRect, ctm: CGAffineTransform, size: u32) -> Vec<String> {
+        let mut grid = vec![vec!['.'; size as usize]; size as usize];
+        let flip = CGAffineTransform {
+            d: -1.0,
+            ty: size as CGFloat,
+            ..CGAffineTransform::IDENTITY
+        };
+        rasterize_image(
+            rect,
+            (2, 2),
+            ctm.concat(flip),
+            (size, size),
+            |(x, y), (image_x, image_y)| {
+                grid[y as usize][x as usize] =
+                    ['a', 'b', 'c', 'd'][(image_y * 2 + image_x) as usize];
+            },
+        );
+        grid.into_iter()
+            .map(|row| row.into_iter().collect())
+            .collect()
+    }
+
+    fn rect(x: CGFloat, y: CGFloat, width: CGFloat, height: CGFloat) -> CGRect {
+        CGRect {
+            origin: CGPoint { x, y },
+            size: CGSize { width, height },
+        }
+    }
+
+    #[test]
+    fn draw_image_at_offset() {
+        // The origin is at the bottom left, so y=1 is the second row from the
+        // bottom.
+        assert_eq!(
+            render(rect(3.0, 1.0, 2.0, 2.0), CGAffineTransform::IDENTITY, 6),
+            ["......", "......", "......", "...ab.", "...cd.", "......"]
+        );
+    }
+
+    #[test]
+    fn draw_image_scaled_and_translated() {
+        let ctm =
+            CGAffineTransform::scale(2.0, 2.0).concat(CGAffineTransform::translation(1.0, 0.0));
+        assert_eq!(
+            render(rect(0.0, 0.0, 2.0, 2.0), ctm, 6),
+            ["......", "......", ".aabb.", ".aabb.", ".ccdd.", ".ccdd."]
+        );
+    }
+
+    #[test]
+    fn draw_image_in_flipped_context() {
+        // The usual transform for using UIKit-style coordinates results in the
+        // image being drawn upside-down, like on a real device.
+        let ctm =
+            CGAffineTransform::scale(1.0, -1.0).concat(CGAffineTransform::translation(0.0, 6.0));
+        assert_eq!(
+            render(rect(1.0, 2.0, 2.0, 2.0), ctm, 6),
+            ["......", "......", ".cd...", ".ab...", "......", "......"]
+        );
+    }
+
+    #[test]
+    fn image_from_premultiplied_argb() {
+        // A 2×1 bitmap with a padded row: opaque red, then half-transparent
+        // green.
+        let data = CGBitmapContextData {
+            data: Ptr::null(),
+            owns_data: false,
+            width: 2,
+            height: 1,
+            bits_per_component: 8,
+            bytes_per_row: 12,
+            color_space: kCGColorSpaceGenericRGB,
+            alpha_info: kCGImageAlphaPremultipliedFirst,
+        };
+        let pixels = [255, 255, 0, 0, 128, 0, 128, 0, 0xAA, 0xAA, 0xAA, 0xAA];
+        let image = to_image(&data, &pixels);
+        assert_eq!(image.dimensions(), (2, 1));
+        assert_eq!(image.pixels(), [255, 0, 0, 255, 0, 255, 0, 128]);
+    }
+}
@@ -0,0 +1,178 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! `CGColor.h`
+
+use super::cg_color_space::{
+    create_with_name, kCGColorSpaceGenericRGB, CGColorSpaceHostObject, CGColorSpaceRef,
+    CGColorSpaceRelease, CGColorSpaceRetain,
+};
+use super::CGFloat;
+use crate::dyld::{export_c_func, FunctionExports};
+use crate::frameworks::core_foundation::{CFRelease, CFRetain, CFTypeRef};
+use crate::mem::{guest_size_of, ConstPtr, GuestUSize, Mem, MutPtr};
+use crate::objc::{nil, objc_classes, ClassExports, HostObject, ObjC};
+use crate::Environment;
+
+pub const CLASSES: ClassExports = objc_classes! {
+
+(env, this, _cmd);
+
+// CGColor seems to be a CFType-based type, but in our implementation those
+// are just Objective-C types, so we need a class for it, but its name is not
+// visible anywhere.
+@implementation _touchHLE_CGColor: NSObject
+
+- (())dealloc {
+    let &CGColorHostObject {
+        color_space,
+        components,
+        ..
+    } = env.objc.borrow(this);
+    CGColorSpaceRelease(env, color_space);
+    env.mem.free(components.cast());
+    env.objc.dealloc_object(this, &mut env.mem)
+}
+
+@end
+
+};
+
+struct CGColorHostObject {
+    color_space: CGColorSpaceRef,
+    /// The components including alpha. These are kept in guest memory so that
+    /// `CGColorGetComponents` can return a pointer to them.
+    components: MutPtr<CGFloat>,
+    component_count: GuestUSize,
+}
+impl HostObject for CGColorHostObject {}
+
+pub type CGColorRef = CFTypeRef;
+
+fn components_for_color_space(env: &Environment, color_space: CGColorSpaceRef) -> GuestUSize {
+    let name = env.objc.borrow::<CGColorSpaceHostObject>(color_space).name;
+    // TODO: support other color spaces
+    assert!(name == kCGColorSpaceGenericRGB);
+    3
+}
+
+pub fn CGColorCreate(
+    env: &mut Environment,
+    color_space: CGColorSpaceRef,
+    components: ConstPtr<CGFloat>,
+) -> CGColorRef {
+    if color_space.is_null() || components.is_null() {
+        return nil;
+    }
+
+    // Color space components plus alpha
+    let component_count = components_for_color_space(env, color_space) + 1;
+    let components: Vec<CGFloat> = (0..component_count)
+        .map(|i| env.mem.read(components + i))
+        .collect();
+    let color_space = CGColorSpaceRetain(env, color_space);
+    create_with_components(&mut env.objc, &mut env.mem, color_space, &components)
+}
+
+pub fn CGColorCreateGenericRGB(
+    env: &mut Environment,
+    red: CGFloat,
+    green: CGFloat,
+    blue: CGFloat,
+    alpha: CGFloat,
+) -> CGColorRef {
+    create_generic_rgb(&mut env.objc, &mut env.mem, (red, green, blue, alpha))
+}
+
+/// [CGColorCreateGenericRGB] for host code that doesn't have an [Environment].
+pub fn create_generic_rgb(
+    objc: &mut ObjC,
+    mem: &mut Mem,
+    (red, green, blue, alpha): (CGFloat, CGFloat, CGFloat, CGFloat),
+) -> CGColorRef {
+    let color_space = create_with_name(objc, mem, kCGColorSpaceGenericRGB);
+    create_with_components(objc, mem, color_space, &[red, green, blue, alpha])
+}
+
+/// Takes ownership of the reference to `color_space`.
+fn create_with_components(
+    objc: &mut ObjC,
+    mem: &mut Mem,
+    color_space: CGColorSpaceRef,
+    components: &[CGFloat],
+) -> CGColorRef {
+    let component_count: GuestUSize = components.len().try_into().unwrap();
+    let guest_components: MutPtr<CGFloat> = mem
+        .alloc(component_count * guest_size_of::<CGFloat>())
+        .cast();
+    for (i, &component) in components.iter().enumerate() {
+        mem.write(guest_components + i as GuestUSize, component);
+    }
+
+    let isa = objc.get_known_class("_touchHLE_CGColor", mem);
+    objc.alloc_object(
+        isa,
+        Box::new(CGColorHostObject {
+            color_space,
+            components: guest_components,
+            component_count,
+        }),
+        mem,
+    )
+}
+
+pub fn CGColorRelease(env: &mut Environment, color: CGColorRef) {
+    if !color.is_null() {
+        CFRelease(env, color);
+    }
+}
+pub fn CGColorRetain(env: &mut Environment, color: CGColorRef) -> CGColorRef {
+    if !color.is_null() {
+        CFRetain(env, color)
+    } else {
+        color
+    }
+}
+
+fn CGColorGetColorSpace(env: &mut Environment, color: CGColorRef) -> CGColorSpaceRef {
+    env.objc.borrow::<CGColorHostObject>(color).color_space
+}
+
+fn CGColorGetNumberOfComponents(env: &mut Environment, color: CGColorRef) -> GuestUSize {
+    env.objc.borrow::<CGColorHostObject>(color).component_count
+}
+
+fn CGColorGetComponents(env: &mut Environment, color: CGColorRef) -> ConstPtr<CGFloat> {
+    env.objc
+        .borrow::<CGColorHostObject>(color)
+        .components
+        .cast_const()
+}
+
+/// Get the color as RGBA, for use by drawing functions.
+pub fn get_rgba(objc: &ObjC, mem: &Mem, color: CGColorRef) -> (CGFloat, CGFloat, CGFloat, CGFloat) {
+    let &CGColorHostObject {
+        components,
+        component_count,
+        ..
+    } = objc.borrow(color);
+    assert!(component_count == 4); // only RGB is supported for now
+    (
+        mem.read(components),
+        mem.read(components + 1),
+        mem.read(components + 2),
+        mem.read(components + 3),
+    )
+}
+
+pub const FUNCTIONS: FunctionExports = &[
+    export_c_func!(CGColorCreate(_, _)),
+    export_c_func!(CGColorCreateGenericRGB(_, _, _, _)),
+    export_c_func!(CGColorRetain(_)),
+    export_c_func!(CGColorRelease(_)),
+    export_c_func!(CGColorGetColorSpace(_)),
+    export_c_func!(CGColorGetNumberOfComponents(_)),
+    export_c_func!(CGColorGetComponents(_)),
+];
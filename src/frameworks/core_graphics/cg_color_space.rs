@@ -9,7 +9,8 @@ use crate::dyld::{export_c_func, ConstantExports, FunctionExports, HostConstant}
 use crate::frameworks::core_foundation::cf_string::CFStringRef;
 use crate::frameworks::core_foundation::{CFRelease, CFRetain, CFTypeRef};
 use crate::frameworks::foundation::ns_string;
-use crate::objc::{msg, objc_classes, ClassExports, HostObject};
+use crate::mem::Mem;
+use crate::objc::{msg, objc_classes, ClassExports, HostObject, ObjC};
 use crate::Environment;
 
 pub const CLASSES: ClassExports = objc_classes! {
@@ -36,16 +37,13 @@ pub fn CGColorSpaceCreateWithName(env: &mut Environment, name: CFStringRef) -> C
     // TODO: support more color spaces
     assert!(msg![env; name isEqualToString:generic_rgb]);
 
-    let isa = env
-        .objc
-        .get_known_class("_touchHLE_CGColorSpace", &mut env.mem);
-    env.objc.alloc_object(
-        isa,
-        Box::new(CGColorSpaceHostObject {
-            name: kCGColorSpaceGenericRGB,
-        }),
-        &mut env.mem,
-    )
+    create_with_name(&mut env.objc, &mut env.mem, kCGColorSpaceGenericRGB)
+}
+
+/// Shortcut for host code, e.g. functions that implicitly use a color space.
+pub fn create_with_name(objc: &mut ObjC, mem: &mut Mem, name: &'static str) -> CGColorSpaceRef {
+    let isa = objc.get_known_class("_touchHLE_CGColorSpace", mem);
+    objc.alloc_object(isa, Box::new(CGColorSpaceHostObject { name }), mem)
 }
 
 pub fn CGColorSpaceRelease(env: &mut Environment, cs: CGColorSpaceRef) {
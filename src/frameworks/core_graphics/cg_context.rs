@@ -6,10 +6,15 @@
 //! `CGContext.h`
 
 use super::cg_bitmap_context;
-use super::{CGFloat, CGRect};
+use super::cg_color::{self, CGColorRef};
+use super::cg_image::CGImageRef;
+use super::cg_path::Path;
+use super::{CGAffineTransform, CGFloat, CGPoint, CGRect};
 use crate::dyld::{export_c_func, FunctionExports};
+use crate::font::Font;
 use crate::frameworks::core_foundation::{CFRelease, CFRetain, CFTypeRef};
-use crate::objc::{objc_classes, ClassExports, HostObject};
+use crate::mem::{ConstPtr, GuestISize, GuestUSize, Mem};
+use crate::objc::{objc_classes, ClassExports, HostObject, ObjC};
 use crate::Environment;
 
 pub const CLASSES: ClassExports = objc_classes! {
@@ -20,13 +25,44 @@ pub const CLASSES: ClassExports = objc_classes! {
 // are just Objective-C types, so we need a class for it, but its name is not
 // visible anywhere.
 @implementation _touchHLE_CGContext: NSObject
+
+- (())dealloc {
+    let &CGContextHostObject {
+        subclass: CGContextSubclass::CGBitmapContext(data),
+        ..
+    } = env.objc.borrow(this);
+    cg_bitmap_context::free_data(env, &data);
+    env.objc.dealloc_object(this, &mut env.mem)
+}
+
 @end
 
 };
 
+#[derive(Default)]
+pub struct State {
+    regular_font: Option<Font>,
+    bold_font: Option<Font>,
+    italic_font: Option<Font>,
+}
+
 pub(super) struct CGContextHostObject {
     pub(super) subclass: CGContextSubclass,
     pub(super) rgb_fill_color: (CGFloat, CGFloat, CGFloat, CGFloat),
+    pub(super) rgb_stroke_color: (CGFloat, CGFloat, CGFloat, CGFloat),
+    pub(super) line_width: CGFloat,
+    /// The current path, which is consumed by drawing it.
+    pub(super) path: Path,
+    /// The current transformation matrix (CTM), mapping user space to the
+    /// context's default coordinate space.
+    pub(super) ctm: CGAffineTransform,
+    pub(super) blend_mode: CGBlendMode,
+    /// Font and size selected with `CGContextSelectFont`, if any.
+    pub(super) font: Option<(FontKind, CGFloat)>,
+    /// Transform from text space to user space. The translation part is the
+    /// text position.
+    pub(super) text_matrix: CGAffineTransform,
+    pub(super) text_drawing_mode: CGTextDrawingMode,
 }
 impl HostObject for CGContextHostObject {}
 
@@ -36,6 +72,33 @@ pub(super) enum CGContextSubclass {
 
 pub type CGContextRef = CFTypeRef;
 
+/// The bundled font (see `font.rs`) that is standing in for the selected one.
+#[derive(Copy, Clone, Debug)]
+pub(super) enum FontKind {
+    Regular,
+    Bold,
+    Italic,
+}
+
+pub type CGTextEncoding = GuestISize;
+pub const kCGEncodingFontSpecific: CGTextEncoding = 0;
+pub const kCGEncodingMacRoman: CGTextEncoding = 1;
+
+pub type CGTextDrawingMode = GuestISize;
+pub const kCGTextFill: CGTextDrawingMode = 0;
+pub const kCGTextStroke: CGTextDrawingMode = 1;
+pub const kCGTextFillStroke: CGTextDrawingMode = 2;
+pub const kCGTextInvisible: CGTextDrawingMode = 3;
+pub const kCGTextFillClip: CGTextDrawingMode = 4;
+pub const kCGTextStrokeClip: CGTextDrawingMode = 5;
+pub const kCGTextFillStrokeClip: CGTextDrawingMode = 6;
+pub const kCGTextClip: CGTextDrawingMode = 7;
+
+pub type CGBlendMode = GuestISize;
+pub const kCGBlendModeNormal: CGBlendMode = 0;
+pub const kCGBlendModeClear: CGBlendMode = 16;
+pub const kCGBlendModeCopy: CGBlendMode = 17;
+
 pub fn CGContextRelease(env: &mut Environment, c: CGContextRef) {
     if !c.is_null() {
         CFRelease(env, c);
@@ -63,13 +126,446 @@ fn CGContextSetRGBFillColor(
         .rgb_fill_color = color;
 }
 
+fn CGContextSetRGBStrokeColor(
+    env: &mut Environment,
+    context: CGContextRef,
+    red: CGFloat,
+    green: CGFloat,
+    blue: CGFloat,
+    alpha: CGFloat,
+) {
+    let color = (red, green, blue, alpha);
+    env.objc
+        .borrow_mut::<CGContextHostObject>(context)
+        .rgb_stroke_color = color;
+}
+
+pub fn CGContextSetFillColorWithColor(
+    env: &mut Environment,
+    context: CGContextRef,
+    color: CGColorRef,
+) {
+    set_fill_color(&mut env.objc, &env.mem, context, color);
+}
+
+/// [CGContextSetFillColorWithColor] for host code that doesn't have an
+/// [Environment].
+pub fn set_fill_color(objc: &mut ObjC, mem: &Mem, context: CGContextRef, color: CGColorRef) {
+    let color = cg_color::get_rgba(objc, mem, color);
+    objc.borrow_mut::<CGContextHostObject>(context)
+        .rgb_fill_color = color;
+}
+
+pub fn CGContextSetStrokeColorWithColor(
+    env: &mut Environment,
+    context: CGContextRef,
+    color: CGColorRef,
+) {
+    let color = cg_color::get_rgba(&env.objc, &env.mem, color);
+    env.objc
+        .borrow_mut::<CGContextHostObject>(context)
+        .rgb_stroke_color = color;
+}
+
+fn CGContextSetLineWidth(env: &mut Environment, context: CGContextRef, width: CGFloat) {
+    env.objc
+        .borrow_mut::<CGContextHostObject>(context)
+        .line_width = width;
+}
+
+fn CGContextSetBlendMode(env: &mut Environment, context: CGContextRef, mode: CGBlendMode) {
+    let mode = match mode {
+        kCGBlendModeNormal | kCGBlendModeClear | kCGBlendModeCopy => mode,
+        _ => {
+            log!("TODO: blend mode {}, using normal blending instead", mode);
+            kCGBlendModeNormal
+        }
+    };
+    env.objc
+        .borrow_mut::<CGContextHostObject>(context)
+        .blend_mode = mode;
+}
+
+fn CGContextGetCTM(env: &mut Environment, context: CGContextRef) -> CGAffineTransform {
+    env.objc.borrow::<CGContextHostObject>(context).ctm
+}
+
+fn CGContextConcatCTM(env: &mut Environment, context: CGContextRef, transform: CGAffineTransform) {
+    let host_object = env.objc.borrow_mut::<CGContextHostObject>(context);
+    host_object.ctm = transform.concat(host_object.ctm);
+}
+
+pub fn CGContextTranslateCTM(
+    env: &mut Environment,
+    context: CGContextRef,
+    tx: CGFloat,
+    ty: CGFloat,
+) {
+    CGContextConcatCTM(env, context, CGAffineTransform::translation(tx, ty));
+}
+
+pub fn CGContextScaleCTM(env: &mut Environment, context: CGContextRef, sx: CGFloat, sy: CGFloat) {
+    CGContextConcatCTM(env, context, CGAffineTransform::scale(sx, sy));
+}
+
+fn CGContextRotateCTM(env: &mut Environment, context: CGContextRef, angle: CGFloat) {
+    CGContextConcatCTM(env, context, CGAffineTransform::rotation(angle));
+}
+
 fn CGContextFillRect(env: &mut Environment, context: CGContextRef, rect: CGRect) {
     cg_bitmap_context::fill_rect(env, context, rect);
 }
 
+fn CGContextStrokeRect(env: &mut Environment, context: CGContextRef, rect: CGRect) {
+    let mut path = Path::default();
+    path.add_rect(
+        (rect.origin.x, rect.origin.y),
+        (rect.size.width, rect.size.height),
+    );
+    stroke_path(env, context, &path);
+}
+
+fn path_mut(env: &mut Environment, context: CGContextRef) -> &mut Path {
+    &mut env.objc.borrow_mut::<CGContextHostObject>(context).path
+}
+
+fn CGContextBeginPath(env: &mut Environment, context: CGContextRef) {
+    *path_mut(env, context) = Path::default();
+}
+
+fn CGContextMoveToPoint(env: &mut Environment, context: CGContextRef, x: CGFloat, y: CGFloat) {
+    path_mut(env, context).move_to((x, y));
+}
+
+fn CGContextAddLineToPoint(env: &mut Environment, context: CGContextRef, x: CGFloat, y: CGFloat) {
+    path_mut(env, context).line_to((x, y));
+}
+
+fn CGContextAddQuadCurveToPoint(
+    env: &mut Environment,
+    context: CGContextRef,
+    cpx: CGFloat,
+    cpy: CGFloat,
+    x: CGFloat,
+    y: CGFloat,
+) {
+    path_mut(env, context).quad_curve_to((cpx, cpy), (x, y));
+}
+
+fn CGContextAddCurveToPoint(
+    env: &mut Environment,
+    context: CGContextRef,
+    cp1x: CGFloat,
+    cp1y: CGFloat,
+    cp2x: CGFloat,
+    cp2y: CGFloat,
+    x: CGFloat,
+    y: CGFloat,
+) {
+    path_mut(env, context).curve_to((cp1x, cp1y), (cp2x, cp2y), (x, y));
+}
+
+fn CGContextAddRect(env: &mut Environment, context: CGContextRef, rect: CGRect) {
+    path_mut(env, context).add_rect(
+        (rect.origin.x, rect.origin.y),
+        (rect.size.width, rect.size.height),
+    );
+}
+
+fn CGContextAddArc(
+    env: &mut Environment,
+    context: CGContextRef,
+    x: CGFloat,
+    y: CGFloat,
+    radius: CGFloat,
+    start_angle: CGFloat,
+    end_angle: CGFloat,
+    clockwise: i32,
+) {
+    path_mut(env, context).arc((x, y), radius, start_angle, end_angle, clockwise != 0);
+}
+
+fn CGContextClosePath(env: &mut Environment, context: CGContextRef) {
+    path_mut(env, context).close();
+}
+
+fn CGContextIsPathEmpty(env: &mut Environment, context: CGContextRef) -> bool {
+    path_mut(env, context).is_empty()
+}
+
+fn CGContextGetPathCurrentPoint(env: &mut Environment, context: CGContextRef) -> CGPoint {
+    let (x, y) = path_mut(env, context).current_point().unwrap_or((0.0, 0.0));
+    CGPoint { x, y }
+}
+
+fn fill_path(env: &mut Environment, context: CGContextRef, path: &Path, even_odd: bool) {
+    let color = env
+        .objc
+        .borrow::<CGContextHostObject>(context)
+        .rgb_fill_color;
+    cg_bitmap_context::fill_polygons(env, context, &path.fill_polygons(), even_odd, color);
+}
+
+fn stroke_path(env: &mut Environment, context: CGContextRef, path: &Path) {
+    let &CGContextHostObject {
+        rgb_stroke_color,
+        line_width,
+        ..
+    } = env.objc.borrow(context);
+    cg_bitmap_context::fill_polygons(
+        env,
+        context,
+        &path.stroke_polygons(line_width),
+        false,
+        rgb_stroke_color,
+    );
+}
+
+fn CGContextFillPath(env: &mut Environment, context: CGContextRef) {
+    let path = std::mem::take(path_mut(env, context));
+    fill_path(env, context, &path, false);
+}
+
+fn CGContextEOFillPath(env: &mut Environment, context: CGContextRef) {
+    let path = std::mem::take(path_mut(env, context));
+    fill_path(env, context, &path, true);
+}
+
+fn CGContextStrokePath(env: &mut Environment, context: CGContextRef) {
+    let path = std::mem::take(path_mut(env, context));
+    stroke_path(env, context, &path);
+}
+
+fn CGContextDrawImage(
+    env: &mut Environment,
+    context: CGContextRef,
+    rect: CGRect,
+    image: CGImageRef,
+) {
+    if image.is_null() {
+        return;
+    }
+    cg_bitmap_context::draw_image(env, context, rect, image);
+}
+
+fn get_font(state: &mut State, kind: FontKind) -> &Font {
+    let (font, constructor): (_, fn() -> Font) = match kind {
+        FontKind::Regular => (&mut state.regular_font, Font::sans_regular),
+        FontKind::Bold => (&mut state.bold_font, Font::sans_bold),
+        FontKind::Italic => (&mut state.italic_font, Font::sans_italic),
+    };
+    font.get_or_insert_with(constructor)
+}
+
+fn CGContextSelectFont(
+    env: &mut Environment,
+    context: CGContextRef,
+    name: ConstPtr<u8>,
+    size: CGFloat,
+    encoding: CGTextEncoding,
+) {
+    let name = env.mem.cstr_at_utf8(name);
+    // Only the bundled fonts are available, which are a substitute for
+    // Helvetica, so only the style of the requested font is considered.
+    let kind = if name.contains("Bold") {
+        FontKind::Bold
+    } else if name.contains("Italic") || name.contains("Oblique") {
+        FontKind::Italic
+    } else {
+        FontKind::Regular
+    };
+    log_dbg!("CGContextSelectFont(): using {:?} for {:?}", kind, name);
+    if encoding != kCGEncodingMacRoman {
+        assert!(encoding == kCGEncodingFontSpecific);
+        log!("TODO: font-specific text encoding, treating as MacRoman");
+    }
+
+    // Load the font now so text drawing doesn't need to
+    get_font(&mut env.framework_state.core_graphics.cg_context, kind);
+    env.objc.borrow_mut::<CGContextHostObject>(context).font = Some((kind, size));
+}
+
+fn CGContextSetTextMatrix(env: &mut Environment, context: CGContextRef, matrix: CGAffineTransform) {
+    env.objc
+        .borrow_mut::<CGContextHostObject>(context)
+        .text_matrix = matrix;
+}
+
+fn CGContextGetTextMatrix(env: &mut Environment, context: CGContextRef) -> CGAffineTransform {
+    env.objc.borrow::<CGContextHostObject>(context).text_matrix
+}
+
+fn CGContextSetTextPosition(env: &mut Environment, context: CGContextRef, x: CGFloat, y: CGFloat) {
+    let host_object = env.objc.borrow_mut::<CGContextHostObject>(context);
+    host_object.text_matrix.tx = x;
+    host_object.text_matrix.ty = y;
+}
+
+fn CGContextGetTextPosition(env: &mut Environment, context: CGContextRef) -> CGPoint {
+    let text_matrix = env.objc.borrow::<CGContextHostObject>(context).text_matrix;
+    CGPoint {
+        x: text_matrix.tx,
+        y: text_matrix.ty,
+    }
+}
+
+fn CGContextSetTextDrawingMode(
+    env: &mut Environment,
+    context: CGContextRef,
+    mode: CGTextDrawingMode,
+) {
+    env.objc
+        .borrow_mut::<CGContextHostObject>(context)
+        .text_drawing_mode = mode;
+}
+
+fn CGContextShowText(
+    env: &mut Environment,
+    context: CGContextRef,
+    string: ConstPtr<u8>,
+    length: GuestUSize,
+) {
+    // TODO: MacRoman is only the same as Latin-1 for ASCII characters
+    let text: String = env
+        .mem
+        .bytes_at(string, length)
+        .iter()
+        .map(|&c| c as char)
+        .collect();
+
+    let &CGContextHostObject {
+        rgb_fill_color,
+        rgb_stroke_color,
+        font,
+        text_matrix,
+        text_drawing_mode,
+        ..
+    } = env.objc.borrow(context);
+    let Some((kind, size)) = font else {
+        log!(
+            "CGContextShowText() called with no font selected, ignoring: {:?}",
+            text
+        );
+        return;
+    };
+    // The clip modes draw like their non-clip equivalents, and then add the
+    // text to the clipping path.
+    let (mode, clip) = match text_drawing_mode {
+        kCGTextFillClip => (kCGTextFill, true),
+        kCGTextStrokeClip => (kCGTextStroke, true),
+        kCGTextFillStrokeClip => (kCGTextFillStroke, true),
+        kCGTextClip => (kCGTextInvisible, true),
+        _ => (text_drawing_mode, false),
+    };
+    if clip {
+        log!(
+            "TODO: clipping to text (drawing mode {}), ignoring",
+            text_drawing_mode
+        );
+    }
+    let color = match mode {
+        kCGTextFill | kCGTextFillStroke => Some(rgb_fill_color),
+        kCGTextStroke => {
+            log!("TODO: stroked text, filling with the stroke color instead");
+            Some(rgb_stroke_color)
+        }
+        kCGTextInvisible => None,
+        _ => {
+            log!(
+                "CGContextShowText() with unknown drawing mode {}, ignoring",
+                mode
+            );
+            return;
+        }
+    };
+
+    let font = get_font(&mut env.framework_state.core_graphics.cg_context, kind);
+    let advance = cg_bitmap_context::show_text(
+        &env.objc,
+        &mut env.mem,
+        context,
+        font,
+        size,
+        &text,
+        text_matrix,
+        color,
+    );
+
+    // The text position moves to the end of the text
+    let (x, y) = text_matrix.apply_to_point((advance, 0.0));
+    CGContextSetTextPosition(env, context, x, y);
+}
+
+fn CGContextShowTextAtPoint(
+    env: &mut Environment,
+    context: CGContextRef,
+    x: CGFloat,
+    y: CGFloat,
+    string: ConstPtr<u8>,
+    length: GuestUSize,
+) {
+    CGContextSetTextPosition(env, context, x, y);
+    CGContextShowText(env, context, string, length);
+}
+
+type CGPathDrawingMode = i32;
+const kCGPathFill: CGPathDrawingMode = 0;
+const kCGPathEOFill: CGPathDrawingMode = 1;
+const kCGPathStroke: CGPathDrawingMode = 2;
+const kCGPathFillStroke: CGPathDrawingMode = 3;
+const kCGPathEOFillStroke: CGPathDrawingMode = 4;
+
+fn CGContextDrawPath(env: &mut Environment, context: CGContextRef, mode: CGPathDrawingMode) {
+    let path = std::mem::take(path_mut(env, context));
+    match mode {
+        kCGPathFill => fill_path(env, context, &path, false),
+        kCGPathEOFill => fill_path(env, context, &path, true),
+        kCGPathStroke => stroke_path(env, context, &path),
+        kCGPathFillStroke | kCGPathEOFillStroke => {
+            fill_path(env, context, &path, mode == kCGPathEOFillStroke);
+            stroke_path(env, context, &path);
+        }
+        _ => log!("CGContextDrawPath() with unknown mode {}, ignoring", mode),
+    }
+}
+
 pub const FUNCTIONS: FunctionExports = &[
     export_c_func!(CGContextRetain(_)),
     export_c_func!(CGContextRelease(_)),
     export_c_func!(CGContextSetRGBFillColor(_, _, _, _, _)),
+    export_c_func!(CGContextSetRGBStrokeColor(_, _, _, _, _)),
+    export_c_func!(CGContextSetFillColorWithColor(_, _)),
+    export_c_func!(CGContextSetStrokeColorWithColor(_, _)),
+    export_c_func!(CGContextSetLineWidth(_, _)),
+    export_c_func!(CGContextSetBlendMode(_, _)),
+    export_c_func!(CGContextGetCTM(_)),
+    export_c_func!(CGContextConcatCTM(_, _)),
+    export_c_func!(CGContextTranslateCTM(_, _, _)),
+    export_c_func!(CGContextScaleCTM(_, _, _)),
+    export_c_func!(CGContextRotateCTM(_, _)),
     export_c_func!(CGContextFillRect(_, _)),
+    export_c_func!(CGContextStrokeRect(_, _)),
+    export_c_func!(CGContextBeginPath(_)),
+    export_c_func!(CGContextMoveToPoint(_, _, _)),
+    export_c_func!(CGContextAddLineToPoint(_, _, _)),
+    export_c_func!(CGContextAddQuadCurveToPoint(_, _, _, _, _)),
+    export_c_func!(CGContextAddCurveToPoint(_, _, _, _, _, _, _)),
+    export_c_func!(CGContextAddRect(_, _)),
+    export_c_func!(CGContextAddArc(_, _, _, _, _, _, _)),
+    export_c_func!(CGContextClosePath(_)),
+    export_c_func!(CGContextIsPathEmpty(_)),
+    export_c_func!(CGContextGetPathCurrentPoint(_)),
+    export_c_func!(CGContextFillPath(_)),
+    export_c_func!(CGContextEOFillPath(_)),
+    export_c_func!(CGContextStrokePath(_)),
+    export_c_func!(CGContextDrawPath(_, _)),
+    export_c_func!(CGContextDrawImage(_, _, _)),
+    export_c_func!(CGContextSelectFont(_, _, _, _)),
+    export_c_func!(CGContextSetTextMatrix(_, _)),
+    export_c_func!(CGContextGetTextMatrix(_)),
+    export_c_func!(CGContextSetTextPosition(_, _, _)),
+    export_c_func!(CGContextGetTextPosition(_)),
+    export_c_func!(CGContextSetTextDrawingMode(_, _)),
+    export_c_func!(CGContextShowText(_, _, _)),
+    export_c_func!(CGContextShowTextAtPoint(_, _, _, _, _)),
 ];
@@ -0,0 +1,249 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! `CGDataProvider.h`
+
+use crate::abi::{CallFromHost, GuestFunction};
+use crate::dyld::{export_c_func, FunctionExports};
+use crate::frameworks::core_foundation::cf_data::CFDataRef;
+use crate::frameworks::core_foundation::{CFRelease, CFRetain, CFTypeRef};
+use crate::frameworks::foundation::ns_data;
+use crate::mem::{ConstPtr, ConstVoidPtr, GuestUSize, MutVoidPtr, SafeRead};
+use crate::objc::{nil, objc_classes, release, retain, ClassExports, HostObject};
+use crate::Environment;
+
+pub const CLASSES: ClassExports = objc_classes! {
+
+(env, this, _cmd);
+
+// CGDataProvider seems to be a CFType-based type, but in our implementation
+// those are just Objective-C types, so we need a class for it, but its name is
+// not visible anywhere.
+@implementation _touchHLE_CGDataProvider: NSObject
+
+- (())dealloc {
+    let &CGDataProviderHostObject { info, source } = env.objc.borrow(this);
+    match source {
+        Source::Data {
+            data,
+            size,
+            release_data,
+        } => {
+            if release_data.addr_with_thumb_bit() != 0 {
+                let () = release_data.call_from_host(env, (info, data, size));
+            }
+        }
+        Source::CFData(data) => release(env, data),
+        Source::Sequential(CGDataProviderSequentialCallbacks { release_info, .. })
+        | Source::Direct {
+            callbacks: CGDataProviderDirectCallbacks { release_info, .. },
+            ..
+        } => {
+            if release_info.addr_with_thumb_bit() != 0 {
+                let () = release_info.call_from_host(env, (info,));
+            }
+        }
+    }
+    env.objc.dealloc_object(this, &mut env.mem)
+}
+
+@end
+
+};
+
+#[derive(Copy, Clone)]
+#[repr(C, packed)]
+struct CGDataProviderSequentialCallbacks {
+    version: u32,
+    get_bytes: GuestFunction,
+    skip_forward: GuestFunction,
+    rewind: GuestFunction,
+    release_info: GuestFunction,
+}
+unsafe impl SafeRead for CGDataProviderSequentialCallbacks {}
+
+#[derive(Copy, Clone)]
+#[repr(C, packed)]
+struct CGDataProviderDirectCallbacks {
+    version: u32,
+    get_byte_pointer: GuestFunction,
+    release_byte_pointer: GuestFunction,
+    get_bytes_at_position: GuestFunction,
+    release_info: GuestFunction,
+}
+unsafe impl SafeRead for CGDataProviderDirectCallbacks {}
+
+/// Where the provider gets its data from.
+#[derive(Copy, Clone)]
+enum Source {
+    /// `CGDataProviderCreateWithData`
+    Data {
+        data: ConstVoidPtr,
+        size: GuestUSize,
+        release_data: GuestFunction,
+    },
+    /// `CGDataProviderCreateWithCFData`, which retains the data.
+    CFData(CFDataRef),
+    /// `CGDataProviderCreateSequential`
+    Sequential(CGDataProviderSequentialCallbacks),
+    /// `CGDataProviderCreateDirect`
+    Direct {
+        size: GuestUSize,
+        callbacks: CGDataProviderDirectCallbacks,
+    },
+}
+
+struct CGDataProviderHostObject {
+    /// Passed to the guest callbacks.
+    info: MutVoidPtr,
+    source: Source,
+}
+impl HostObject for CGDataProviderHostObject {}
+
+pub type CGDataProviderRef = CFTypeRef;
+
+fn create(env: &mut Environment, info: MutVoidPtr, source: Source) -> CGDataProviderRef {
+    let isa = env
+        .objc
+        .get_known_class("_touchHLE_CGDataProvider", &mut env.mem);
+    env.objc.alloc_object(
+        isa,
+        Box::new(CGDataProviderHostObject { info, source }),
+        &mut env.mem,
+    )
+}
+
+fn CGDataProviderCreateWithData(
+    env: &mut Environment,
+    info: MutVoidPtr,
+    data: ConstVoidPtr,
+    size: GuestUSize,
+    release_data: GuestFunction,
+) -> CGDataProviderRef {
+    let source = Source::Data {
+        data,
+        size,
+        release_data,
+    };
+    create(env, info, source)
+}
+
+fn CGDataProviderCreateWithCFData(env: &mut Environment, data: CFDataRef) -> CGDataProviderRef {
+    if data.is_null() {
+        return nil;
+    }
+    let data = retain(env, data);
+    create(env, MutVoidPtr::null(), Source::CFData(data))
+}
+
+fn CGDataProviderCreateSequential(
+    env: &mut Environment,
+    info: MutVoidPtr,
+    callbacks: ConstPtr<CGDataProviderSequentialCallbacks>,
+) -> CGDataProviderRef {
+    let callbacks = env.mem.read(callbacks);
+    assert!(callbacks.version == 0);
+    create(env, info, Source::Sequential(callbacks))
+}
+
+fn CGDataProviderCreateDirect(
+    env: &mut Environment,
+    info: MutVoidPtr,
+    size: i64, // off_t
+    callbacks: ConstPtr<CGDataProviderDirectCallbacks>,
+) -> CGDataProviderRef {
+    let callbacks = env.mem.read(callbacks);
+    assert!(callbacks.version == 0);
+    let size = size.try_into().unwrap();
+    create(env, info, Source::Direct { size, callbacks })
+}
+
+pub fn CGDataProviderRelease(env: &mut Environment, provider: CGDataProviderRef) {
+    if !provider.is_null() {
+        CFRelease(env, provider);
+    }
+}
+pub fn CGDataProviderRetain(
+    env: &mut Environment,
+    provider: CGDataProviderRef,
+) -> CGDataProviderRef {
+    if !provider.is_null() {
+        CFRetain(env, provider)
+    } else {
+        provider
+    }
+}
+
+fn CGDataProviderCopyData(env: &mut Environment, provider: CGDataProviderRef) -> CFDataRef {
+    let bytes = get_bytes(env, provider);
+    ns_data::from_vec(env, bytes)
+}
+
+/// Get a copy of all the data supplied by the provider. This may call into
+/// guest code.
+pub(super) fn get_bytes(env: &mut Environment, provider: CGDataProviderRef) -> Vec<u8> {
+    let &CGDataProviderHostObject { info, source } = env.objc.borrow(provider);
+    match source {
+        Source::Data { data, size, .. } => env.mem.bytes_at(data.cast(), size).to_vec(),
+        Source::CFData(data) => ns_data::to_rust_slice(env, data).to_vec(),
+        Source::Sequential(CGDataProviderSequentialCallbacks {
+            get_bytes, rewind, ..
+        }) => {
+            if rewind.addr_with_thumb_bit() != 0 {
+                let () = rewind.call_from_host(env, (info,));
+            }
+            const BUFFER_SIZE: GuestUSize = 4096;
+            let buffer = env.mem.alloc(BUFFER_SIZE);
+            let mut bytes = Vec::new();
+            loop {
+                let count: GuestUSize = get_bytes.call_from_host(env, (info, buffer, BUFFER_SIZE));
+                if count == 0 {
+                    break;
+                }
+                assert!(count <= BUFFER_SIZE);
+                bytes.extend_from_slice(env.mem.bytes_at(buffer.cast(), count));
+            }
+            env.mem.free(buffer);
+            bytes
+        }
+        Source::Direct {
+            size,
+            callbacks:
+                CGDataProviderDirectCallbacks {
+                    get_byte_pointer,
+                    release_byte_pointer,
+                    get_bytes_at_position,
+                    ..
+                },
+        } => {
+            if get_byte_pointer.addr_with_thumb_bit() != 0 {
+                let pointer: ConstVoidPtr = get_byte_pointer.call_from_host(env, (info,));
+                let bytes = env.mem.bytes_at(pointer.cast(), size).to_vec();
+                if release_byte_pointer.addr_with_thumb_bit() != 0 {
+                    let () = release_byte_pointer.call_from_host(env, (info, pointer));
+                }
+                bytes
+            } else {
+                let buffer = env.mem.alloc(size);
+                let count: GuestUSize =
+                    get_bytes_at_position.call_from_host(env, (info, buffer, 0i64, size));
+                assert!(count <= size);
+                let bytes = env.mem.bytes_at(buffer.cast(), count).to_vec();
+                env.mem.free(buffer);
+                bytes
+            }
+        }
+    }
+}
+
+pub const FUNCTIONS: FunctionExports = &[
+    export_c_func!(CGDataProviderCreateWithData(_, _, _, _)),
+    export_c_func!(CGDataProviderCreateWithCFData(_)),
+    export_c_func!(CGDataProviderCreateSequential(_, _)),
+    export_c_func!(CGDataProviderCreateDirect(_, _, _)),
+    export_c_func!(CGDataProviderRetain(_)),
+    export_c_func!(CGDataProviderRelease(_)),
+    export_c_func!(CGDataProviderCopyData(_)),
+];
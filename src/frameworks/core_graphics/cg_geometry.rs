@@ -7,9 +7,11 @@
 
 use super::CGFloat;
 use crate::abi::{impl_GuestRet_for_large_struct, GuestArg};
-use crate::mem::SafeRead;
+use crate::dyld::{export_c_func, ConstantExports, FunctionExports, HostConstant};
+use crate::mem::{ConstVoidPtr, Mem, SafeRead};
+use crate::Environment;
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 #[repr(C, packed)]
 pub struct CGPoint {
     pub x: CGFloat,
@@ -32,7 +34,7 @@ impl GuestArg for CGPoint {
     }
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 #[repr(C, packed)]
 pub struct CGSize {
     pub width: CGFloat,
@@ -55,7 +57,7 @@ impl GuestArg for CGSize {
     }
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 #[repr(C, packed)]
 pub struct CGRect {
     pub origin: CGPoint,
@@ -77,3 +79,334 @@ impl GuestArg for CGRect {
         self.size.to_regs(&mut regs[2..4]);
     }
 }
+
+impl CGRect {
+    pub const ZERO: Self = CGRect {
+        origin: CGPoint { x: 0.0, y: 0.0 },
+        size: CGSize {
+            width: 0.0,
+            height: 0.0,
+        },
+    };
+
+    /// The "null" rect, which is what e.g. the intersection of two disjoint
+    /// rects is. It is distinct from an empty rect with a position.
+    pub const NULL: Self = CGRect {
+        origin: CGPoint {
+            x: CGFloat::INFINITY,
+            y: CGFloat::INFINITY,
+        },
+        size: CGSize {
+            width: 0.0,
+            height: 0.0,
+        },
+    };
+
+    pub fn is_null(self) -> bool {
+        self.origin.x == CGFloat::INFINITY || self.origin.y == CGFloat::INFINITY
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.is_null() || self.size.width == 0.0 || self.size.height == 0.0
+    }
+
+    /// Returns an equivalent rect with a non-negative width and height.
+    pub fn standardize(self) -> Self {
+        if self.is_null() {
+            return Self::NULL;
+        }
+        let CGRect {
+            origin: CGPoint { mut x, mut y },
+            size: CGSize {
+                mut width,
+                mut height,
+            },
+        } = self;
+        if width < 0.0 {
+            x += width;
+            width = -width;
+        }
+        if height < 0.0 {
+            y += height;
+            height = -height;
+        }
+        CGRect {
+            origin: CGPoint { x, y },
+            size: CGSize { width, height },
+        }
+    }
+
+    pub fn min_x(self) -> CGFloat {
+        self.standardize().origin.x
+    }
+    pub fn min_y(self) -> CGFloat {
+        self.standardize().origin.y
+    }
+    pub fn max_x(self) -> CGFloat {
+        let rect = self.standardize();
+        rect.origin.x + rect.size.width
+    }
+    pub fn max_y(self) -> CGFloat {
+        let rect = self.standardize();
+        rect.origin.y + rect.size.height
+    }
+    pub fn mid_x(self) -> CGFloat {
+        self.origin.x + self.size.width / 2.0
+    }
+    pub fn mid_y(self) -> CGFloat {
+        self.origin.y + self.size.height / 2.0
+    }
+
+    fn from_edges(min_x: CGFloat, min_y: CGFloat, max_x: CGFloat, max_y: CGFloat) -> Self {
+        CGRect {
+            origin: CGPoint { x: min_x, y: min_y },
+            size: CGSize {
+                width: max_x - min_x,
+                height: max_y - min_y,
+            },
+        }
+    }
+
+    /// Returns the overlapping part of two rects, or [Self::NULL] if they
+    /// don't overlap. Rects that only share an edge have an empty
+    /// intersection.
+    pub fn intersection(self, other: Self) -> Self {
+        if self.is_null() || other.is_null() {
+            return Self::NULL;
+        }
+        let min_x = self.min_x().max(other.min_x());
+        let min_y = self.min_y().max(other.min_y());
+        let max_x = self.max_x().min(other.max_x());
+        let max_y = self.max_y().min(other.max_y());
+        if max_x < min_x || max_y < min_y {
+            return Self::NULL;
+        }
+        Self::from_edges(min_x, min_y, max_x, max_y)
+    }
+
+    /// Returns the smallest rect containing both rects. The null rect is
+    /// ignored, but empty rects are not.
+    pub fn union(self, other: Self) -> Self {
+        if self.is_null() {
+            return other.standardize();
+        }
+        if other.is_null() {
+            return self.standardize();
+        }
+        Self::from_edges(
+            self.min_x().min(other.min_x()),
+            self.min_y().min(other.min_y()),
+            self.max_x().max(other.max_x()),
+            self.max_y().max(other.max_y()),
+        )
+    }
+
+    /// Whether the point is inside the rect. The minimum edges are inclusive
+    /// and the maximum edges are exclusive.
+    pub fn contains_point(self, point: CGPoint) -> bool {
+        !self.is_null()
+            && (self.min_x()..self.max_x()).contains(&point.x)
+            && (self.min_y()..self.max_y()).contains(&point.y)
+    }
+
+    pub fn contains_rect(self, other: Self) -> bool {
+        self.union(other) == self.standardize()
+    }
+
+    /// Returns the rect shrunk by `dx` on the left and right and `dy` on the
+    /// top and bottom (or grown, if they are negative), or [Self::NULL] if
+    /// nothing is left.
+    pub fn inset(self, dx: CGFloat, dy: CGFloat) -> Self {
+        if self.is_null() {
+            return Self::NULL;
+        }
+        let rect = self.standardize();
+        let width = rect.size.width - dx * 2.0;
+        let height = rect.size.height - dy * 2.0;
+        if width < 0.0 || height < 0.0 {
+            return Self::NULL;
+        }
+        CGRect {
+            origin: CGPoint {
+                x: rect.origin.x + dx,
+                y: rect.origin.y + dy,
+            },
+            size: CGSize { width, height },
+        }
+    }
+
+    /// Returns the smallest rect with integer edges that contains this rect.
+    pub fn integral(self) -> Self {
+        if self.is_null() {
+            return Self::NULL;
+        }
+        Self::from_edges(
+            self.min_x().floor(),
+            self.min_y().floor(),
+            self.max_x().ceil(),
+            self.max_y().ceil(),
+        )
+    }
+}
+
+fn CGRectStandardize(_env: &mut Environment, rect: CGRect) -> CGRect {
+    rect.standardize()
+}
+fn CGRectIsNull(_env: &mut Environment, rect: CGRect) -> bool {
+    rect.is_null()
+}
+fn CGRectIsEmpty(_env: &mut Environment, rect: CGRect) -> bool {
+    rect.is_empty()
+}
+fn CGRectGetMinX(_env: &mut Environment, rect: CGRect) -> CGFloat {
+    rect.min_x()
+}
+fn CGRectGetMinY(_env: &mut Environment, rect: CGRect) -> CGFloat {
+    rect.min_y()
+}
+fn CGRectGetMidX(_env: &mut Environment, rect: CGRect) -> CGFloat {
+    rect.mid_x()
+}
+fn CGRectGetMidY(_env: &mut Environment, rect: CGRect) -> CGFloat {
+    rect.mid_y()
+}
+fn CGRectGetMaxX(_env: &mut Environment, rect: CGRect) -> CGFloat {
+    rect.max_x()
+}
+fn CGRectGetMaxY(_env: &mut Environment, rect: CGRect) -> CGFloat {
+    rect.max_y()
+}
+fn CGRectGetWidth(_env: &mut Environment, rect: CGRect) -> CGFloat {
+    rect.standardize().size.width
+}
+fn CGRectGetHeight(_env: &mut Environment, rect: CGRect) -> CGFloat {
+    rect.standardize().size.height
+}
+fn CGRectIntersection(_env: &mut Environment, a: CGRect, b: CGRect) -> CGRect {
+    a.intersection(b)
+}
+fn CGRectIntersectsRect(_env: &mut Environment, a: CGRect, b: CGRect) -> bool {
+    !a.intersection(b).is_null()
+}
+fn CGRectUnion(_env: &mut Environment, a: CGRect, b: CGRect) -> CGRect {
+    a.union(b)
+}
+fn CGRectContainsPoint(_env: &mut Environment, rect: CGRect, point: CGPoint) -> bool {
+    rect.contains_point(point)
+}
+fn CGRectContainsRect(_env: &mut Environment, a: CGRect, b: CGRect) -> bool {
+    a.contains_rect(b)
+}
+fn CGRectInset(_env: &mut Environment, rect: CGRect, dx: CGFloat, dy: CGFloat) -> CGRect {
+    rect.inset(dx, dy)
+}
+fn CGRectIntegral(_env: &mut Environment, rect: CGRect) -> CGRect {
+    rect.integral()
+}
+
+pub const CONSTANTS: ConstantExports = &[
+    (
+        "_CGRectZero",
+        HostConstant::Custom(|mem: &mut Mem| -> ConstVoidPtr {
+            mem.alloc_and_write(CGRect::ZERO).cast().cast_const()
+        }),
+    ),
+    (
+        "_CGRectNull",
+        HostConstant::Custom(|mem: &mut Mem| -> ConstVoidPtr {
+            mem.alloc_and_write(CGRect::NULL).cast().cast_const()
+        }),
+    ),
+];
+
+pub const FUNCTIONS: FunctionExports = &[
+    export_c_func!(CGRectStandardize(_)),
+    export_c_func!(CGRectIsNull(_)),
+    export_c_func!(CGRectIsEmpty(_)),
+    export_c_func!(CGRectGetMinX(_)),
+    export_c_func!(CGRectGetMinY(_)),
+    export_c_func!(CGRectGetMidX(_)),
+    export_c_func!(CGRectGetMidY(_)),
+    export_c_func!(CGRectGetMaxX(_)),
+    export_c_func!(CGRectGetMaxY(_)),
+    export_c_func!(CGRectGetWidth(_)),
+    export_c_func!(CGRectGetHeight(_)),
+    export_c_func!(CGRectIntersection(_, _)),
+    export_c_func!(CGRectIntersectsRect(_, _)),
+    export_c_func!(CGRectUnion(_, _)),
+    export_c_func!(CGRectContainsPoint(_, _)),
+    export_c_func!(CGRectContainsRect(_, _)),
+    export_c_func!(CGRectInset(_, _, _)),
+    export_c_func!(CGRectIntegral(_)),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rect(x: CGFloat, y: CGFloat, width: CGFloat, height: CGFloat) -> CGRect {
+        CGRect {
+            origin: CGPoint { x, y },
+            size: CGSize { width, height },
+        }
+    }
+
+    #[test]
+    fn intersection_and_union() {
+        let a = rect(0.0, 0.0, 10.0, 10.0);
+        let b = rect(5.0, 5.0, 10.0, 10.0);
+        assert_eq!(a.intersection(b), rect(5.0, 5.0, 5.0, 5.0));
+        assert_eq!(a.union(b), rect(0.0, 0.0, 15.0, 15.0));
+
+        // Negative sizes are normalized.
+        let flipped = rect(15.0, 15.0, -10.0, -10.0);
+        assert_eq!(a.intersection(flipped), rect(5.0, 5.0, 5.0, 5.0));
+
+        // Disjoint rects have a null intersection, but touching ones don't.
+        let far = rect(20.0, 0.0, 1.0, 1.0);
+        assert!(a.intersection(far).is_null());
+        let touching = a.intersection(rect(10.0, 0.0, 5.0, 5.0));
+        assert_eq!(touching, rect(10.0, 0.0, 0.0, 5.0));
+        assert!(touching.is_empty() && !touching.is_null());
+
+        // The null rect is ignored by union, but empty rects are not.
+        assert_eq!(a.union(CGRect::NULL), a);
+        assert_eq!(CGRect::NULL.union(flipped), b);
+        assert_eq!(
+            a.union(rect(20.0, 20.0, 0.0, 0.0)),
+            rect(0.0, 0.0, 20.0, 20.0)
+        );
+        assert!(CGRect::NULL.intersection(a).is_null());
+    }
+
+    #[test]
+    fn containment() {
+        let a = rect(10.0, 20.0, 30.0, 40.0);
+        assert!(a.contains_point(CGPoint { x: 10.0, y: 20.0 }));
+        assert!(!a.contains_point(CGPoint { x: 40.0, y: 30.0 }));
+        assert!(!CGRect::NULL.contains_point(CGPoint { x: 0.0, y: 0.0 }));
+
+        assert!(a.contains_rect(rect(15.0, 25.0, 5.0, 5.0)));
+        assert!(a.contains_rect(a));
+        assert!(!a.contains_rect(rect(35.0, 25.0, 10.0, 5.0)));
+        assert!(a.contains_rect(CGRect::NULL));
+        assert!(!CGRect::NULL.contains_rect(a));
+    }
+
+    #[test]
+    fn inset_and_integral() {
+        let a = rect(10.0, 20.0, 30.0, 40.0);
+        assert_eq!(a.inset(5.0, -5.0), rect(15.0, 15.0, 20.0, 50.0));
+        assert!(a.inset(16.0, 0.0).is_null());
+
+        assert_eq!(
+            rect(0.5, -1.5, 2.0, 1.2).integral(),
+            rect(0.0, -2.0, 3.0, 2.0)
+        );
+        assert!(CGRect::NULL.integral().is_null());
+
+        assert_eq!((a.mid_x(), a.mid_y()), (25.0, 40.0));
+        assert_eq!((a.max_x(), a.max_y()), (40.0, 60.0));
+        assert!(CGRect::ZERO.is_empty() && !CGRect::ZERO.is_null());
+    }
+}
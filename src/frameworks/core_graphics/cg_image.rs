@@ -5,6 +5,60 @@
  */
 //! `CGImage.h`
 
+use super::cg_color_space::{
+    create_with_name, kCGColorSpaceGenericRGB, CGColorSpaceRef, CGColorSpaceRelease,
+    CGColorSpaceRetain,
+};
+use super::cg_data_provider::{
+    self, CGDataProviderRef, CGDataProviderRelease, CGDataProviderRetain,
+};
+use super::CGFloat;
+use crate::dyld::{export_c_func, FunctionExports};
+use crate::frameworks::core_foundation::{CFRelease, CFRetain, CFTypeRef};
+use crate::image::Image;
+use crate::mem::{ConstPtr, GuestUSize};
+use crate::objc::{nil, objc_classes, ClassExports, HostObject, ObjC};
+use crate::Environment;
+
+pub const CLASSES: ClassExports = objc_classes! {
+
+(env, this, _cmd);
+
+// CGImage seems to be a CFType-based type, but in our implementation those
+// are just Objective-C types, so we need a class for it, but its name is not
+// visible anywhere.
+@implementation _touchHLE_CGImage: NSObject
+
+- (())dealloc {
+    let &CGImageHostObject {
+        color_space,
+        data_provider,
+        ..
+    } = env.objc.borrow(this);
+    CGColorSpaceRelease(env, color_space);
+    CGDataProviderRelease(env, data_provider);
+    env.objc.dealloc_object(this, &mut env.mem)
+}
+
+@end
+
+};
+
+pub(super) struct CGImageHostObject {
+    /// The pixels converted to the format used by [Image], for drawing.
+    pub(super) image: Image,
+    bits_per_component: GuestUSize,
+    bits_per_pixel: GuestUSize,
+    bytes_per_row: GuestUSize,
+    bitmap_info: CGBitmapInfo,
+    color_space: CGColorSpaceRef,
+    /// `nil` for images that weren't created with `CGImageCreate`.
+    data_provider: CGDataProviderRef,
+}
+impl HostObject for CGImageHostObject {}
+
+pub type CGImageRef = CFTypeRef;
+
 pub type CGImageAlphaInfo = u32;
 pub const kCGImageAlphaNone: CGImageAlphaInfo = 0;
 pub const kCGImageAlphaPremultipliedLast: CGImageAlphaInfo = 1;
@@ -14,3 +68,272 @@ pub const kCGImageAlphaFirst: CGImageAlphaInfo = 4;
 pub const kCGImageAlphaNoneSkipLast: CGImageAlphaInfo = 5;
 pub const kCGImageAlphaNoneSkipFirst: CGImageAlphaInfo = 6;
 pub const kCGImageAlphaOnly: CGImageAlphaInfo = 7;
+
+pub type CGBitmapInfo = u32;
+pub const kCGBitmapAlphaInfoMask: CGBitmapInfo = 0x1F;
+pub const kCGBitmapFloatComponents: CGBitmapInfo = 1 << 8;
+pub const kCGBitmapByteOrderMask: CGBitmapInfo = 0x7000;
+pub const kCGBitmapByteOrderDefault: CGBitmapInfo = 0 << 12;
+pub const kCGBitmapByteOrder32Little: CGBitmapInfo = 2 << 12;
+pub const kCGBitmapByteOrder32Big: CGBitmapInfo = 4 << 12;
+
+pub type CGColorRenderingIntent = i32;
+
+/// Create a `CGImage` from a decoded image. This is a touchHLE-specific helper
+/// for use by other frameworks.
+pub fn from_image(env: &mut Environment, image: Image) -> CGImageRef {
+    let (width, _height) = image.dimensions();
+    let color_space = create_with_name(&mut env.objc, &mut env.mem, kCGColorSpaceGenericRGB);
+    let host_object = CGImageHostObject {
+        image,
+        bits_per_component: 8,
+        bits_per_pixel: 32,
+        bytes_per_row: width * 4,
+        bitmap_info: kCGImageAlphaLast,
+        color_space,
+        data_provider: nil,
+    };
+    let isa = env.objc.get_known_class("_touchHLE_CGImage", &mut env.mem);
+    env.objc
+        .alloc_object(isa, Box::new(host_object), &mut env.mem)
+}
+
+/// Shortcut for use by other frameworks: get the decoded pixels of a `CGImage`.
+pub fn borrow_image(objc: &ObjC, image: CGImageRef) -> &Image {
+    &objc.borrow::<CGImageHostObject>(image).image
+}
+
+fn CGImageCreate(
+    env: &mut Environment,
+    width: GuestUSize,
+    height: GuestUSize,
+    bits_per_component: GuestUSize,
+    bits_per_pixel: GuestUSize,
+    bytes_per_row: GuestUSize,
+    color_space: CGColorSpaceRef,
+    bitmap_info: CGBitmapInfo,
+    data_provider: CGDataProviderRef,
+    decode: ConstPtr<CGFloat>,
+    _should_interpolate: bool,
+    _intent: CGColorRenderingIntent,
+) -> CGImageRef {
+    if color_space.is_null() || data_provider.is_null() {
+        return nil;
+    }
+    if !decode.is_null() {
+        log!("TODO: CGImageCreate() decode array {:?}, ignoring", decode);
+    }
+
+    let data = cg_data_provider::get_bytes(env, data_provider);
+    let Ok(pixels) = convert_to_rgba8(
+        &data,
+        (width, height),
+        bits_per_component,
+        bits_per_pixel,
+        bytes_per_row,
+        bitmap_info,
+    ) else {
+        log!(
+            "Warning: CGImageCreate() failed for unsupported format (bpc {}, bpp {}, bitmap info {:#x}) or insufficient data",
+            bits_per_component,
+            bits_per_pixel,
+            bitmap_info
+        );
+        return nil;
+    };
+
+    let host_object = CGImageHostObject {
+        image: Image::from_pixels(pixels, (width, height)),
+        bits_per_component,
+        bits_per_pixel,
+        bytes_per_row,
+        bitmap_info,
+        color_space: CGColorSpaceRetain(env, color_space),
+        data_provider: CGDataProviderRetain(env, data_provider),
+    };
+    let isa = env.objc.get_known_class("_touchHLE_CGImage", &mut env.mem);
+    env.objc
+        .alloc_object(isa, Box::new(host_object), &mut env.mem)
+}
+
+/// Convert pixel data in a `CGImageCreate`-compatible format to 8 bits per
+/// channel non-premultiplied RGBA, the format used by [Image].
+fn convert_to_rgba8(
+    data: &[u8],
+    dimensions: (GuestUSize, GuestUSize),
+    bits_per_component: GuestUSize,
+    bits_per_pixel: GuestUSize,
+    bytes_per_row: GuestUSize,
+    bitmap_info: CGBitmapInfo,
+) -> Result<Vec<u8>, ()> {
+    let (width, height) = dimensions;
+    if bits_per_component != 8 || (bitmap_info & kCGBitmapFloatComponents) != 0 {
+        return Err(()); // TODO: other bit depths
+    }
+    let alpha_info = bitmap_info & kCGBitmapAlphaInfoMask;
+    let little_endian = match bitmap_info & kCGBitmapByteOrderMask {
+        kCGBitmapByteOrderDefault | kCGBitmapByteOrder32Big => false,
+        kCGBitmapByteOrder32Little => true,
+        _ => return Err(()), // TODO: 16-bit byte orders
+    };
+    let bytes_per_pixel = match (alpha_info, bits_per_pixel) {
+        (kCGImageAlphaNone, 24) => 3,
+        (kCGImageAlphaOnly, 8) => 1,
+        (
+            kCGImageAlphaPremultipliedLast
+            | kCGImageAlphaPremultipliedFirst
+            | kCGImageAlphaLast
+            | kCGImageAlphaFirst
+            | kCGImageAlphaNoneSkipLast
+            | kCGImageAlphaNoneSkipFirst,
+            32,
+        ) => 4,
+        _ => return Err(()),
+    };
+    if width.checked_mul(bytes_per_pixel).ok_or(())? > bytes_per_row {
+        return Err(());
+    }
+    if height > 0 {
+        let size = (height - 1) * bytes_per_row + width * bytes_per_pixel;
+        if data.len() < size as usize {
+            return Err(());
+        }
+    }
+
+    let mut pixels = Vec::with_capacity(width as usize * height as usize * 4);
+    for y in 0..height {
+        for x in 0..width {
+            let idx = (y * bytes_per_row + x * bytes_per_pixel) as usize;
+            let mut p = [0u8; 4];
+            p[..bytes_per_pixel as usize]
+                .copy_from_slice(&data[idx..idx + bytes_per_pixel as usize]);
+            if little_endian {
+                p[..bytes_per_pixel as usize].reverse();
+            }
+            let [r, g, b, a] = match alpha_info {
+                kCGImageAlphaNone => [p[0], p[1], p[2], 255],
+                kCGImageAlphaOnly => [0, 0, 0, p[0]],
+                kCGImageAlphaPremultipliedLast | kCGImageAlphaLast => p,
+                kCGImageAlphaPremultipliedFirst | kCGImageAlphaFirst => [p[1], p[2], p[3], p[0]],
+                kCGImageAlphaNoneSkipLast => [p[0], p[1], p[2], 255],
+                kCGImageAlphaNoneSkipFirst => [p[1], p[2], p[3], 255],
+                _ => unreachable!(),
+            };
+            let premultiplied = matches!(
+                alpha_info,
+                kCGImageAlphaPremultipliedLast | kCGImageAlphaPremultipliedFirst
+            );
+            let unpremultiply = |c: u8| {
+                if premultiplied && a != 0 {
+                    ((c as u32 * 255 + a as u32 / 2) / a as u32).min(255) as u8
+                } else {
+                    c
+                }
+            };
+            pixels.extend_from_slice(&[unpremultiply(r), unpremultiply(g), unpremultiply(b), a]);
+        }
+    }
+    Ok(pixels)
+}
+
+pub fn CGImageRelease(env: &mut Environment, image: CGImageRef) {
+    if !image.is_null() {
+        CFRelease(env, image);
+    }
+}
+pub fn CGImageRetain(env: &mut Environment, image: CGImageRef) -> CGImageRef {
+    if !image.is_null() {
+        CFRetain(env, image)
+    } else {
+        image
+    }
+}
+
+pub fn CGImageGetWidth(env: &mut Environment, image: CGImageRef) -> GuestUSize {
+    let (width, _height) = env
+        .objc
+        .borrow::<CGImageHostObject>(image)
+        .image
+        .dimensions();
+    width
+}
+pub fn CGImageGetHeight(env: &mut Environment, image: CGImageRef) -> GuestUSize {
+    let (_width, height) = env
+        .objc
+        .borrow::<CGImageHostObject>(image)
+        .image
+        .dimensions();
+    height
+}
+fn CGImageGetBitsPerComponent(env: &mut Environment, image: CGImageRef) -> GuestUSize {
+    env.objc
+        .borrow::<CGImageHostObject>(image)
+        .bits_per_component
+}
+fn CGImageGetBitsPerPixel(env: &mut Environment, image: CGImageRef) -> GuestUSize {
+    env.objc.borrow::<CGImageHostObject>(image).bits_per_pixel
+}
+fn CGImageGetBytesPerRow(env: &mut Environment, image: CGImageRef) -> GuestUSize {
+    env.objc.borrow::<CGImageHostObject>(image).bytes_per_row
+}
+fn CGImageGetBitmapInfo(env: &mut Environment, image: CGImageRef) -> CGBitmapInfo {
+    env.objc.borrow::<CGImageHostObject>(image).bitmap_info
+}
+fn CGImageGetAlphaInfo(env: &mut Environment, image: CGImageRef) -> CGImageAlphaInfo {
+    CGImageGetBitmapInfo(env, image) & kCGBitmapAlphaInfoMask
+}
+fn CGImageGetColorSpace(env: &mut Environment, image: CGImageRef) -> CGColorSpaceRef {
+    env.objc.borrow::<CGImageHostObject>(image).color_space
+}
+fn CGImageGetDataProvider(env: &mut Environment, image: CGImageRef) -> CGDataProviderRef {
+    env.objc.borrow::<CGImageHostObject>(image).data_provider
+}
+
+pub const FUNCTIONS: FunctionExports = &[
+    export_c_func!(CGImageCreate(_, _, _, _, _, _, _, _, _, _, _)),
+    export_c_func!(CGImageRetain(_)),
+    export_c_func!(CGImageRelease(_)),
+    export_c_func!(CGImageGetWidth(_)),
+    export_c_func!(CGImageGetHeight(_)),
+    export_c_func!(CGImageGetBitsPerComponent(_)),
+    export_c_func!(CGImageGetBitsPerPixel(_)),
+    export_c_func!(CGImageGetBytesPerRow(_)),
+    export_c_func!(CGImageGetBitmapInfo(_)),
+    export_c_func!(CGImageGetAlphaInfo(_)),
+    export_c_func!(CGImageGetColorSpace(_)),
+    export_c_func!(CGImageGetDataProvider(_)),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn convert_formats() {
+        // RGBX with padding at the end of each row
+        let data = [1, 2, 3, 0, 4, 5, 6, 0, 99, 99, 7, 8, 9, 0, 10, 11, 12, 0];
+        assert_eq!(
+            convert_to_rgba8(&data, (2, 2), 8, 32, 10, kCGImageAlphaNoneSkipLast),
+            Ok(vec![
+                1, 2, 3, 255, 4, 5, 6, 255, 7, 8, 9, 255, 10, 11, 12, 255
+            ])
+        );
+        // Premultiplied BGRA, as commonly used on iPhone OS
+        assert_eq!(
+            convert_to_rgba8(
+                &[50, 0, 100, 128, 0, 0, 0, 0],
+                (2, 1),
+                8,
+                32,
+                8,
+                kCGImageAlphaPremultipliedFirst | kCGBitmapByteOrder32Little
+            ),
+            Ok(vec![199, 0, 100, 128, 0, 0, 0, 0])
+        );
+        // Not enough data
+        assert_eq!(
+            convert_to_rgba8(&data[..17], (2, 2), 8, 32, 10, kCGImageAlphaLast),
+            Err(())
+        );
+    }
+}
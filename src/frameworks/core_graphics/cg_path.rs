@@ -0,0 +1,452 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! Paths and their rasterization.
+//!
+//! This is the internal representation of the paths built by `CGContext`'s
+//! path functions (`CGContextMoveToPoint` etc). Curves and arcs are flattened
+//! into line segments as they are added, so a path is just a list of polygons.
+//!
+//! Rasterization is simple scanline polygon filling, sampling at pixel
+//! centers, without anti-aliasing.
+
+use super::CGFloat;
+
+pub(super) type Point = (CGFloat, CGFloat);
+
+#[derive(Clone, Debug, Default)]
+struct Subpath {
+    points: Vec<Point>,
+    closed: bool,
+}
+
+#[derive(Clone, Debug, Default)]
+pub(super) struct Path {
+    subpaths: Vec<Subpath>,
+}
+
+impl Path {
+    pub(super) fn is_empty(&self) -> bool {
+        self.subpaths.is_empty()
+    }
+
+    pub(super) fn current_point(&self) -> Option<Point> {
+        let subpath = self.subpaths.last()?;
+        if subpath.closed {
+            subpath.points.first().copied()
+        } else {
+            subpath.points.last().copied()
+        }
+    }
+
+    pub(super) fn move_to(&mut self, point: Point) {
+        // A move immediately after another move replaces it.
+        if let Some(subpath) = self.subpaths.last_mut() {
+            if subpath.points.len() == 1 && !subpath.closed {
+                subpath.points[0] = point;
+                return;
+            }
+        }
+        self.subpaths.push(Subpath {
+            points: vec![point],
+            closed: false,
+        });
+    }
+
+    pub(super) fn line_to(&mut self, point: Point) {
+        let Some(current) = self.current_point() else {
+            log!("Warning: adding a line to a path with no current point");
+            return;
+        };
+        if self.subpaths.last().unwrap().closed {
+            // The next subpath starts where the closed one did.
+            self.move_to(current);
+        }
+        self.subpaths.last_mut().unwrap().points.push(point);
+    }
+
+    pub(super) fn quad_curve_to(&mut self, control: Point, end: Point) {
+        let Some(start) = self.current_point() else {
+            log!("Warning: adding a curve to a path with no current point");
+            return;
+        };
+        let segments = segment_count(&[start, control, end]);
+        for i in 1..=segments {
+            let t = i as CGFloat / segments as CGFloat;
+            let u = 1.0 - t;
+            let (a, b, c) = (u * u, 2.0 * u * t, t * t);
+            self.line_to((
+                a * start.0 + b * control.0 + c * end.0,
+                a * start.1 + b * control.1 + c * end.1,
+            ));
+        }
+    }
+
+    pub(super) fn curve_to(&mut self, control1: Point, control2: Point, end: Point) {
+        let Some(start) = self.current_point() else {
+            log!("Warning: adding a curve to a path with no current point");
+            return;
+        };
+        let segments = segment_count(&[start, control1, control2, end]);
+        for i in 1..=segments {
+            let t = i as CGFloat / segments as CGFloat;
+            let u = 1.0 - t;
+            let (a, b, c, d) = (u * u * u, 3.0 * u * u * t, 3.0 * u * t * t, t * t * t);
+            self.line_to((
+                a * start.0 + b * control1.0 + c * control2.0 + d * end.0,
+                a * start.1 + b * control1.1 + c * control2.1 + d * end.1,
+            ));
+        }
+    }
+
+    /// Add an arc of a circle. Angles are in radians, measured from the
+    /// positive x-axis towards the positive y-axis, and `clockwise` means
+    /// decreasing angle. If the path has a current point, a line is added
+    /// from it to the start of the arc.
+    pub(super) fn arc(
+        &mut self,
+        center: Point,
+        radius: CGFloat,
+        start_angle: CGFloat,
+        end_angle: CGFloat,
+        clockwise: bool,
+    ) {
+        use std::f32::consts::TAU;
+
+        let mut sweep = end_angle - start_angle;
+        if clockwise {
+            while sweep > 0.0 {
+                sweep -= TAU;
+            }
+        } else {
+            while sweep < 0.0 {
+                sweep += TAU;
+            }
+        }
+
+        let point_at = |angle: CGFloat| {
+            (
+                center.0 + radius * angle.cos(),
+                center.1 + radius * angle.sin(),
+            )
+        };
+
+        let start = point_at(start_angle);
+        if self.current_point().is_some() {
+            self.line_to(start);
+        } else {
+            self.move_to(start);
+        }
+
+        // Roughly one segment per two units of arc length, which is plenty at
+        // typical scales.
+        let arc_length = (sweep * radius).abs();
+        let segments = ((arc_length / 2.0).ceil() as u32).clamp(1, 256);
+        for i in 1..=segments {
+            let t = i as CGFloat / segments as CGFloat;
+            self.line_to(point_at(start_angle + sweep * t));
+        }
+    }
+
+    pub(super) fn add_rect(&mut self, origin: Point, size: (CGFloat, CGFloat)) {
+        let (x, y) = origin;
+        let (width, height) = size;
+        self.move_to((x, y));
+        self.line_to((x + width, y));
+        self.line_to((x + width, y + height));
+        self.line_to((x, y + height));
+        self.close();
+    }
+
+    pub(super) fn close(&mut self) {
+        if let Some(subpath) = self.subpaths.last_mut() {
+            subpath.closed = true;
+        }
+    }
+
+    /// Get the polygons to fill to fill this path. Open subpaths are
+    /// implicitly closed.
+    pub(super) fn fill_polygons(&self) -> Vec<Vec<Point>> {
+        self.subpaths
+            .iter()
+            .filter(|subpath| subpath.points.len() > 2)
+            .map(|subpath| subpath.points.clone())
+            .collect()
+    }
+
+    /// Get the polygons to fill (with the non-zero winding rule) to stroke
+    /// this path. Butt caps and mitered joins are used.
+    pub(super) fn stroke_polygons(&self, line_width: CGFloat) -> Vec<Vec<Point>> {
+        let half_width = line_width / 2.0;
+        let mut polygons = Vec::new();
+        for subpath in &self.subpaths {
+            let mut points = subpath.points.clone();
+            points.dedup();
+            if subpath.closed && points.len() > 1 && points.first() == points.last() {
+                points.pop();
+            }
+            if points.len() < 2 {
+                continue;
+            }
+
+            let segment_count = if subpath.closed {
+                points.len()
+            } else {
+                points.len() - 1
+            };
+            let segment = |i: usize| (points[i % points.len()], points[(i + 1) % points.len()]);
+
+            for i in 0..segment_count {
+                let (a, b) = segment(i);
+                let n = scale(normal(a, b), half_width);
+                polygons.push(oriented(vec![add(a, n), add(b, n), sub(b, n), sub(a, n)]));
+            }
+
+            let joins = if subpath.closed {
+                0..segment_count
+            } else {
+                1..segment_count
+            };
+            for i in joins {
+                let (a, vertex) = segment((i + segment_count - 1) % segment_count);
+                let (_, b) = segment(i);
+                if let Some(join) = miter_join(a, vertex, b, half_width) {
+                    polygons.push(join);
+                }
+            }
+        }
+        polygons
+    }
+}
+
+/// The default miter limit, see `CGContextSetMiterLimit`.
+const MITER_LIMIT: CGFloat = 10.0;
+
+/// Polygon filling the outside corner where the segments `a`-`vertex` and
+/// `vertex`-`b` meet, if there is a corner.
+fn miter_join(a: Point, vertex: Point, b: Point, half_width: CGFloat) -> Option<Vec<Point>> {
+    let n1 = normal(a, vertex);
+    let n2 = normal(vertex, b);
+    let turn = cross(sub(vertex, a), sub(b, vertex));
+    if turn == 0.0 {
+        return None;
+    }
+    // The outside of the corner is on the right for a left turn.
+    let side = if turn > 0.0 { -half_width } else { half_width };
+    let p1 = add(vertex, scale(n1, side));
+    let p2 = add(vertex, scale(n2, side));
+
+    let cos = dot(n1, n2);
+    let miter_ratio = (2.0 / (1.0 + cos)).sqrt();
+    let polygon = if cos > -1.0 && miter_ratio <= MITER_LIMIT {
+        let miter = add(vertex, scale(add(n1, n2), side / (1.0 + cos)));
+        vec![vertex, p1, miter, p2]
+    } else {
+        // Bevel join
+        vec![vertex, p1, p2]
+    };
+    Some(oriented(polygon))
+}
+
+fn add(a: Point, b: Point) -> Point {
+    (a.0 + b.0, a.1 + b.1)
+}
+fn sub(a: Point, b: Point) -> Point {
+    (a.0 - b.0, a.1 - b.1)
+}
+fn scale(a: Point, factor: CGFloat) -> Point {
+    (a.0 * factor, a.1 * factor)
+}
+fn dot(a: Point, b: Point) -> CGFloat {
+    a.0 * b.0 + a.1 * b.1
+}
+fn cross(a: Point, b: Point) -> CGFloat {
+    a.0 * b.1 - a.1 * b.0
+}
+/// Unit vector perpendicular to the line from `a` to `b`.
+fn normal(a: Point, b: Point) -> Point {
+    let (dx, dy) = sub(b, a);
+    let length = (dx * dx + dy * dy).sqrt();
+    (-dy / length, dx / length)
+}
+
+/// Make sure all polygons that make up a stroke wind the same way, so that
+/// overlapping parts don't cancel each other out when filled.
+fn oriented(mut polygon: Vec<Point>) -> Vec<Point> {
+    let area: CGFloat = (0..polygon.len())
+        .map(|i| cross(polygon[i], polygon[(i + 1) % polygon.len()]))
+        .sum();
+    if area < 0.0 {
+        polygon.reverse();
+    }
+    polygon
+}
+
+/// How many line segments to approximate a curve with, based on the length
+/// of its control polygon.
+fn segment_count(control_points: &[Point]) -> u32 {
+    let length: CGFloat = control_points
+        .windows(2)
+        .map(|pair| {
+            let (dx, dy) = sub(pair[1], pair[0]);
+            (dx * dx + dy * dy).sqrt()
+        })
+        .sum();
+    ((length / 2.0).ceil() as u32).clamp(1, 256)
+}
+
+/// Fill polygons, calling `put_pixel` for each pixel (within `width` and
+/// `height`) whose center is inside them. With `even_odd`, the even-odd rule
+/// is used rather than the non-zero winding rule.
+pub(super) fn rasterize_polygons(
+    polygons: &[Vec<Point>],
+    even_odd: bool,
+    width: u32,
+    height: u32,
+    mut put_pixel: impl FnMut(i32, i32),
+) {
+    let edges: Vec<(Point, Point)> = polygons
+        .iter()
+        .flat_map(|polygon| {
+            (0..polygon.len()).map(move |i| (polygon[i], polygon[(i + 1) % polygon.len()]))
+        })
+        .filter(|&((_, y0), (_, y1))| y0 != y1)
+        .collect();
+    if edges.is_empty() {
+        return;
+    }
+
+    let min_y = edges
+        .iter()
+        .map(|&((_, y0), (_, y1))| y0.min(y1))
+        .fold(CGFloat::INFINITY, CGFloat::min);
+    let max_y = edges
+        .iter()
+        .map(|&((_, y0), (_, y1))| y0.max(y1))
+        .fold(CGFloat::NEG_INFINITY, CGFloat::max);
+    let y_start = (min_y - 0.5).ceil().max(0.0) as u32;
+    let y_end = ((max_y - 0.5).ceil().max(0.0) as u32).min(height);
+
+    let mut crossings: Vec<(CGFloat, i32)> = Vec::new();
+    for y in y_start..y_end {
+        let center_y = y as CGFloat + 0.5;
+        crossings.clear();
+        for &((x0, y0), (x1, y1)) in &edges {
+            if (y0 <= center_y && center_y < y1) || (y1 <= center_y && center_y < y0) {
+                let x = x0 + (center_y - y0) * (x1 - x0) / (y1 - y0);
+                crossings.push((x, if y1 > y0 { 1 } else { -1 }));
+            }
+        }
+        crossings.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        let mut winding = 0;
+        for pair in crossings.windows(2) {
+            let ((x_a, direction), (x_b, _)) = (pair[0], pair[1]);
+            winding += direction;
+            let inside = if even_odd {
+                winding % 2 != 0
+            } else {
+                winding != 0
+            };
+            if !inside {
+                continue;
+            }
+            // Pixels whose centers are in [x_a, x_b)
+            let x_start = (x_a - 0.5).ceil().max(0.0) as u32;
+            let x_end = ((x_b - 0.5).ceil().max(0.0) as u32).min(width);
+            for x in x_start..x_end {
+                put_pixel(x as i32, y as i32);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Rasterize into a grid of characters, for easy comparison.
+    fn render(polygons: &[Vec<Point>], even_odd: bool, size: u32) -> Vec<String> {
+        let mut grid = vec![vec!['.'; size as usize]; size as usize];
+        rasterize_polygons(polygons, even_odd, size, size, |x, y| {
+            grid[y as usize][x as usize] = '#';
+        });
+        grid.into_iter()
+            .map(|row| row.into_iter().collect())
+            .collect()
+    }
+
+    #[test]
+    fn stroke_rect() {
+        let mut path = Path::default();
+        path.add_rect((2.0, 2.0), (4.0, 4.0));
+        assert_eq!(
+            render(&path.stroke_polygons(2.0), false, 9),
+            [
+                ".........",
+                ".######..",
+                ".######..",
+                ".##..##..",
+                ".##..##..",
+                ".######..",
+                ".######..",
+                ".........",
+                ".........",
+            ]
+        );
+    }
+
+    #[test]
+    fn stroke_open_path() {
+        // Butt caps: the stroke doesn't extend past the ends of the line.
+        let mut path = Path::default();
+        path.move_to((1.0, 4.0));
+        path.line_to((7.0, 4.0));
+        assert_eq!(
+            render(&path.stroke_polygons(2.0), false, 9),
+            [
+                ".........",
+                ".........",
+                ".........",
+                ".######..",
+                ".######..",
+                ".........",
+                ".........",
+                ".........",
+                ".........",
+            ]
+        );
+    }
+
+    #[test]
+    fn fill_rules() {
+        // Two nested squares with the same winding direction.
+        let mut path = Path::default();
+        path.add_rect((0.0, 0.0), (7.0, 7.0));
+        path.add_rect((2.0, 2.0), (3.0, 3.0));
+        let polygons = path.fill_polygons();
+        assert_eq!(
+            render(&polygons, false, 7),
+            ["#######", "#######", "#######", "#######", "#######", "#######", "#######",]
+        );
+        assert_eq!(
+            render(&polygons, true, 7),
+            ["#######", "#######", "##...##", "##...##", "##...##", "#######", "#######",]
+        );
+    }
+
+    #[test]
+    fn arc_and_close() {
+        let mut path = Path::default();
+        path.arc((4.5, 4.5), 3.0, 0.0, std::f32::consts::TAU, false);
+        path.close();
+        let grid = render(&path.fill_polygons(), false, 9);
+        // Roughly a circle: filled in the middle, empty in the corners.
+        assert_eq!(&grid[4][2..7], "#####");
+        assert_eq!(&grid[0][..2], "..");
+        assert_eq!(&grid[8][7..], "..");
+        assert_eq!(path.current_point(), Some((7.5, 4.5)));
+    }
+}
@@ -11,44 +11,94 @@
 //! Being aware of this concept will make common types like `NSArray` and
 //! `NSString` easier to understand.
 
+use crate::abi::{impl_GuestRet_for_large_struct, GuestArg};
+use crate::mem::{MutPtr, SafeRead};
+use crate::objc::{id, nil};
+use crate::Environment;
+
 pub mod ns_array;
 pub mod ns_autorelease_pool;
 pub mod ns_bundle;
 pub mod ns_character_set;
 pub mod ns_coder;
 pub mod ns_data;
+pub mod ns_date;
 pub mod ns_dictionary;
 pub mod ns_fast_enumeration;
 pub mod ns_file_manager;
+pub mod ns_invocation;
 pub mod ns_keyed_unarchiver;
 pub mod ns_locale;
+pub mod ns_method_signature;
+pub mod ns_notification_center;
 pub mod ns_null;
 pub mod ns_object;
 pub mod ns_process_info;
+pub mod ns_property_list_serialization;
 pub mod ns_run_loop;
+pub mod ns_scanner;
 pub mod ns_set;
 pub mod ns_string;
 pub mod ns_thread;
 pub mod ns_timer;
 pub mod ns_url;
+pub mod ns_url_connection;
+pub mod ns_url_request;
+pub mod ns_url_response;
+pub mod ns_user_defaults;
 pub mod ns_value;
 
 #[derive(Default)]
 pub struct State {
     ns_autorelease_pool: ns_autorelease_pool::State,
     ns_bundle: ns_bundle::State,
+    ns_file_manager: ns_file_manager::State,
     ns_locale: ns_locale::State,
+    ns_notification_center: ns_notification_center::State,
     ns_null: ns_null::State,
     ns_run_loop: ns_run_loop::State,
     ns_string: ns_string::State,
+    ns_thread: ns_thread::State,
+    ns_user_defaults: ns_user_defaults::State,
 }
 
 pub type NSInteger = i32;
 pub type NSUInteger = u32;
 
+pub type NSComparisonResult = NSInteger;
+pub const NSOrderedAscending: NSComparisonResult = -1;
+pub const NSOrderedSame: NSComparisonResult = 0;
+pub const NSOrderedDescending: NSComparisonResult = 1;
+
+/// Used by search methods to indicate that nothing was found.
+pub const NSNotFound: NSInteger = NSInteger::MAX;
+
 /// Number of seconds.
 pub type NSTimeInterval = f64;
 
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[repr(C, packed)]
+pub struct NSRange {
+    pub location: NSUInteger,
+    pub length: NSUInteger,
+}
+unsafe impl SafeRead for NSRange {}
+impl_GuestRet_for_large_struct!(NSRange);
+impl GuestArg for NSRange {
+    const REG_COUNT: usize = 2;
+
+    fn from_regs(regs: &[u32]) -> Self {
+        NSRange {
+            location: GuestArg::from_regs(&regs[0..1]),
+            length: GuestArg::from_regs(&regs[1..2]),
+        }
+    }
+    fn to_regs(self, regs: &mut [u32]) {
+        self.location.to_regs(&mut regs[0..1]);
+        self.length.to_regs(&mut regs[1..2]);
+    }
+}
+
 /// Utility to help with implementing the `hash` method, which various classes
 /// in Foundation have to do.
 fn hash_helper<T: std::hash::Hash>(hashable: &T) -> NSUInteger {
@@ -61,3 +111,13 @@ fn hash_helper<T: std::hash::Hash>(hashable: &T) -> NSUInteger {
     let hash_u64: u64 = hasher.finish();
     (hash_u64 as u32) ^ ((hash_u64 >> 32) as u32)
 }
+
+/// Utility for methods with an `error:` parameter (`NSError**`) to use when
+/// they fail. `NSError` isn't implemented yet, so this just makes sure the app
+/// doesn't see a garbage error object.
+fn set_error_nil(env: &mut Environment, error: MutPtr<id>) {
+    if !error.is_null() {
+        // TODO: create a real NSError
+        env.mem.write(error, nil);
+    }
+}
@@ -5,17 +5,72 @@
  */
 //! The `NSArray` class cluster, including `NSMutableArray`.
 
-use super::{ns_keyed_unarchiver, NSUInteger};
+use super::ns_property_list_serialization::{
+    from_plist_value, read_plist_file, to_plist_value, write_plist_file,
+};
+use super::{ns_keyed_unarchiver, NSComparisonResult, NSOrderedDescending, NSUInteger};
 use crate::mem::MutVoidPtr;
-use crate::objc::{id, msg_class, objc_classes, release, retain, ClassExports, HostObject};
+use crate::objc::{
+    autorelease, id, msg, msg_class, msg_send, nil, objc_classes, release, retain, ClassExports,
+    HostObject, SEL,
+};
 use crate::Environment;
+use plist::Value;
 
-/// Belongs to _touchHLE_NSArray
+/// Belongs to _touchHLE_NSArray and _touchHLE_NSMutableArray
+#[derive(Default)]
 struct ArrayHostObject {
     array: Vec<id>,
 }
 impl HostObject for ArrayHostObject {}
 
+/// Retaining and releasing objects, abstracted so that the ownership logic of
+/// [ArrayHostObject] can be tested without a full [Environment].
+trait RetainRelease {
+    fn retain(&mut self, object: id) -> id;
+    fn release(&mut self, object: id);
+}
+impl RetainRelease for Environment {
+    fn retain(&mut self, object: id) -> id {
+        retain(self, object)
+    }
+    fn release(&mut self, object: id) {
+        release(self, object)
+    }
+}
+
+// The array owns a reference to each of its elements.
+// TODO: raise NSRangeException etc rather than panicking
+impl ArrayHostObject {
+    fn insert(&mut self, rr: &mut impl RetainRelease, index: NSUInteger, object: id) {
+        assert!(object != nil); // TODO: raise proper exception
+        assert!(index as usize <= self.array.len());
+        let object = rr.retain(object);
+        self.array.insert(index as usize, object);
+    }
+    fn push(&mut self, rr: &mut impl RetainRelease, object: id) {
+        let index = self.array.len().try_into().unwrap();
+        self.insert(rr, index, object);
+    }
+    fn replace(&mut self, rr: &mut impl RetainRelease, index: NSUInteger, object: id) {
+        assert!(object != nil); // TODO: raise proper exception
+
+        // Retain first in case the object is replacing itself.
+        let object = rr.retain(object);
+        let old = std::mem::replace(&mut self.array[index as usize], object);
+        rr.release(old);
+    }
+    fn remove(&mut self, rr: &mut impl RetainRelease, index: NSUInteger) {
+        let object = self.array.remove(index as usize);
+        rr.release(object);
+    }
+    fn clear(&mut self, rr: &mut impl RetainRelease) {
+        for object in std::mem::take(&mut self.array) {
+            rr.release(object);
+        }
+    }
+}
+
 pub const CLASSES: ClassExports = objc_classes! {
 
 (env, this, _cmd);
@@ -34,12 +89,38 @@ pub const CLASSES: ClassExports = objc_classes! {
     msg_class![env; _touchHLE_NSArray allocWithZone:zone]
 }
 
++ (id)arrayWithContentsOfFile:(id)path { // NSString*
+    let new: id = msg![env; this alloc];
+    let new: id = msg![env; new initWithContentsOfFile:path];
+    autorelease(env, new)
+}
+
 // NSCopying implementation
 - (id)copyWithZone:(MutVoidPtr)_zone {
-    // TODO: override this once we have NSMutableArray!
     retain(env, this)
 }
 
+// NSMutableCopying implementation
+- (id)mutableCopyWithZone:(MutVoidPtr)_zone {
+    let count: NSUInteger = msg![env; this count];
+    let mut objects = Vec::with_capacity(count as usize);
+    for i in 0..count {
+        let object: id = msg![env; this objectAtIndex:i];
+        objects.push(retain(env, object));
+    }
+    let new: id = msg_class![env; NSMutableArray alloc];
+    env.objc.borrow_mut::<ArrayHostObject>(new).array = objects;
+    new
+}
+
+- (bool)writeToFile:(id)path // NSString*
+         atomically:(bool)_atomically {
+    let Some(value) = to_plist_value(env, this) else {
+        return false;
+    };
+    write_plist_file(env, path, &value)
+}
+
 @end
 
 // Our private subclass that is the single implementation of NSArray for the
@@ -75,19 +156,99 @@ pub const CLASSES: ClassExports = objc_classes! {
     this
 }
 
+- (id)initWithContentsOfFile:(id)path { // NSString*
+    init_with_contents_of_file(env, this, path)
+}
+
 - (())dealloc {
-    let host_object: &mut ArrayHostObject = env.objc.borrow_mut(this);
-    let array = std::mem::take(&mut host_object.array);
+    dealloc(env, this)
+}
+
+// TODO: more init methods, etc
 
-    for object in array {
-        release(env, object);
+- (NSUInteger)count {
+    env.objc.borrow::<ArrayHostObject>(this).array.len().try_into().unwrap()
+}
+- (id)objectAtIndex:(NSUInteger)index {
+    // TODO: throw real exception rather than panic if out-of-bounds?
+    env.objc.borrow::<ArrayHostObject>(this).array[index as usize]
+}
+
+@end
+
+// NSMutableArray is an abstract class. A subclass must provide everything
+// NSArray provides, plus:
+// - (void)insertObject:(id)anObject atIndex:(NSUInteger)index;
+// - (void)removeObjectAtIndex:(NSUInteger)index;
+// - (void)addObject:(id)anObject;
+// - (void)removeLastObject;
+// - (void)replaceObjectAtIndex:(NSUInteger)index withObject:(id)anObject;
+// Note that it inherits from NSArray, so we must ensure we override any default
+// methods that would be inappropriate for mutability.
+@implementation NSMutableArray: NSArray
+
++ (id)allocWithZone:(MutVoidPtr)zone {
+    // NSMutableArray might be subclassed by something which needs
+    // allocWithZone: to have the normal behaviour. Unimplemented: call
+    // superclass alloc then.
+    assert!(this == env.objc.get_known_class("NSMutableArray", &mut env.mem));
+    msg_class![env; _touchHLE_NSMutableArray allocWithZone:zone]
+}
+
++ (id)array {
+    let new: id = msg![env; this alloc];
+    let new: id = msg![env; new init];
+    autorelease(env, new)
+}
+
++ (id)arrayWithCapacity:(NSUInteger)capacity {
+    let new: id = msg![env; this alloc];
+    let new: id = msg![env; new initWithCapacity:capacity];
+    autorelease(env, new)
+}
+
+// NSCopying implementation (returns an immutable copy)
+- (id)copyWithZone:(MutVoidPtr)_zone {
+    let count: NSUInteger = msg![env; this count];
+    let mut objects = Vec::with_capacity(count as usize);
+    for i in 0..count {
+        let object: id = msg![env; this objectAtIndex:i];
+        objects.push(retain(env, object));
     }
+    from_vec(env, objects)
+}
 
-    // FIXME: this should do a super-call instead
-    env.objc.dealloc_object(this, &mut env.mem)
+@end
+
+// Our private subclass that is the single implementation of NSMutableArray for
+// the time being.
+@implementation _touchHLE_NSMutableArray: NSMutableArray
+
++ (id)allocWithZone:(MutVoidPtr)_zone {
+    let host_object = Box::new(ArrayHostObject {
+        array: Vec::new(),
+    });
+    env.objc.alloc_object(this, host_object, &mut env.mem)
 }
 
-// TODO: more init methods, etc
+- (id)init {
+    msg![env; this initWithCapacity:0u32]
+}
+
+- (id)initWithCapacity:(NSUInteger)capacity {
+    let host_object: &mut ArrayHostObject = env.objc.borrow_mut(this);
+    assert!(host_object.array.is_empty());
+    host_object.array.reserve(capacity as usize);
+    this
+}
+
+- (id)initWithContentsOfFile:(id)path { // NSString*
+    init_with_contents_of_file(env, this, path)
+}
+
+- (())dealloc {
+    dealloc(env, this)
+}
 
 - (NSUInteger)count {
     env.objc.borrow::<ArrayHostObject>(this).array.len().try_into().unwrap()
@@ -97,10 +258,113 @@ pub const CLASSES: ClassExports = objc_classes! {
     env.objc.borrow::<ArrayHostObject>(this).array[index as usize]
 }
 
+// The host object is taken out of the array while retaining or releasing
+// objects, because that can run arbitrary code (e.g. a dealloc method), which
+// could touch the array.
+- (())addObject:(id)object {
+    let mut host_object = std::mem::take(env.objc.borrow_mut::<ArrayHostObject>(this));
+    host_object.push(env, object);
+    *env.objc.borrow_mut(this) = host_object;
+}
+- (())insertObject:(id)object
+           atIndex:(NSUInteger)index {
+    let mut host_object = std::mem::take(env.objc.borrow_mut::<ArrayHostObject>(this));
+    host_object.insert(env, index, object);
+    *env.objc.borrow_mut(this) = host_object;
+}
+- (())replaceObjectAtIndex:(NSUInteger)index
+                withObject:(id)object {
+    let mut host_object = std::mem::take(env.objc.borrow_mut::<ArrayHostObject>(this));
+    host_object.replace(env, index, object);
+    *env.objc.borrow_mut(this) = host_object;
+}
+
+- (())removeObjectAtIndex:(NSUInteger)index {
+    let mut host_object = std::mem::take(env.objc.borrow_mut::<ArrayHostObject>(this));
+    host_object.remove(env, index);
+    *env.objc.borrow_mut(this) = host_object;
+}
+- (())removeLastObject {
+    let count: NSUInteger = msg![env; this count];
+    assert!(count > 0); // TODO: raise proper exception
+    msg![env; this removeObjectAtIndex:(count - 1)]
+}
+- (())removeAllObjects {
+    let mut host_object = std::mem::take(env.objc.borrow_mut::<ArrayHostObject>(this));
+    host_object.clear(env);
+    *env.objc.borrow_mut(this) = host_object;
+}
+
+- (())sortUsingSelector:(SEL)comparator {
+    let array = std::mem::take(&mut env.objc.borrow_mut::<ArrayHostObject>(this).array);
+    let array = merge_sort_by(array, |a, b| {
+        let result: NSComparisonResult = msg_send(env, (a, comparator, b));
+        result == NSOrderedDescending
+    });
+    env.objc.borrow_mut::<ArrayHostObject>(this).array = array;
+}
+
 @end
 
 };
 
+/// Shared implementation of `initWithContentsOfFile:` for our subclasses.
+fn init_with_contents_of_file(env: &mut Environment, this: id, path: id) -> id {
+    let Some(Value::Array(array)) = read_plist_file(env, path) else {
+        release(env, this);
+        return nil;
+    };
+    // Unsupported values are left out, since arrays can't contain nil.
+    let objects = array
+        .iter()
+        .map(|item| from_plist_value(env, item))
+        .filter(|&object| object != nil)
+        .collect();
+    let host_object: &mut ArrayHostObject = env.objc.borrow_mut(this);
+    assert!(host_object.array.is_empty());
+    host_object.array = objects; // objects are already retained
+    this
+}
+
+/// Shared implementation of `dealloc` for our subclasses.
+fn dealloc(env: &mut Environment, this: id) {
+    let mut host_object = std::mem::take(env.objc.borrow_mut::<ArrayHostObject>(this));
+    host_object.clear(env);
+
+    // FIXME: this should do a super-call instead
+    env.objc.dealloc_object(this, &mut env.mem)
+}
+
+/// Stable merge sort for sorting with a guest comparator, where `is_greater`
+/// tells whether the first item should come after the second. Unlike the
+/// standard library's sorts, this doesn't rely on the comparator being
+/// consistent: the result is always some permutation of the input.
+fn merge_sort_by<T: Copy>(mut items: Vec<T>, mut is_greater: impl FnMut(T, T) -> bool) -> Vec<T> {
+    let mut merged = Vec::with_capacity(items.len());
+    let mut width = 1;
+    while width < items.len() {
+        merged.clear();
+        for chunk in items.chunks(width * 2) {
+            let (mut left, mut right) = chunk.split_at(width.min(chunk.len()));
+            while let (Some(&a), Some(&b)) = (left.first(), right.first()) {
+                // Taking from the left on ties keeps the sort stable.
+                if is_greater(a, b) {
+                    merged.push(b);
+                    right = &right[1..];
+                } else {
+                    merged.push(a);
+                    left = &left[1..];
+                }
+            }
+            merged.extend_from_slice(left);
+            merged.extend_from_slice(right);
+        }
+        std::mem::swap(&mut items, &mut merged);
+        width *= 2;
+    }
+    items
+}
+
 /// Shortcut for host code, roughly equivalent to
 /// `[[NSArray alloc] initWithObjects:count]` but without copying.
 /// The elements should already be "retained by" the `Vec`.
@@ -109,3 +373,79 @@ pub fn from_vec(env: &mut Environment, objects: Vec<id>) -> id {
     env.objc.borrow_mut::<ArrayHostObject>(array).array = objects;
     array
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    /// Counts references instead of managing real objects.
+    #[derive(Default)]
+    struct RefCounts(HashMap<id, i32>);
+    impl RetainRelease for RefCounts {
+        fn retain(&mut self, object: id) -> id {
+            *self.0.entry(object).or_insert(1) += 1;
+            object
+        }
+        fn release(&mut self, object: id) {
+            *self.0.get_mut(&object).unwrap() -= 1;
+        }
+    }
+    impl RefCounts {
+        fn count(&self, object: id) -> i32 {
+            self.0.get(&object).copied().unwrap_or(1)
+        }
+    }
+
+    #[test]
+    fn retain_counts() {
+        let mut rc = RefCounts::default();
+        let a: id = id::from_bits(0x1000);
+        let b: id = id::from_bits(0x2000);
+        let c: id = id::from_bits(0x3000);
+        let mut array = ArrayHostObject { array: Vec::new() };
+
+        array.push(&mut rc, a);
+        array.push(&mut rc, b);
+        array.insert(&mut rc, 0, a);
+        assert_eq!(array.array, [a, a, b]);
+        assert_eq!((rc.count(a), rc.count(b)), (3, 2));
+
+        array.remove(&mut rc, 0);
+        assert_eq!(array.array, [a, b]);
+        assert_eq!(rc.count(a), 2);
+
+        array.replace(&mut rc, 1, c);
+        assert_eq!(array.array, [a, c]);
+        assert_eq!((rc.count(b), rc.count(c)), (1, 2));
+
+        // Replacing an object with itself mustn't free it.
+        array.replace(&mut rc, 1, c);
+        assert_eq!(rc.count(c), 2);
+
+        array.clear(&mut rc);
+        assert!(array.array.is_empty());
+        assert_eq!((rc.count(a), rc.count(b), rc.count(c)), (1, 1, 1));
+    }
+
+    #[test]
+    fn sorting() {
+        let items = vec![(3, 'a'), (1, 'b'), (2, 'c'), (1, 'd'), (3, 'e')];
+        let sorted = merge_sort_by(items.clone(), |a, b| a.0 > b.0);
+        // Equal items keep their order.
+        assert_eq!(sorted, [(1, 'b'), (1, 'd'), (2, 'c'), (3, 'a'), (3, 'e')]);
+
+        // A comparator that contradicts itself still produces a permutation.
+        let mut calls = 0;
+        let mut sorted = merge_sort_by(items.clone(), |_, _| {
+            calls += 1;
+            calls % 3 == 0
+        });
+        sorted.sort();
+        let mut expected = items;
+        expected.sort();
+        assert_eq!(sorted, expected);
+
+        assert!(merge_sort_by(Vec::<i32>::new(), |a, b| a > b).is_empty());
+    }
+}
@@ -6,24 +6,84 @@
 //! `NSAutoreleasePool`.
 
 use crate::mem::MutVoidPtr;
-use crate::objc::{id, msg, objc_classes, release, ClassExports, HostObject};
-use crate::Environment;
+use crate::objc::{id, msg, objc_classes, release, ClassExports, TrivialHostObject};
+use crate::{Environment, ThreadID};
+use std::collections::HashMap;
 
 #[derive(Default)]
 pub struct State {
-    pool_stack: Vec<id>,
+    /// Each thread has its own stack of pools.
+    pool_stacks: HashMap<ThreadID, PoolStack>,
 }
 impl State {
     fn get(env: &mut Environment) -> &mut Self {
         &mut env.framework_state.foundation.ns_autorelease_pool
     }
+    fn current_stack(env: &mut Environment) -> &mut PoolStack {
+        let current_thread = env.current_thread;
+        Self::get(env)
+            .pool_stacks
+            .entry(current_thread)
+            .or_default()
+    }
+}
+
+/// A thread's stack of pools. The objects in each pool are stored here rather
+/// than in the pool object, so that the draining logic doesn't need access to
+/// the objects.
+#[derive(Default)]
+struct PoolStack {
+    /// Pools and their objects, innermost last. The list of objects is allowed
+    /// to contain duplicates, which get released several times!
+    pools: Vec<(id, Vec<id>)>,
+}
+
+/// Something [PoolStack::next_drain_step] wants the caller to do.
+#[derive(Debug, PartialEq)]
+enum DrainStep {
+    /// Release an object that was in a pool.
+    Release(id),
+    /// Deallocate a pool nested inside the pool being drained, which is now
+    /// empty and has been removed from the stack.
+    DeallocInnerPool(id),
 }
 
-struct NSAutoreleasePoolHostObject {
-    /// This is allowed to contain duplicates, which get released several times!
-    objects: Vec<id>,
+impl PoolStack {
+    fn push_pool(&mut self, pool: id) {
+        self.pools.push((pool, Vec::new()));
+    }
+    /// Add an object to the innermost pool. Returns [false] if there is none.
+    fn add_object(&mut self, object: id) -> bool {
+        let Some((_pool, objects)) = self.pools.last_mut() else {
+            return false;
+        };
+        objects.push(object);
+        true
+    }
+    /// Get the next step in draining `pool`, or [None] if it's been drained
+    /// and removed from the stack.
+    ///
+    /// Releasing an object can run arbitrary code, which might autorelease more
+    /// objects, so the pool stays on the stack and this has to be called again
+    /// after each step. Pools nested inside `pool` get drained first, and
+    /// objects are released in the reverse of the order they were added.
+    fn next_drain_step(&mut self, pool: id) -> Option<DrainStep> {
+        if !self.pools.iter().any(|&(other_pool, _)| other_pool == pool) {
+            return None;
+        }
+        let (innermost_pool, objects) = self.pools.last_mut().unwrap();
+        if let Some(object) = objects.pop() {
+            return Some(DrainStep::Release(object));
+        }
+        let innermost_pool = *innermost_pool;
+        self.pools.pop();
+        if innermost_pool == pool {
+            None
+        } else {
+            Some(DrainStep::DeallocInnerPool(innermost_pool))
+        }
+    }
 }
-impl HostObject for NSAutoreleasePoolHostObject {}
 
 pub const CLASSES: ClassExports = objc_classes! {
 
@@ -32,26 +92,33 @@ pub const CLASSES: ClassExports = objc_classes! {
 @implementation NSAutoreleasePool: NSObject
 
 + (id)allocWithZone:(MutVoidPtr)_zone {
-    let host_object = Box::new(NSAutoreleasePoolHostObject {
-        objects: Vec::new(),
-    });
-    env.objc.alloc_object(this, host_object, &mut env.mem)
+    env.objc.alloc_object(this, Box::new(TrivialHostObject), &mut env.mem)
 }
 
 + (())addObject:(id)obj {
-    let current_pool = State::get(env).pool_stack.last().copied().unwrap();
-    msg![env; current_pool addObject:obj]
+    if !State::current_stack(env).add_object(obj) {
+        log!(
+            "Warning: {:?} autoreleased on thread {} with no pool in place, leaking",
+            obj,
+            env.current_thread
+        );
+    }
 }
 
 - (id)init {
-    assert!(env.current_thread == 0); // TODO: per-thread stacks
-    State::get(env).pool_stack.push(this);
-    log_dbg!("New pool: {:?}", this);
+    State::current_stack(env).push_pool(this);
+    log_dbg!("New pool on thread {}: {:?}", env.current_thread, this);
     this
 }
 
 - (())addObject:(id)obj {
-    env.objc.borrow_mut::<NSAutoreleasePoolHostObject>(this).objects.push(obj);
+    // TODO: Pools other than the innermost one on the current thread are
+    // probably never used this way.
+    let stack = State::current_stack(env);
+    let Some((_, objects)) = stack.pools.iter_mut().find(|(pool, _)| *pool == this) else {
+        panic!("{:?} is not in the current thread's stack of pools", this);
+    };
+    objects.push(obj);
 }
 
 - (id)retain {
@@ -69,16 +136,81 @@ pub const CLASSES: ClassExports = objc_classes! {
 
 - (())dealloc {
     log_dbg!("Draining pool: {:?}", this);
-    let pop_res = State::get(env).pool_stack.pop();
-    assert!(pop_res == Some(this));
-    let host_obj: &mut NSAutoreleasePoolHostObject = env.objc.borrow_mut(this);
-    let objects = std::mem::take(&mut host_obj.objects);
-    env.objc.dealloc_object(this, &mut env.mem);
-    for object in objects {
-        release(env, object);
+    while let Some(step) = State::current_stack(env).next_drain_step(this) {
+        match step {
+            DrainStep::Release(object) => release(env, object),
+            DrainStep::DeallocInnerPool(pool) => {
+                log_dbg!("Pool {:?} drained by outer pool {:?}", pool, this);
+                env.objc.dealloc_object(pool, &mut env.mem)
+            }
+        }
     }
+    env.objc.dealloc_object(this, &mut env.mem)
 }
 
 @end
 
 };
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nested_pools() {
+        let [outer, inner, a, b, c, d, e] =
+            [0x100, 0x200, 0x1, 0x2, 0x3, 0x4, 0x5].map(id::from_bits);
+
+        let mut stack = PoolStack::default();
+        assert!(!stack.add_object(a));
+
+        stack.push_pool(outer);
+        assert!(stack.add_object(a));
+        assert!(stack.add_object(b));
+        assert!(stack.add_object(a));
+        stack.push_pool(inner);
+        assert!(stack.add_object(c));
+        assert!(stack.add_object(d));
+
+        // Draining the outer pool drains the inner one first.
+        let mut steps = Vec::new();
+        while let Some(step) = stack.next_drain_step(outer) {
+            // Simulate d's dealloc method autoreleasing another object.
+            if step == DrainStep::Release(d) {
+                assert!(stack.add_object(e));
+            }
+            steps.push(step);
+        }
+        use DrainStep::*;
+        assert_eq!(
+            steps,
+            [
+                Release(d),
+                Release(e),
+                Release(c),
+                DeallocInnerPool(inner),
+                Release(a),
+                Release(b),
+                Release(a),
+            ]
+        );
+        assert!(stack.pools.is_empty());
+        assert_eq!(stack.next_drain_step(outer), None);
+    }
+
+    #[test]
+    fn drain_inner_pool() {
+        let [outer, inner, a, b] = [0x100, 0x200, 0x1, 0x2].map(id::from_bits);
+        let mut stack = PoolStack::default();
+        stack.push_pool(outer);
+        stack.add_object(a);
+        stack.push_pool(inner);
+        stack.add_object(b);
+
+        assert_eq!(stack.next_drain_step(inner), Some(DrainStep::Release(b)));
+        assert_eq!(stack.next_drain_step(inner), None);
+        // The outer pool is untouched and receives new objects.
+        assert!(stack.add_object(b));
+        assert_eq!(stack.pools, [(outer, vec![a, b])]);
+    }
+}
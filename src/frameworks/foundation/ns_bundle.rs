@@ -5,9 +5,16 @@
  */
 //! `NSBundle`.
 
-use super::ns_string::from_rust_string;
+use super::ns_property_list_serialization::from_plist_value;
+use super::ns_string::{from_rust_string, to_rust_string};
+use super::NSUInteger;
 use crate::bundle::Bundle;
-use crate::objc::{id, msg, msg_class, objc_classes, release, ClassExports, HostObject};
+use crate::fs::{GuestPath, GuestPathBuf};
+use crate::objc::{
+    autorelease, id, msg, msg_class, nil, objc_classes, release, ClassExports, HostObject,
+};
+use crate::Environment;
+use plist::Value;
 
 #[derive(Default)]
 pub struct State {
@@ -22,6 +29,8 @@ struct NSBundleHostObject {
     bundle_path: id,
     /// NSURL with bundle path. [None] if not created yet.
     bundle_url: Option<id>,
+    /// NSDictionary with the `Info.plist` contents. [None] if not created yet.
+    info_dictionary: Option<id>,
 }
 impl HostObject for NSBundleHostObject {}
 
@@ -41,6 +50,7 @@ pub const CLASSES: ClassExports = objc_classes! {
             _bundle: None,
             bundle_path,
             bundle_url: None,
+            info_dictionary: None,
         };
         let new = env.objc.alloc_object(
             this,
@@ -53,10 +63,13 @@ pub const CLASSES: ClassExports = objc_classes! {
 }
 
 - (())dealloc {
-    let &NSBundleHostObject { bundle_url, .. } = env.objc.borrow(this);
+    let &NSBundleHostObject { bundle_url, info_dictionary, .. } = env.objc.borrow(this);
     if let Some(bundle_url) = bundle_url {
         release(env, bundle_url);
     }
+    if let Some(info_dictionary) = info_dictionary {
+        release(env, info_dictionary);
+    }
     env.objc.dealloc_object(this, &mut env.mem)
 }
 
@@ -86,8 +99,253 @@ pub const CLASSES: ClassExports = objc_classes! {
     msg![env; this bundleURL]
 }
 
+- (id)pathForResource:(id)name // NSString*
+               ofType:(id)extension { // NSString*
+    msg![env; this pathForResource:name ofType:extension inDirectory:nil]
+}
+- (id)pathForResource:(id)name // NSString*
+               ofType:(id)extension // NSString*
+          inDirectory:(id)directory { // NSString*
+    // A nil name means the first file with the extension.
+    let name = (name != nil).then(|| to_rust_string(env, name));
+    let extension = (extension != nil).then(|| to_rust_string(env, extension));
+    let directory = (directory != nil).then(|| to_rust_string(env, directory));
+    let Some(path) = find_resource(
+        env,
+        this,
+        name.as_deref(),
+        extension.as_deref(),
+        directory.as_deref(),
+    ) else {
+        log_dbg!(
+            "[{:?} pathForResource:{:?} ofType:{:?} inDirectory:{:?}] => nil",
+            this,
+            name,
+            extension,
+            directory,
+        );
+        return nil;
+    };
+    let path = from_rust_string(env, String::from(path));
+    autorelease(env, path)
+}
+
+- (id)infoDictionary {
+    if let Some(dict) = env.objc.borrow::<NSBundleHostObject>(this).info_dictionary {
+        dict
+    } else {
+        // TODO: non-main bundles
+        let plist = Value::Dictionary(env.bundle.info_plist().clone());
+        let new = from_plist_value(env, &plist);
+        env.objc.borrow_mut::<NSBundleHostObject>(this).info_dictionary = Some(new);
+        new
+    }
+}
+- (id)objectForInfoDictionaryKey:(id)key { // NSString*
+    // TODO: localized values from InfoPlist.strings
+    let info_dictionary: id = msg![env; this infoDictionary];
+    msg![env; info_dictionary objectForKey:key]
+}
+
 // TODO: constructors, more accessors
 
 @end
 
 };
+
+/// Implementation of `pathForResource:ofType:inDirectory:`.
+fn find_resource(
+    env: &mut Environment,
+    bundle: id,
+    name: Option<&str>,
+    extension: Option<&str>,
+    directory: Option<&str>,
+) -> Option<GuestPathBuf> {
+    let bundle_path: id = msg![env; bundle resourcePath];
+    let bundle_path = GuestPathBuf::from(to_rust_string(env, bundle_path).into_owned());
+
+    // TODO: non-main bundles
+    let mut languages = Vec::new();
+    let preferred_languages: id = msg_class![env; NSLocale preferredLanguages];
+    let count: NSUInteger = msg![env; preferred_languages count];
+    for i in 0..count {
+        let language: id = msg![env; preferred_languages objectAtIndex:i];
+        languages.push(to_rust_string(env, language).into_owned());
+    }
+    if let Some(region) = env.bundle.development_region() {
+        languages.push(region.to_string());
+    }
+
+    let directories = resource_directories(&bundle_path, directory, &languages);
+    let Some(name) = name else {
+        return directories.iter().find_map(|dir| {
+            let file_names = env.fs.read_dir(dir).ok()?;
+            let file_name = first_file_of_type(&file_names, extension?)?;
+            Some(dir.join(file_name))
+        });
+    };
+    let file_names = resource_file_names(name, extension);
+    directories
+        .iter()
+        .flat_map(|dir| file_names.iter().map(|file_name| dir.join(file_name)))
+        .find(|path| env.fs.exists(path))
+}
+
+/// Find the first of some (sorted) file names that has a given extension.
+fn first_file_of_type<'a>(file_names: &'a [String], extension: &str) -> Option<&'a str> {
+    file_names.iter().map(String::as_str).find(|file_name| {
+        file_name
+            .rsplit_once('.')
+            .is_some_and(|(stem, file_extension)| !stem.is_empty() && file_extension == extension)
+    })
+}
+
+/// The file names that could match a resource, in order of preference.
+///
+/// touchHLE emulates a device with a non-Retina display, so `@2x` variants are
+/// only used if there is no normal version of a file. The `~iphone` device
+/// modifier is preferred over a file with no modifier.
+fn resource_file_names(name: &str, extension: Option<&str>) -> Vec<String> {
+    let (stem, extension) = match extension {
+        Some(extension) if !extension.is_empty() => (name, Some(extension)),
+        _ => match name.rsplit_once('.') {
+            Some((stem, extension)) => (stem, Some(extension)),
+            None => (name, None),
+        },
+    };
+    let suffixes: &[&str] = if stem.ends_with("@2x") {
+        // The app explicitly asked for the @2x version.
+        &["~iphone", ""]
+    } else {
+        &["~iphone", "", "@2x~iphone", "@2x"]
+    };
+    suffixes
+        .iter()
+        .map(|suffix| match extension {
+            Some(extension) => format!("{}{}.{}", stem, suffix, extension),
+            None => format!("{}{}", stem, suffix),
+        })
+        .collect()
+}
+
+/// The directories that should be searched for a resource, in order of
+/// preference. Non-localized resources are preferred, then the localization
+/// directories (`.lproj`) for each language in `languages`.
+fn resource_directories(
+    bundle_path: &GuestPath,
+    directory: Option<&str>,
+    languages: &[String],
+) -> Vec<GuestPathBuf> {
+    let subdirectory = |dir: GuestPathBuf| match directory {
+        Some(directory) if !directory.is_empty() => dir.join(directory),
+        _ => dir,
+    };
+
+    let mut directories = vec![subdirectory(bundle_path.into())];
+    for language in languages {
+        for lproj in localization_directory_names(language) {
+            let dir = subdirectory(bundle_path.join(lproj));
+            if !directories
+                .iter()
+                .any(|other| other.as_str() == dir.as_str())
+            {
+                directories.push(dir);
+            }
+        }
+    }
+    directories
+}
+
+/// Older apps name their localization directories after the language in
+/// English (e.g. `French.lproj`) rather than using an ISO 639 code (e.g.
+/// `fr.lproj`), so both have to be tried.
+fn localization_directory_names(language: &str) -> Vec<String> {
+    const LEGACY_NAMES: &[(&str, &str)] = &[
+        ("en", "English"),
+        ("fr", "French"),
+        ("de", "German"),
+        ("it", "Italian"),
+        ("es", "Spanish"),
+        ("nl", "Dutch"),
+        ("ja", "Japanese"),
+    ];
+
+    let mut names = vec![format!("{}.lproj", language)];
+    for &(code, legacy_name) in LEGACY_NAMES {
+        if language == code {
+            names.push(format!("{}.lproj", legacy_name));
+        } else if language == legacy_name {
+            names.push(format!("{}.lproj", code));
+        }
+    }
+    names
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_names() {
+        assert_eq!(
+            resource_file_names("Level1", Some("plist")),
+            [
+                "Level1~iphone.plist",
+                "Level1.plist",
+                "Level1@2x~iphone.plist",
+                "Level1@2x.plist",
+            ]
+        );
+        // The extension can be part of the name instead.
+        assert_eq!(
+            resource_file_names("music.caf", None),
+            resource_file_names("music", Some("caf"))
+        );
+        assert_eq!(
+            resource_file_names("music.caf", Some("")),
+            resource_file_names("music", Some("caf"))
+        );
+        assert_eq!(
+            resource_file_names("button@2x", Some("png")),
+            ["button@2x~iphone.png", "button@2x.png"]
+        );
+        assert_eq!(resource_file_names("README", None)[1], "README");
+    }
+
+    #[test]
+    fn first_file_with_extension() {
+        let file_names =
+            [".plist", "Default.png", "Level1.plist", "Level2.plist"].map(String::from);
+        assert_eq!(
+            first_file_of_type(&file_names, "plist"),
+            Some("Level1.plist")
+        );
+        assert_eq!(first_file_of_type(&file_names, "png"), Some("Default.png"));
+        assert_eq!(first_file_of_type(&file_names, "caf"), None);
+    }
+
+    #[test]
+    fn localized_directories() {
+        let bundle_path = GuestPath::new(&"/Game.app");
+        let languages = ["fr".to_string(), "English".to_string()];
+        let directories: Vec<String> =
+            resource_directories(bundle_path, Some("Levels"), &languages)
+                .into_iter()
+                .map(String::from)
+                .collect();
+        assert_eq!(
+            directories,
+            [
+                "/Game.app/Levels",
+                "/Game.app/fr.lproj/Levels",
+                "/Game.app/French.lproj/Levels",
+                "/Game.app/English.lproj/Levels",
+                "/Game.app/en.lproj/Levels",
+            ]
+        );
+
+        // Duplicate languages aren't searched twice.
+        let languages = ["en".to_string(), "English".to_string()];
+        assert_eq!(resource_directories(bundle_path, None, &languages).len(), 3);
+    }
+}
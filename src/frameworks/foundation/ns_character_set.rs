@@ -8,16 +8,72 @@
 use super::ns_string;
 use crate::mem::MutVoidPtr;
 use crate::objc::{
-    autorelease, id, msg, msg_class, objc_classes, retain, ClassExports, HostObject,
+    autorelease, id, msg, msg_class, objc_classes, retain, Class, ClassExports, HostObject,
 };
-use std::collections::HashSet;
+use crate::Environment;
+
+/// A set of UTF-16 code units, i.e. of characters in the Basic Multilingual
+/// Plane, with one bit for each.
+#[derive(Clone)]
+struct CodeUnitSet(Box<[u64; 0x10000 / 64]>);
+impl CodeUnitSet {
+    fn new() -> Self {
+        CodeUnitSet(Box::new([0; 0x10000 / 64]))
+    }
+    fn from_code_units(code_units: impl IntoIterator<Item = u16>) -> Self {
+        let mut set = Self::new();
+        for code_unit in code_units {
+            set.insert(code_unit);
+        }
+        set
+    }
+    fn insert(&mut self, code_unit: u16) {
+        self.0[usize::from(code_unit / 64)] |= 1 << (code_unit % 64);
+    }
+    fn contains(&self, code_unit: u16) -> bool {
+        self.0[usize::from(code_unit / 64)] & (1 << (code_unit % 64)) != 0
+    }
+    fn inverted(&self) -> Self {
+        CodeUnitSet(Box::new(self.0.map(|bits| !bits)))
+    }
+}
 
 /// Belongs to _touchHLE_NSCharacterSet
 struct CharacterSetHostObject {
-    set: HashSet<u16>,
+    set: CodeUnitSet,
 }
 impl HostObject for CharacterSetHostObject {}
 
+/// Unicode General Category Zs, plus tab, as in `whitespaceCharacterSet`.
+const WHITESPACE: &[u16] = &[
+    0x0009, 0x0020, 0x00A0, 0x1680, 0x2000, 0x2001, 0x2002, 0x2003, 0x2004, 0x2005, 0x2006, 0x2007,
+    0x2008, 0x2009, 0x200A, 0x202F, 0x205F, 0x3000,
+];
+/// The characters in `newlineCharacterSet`.
+const NEWLINES: &[u16] = &[0x000A, 0x000B, 0x000C, 0x000D, 0x0085, 0x2028, 0x2029];
+/// The zero digit of each run of ten digits in Unicode General Category Nd
+/// (within the BMP), as in `decimalDigitCharacterSet`.
+const DECIMAL_DIGIT_ZEROES: &[u16] = &[
+    0x0030, 0x0660, 0x06F0, 0x07C0, 0x0966, 0x09E6, 0x0A66, 0x0AE6, 0x0B66, 0x0BE6, 0x0C66, 0x0CE6,
+    0x0D66, 0x0DE6, 0x0E50, 0x0ED0, 0x0F20, 0x1040, 0x1090, 0x17E0, 0x1810, 0x1946, 0x19D0, 0x1A80,
+    0x1A90, 0x1B50, 0x1BB0, 0x1C40, 0x1C50, 0xA620, 0xA8D0, 0xA900, 0xA9D0, 0xA9F0, 0xAA50, 0xABF0,
+    0xFF10,
+];
+
+fn decimal_digits() -> CodeUnitSet {
+    CodeUnitSet::from_code_units(
+        DECIMAL_DIGIT_ZEROES
+            .iter()
+            .flat_map(|&zero| zero..zero + 10),
+    )
+}
+
+fn with_set(env: &mut Environment, class: Class, set: CodeUnitSet) -> id {
+    let new: id = msg![env; class alloc];
+    env.objc.borrow_mut::<CharacterSetHostObject>(new).set = set;
+    autorelease(env, new)
+}
+
 pub const CLASSES: ClassExports = objc_classes! {
 
 (env, this, _cmd);
@@ -38,15 +94,28 @@ pub const CLASSES: ClassExports = objc_classes! {
 
 // This doesn't have a corresponding init method for some reason.
 + (id)characterSetWithCharactersInString:(id)string { // NSString*
-    let mut set = HashSet::new();
-    ns_string::for_each_code_unit(env, string, |_idx, c| { set.insert(c); });
+    let mut set = CodeUnitSet::new();
+    ns_string::for_each_code_unit(env, string, |_idx, c| set.insert(c));
+    with_set(env, this, set)
+}
 
-    let new: id = msg![env; this alloc];
-    env.objc.borrow_mut::<CharacterSetHostObject>(new).set = set;
++ (id)whitespaceCharacterSet {
+    let set = CodeUnitSet::from_code_units(WHITESPACE.iter().copied());
+    with_set(env, this, set)
+}
+
++ (id)whitespaceAndNewlineCharacterSet {
+    let set = CodeUnitSet::from_code_units(WHITESPACE.iter().chain(NEWLINES).copied());
+    with_set(env, this, set)
+}
 
-    autorelease(env, new);
++ (id)newlineCharacterSet {
+    let set = CodeUnitSet::from_code_units(NEWLINES.iter().copied());
+    with_set(env, this, set)
+}
 
-    new
++ (id)decimalDigitCharacterSet {
+    with_set(env, this, decimal_digits())
 }
 
 // NSCopying implementation
@@ -63,7 +132,7 @@ pub const CLASSES: ClassExports = objc_classes! {
 
 + (id)allocWithZone:(MutVoidPtr)_zone {
     let host_object = Box::new(CharacterSetHostObject {
-        set: HashSet::new(),
+        set: CodeUnitSet::new(),
     });
     env.objc.alloc_object(this, host_object, &mut env.mem)
 }
@@ -71,9 +140,49 @@ pub const CLASSES: ClassExports = objc_classes! {
 // TODO: initWithCoder:
 
 - (bool)characterIsMember:(u16)code_unit {
-    env.objc.borrow::<CharacterSetHostObject>(this).set.contains(&code_unit)
+    env.objc.borrow::<CharacterSetHostObject>(this).set.contains(code_unit)
+}
+
+- (id)invertedSet {
+    let set = env.objc.borrow::<CharacterSetHostObject>(this).set.inverted();
+    let class = env.objc.get_known_class("_touchHLE_NSCharacterSet", &mut env.mem);
+    with_set(env, class, set)
 }
 
 @end
 
 };
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn membership() {
+        let set = CodeUnitSet::from_code_units("aé日".encode_utf16());
+        assert!(set.contains(b'a'.into()));
+        assert!(set.contains(0xE9));
+        assert!(set.contains(0x65E5));
+        assert!(!set.contains(b'b'.into()));
+        assert!(!set.contains(0));
+        assert!(!set.contains(0xFFFF));
+
+        let digits = decimal_digits();
+        let arabic_and_fullwidth = "٠٩０９";
+        assert!("0123456789".encode_utf16().all(|c| digits.contains(c)));
+        assert!(arabic_and_fullwidth
+            .encode_utf16()
+            .all(|c| digits.contains(c)));
+        assert!(!"/:aⅣ".encode_utf16().any(|c| digits.contains(c)));
+    }
+
+    #[test]
+    fn inversion() {
+        let whitespace = CodeUnitSet::from_code_units(WHITESPACE.iter().copied());
+        let inverted = whitespace.inverted();
+        for c in "\0 \t\nx\u{3000}\u{FFFF}".encode_utf16() {
+            assert_eq!(inverted.contains(c), !whitespace.contains(c));
+        }
+        assert!(inverted.inverted().0 == whitespace.0);
+    }
+}
@@ -4,14 +4,32 @@
  * file, You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 //! `NSData` and `NSMutableData`.
+//!
+//! Most data is only ever looked at by host code (e.g. when loading an image),
+//! so the contents are kept on the host side and only copied into guest memory
+//! if the app asks for a pointer to them.
 
-use super::NSUInteger;
-use crate::mem::{ConstVoidPtr, MutVoidPtr, Ptr};
-use crate::objc::{autorelease, id, msg, objc_classes, retain, ClassExports, HostObject};
+use super::ns_string::to_rust_string;
+use super::{NSRange, NSUInteger};
+use crate::fs::{GuestOpenOptions, GuestPath};
+use crate::mem::{ConstPtr, ConstVoidPtr, MutVoidPtr};
+use crate::objc::{
+    autorelease, id, msg, msg_class, nil, objc_classes, release, retain, ClassExports, HostObject,
+};
+use crate::Environment;
+use std::io::Write;
 
-struct NSDataHostObject {
-    bytes: MutVoidPtr,
-    length: NSUInteger,
+enum NSDataHostObject {
+    /// Contents stored on the host side.
+    Host(Vec<u8>),
+    /// Contents stored in guest memory, either because the app provided them
+    /// (`initWithBytesNoCopy:length:`) or because it asked for a pointer to
+    /// them (`bytes`).
+    Guest {
+        bytes: MutVoidPtr,
+        length: NSUInteger,
+        free_when_done: bool,
+    },
 }
 impl HostObject for NSDataHostObject {}
 
@@ -23,13 +41,23 @@ pub const CLASSES: ClassExports = objc_classes! {
 @implementation NSData: NSObject
 
 + (id)allocWithZone:(MutVoidPtr)_zone {
-    let host_object = Box::new(NSDataHostObject {
-        bytes: Ptr::null(),
-        length: 0,
-    });
+    let host_object = Box::new(NSDataHostObject::Host(Vec::new()));
     env.objc.alloc_object(this, host_object, &mut env.mem)
 }
 
++ (id)data {
+    let new: id = msg![env; this alloc];
+    let new: id = msg![env; new init];
+    autorelease(env, new)
+}
+
++ (id)dataWithBytes:(ConstVoidPtr)bytes
+             length:(NSUInteger)length {
+    let new: id = msg![env; this alloc];
+    let new: id = msg![env; new initWithBytes:bytes length:length];
+    autorelease(env, new)
+}
+
 + (id)dataWithBytesNoCopy:(MutVoidPtr)bytes
                    length:(NSUInteger)length {
     let new: id = msg![env; this alloc];
@@ -37,20 +65,78 @@ pub const CLASSES: ClassExports = objc_classes! {
     autorelease(env, new)
 }
 
++ (id)dataWithBytesNoCopy:(MutVoidPtr)bytes
+                   length:(NSUInteger)length
+             freeWhenDone:(bool)free_when_done {
+    let new: id = msg![env; this alloc];
+    let new: id = msg![env; new initWithBytesNoCopy:bytes
+                                             length:length
+                                       freeWhenDone:free_when_done];
+    autorelease(env, new)
+}
+
++ (id)dataWithContentsOfFile:(id)path { // NSString*
+    let new: id = msg![env; this alloc];
+    let new: id = msg![env; new initWithContentsOfFile:path];
+    autorelease(env, new)
+}
+
++ (id)dataWithData:(id)data { // NSData*
+    let new: id = msg![env; this alloc];
+    let new: id = msg![env; new initWithData:data];
+    autorelease(env, new)
+}
+
+- (id)init {
+    *env.objc.borrow_mut(this) = NSDataHostObject::Host(Vec::new());
+    this
+}
+
+- (id)initWithBytes:(ConstVoidPtr)bytes
+             length:(NSUInteger)length {
+    let contents = env.mem.bytes_at(bytes.cast(), length).to_vec();
+    *env.objc.borrow_mut(this) = NSDataHostObject::Host(contents);
+    this
+}
+
 - (id)initWithBytesNoCopy:(MutVoidPtr)bytes
                    length:(NSUInteger)length {
-    let host_object = env.objc.borrow_mut::<NSDataHostObject>(this);
-    assert!(host_object.bytes.is_null() && host_object.length == 0);
-    host_object.bytes = bytes;
-    host_object.length = length;
+    msg![env; this initWithBytesNoCopy:bytes length:length freeWhenDone:true]
+}
+
+- (id)initWithBytesNoCopy:(MutVoidPtr)bytes
+                   length:(NSUInteger)length
+             freeWhenDone:(bool)free_when_done {
+    *env.objc.borrow_mut(this) = NSDataHostObject::Guest {
+        bytes,
+        length,
+        free_when_done,
+    };
+    this
+}
+
+- (id)initWithContentsOfFile:(id)path { // NSString*
+    let path = to_rust_string(env, path); // TODO: avoid copy?
+    let Ok(contents) = env.fs.read(GuestPath::new(&path)) else {
+        release(env, this);
+        return nil;
+    };
+    *env.objc.borrow_mut(this) = NSDataHostObject::Host(contents);
+    this
+}
+
+- (id)initWithData:(id)data { // NSData*
+    let contents = to_rust_slice(env, data).to_vec();
+    *env.objc.borrow_mut(this) = NSDataHostObject::Host(contents);
     this
 }
 
 - (())dealloc {
-    let &NSDataHostObject { bytes, .. } = env.objc.borrow(this);
-    if !bytes.is_null() {
-        env.mem.free(bytes);
-    }
+    let host_object = std::mem::replace(
+        env.objc.borrow_mut::<NSDataHostObject>(this),
+        NSDataHostObject::Host(Vec::new()),
+    );
+    free_guest_copy(env, host_object);
     env.objc.dealloc_object(this, &mut env.mem)
 }
 
@@ -59,13 +145,207 @@ pub const CLASSES: ClassExports = objc_classes! {
     retain(env, this)
 }
 
+// NSMutableCopying implementation
+- (id)mutableCopyWithZone:(MutVoidPtr)_zone {
+    let new: id = msg_class![env; NSMutableData alloc];
+    msg![env; new initWithData:this]
+}
+
 - (ConstVoidPtr)bytes {
-    env.objc.borrow::<NSDataHostObject>(this).bytes.cast_const()
+    move_to_guest(env, this).cast_const()
 }
 - (NSUInteger)length {
-    env.objc.borrow::<NSDataHostObject>(this).length
+    match *env.objc.borrow::<NSDataHostObject>(this) {
+        NSDataHostObject::Host(ref contents) => contents.len().try_into().unwrap(),
+        NSDataHostObject::Guest { length, .. } => length,
+    }
+}
+
+- (())getBytes:(MutVoidPtr)buffer
+         length:(NSUInteger)length {
+    let length = length.min(msg![env; this length]);
+    let range = NSRange { location: 0, length };
+    msg![env; this getBytes:buffer range:range]
+}
+- (())getBytes:(MutVoidPtr)buffer
+          range:(NSRange)range {
+    let range = checked_range(env, this, range);
+    let contents = to_rust_slice(env, this)[range].to_vec();
+    let length = contents.len().try_into().unwrap();
+    env.mem
+        .bytes_at_mut(buffer.cast(), length)
+        .copy_from_slice(&contents);
+}
+
+- (id)subdataWithRange:(NSRange)range {
+    let range = checked_range(env, this, range);
+    let contents = to_rust_slice(env, this)[range].to_vec();
+    let new = from_vec(env, contents);
+    autorelease(env, new)
+}
+
+- (bool)isEqualToData:(id)other { // NSData*
+    this == other || to_rust_slice(env, this) == to_rust_slice(env, other)
+}
+
+- (bool)writeToFile:(id)path // NSString*
+         atomically:(bool)_atomically {
+    let path = to_rust_string(env, path); // TODO: avoid copy?
+    let mut options = GuestOpenOptions::new();
+    options.write().create().truncate();
+    // TODO: atomic writes
+    let Ok(mut file) = env.fs.open_with_options(GuestPath::new(&path), options) else {
+        log!("Warning: couldn't open {:?} to write data", path);
+        return false;
+    };
+    file.write_all(to_rust_slice(env, this)).is_ok()
+}
+
+@end
+
+@implementation NSMutableData: NSData
+
++ (id)dataWithCapacity:(NSUInteger)capacity {
+    let new: id = msg![env; this alloc];
+    let new: id = msg![env; new initWithCapacity:capacity];
+    autorelease(env, new)
+}
+
++ (id)dataWithLength:(NSUInteger)length {
+    let new: id = msg![env; this alloc];
+    let new: id = msg![env; new initWithLength:length];
+    autorelease(env, new)
+}
+
+- (id)initWithCapacity:(NSUInteger)capacity {
+    let contents = Vec::with_capacity(capacity as usize);
+    *env.objc.borrow_mut(this) = NSDataHostObject::Host(contents);
+    this
+}
+
+- (id)initWithLength:(NSUInteger)length {
+    let contents = vec![0; length as usize];
+    *env.objc.borrow_mut(this) = NSDataHostObject::Host(contents);
+    this
+}
+
+// NSCopying implementation (returns an immutable copy)
+- (id)copyWithZone:(MutVoidPtr)_zone {
+    let new: id = msg_class![env; NSData alloc];
+    msg![env; new initWithData:this]
+}
+
+- (MutVoidPtr)mutableBytes {
+    move_to_guest(env, this)
+}
+
+// These may move the contents, like on iPhone OS, so any pointer previously
+// returned by `bytes` or `mutableBytes` becomes invalid.
+- (())setLength:(NSUInteger)length {
+    move_to_host(env, this).resize(length as usize, 0);
+}
+- (())appendBytes:(ConstVoidPtr)bytes
+           length:(NSUInteger)length {
+    let new_bytes = env.mem.bytes_at(bytes.cast(), length).to_vec();
+    move_to_host(env, this).extend_from_slice(&new_bytes);
+}
+- (())appendData:(id)data { // NSData*
+    let new_bytes = to_rust_slice(env, data).to_vec();
+    move_to_host(env, this).extend_from_slice(&new_bytes);
 }
 
 @end
 
 };
+
+/// Shortcut for host code, roughly equivalent to
+/// `[[NSData alloc] initWithBytes:length:]` but without copying.
+pub fn from_vec(env: &mut Environment, contents: Vec<u8>) -> id {
+    let data: id = msg_class![env; NSData alloc];
+    *env.objc.borrow_mut(data) = NSDataHostObject::Host(contents);
+    data
+}
+
+/// Shortcut for host code, provides a view of the contents of an `NSData`
+/// object without copying them into guest memory.
+pub fn to_rust_slice(env: &Environment, data: id) -> &[u8] {
+    match *env.objc.borrow::<NSDataHostObject>(data) {
+        NSDataHostObject::Host(ref contents) => contents,
+        NSDataHostObject::Guest { bytes, length, .. } => {
+            let bytes: ConstPtr<u8> = bytes.cast_const().cast();
+            env.mem.bytes_at(bytes, length)
+        }
+    }
+}
+
+/// Check a range is within the bounds of an `NSData` object and convert it to
+/// a Rust range.
+fn checked_range(env: &mut Environment, data: id, range: NSRange) -> std::ops::Range<usize> {
+    let length: NSUInteger = msg![env; data length];
+    let NSRange {
+        location,
+        length: range_length,
+    } = range;
+    // TODO: raise NSRangeException
+    assert!(
+        location
+            .checked_add(range_length)
+            .map_or(false, |end| end <= length),
+        "Range {:?} out of bounds for data of length {}",
+        range,
+        length
+    );
+    (location as usize)..((location + range_length) as usize)
+}
+
+/// Make sure the contents of an `NSData` object are in guest memory and
+/// return a pointer to them.
+fn move_to_guest(env: &mut Environment, data: id) -> MutVoidPtr {
+    let contents = match *env.objc.borrow_mut::<NSDataHostObject>(data) {
+        NSDataHostObject::Host(ref mut contents) => std::mem::take(contents),
+        NSDataHostObject::Guest { bytes, .. } => return bytes,
+    };
+    let length: NSUInteger = contents.len().try_into().unwrap();
+    // Always allocate at least a byte so that the pointer isn't NULL.
+    let bytes = env.mem.alloc(length.max(1));
+    env.mem
+        .bytes_at_mut(bytes.cast(), length)
+        .copy_from_slice(&contents);
+    *env.objc.borrow_mut(data) = NSDataHostObject::Guest {
+        bytes,
+        length,
+        free_when_done: true,
+    };
+    bytes
+}
+
+/// Make sure the contents of an `NSData` object are on the host side (freeing
+/// the guest memory copy, if any), so that they can be resized.
+fn move_to_host(env: &mut Environment, data: id) -> &mut Vec<u8> {
+    if let &NSDataHostObject::Guest { bytes, length, .. } = env.objc.borrow(data) {
+        let bytes: ConstPtr<u8> = bytes.cast_const().cast();
+        let contents = env.mem.bytes_at(bytes, length).to_vec();
+        let old = std::mem::replace(
+            env.objc.borrow_mut::<NSDataHostObject>(data),
+            NSDataHostObject::Host(contents),
+        );
+        free_guest_copy(env, old);
+    }
+    let NSDataHostObject::Host(contents) = env.objc.borrow_mut(data) else {
+        unreachable!();
+    };
+    contents
+}
+
+fn free_guest_copy(env: &mut Environment, host_object: NSDataHostObject) {
+    if let NSDataHostObject::Guest {
+        bytes,
+        free_when_done: true,
+        ..
+    } = host_object
+    {
+        if !bytes.is_null() {
+            env.mem.free(bytes);
+        }
+    }
+}
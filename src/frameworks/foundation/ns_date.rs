@@ -0,0 +1,234 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! `NSDate`.
+
+use super::{
+    NSComparisonResult, NSOrderedAscending, NSOrderedDescending, NSOrderedSame, NSTimeInterval,
+    NSUInteger,
+};
+use crate::libc::time::realtime_since_unix_epoch;
+use crate::mem::MutVoidPtr;
+use crate::objc::{
+    autorelease, id, msg, msg_class, objc_classes, retain, Class, ClassExports, HostObject,
+};
+use crate::Environment;
+use std::time::{Duration, SystemTime};
+
+/// Number of seconds between the Unix epoch (1970-01-01 00:00:00 UTC) and
+/// Apple's reference date (2001-01-01 00:00:00 UTC).
+const SECONDS_FROM_UNIX_EPOCH_TO_REFERENCE_DATE: NSTimeInterval = 978_307_200.0;
+
+struct NSDateHostObject {
+    /// Seconds since the reference date.
+    time_interval: NSTimeInterval,
+}
+impl HostObject for NSDateHostObject {}
+
+/// Current time as a number of seconds since the reference date.
+///
+/// See [crate::libc::time] for where this comes from.
+pub fn now_since_reference_date(env: &mut Environment) -> NSTimeInterval {
+    realtime_since_unix_epoch(env).as_secs_f64() - SECONDS_FROM_UNIX_EPOCH_TO_REFERENCE_DATE
+}
+
+/// Convert a host time to a number of seconds since the reference date.
+pub fn since_reference_date(time: SystemTime) -> NSTimeInterval {
+    let since_unix_epoch = match time.duration_since(SystemTime::UNIX_EPOCH) {
+        Ok(duration) => duration.as_secs_f64(),
+        Err(e) => -e.duration().as_secs_f64(),
+    };
+    since_unix_epoch - SECONDS_FROM_UNIX_EPOCH_TO_REFERENCE_DATE
+}
+
+/// Convert a number of seconds since the reference date to a host time. This
+/// is the inverse of [since_reference_date].
+pub fn to_system_time(since_reference_date: NSTimeInterval) -> SystemTime {
+    let since_unix_epoch = since_reference_date + SECONDS_FROM_UNIX_EPOCH_TO_REFERENCE_DATE;
+    if since_unix_epoch >= 0.0 {
+        SystemTime::UNIX_EPOCH + Duration::from_secs_f64(since_unix_epoch)
+    } else {
+        SystemTime::UNIX_EPOCH - Duration::from_secs_f64(-since_unix_epoch)
+    }
+}
+
+fn compare(a: NSTimeInterval, b: NSTimeInterval) -> NSComparisonResult {
+    if a < b {
+        NSOrderedAscending
+    } else if a > b {
+        NSOrderedDescending
+    } else {
+        NSOrderedSame
+    }
+}
+
+pub const CLASSES: ClassExports = objc_classes! {
+
+(env, this, _cmd);
+
+@implementation NSDate: NSObject
+
++ (id)allocWithZone:(MutVoidPtr)_zone {
+    let host_object = Box::new(NSDateHostObject { time_interval: 0.0 });
+    env.objc.alloc_object(this, host_object, &mut env.mem)
+}
+
++ (id)date {
+    msg![env; this dateWithTimeIntervalSinceNow:0.0]
+}
+
++ (id)dateWithTimeIntervalSinceNow:(NSTimeInterval)secs {
+    let new: id = msg![env; this alloc];
+    let new: id = msg![env; new initWithTimeIntervalSinceNow:secs];
+    autorelease(env, new)
+}
+
++ (id)dateWithTimeIntervalSinceReferenceDate:(NSTimeInterval)secs {
+    let new: id = msg![env; this alloc];
+    let new: id = msg![env; new initWithTimeIntervalSinceReferenceDate:secs];
+    autorelease(env, new)
+}
+
++ (id)dateWithTimeIntervalSince1970:(NSTimeInterval)secs {
+    let secs = secs - SECONDS_FROM_UNIX_EPOCH_TO_REFERENCE_DATE;
+    msg![env; this dateWithTimeIntervalSinceReferenceDate:secs]
+}
+
++ (NSTimeInterval)timeIntervalSinceReferenceDate {
+    now_since_reference_date(env)
+}
+
+- (id)init {
+    msg![env; this initWithTimeIntervalSinceNow:0.0]
+}
+
+- (id)initWithTimeIntervalSinceNow:(NSTimeInterval)secs {
+    let time_interval = now_since_reference_date(env) + secs;
+    env.objc.borrow_mut::<NSDateHostObject>(this).time_interval = time_interval;
+    this
+}
+
+- (id)initWithTimeIntervalSinceReferenceDate:(NSTimeInterval)secs {
+    env.objc.borrow_mut::<NSDateHostObject>(this).time_interval = secs;
+    this
+}
+
+- (NSTimeInterval)timeIntervalSinceReferenceDate {
+    env.objc.borrow::<NSDateHostObject>(this).time_interval
+}
+
+- (NSTimeInterval)timeIntervalSinceNow {
+    let now = now_since_reference_date(env);
+    env.objc.borrow::<NSDateHostObject>(this).time_interval - now
+}
+
+- (NSTimeInterval)timeIntervalSince1970 {
+    let time_interval = env.objc.borrow::<NSDateHostObject>(this).time_interval;
+    time_interval + SECONDS_FROM_UNIX_EPOCH_TO_REFERENCE_DATE
+}
+
+- (NSTimeInterval)timeIntervalSinceDate:(id)other { // NSDate*
+    let a = env.objc.borrow::<NSDateHostObject>(this).time_interval;
+    let b = env.objc.borrow::<NSDateHostObject>(other).time_interval;
+    a - b
+}
+
+- (id)dateByAddingTimeInterval:(NSTimeInterval)secs {
+    let time_interval = env.objc.borrow::<NSDateHostObject>(this).time_interval + secs;
+    msg_class![env; NSDate dateWithTimeIntervalSinceReferenceDate:time_interval]
+}
+
+- (NSComparisonResult)compare:(id)other { // NSDate*
+    let a = env.objc.borrow::<NSDateHostObject>(this).time_interval;
+    let b = env.objc.borrow::<NSDateHostObject>(other).time_interval;
+    compare(a, b)
+}
+
+- (id)earlierDate:(id)other { // NSDate*
+    let res: NSComparisonResult = msg![env; this compare:other];
+    if res == NSOrderedDescending {
+        other
+    } else {
+        this
+    }
+}
+
+- (id)laterDate:(id)other { // NSDate*
+    let res: NSComparisonResult = msg![env; this compare:other];
+    if res == NSOrderedAscending {
+        other
+    } else {
+        this
+    }
+}
+
+- (bool)isEqualToDate:(id)other { // NSDate*
+    let res: NSComparisonResult = msg![env; this compare:other];
+    res == NSOrderedSame
+}
+
+- (NSUInteger)hash {
+    let time_interval = env.objc.borrow::<NSDateHostObject>(this).time_interval;
+    super::hash_helper(&time_interval.to_bits())
+}
+- (bool)isEqual:(id)other {
+    if this == other {
+        return true;
+    }
+    let class: Class = msg_class![env; NSDate class];
+    if !msg![env; other isKindOfClass:class] {
+        return false;
+    }
+    msg![env; this isEqualToDate:other]
+}
+
+// TODO: distantPast/distantFuture etc
+
+// NSCopying implementation
+- (id)copyWithZone:(MutVoidPtr)_zone {
+    retain(env, this)
+}
+
+@end
+
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reference_date_conversion() {
+        let reference_date = SystemTime::UNIX_EPOCH + Duration::from_secs(978_307_200);
+        assert_eq!(since_reference_date(reference_date), 0.0);
+        assert_eq!(
+            since_reference_date(reference_date + Duration::from_millis(1500)),
+            1.5
+        );
+        assert_eq!(
+            since_reference_date(SystemTime::UNIX_EPOCH),
+            -SECONDS_FROM_UNIX_EPOCH_TO_REFERENCE_DATE
+        );
+
+        assert_eq!(to_system_time(0.0), reference_date);
+        assert_eq!(
+            to_system_time(-SECONDS_FROM_UNIX_EPOCH_TO_REFERENCE_DATE - 2.0),
+            SystemTime::UNIX_EPOCH - Duration::from_secs(2)
+        );
+        assert_eq!(since_reference_date(to_system_time(1.5)), 1.5);
+    }
+
+    #[test]
+    fn comparison() {
+        assert_eq!(compare(1.0, 2.5), NSOrderedAscending);
+        assert_eq!(compare(2.5, 1.0), NSOrderedDescending);
+        assert_eq!(compare(-3.0, -3.0), NSOrderedSame);
+        // Dates near the present are hundreds of millions of seconds after
+        // the reference date.
+        let now = 700_000_000.25;
+        assert_eq!(compare(now + 0.5, now), NSOrderedDescending);
+        assert_eq!((now + 0.5) - now, 0.5);
+    }
+}
@@ -5,12 +5,17 @@
  */
 //! The `NSDictionary` class cluster, including `NSMutableDictionary`.
 
+use super::ns_property_list_serialization::{
+    from_plist_value, read_plist_file, to_plist_value, write_plist_file,
+};
+use super::ns_string::from_rust_string;
 use super::NSUInteger;
 use crate::mem::MutVoidPtr;
 use crate::objc::{
     autorelease, id, msg, msg_class, nil, objc_classes, release, retain, ClassExports, HostObject,
 };
 use crate::Environment;
+use plist::Value;
 use std::collections::HashMap;
 
 /// Alias for the return type of the `hash` method of the `NSObject` protocol.
@@ -59,6 +64,8 @@ impl DictionaryHostObject {
         };
         for &mut (candidate_key, ref mut existing_value) in collisions.iter_mut() {
             if candidate_key == key || msg![env; candidate_key isEqualTo:key] {
+                // The existing key is kept, like in Apple's implementation.
+                release(env, key);
                 release(env, *existing_value);
                 *existing_value = value;
                 return;
@@ -67,6 +74,29 @@ impl DictionaryHostObject {
         collisions.push((key, value));
         self.count += 1;
     }
+    pub(super) fn remove(&mut self, env: &mut Environment, key: id) {
+        let hash: Hash = msg![env; key hash];
+        let Some(collisions) = self.map.get_mut(&hash) else {
+            return;
+        };
+        let mut found = None;
+        for (i, &(candidate_key, _)) in collisions.iter().enumerate() {
+            if candidate_key == key || msg![env; candidate_key isEqualTo:key] {
+                found = Some(i);
+                break;
+            }
+        }
+        let Some(i) = found else {
+            return;
+        };
+        let (existing_key, existing_value) = collisions.remove(i);
+        if collisions.is_empty() {
+            self.map.remove(&hash);
+        }
+        self.count -= 1;
+        release(env, existing_key);
+        release(env, existing_value);
+    }
     pub(super) fn release(&mut self, env: &mut Environment) {
         for collisions in self.map.values() {
             for &(key, value) in collisions {
@@ -108,12 +138,26 @@ pub const CLASSES: ClassExports = objc_classes! {
     autorelease(env, new_dict)
 }
 
++ (id)dictionaryWithContentsOfFile:(id)path { // NSString*
+    let new_dict: id = msg![env; this alloc];
+    let new_dict: id = msg![env; new_dict initWithContentsOfFile:path];
+    autorelease(env, new_dict)
+}
+
 // NSCopying implementation
 - (id)copyWithZone:(MutVoidPtr)_zone {
     // TODO: override this once we have NSMutableString!
     retain(env, this)
 }
 
+- (bool)writeToFile:(id)path // NSString*
+         atomically:(bool)_atomically {
+    let Some(value) = to_plist_value(env, this) else {
+        return false;
+    };
+    write_plist_file(env, path, &value)
+}
+
 // TODO
 
 @end
@@ -134,6 +178,10 @@ pub const CLASSES: ClassExports = objc_classes! {
     env.objc.dealloc_object(this, &mut env.mem)
 }
 
+- (id)initWithContentsOfFile:(id)path { // NSString*
+    init_with_contents_of_file(env, this, path)
+}
+
 - (id)initWithObjectsAndKeys:(id)first_object, ...va_args {
     let first_key: id = va_args.next(env);
     assert!(first_key != nil); // TODO: raise proper exception
@@ -170,4 +218,130 @@ pub const CLASSES: ClassExports = objc_classes! {
 
 @end
 
+// NSMutableDictionary is an abstract class. A subclass must provide everything
+// NSDictionary provides, plus:
+// - (void)setObject:(id)object forKey:(id)key
+// - (void)removeObjectForKey:(id)key
+// Note that it inherits from NSDictionary, so we must ensure we override
+// any default methods that would be inappropriate for mutability.
+@implementation NSMutableDictionary: NSDictionary
+
++ (id)allocWithZone:(MutVoidPtr)zone {
+    // NSMutableDictionary might be subclassed by something which needs
+    // allocWithZone: to have the normal behaviour. Unimplemented: call
+    // superclass alloc then.
+    assert!(this == env.objc.get_known_class("NSMutableDictionary", &mut env.mem));
+    msg_class![env; _touchHLE_NSMutableDictionary allocWithZone:zone]
+}
+
++ (id)dictionary {
+    let new_dict: id = msg![env; this alloc];
+    let new_dict: id = msg![env; new_dict init];
+    autorelease(env, new_dict)
+}
+
+@end
+
+// Our private subclass that is the single implementation of
+// NSMutableDictionary for the time being.
+@implementation _touchHLE_NSMutableDictionary: NSMutableDictionary
+
++ (id)allocWithZone:(MutVoidPtr)_zone {
+    let host_object = Box::<DictionaryHostObject>::default();
+    env.objc.alloc_object(this, host_object, &mut env.mem)
+}
+
+- (())dealloc {
+    std::mem::take(env.objc.borrow_mut::<DictionaryHostObject>(this)).release(env);
+
+    // FIXME: this should do a super-call instead
+    env.objc.dealloc_object(this, &mut env.mem)
+}
+
+- (id)init {
+    *env.objc.borrow_mut(this) = <DictionaryHostObject as Default>::default();
+    this
+}
+
+- (id)initWithContentsOfFile:(id)path { // NSString*
+    init_with_contents_of_file(env, this, path)
+}
+
+// TODO: enumeration, more init methods, etc
+
+- (NSUInteger)count {
+    env.objc.borrow::<DictionaryHostObject>(this).count
+}
+- (id)objectForKey:(id)key {
+    let host_obj: DictionaryHostObject = std::mem::take(env.objc.borrow_mut(this));
+    let res = host_obj.lookup(env, key);
+    *env.objc.borrow_mut(this) = host_obj;
+    res
+}
+
+- (())setObject:(id)object forKey:(id)key {
+    assert!(object != nil); // TODO: raise proper exception
+    assert!(key != nil); // TODO: raise proper exception
+    let mut host_obj: DictionaryHostObject = std::mem::take(env.objc.borrow_mut(this));
+    host_obj.insert(env, key, object, /* copy_key: */ true);
+    *env.objc.borrow_mut(this) = host_obj;
+}
+- (())removeObjectForKey:(id)key {
+    let mut host_obj: DictionaryHostObject = std::mem::take(env.objc.borrow_mut(this));
+    host_obj.remove(env, key);
+    *env.objc.borrow_mut(this) = host_obj;
+}
+- (())removeAllObjects {
+    std::mem::take(env.objc.borrow_mut::<DictionaryHostObject>(this)).release(env);
+}
+
+// NSCopying implementation (returns an immutable copy)
+- (id)copyWithZone:(MutVoidPtr)_zone {
+    let mut host_object = <DictionaryHostObject as Default>::default();
+    let keys: Vec<id> = env.objc.borrow::<DictionaryHostObject>(this).iter_keys().collect();
+    for key in keys {
+        let value: id = msg![env; this objectForKey:key];
+        host_object.insert(env, key, value, /* copy_key: */ false);
+    }
+    let new: id = msg_class![env; _touchHLE_NSDictionary alloc];
+    *env.objc.borrow_mut(new) = host_object;
+    new
+}
+
+@end
+
 };
+
+/// Shared implementation of `initWithContentsOfFile:` for our subclasses.
+fn init_with_contents_of_file(env: &mut Environment, this: id, path: id) -> id {
+    let Some(Value::Dictionary(dictionary)) = read_plist_file(env, path) else {
+        release(env, this);
+        return nil;
+    };
+    let mut host_object = <DictionaryHostObject as Default>::default();
+    for (key, value) in dictionary.iter() {
+        let value = from_plist_value(env, value);
+        if value == nil {
+            continue;
+        }
+        let key = from_rust_string(env, key.clone());
+        host_object.insert(env, key, value, /* copy_key: */ false);
+        release(env, key);
+        release(env, value);
+    }
+    *env.objc.borrow_mut(this) = host_object;
+    this
+}
+
+/// Shortcut for host code, roughly equivalent to
+/// `[[NSDictionary alloc] initWithObjects:forKeys:count:]`. The keys are
+/// copied and the objects are retained, as usual.
+pub fn dict_from_keys_and_objects(env: &mut Environment, keys_and_objects: &[(id, id)]) -> id {
+    let mut host_object = <DictionaryHostObject as Default>::default();
+    for &(key, object) in keys_and_objects {
+        host_object.insert(env, key, object, /* copy_key: */ true);
+    }
+    let new: id = msg_class![env; _touchHLE_NSDictionary alloc];
+    *env.objc.borrow_mut(new) = host_object;
+    new
+}
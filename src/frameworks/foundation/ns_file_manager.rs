@@ -5,11 +5,22 @@
  */
 //! `NSFileManager` etc.
 
-use super::{ns_array, ns_string, NSUInteger};
-use crate::dyld::{export_c_func, FunctionExports};
-use crate::objc::{autorelease, id};
+use super::ns_dictionary::dict_from_keys_and_objects;
+use super::ns_string::{get_static_str, to_rust_string};
+use super::{ns_array, ns_date, ns_string, set_error_nil, NSUInteger};
+use crate::dyld::{export_c_func, ConstantExports, FunctionExports, HostConstant};
+use crate::fs::GuestPathBuf;
+use crate::mem::MutPtr;
+use crate::objc::{
+    autorelease, id, msg, msg_class, nil, objc_classes, release, ClassExports, TrivialHostObject,
+};
 use crate::Environment;
 
+#[derive(Default)]
+pub struct State {
+    default_manager: Option<id>,
+}
+
 type NSSearchPathDirectory = NSUInteger;
 const NSDocumentDirectory: NSSearchPathDirectory = 9;
 
@@ -35,3 +46,155 @@ fn NSSearchPathForDirectoriesInDomains(
 
 pub const FUNCTIONS: FunctionExports =
     &[export_c_func!(NSSearchPathForDirectoriesInDomains(_, _, _))];
+
+// File attribute keys and values (NSString*)
+const NSFileType: &str = "NSFileType";
+const NSFileTypeDirectory: &str = "NSFileTypeDirectory";
+const NSFileTypeRegular: &str = "NSFileTypeRegular";
+const NSFileSize: &str = "NSFileSize";
+const NSFileModificationDate: &str = "NSFileModificationDate";
+
+pub const CONSTANTS: ConstantExports = &[
+    ("_NSFileType", HostConstant::NSString(NSFileType)),
+    (
+        "_NSFileTypeDirectory",
+        HostConstant::NSString(NSFileTypeDirectory),
+    ),
+    (
+        "_NSFileTypeRegular",
+        HostConstant::NSString(NSFileTypeRegular),
+    ),
+    ("_NSFileSize", HostConstant::NSString(NSFileSize)),
+    (
+        "_NSFileModificationDate",
+        HostConstant::NSString(NSFileModificationDate),
+    ),
+];
+
+pub const CLASSES: ClassExports = objc_classes! {
+
+(env, this, _cmd);
+
+@implementation NSFileManager: NSObject
+
++ (id)defaultManager {
+    if let Some(manager) = env.framework_state.foundation.ns_file_manager.default_manager {
+        manager
+    } else {
+        let new = env.objc.alloc_static_object(this, Box::new(TrivialHostObject), &mut env.mem);
+        env.framework_state.foundation.ns_file_manager.default_manager = Some(new);
+        new
+    }
+}
+
+- (bool)fileExistsAtPath:(id)path { // NSString*
+    let path = guest_path(env, path);
+    env.fs.exists(&path)
+}
+- (bool)fileExistsAtPath:(id)path // NSString*
+             isDirectory:(MutPtr<u8>)is_dir { // BOOL*
+    let path = guest_path(env, path);
+    if !env.fs.exists(&path) {
+        return false;
+    }
+    if !is_dir.is_null() {
+        let value = env.fs.is_dir(&path);
+        env.mem.write(is_dir, value.into());
+    }
+    true
+}
+
+- (id)contentsOfDirectoryAtPath:(id)path // NSString*
+                          error:(MutPtr<id>)error { // NSError**
+    let path = guest_path(env, path);
+    let Ok(names) = env.fs.read_dir(&path) else {
+        set_error_nil(env, error);
+        return nil;
+    };
+    let names = names
+        .into_iter()
+        .map(|name| ns_string::from_rust_string(env, name))
+        .collect();
+    let array = ns_array::from_vec(env, names);
+    autorelease(env, array)
+}
+
+- (bool)createDirectoryAtPath:(id)path // NSString*
+  withIntermediateDirectories:(bool)intermediates
+                   attributes:(id)attributes // NSDictionary*
+                        error:(MutPtr<id>)error { // NSError**
+    if attributes != nil {
+        log!("TODO: createDirectoryAtPath: attributes {:?} ignored", attributes);
+    }
+    let path = guest_path(env, path);
+    let result = if intermediates {
+        env.fs.create_dir_all(&path)
+    } else {
+        env.fs.create_dir(&path)
+    };
+    if result.is_err() {
+        set_error_nil(env, error);
+    }
+    result.is_ok()
+}
+// Deprecated, but still used by some apps.
+- (bool)createDirectoryAtPath:(id)path // NSString*
+                   attributes:(id)attributes { // NSDictionary*
+    msg![env; this createDirectoryAtPath:path
+             withIntermediateDirectories:false
+                              attributes:attributes
+                                   error:(MutPtr::<id>::null())]
+}
+
+- (bool)removeItemAtPath:(id)path // NSString*
+                   error:(MutPtr<id>)error { // NSError**
+    let path = guest_path(env, path);
+    let result = env.fs.remove(&path);
+    if result.is_err() {
+        set_error_nil(env, error);
+    }
+    result.is_ok()
+}
+
+- (id)attributesOfItemAtPath:(id)path // NSString*
+                       error:(MutPtr<id>)error { // NSError**
+    let path = guest_path(env, path);
+    let Ok(metadata) = env.fs.metadata(&path) else {
+        set_error_nil(env, error);
+        return nil;
+    };
+
+    // TODO: more attributes
+    let type_key = get_static_str(env, NSFileType);
+    let type_value = get_static_str(env, if metadata.is_dir {
+        NSFileTypeDirectory
+    } else {
+        NSFileTypeRegular
+    });
+    let size_key = get_static_str(env, NSFileSize);
+    let size_value: id = msg_class![env; NSNumber alloc];
+    let size_value: id = msg![env; size_value initWithUnsignedLongLong:(metadata.size)];
+    let mut keys_and_objects = vec![(type_key, type_value), (size_key, size_value)];
+    if let Some(modified) = metadata.modified {
+        let date_key = get_static_str(env, NSFileModificationDate);
+        let time_interval = ns_date::since_reference_date(modified);
+        let date_value: id = msg_class![env; NSDate alloc];
+        let date_value: id = msg![env; date_value
+                                  initWithTimeIntervalSinceReferenceDate:time_interval];
+        keys_and_objects.push((date_key, date_value));
+    }
+
+    let dict = dict_from_keys_and_objects(env, &keys_and_objects);
+    for (_key, object) in keys_and_objects {
+        release(env, object); // no-op for static strings
+    }
+    autorelease(env, dict)
+}
+
+@end
+
+};
+
+fn guest_path(env: &mut Environment, path: id) -> GuestPathBuf {
+    GuestPathBuf::from(to_rust_string(env, path).into_owned()) // TODO: avoid copy
+}
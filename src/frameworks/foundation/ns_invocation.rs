@@ -0,0 +1,361 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! `NSInvocation`, a message packaged as an object. This is also what message
+//! forwarding (`forwardInvocation:`) is built on.
+//!
+//! Resources:
+//! - Apple's [Objective-C Runtime Programming Guide](https://developer.apple.com/library/archive/documentation/Cocoa/Conceptual/ObjCRuntimeGuide/Articles/ocrtForwarding.html)
+//!   explains message forwarding.
+
+use super::ns_method_signature::{layouts, TypeKind, TypeLayout};
+use super::NSInteger;
+use crate::abi::{extend_stack_for_args, write_next_arg, GuestArg};
+use crate::cpu::Cpu;
+use crate::mem::{ConstPtr, ConstVoidPtr, MutVoidPtr, Ptr};
+use crate::objc::{
+    autorelease, id, msg, msg_class, msg_send_with_guest_args, nil, objc_classes, release, retain,
+    ClassExports, HostObject, SEL,
+};
+use crate::Environment;
+
+struct NSInvocationHostObject {
+    signature: id,
+    return_layout: TypeLayout,
+    argument_layouts: Vec<TypeLayout>,
+    /// The bytes of each argument. The first two are the target and the
+    /// selector.
+    arguments: Vec<Vec<u8>>,
+    return_value: Vec<u8>,
+    /// Set by `retainArguments`.
+    retains_arguments: bool,
+}
+impl HostObject for NSInvocationHostObject {}
+
+/// Split the words of a message's arguments, as passed in the registers and on
+/// the stack, into the bytes of each argument.
+fn unpack_arguments(words: &[u32], argument_layouts: &[TypeLayout]) -> Vec<Vec<u8>> {
+    let mut words = words;
+    argument_layouts
+        .iter()
+        .map(|layout| {
+            let (argument_words, rest) = words.split_at(layout.word_count());
+            words = rest;
+            bytes_from_words(argument_words, layout.size)
+        })
+        .collect()
+}
+
+/// Inverse of [unpack_arguments].
+fn pack_arguments(arguments: &[Vec<u8>]) -> Vec<u32> {
+    arguments
+        .iter()
+        .flat_map(|argument| words_from_bytes(argument))
+        .collect()
+}
+
+fn bytes_from_words(words: &[u32], size: u32) -> Vec<u8> {
+    let mut bytes: Vec<u8> = words.iter().flat_map(|word| word.to_le_bytes()).collect();
+    bytes.truncate(size as usize);
+    bytes
+}
+
+/// Pack bytes into words, padding the last one with zeroes.
+fn words_from_bytes(bytes: &[u8]) -> Vec<u32> {
+    bytes
+        .chunks(4)
+        .map(|chunk| {
+            let mut word = [0u8; 4];
+            word[..chunk.len()].copy_from_slice(chunk);
+            u32::from_le_bytes(word)
+        })
+        .collect()
+}
+
+fn object_from_bytes(bytes: &[u8]) -> id {
+    Ptr::from_bits(u32::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+/// Set an argument, taking care of retaining and releasing it if necessary.
+fn set_argument(env: &mut Environment, invocation: id, index: usize, bytes: Vec<u8>) {
+    let host_object = env.objc.borrow_mut::<NSInvocationHostObject>(invocation);
+    let layout = host_object.argument_layouts[index];
+    assert_eq!(bytes.len(), layout.size as usize);
+    let old = std::mem::replace(&mut host_object.arguments[index], bytes);
+    if host_object.retains_arguments && layout.kind == TypeKind::Object {
+        let new = object_from_bytes(&host_object.arguments[index]);
+        retain(env, new);
+        release(env, object_from_bytes(&old));
+    }
+}
+
+/// Package the arguments of a message that is being sent, which must still be
+/// in the registers and on the stack, into an invocation, hand it to the
+/// receiver's `forwardInvocation:` method, and then return the invocation's
+/// return value to the sender. For use by [crate::objc]'s message forwarding.
+pub fn forward_invocation(env: &mut Environment, receiver: id, signature: id) {
+    let (return_layout, argument_layouts) = layouts(env, signature);
+
+    // Methods that return a struct via a pointer get it before the receiver.
+    let stret = return_layout.is_stret();
+    let first_word = stret as usize;
+    let word_count: usize = argument_layouts.iter().map(|l| l.word_count()).sum();
+    let regs = env.cpu.regs();
+    let words: Vec<u32> = (first_word..first_word + word_count)
+        .map(|i| {
+            if i < 4 {
+                regs[i]
+            } else {
+                let stack_ptr: ConstPtr<u32> = Ptr::from_bits(regs[Cpu::SP]);
+                env.mem.read(stack_ptr + (i - 4).try_into().unwrap())
+            }
+        })
+        .collect();
+    let stret_ptr: MutVoidPtr = Ptr::from_bits(regs[0]);
+
+    let invocation: id = msg_class![env; NSInvocation invocationWithMethodSignature:signature];
+    let host_object = env.objc.borrow_mut::<NSInvocationHostObject>(invocation);
+    host_object.arguments = unpack_arguments(&words, &argument_layouts);
+
+    () = msg![env; receiver forwardInvocation:invocation];
+
+    let host_object = env.objc.borrow::<NSInvocationHostObject>(invocation);
+    let return_value = host_object.return_value.clone();
+    if stret {
+        env.mem
+            .bytes_at_mut(stret_ptr.cast(), return_layout.size)
+            .copy_from_slice(&return_value);
+    } else {
+        let return_words = words_from_bytes(&return_value);
+        env.cpu.regs_mut()[..return_words.len()].copy_from_slice(&return_words);
+    }
+}
+
+pub const CLASSES: ClassExports = objc_classes! {
+
+(env, this, _cmd);
+
+@implementation NSInvocation: NSObject
+
++ (id)invocationWithMethodSignature:(id)signature { // NSMethodSignature*
+    let (return_layout, argument_layouts) = layouts(env, signature);
+    let host_object = Box::new(NSInvocationHostObject {
+        signature: retain(env, signature),
+        return_layout,
+        arguments: argument_layouts
+            .iter()
+            .map(|layout| vec![0; layout.size as usize])
+            .collect(),
+        argument_layouts,
+        return_value: vec![0; return_layout.size as usize],
+        retains_arguments: false,
+    });
+    let new = env.objc.alloc_object(this, host_object, &mut env.mem);
+    autorelease(env, new)
+}
+
+- (())dealloc {
+    let host_object = env.objc.borrow::<NSInvocationHostObject>(this);
+    let signature = host_object.signature;
+    let retained: Vec<id> = if host_object.retains_arguments {
+        host_object
+            .arguments
+            .iter()
+            .zip(host_object.argument_layouts.iter())
+            .filter(|&(_, layout)| layout.kind == TypeKind::Object)
+            .map(|(bytes, _)| object_from_bytes(bytes))
+            .collect()
+    } else {
+        Vec::new()
+    };
+    for object in retained {
+        release(env, object);
+    }
+    release(env, signature);
+    env.objc.dealloc_object(this, &mut env.mem)
+}
+
+- (id)methodSignature {
+    env.objc.borrow::<NSInvocationHostObject>(this).signature
+}
+
+- (())retainArguments {
+    let host_object = env.objc.borrow_mut::<NSInvocationHostObject>(this);
+    if host_object.retains_arguments {
+        return;
+    }
+    host_object.retains_arguments = true;
+    // TODO: C string arguments should be copied too.
+    let objects: Vec<id> = host_object
+        .arguments
+        .iter()
+        .zip(host_object.argument_layouts.iter())
+        .filter(|&(_, layout)| layout.kind == TypeKind::Object)
+        .map(|(bytes, _)| object_from_bytes(bytes))
+        .collect();
+    for object in objects {
+        retain(env, object);
+    }
+}
+- (bool)argumentsRetained {
+    env.objc.borrow::<NSInvocationHostObject>(this).retains_arguments
+}
+
+- (id)target {
+    object_from_bytes(&env.objc.borrow::<NSInvocationHostObject>(this).arguments[0])
+}
+- (())setTarget:(id)target {
+    set_argument(env, this, 0, target.to_bits().to_le_bytes().to_vec())
+}
+
+- (SEL)selector {
+    let bytes = &env.objc.borrow::<NSInvocationHostObject>(this).arguments[1];
+    SEL::from_regs(&[u32::from_le_bytes(bytes[..].try_into().unwrap())])
+}
+- (())setSelector:(SEL)selector {
+    let mut word = [0];
+    selector.to_regs(&mut word);
+    set_argument(env, this, 1, word[0].to_le_bytes().to_vec())
+}
+
+- (())getArgument:(MutVoidPtr)buffer
+          atIndex:(NSInteger)index {
+    let host_object = env.objc.borrow::<NSInvocationHostObject>(this);
+    let bytes = &host_object.arguments[index as usize];
+    env.mem
+        .bytes_at_mut(buffer.cast(), bytes.len() as u32)
+        .copy_from_slice(bytes);
+}
+- (())setArgument:(ConstVoidPtr)buffer
+          atIndex:(NSInteger)index {
+    let index = index as usize;
+    let size = env.objc.borrow::<NSInvocationHostObject>(this).argument_layouts[index].size;
+    let bytes = env.mem.bytes_at(buffer.cast(), size).to_vec();
+    set_argument(env, this, index, bytes)
+}
+
+- (())getReturnValue:(MutVoidPtr)buffer {
+    let host_object = env.objc.borrow::<NSInvocationHostObject>(this);
+    let bytes = &host_object.return_value;
+    env.mem
+        .bytes_at_mut(buffer.cast(), bytes.len() as u32)
+        .copy_from_slice(bytes);
+}
+- (())setReturnValue:(ConstVoidPtr)buffer {
+    let host_object = env.objc.borrow::<NSInvocationHostObject>(this);
+    let size = host_object.return_layout.size;
+    let bytes = env.mem.bytes_at(buffer.cast(), size).to_vec();
+    env.objc.borrow_mut::<NSInvocationHostObject>(this).return_value = bytes;
+}
+
+- (())invoke {
+    let target: id = msg![env; this target];
+    msg![env; this invokeWithTarget:target]
+}
+
+- (())invokeWithTarget:(id)target {
+    if target == nil {
+        // Like a message to nil, this does nothing.
+        return;
+    }
+    let host_object = env.objc.borrow::<NSInvocationHostObject>(this);
+    let return_layout = host_object.return_layout;
+    let mut arguments = host_object.arguments.clone();
+    arguments[0] = target.to_bits().to_le_bytes().to_vec();
+
+    let stret = return_layout.is_stret();
+    let stret_ptr = if stret {
+        env.mem.alloc(return_layout.size)
+    } else {
+        Ptr::null()
+    };
+    let mut words = pack_arguments(&arguments);
+    if stret {
+        words.insert(0, stret_ptr.to_bits());
+    }
+
+    let old_sp = extend_stack_for_args(words.len(), env.cpu.regs_mut());
+    let mut reg_offset = 0;
+    for word in words {
+        write_next_arg(&mut reg_offset, env.cpu.regs_mut(), &mut env.mem, word);
+    }
+    msg_send_with_guest_args(env, stret);
+    env.cpu.regs_mut()[Cpu::SP] = old_sp;
+
+    let return_value = if stret {
+        let bytes = env.mem.bytes_at(stret_ptr.cast(), return_layout.size).to_vec();
+        env.mem.free(stret_ptr);
+        bytes
+    } else {
+        bytes_from_words(&env.cpu.regs()[..2], return_layout.size)
+    };
+    env.objc.borrow_mut::<NSInvocationHostObject>(this).return_value = return_value;
+}
+
+@end
+
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layout(size: u32, kind: TypeKind) -> TypeLayout {
+        TypeLayout {
+            size,
+            align: size.clamp(1, 4),
+            kind,
+        }
+    }
+
+    #[test]
+    fn proxy_relays_arguments() {
+        // -[Backing setChar:(char)c double:(double)d rect:(CGRect)r]
+        let argument_layouts = [
+            layout(4, TypeKind::Object),
+            layout(4, TypeKind::Other),
+            layout(1, TypeKind::Other),
+            layout(8, TypeKind::Other),
+            layout(16, TypeKind::Aggregate),
+        ];
+        let proxy: u32 = 0x1000;
+        let backing: u32 = 0x2000;
+        let sel: u32 = 0x3000;
+        let rect = [1.0f32, 2.0, 3.0, 4.0].map(f32::to_bits);
+        let double = 5.0f64.to_bits();
+        let sent = [
+            proxy,
+            sel,
+            b'x'.into(),
+            double as u32,
+            (double >> 32) as u32,
+            rect[0],
+            rect[1],
+            rect[2],
+            rect[3],
+        ];
+
+        // What the runtime captures when the proxy doesn't recognize the
+        // selector.
+        let mut arguments = unpack_arguments(&sent, &argument_layouts);
+        assert_eq!(arguments.len(), 5);
+        assert_eq!(object_from_bytes(&arguments[0]).to_bits(), proxy);
+        assert_eq!(arguments[2], vec![b'x']);
+        assert_eq!(arguments[3], 5.0f64.to_le_bytes());
+
+        // What -[proxy forwardInvocation:] does before invoking the
+        // invocation with the backing object as the target.
+        arguments[0] = backing.to_le_bytes().to_vec();
+        let relayed = pack_arguments(&arguments);
+        assert_eq!(relayed[0], backing);
+        assert_eq!(relayed[1..], sent[1..]);
+
+        // Return values come back the same way.
+        let return_value = bytes_from_words(&[0x12345678, 0x9abcdef0], 8);
+        assert_eq!(words_from_bytes(&return_value), [0x12345678, 0x9abcdef0]);
+        assert_eq!(bytes_from_words(&[0xff], 1), [0xff]);
+        assert_eq!(words_from_bytes(&[0xff, 0x01]), [0x1ff]);
+    }
+}
@@ -0,0 +1,298 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! `NSMethodSignature`, and parsing of Objective-C type encodings.
+//!
+//! Resources:
+//! - Apple's [Type Encodings](https://developer.apple.com/library/archive/documentation/Cocoa/Conceptual/ObjCRuntimeGuide/Articles/ocrtTypeEncodings.html)
+
+use super::NSUInteger;
+use crate::mem::{ConstPtr, GuestUSize, MutVoidPtr};
+use crate::objc::{autorelease, id, msg, objc_classes, ClassExports, HostObject};
+use crate::Environment;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(super) enum TypeKind {
+    /// An object pointer (`@`), which `retainArguments` cares about.
+    Object,
+    /// A struct, union or array.
+    Aggregate,
+    Other,
+}
+
+/// The parts of a type that matter for passing it around: size, alignment
+/// and kind.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(super) struct TypeLayout {
+    pub(super) size: GuestUSize,
+    pub(super) align: GuestUSize,
+    pub(super) kind: TypeKind,
+}
+impl TypeLayout {
+    /// Number of argument words (registers or stack slots) a value of this
+    /// type takes up.
+    pub(super) fn word_count(self) -> usize {
+        self.size.div_ceil(4) as usize
+    }
+
+    /// Whether a method returning this type returns it via a pointer, and must
+    /// therefore be called with `objc_msgSend_stret`.
+    pub(super) fn is_stret(self) -> bool {
+        self.kind == TypeKind::Aggregate && self.size > 4
+    }
+}
+
+fn align_up(value: GuestUSize, align: GuestUSize) -> GuestUSize {
+    value.next_multiple_of(align)
+}
+
+/// Parse a single type at the start of a type encoding, returning its layout
+/// and the rest of the encoding.
+fn parse_type(types: &[u8]) -> (TypeLayout, &[u8]) {
+    // Qualifiers like `const` (r) and `out` (o) don't affect the layout.
+    let start = types
+        .iter()
+        .position(|c| !b"rnNoORV".contains(c))
+        .unwrap_or(types.len());
+    let (&c, rest) = types[start..]
+        .split_first()
+        .expect("Truncated type encoding");
+
+    // 64-bit types are only 4-byte aligned on this platform.
+    let simple = |size: GuestUSize, kind| TypeLayout {
+        size,
+        align: size.clamp(1, 4),
+        kind,
+    };
+    let pointer = simple(4, TypeKind::Other);
+
+    match c {
+        b'v' => (simple(0, TypeKind::Other), rest),
+        b'c' | b'C' | b'B' => (simple(1, TypeKind::Other), rest),
+        b's' | b'S' => (simple(2, TypeKind::Other), rest),
+        b'i' | b'I' | b'l' | b'L' | b'f' | b'*' | b'#' | b':' | b'?' => (pointer, rest),
+        b'q' | b'Q' | b'd' => (simple(8, TypeKind::Other), rest),
+        b'@' => {
+            // Blocks are `@?`, and objects may have a class name: `@"Foo"`.
+            let rest = match rest {
+                [b'?', rest @ ..] => rest,
+                [b'"', rest @ ..] => {
+                    let end = rest.iter().position(|&c| c == b'"').unwrap();
+                    &rest[end + 1..]
+                }
+                _ => rest,
+            };
+            (simple(4, TypeKind::Object), rest)
+        }
+        b'^' => {
+            let (_pointee, rest) = parse_type(rest);
+            (pointer, rest)
+        }
+        b'[' => {
+            let digits = rest.iter().position(|c| !c.is_ascii_digit()).unwrap();
+            let count: GuestUSize = std::str::from_utf8(&rest[..digits])
+                .unwrap()
+                .parse()
+                .unwrap();
+            let (element, rest) = parse_type(&rest[digits..]);
+            let rest = rest
+                .strip_prefix(b"]")
+                .expect("Unterminated array type encoding");
+            let layout = TypeLayout {
+                size: element.size * count,
+                align: element.align,
+                kind: TypeKind::Aggregate,
+            };
+            (layout, rest)
+        }
+        b'{' | b'(' => {
+            let is_union = c == b'(';
+            let close = if is_union { b')' } else { b'}' };
+            let name_end = rest.iter().position(|&c| c == b'=' || c == close).unwrap();
+            let mut rest = &rest[name_end..];
+            let mut size = 0;
+            let mut align = 1;
+            // Opaque structs like `{__CFString}` have no field list.
+            if rest[0] == b'=' {
+                rest = &rest[1..];
+                while rest[0] != close {
+                    let (field, field_rest) = parse_type(rest);
+                    size = if is_union {
+                        size.max(field.size)
+                    } else {
+                        align_up(size, field.align) + field.size
+                    };
+                    align = align.max(field.align);
+                    rest = field_rest;
+                }
+            }
+            let layout = TypeLayout {
+                size: align_up(size, align),
+                align,
+                kind: TypeKind::Aggregate,
+            };
+            (layout, &rest[1..])
+        }
+        _ => panic!("Unhandled type encoding {:?}", c as char),
+    }
+}
+
+/// Parse a method's type encoding string (as found in the app binary or given
+/// to `class_addMethod`) into the encodings and layouts of the return type,
+/// followed by those of each argument.
+fn parse_method_types(types: &[u8]) -> Vec<(&[u8], TypeLayout)> {
+    let mut parsed = Vec::new();
+    let mut types = types;
+    while !types.is_empty() {
+        let (layout, rest) = parse_type(types);
+        parsed.push((&types[..types.len() - rest.len()], layout));
+        // Each type may be followed by its stack frame offset, which is
+        // meaningless to us.
+        let offset_len = rest
+            .iter()
+            .position(|&c| !(c.is_ascii_digit() || c == b'-'))
+            .unwrap_or(rest.len());
+        types = &rest[offset_len..];
+    }
+    parsed
+}
+
+struct NSMethodSignatureHostObject {
+    /// The return type, followed by the argument types (including the
+    /// receiver and selector). The encoding strings are in guest memory so
+    /// that they can be returned by `getArgumentTypeAtIndex:` etc.
+    types: Vec<(ConstPtr<u8>, TypeLayout)>,
+}
+impl HostObject for NSMethodSignatureHostObject {}
+
+/// Get the layouts of a signature's return type and of its argument types
+/// (including the receiver and selector). For use by `NSInvocation`.
+pub(super) fn layouts(env: &Environment, signature: id) -> (TypeLayout, Vec<TypeLayout>) {
+    let host_object = env.objc.borrow::<NSMethodSignatureHostObject>(signature);
+    let mut layouts = host_object.types.iter().map(|&(_, layout)| layout);
+    let return_layout = layouts.next().unwrap();
+    (return_layout, layouts.collect())
+}
+
+pub const CLASSES: ClassExports = objc_classes! {
+
+(env, this, _cmd);
+
+@implementation NSMethodSignature: NSObject
+
++ (id)allocWithZone:(MutVoidPtr)_zone {
+    let host_object = Box::new(NSMethodSignatureHostObject { types: Vec::new() });
+    env.objc.alloc_object(this, host_object, &mut env.mem)
+}
+
++ (id)signatureWithObjCTypes:(ConstPtr<u8>)types {
+    let types = env.mem.cstr_at(types).to_vec();
+    let types = parse_method_types(&types)
+        .into_iter()
+        .map(|(encoding, layout)| {
+            (env.mem.alloc_and_write_cstr(encoding).cast_const(), layout)
+        })
+        .collect();
+    let new: id = msg![env; this alloc];
+    env.objc.borrow_mut::<NSMethodSignatureHostObject>(new).types = types;
+    autorelease(env, new)
+}
+
+- (())dealloc {
+    let types = std::mem::take(&mut env.objc.borrow_mut::<NSMethodSignatureHostObject>(this).types);
+    for (encoding, _) in types {
+        env.mem.free(encoding.cast_mut().cast());
+    }
+    env.objc.dealloc_object(this, &mut env.mem)
+}
+
+- (NSUInteger)numberOfArguments {
+    let host_object = env.objc.borrow::<NSMethodSignatureHostObject>(this);
+    (host_object.types.len() - 1).try_into().unwrap()
+}
+
+- (ConstPtr<u8>)getArgumentTypeAtIndex:(NSUInteger)index {
+    let host_object = env.objc.borrow::<NSMethodSignatureHostObject>(this);
+    host_object.types[index as usize + 1].0
+}
+
+- (ConstPtr<u8>)methodReturnType {
+    env.objc.borrow::<NSMethodSignatureHostObject>(this).types[0].0
+}
+
+- (NSUInteger)methodReturnLength {
+    env.objc.borrow::<NSMethodSignatureHostObject>(this).types[0].1.size
+}
+
+- (NSUInteger)frameLength {
+    let (_, argument_layouts) = layouts(env, this);
+    let words: usize = argument_layouts.iter().map(|layout| layout.word_count()).sum();
+    (words * 4).try_into().unwrap()
+}
+
+@end
+
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sizes(types: &str) -> Vec<(&str, GuestUSize)> {
+        parse_method_types(types.as_bytes())
+            .into_iter()
+            .map(|(encoding, layout)| (std::str::from_utf8(encoding).unwrap(), layout.size))
+            .collect()
+    }
+
+    #[test]
+    fn method_type_encodings() {
+        assert_eq!(
+            sizes("v12@0:4i8"),
+            vec![("v", 0), ("@", 4), (":", 4), ("i", 4)]
+        );
+        assert_eq!(
+            sizes("@24@0:4r^{__CFString}8d12c20"),
+            vec![
+                ("@", 4),
+                ("@", 4),
+                (":", 4),
+                ("r^{__CFString}", 4),
+                ("d", 8),
+                ("c", 1)
+            ]
+        );
+        // Encodings without offsets, as used with signatureWithObjCTypes:.
+        assert_eq!(
+            sizes("{CGRect={CGPoint=ff}{CGSize=ff}}@:@?"),
+            vec![
+                ("{CGRect={CGPoint=ff}{CGSize=ff}}", 16),
+                ("@", 4),
+                (":", 4),
+                ("@?", 4)
+            ]
+        );
+    }
+
+    #[test]
+    fn aggregate_layouts() {
+        let layout = |types: &str| parse_type(types.as_bytes()).0;
+
+        // Fields are padded to their alignment, and 64-bit types are only
+        // 4-byte aligned.
+        let mixed = layout("{Mixed=csiqc}");
+        assert_eq!((mixed.size, mixed.align), (20, 4));
+        assert!(mixed.is_stret());
+        let small = layout("{Small=cc}");
+        assert_eq!((small.size, small.align), (2, 1));
+        assert!(!small.is_stret());
+        assert!(!layout("d").is_stret());
+
+        assert_eq!(layout("(Either=cid)").size, 8);
+        assert_eq!(layout("[3s]").size, 6);
+        assert_eq!(layout("{Padded=[3c]i}").size, 8);
+        assert_eq!(layout("@\"NSString\"").kind, TypeKind::Object);
+    }
+}
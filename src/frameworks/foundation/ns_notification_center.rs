@@ -0,0 +1,288 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! `NSNotificationCenter` and `NSNotification`.
+//!
+//! Notifications are dispatched synchronously on the posting thread, which is
+//! what the default center does on iPhone OS.
+
+use super::ns_string::{get_static_str, to_rust_string};
+use crate::mem::MutVoidPtr;
+use crate::objc::{
+    autorelease, id, msg, msg_class, msg_send, nil, objc_classes, release, retain, ClassExports,
+    HostObject, SEL,
+};
+use crate::Environment;
+
+#[derive(Default)]
+pub struct State {
+    default_center: Option<id>,
+}
+
+/// An observer registered with `addObserver:selector:name:object:`.
+struct Observation {
+    /// Weak reference
+    observer: id,
+    selector: SEL,
+    /// [None] means notifications with any name are matched.
+    name: Option<String>,
+    /// Weak reference. `nil` means notifications from any object are matched.
+    object: id,
+}
+impl Observation {
+    fn matches(&self, name: &str, object: id) -> bool {
+        (self.name.is_none() || self.name.as_deref() == Some(name))
+            && (self.object == nil || self.object == object)
+    }
+}
+
+#[derive(Default)]
+struct NSNotificationCenterHostObject {
+    /// In order of registration, which is the order observers are notified in.
+    observations: Vec<Observation>,
+}
+impl HostObject for NSNotificationCenterHostObject {}
+impl NSNotificationCenterHostObject {
+    /// Remove the observations of `observer` that match the `name` and `object`
+    /// filters, where [None] and `nil` match everything.
+    fn remove(&mut self, observer: id, name: Option<&str>, object: id) {
+        self.observations.retain(|observation| {
+            !(observation.observer == observer
+                && (name.is_none() || observation.name.as_deref() == name)
+                && (object == nil || observation.object == object))
+        });
+    }
+    /// Get the observers that should receive a notification, and the
+    /// selectors to send them.
+    fn observers_for(&self, name: &str, object: id) -> Vec<(id, SEL)> {
+        self.observations
+            .iter()
+            .filter(|observation| observation.matches(name, object))
+            .map(|observation| (observation.observer, observation.selector))
+            .collect()
+    }
+}
+
+struct NSNotificationHostObject {
+    /// `NSString*`
+    name: id,
+    object: id,
+    /// `NSDictionary*`
+    user_info: id,
+}
+impl HostObject for NSNotificationHostObject {}
+
+pub const CLASSES: ClassExports = objc_classes! {
+
+(env, this, _cmd);
+
+@implementation NSNotification: NSObject
+
++ (id)allocWithZone:(MutVoidPtr)_zone {
+    let host_object = Box::new(NSNotificationHostObject {
+        name: nil,
+        object: nil,
+        user_info: nil,
+    });
+    env.objc.alloc_object(this, host_object, &mut env.mem)
+}
+
++ (id)notificationWithName:(id)name // NSString*
+                    object:(id)object {
+    msg![env; this notificationWithName:name object:object userInfo:nil]
+}
++ (id)notificationWithName:(id)name // NSString*
+                    object:(id)object
+                  userInfo:(id)user_info { // NSDictionary*
+    let new: id = msg![env; this alloc];
+    let new: id = msg![env; new initWithName:name object:object userInfo:user_info];
+    autorelease(env, new)
+}
+
+- (id)initWithName:(id)name // NSString*
+            object:(id)object
+          userInfo:(id)user_info { // NSDictionary*
+    let name: id = msg![env; name copy];
+    retain(env, object);
+    retain(env, user_info);
+    *env.objc.borrow_mut(this) = NSNotificationHostObject {
+        name,
+        object,
+        user_info,
+    };
+    this
+}
+
+- (())dealloc {
+    let &NSNotificationHostObject { name, object, user_info } = env.objc.borrow(this);
+    release(env, name);
+    release(env, object);
+    release(env, user_info);
+    env.objc.dealloc_object(this, &mut env.mem)
+}
+
+- (id)name {
+    env.objc.borrow::<NSNotificationHostObject>(this).name
+}
+- (id)object {
+    env.objc.borrow::<NSNotificationHostObject>(this).object
+}
+- (id)userInfo {
+    env.objc.borrow::<NSNotificationHostObject>(this).user_info
+}
+
+@end
+
+@implementation NSNotificationCenter: NSObject
+
++ (id)allocWithZone:(MutVoidPtr)_zone {
+    let host_object = Box::<NSNotificationCenterHostObject>::default();
+    env.objc.alloc_object(this, host_object, &mut env.mem)
+}
+
++ (id)defaultCenter {
+    if let Some(center) = env.framework_state.foundation.ns_notification_center.default_center {
+        center
+    } else {
+        let host_object = Box::<NSNotificationCenterHostObject>::default();
+        let new = env.objc.alloc_static_object(this, host_object, &mut env.mem);
+        env.framework_state.foundation.ns_notification_center.default_center = Some(new);
+        new
+    }
+}
+
+- (())addObserver:(id)observer
+         selector:(SEL)selector
+             name:(id)name // NSString*
+           object:(id)object {
+    let name = (name != nil).then(|| to_rust_string(env, name).into_owned());
+    log_dbg!(
+        "[{:?} addObserver:{:?} selector:{} name:{:?} object:{:?}]",
+        this,
+        observer,
+        selector.as_str(&env.mem),
+        name,
+        object,
+    );
+    let host_object = env.objc.borrow_mut::<NSNotificationCenterHostObject>(this);
+    host_object.observations.push(Observation {
+        observer,
+        selector,
+        name,
+        object,
+    });
+}
+
+- (())removeObserver:(id)observer {
+    let host_object = env.objc.borrow_mut::<NSNotificationCenterHostObject>(this);
+    host_object.remove(observer, None, nil);
+}
+- (())removeObserver:(id)observer
+                name:(id)name // NSString*
+              object:(id)object {
+    let name = (name != nil).then(|| to_rust_string(env, name));
+    let host_object = env.objc.borrow_mut::<NSNotificationCenterHostObject>(this);
+    host_object.remove(observer, name.as_deref(), object);
+}
+
+- (())postNotification:(id)notification { // NSNotification*
+    let name: id = msg![env; notification name];
+    let object: id = msg![env; notification object];
+    let name = to_rust_string(env, name);
+    let observers = env
+        .objc
+        .borrow::<NSNotificationCenterHostObject>(this)
+        .observers_for(&name, object);
+    log_dbg!(
+        "Posting notification {:?} ({:?}) to {} observer(s)",
+        notification,
+        name,
+        observers.len()
+    );
+    // Observers might be added or removed while this is happening, but that
+    // affects only later posts.
+    for (observer, selector) in observers {
+        let _: () = msg_send(env, (observer, selector, notification));
+    }
+}
+- (())postNotificationName:(id)name // NSString*
+                    object:(id)object {
+    msg![env; this postNotificationName:name object:object userInfo:nil]
+}
+- (())postNotificationName:(id)name // NSString*
+                    object:(id)object
+                  userInfo:(id)user_info { // NSDictionary*
+    let notification: id = msg_class![env; NSNotification alloc];
+    let notification: id = msg![env; notification initWithName:name
+                                                        object:object
+                                                      userInfo:user_info];
+    let _: () = msg![env; this postNotification:notification];
+    release(env, notification);
+}
+
+@end
+
+};
+
+/// Shortcut for host code: post a notification with a constant name and no
+/// `userInfo` to the default center.
+pub fn post_notification(env: &mut Environment, name: &'static str, object: id) {
+    let name = get_static_str(env, name);
+    let center: id = msg_class![env; NSNotificationCenter defaultCenter];
+    msg![env; center postNotificationName:name object:object]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::abi::GuestArg;
+
+    #[test]
+    fn matching_observers() {
+        let [observer, other_observer, sender, other_sender] =
+            [0x100, 0x200, 0x300, 0x400].map(id::from_bits);
+        let [selector, other_selector] = [0x1000, 0x2000].map(|bits| SEL::from_regs(&[bits]));
+
+        let mut center = NSNotificationCenterHostObject::default();
+        center.observations.push(Observation {
+            observer,
+            selector,
+            name: Some("LevelDidEnd".to_string()),
+            object: sender,
+        });
+        center.observations.push(Observation {
+            observer: other_observer,
+            selector: other_selector,
+            name: None,
+            object: nil,
+        });
+
+        assert_eq!(
+            center.observers_for("LevelDidEnd", sender),
+            [(observer, selector), (other_observer, other_selector)]
+        );
+        // Non-matching name or object
+        assert_eq!(
+            center.observers_for("LevelDidStart", sender),
+            [(other_observer, other_selector)]
+        );
+        assert_eq!(
+            center.observers_for("LevelDidEnd", other_sender),
+            [(other_observer, other_selector)]
+        );
+
+        center.remove(other_observer, Some("LevelDidEnd"), nil);
+        assert_eq!(center.observations.len(), 2);
+        center.remove(other_observer, None, nil);
+        assert_eq!(
+            center.observers_for("LevelDidEnd", sender),
+            [(observer, selector)]
+        );
+        center.remove(observer, None, other_sender);
+        assert_eq!(center.observations.len(), 1);
+        center.remove(observer, None, nil);
+        assert!(center.observers_for("LevelDidEnd", sender).is_empty());
+    }
+}
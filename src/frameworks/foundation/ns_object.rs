@@ -14,12 +14,21 @@
 //!
 //! See also: [crate::objc], especially the `objects` module.
 
-use super::ns_string::to_rust_string;
+use super::ns_run_loop;
+use super::ns_string::{from_rust_string, to_rust_string};
 use super::NSUInteger;
 use crate::mem::MutVoidPtr;
 use crate::objc::{
-    id, msg, msg_class, msg_send, objc_classes, Class, ClassExports, ObjC, TrivialHostObject,
+    autorelease, id, msg, msg_class, msg_send, nil, objc_classes, remove_associated_objects, Class,
+    ClassExports, ObjC, TrivialHostObject, SEL,
 };
+use crate::Environment;
+
+/// Get an `NSMethodSignature` for a class's method, if its types are known.
+fn method_signature(env: &mut Environment, class: Class, selector: SEL) -> id {
+    let Some(types) = env.objc.get_method_types(class, selector) else { return nil; };
+    msg_class![env; NSMethodSignature signatureWithObjCTypes:types]
+}
 
 pub const CLASSES: ClassExports = objc_classes! {
 
@@ -44,6 +53,18 @@ pub const CLASSES: ClassExports = objc_classes! {
     this
 }
 
++ (bool)instancesRespondToSelector:(SEL)selector {
+    env.objc.class_has_method(this, selector)
+}
+
++ (id)instanceMethodSignatureForSelector:(SEL)selector { // NSMethodSignature*
+    method_signature(env, this, selector)
+}
++ (id)methodSignatureForSelector:(SEL)selector { // NSMethodSignature*
+    let metaclass = ObjC::read_isa(this, &env.mem);
+    method_signature(env, metaclass, selector)
+}
+
 // See the instance method section for the normal versions of these.
 + (id)retain {
     this // classes are not refcounted
@@ -69,6 +90,9 @@ pub const CLASSES: ClassExports = objc_classes! {
     log_dbg!("[{:?} release]", this);
     if env.objc.decrement_refcount(this) {
         () = msg![env; this dealloc];
+        // Apple's runtime does this during deallocation, but many of our
+        // dealloc implementations call dealloc_object() directly.
+        remove_associated_objects(env, this);
     }
 }
 - (id)autorelease {
@@ -92,6 +116,36 @@ pub const CLASSES: ClassExports = objc_classes! {
     let this_class: Class = msg![env; this class];
     env.objc.class_is_subclass_of(this_class, class)
 }
+- (bool)respondsToSelector:(SEL)selector {
+    let this_class: Class = msg![env; this class];
+    env.objc.class_has_method(this_class, selector)
+}
+
+// Message forwarding: see objc_msgSend() and NSInvocation.
+- (id)methodSignatureForSelector:(SEL)selector { // NSMethodSignature*
+    let class = ObjC::read_isa(this, &env.mem);
+    method_signature(env, class, selector)
+}
+- (())forwardInvocation:(id)invocation { // NSInvocation*
+    let selector: SEL = msg![env; invocation selector];
+    msg![env; this doesNotRecognizeSelector:selector]
+}
+- (())doesNotRecognizeSelector:(SEL)selector {
+    let class = ObjC::read_isa(this, &env.mem);
+    panic!(
+        "{:?} (class \"{}\") does not recognize selector \"{}\"!",
+        this,
+        env.objc.get_class_name(class),
+        selector.as_str(&env.mem)
+    );
+}
+
+- (id)description { // NSString*
+    let class: Class = msg![env; this class];
+    let description = format!("<{}: {:?}>", env.objc.get_class_name(class), this);
+    let description = from_rust_string(env, description);
+    autorelease(env, description)
+}
 
 - (NSUInteger)hash {
     this.to_bits()
@@ -105,6 +159,29 @@ pub const CLASSES: ClassExports = objc_classes! {
     msg![env; this copyWithZone:(MutVoidPtr::null())]
 }
 
+- (())performSelectorOnMainThread:(SEL)selector
+                       withObject:(id)object
+                    waitUntilDone:(bool)wait {
+    if wait && env.current_thread == 0 {
+        // Waiting on the main thread would deadlock, so Apple's
+        // implementation sends the message immediately in this case.
+        return msg_send(env, (this, selector, object));
+    }
+
+    log_dbg!(
+        "Thread {} queueing {:?} message to {:?} for main thread{}",
+        env.current_thread,
+        selector.as_str(&env.mem),
+        this,
+        if wait { " and waiting" } else { "" },
+    );
+    let run_loop: id = msg_class![env; NSRunLoop mainRunLoop];
+    let waiting_thread = wait.then_some(env.current_thread);
+    ns_run_loop::add_pending_perform(env, run_loop, this, selector, object, waiting_thread);
+    if wait {
+        env.block_current_thread();
+    }
+}
 
 // NSKeyValueCoding
 - (())setValue:(id)value
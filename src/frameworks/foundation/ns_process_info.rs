@@ -6,8 +6,8 @@
 //! `NSProcessInfo`.
 
 use super::NSTimeInterval;
+use crate::libc::time::monotonic_since_startup;
 use crate::objc::{objc_classes, ClassExports};
-use std::time::Instant;
 
 pub const CLASSES: ClassExports = objc_classes! {
 
@@ -16,7 +16,7 @@ pub const CLASSES: ClassExports = objc_classes! {
 @implementation NSProcessInfo: NSObject
 
 + (NSTimeInterval)systemUptime {
-    Instant::now().duration_since(env.startup_time).as_secs_f64()
+    monotonic_since_startup(env).as_secs_f64()
 }
 
 @end
@@ -0,0 +1,247 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! Conversion between property list objects and [plist::Value], and reading
+//! and writing of property list files.
+//!
+//! Property lists can only contain `NSString`, `NSNumber`, `NSData`, `NSDate`,
+//! `NSArray` and `NSDictionary` objects. This module is the equivalent of
+//! `NSPropertyListSerialization`, which is not itself implemented yet.
+
+use super::ns_dictionary::{dict_from_keys_and_objects, DictionaryHostObject};
+use super::ns_string::{from_rust_string, to_rust_string};
+use super::ns_value::NSNumberHostObject;
+use super::{ns_array, ns_data, ns_date, NSTimeInterval, NSUInteger};
+use crate::fs::{GuestOpenOptions, GuestPath};
+use crate::objc::{id, msg, msg_class, nil, release, Class};
+use crate::Environment;
+use plist::{Dictionary, Value};
+use std::io::{Cursor, Write};
+
+/// Convert a property list object to a [plist::Value]. Returns [None] if the
+/// object (or anything it contains) is not a property list object.
+pub fn to_plist_value(env: &mut Environment, object: id) -> Option<Value> {
+    let string_class: Class = msg_class![env; NSString class];
+    let number_class: Class = msg_class![env; NSNumber class];
+    let data_class: Class = msg_class![env; NSData class];
+    let date_class: Class = msg_class![env; NSDate class];
+    let array_class: Class = msg_class![env; NSArray class];
+    let dictionary_class: Class = msg_class![env; NSDictionary class];
+
+    let value = if msg![env; object isKindOfClass:string_class] {
+        Value::String(to_rust_string(env, object).into_owned())
+    } else if msg![env; object isKindOfClass:number_class] {
+        match *env.objc.borrow::<NSNumberHostObject>(object) {
+            NSNumberHostObject::Bool(value) => Value::Boolean(value),
+            NSNumberHostObject::LongLong(value) => Value::Integer(value.into()),
+            NSNumberHostObject::UnsignedLongLong(value) => Value::Integer(value.into()),
+            NSNumberHostObject::Float(value) => Value::Real(value.into()),
+            NSNumberHostObject::Double(value) => Value::Real(value),
+        }
+    } else if msg![env; object isKindOfClass:data_class] {
+        Value::Data(ns_data::to_rust_slice(env, object).to_vec())
+    } else if msg![env; object isKindOfClass:date_class] {
+        let interval: NSTimeInterval = msg![env; object timeIntervalSinceReferenceDate];
+        Value::Date(ns_date::to_system_time(interval).into())
+    } else if msg![env; object isKindOfClass:array_class] {
+        let count: NSUInteger = msg![env; object count];
+        let mut array = Vec::with_capacity(count as usize);
+        for i in 0..count {
+            let item: id = msg![env; object objectAtIndex:i];
+            array.push(to_plist_value(env, item)?);
+        }
+        Value::Array(array)
+    } else if msg![env; object isKindOfClass:dictionary_class] {
+        let keys: Vec<id> = env
+            .objc
+            .borrow::<DictionaryHostObject>(object)
+            .iter_keys()
+            .collect();
+        let mut dictionary = Dictionary::new();
+        for key in keys {
+            // Property list dictionary keys are always strings.
+            if !msg![env; key isKindOfClass:string_class] {
+                log!("Warning: {:?} is not a valid property list key", key);
+                return None;
+            }
+            let value: id = msg![env; object objectForKey:key];
+            let key = to_rust_string(env, key).into_owned();
+            dictionary.insert(key, to_plist_value(env, value)?);
+        }
+        Value::Dictionary(dictionary)
+    } else {
+        let class: Class = msg![env; object class];
+        log!(
+            "Warning: {:?} (class {:?}) is not a property list object",
+            object,
+            class
+        );
+        return None;
+    };
+    Some(value)
+}
+
+/// Create a property list object (with a +1 reference count) from a
+/// [plist::Value]. Like Apple's implementation, this creates immutable
+/// objects. Values without an equivalent object (UIDs) become nil.
+pub fn from_plist_value(env: &mut Environment, value: &Value) -> id {
+    match value {
+        Value::String(string) => from_rust_string(env, string.clone()),
+        &Value::Boolean(value) => {
+            let number: id = msg_class![env; NSNumber alloc];
+            msg![env; number initWithBool:value]
+        }
+        Value::Integer(integer) => {
+            let number: id = msg_class![env; NSNumber alloc];
+            if let Some(value) = integer.as_signed() {
+                msg![env; number initWithLongLong:value]
+            } else {
+                let value = integer.as_unsigned().unwrap();
+                msg![env; number initWithUnsignedLongLong:value]
+            }
+        }
+        &Value::Real(value) => {
+            let number: id = msg_class![env; NSNumber alloc];
+            msg![env; number initWithDouble:value]
+        }
+        Value::Data(bytes) => ns_data::from_vec(env, bytes.clone()),
+        Value::Array(array) => {
+            // Unsupported values are left out, since collections can't
+            // contain nil.
+            let objects = array
+                .iter()
+                .map(|item| from_plist_value(env, item))
+                .filter(|&object| object != nil)
+                .collect();
+            ns_array::from_vec(env, objects)
+        }
+        Value::Dictionary(dictionary) => {
+            let keys_and_objects: Vec<(id, id)> = dictionary
+                .iter()
+                .filter_map(|(key, value)| {
+                    let value = from_plist_value(env, value);
+                    if value == nil {
+                        return None;
+                    }
+                    let key = from_rust_string(env, key.clone());
+                    Some((key, value))
+                })
+                .collect();
+            let dictionary = dict_from_keys_and_objects(env, &keys_and_objects);
+            for (key, value) in keys_and_objects {
+                release(env, key);
+                release(env, value);
+            }
+            dictionary
+        }
+        Value::Date(date) => {
+            let since_reference_date = ns_date::since_reference_date((*date).into());
+            let date: id = msg_class![env; NSDate alloc];
+            msg![env; date initWithTimeIntervalSinceReferenceDate:since_reference_date]
+        }
+        _ => {
+            log!("Warning: unsupported property list value {:?}", value);
+            nil
+        }
+    }
+}
+
+/// Read a property list file in either the XML or binary format, for use by
+/// methods like `initWithContentsOfFile:`. Returns [None] if the file doesn't
+/// exist or isn't a valid property list.
+pub(super) fn read_plist_file(env: &mut Environment, path: id) -> Option<Value> {
+    let path = to_rust_string(env, path); // TODO: avoid copy?
+    let Ok(bytes) = env.fs.read(GuestPath::new(&path)) else {
+        log!("Warning: couldn't read property list file {:?}", path);
+        return None;
+    };
+    let value = deserialize_plist(&bytes);
+    if value.is_none() {
+        log!("Warning: couldn't parse property list file {:?}", path);
+    }
+    value
+}
+
+/// Write a property list file in the XML format, for use by methods like
+/// `writeToFile:atomically:`. Returns `false` on failure.
+pub(super) fn write_plist_file(env: &mut Environment, path: id, value: &Value) -> bool {
+    let bytes = serialize_plist_xml(value);
+
+    let path = to_rust_string(env, path); // TODO: avoid copy?
+    let mut options = GuestOpenOptions::new();
+    options.write().create().truncate();
+    // TODO: atomic writes
+    let Ok(mut file) = env.fs.open_with_options(GuestPath::new(&path), options) else {
+        log!("Warning: couldn't open {:?} to write property list", path);
+        return false;
+    };
+    file.write_all(&bytes).is_ok()
+}
+
+fn deserialize_plist(bytes: &[u8]) -> Option<Value> {
+    // The format is detected automatically.
+    Value::from_reader(Cursor::new(bytes)).ok()
+}
+
+fn serialize_plist_xml(value: &Value) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    value.to_writer_xml(&mut bytes).unwrap();
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nested_plist() -> Value {
+        let mut level = Dictionary::new();
+        level.insert("name".into(), Value::String("Ünderground".into()));
+        level.insert("par".into(), Value::Integer(42.into()));
+        level.insert("gravity".into(), Value::Real(-9.8));
+        level.insert("locked".into(), Value::Boolean(false));
+        level.insert("tiles".into(), Value::Data(vec![0, 1, 2, 255]));
+        level.insert(
+            "created".into(),
+            Value::Date(ns_date::to_system_time(300_000_000.0).into()),
+        );
+        level.insert(
+            "spawns".into(),
+            Value::Array(vec![
+                Value::Array(vec![Value::Integer(1.into()), Value::Integer(2.into())]),
+                Value::Array(vec![]),
+            ]),
+        );
+        let mut root = Dictionary::new();
+        root.insert("version".into(), Value::Integer(3.into()));
+        root.insert(
+            "levels".into(),
+            Value::Array(vec![Value::Dictionary(level)]),
+        );
+        Value::Dictionary(root)
+    }
+
+    #[test]
+    fn xml_round_trip() {
+        let value = nested_plist();
+        let bytes = serialize_plist_xml(&value);
+        assert!(bytes.starts_with(b"<?xml"));
+        assert_eq!(deserialize_plist(&bytes), Some(value));
+    }
+
+    #[test]
+    fn binary_round_trip() {
+        let value = nested_plist();
+        let mut bytes = Vec::new();
+        value.to_writer_binary(&mut bytes).unwrap();
+        assert!(bytes.starts_with(b"bplist00"));
+        assert_eq!(deserialize_plist(&bytes), Some(value));
+    }
+
+    #[test]
+    fn invalid_plist() {
+        assert_eq!(deserialize_plist(b"<?xml version=\"1.0\"?><plist>"), None);
+        assert_eq!(deserialize_plist(b""), None);
+    }
+}
@@ -8,16 +8,19 @@
 //! Resources:
 //! - Apple's [Threading Programming Guide](https://developer.apple.com/library/archive/documentation/Cocoa/Conceptual/Multithreading/Introduction/Introduction.html)
 
-use super::{ns_string, ns_timer};
+use super::{ns_string, ns_timer, ns_url_connection, NSTimeInterval};
 use crate::dyld::{ConstantExports, HostConstant};
 use crate::frameworks::audio_toolbox::audio_queue::{handle_audio_queue, AudioQueueRef};
+use crate::frameworks::core_animation::ca_display_link::handle_display_link;
 use crate::frameworks::core_foundation::cf_run_loop::{
     kCFRunLoopCommonModes, kCFRunLoopDefaultMode, CFRunLoopRef,
 };
 use crate::frameworks::uikit;
-use crate::objc::{id, msg, objc_classes, release, retain, ClassExports, HostObject};
-use crate::Environment;
-use std::time::Duration;
+use crate::objc::{
+    id, msg, msg_class, msg_send, objc_classes, release, retain, ClassExports, HostObject, SEL,
+};
+use crate::{Environment, ThreadID};
+use std::time::{Duration, Instant};
 
 /// `NSString*`
 pub type NSRunLoopMode = id;
@@ -48,9 +51,28 @@ struct NSRunLoopHostObject {
     /// Strong references to `NSTimer*` in no particular order. Timers are owned
     /// by the run loop. The timer must remove itself when invalidated.
     timers: Vec<id>,
+    /// Strong references to `CADisplayLink*` in no particular order. The
+    /// display link must remove itself when invalidated.
+    display_links: Vec<id>,
+    /// Strong references to `NSURLConnection*` in no particular order. The
+    /// connection must remove itself once it's finished.
+    url_connections: Vec<id>,
+    /// Messages to send on the run loop's thread, in order, e.g. from
+    /// `performSelectorOnMainThread:withObject:waitUntilDone:`.
+    pending_performs: Vec<PendingPerform>,
 }
 impl HostObject for NSRunLoopHostObject {}
 
+struct PendingPerform {
+    /// Strong reference
+    target: id,
+    selector: SEL,
+    /// Strong reference
+    object: id,
+    /// Thread to unblock once the message has been sent, if any.
+    waiting_thread: Option<ThreadID>,
+}
+
 pub const CLASSES: ClassExports = objc_classes! {
 
 (env, this, _cmd);
@@ -64,6 +86,9 @@ pub const CLASSES: ClassExports = objc_classes! {
         let host_object = Box::new(NSRunLoopHostObject {
             audio_queues: Vec::new(),
             timers: Vec::new(),
+            display_links: Vec::new(),
+            url_connections: Vec::new(),
+            pending_performs: Vec::new(),
         });
         let new = env.objc.alloc_static_object(this, host_object, &mut env.mem);
         env.framework_state.foundation.ns_run_loop.main_thread_run_loop = Some(new);
@@ -89,9 +114,7 @@ pub const CLASSES: ClassExports = objc_classes! {
 
 - (())addTimer:(id)timer // NSTimer*
        forMode:(NSRunLoopMode)mode {
-    let default_mode = ns_string::get_static_str(env, NSDefaultRunLoopMode);
-    // TODO: handle other modes
-    assert!(msg![env; mode isEqualToString:default_mode]);
+    assert_supported_mode(env, mode);
 
     log_dbg!("Adding timer {:?} to run loop {:?}", timer, this);
 
@@ -104,9 +127,24 @@ pub const CLASSES: ClassExports = objc_classes! {
 }
 
 - (())run {
-    run_run_loop(env, this);
+    run_run_loop(env, this, None, /* return_after_source: */ false);
+}
+
+- (())runUntilDate:(id)limit_date { // NSDate*
+    let deadline = date_to_instant(env, limit_date);
+    run_run_loop(env, this, Some(deadline), /* return_after_source: */ false);
+}
+
+- (bool)runMode:(NSRunLoopMode)mode
+     beforeDate:(id)limit_date { // NSDate*
+    assert_supported_mode(env, mode);
+    if !has_sources(env, this) {
+        return false;
+    }
+    let deadline = date_to_instant(env, limit_date);
+    run_run_loop(env, this, Some(deadline), /* return_after_source: */ true);
+    true
 }
-// TODO: other run methods
 
 @end
 
@@ -153,15 +191,183 @@ pub(super) fn remove_timer(env: &mut Environment, run_loop: id, timer: id) {
     }
 }
 
-fn run_run_loop(env: &mut Environment, run_loop: id) {
-    log_dbg!("Entering run loop {:?} (indefinitely)", run_loop);
+/// For use by `CADisplayLink`.
+pub fn add_display_link(env: &mut Environment, run_loop: id, link: id, mode: NSRunLoopMode) {
+    assert_supported_mode(env, mode);
+    retain(env, link);
+    let links = &mut env
+        .objc
+        .borrow_mut::<NSRunLoopHostObject>(run_loop)
+        .display_links;
+    assert!(!links.contains(&link));
+    links.push(link);
+}
+
+/// For use by `CADisplayLink` so it can remove itself once it's invalidated.
+pub fn remove_display_link(env: &mut Environment, run_loop: id, link: id) {
+    let links = &mut env
+        .objc
+        .borrow_mut::<NSRunLoopHostObject>(run_loop)
+        .display_links;
+    let link_idx = links.iter().position(|&item| item == link).unwrap();
+    links.swap_remove(link_idx);
+    release(env, link);
+}
+
+/// For use by `NSURLConnection`.
+pub(super) fn add_url_connection(env: &mut Environment, run_loop: id, connection: id) {
+    retain(env, connection);
+    let connections = &mut env
+        .objc
+        .borrow_mut::<NSRunLoopHostObject>(run_loop)
+        .url_connections;
+    assert!(!connections.contains(&connection));
+    connections.push(connection);
+}
+
+/// For use by `NSURLConnection` so it can remove itself once it's finished.
+pub(super) fn remove_url_connection(env: &mut Environment, run_loop: id, connection: id) {
+    let connections = &mut env
+        .objc
+        .borrow_mut::<NSRunLoopHostObject>(run_loop)
+        .url_connections;
+    let connection_idx = connections
+        .iter()
+        .position(|&item| item == connection)
+        .unwrap();
+    connections.swap_remove(connection_idx);
+    release(env, connection);
+}
+
+/// For use by `NSObject`: queue a message to be sent to `target` on the run
+/// loop's thread. If `waiting_thread` is provided, that thread will be
+/// unblocked once the message has been sent.
+pub(super) fn add_pending_perform(
+    env: &mut Environment,
+    run_loop: id,
+    target: id,
+    selector: SEL,
+    object: id,
+    waiting_thread: Option<ThreadID>,
+) {
+    retain(env, target);
+    retain(env, object);
+    env.objc
+        .borrow_mut::<NSRunLoopHostObject>(run_loop)
+        .pending_performs
+        .push(PendingPerform {
+            target,
+            selector,
+            object,
+            waiting_thread,
+        });
+}
+
+/// Returns [true] if any messages were sent.
+fn handle_pending_performs(env: &mut Environment, run_loop: id) -> bool {
+    let pending_performs = std::mem::take(
+        &mut env
+            .objc
+            .borrow_mut::<NSRunLoopHostObject>(run_loop)
+            .pending_performs,
+    );
+    let handled_any = !pending_performs.is_empty();
+    for PendingPerform {
+        target,
+        selector,
+        object,
+        waiting_thread,
+    } in pending_performs
+    {
+        log_dbg!(
+            "Run loop {:?} sending {:?} message to {:?}",
+            run_loop,
+            selector.as_str(&env.mem),
+            target
+        );
+        let _: () = msg_send(env, (target, selector, object));
+        release(env, target);
+        release(env, object);
+        if let Some(waiting_thread) = waiting_thread {
+            env.unblock_thread(waiting_thread);
+        }
+    }
+    handled_any
+}
+
+/// TODO: handle other modes. The common modes include the default mode, so
+/// treating them the same is good enough for now.
+fn assert_supported_mode(env: &mut Environment, mode: NSRunLoopMode) {
+    let default_mode = ns_string::get_static_str(env, NSDefaultRunLoopMode);
+    let common_modes = ns_string::get_static_str(env, NSRunLoopCommonModes);
+    assert!(
+        msg![env; mode isEqualToString:default_mode]
+            || msg![env; mode isEqualToString:common_modes]
+    );
+}
+
+/// Convert an `NSDate*` to an [Instant]. Dates too far in the future (e.g.
+/// `distantFuture`) are clamped to something merely very far in the future.
+fn date_to_instant(env: &mut Environment, date: id) -> Instant {
+    let interval: NSTimeInterval = msg![env; date timeIntervalSinceNow];
+    let now = Instant::now();
+    if interval <= 0.0 {
+        return now;
+    }
+    const MAX_INTERVAL: Duration = Duration::from_secs(60 * 60 * 24 * 365);
+    now + Duration::try_from_secs_f64(interval)
+        .unwrap_or(MAX_INTERVAL)
+        .min(MAX_INTERVAL)
+}
+
+/// Whether the run loop has anything that could make it do work. The main
+/// thread's run loop always has a source: the window's events.
+fn has_sources(env: &mut Environment, run_loop: id) -> bool {
+    let is_main_run_loop = env
+        .framework_state
+        .foundation
+        .ns_run_loop
+        .main_thread_run_loop
+        == Some(run_loop);
+    let host_object = env.objc.borrow::<NSRunLoopHostObject>(run_loop);
+    is_main_run_loop
+        || !host_object.timers.is_empty()
+        || !host_object.display_links.is_empty()
+        || !host_object.audio_queues.is_empty()
+        || !host_object.url_connections.is_empty()
+        || !host_object.pending_performs.is_empty()
+}
+
+/// Run the run loop until `deadline` (or forever if [None]). If
+/// `return_after_source` is [true], it will also return once a timer has fired
+/// or a pending message has been sent.
+fn run_run_loop(
+    env: &mut Environment,
+    run_loop: id,
+    deadline: Option<Instant>,
+    return_after_source: bool,
+) {
+    log_dbg!(
+        "Entering run loop {:?} (until {:?}, return after source: {})",
+        run_loop,
+        deadline,
+        return_after_source
+    );
 
     // Temporary vectors used to track things without needing a reference to the
     // environment or to lock the object. Re-used each iteration for efficiency.
     let mut timers_tmp = Vec::new();
+    let mut display_links_tmp = Vec::new();
     let mut audio_queues_tmp = Vec::new();
+    let mut url_connections_tmp = Vec::new();
 
     loop {
+        let mut handled_source = false;
+
+        // Like on iPhone OS, each iteration has its own autorelease pool, so
+        // objects autoreleased by timers etc don't build up indefinitely.
+        let pool: id = msg_class![env; NSAutoreleasePool new];
+
         env.window.poll_for_events(&env.options);
 
         uikit::handle_events(env);
@@ -170,7 +376,18 @@ fn run_run_loop(env: &mut Environment, run_loop: id) {
         timers_tmp.extend_from_slice(&env.objc.borrow::<NSRunLoopHostObject>(run_loop).timers);
 
         for timer in timers_tmp.drain(..) {
-            ns_timer::handle_timer(env, timer);
+            handled_source |= ns_timer::handle_timer(env, timer);
+        }
+
+        assert!(display_links_tmp.is_empty());
+        display_links_tmp.extend_from_slice(
+            &env.objc
+                .borrow::<NSRunLoopHostObject>(run_loop)
+                .display_links,
+        );
+
+        for link in display_links_tmp.drain(..) {
+            handled_source |= handle_display_link(env, link);
         }
 
         assert!(audio_queues_tmp.is_empty());
@@ -184,13 +401,45 @@ fn run_run_loop(env: &mut Environment, run_loop: id) {
             handle_audio_queue(env, audio_queue);
         }
 
-        // This is a hack, but it saves a lot of CPU usage, as much as 75%!
-        // 5ms is an arbitrary but apparently effective value. If it's too small
-        // there won't be much benefit, and if it's too large there'll be too
-        // much lag.
+        assert!(url_connections_tmp.is_empty());
+        url_connections_tmp.extend_from_slice(
+            &env.objc
+                .borrow::<NSRunLoopHostObject>(run_loop)
+                .url_connections,
+        );
+
+        for connection in url_connections_tmp.drain(..) {
+            handled_source |= ns_url_connection::handle_url_connection(env, connection);
+        }
+
+        handled_source |= handle_pending_performs(env, run_loop);
+
+        uikit::recomposite_if_necessary(env);
+
+        let _: () = msg![env; pool drain];
+
+        if return_after_source && handled_source {
+            break;
+        }
+
+        // Let the app's other threads run for a bit, and then sleep for
+        // whatever time remains. Sleeping is a hack, but it saves a lot of CPU
+        // usage, as much as 75%! 5ms is an arbitrary but apparently effective
+        // value. If it's too small there won't be much benefit, and if it's too
+        // large there'll be too much lag.
         // TODO: Try to calculate how much time remains until the next event
         // and sleep only that much.
-        // FIXME: Run the app's other threads if they are active.
-        std::thread::sleep(Duration::from_millis(5));
+        let mut sleep_until = Instant::now() + Duration::from_millis(5);
+        if let Some(deadline) = deadline {
+            sleep_until = sleep_until.min(deadline);
+        }
+        env.run_other_threads(sleep_until);
+        std::thread::sleep(sleep_until.saturating_duration_since(Instant::now()));
+
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            break;
+        }
     }
+
+    log_dbg!("Exiting run loop {:?}", run_loop);
 }
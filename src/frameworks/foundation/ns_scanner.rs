@@ -0,0 +1,452 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! `NSScanner`.
+//!
+//! Resources:
+//! - Apple's [String Programming Guide](https://developer.apple.com/library/archive/documentation/Cocoa/Conceptual/Strings/Articles/Scanners.html)
+
+use super::ns_string::{for_each_code_unit, from_utf16};
+use super::{NSInteger, NSUInteger};
+use crate::mem::{MutPtr, MutVoidPtr, SafeWrite};
+use crate::objc::{
+    autorelease, id, msg, msg_class, nil, objc_classes, release, retain, ClassExports, HostObject,
+};
+use crate::Environment;
+
+/// The host-side scanning state and logic, separate from the Objective-C
+/// parts so it can be tested.
+struct Scanner {
+    code_units: Vec<u16>,
+    location: usize,
+    case_sensitive: bool,
+}
+impl Scanner {
+    fn remaining(&self) -> &[u16] {
+        &self.code_units[self.location..]
+    }
+
+    fn scan_integer(&mut self) -> Option<i64> {
+        let (value, len) = parse_integer(self.remaining())?;
+        self.location += len;
+        Some(value)
+    }
+
+    fn scan_double(&mut self) -> Option<f64> {
+        let (value, len) = parse_double(self.remaining())?;
+        self.location += len;
+        Some(value)
+    }
+
+    /// Scan the string `expected` if it comes next.
+    fn scan_string(&mut self, expected: &[u16]) -> bool {
+        let remaining = self.remaining();
+        let matches = remaining.len() >= expected.len()
+            && code_units_match(&remaining[..expected.len()], expected, self.case_sensitive);
+        if matches {
+            self.location += expected.len();
+        }
+        matches
+    }
+
+    /// Scan everything up to the next occurrence of `stop` (or the end). This
+    /// fails if nothing would be scanned.
+    fn scan_up_to_string(&mut self, stop: &[u16]) -> Option<Vec<u16>> {
+        let remaining = self.remaining();
+        let len = if stop.is_empty() {
+            remaining.len()
+        } else {
+            remaining
+                .windows(stop.len())
+                .position(|window| code_units_match(window, stop, self.case_sensitive))
+                .unwrap_or(remaining.len())
+        };
+        if len == 0 {
+            return None;
+        }
+        let scanned = remaining[..len].to_vec();
+        self.location += len;
+        Some(scanned)
+    }
+}
+
+fn ascii_at(code_units: &[u16], index: usize) -> Option<u8> {
+    code_units.get(index).and_then(|&c| u8::try_from(c).ok())
+}
+
+fn count_digits(code_units: &[u16], start: usize) -> usize {
+    (start..)
+        .take_while(|&i| ascii_at(code_units, i).is_some_and(|c| c.is_ascii_digit()))
+        .count()
+}
+
+/// Parse an optionally-signed decimal integer at the start of `code_units`,
+/// returning it (saturated to the range of [i64]) and the number of code units
+/// it took up.
+fn parse_integer(code_units: &[u16]) -> Option<(i64, usize)> {
+    let (negative, sign_len) = match ascii_at(code_units, 0) {
+        Some(b'-') => (true, 1),
+        Some(b'+') => (false, 1),
+        _ => (false, 0),
+    };
+    let digit_count = count_digits(code_units, sign_len);
+    if digit_count == 0 {
+        return None;
+    }
+    let len = sign_len + digit_count;
+    let value = code_units[sign_len..len].iter().fold(0i64, |value, &c| {
+        let digit = i64::from(c - u16::from(b'0'));
+        let value = value.saturating_mul(10);
+        if negative {
+            value.saturating_sub(digit)
+        } else {
+            value.saturating_add(digit)
+        }
+    });
+    Some((value, len))
+}
+
+/// Parse a decimal floating-point number (with optional sign, fraction and
+/// exponent) at the start of `code_units`, returning it and the number of
+/// code units it took up.
+fn parse_double(code_units: &[u16]) -> Option<(f64, usize)> {
+    let mut len = match ascii_at(code_units, 0) {
+        Some(b'-' | b'+') => 1,
+        _ => 0,
+    };
+    let integer_digits = count_digits(code_units, len);
+    len += integer_digits;
+    let mut fraction_digits = 0;
+    if ascii_at(code_units, len) == Some(b'.') {
+        fraction_digits = count_digits(code_units, len + 1);
+        if integer_digits + fraction_digits > 0 {
+            len += 1 + fraction_digits;
+        }
+    }
+    if integer_digits + fraction_digits == 0 {
+        return None;
+    }
+    // The exponent is only part of the number if it has digits.
+    if let Some(b'e' | b'E') = ascii_at(code_units, len) {
+        let sign_len = match ascii_at(code_units, len + 1) {
+            Some(b'-' | b'+') => 1,
+            _ => 0,
+        };
+        let exponent_digits = count_digits(code_units, len + 1 + sign_len);
+        if exponent_digits > 0 {
+            len += 1 + sign_len + exponent_digits;
+        }
+    }
+    // Everything so far is ASCII.
+    let text: String = code_units[..len].iter().map(|&c| c as u8 as char).collect();
+    Some((text.parse().unwrap(), len))
+}
+
+/// Compare code units, ignoring (simple) case differences if requested.
+fn code_units_match(a: &[u16], b: &[u16], case_sensitive: bool) -> bool {
+    let fold = |c: u16| {
+        let mut lowercase = char::from_u32(c.into())
+            .into_iter()
+            .flat_map(char::to_lowercase);
+        match (lowercase.next(), lowercase.next()) {
+            (Some(lower), None) => u16::try_from(u32::from(lower)).unwrap_or(c),
+            _ => c,
+        }
+    };
+    a.len() == b.len()
+        && a.iter()
+            .zip(b)
+            .all(|(&a, &b)| a == b || (!case_sensitive && fold(a) == fold(b)))
+}
+
+fn to_utf16(env: &mut Environment, string: id) -> Vec<u16> {
+    let mut code_units = Vec::new();
+    for_each_code_unit(env, string, |_idx, c| code_units.push(c));
+    code_units
+}
+
+struct NSScannerHostObject {
+    string: id,
+    characters_to_be_skipped: id,
+    scanner: Scanner,
+}
+impl HostObject for NSScannerHostObject {}
+
+/// Skip the `charactersToBeSkipped`, then run the host-side scan operation.
+fn scan<T>(env: &mut Environment, this: id, f: impl FnOnce(&mut Scanner) -> T) -> T {
+    let set = env
+        .objc
+        .borrow::<NSScannerHostObject>(this)
+        .characters_to_be_skipped;
+    if set != nil {
+        loop {
+            let host_object = env.objc.borrow::<NSScannerHostObject>(this);
+            let Some(&c) = host_object.scanner.remaining().first() else { break; };
+            if !msg![env; set characterIsMember:c] {
+                break;
+            }
+            env.objc
+                .borrow_mut::<NSScannerHostObject>(this)
+                .scanner
+                .location += 1;
+        }
+    }
+    f(&mut env.objc.borrow_mut::<NSScannerHostObject>(this).scanner)
+}
+
+fn write_result<T: SafeWrite>(env: &mut Environment, ptr: MutPtr<T>, value: T) {
+    if !ptr.is_null() {
+        env.mem.write(ptr, value);
+    }
+}
+
+pub const CLASSES: ClassExports = objc_classes! {
+
+(env, this, _cmd);
+
+@implementation NSScanner: NSObject
+
++ (id)allocWithZone:(MutVoidPtr)_zone {
+    let host_object = Box::new(NSScannerHostObject {
+        string: nil,
+        characters_to_be_skipped: nil,
+        scanner: Scanner {
+            code_units: Vec::new(),
+            location: 0,
+            case_sensitive: false,
+        },
+    });
+    env.objc.alloc_object(this, host_object, &mut env.mem)
+}
+
++ (id)scannerWithString:(id)string { // NSString*
+    let new: id = msg![env; this alloc];
+    let new: id = msg![env; new initWithString:string];
+    autorelease(env, new)
+}
+
+- (id)initWithString:(id)string { // NSString*
+    let string: id = msg![env; string copy];
+    let code_units = to_utf16(env, string);
+    let skipped: id = msg_class![env; NSCharacterSet whitespaceAndNewlineCharacterSet];
+    let skipped = retain(env, skipped);
+    let host_object = env.objc.borrow_mut::<NSScannerHostObject>(this);
+    host_object.string = string;
+    host_object.characters_to_be_skipped = skipped;
+    host_object.scanner.code_units = code_units;
+    this
+}
+
+- (())dealloc {
+    let host_object = env.objc.borrow::<NSScannerHostObject>(this);
+    let (string, skipped) = (host_object.string, host_object.characters_to_be_skipped);
+    release(env, string);
+    release(env, skipped);
+    env.objc.dealloc_object(this, &mut env.mem)
+}
+
+- (id)string {
+    env.objc.borrow::<NSScannerHostObject>(this).string
+}
+
+- (NSUInteger)scanLocation {
+    let location = env.objc.borrow::<NSScannerHostObject>(this).scanner.location;
+    location.try_into().unwrap()
+}
+- (())setScanLocation:(NSUInteger)location {
+    let scanner = &mut env.objc.borrow_mut::<NSScannerHostObject>(this).scanner;
+    // TODO: raise NSRangeException instead of panicking
+    assert!(location as usize <= scanner.code_units.len());
+    scanner.location = location as usize;
+}
+
+- (bool)caseSensitive {
+    env.objc.borrow::<NSScannerHostObject>(this).scanner.case_sensitive
+}
+- (())setCaseSensitive:(bool)case_sensitive {
+    env.objc.borrow_mut::<NSScannerHostObject>(this).scanner.case_sensitive = case_sensitive;
+}
+
+- (id)charactersToBeSkipped { // NSCharacterSet*
+    env.objc.borrow::<NSScannerHostObject>(this).characters_to_be_skipped
+}
+- (())setCharactersToBeSkipped:(id)set { // NSCharacterSet*
+    let set: id = msg![env; set copy];
+    let host_object = env.objc.borrow_mut::<NSScannerHostObject>(this);
+    let old = std::mem::replace(&mut host_object.characters_to_be_skipped, set);
+    release(env, old);
+}
+
+// Only skippable characters left counts as being at the end, but this doesn't
+// move the scan location.
+- (bool)isAtEnd {
+    let location = env.objc.borrow::<NSScannerHostObject>(this).scanner.location;
+    let at_end = scan(env, this, |scanner| scanner.remaining().is_empty());
+    env.objc.borrow_mut::<NSScannerHostObject>(this).scanner.location = location;
+    at_end
+}
+
+- (bool)scanInt:(MutPtr<i32>)result {
+    let Some(value) = scan(env, this, Scanner::scan_integer) else { return false; };
+    let value = value.clamp(i32::MIN.into(), i32::MAX.into()) as i32;
+    write_result(env, result, value);
+    true
+}
+- (bool)scanInteger:(MutPtr<NSInteger>)result {
+    let Some(value) = scan(env, this, Scanner::scan_integer) else { return false; };
+    let value = value.clamp(NSInteger::MIN.into(), NSInteger::MAX.into()) as NSInteger;
+    write_result(env, result, value);
+    true
+}
+- (bool)scanLongLong:(MutPtr<i64>)result {
+    let Some(value) = scan(env, this, Scanner::scan_integer) else { return false; };
+    write_result(env, result, value);
+    true
+}
+
+- (bool)scanFloat:(MutPtr<f32>)result {
+    let Some(value) = scan(env, this, Scanner::scan_double) else { return false; };
+    write_result(env, result, value as f32);
+    true
+}
+- (bool)scanDouble:(MutPtr<f64>)result {
+    let Some(value) = scan(env, this, Scanner::scan_double) else { return false; };
+    write_result(env, result, value);
+    true
+}
+
+- (bool)scanString:(id)string // NSString*
+        intoString:(MutPtr<id>)result { // NSString**
+    let expected = to_utf16(env, string);
+    if !scan(env, this, |scanner| scanner.scan_string(&expected)) {
+        return false;
+    }
+    if !result.is_null() {
+        // The scanned text may differ in case from the expected string.
+        let location = env.objc.borrow::<NSScannerHostObject>(this).scanner.location;
+        let code_units = &env.objc.borrow::<NSScannerHostObject>(this).scanner.code_units;
+        let scanned = code_units[location - expected.len()..location].to_vec();
+        let scanned = from_utf16(env, scanned);
+        let scanned = autorelease(env, scanned);
+        env.mem.write(result, scanned);
+    }
+    true
+}
+
+- (bool)scanUpToString:(id)stop // NSString*
+            intoString:(MutPtr<id>)result { // NSString**
+    let stop = to_utf16(env, stop);
+    let scanned = scan(env, this, |scanner| scanner.scan_up_to_string(&stop));
+    let Some(scanned) = scanned else { return false; };
+    if !result.is_null() {
+        let scanned = from_utf16(env, scanned);
+        let scanned = autorelease(env, scanned);
+        env.mem.write(result, scanned);
+    }
+    true
+}
+
+- (bool)scanCharactersFromSet:(id)set // NSCharacterSet*
+                   intoString:(MutPtr<id>)result { // NSString**
+    let start = scan(env, this, |scanner| scanner.location);
+    let mut end = start;
+    loop {
+        let code_units = &env.objc.borrow::<NSScannerHostObject>(this).scanner.code_units;
+        let Some(&c) = code_units.get(end) else { break; };
+        if !msg![env; set characterIsMember:c] {
+            break;
+        }
+        end += 1;
+    }
+    if end == start {
+        return false;
+    }
+    let host_object = env.objc.borrow_mut::<NSScannerHostObject>(this);
+    host_object.scanner.location = end;
+    let scanned = host_object.scanner.code_units[start..end].to_vec();
+    if !result.is_null() {
+        let scanned = from_utf16(env, scanned);
+        let scanned = autorelease(env, scanned);
+        env.mem.write(result, scanned);
+    }
+    true
+}
+
+@end
+
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scanner(string: &str) -> Scanner {
+        Scanner {
+            code_units: string.encode_utf16().collect(),
+            location: 0,
+            case_sensitive: false,
+        }
+    }
+
+    fn utf16(string: &str) -> Vec<u16> {
+        string.encode_utf16().collect()
+    }
+
+    /// What [scan] does with the default `charactersToBeSkipped`.
+    fn skip_whitespace(scanner: &mut Scanner) {
+        while scanner.remaining().first() == Some(&u16::from(b' ')) {
+            scanner.location += 1;
+        }
+    }
+
+    #[test]
+    fn mixed_numbers_and_text() {
+        let mut scanner = scanner("Level 12: score=-3.5e2, lives 3x");
+
+        skip_whitespace(&mut scanner);
+        assert_eq!(scanner.scan_integer(), None);
+        assert!(scanner.scan_string(&utf16("LEVEL")));
+        skip_whitespace(&mut scanner);
+        assert_eq!(scanner.scan_integer(), Some(12));
+        assert!(!scanner.scan_string(&utf16("score")));
+        assert_eq!(
+            scanner.scan_up_to_string(&utf16("=")),
+            Some(utf16(": score"))
+        );
+        assert!(scanner.scan_string(&utf16("=")));
+        assert_eq!(scanner.scan_double(), Some(-350.0));
+        assert_eq!(scanner.location, 22);
+
+        assert!(scanner.scan_string(&utf16(",")));
+        skip_whitespace(&mut scanner);
+        assert_eq!(
+            scanner.scan_up_to_string(&utf16("3")),
+            Some(utf16("lives "))
+        );
+        // An integer stops at the first non-digit.
+        assert_eq!(scanner.scan_integer(), Some(3));
+        assert_eq!(scanner.scan_double(), None);
+        assert_eq!(scanner.scan_up_to_string(&utf16("!")), Some(utf16("x")));
+        assert_eq!(scanner.location, 32);
+        assert!(scanner.remaining().is_empty());
+        assert_eq!(scanner.scan_up_to_string(&utf16("!")), None);
+    }
+
+    #[test]
+    fn number_formats() {
+        let double = |string: &str| parse_double(&utf16(string));
+        assert_eq!(double("1.5"), Some((1.5, 3)));
+        assert_eq!(double(".5x"), Some((0.5, 2)));
+        assert_eq!(double("+2."), Some((2.0, 3)));
+        assert_eq!(double("3e"), Some((3.0, 1)));
+        assert_eq!(double("3e-1"), Some((0.3, 4)));
+        assert_eq!(double("-."), None);
+
+        let integer = |string: &str| parse_integer(&utf16(string));
+        assert_eq!(integer("-42abc"), Some((-42, 3)));
+        assert_eq!(integer("+"), None);
+        assert_eq!(integer("99999999999999999999"), Some((i64::MAX, 20)));
+    }
+}
@@ -5,13 +5,15 @@
  */
 //! The `NSSet` class cluster, including `NSMutableSet` and `NSCountedSet`.
 
+use super::ns_array;
 use super::ns_dictionary::DictionaryHostObject;
 use super::ns_fast_enumeration::NSFastEnumerationState;
 use super::NSUInteger;
-use crate::mem::{MutPtr, MutVoidPtr};
+use crate::mem::{GuestUSize, MutPtr, MutVoidPtr};
 use crate::objc::{
     autorelease, id, msg, msg_class, nil, objc_classes, retain, ClassExports, HostObject,
 };
+use crate::Environment;
 
 /// Belongs to _touchHLE_NSSet
 struct SetHostObject {
@@ -82,48 +84,89 @@ pub const CLASSES: ClassExports = objc_classes! {
 
 // TODO: more init methods, etc
 
-// TODO: accessors
+- (NSUInteger)count {
+    env.objc.borrow::<SetHostObject>(this).dict.count
+}
+- (id)member:(id)object {
+    let host_object = env.objc.borrow::<SetHostObject>(this);
+    // The values are all NSNull, so look up the key instead.
+    let keys: Vec<id> = host_object.dict.iter_keys().collect();
+    for key in keys {
+        if key == object || msg![env; key isEqualTo:object] {
+            return key;
+        }
+    }
+    nil
+}
+- (id)anyObject {
+    let host_object = env.objc.borrow::<SetHostObject>(this);
+    host_object.dict.iter_keys().next().unwrap_or(nil)
+}
+- (id)allObjects {
+    let objects: Vec<id> = env
+        .objc
+        .borrow::<SetHostObject>(this)
+        .dict
+        .iter_keys()
+        .collect();
+    for &object in &objects {
+        retain(env, object);
+    }
+    let array = ns_array::from_vec(env, objects);
+    autorelease(env, array)
+}
 
 // NSFastEnumeration implementation
 - (NSUInteger)countByEnumeratingWithState:(MutPtr<NSFastEnumerationState>)state
                                   objects:(MutPtr<id>)stackbuf
                                     count:(NSUInteger)len {
-    let host_object = env.objc.borrow::<SetHostObject>(this);
-
-    if host_object.dict.count == 0 {
-        return 0;
-    }
-
-    // TODO: handle size > 1
-    assert!(host_object.dict.count == 1);
-    assert!(len >= host_object.dict.count);
-
+    // The state field is used to store how many objects have been returned so
+    // far. The set can't be mutated, so the iteration order stays the same.
     let NSFastEnumerationState {
-        state: is_first_round,
+        state: start_index,
         ..
     } = env.mem.read(state);
 
-    match is_first_round {
-        0 => {
-            let object = host_object.dict.iter_keys().next().unwrap();
-            env.mem.write(stackbuf, object);
-            env.mem.write(state, NSFastEnumerationState {
-                state: 1,
-                items_ptr: stackbuf,
-                // can be anything as long as it's dereferenceable and the same
-                // each iteration
-                mutations_ptr: stackbuf.cast(),
-                extra: Default::default(),
-            });
-            1 // returned object count
-        },
-        1 => {
-            0 // end of iteration
-        },
-        _ => panic!(), // app failed to initialize the buffer?
+    let host_object = env.objc.borrow::<SetHostObject>(this);
+    let objects: Vec<id> = host_object
+        .dict
+        .iter_keys()
+        .skip(start_index as usize)
+        .take(len as usize)
+        .collect();
+    if objects.is_empty() {
+        return 0; // end of iteration
+    }
+
+    for (i, &object) in objects.iter().enumerate() {
+        env.mem.write(stackbuf + i as GuestUSize, object);
     }
+    let count = objects.len() as NSUInteger;
+    env.mem.write(state, NSFastEnumerationState {
+        state: start_index + count,
+        items_ptr: stackbuf,
+        // can be anything as long as it's dereferenceable and the same
+        // each iteration
+        mutations_ptr: stackbuf.cast(),
+        extra: Default::default(),
+    });
+    count
 }
 
 @end
 
 };
+
+/// Shortcut for host code, roughly equivalent to
+/// `[[NSSet alloc] initWithObjects:count:]`. Unlike [ns_array::from_vec], the
+/// objects are retained by the set, not moved into it.
+pub fn from_vec(env: &mut Environment, objects: Vec<id>) -> id {
+    let set: id = msg_class![env; NSSet alloc];
+    let mut dict = <DictionaryHostObject as Default>::default();
+    let null: id = msg_class![env; NSNull null];
+    for object in objects {
+        dict.insert(env, object, null, /* copy_key: */ false);
+    }
+    env.objc.borrow_mut::<SetHostObject>(set).dict = dict;
+    set
+}
@@ -5,26 +5,37 @@
  */
 //! The `NSString` class cluster, including `NSMutableString`.
 
-use super::ns_array;
-use super::NSUInteger;
-use crate::frameworks::core_graphics::{CGRect, CGSize};
+use super::{
+    ns_array, ns_data, set_error_nil, NSComparisonResult, NSNotFound, NSOrderedAscending,
+    NSOrderedDescending, NSOrderedSame, NSRange, NSUInteger,
+};
+use crate::abi::VAList;
+use crate::frameworks::core_graphics::{CGFloat, CGPoint, CGRect, CGSize};
 use crate::frameworks::uikit::ui_font::{
     self, UILineBreakMode, UILineBreakModeWordWrap, UITextAlignment, UITextAlignmentLeft,
 };
-use crate::fs::GuestPath;
-use crate::mem::{ConstPtr, Mem, MutPtr, MutVoidPtr, SafeRead};
+use crate::fs::{GuestOpenOptions, GuestPath};
+use crate::libc::stdio::printf;
+use crate::mem::{ConstPtr, ConstVoidPtr, Mem, MutPtr, MutVoidPtr, Ptr, SafeRead};
 use crate::objc::{
-    autorelease, id, msg, msg_class, objc_classes, retain, Class, ClassExports, HostObject, ObjC,
+    autorelease, id, msg, msg_class, nil, objc_classes, release, retain, Class, ClassExports,
+    HostObject, ObjC,
 };
 use crate::Environment;
 use std::borrow::Cow;
+use std::cmp::Ordering;
 use std::collections::HashMap;
+use std::io::Write;
 use std::string::FromUtf16Error;
 
 pub type NSStringEncoding = NSUInteger;
+pub const NSASCIIStringEncoding: NSUInteger = 1;
 pub const NSUTF8StringEncoding: NSUInteger = 4;
+pub const NSISOLatin1StringEncoding: NSUInteger = 5;
 pub const NSUnicodeStringEncoding: NSUInteger = 10;
 pub const NSUTF16StringEncoding: NSUInteger = NSUnicodeStringEncoding;
+pub const NSUTF16BigEndianStringEncoding: NSUInteger = 0x90000100;
+pub const NSUTF16LittleEndianStringEncoding: NSUInteger = 0x94000100;
 
 #[derive(Default)]
 pub struct State {
@@ -57,43 +68,79 @@ enum StringHostObject {
 }
 impl HostObject for StringHostObject {}
 impl StringHostObject {
-    fn decode(bytes: Cow<[u8]>, encoding: NSStringEncoding) -> StringHostObject {
+    /// Decode a string from bytes in some encoding. Returns [None] if the
+    /// bytes aren't valid in that encoding.
+    fn decode(bytes: Cow<[u8]>, encoding: NSStringEncoding) -> Option<StringHostObject> {
         if bytes.len() == 0 {
-            return StringHostObject::Utf8(Cow::Borrowed(""));
+            return Some(StringHostObject::Utf8(Cow::Borrowed("")));
         }
 
-        // TODO: error handling
-
         match encoding {
             NSUTF8StringEncoding => {
-                let string = String::from_utf8(bytes.into_owned()).unwrap();
-                StringHostObject::Utf8(Cow::Owned(string))
+                let string = String::from_utf8(bytes.into_owned()).ok()?;
+                Some(StringHostObject::Utf8(Cow::Owned(string)))
             }
             NSUTF16StringEncoding => {
-                assert!(bytes.len() % 2 == 0);
-
-                let is_big_endian = match &bytes[0..2] {
-                    [0xFE, 0xFF] => true,
-                    [0xFF, 0xFE] => false,
-                    // TODO: what does NSUnicodeStringEncoding mean if no BOM is
-                    // present?
-                    _ => unimplemented!("Default endianness"),
+                // The byte order mark, if present, determines the endianness
+                // and is not part of the string. Without one, big-endian is
+                // assumed, as the Unicode standard recommends.
+                let (is_big_endian, bytes) = match bytes.get(0..2) {
+                    Some([0xFE, 0xFF]) => (true, &bytes[2..]),
+                    Some([0xFF, 0xFE]) => (false, &bytes[2..]),
+                    _ => (true, &bytes[..]),
                 };
-                // TODO: Should the BOM be stripped? Always/sometimes/never?
-
-                StringHostObject::Utf16(if is_big_endian {
-                    bytes
-                        .chunks(2)
-                        .map(|chunk| u16::from_be_bytes(chunk.try_into().unwrap()))
-                        .collect()
+                decode_utf16(bytes, is_big_endian)
+            }
+            // With an explicit byte order, a byte order mark is just a
+            // zero-width no-break space and is kept.
+            NSUTF16BigEndianStringEncoding => decode_utf16(&bytes, true),
+            NSUTF16LittleEndianStringEncoding => decode_utf16(&bytes, false),
+            NSASCIIStringEncoding | NSISOLatin1StringEncoding => {
+                if encoding == NSASCIIStringEncoding && !bytes.is_ascii() {
+                    return None;
+                }
+                // Latin-1 is the first 256 code points of Unicode.
+                let string = bytes.iter().map(|&byte| char::from(byte)).collect();
+                Some(StringHostObject::Utf8(Cow::Owned(string)))
+            }
+            _ => {
+                log!("Warning: unimplemented string encoding {:#x}", encoding);
+                None
+            }
+        }
+    }
+    /// Encode the string as bytes in some encoding, without a null terminator.
+    /// Returns [None] if the string can't be represented in that encoding.
+    fn encode(&self, encoding: NSStringEncoding) -> Option<Vec<u8>> {
+        match encoding {
+            NSUTF8StringEncoding => Some(self.to_utf8().ok()?.as_bytes().to_vec()),
+            NSUTF16StringEncoding => {
+                // Like on the iPhone, use the native (little-endian) byte
+                // order and prefix a byte order mark.
+                let mut bytes = vec![0xFF, 0xFE];
+                bytes.extend(self.iter_code_units().flat_map(u16::to_le_bytes));
+                Some(bytes)
+            }
+            NSUTF16BigEndianStringEncoding => {
+                Some(self.iter_code_units().flat_map(u16::to_be_bytes).collect())
+            }
+            NSUTF16LittleEndianStringEncoding => {
+                Some(self.iter_code_units().flat_map(u16::to_le_bytes).collect())
+            }
+            NSASCIIStringEncoding | NSISOLatin1StringEncoding => {
+                let limit = if encoding == NSASCIIStringEncoding {
+                    0x80
                 } else {
-                    bytes
-                        .chunks(2)
-                        .map(|chunk| u16::from_le_bytes(chunk.try_into().unwrap()))
-                        .collect()
-                })
+                    0x100
+                };
+                self.iter_code_units()
+                    .map(|unit| (unit < limit).then_some(unit as u8))
+                    .collect()
+            }
+            _ => {
+                log!("Warning: unimplemented string encoding {:#x}", encoding);
+                None
             }
-            _ => panic!("Unimplemented encoding: {}", encoding),
         }
     }
     fn to_utf8(&self) -> Result<Cow<'static, str>, FromUtf16Error> {
@@ -127,6 +174,34 @@ impl StringHostObject {
     }
 }
 
+/// Size of the null terminator of a C string in some encoding.
+fn terminator_size(encoding: NSStringEncoding) -> usize {
+    // UTF-16 strings need a two-byte null terminator.
+    match encoding {
+        NSUTF16StringEncoding
+        | NSUTF16BigEndianStringEncoding
+        | NSUTF16LittleEndianStringEncoding => 2,
+        _ => 1,
+    }
+}
+
+fn decode_utf16(bytes: &[u8], is_big_endian: bool) -> Option<StringHostObject> {
+    if bytes.len() % 2 != 0 {
+        return None;
+    }
+    Some(StringHostObject::Utf16(if is_big_endian {
+        bytes
+            .chunks(2)
+            .map(|chunk| u16::from_be_bytes(chunk.try_into().unwrap()))
+            .collect()
+    } else {
+        bytes
+            .chunks(2)
+            .map(|chunk| u16::from_le_bytes(chunk.try_into().unwrap()))
+            .collect()
+    }))
+}
+
 enum CodeUnitIterator<'a> {
     Utf8(std::str::EncodeUtf16<'a>),
     Utf16(std::slice::Iter<'a, u16>),
@@ -171,6 +246,81 @@ impl<'a> CodeUnitIterator<'a> {
     }
 }
 
+/// Find the first occurrence of `needle` in `haystack`, returning the index in
+/// code units. An empty `needle` is never found, as with `rangeOfString:`.
+fn find_code_units(haystack: &[u16], needle: &[u16]) -> Option<usize> {
+    if needle.is_empty() {
+        return None;
+    }
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+/// Lowercase a string for case-insensitive comparison. Unpaired surrogates
+/// are left as they are.
+fn fold_case(code_units: &[u16]) -> Utf16String {
+    let mut folded = Vec::with_capacity(code_units.len());
+    for c in char::decode_utf16(code_units.iter().copied()) {
+        match c {
+            Ok(c) => {
+                for c in c.to_lowercase() {
+                    let mut buffer = [0; 2];
+                    folded.extend_from_slice(c.encode_utf16(&mut buffer));
+                }
+            }
+            Err(err) => folded.push(err.unpaired_surrogate()),
+        }
+    }
+    folded
+}
+
+/// Apply a case mapping like [str::to_uppercase] to a string. Unpaired
+/// surrogates can't be mapped and are left as they are.
+fn map_case(code_units: &[u16], mut mapping: impl FnMut(&str) -> String) -> Utf16String {
+    let mut mapped = Vec::with_capacity(code_units.len());
+    let mut run = String::new();
+    for c in char::decode_utf16(code_units.iter().copied()) {
+        match c {
+            Ok(c) => run.push(c),
+            Err(err) => {
+                mapped.extend(mapping(&run).encode_utf16());
+                run.clear();
+                mapped.push(err.unpaired_surrogate());
+            }
+        }
+    }
+    mapped.extend(mapping(&run).encode_utf16());
+    mapped
+}
+
+/// Uppercase the first character of each word and lowercase the rest, like
+/// `capitalizedString`. Words are separated by whitespace.
+fn capitalize_words(string: &str) -> String {
+    let mut capitalized = String::with_capacity(string.len());
+    let mut at_word_start = true;
+    for c in string.chars() {
+        if c.is_whitespace() {
+            capitalized.push(c);
+            at_word_start = true;
+        } else if at_word_start {
+            capitalized.extend(c.to_uppercase());
+            at_word_start = false;
+        } else {
+            capitalized.extend(c.to_lowercase());
+        }
+    }
+    capitalized
+}
+
+fn comparison_result(ordering: Ordering) -> NSComparisonResult {
+    match ordering {
+        Ordering::Less => NSOrderedAscending,
+        Ordering::Equal => NSOrderedSame,
+        Ordering::Greater => NSOrderedDescending,
+    }
+}
+
 pub const CLASSES: ClassExports = objc_classes! {
 
 (env, this, _cmd);
@@ -202,6 +352,30 @@ pub const CLASSES: ClassExports = objc_classes! {
     autorelease(env, new)
 }
 
++ (id)stringWithCharacters:(ConstPtr<u16>)characters
+                   length:(NSUInteger)length {
+    let new: id = msg![env; this alloc];
+    let new: id = msg![env; new initWithCharacters:characters length:length];
+    autorelease(env, new)
+}
+
++ (id)stringWithFormat:(id)format, // NSString*
+                       ...args {
+    let res = with_format(env, format, args);
+    let new: id = msg![env; this alloc];
+    *env.objc.borrow_mut(new) = StringHostObject::Utf8(Cow::Owned(res));
+    autorelease(env, new)
+}
+
++ (id)localizedStringWithFormat:(id)format, // NSString*
+                                ...args {
+    // TODO: locale-specific formatting (e.g. decimal separators)
+    let res = with_format(env, format, args);
+    let new: id = msg![env; this alloc];
+    *env.objc.borrow_mut(new) = StringHostObject::Utf8(Cow::Owned(res));
+    autorelease(env, new)
+}
+
 + (id)stringWithContentsOfFile:(id)path // NSString*
                       encoding:(NSStringEncoding)encoding
                          error:(MutPtr<id>)error { // NSError**
@@ -246,6 +420,10 @@ pub const CLASSES: ClassExports = objc_classes! {
     utf16[index as usize]
 }
 
+- (id)description {
+    this
+}
+
 - (NSUInteger)hash {
     // TODO: avoid copying
     super::hash_helper(&to_rust_string(env, this))
@@ -269,6 +447,102 @@ pub const CLASSES: ClassExports = objc_classes! {
     to_rust_string(env, this) == to_rust_string(env, other)
 }
 
+- (NSComparisonResult)compare:(id)other { // NSString*
+    // TODO: avoid copying
+    // TODO: Apple's default comparison isn't purely literal.
+    let a = to_utf16(env, this);
+    let b = to_utf16(env, other);
+    comparison_result(a.cmp(&b))
+}
+- (NSComparisonResult)caseInsensitiveCompare:(id)other { // NSString*
+    // TODO: avoid copying
+    let a = fold_case(&to_utf16(env, this));
+    let b = fold_case(&to_utf16(env, other));
+    comparison_result(a.cmp(&b))
+}
+
+- (bool)hasPrefix:(id)prefix { // NSString*
+    // TODO: avoid copying
+    to_utf16(env, this).starts_with(&to_utf16(env, prefix))
+}
+- (bool)hasSuffix:(id)suffix { // NSString*
+    // TODO: avoid copying
+    to_utf16(env, this).ends_with(&to_utf16(env, suffix))
+}
+
+- (NSRange)rangeOfString:(id)search_string { // NSString*
+    // TODO: avoid copying
+    let utf16 = to_utf16(env, this);
+    let search_utf16 = to_utf16(env, search_string);
+    match find_code_units(&utf16, &search_utf16) {
+        Some(location) => NSRange {
+            location: location.try_into().unwrap(),
+            length: search_utf16.len().try_into().unwrap(),
+        },
+        None => NSRange {
+            location: NSNotFound as NSUInteger,
+            length: 0,
+        },
+    }
+}
+
+- (id)substringFromIndex:(NSUInteger)from { // NSString*
+    let length: NSUInteger = msg![env; this length];
+    // TODO: raise NSRangeException instead of panicking
+    assert!(from <= length);
+    let range = NSRange { location: from, length: length - from };
+    msg![env; this substringWithRange:range]
+}
+- (id)substringToIndex:(NSUInteger)to { // NSString*
+    let range = NSRange { location: 0, length: to };
+    msg![env; this substringWithRange:range]
+}
+- (id)substringWithRange:(NSRange)range { // NSString*
+    // TODO: avoid copying
+    let utf16 = to_utf16(env, this);
+    let NSRange { location, length } = range;
+    let end = location.checked_add(length);
+    // TODO: raise NSRangeException instead of panicking
+    assert!(end.is_some_and(|end| end as usize <= utf16.len()));
+    let substring = utf16[location as usize..][..length as usize].to_vec();
+    let new = msg_class![env; _touchHLE_NSString alloc];
+    *env.objc.borrow_mut(new) = StringHostObject::Utf16(substring);
+    autorelease(env, new)
+}
+
+- (id)stringByAppendingString:(id)other { // NSString*
+    // TODO: avoid copying
+    let mut utf16 = to_utf16(env, this);
+    utf16.extend(to_utf16(env, other));
+    let new = msg_class![env; _touchHLE_NSString alloc];
+    *env.objc.borrow_mut(new) = StringHostObject::Utf16(utf16);
+    autorelease(env, new)
+}
+
+// Case mapping uses Unicode's default (locale-independent) rules, so it may
+// change the length of the string, e.g. "ß" becomes "SS".
+- (id)lowercaseString { // NSString*
+    // TODO: avoid copying
+    let lowercase = map_case(&to_utf16(env, this), str::to_lowercase);
+    let new = msg_class![env; _touchHLE_NSString alloc];
+    *env.objc.borrow_mut(new) = StringHostObject::Utf16(lowercase);
+    autorelease(env, new)
+}
+- (id)uppercaseString { // NSString*
+    // TODO: avoid copying
+    let uppercase = map_case(&to_utf16(env, this), str::to_uppercase);
+    let new = msg_class![env; _touchHLE_NSString alloc];
+    *env.objc.borrow_mut(new) = StringHostObject::Utf16(uppercase);
+    autorelease(env, new)
+}
+- (id)capitalizedString { // NSString*
+    // TODO: avoid copying
+    let capitalized = map_case(&to_utf16(env, this), capitalize_words);
+    let new = msg_class![env; _touchHLE_NSString alloc];
+    *env.objc.borrow_mut(new) = StringHostObject::Utf16(capitalized);
+    autorelease(env, new)
+}
+
 // NSCopying implementation
 - (id)copyWithZone:(MutVoidPtr)_zone {
     // TODO: override this once we have NSMutableString!
@@ -278,21 +552,27 @@ pub const CLASSES: ClassExports = objc_classes! {
 - (bool)getCString:(MutPtr<u8>)buffer
          maxLength:(NSUInteger)buffer_size
           encoding:(NSStringEncoding)encoding {
-    assert!(encoding == NSUTF8StringEncoding); // TODO: other encodings
-
-    let src = to_rust_string(env, this);
+    let string = StringHostObject::Utf8(to_rust_string(env, this));
+    let Some(mut bytes) = string.encode(encoding) else {
+        return false;
+    };
+    bytes.resize(bytes.len() + terminator_size(encoding), 0);
     let dest = env.mem.bytes_at_mut(buffer, buffer_size);
-    if dest.len() < src.as_bytes().len() + 1 { // include null terminator
+    if dest.len() < bytes.len() {
         return false;
     }
-
-    for (i, &byte) in src.as_bytes().iter().chain(b"\0".iter()).enumerate() {
-        dest[i] = byte;
-    }
+    dest[..bytes.len()].copy_from_slice(&bytes);
 
     true
 }
 
+- (())getCharacters:(MutPtr<u16>)buffer {
+    // TODO: avoid copying
+    let mut utf16: Utf16String = Vec::new();
+    for_each_code_unit(env, this, |_idx, c| utf16.push(c));
+    env.mem.write_utf16(buffer, &utf16);
+}
+
 - (id)componentsSeparatedByString:(id)separator { // NSString*
     // TODO: support foreign subclasses (perhaps via a helper function that
     // copies the string first)
@@ -360,25 +640,13 @@ pub const CLASSES: ClassExports = objc_classes! {
     assert!(res_end >= res_start);
     let res_length = res_end - res_start;
 
-    let res = if res_length == initial_length {
-        retain(env, this)
+    if res_length == initial_length {
+        let res = retain(env, this);
+        autorelease(env, res)
     } else {
-        // TODO: just call `substringWithRange:` here instead, the only reason
-        // the current code doesn't is that it would require figuring out the
-        // ABI of NSRange.
-        let mut res_utf16: Utf16String = Vec::with_capacity(res_length as usize);
-
-        for_each_code_unit(env, this, |idx, c| {
-            if res_start <= idx && idx < res_end {
-                res_utf16.push(c);
-            }
-        });
-
-        let res = msg_class![env; _touchHLE_NSString alloc];
-        *env.objc.borrow_mut(res) = StringHostObject::Utf16(res_utf16);
-        res
-    };
-    autorelease(env, res)
+        let range = NSRange { location: res_start, length: res_length };
+        msg![env; this substringWithRange:range]
+    }
 }
 
 - (id)stringByReplacingOccurrencesOfString:(id)target // NSString*
@@ -438,9 +706,57 @@ pub const CLASSES: ClassExports = objc_classes! {
     c_string
 }
 
+- (ConstPtr<u8>)cStringUsingEncoding:(NSStringEncoding)encoding {
+    // TODO: handle foreign subclasses of NSString
+    let Some(mut bytes) = env.objc.borrow::<StringHostObject>(this).encode(encoding) else {
+        return Ptr::null();
+    };
+    bytes.resize(bytes.len() + terminator_size(encoding), 0);
+    // The NSData is autoreleased, so the C string lives as long as it would
+    // on the iPhone.
+    let data = ns_data::from_vec(env, bytes);
+    autorelease(env, data);
+    let c_string: ConstVoidPtr = msg![env; data bytes];
+    c_string.cast()
+}
+
+- (id)dataUsingEncoding:(NSStringEncoding)encoding { // NSData*
+    // TODO: handle foreign subclasses of NSString
+    let Some(bytes) = env.objc.borrow::<StringHostObject>(this).encode(encoding) else {
+        return nil;
+    };
+    let data = ns_data::from_vec(env, bytes);
+    autorelease(env, data)
+}
+
+- (bool)writeToFile:(id)path // NSString*
+         atomically:(bool)_atomically
+           encoding:(NSStringEncoding)encoding
+              error:(MutPtr<id>)error { // NSError**
+    // TODO: handle foreign subclasses of NSString
+    let Some(bytes) = env.objc.borrow::<StringHostObject>(this).encode(encoding) else {
+        set_error_nil(env, error);
+        return false;
+    };
+
+    let path = to_rust_string(env, path); // TODO: avoid copy?
+    let mut options = GuestOpenOptions::new();
+    options.write().create().truncate();
+    // TODO: atomic writes
+    let Ok(mut file) = env.fs.open_with_options(GuestPath::new(&path), options) else {
+        log!("Warning: couldn't open {:?} to write string", path);
+        set_error_nil(env, error);
+        return false;
+    };
+    if file.write_all(&bytes).is_err() {
+        set_error_nil(env, error);
+        return false;
+    }
+    true
+}
+
 // These come from a category in UIKit (UIStringDrawing).
 // TODO: Implement categories so we can completely move the code to UIFont.
-// TODO: More `sizeWithFont:` variants
 - (CGSize)sizeWithFont:(id)font { // UIFont*
     // TODO: avoid copy
     let text = to_rust_string(env, this);
@@ -457,8 +773,34 @@ pub const CLASSES: ClassExports = objc_classes! {
          lineBreakMode:(UILineBreakMode)line_break_mode {
     // TODO: avoid copy
     let text = to_rust_string(env, this);
+    // Lines that don't fit within the height aren't counted.
+    let text = ui_font::fit_text(env, font, &text, size, 0, line_break_mode);
     ui_font::size_with_font(env, font, &text, Some((size, line_break_mode)))
 }
+- (CGSize)sizeWithFont:(id)font // UIFont*
+              forWidth:(CGFloat)width
+         lineBreakMode:(UILineBreakMode)line_break_mode {
+    // TODO: avoid copy
+    let text = to_rust_string(env, this);
+    let text = single_line(env, font, &text, width, line_break_mode);
+    ui_font::size_with_font(env, font, &text, None)
+}
+
+- (CGSize)drawAtPoint:(CGPoint)point
+             withFont:(id)font { // UIFont*
+    // TODO: avoid copy
+    let text = to_rust_string(env, this);
+    ui_font::draw_at_point(env, font, &text, point)
+}
+- (CGSize)drawAtPoint:(CGPoint)point
+             forWidth:(CGFloat)width
+             withFont:(id)font // UIFont*
+        lineBreakMode:(UILineBreakMode)line_break_mode {
+    // TODO: avoid copy
+    let text = to_rust_string(env, this);
+    let text = single_line(env, font, &text, width, line_break_mode);
+    ui_font::draw_at_point(env, font, &text, point)
+}
 
 - (CGSize)drawInRect:(CGRect)rect
             withFont:(id)font { // UIFont*
@@ -500,17 +842,31 @@ pub const CLASSES: ClassExports = objc_classes! {
 - (id)initWithBytes:(ConstPtr<u8>)bytes
              length:(NSUInteger)len
            encoding:(NSStringEncoding)encoding {
-    assert!(encoding == NSUTF8StringEncoding); // TODO: other encodings
-
-    // TODO: error handling
     let slice = env.mem.bytes_at(bytes, len);
-    let host_object = StringHostObject::decode(Cow::Borrowed(slice), encoding);
+    let Some(host_object) = StringHostObject::decode(Cow::Borrowed(slice), encoding) else {
+        release(env, this);
+        return nil;
+    };
 
     *env.objc.borrow_mut(this) = host_object;
 
     this
 }
 
+- (id)initWithCharacters:(ConstPtr<u16>)characters
+                  length:(NSUInteger)length {
+    let utf16 = env.mem.utf16_at(characters, length);
+    *env.objc.borrow_mut(this) = StringHostObject::Utf16(utf16);
+    this
+}
+
+- (id)initWithFormat:(id)format, // NSString*
+                     ...args {
+    let res = with_format(env, format, args);
+    *env.objc.borrow_mut(this) = StringHostObject::Utf8(Cow::Owned(res));
+    this
+}
+
 - (id)initWithCString:(ConstPtr<u8>)c_string {
     // This is a deprecated method nobody should use, but unfortunately, it is
     // used. The encoding it should use is [NSString defaultCStringEncoding]
@@ -530,13 +886,21 @@ pub const CLASSES: ClassExports = objc_classes! {
 - (id)initWithContentsOfFile:(id)path // NSString*
                     encoding:(NSStringEncoding)encoding
                        error:(MutPtr<id>)error { // NSError**
-    assert!(error.is_null()); // TODO: error handling
-
     // TODO: avoid copy?
     let path = to_rust_string(env, path);
-    let bytes = env.fs.read(GuestPath::new(&path)).unwrap();
+    let Ok(bytes) = env.fs.read(GuestPath::new(&path)) else {
+        log!("Warning: couldn't read string from file {:?}", path);
+        set_error_nil(env, error);
+        release(env, this);
+        return nil;
+    };
 
-    let host_object = StringHostObject::decode(Cow::Owned(bytes), encoding);
+    let Some(host_object) = StringHostObject::decode(Cow::Owned(bytes), encoding) else {
+        log!("Warning: file {:?} is not valid in encoding {:#x}", path, encoding);
+        set_error_nil(env, error);
+        release(env, this);
+        return nil;
+    };
 
     *env.objc.borrow_mut(this) = host_object;
 
@@ -617,6 +981,14 @@ pub fn from_rust_string(env: &mut Environment, from: String) -> id {
     string
 }
 
+/// Shortcut for host code, like [from_rust_string] but for UTF-16 code units,
+/// which need not be well-formed.
+pub fn from_utf16(env: &mut Environment, from: Vec<u16>) -> id {
+    let string: id = msg_class![env; _touchHLE_NSString alloc];
+    *env.objc.borrow_mut(string) = StringHostObject::Utf16(from);
+    string
+}
+
 /// Shortcut for host code, provides a view of a string in UTF-8.
 /// Warning: This may panic if the string is not valid UTF-16!
 ///
@@ -631,6 +1003,63 @@ pub fn to_rust_string(env: &mut Environment, string: id) -> Cow<'static, str> {
         .unwrap()
 }
 
+/// [printf::ArgSource] for the variable arguments of `stringWithFormat:` and
+/// similar methods, which additionally support `%@`.
+struct FormatArgs<'a> {
+    env: &'a mut Environment,
+    args: VAList,
+}
+impl printf::ArgSource for FormatArgs<'_> {
+    fn word(&mut self, index: usize) -> u32 {
+        self.args.peek_word(self.env, index)
+    }
+    fn c_string(&mut self, ptr: ConstPtr<u8>, max_len: Option<usize>) -> Vec<u8> {
+        printf::read_c_string(self.env, ptr, max_len)
+    }
+    fn object_description(&mut self, object: u32) -> Vec<u8> {
+        let object: id = Ptr::from_bits(object);
+        let description: id = msg![self.env; object description];
+        to_rust_string(self.env, description).as_bytes().to_vec()
+    }
+}
+
+/// Format a string like `stringWithFormat:` would, using the `printf` engine.
+pub fn with_format(env: &mut Environment, format: id, args: VAList) -> String {
+    // TODO: avoid copy
+    let format = to_rust_string(env, format);
+    log_dbg!("Processing format string {:?}", format);
+    let res = printf::format(format.as_bytes(), &mut FormatArgs { env, args });
+    // %s with non-UTF-8 bytes could produce invalid UTF-8.
+    let res = String::from_utf8_lossy(&res).into_owned();
+    log_dbg!("=> {:?}", res);
+    res
+}
+
+/// Fit text on a single line of the given width, for the `forWidth:` variants
+/// of the UIKit string drawing methods.
+fn single_line(
+    env: &mut Environment,
+    font: id,
+    text: &str,
+    width: CGFloat,
+    line_break_mode: UILineBreakMode,
+) -> String {
+    let size = CGSize {
+        width,
+        height: CGFloat::INFINITY,
+    };
+    ui_font::fit_text(env, font, text, size, 1, line_break_mode)
+}
+
+/// Shortcut for host code, copies a string's UTF-16 code units.
+fn to_utf16(env: &mut Environment, string: id) -> Utf16String {
+    // TODO: handle foreign subclasses of NSString
+    env.objc
+        .borrow::<StringHostObject>(string)
+        .iter_code_units()
+        .collect()
+}
+
 /// Shortcut for host code, calls a callback once for each UTF-16 code-unit in a
 /// string. This is equivalent to a for loop using the `length` and
 /// `characterAtIndex:` methods, but much more efficient.
@@ -648,3 +1077,145 @@ where
             idx += 1;
         });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip_through_file(string: &str, encoding: NSStringEncoding) -> Vec<u8> {
+        let host_object = StringHostObject::Utf8(Cow::Owned(string.to_string()));
+        let bytes = host_object.encode(encoding).unwrap();
+
+        let path = std::env::temp_dir().join(format!(
+            "touchHLE_test_string_{:x}_{}.txt",
+            encoding,
+            std::process::id()
+        ));
+        std::fs::write(&path, &bytes).unwrap();
+        let read_bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let decoded = StringHostObject::decode(Cow::Owned(read_bytes), encoding).unwrap();
+        assert_eq!(decoded.to_utf8().unwrap(), string);
+        bytes
+    }
+
+    #[test]
+    fn file_round_trips() {
+        // Includes characters outside the BMP, which need surrogate pairs.
+        let string = "Ça va? 日本語 🎮";
+
+        let utf8 = round_trip_through_file(string, NSUTF8StringEncoding);
+        assert_eq!(utf8, string.as_bytes());
+
+        let utf16 = round_trip_through_file(string, NSUTF16StringEncoding);
+        assert_eq!(&utf16[..4], &[0xFF, 0xFE, b'\xC7', 0x00]);
+
+        let utf16_be = round_trip_through_file(string, NSUTF16BigEndianStringEncoding);
+        assert_eq!(&utf16_be[..2], &[0x00, 0xC7]);
+
+        let utf16_le = round_trip_through_file(string, NSUTF16LittleEndianStringEncoding);
+        assert_eq!(utf16_le, &utf16[2..]);
+    }
+
+    #[test]
+    fn utf16_byte_order_detection() {
+        let decode = |bytes: &[u8]| {
+            StringHostObject::decode(Cow::Borrowed(bytes), NSUTF16StringEncoding)
+                .map(|string| string.to_utf8().unwrap().into_owned())
+        };
+        assert_eq!(decode(b"\xFE\xFF\x00h\x00\xE9").as_deref(), Some("hé"));
+        assert_eq!(decode(b"\xFF\xFEh\x00\xE9\x00").as_deref(), Some("hé"));
+        // Big-endian is assumed without a byte order mark.
+        assert_eq!(decode(b"\x00h\x00\xE9").as_deref(), Some("hé"));
+        assert_eq!(decode(b"\xFF\xFEh"), None);
+    }
+
+    #[test]
+    fn prefix_and_suffix_with_multibyte_characters() {
+        let utf16 = |string: &str| string.encode_utf16().collect::<Utf16String>();
+        let string = utf16("日本語 🎮");
+
+        assert!(string.starts_with(&utf16("日本")));
+        assert!(!string.starts_with(&utf16("本")));
+        assert!(string.ends_with(&utf16(" 🎮")));
+        assert!(string.ends_with(&utf16("🎮")));
+        assert!(!string.ends_with(&utf16("語")));
+
+        // Case-insensitive comparison works beyond ASCII.
+        assert_eq!(fold_case(&utf16("ÇA VA")), utf16("ça va"));
+        assert_eq!(fold_case(&[0xD800, 0x41]), [0xD800u16, 0x61]);
+    }
+
+    #[test]
+    fn range_search() {
+        let utf16 = |string: &str| string.encode_utf16().collect::<Utf16String>();
+        let string = utf16("🎮 日本語 日本");
+
+        // Indices are in UTF-16 code units, so the emoji counts twice.
+        assert_eq!(find_code_units(&string, &utf16("日本")), Some(3));
+        assert_eq!(find_code_units(&string, &utf16("🎮")), Some(0));
+        // rangeOfString: turns these into NSNotFound.
+        assert_eq!(find_code_units(&string, &utf16("中")), None);
+        assert_eq!(find_code_units(&string, &utf16("")), None);
+        assert_eq!(find_code_units(&utf16("日"), &utf16("日本")), None);
+        assert_eq!(NSNotFound as NSUInteger, 0x7fffffff);
+    }
+
+    #[test]
+    fn case_mapping() {
+        let utf16 = |string: &str| string.encode_utf16().collect::<Utf16String>();
+        let map = |string: &str, mapping: fn(&str) -> String| {
+            String::from_utf16(&map_case(&utf16(string), mapping)).unwrap()
+        };
+
+        assert_eq!(map("Ça Va? ÉTÉ", str::to_lowercase), "ça va? été");
+        assert_eq!(map("straße", str::to_uppercase), "STRASSE");
+        // Sigma has a special form at the end of a word.
+        assert_eq!(map("ΌΣΟΣ", str::to_lowercase), "όσο\u{3C2}");
+        assert_eq!(
+            map("hello wORLD\tçava 日本", capitalize_words),
+            "Hello World\tÇava 日本"
+        );
+
+        // Unpaired surrogates survive, and the text around them is mapped.
+        let mut with_surrogate = utf16("a");
+        with_surrogate.push(0xDC00);
+        with_surrogate.extend(utf16("b"));
+        let mut expected = utf16("A");
+        expected.push(0xDC00);
+        expected.extend(utf16("B"));
+        assert_eq!(map_case(&with_surrogate, str::to_uppercase), expected);
+    }
+
+    #[test]
+    fn invalid_utf8() {
+        let decoded = StringHostObject::decode(Cow::Borrowed(b"\xC3("), NSUTF8StringEncoding);
+        assert!(decoded.is_none());
+        // An unpaired surrogate can't be encoded as UTF-8.
+        let unpaired = StringHostObject::Utf16(vec![0xD800]);
+        assert!(unpaired.encode(NSUTF8StringEncoding).is_none());
+        assert!(unpaired.encode(NSUTF16StringEncoding).is_some());
+    }
+
+    #[test]
+    fn ascii_and_latin1() {
+        let string = StringHostObject::Utf8(Cow::Borrowed("café"));
+        assert!(string.encode(NSASCIIStringEncoding).is_none());
+        let latin1 = string.encode(NSISOLatin1StringEncoding).unwrap();
+        assert_eq!(latin1, b"caf\xE9");
+        let decoded = StringHostObject::decode(Cow::Borrowed(&latin1), NSISOLatin1StringEncoding);
+        assert_eq!(decoded.unwrap().to_utf8().unwrap(), "café");
+        assert!(StringHostObject::decode(Cow::Borrowed(&latin1), NSASCIIStringEncoding).is_none());
+
+        let ascii = StringHostObject::Utf8(Cow::Borrowed("cafe"));
+        assert_eq!(ascii.encode(NSASCIIStringEncoding).unwrap(), b"cafe");
+        // Characters beyond Latin-1 can't be encoded.
+        let euro = StringHostObject::Utf8(Cow::Borrowed("€"));
+        assert!(euro.encode(NSISOLatin1StringEncoding).is_none());
+
+        // Unknown encodings fail rather than panicking.
+        assert!(ascii.encode(0x1234).is_none());
+        assert!(StringHostObject::decode(Cow::Borrowed(b"cafe"), 0x1234).is_none());
+    }
+}
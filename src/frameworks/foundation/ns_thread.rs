@@ -5,7 +5,52 @@
  */
 //! `NSThread`.
 
-use crate::objc::{objc_classes, ClassExports};
+use super::NSTimeInterval;
+use crate::dyld::{export_c_func, FunctionExports};
+use crate::mem::MutVoidPtr;
+use crate::objc::{
+    id, msg, msg_class, msg_send, nil, objc_classes, release, retain, ClassExports, HostObject, SEL,
+};
+use crate::{Environment, ThreadID};
+use std::collections::HashMap;
+use std::time::Duration;
+
+#[derive(Default)]
+pub struct State {
+    /// Strong references to the `NSThread*` for each thread that has one.
+    /// Threads started with `-start` are removed once they finish, and
+    /// objects created on-demand by `+currentThread` are removed when their
+    /// thread exits (see [thread_exited]).
+    threads: HashMap<ThreadID, id>,
+}
+impl State {
+    fn get(env: &mut Environment) -> &mut Self {
+        &mut env.framework_state.foundation.ns_thread
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum ThreadStatus {
+    NotStarted,
+    Executing,
+    Finished,
+}
+
+struct NSThreadHostObject {
+    /// Strong reference
+    target: id,
+    selector: Option<SEL>,
+    /// Strong reference
+    object: id,
+    /// Set once the thread is running (or for threads not created by
+    /// `NSThread`, once the object is created).
+    thread_id: Option<ThreadID>,
+    status: ThreadStatus,
+    /// Strong reference to an `NSMutableDictionary*`, created on first access
+    /// to `threadDictionary`.
+    thread_dictionary: id,
+}
+impl HostObject for NSThreadHostObject {}
 
 pub const CLASSES: ClassExports = objc_classes! {
 
@@ -13,13 +58,238 @@ pub const CLASSES: ClassExports = objc_classes! {
 
 @implementation NSThread: NSObject
 
++ (id)allocWithZone:(MutVoidPtr)_zone {
+    let host_object = Box::new(NSThreadHostObject {
+        target: nil,
+        selector: None,
+        object: nil,
+        thread_id: None,
+        status: ThreadStatus::NotStarted,
+        thread_dictionary: nil,
+    });
+    env.objc.alloc_object(this, host_object, &mut env.mem)
+}
+
++ (id)currentThread {
+    let current_thread = env.current_thread;
+    thread_object_for(env, current_thread)
+}
+
++ (id)mainThread {
+    thread_object_for(env, 0)
+}
+
++ (bool)isMainThread {
+    env.current_thread == 0
+}
+
 + (bool)setThreadPriority:(f64)priority {
     log!("TODO: [NSThread setThreadPriority:{:?}] (ignored)", priority);
     true
 }
 
-// TODO: construction etc
++ (())sleepForTimeInterval:(NSTimeInterval)ti {
+    log_dbg!("[NSThread sleepForTimeInterval:{:?}]", ti);
+    env.sleep(sleep_duration(ti));
+}
+
++ (())sleepUntilDate:(id)date {
+    let ti: NSTimeInterval = msg![env; date timeIntervalSinceNow];
+    log_dbg!("[NSThread sleepUntilDate:{:?}] ({:?}s from now)", date, ti);
+    env.sleep(sleep_duration(ti));
+}
+
++ (())detachNewThreadSelector:(SEL)selector
+                     toTarget:(id)target
+                   withObject:(id)object {
+    let thread: id = msg![env; this alloc];
+    let thread: id = msg![env; thread initWithTarget:target
+                                            selector:selector
+                                              object:object];
+    () = msg![env; thread start];
+    release(env, thread);
+}
+
+- (id)initWithTarget:(id)target
+            selector:(SEL)selector
+              object:(id)object {
+    retain(env, target);
+    retain(env, object);
+
+    let host_object = env.objc.borrow_mut::<NSThreadHostObject>(this);
+    host_object.target = target;
+    host_object.selector = Some(selector);
+    host_object.object = object;
+    this
+}
+
+- (())dealloc {
+    let &NSThreadHostObject {
+        target,
+        object,
+        thread_dictionary,
+        ..
+    } = env.objc.borrow(this);
+    release(env, target);
+    release(env, object);
+    release(env, thread_dictionary);
+    env.objc.dealloc_object(this, &mut env.mem)
+}
+
+- (())start {
+    let host_object = env.objc.borrow::<NSThreadHostObject>(this);
+    // TODO: raise proper exception
+    assert!(host_object.status == ThreadStatus::NotStarted);
+
+    // The helper is a host function, so a guest function that calls it is
+    // needed to use it as a thread's start routine.
+    let start_routine = env
+        .dyld
+        .create_proc_address(
+            &mut env.mem,
+            &mut env.cpu,
+            "__touchHLE_NSThreadInvocationHelper",
+        )
+        .unwrap();
+    let thread_id = env.new_thread(start_routine, this.cast());
+
+    let host_object = env.objc.borrow_mut::<NSThreadHostObject>(this);
+    host_object.thread_id = Some(thread_id);
+    host_object.status = ThreadStatus::Executing;
+
+    // Released by the invocation helper once the thread finishes.
+    retain(env, this);
+    State::get(env).threads.insert(thread_id, this);
+
+    log_dbg!("Started NSThread {:?} (thread ID: {})", this, thread_id);
+}
+
+- (())main {
+    let &NSThreadHostObject {
+        target,
+        selector,
+        object,
+        ..
+    } = env.objc.borrow(this);
+    if let Some(selector) = selector {
+        // Signature should be `- (void)threadMain:(id)object`.
+        let _: () = msg_send(env, (target, selector, object));
+    }
+}
+
+- (id)threadDictionary {
+    let existing = env.objc.borrow::<NSThreadHostObject>(this).thread_dictionary;
+    if existing != nil {
+        return existing;
+    }
+    let new: id = msg_class![env; NSMutableDictionary new];
+    env.objc.borrow_mut::<NSThreadHostObject>(this).thread_dictionary = new;
+    new
+}
+
+- (bool)isExecuting {
+    env.objc.borrow::<NSThreadHostObject>(this).status == ThreadStatus::Executing
+}
+- (bool)isFinished {
+    env.objc.borrow::<NSThreadHostObject>(this).status == ThreadStatus::Finished
+}
+- (bool)isMainThread {
+    env.objc.borrow::<NSThreadHostObject>(this).thread_id == Some(0)
+}
 
 @end
 
 };
+
+/// Get the `NSThread*` for a thread, creating it if the thread wasn't created
+/// by `NSThread` (e.g. the main thread, or a thread created with
+/// `pthread_create`).
+fn thread_object_for(env: &mut Environment, thread_id: ThreadID) -> id {
+    if let Some(&existing) = State::get(env).threads.get(&thread_id) {
+        return existing;
+    }
+
+    let new: id = msg_class![env; NSThread alloc];
+    let host_object = env.objc.borrow_mut::<NSThreadHostObject>(new);
+    host_object.thread_id = Some(thread_id);
+    host_object.status = ThreadStatus::Executing;
+    State::get(env).threads.insert(thread_id, new);
+    new
+}
+
+/// Start routine for threads created by `NSThread`. This sends the `main`
+/// message to the thread object, wrapped in an autorelease pool, and then
+/// releases the thread object.
+fn _touchHLE_NSThreadInvocationHelper(env: &mut Environment, thread: id) {
+    log_dbg!(
+        "NSThread {:?} (thread ID: {}) is running",
+        thread,
+        env.current_thread
+    );
+
+    let pool: id = msg_class![env; NSAutoreleasePool new];
+    let _: () = msg![env; thread main];
+    release(env, pool);
+
+    log_dbg!("NSThread {:?} finished", thread);
+
+    // The thread dictionary shouldn't outlive the thread, even if something
+    // else is still holding on to the NSThread object.
+    let host_object = env.objc.borrow_mut::<NSThreadHostObject>(thread);
+    host_object.status = ThreadStatus::Finished;
+    let thread_dictionary = std::mem::replace(&mut host_object.thread_dictionary, nil);
+    release(env, thread_dictionary);
+    let current_thread = env.current_thread;
+    State::get(env).threads.remove(&current_thread);
+    release(env, thread);
+}
+
+/// For use by [crate::Environment]: release the `NSThread*` created on-demand
+/// for a thread that is exiting, if there is one.
+pub fn thread_exited(env: &mut Environment, thread_id: ThreadID) {
+    let Some(thread) = State::get(env).threads.remove(&thread_id) else {
+        return;
+    };
+    log_dbg!(
+        "Releasing NSThread {:?} for exited thread {}",
+        thread,
+        thread_id
+    );
+    let host_object = env.objc.borrow_mut::<NSThreadHostObject>(thread);
+    host_object.status = ThreadStatus::Finished;
+    let thread_dictionary = std::mem::replace(&mut host_object.thread_dictionary, nil);
+    release(env, thread_dictionary);
+    release(env, thread);
+}
+
+/// Convert a time interval to sleep for to a [Duration]. Negative (or NaN)
+/// intervals mean not sleeping at all, and intervals too long to represent
+/// (e.g. until `distantFuture`) are clamped to something merely very long.
+fn sleep_duration(ti: NSTimeInterval) -> Duration {
+    if ti.is_nan() || ti <= 0.0 {
+        return Duration::ZERO;
+    }
+    const MAX_INTERVAL: Duration = Duration::from_secs(60 * 60 * 24 * 365);
+    Duration::try_from_secs_f64(ti)
+        .unwrap_or(MAX_INTERVAL)
+        .min(MAX_INTERVAL)
+}
+
+pub const FUNCTIONS: FunctionExports = &[export_c_func!(_touchHLE_NSThreadInvocationHelper(_))];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sleep_durations() {
+        assert_eq!(sleep_duration(1.5), Duration::from_millis(1500));
+        assert_eq!(sleep_duration(0.0), Duration::ZERO);
+        assert_eq!(sleep_duration(-1.0), Duration::ZERO);
+        assert_eq!(sleep_duration(f64::NAN), Duration::ZERO);
+        assert_eq!(sleep_duration(f64::NEG_INFINITY), Duration::ZERO);
+        let max = sleep_duration(f64::INFINITY);
+        assert!(max >= Duration::from_secs(60 * 60 * 24));
+        assert_eq!(sleep_duration(1e300), max);
+    }
+}
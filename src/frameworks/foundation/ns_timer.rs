@@ -4,10 +4,14 @@
  * file, You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 //! `NSTimer`.
+//!
+//! This is also used for `CFRunLoopTimer`, which is toll-free bridged with it.
 
 use super::ns_run_loop::NSDefaultRunLoopMode;
 use super::NSTimeInterval;
 use super::{ns_run_loop, ns_string};
+use crate::abi::{CallFromHost, GuestFunction};
+use crate::mem::MutVoidPtr;
 use crate::objc::{
     autorelease, id, msg, msg_class, msg_send, nil, objc_classes, release, retain, ClassExports,
     HostObject, SEL,
@@ -15,13 +19,32 @@ use crate::objc::{
 use crate::Environment;
 use std::time::{Duration, Instant};
 
+/// What a timer does when it fires.
+#[derive(Copy, Clone)]
+enum TimerAction {
+    /// Send a message, for timers created with `NSTimer` methods.
+    Message {
+        /// Strong reference
+        target: id,
+        selector: SEL,
+    },
+    /// Call a `CFRunLoopTimerCallBack`, for timers created with
+    /// `CFRunLoopTimerCreate()`.
+    Callback {
+        /// `void (*callout)(CFRunLoopTimerRef timer, void *info)`
+        callout: GuestFunction,
+        info: MutVoidPtr,
+        /// `void (*release)(const void *info)`, called when the timer is
+        /// deallocated.
+        release_info: Option<GuestFunction>,
+    },
+}
+
 struct NSTimerHostObject {
     ns_interval: NSTimeInterval,
     /// Copy of `ns_interval` in Rust's type for time intervals. Keep in sync!
     rust_interval: Duration,
-    /// Strong reference
-    target: id,
-    selector: SEL,
+    action: TimerAction,
     /// Strong reference
     user_info: id,
     repeats: bool,
@@ -52,8 +75,7 @@ pub const CLASSES: ClassExports = objc_classes! {
     let host_object = Box::new(NSTimerHostObject {
         ns_interval,
         rust_interval,
-        target,
-        selector,
+        action: TimerAction::Message { target, selector },
         user_info,
         repeats,
         due_by: Some(Instant::now().checked_add(rust_interval).unwrap()),
@@ -94,11 +116,21 @@ pub const CLASSES: ClassExports = objc_classes! {
 
 - (())dealloc {
     let &NSTimerHostObject {
-        target,
+        action,
         user_info,
         ..
     } = env.objc.borrow(this);
-    release(env, target);
+    match action {
+        TimerAction::Message { target, .. } => release(env, target),
+        TimerAction::Callback {
+            info,
+            release_info: Some(release_info),
+            ..
+        } => {
+            let () = release_info.call_from_host(env, (info.cast_const(),));
+        }
+        TimerAction::Callback { .. } => (),
+    }
     release(env, user_info);
     env.objc.dealloc_object(this, &mut env.mem)
 }
@@ -118,6 +150,30 @@ pub const CLASSES: ClassExports = objc_classes! {
     env.objc.borrow::<NSTimerHostObject>(this).due_by.is_some()
 }
 
+- (())invalidate {
+    invalidate(env, this);
+}
+
+- (())fire {
+    let &NSTimerHostObject {
+        action,
+        repeats,
+        due_by,
+        ..
+    } = env.objc.borrow(this);
+    if due_by.is_none() {
+        return;
+    }
+    // Firing early doesn't affect a repeating timer's schedule, but a
+    // non-repeating timer is invalidated.
+    retain(env, this);
+    fire_action(env, this, action);
+    if !repeats {
+        invalidate(env, this);
+    }
+    release(env, this);
+}
+
 // TODO: more constructors
 // TODO: more accessors
 
@@ -125,6 +181,56 @@ pub const CLASSES: ClassExports = objc_classes! {
 
 };
 
+/// For use by `CFRunLoopTimerCreate()`: create a timer that calls a guest
+/// function. `fire_in` is the time until the first firing, and the timer
+/// repeats if `interval` is positive. `release_info` is called with `info`
+/// when the timer is deallocated. The timer is returned with a reference
+/// count of 1.
+pub fn new_with_callback(
+    env: &mut Environment,
+    fire_in: NSTimeInterval,
+    interval: NSTimeInterval,
+    callout: GuestFunction,
+    info: MutVoidPtr,
+    release_info: Option<GuestFunction>,
+) -> id {
+    let repeats = interval > 0.0;
+    let ns_interval = interval.max(0.0001);
+    let rust_interval = Duration::from_secs_f64(ns_interval);
+
+    let host_object = Box::new(NSTimerHostObject {
+        ns_interval,
+        rust_interval,
+        action: TimerAction::Callback {
+            callout,
+            info,
+            release_info,
+        },
+        user_info: nil,
+        repeats,
+        due_by: Some(
+            Instant::now()
+                .checked_add(Duration::from_secs_f64(fire_in.max(0.0)))
+                .unwrap(),
+        ),
+        run_loop: nil,
+    });
+    let class = env.objc.get_known_class("NSTimer", &mut env.mem);
+    let new = env.objc.alloc_object(class, host_object, &mut env.mem);
+
+    log_dbg!(
+        "New {} timer {:?}, first firing in {}s, interval {}s, callback {:?} with info {:?}",
+        if repeats { "repeating" } else { "single-use" },
+        new,
+        fire_in,
+        ns_interval,
+        callout,
+        info,
+    );
+
+    new
+}
+
 /// For use by `NSRunLoop`
 pub(super) fn set_run_loop(env: &mut Environment, timer: id, run_loop: id) {
     let host_object = env.objc.borrow_mut::<NSTimerHostObject>(timer);
@@ -132,31 +238,112 @@ pub(super) fn set_run_loop(env: &mut Environment, timer: id, run_loop: id) {
     host_object.run_loop = run_loop;
 }
 
+/// Work out when a repeating timer that was due at `due_by` should next fire,
+/// if it fired at `now`. Also returns the number of intervals that were missed
+/// entirely. This is also used for other things that happen at regular
+/// intervals, e.g. accelerometer updates.
+pub fn reschedule(due_by: Instant, now: Instant, interval: Duration) -> (Instant, u32) {
+    let overdue_by = now.saturating_duration_since(due_by);
+
+    // When rescheduling a repeating timer, the next firing should be based
+    // on when the timer should have fired, not when it actually fired, so
+    // that there is no drift over time.
+    //
+    // For example, if a timer has an interval of 60s and starts at 00:00,
+    // the first firing would be scheduled for 01:00, and the second firing
+    // should be scheduled for 02:00, even if the first firing was at 01:01.
+    //
+    // However: if the timer handling is delayed past a whole interval, it
+    // should not try to catch up. For example, if the first firing is
+    // scheduled for 01:00 but happens at 02:30, then the next firing should
+    // be scheduled for 03:00.
+    // TODO: Use `.div_duration_f64()` once that is stabilized.
+    let advance_by = (overdue_by.as_secs_f64() / interval.as_secs_f64())
+        .max(1.0)
+        .ceil();
+    assert!(advance_by == (advance_by as u32) as f64);
+    let advance_by = advance_by as u32;
+    let next = due_by
+        .checked_add(interval.checked_mul(advance_by).unwrap())
+        .unwrap();
+    (next, advance_by - 1)
+}
+
+/// Send the timer's message or call its callback.
+fn fire_action(env: &mut Environment, timer: id, action: TimerAction) {
+    let pool: id = msg_class![env; NSAutoreleasePool new];
+
+    match action {
+        TimerAction::Message { target, selector } => {
+            log_dbg!(
+                "Timer {:?} fired, sending {:?} message to {:?}",
+                timer,
+                selector.as_str(&env.mem),
+                target
+            );
+            // Signature should be `- (void)timerDidFire:(NSTimer *)which`.
+            let _: () = msg_send(env, (target, selector, timer));
+        }
+        TimerAction::Callback { callout, info, .. } => {
+            log_dbg!(
+                "Timer {:?} fired, calling {:?} with info {:?}",
+                timer,
+                callout,
+                info
+            );
+            let () = callout.call_from_host(env, (timer, info));
+        }
+    }
+
+    release(env, pool);
+}
+
+/// Stop a timer from firing again, remove it from its run loop, and release
+/// its target and user info. It's safe to call this more than once.
+fn invalidate(env: &mut Environment, timer: id) {
+    let host_object = env.objc.borrow_mut::<NSTimerHostObject>(timer);
+    host_object.due_by = None;
+    let run_loop = std::mem::replace(&mut host_object.run_loop, nil);
+    let user_info = std::mem::replace(&mut host_object.user_info, nil);
+    let target = match &mut host_object.action {
+        TimerAction::Message { target, .. } => std::mem::replace(target, nil),
+        TimerAction::Callback { .. } => nil,
+    };
+
+    log_dbg!("Invalidating timer {:?}", timer);
+
+    release(env, target);
+    release(env, user_info);
+    // This must be last, because the run loop might own the only reference
+    // to the timer.
+    if run_loop != nil {
+        ns_run_loop::remove_timer(env, run_loop, timer);
+    }
+}
+
 /// For use by `NSRunLoop`: check if a timer is due to fire and fire it if
-/// necessary.
-pub(super) fn handle_timer(env: &mut Environment, timer: id) {
+/// necessary. Returns [true] if it fired.
+pub(super) fn handle_timer(env: &mut Environment, timer: id) -> bool {
     let &NSTimerHostObject {
-        ns_interval,
         rust_interval,
-        target,
-        selector,
+        action,
         repeats,
         due_by,
-        run_loop,
         ..
     } = env.objc.borrow(timer);
 
-    // invalidated timers should have already been removed from the run loop
-    let due_by = due_by.unwrap();
+    // The timer might have been invalidated by another timer's message, and
+    // not yet removed from the run loop.
+    let Some(due_by) = due_by else {
+        return false;
+    };
 
     let now = Instant::now();
 
     if due_by > now {
-        return;
+        return false;
     }
 
-    let overdue_by = now.duration_since(due_by);
-
     // Timer may be released when it's invalidated, so we need to retain it so
     // it's still around to pass to the timer target.
     retain(env, timer);
@@ -164,45 +351,75 @@ pub(super) fn handle_timer(env: &mut Environment, timer: id) {
     // Advancing the timer before sending its message seems like a good idea
     // considering this function is potentially re-entrant.
     if repeats {
-        // When rescheduling a repeating timer, the next firing should be based
-        // on when the timer should have fired, not when it actually fired, so
-        // that there is no drift over time.
-        //
-        // For example, if a timer has an interval of 60s and starts at 00:00,
-        // the first firing would be scheduled for 01:00, and the second firing
-        // should be scheduled for 02:00, even if the first firing was at 01:01.
-        //
-        // However: if the timer handling is delayed past a whole interval, it
-        // should not try to catch up. For example, if the first firing is
-        // scheduled for 01:00 but happens at 02:30, then the next firing should
-        // be scheduled for 03:00.
-        // TODO: Use `.div_duration_f64()` once that is stabilized.
-        let advance_by = (overdue_by.as_secs_f64() / ns_interval).max(1.0).ceil();
-        assert!(advance_by == (advance_by as u32) as f64);
-        let advance_by = advance_by as u32;
-        if advance_by > 1 {
-            log!("Warning: Timer {:?} is lagging. It is overdue by {}s and has missed {} interval(s)!", timer, overdue_by.as_secs_f64(), advance_by - 1);
+        let (next, missed) = reschedule(due_by, now, rust_interval);
+        if missed > 0 {
+            log!(
+                "Warning: Timer {:?} is lagging. It is overdue by {}s and has missed {} interval(s)!",
+                timer,
+                now.duration_since(due_by).as_secs_f64(),
+                missed
+            );
         }
-        let advance_by = rust_interval.checked_mul(advance_by).unwrap();
-        env.objc.borrow_mut::<NSTimerHostObject>(timer).due_by =
-            Some(due_by.checked_add(advance_by).unwrap());
+        env.objc.borrow_mut::<NSTimerHostObject>(timer).due_by = Some(next);
+        fire_action(env, timer, action);
     } else {
         env.objc.borrow_mut::<NSTimerHostObject>(timer).due_by = None;
-        ns_run_loop::remove_timer(env, run_loop, timer);
+        fire_action(env, timer, action);
+        invalidate(env, timer);
     }
 
-    log_dbg!(
-        "Timer {:?} fired, sending {:?} message to {:?}",
-        timer,
-        selector.as_str(&env.mem),
-        target
-    );
+    release(env, timer);
 
-    let pool: id = msg_class![env; NSAutoreleasePool new];
+    true
+}
 
-    // Signature should be `- (void)timerDidFire:(NSTimer *)which`.
-    let _: () = msg_send(env, (target, selector, timer));
+#[cfg(test)]
+mod tests {
+    use super::reschedule;
+    use std::time::{Duration, Instant};
 
-    release(env, timer);
-    release(env, pool);
+    /// Simulate a run loop checking a repeating timer at regular intervals,
+    /// and count how many times it fires.
+    fn count_firings(interval_ms: u64, tick_ms: u64, total_ms: u64) -> u32 {
+        let start = Instant::now();
+        let interval = Duration::from_millis(interval_ms);
+        let mut due_by = start + interval;
+        let mut firings = 0;
+        let mut elapsed = 0;
+        while elapsed <= total_ms {
+            let now = start + Duration::from_millis(elapsed);
+            if due_by <= now {
+                firings += 1;
+                due_by = reschedule(due_by, now, interval).0;
+            }
+            elapsed += tick_ms;
+        }
+        firings
+    }
+
+    #[test]
+    fn repeating_timer_fires_expected_number_of_times() {
+        // 10 times per second, checked at roughly 60Hz
+        assert_eq!(count_firings(100, 16, 1010), 10);
+        // Checked at exactly the right times
+        assert_eq!(count_firings(100, 100, 1000), 10);
+        // Checked more slowly than the timer's rate: missed firings are not
+        // caught up on, so it fires once per check
+        assert_eq!(count_firings(50, 120, 1200), 10);
+    }
+
+    #[test]
+    fn no_drift_or_catch_up() {
+        let start = Instant::now();
+        let interval = Duration::from_secs(60);
+        let due_by = start + interval;
+        // Fired a bit late: the next firing is still on schedule
+        let (next, missed) = reschedule(due_by, due_by + Duration::from_secs(1), interval);
+        assert_eq!(next, start + Duration::from_secs(120));
+        assert_eq!(missed, 0);
+        // Fired very late: skip to the next scheduled time in the future
+        let (next, missed) = reschedule(due_by, due_by + Duration::from_secs(90), interval);
+        assert_eq!(next, start + Duration::from_secs(180));
+        assert_eq!(missed, 1);
+    }
 }
@@ -89,6 +89,10 @@ pub const CLASSES: ClassExports = objc_classes! {
 }
 
 - (id)absoluteURL {
+    // FIXME: don't assume URL is already absolute
+    this
+}
+- (id)absoluteString {
     // FIXME: don't assume URL is already absolute
     let &NSURLHostObject::OtherURL { ns_string } = env.objc.borrow(this) else {
         unimplemented!(); // TODO
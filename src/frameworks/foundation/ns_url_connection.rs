@@ -0,0 +1,289 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! `NSURLConnection`.
+//!
+//! Requests are sent by [crate::http] on a host thread, and the results are
+//! delivered to the delegate by the run loop. Network access is disabled
+//! unless the user passes `--allow-network-access`. Otherwise, all requests
+//! fail as if the device were offline.
+
+use super::{ns_data, ns_run_loop, ns_url_request, ns_url_response, set_error_nil};
+use crate::http;
+use crate::mem::{MutPtr, MutVoidPtr};
+use crate::objc::{
+    autorelease, id, msg, msg_class, msg_send, nil, objc_classes, release, retain, ClassExports,
+    HostObject,
+};
+use crate::Environment;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+
+type HttpResult = Result<http::Response, String>;
+
+enum ConnectionState {
+    NotStarted,
+    /// Waiting for the host thread to send the result.
+    Loading(Receiver<HttpResult>),
+    /// The delegate has been told about the result, or the connection was
+    /// cancelled.
+    Finished,
+}
+
+struct NSURLConnectionHostObject {
+    /// Strong reference to `NSURLRequest*`
+    request: id,
+    /// Strong reference, until the connection finishes.
+    delegate: id,
+    state: ConnectionState,
+    /// Weak reference. The run loop the connection is scheduled in, if any.
+    run_loop: Option<id>,
+}
+impl HostObject for NSURLConnectionHostObject {}
+
+pub const CLASSES: ClassExports = objc_classes! {
+
+(env, this, _cmd);
+
+@implementation NSURLConnection: NSObject
+
++ (id)allocWithZone:(MutVoidPtr)_zone {
+    let host_object = Box::new(NSURLConnectionHostObject {
+        request: nil,
+        delegate: nil,
+        state: ConnectionState::NotStarted,
+        run_loop: None,
+    });
+    env.objc.alloc_object(this, host_object, &mut env.mem)
+}
+
++ (id)sendSynchronousRequest:(id)request // NSURLRequest*
+           returningResponse:(MutPtr<id>)response // NSURLResponse**
+                       error:(MutPtr<id>)error { // NSError**
+    let url: id = msg![env; request URL];
+    let result = send_request(env, request);
+    let http_response = match result {
+        Ok(http_response) => http_response,
+        Err(message) => {
+            log!("Warning: synchronous request failed: {}", message);
+            if !response.is_null() {
+                env.mem.write(response, nil);
+            }
+            set_error_nil(env, error);
+            return nil;
+        }
+    };
+    if !response.is_null() {
+        let new = ns_url_response::from_http_response(env, url, &http_response);
+        let new = autorelease(env, new);
+        env.mem.write(response, new);
+    }
+    let data = ns_data::from_vec(env, http_response.body);
+    autorelease(env, data)
+}
+
++ (id)connectionWithRequest:(id)request // NSURLRequest*
+                   delegate:(id)delegate {
+    let new: id = msg![env; this alloc];
+    let new: id = msg![env; new initWithRequest:request delegate:delegate];
+    autorelease(env, new)
+}
+
+- (id)initWithRequest:(id)request // NSURLRequest*
+             delegate:(id)delegate {
+    msg![env; this initWithRequest:request delegate:delegate startImmediately:true]
+}
+- (id)initWithRequest:(id)request // NSURLRequest*
+             delegate:(id)delegate
+     startImmediately:(bool)start_immediately {
+    let request: id = msg![env; request copy];
+    retain(env, delegate);
+    let host_object = env.objc.borrow_mut::<NSURLConnectionHostObject>(this);
+    host_object.request = request;
+    host_object.delegate = delegate;
+    if start_immediately {
+        () = msg![env; this start];
+    }
+    this
+}
+
+- (())dealloc {
+    let &NSURLConnectionHostObject {
+        request, delegate, ..
+    } = env.objc.borrow(this);
+    release(env, request);
+    release(env, delegate);
+    env.objc.dealloc_object(this, &mut env.mem)
+}
+
+- (id)originalRequest {
+    env.objc.borrow::<NSURLConnectionHostObject>(this).request
+}
+
+- (())start {
+    let host_object = env.objc.borrow::<NSURLConnectionHostObject>(this);
+    if !matches!(host_object.state, ConnectionState::NotStarted) {
+        return;
+    }
+    let request = host_object.request;
+
+    let (sender, receiver) = mpsc::channel();
+    if env.options.network_access {
+        let http_request = ns_url_request::to_http_request(env, request);
+        log_dbg!(
+            "{:?} starting {} request for {:?}",
+            this,
+            http_request.method,
+            http_request.url
+        );
+        std::thread::spawn(move || {
+            // The receiver might have been dropped by cancellation.
+            let _ = sender.send(http::send(&http_request));
+        });
+    } else {
+        // The delegate still needs to be told, but not before this returns.
+        sender.send(Err(network_disabled_message())).unwrap();
+    }
+
+    // TODO: scheduling in other run loops and modes
+    let run_loop: id = msg_class![env; NSRunLoop currentRunLoop];
+    let host_object = env.objc.borrow_mut::<NSURLConnectionHostObject>(this);
+    host_object.state = ConnectionState::Loading(receiver);
+    host_object.run_loop = Some(run_loop);
+    ns_run_loop::add_url_connection(env, run_loop, this);
+}
+
+- (())cancel {
+    log_dbg!("{:?} cancelled", this);
+    finish(env, this);
+}
+
+@end
+
+};
+
+fn network_disabled_message() -> String {
+    "Network access is disabled. Use the --allow-network-access option to enable it.".to_string()
+}
+
+/// Send a request and wait for the result, unless network access is disabled.
+fn send_request(env: &mut Environment, request: id) -> HttpResult {
+    if !env.options.network_access {
+        return Err(network_disabled_message());
+    }
+    let http_request = ns_url_request::to_http_request(env, request);
+    log_dbg!(
+        "Sending synchronous {} request for {:?}",
+        http_request.method,
+        http_request.url
+    );
+    http::send(&http_request)
+}
+
+fn is_finished(env: &mut Environment, connection: id) -> bool {
+    matches!(
+        env.objc
+            .borrow::<NSURLConnectionHostObject>(connection)
+            .state,
+        ConnectionState::Finished
+    )
+}
+
+/// Stop the connection, if it's not already finished, and let go of the
+/// delegate.
+fn finish(env: &mut Environment, connection: id) {
+    let host_object = env.objc.borrow_mut::<NSURLConnectionHostObject>(connection);
+    if matches!(host_object.state, ConnectionState::Finished) {
+        return;
+    }
+    host_object.state = ConnectionState::Finished;
+    let delegate = std::mem::replace(&mut host_object.delegate, nil);
+    let run_loop = host_object.run_loop.take();
+    release(env, delegate);
+    // This might release the last reference, so it must come last.
+    if let Some(run_loop) = run_loop {
+        ns_run_loop::remove_url_connection(env, run_loop, connection);
+    }
+}
+
+/// Send a message to the delegate if it implements it.
+fn send_to_delegate(env: &mut Environment, delegate: id, selector: &str, args: &[id]) {
+    let Some(sel) = env.objc.lookup_selector(selector) else {
+        return;
+    };
+    if delegate == nil || !msg![env; delegate respondsToSelector:sel] {
+        return;
+    }
+    match *args {
+        [connection] => msg_send(env, (delegate, sel, connection)),
+        [connection, arg] => msg_send(env, (delegate, sel, connection, arg)),
+        _ => unreachable!(),
+    }
+}
+
+/// For use by `NSRunLoop`: if the connection's result has arrived, tell the
+/// delegate about it. Returns [true] if it did.
+pub(super) fn handle_url_connection(env: &mut Environment, connection: id) -> bool {
+    let host_object = env.objc.borrow::<NSURLConnectionHostObject>(connection);
+    let ConnectionState::Loading(ref receiver) = host_object.state else {
+        return false;
+    };
+    let result = match receiver.try_recv() {
+        Ok(result) => result,
+        Err(TryRecvError::Empty) => return false,
+        Err(TryRecvError::Disconnected) => Err("Request thread panicked".to_string()),
+    };
+    let delegate = host_object.delegate;
+    let request = host_object.request;
+
+    // The delegate might release the connection, but it should survive until
+    // the delegate has been told everything.
+    retain(env, connection);
+    match result {
+        Ok(http_response) => {
+            log_dbg!(
+                "{:?} received response with status {} and {} bytes of data",
+                connection,
+                http_response.status_code,
+                http_response.body.len()
+            );
+            let url: id = msg![env; request URL];
+            let response = ns_url_response::from_http_response(env, url, &http_response);
+            send_to_delegate(
+                env,
+                delegate,
+                "connection:didReceiveResponse:",
+                &[connection, response],
+            );
+            release(env, response);
+            // The delegate might cancel the connection at any point.
+            if !http_response.body.is_empty() && !is_finished(env, connection) {
+                let data = ns_data::from_vec(env, http_response.body);
+                send_to_delegate(
+                    env,
+                    delegate,
+                    "connection:didReceiveData:",
+                    &[connection, data],
+                );
+                release(env, data);
+            }
+            if !is_finished(env, connection) {
+                send_to_delegate(env, delegate, "connectionDidFinishLoading:", &[connection]);
+            }
+        }
+        Err(message) => {
+            log!("Warning: {:?} failed: {}", connection, message);
+            // TODO: create a real NSError
+            send_to_delegate(
+                env,
+                delegate,
+                "connection:didFailWithError:",
+                &[connection, nil],
+            );
+        }
+    }
+    finish(env, connection);
+    release(env, connection);
+    true
+}
@@ -0,0 +1,260 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! `NSURLRequest` and `NSMutableURLRequest`.
+
+use super::ns_dictionary::dict_from_keys_and_objects;
+use super::ns_string::{from_rust_string, to_rust_string};
+use super::{ns_data, NSTimeInterval, NSUInteger};
+use crate::http;
+use crate::mem::MutVoidPtr;
+use crate::objc::{
+    autorelease, id, msg, nil, objc_classes, release, retain, Class, ClassExports, HostObject,
+};
+use crate::Environment;
+use std::time::Duration;
+
+type NSURLRequestCachePolicy = NSUInteger;
+const NSURLRequestUseProtocolCachePolicy: NSURLRequestCachePolicy = 0;
+
+/// The default for requests created without an explicit timeout.
+const DEFAULT_TIMEOUT_INTERVAL: NSTimeInterval = 60.0;
+
+#[derive(Clone)]
+struct NSURLRequestHostObject {
+    /// Strong reference to `NSURL*`
+    url: id,
+    method: String,
+    /// In order of first being set. Names are case-insensitive.
+    headers: Vec<(String, String)>,
+    /// Strong reference to `NSData*`, or `nil`
+    body: id,
+    timeout_interval: NSTimeInterval,
+}
+impl HostObject for NSURLRequestHostObject {}
+impl NSURLRequestHostObject {
+    fn header_index(&self, name: &str) -> Option<usize> {
+        self.headers
+            .iter()
+            .position(|(other_name, _)| other_name.eq_ignore_ascii_case(name))
+    }
+}
+
+pub const CLASSES: ClassExports = objc_classes! {
+
+(env, this, _cmd);
+
+@implementation NSURLRequest: NSObject
+
++ (id)allocWithZone:(MutVoidPtr)_zone {
+    let host_object = Box::new(NSURLRequestHostObject {
+        url: nil,
+        method: "GET".to_string(),
+        headers: Vec::new(),
+        body: nil,
+        timeout_interval: DEFAULT_TIMEOUT_INTERVAL,
+    });
+    env.objc.alloc_object(this, host_object, &mut env.mem)
+}
+
++ (id)requestWithURL:(id)url { // NSURL*
+    let new: id = msg![env; this alloc];
+    let new: id = msg![env; new initWithURL:url];
+    autorelease(env, new)
+}
++ (id)requestWithURL:(id)url // NSURL*
+         cachePolicy:(NSURLRequestCachePolicy)cache_policy
+     timeoutInterval:(NSTimeInterval)timeout_interval {
+    let new: id = msg![env; this alloc];
+    let new: id = msg![env; new initWithURL:url
+                                cachePolicy:cache_policy
+                            timeoutInterval:timeout_interval];
+    autorelease(env, new)
+}
+
+- (id)initWithURL:(id)url { // NSURL*
+    msg![env; this initWithURL:url
+                   cachePolicy:NSURLRequestUseProtocolCachePolicy
+               timeoutInterval:DEFAULT_TIMEOUT_INTERVAL]
+}
+- (id)initWithURL:(id)url // NSURL*
+      cachePolicy:(NSURLRequestCachePolicy)_cache_policy
+  timeoutInterval:(NSTimeInterval)timeout_interval {
+    // TODO: caching
+    let url: id = msg![env; url copy];
+    let host_object = env.objc.borrow_mut::<NSURLRequestHostObject>(this);
+    host_object.url = url;
+    host_object.timeout_interval = timeout_interval;
+    this
+}
+
+- (())dealloc {
+    let &NSURLRequestHostObject { url, body, .. } = env.objc.borrow(this);
+    release(env, url);
+    release(env, body);
+    env.objc.dealloc_object(this, &mut env.mem)
+}
+
+// NSCopying implementation
+- (id)copyWithZone:(MutVoidPtr)_zone {
+    retain(env, this)
+}
+
+// NSMutableCopying implementation
+- (id)mutableCopyWithZone:(MutVoidPtr)_zone {
+    let class = env.objc.get_known_class("NSMutableURLRequest", &mut env.mem);
+    copy_request(env, this, class)
+}
+
+- (id)URL {
+    env.objc.borrow::<NSURLRequestHostObject>(this).url
+}
+- (id)HTTPMethod {
+    let method = env.objc.borrow::<NSURLRequestHostObject>(this).method.clone();
+    let method = from_rust_string(env, method);
+    autorelease(env, method)
+}
+- (id)HTTPBody {
+    env.objc.borrow::<NSURLRequestHostObject>(this).body
+}
+- (NSTimeInterval)timeoutInterval {
+    env.objc.borrow::<NSURLRequestHostObject>(this).timeout_interval
+}
+
+- (id)valueForHTTPHeaderField:(id)field { // NSString*
+    let field = to_rust_string(env, field);
+    let host_object = env.objc.borrow::<NSURLRequestHostObject>(this);
+    let Some(idx) = host_object.header_index(&field) else {
+        return nil;
+    };
+    let value = host_object.headers[idx].1.clone();
+    let value = from_rust_string(env, value);
+    autorelease(env, value)
+}
+- (id)allHTTPHeaderFields {
+    let headers = env.objc.borrow::<NSURLRequestHostObject>(this).headers.clone();
+    let dict = headers_to_dict(env, &headers);
+    autorelease(env, dict)
+}
+
+@end
+
+@implementation NSMutableURLRequest: NSURLRequest
+
+// NSCopying implementation (returns an immutable copy)
+- (id)copyWithZone:(MutVoidPtr)_zone {
+    let class = env.objc.get_known_class("NSURLRequest", &mut env.mem);
+    copy_request(env, this, class)
+}
+
+- (())setURL:(id)url { // NSURL*
+    let url: id = msg![env; url copy];
+    let old = std::mem::replace(
+        &mut env.objc.borrow_mut::<NSURLRequestHostObject>(this).url,
+        url,
+    );
+    release(env, old);
+}
+- (())setHTTPMethod:(id)method { // NSString*
+    let method = to_rust_string(env, method).into_owned();
+    env.objc.borrow_mut::<NSURLRequestHostObject>(this).method = method;
+}
+- (())setHTTPBody:(id)body { // NSData*
+    let body: id = if body == nil { nil } else { msg![env; body copy] };
+    let old = std::mem::replace(
+        &mut env.objc.borrow_mut::<NSURLRequestHostObject>(this).body,
+        body,
+    );
+    release(env, old);
+}
+- (())setTimeoutInterval:(NSTimeInterval)timeout_interval {
+    env.objc.borrow_mut::<NSURLRequestHostObject>(this).timeout_interval = timeout_interval;
+}
+- (())setCachePolicy:(NSURLRequestCachePolicy)_cache_policy {
+    // TODO: caching
+}
+
+- (())setValue:(id)value // NSString*
+forHTTPHeaderField:(id)field { // NSString*
+    let field = to_rust_string(env, field).into_owned();
+    let value = (value != nil).then(|| to_rust_string(env, value).into_owned());
+    let host_object = env.objc.borrow_mut::<NSURLRequestHostObject>(this);
+    match (host_object.header_index(&field), value) {
+        (Some(idx), Some(value)) => host_object.headers[idx].1 = value,
+        (Some(idx), None) => {
+            host_object.headers.remove(idx);
+        }
+        (None, Some(value)) => host_object.headers.push((field, value)),
+        (None, None) => (),
+    }
+}
+- (())addValue:(id)value // NSString*
+forHTTPHeaderField:(id)field { // NSString*
+    let field = to_rust_string(env, field).into_owned();
+    let value = to_rust_string(env, value);
+    let host_object = env.objc.borrow_mut::<NSURLRequestHostObject>(this);
+    // Multiple values for a header are comma-separated.
+    if let Some(idx) = host_object.header_index(&field) {
+        let existing = &mut host_object.headers[idx].1;
+        existing.push(',');
+        existing.push_str(&value);
+    } else {
+        host_object.headers.push((field, value.into_owned()));
+    }
+}
+
+@end
+
+};
+
+/// Create a new request object of the given class with the same contents.
+fn copy_request(env: &mut Environment, request: id, class: Class) -> id {
+    let host_object = env.objc.borrow::<NSURLRequestHostObject>(request).clone();
+    retain(env, host_object.url);
+    retain(env, host_object.body);
+    let new: id = msg![env; class alloc];
+    *env.objc.borrow_mut(new) = host_object;
+    new
+}
+
+/// For use by `NSURLConnection`: get everything needed to send the request.
+pub(super) fn to_http_request(env: &mut Environment, request: id) -> http::Request {
+    let host_object = env.objc.borrow::<NSURLRequestHostObject>(request).clone();
+    let url: id = msg![env; (host_object.url) absoluteString];
+    let url = to_rust_string(env, url).into_owned();
+    let body = if host_object.body == nil {
+        Vec::new()
+    } else {
+        ns_data::to_rust_slice(env, host_object.body).to_vec()
+    };
+    // Invalid (e.g. infinite) timeouts become zero, which means no timeout.
+    let timeout = Duration::try_from_secs_f64(host_object.timeout_interval).unwrap_or_default();
+    http::Request {
+        method: host_object.method,
+        url,
+        headers: host_object.headers,
+        body,
+        timeout,
+    }
+}
+
+/// Create an `NSDictionary` (with a +1 reference count) of HTTP header names
+/// and values. Also used by `NSHTTPURLResponse`.
+pub(super) fn headers_to_dict(env: &mut Environment, headers: &[(String, String)]) -> id {
+    let keys_and_objects: Vec<(id, id)> = headers
+        .iter()
+        .map(|(name, value)| {
+            let name = from_rust_string(env, name.clone());
+            let value = from_rust_string(env, value.clone());
+            (name, value)
+        })
+        .collect();
+    let dict = dict_from_keys_and_objects(env, &keys_and_objects);
+    for (name, value) in keys_and_objects {
+        release(env, name);
+        release(env, value);
+    }
+    dict
+}
@@ -0,0 +1,96 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! `NSURLResponse` and `NSHTTPURLResponse`.
+
+use super::ns_url_request::headers_to_dict;
+use super::NSInteger;
+use crate::http;
+use crate::mem::MutVoidPtr;
+use crate::objc::{
+    autorelease, id, msg_class, nil, objc_classes, release, retain, ClassExports, HostObject,
+};
+use crate::Environment;
+
+struct NSURLResponseHostObject {
+    /// Strong reference to `NSURL*`
+    url: id,
+    status_code: NSInteger,
+    headers: Vec<(String, String)>,
+    /// -1 if unknown.
+    expected_content_length: i64,
+}
+impl HostObject for NSURLResponseHostObject {}
+
+pub const CLASSES: ClassExports = objc_classes! {
+
+(env, this, _cmd);
+
+@implementation NSURLResponse: NSObject
+
++ (id)allocWithZone:(MutVoidPtr)_zone {
+    let host_object = Box::new(NSURLResponseHostObject {
+        url: nil,
+        status_code: 0,
+        headers: Vec::new(),
+        expected_content_length: -1,
+    });
+    env.objc.alloc_object(this, host_object, &mut env.mem)
+}
+
+- (())dealloc {
+    let url = env.objc.borrow::<NSURLResponseHostObject>(this).url;
+    release(env, url);
+    env.objc.dealloc_object(this, &mut env.mem)
+}
+
+// NSCopying implementation
+- (id)copyWithZone:(MutVoidPtr)_zone {
+    retain(env, this)
+}
+
+- (id)URL {
+    env.objc.borrow::<NSURLResponseHostObject>(this).url
+}
+- (i64)expectedContentLength {
+    env.objc.borrow::<NSURLResponseHostObject>(this).expected_content_length
+}
+
+// TODO: MIMEType, textEncodingName, suggestedFilename
+
+@end
+
+@implementation NSHTTPURLResponse: NSURLResponse
+
+- (NSInteger)statusCode {
+    env.objc.borrow::<NSURLResponseHostObject>(this).status_code
+}
+- (id)allHeaderFields {
+    let headers = env.objc.borrow::<NSURLResponseHostObject>(this).headers.clone();
+    let dict = headers_to_dict(env, &headers);
+    autorelease(env, dict)
+}
+
+@end
+
+};
+
+/// For use by `NSURLConnection`: create an `NSHTTPURLResponse` (with a +1
+/// reference count) for a response to a request for `url` (`NSURL*`).
+pub(super) fn from_http_response(env: &mut Environment, url: id, response: &http::Response) -> id {
+    retain(env, url);
+    let expected_content_length = response
+        .header("Content-Length")
+        .and_then(|length| length.parse().ok())
+        .unwrap_or(-1);
+    let new: id = msg_class![env; NSHTTPURLResponse alloc];
+    *env.objc.borrow_mut(new) = NSURLResponseHostObject {
+        url,
+        status_code: response.status_code.into(),
+        headers: response.headers.clone(),
+        expected_content_length,
+    };
+    new
+}
@@ -0,0 +1,420 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! `NSUserDefaults`.
+//!
+//! Like on iPhone OS, the values set by the app are stored in a property list
+//! file in `Library/Preferences` within the app's sandbox, named after the
+//! bundle identifier.
+
+use super::ns_property_list_serialization::{from_plist_value, to_plist_value};
+use super::ns_string::{from_rust_string, to_rust_string};
+use super::NSInteger;
+use crate::fs::{GuestOpenOptions, GuestPathBuf};
+use crate::objc::{autorelease, id, msg, nil, objc_classes, ClassExports, HostObject};
+use crate::Environment;
+use plist::{Dictionary, Value};
+use std::io::{Cursor, Write};
+
+#[derive(Default)]
+pub struct State {
+    /// [NSUserDefaults standardUserDefaults]
+    standard_user_defaults: Option<id>,
+}
+impl State {
+    fn get(env: &mut Environment) -> &mut Self {
+        &mut env.framework_state.foundation.ns_user_defaults
+    }
+}
+
+#[derive(Default)]
+struct NSUserDefaultsHostObject {
+    /// Values set by the app. These are the ones that get saved.
+    values: Dictionary,
+    /// Fallback values provided with `registerDefaults:`. These are not saved.
+    registered: Dictionary,
+    /// Whether `values` has been changed since it was last saved.
+    dirty: bool,
+}
+impl HostObject for NSUserDefaultsHostObject {}
+impl NSUserDefaultsHostObject {
+    fn lookup(&self, key: &str) -> Option<&Value> {
+        self.values.get(key).or_else(|| self.registered.get(key))
+    }
+    fn set(&mut self, key: String, value: Value) {
+        self.values.insert(key, value);
+        self.dirty = true;
+    }
+    fn remove(&mut self, key: &str) {
+        if self.values.remove(key).is_some() {
+            self.dirty = true;
+        }
+    }
+}
+
+pub const CLASSES: ClassExports = objc_classes! {
+
+(env, this, _cmd);
+
+@implementation NSUserDefaults: NSObject
+
++ (id)standardUserDefaults {
+    if let Some(existing) = State::get(env).standard_user_defaults {
+        return existing;
+    }
+
+    let path = preferences_path(env);
+    let values = match env.fs.read(&path) {
+        Ok(bytes) => parse_defaults(&bytes).unwrap_or_else(|| {
+            log!("Warning: couldn't parse user defaults at {:?}, ignoring them", path);
+            Dictionary::new()
+        }),
+        Err(()) => Dictionary::new(),
+    };
+    let host_object = Box::new(NSUserDefaultsHostObject {
+        values,
+        ..Default::default()
+    });
+    let new = env.objc.alloc_static_object(this, host_object, &mut env.mem);
+    State::get(env).standard_user_defaults = Some(new);
+    new
+}
+
+// This is a singleton, it shouldn't be deallocated.
+- (id)retain { this }
+- (id)autorelease { this }
+- (())release {}
+
+- (())registerDefaults:(id)dictionary { // NSDictionary*
+    let Some(Value::Dictionary(defaults)) = to_plist_value(env, dictionary) else {
+        log!("Warning: ignoring registerDefaults: with non-dictionary {:?}", dictionary);
+        return;
+    };
+    let host_object = env.objc.borrow_mut::<NSUserDefaultsHostObject>(this);
+    for (key, value) in defaults {
+        host_object.registered.insert(key, value);
+    }
+}
+
+- (id)dictionaryRepresentation {
+    let host_object = env.objc.borrow::<NSUserDefaultsHostObject>(this);
+    let mut dictionary = host_object.registered.clone();
+    for (key, value) in host_object.values.iter() {
+        dictionary.insert(key.clone(), value.clone());
+    }
+    let dictionary = from_plist_value(env, &Value::Dictionary(dictionary));
+    autorelease(env, dictionary)
+}
+
+- (id)objectForKey:(id)key { // NSString*
+    let key = to_rust_string(env, key);
+    let value = env.objc.borrow::<NSUserDefaultsHostObject>(this).lookup(&key);
+    let Some(value) = value.cloned() else {
+        return nil;
+    };
+    let object = from_plist_value(env, &value);
+    autorelease(env, object)
+}
+- (())setObject:(id)object
+        forKey:(id)key { // NSString*
+    if object == nil {
+        return msg![env; this removeObjectForKey:key];
+    }
+    let Some(value) = to_plist_value(env, object) else {
+        log!("Warning: ignoring setObject:{:?} forKey:{:?}", object, key);
+        return;
+    };
+    let key = to_rust_string(env, key).into_owned();
+    env.objc.borrow_mut::<NSUserDefaultsHostObject>(this).set(key, value);
+}
+- (())removeObjectForKey:(id)key { // NSString*
+    let key = to_rust_string(env, key);
+    env.objc.borrow_mut::<NSUserDefaultsHostObject>(this).remove(&key);
+}
+
+- (id)stringForKey:(id)key { // NSString*
+    let key = to_rust_string(env, key);
+    let string = match env.objc.borrow::<NSUserDefaultsHostObject>(this).lookup(&key) {
+        Some(Value::String(string)) => string.clone(),
+        Some(Value::Integer(integer)) => integer.to_string(),
+        Some(Value::Real(real)) => real.to_string(),
+        _ => return nil,
+    };
+    let string = from_rust_string(env, string);
+    autorelease(env, string)
+}
+- (id)arrayForKey:(id)key { // NSString*
+    let key = to_rust_string(env, key);
+    let value = env.objc.borrow::<NSUserDefaultsHostObject>(this).lookup(&key);
+    let Some(value @ Value::Array(_)) = value.cloned() else {
+        return nil;
+    };
+    let array = from_plist_value(env, &value);
+    autorelease(env, array)
+}
+- (id)dictionaryForKey:(id)key { // NSString*
+    let key = to_rust_string(env, key);
+    let value = env.objc.borrow::<NSUserDefaultsHostObject>(this).lookup(&key);
+    let Some(value @ Value::Dictionary(_)) = value.cloned() else {
+        return nil;
+    };
+    let dictionary = from_plist_value(env, &value);
+    autorelease(env, dictionary)
+}
+- (id)dataForKey:(id)key { // NSString*
+    let key = to_rust_string(env, key);
+    let value = env.objc.borrow::<NSUserDefaultsHostObject>(this).lookup(&key);
+    let Some(value @ Value::Data(_)) = value.cloned() else {
+        return nil;
+    };
+    let data = from_plist_value(env, &value);
+    autorelease(env, data)
+}
+
+- (bool)boolForKey:(id)key { // NSString*
+    let key = to_rust_string(env, key);
+    let host_object = env.objc.borrow::<NSUserDefaultsHostObject>(this);
+    host_object.lookup(&key).map_or(false, value_as_bool)
+}
+- (NSInteger)integerForKey:(id)key { // NSString*
+    let key = to_rust_string(env, key);
+    let host_object = env.objc.borrow::<NSUserDefaultsHostObject>(this);
+    host_object.lookup(&key).map_or(0, value_as_integer) as NSInteger
+}
+- (f32)floatForKey:(id)key { // NSString*
+    let key = to_rust_string(env, key);
+    let host_object = env.objc.borrow::<NSUserDefaultsHostObject>(this);
+    host_object.lookup(&key).map_or(0.0, value_as_double) as f32
+}
+- (f64)doubleForKey:(id)key { // NSString*
+    let key = to_rust_string(env, key);
+    let host_object = env.objc.borrow::<NSUserDefaultsHostObject>(this);
+    host_object.lookup(&key).map_or(0.0, value_as_double)
+}
+
+- (())setBool:(bool)value
+       forKey:(id)key { // NSString*
+    let key = to_rust_string(env, key).into_owned();
+    let host_object = env.objc.borrow_mut::<NSUserDefaultsHostObject>(this);
+    host_object.set(key, Value::Boolean(value));
+}
+- (())setInteger:(NSInteger)value
+          forKey:(id)key { // NSString*
+    let key = to_rust_string(env, key).into_owned();
+    let host_object = env.objc.borrow_mut::<NSUserDefaultsHostObject>(this);
+    host_object.set(key, Value::Integer(i64::from(value).into()));
+}
+- (())setFloat:(f32)value
+        forKey:(id)key { // NSString*
+    let key = to_rust_string(env, key).into_owned();
+    let host_object = env.objc.borrow_mut::<NSUserDefaultsHostObject>(this);
+    host_object.set(key, Value::Real(value.into()));
+}
+- (())setDouble:(f64)value
+         forKey:(id)key { // NSString*
+    let key = to_rust_string(env, key).into_owned();
+    let host_object = env.objc.borrow_mut::<NSUserDefaultsHostObject>(this);
+    host_object.set(key, Value::Real(value));
+}
+
+- (bool)synchronize {
+    let host_object = env.objc.borrow::<NSUserDefaultsHostObject>(this);
+    if !host_object.dirty {
+        return true;
+    }
+    let bytes = serialize_defaults(&host_object.values);
+
+    let path = preferences_path(env);
+    let mut options = GuestOpenOptions::new();
+    options.write().create().truncate();
+    let Ok(mut file) = env.fs.open_with_options(&path, options) else {
+        log!("Warning: couldn't open {:?} to save user defaults", path);
+        return false;
+    };
+    if let Err(e) = file.write_all(&bytes) {
+        log!("Warning: couldn't save user defaults to {:?}: {}", path, e);
+        return false;
+    }
+    log_dbg!("Saved user defaults to {:?}", path);
+
+    env.objc.borrow_mut::<NSUserDefaultsHostObject>(this).dirty = false;
+    true
+}
+
+@end
+
+};
+
+/// For use when the app is about to exit: save any unsaved changes to the
+/// user defaults, like iPhone OS does.
+pub fn synchronize_standard_user_defaults(env: &mut Environment) {
+    if let Some(defaults) = State::get(env).standard_user_defaults {
+        let _: bool = msg![env; defaults synchronize];
+    }
+}
+
+/// Get the path where the user defaults are stored.
+fn preferences_path(env: &mut Environment) -> GuestPathBuf {
+    let file_name = format!("{}.plist", env.bundle.bundle_identifier());
+    env.fs
+        .home_directory()
+        .join("Library/Preferences")
+        .join(file_name)
+}
+
+/// Parse the saved user defaults. Returns [None] if they are corrupt.
+fn parse_defaults(bytes: &[u8]) -> Option<Dictionary> {
+    Value::from_reader(Cursor::new(bytes))
+        .ok()?
+        .into_dictionary()
+}
+
+/// Serialize the user defaults for saving. iPhone OS uses the binary format.
+fn serialize_defaults(values: &Dictionary) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    Value::Dictionary(values.clone())
+        .to_writer_binary(&mut bytes)
+        .unwrap();
+    bytes
+}
+
+/// Parse the number at the start of a string, in the forgiving manner of
+/// `-[NSString doubleValue]`.
+fn parse_number_prefix(string: &str) -> f64 {
+    let string = string.trim_start();
+    let mut end = 0;
+    for (i, c) in string.char_indices() {
+        let valid = match c {
+            '0'..='9' | '.' => true,
+            '+' | '-' => i == 0 || matches!(string.as_bytes()[i - 1], b'e' | b'E'),
+            'e' | 'E' => i > 0,
+            _ => false,
+        };
+        if !valid {
+            break;
+        }
+        end = i + c.len_utf8();
+    }
+    // Trailing junk like "1e" or "1.2." can make the prefix invalid.
+    (1..=end)
+        .rev()
+        .find_map(|len| string[..len].parse().ok())
+        .unwrap_or(0.0)
+}
+
+/// Conversion used by `boolForKey:`. Numbers are true if non-zero, strings are
+/// handled like `-[NSString boolValue]`.
+fn value_as_bool(value: &Value) -> bool {
+    match value {
+        &Value::Boolean(value) => value,
+        Value::Integer(_) | Value::Real(_) => value_as_double(value) != 0.0,
+        Value::String(string) => {
+            let string = string.trim_start();
+            let string = string.strip_prefix(['+', '-']).unwrap_or(string);
+            let string = string.trim_start_matches('0');
+            matches!(
+                string.chars().next(),
+                Some('Y' | 'y' | 'T' | 't' | '1'..='9')
+            )
+        }
+        _ => false,
+    }
+}
+
+/// Conversion used by `integerForKey:`. Reals are truncated.
+fn value_as_integer(value: &Value) -> i64 {
+    match value {
+        Value::Integer(integer) => integer
+            .as_signed()
+            .unwrap_or_else(|| integer.as_unsigned().unwrap() as i64),
+        _ => value_as_double(value) as i64,
+    }
+}
+
+/// Conversion used by `floatForKey:` and `doubleForKey:`.
+fn value_as_double(value: &Value) -> f64 {
+    match value {
+        &Value::Boolean(value) => value as i64 as f64,
+        Value::Integer(integer) => integer
+            .as_signed()
+            .map(|value| value as f64)
+            .unwrap_or_else(|| integer.as_unsigned().unwrap() as f64),
+        &Value::Real(real) => real,
+        Value::String(string) => parse_number_prefix(string),
+        _ => 0.0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn persist_and_reload() {
+        let mut defaults = NSUserDefaultsHostObject::default();
+        defaults
+            .registered
+            .insert("difficulty".into(), Value::Integer(1.into()));
+        defaults
+            .registered
+            .insert("volume".into(), Value::Real(0.5));
+        defaults.set("highScore".into(), Value::Integer(12345.into()));
+        defaults.set("playerName".into(), Value::String("Zoë".into()));
+        defaults.set("difficulty".into(), Value::Integer(3.into()));
+        assert!(defaults.dirty);
+
+        let path = std::env::temp_dir().join(format!(
+            "touchHLE_test_user_defaults_{}.plist",
+            std::process::id()
+        ));
+        std::fs::write(&path, serialize_defaults(&defaults.values)).unwrap();
+
+        // "Restart": only the values set by the app should have been saved,
+        // and the app has to register its defaults again.
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let mut reloaded = NSUserDefaultsHostObject {
+            values: parse_defaults(&bytes).unwrap(),
+            ..Default::default()
+        };
+        assert_eq!(reloaded.values, defaults.values);
+        assert!(reloaded.lookup("volume").is_none());
+        reloaded.registered = defaults.registered.clone();
+
+        assert_eq!(
+            value_as_integer(reloaded.lookup("highScore").unwrap()),
+            12345
+        );
+        assert_eq!(
+            reloaded.lookup("playerName").unwrap().as_string(),
+            Some("Zoë")
+        );
+        assert_eq!(value_as_integer(reloaded.lookup("difficulty").unwrap()), 3);
+        assert_eq!(value_as_double(reloaded.lookup("volume").unwrap()), 0.5);
+        assert!(!reloaded.dirty);
+    }
+
+    #[test]
+    fn corrupt_defaults() {
+        assert!(parse_defaults(b"not a plist").is_none());
+    }
+
+    #[test]
+    fn typed_conversions() {
+        let string = |s: &str| Value::String(s.into());
+        assert!(value_as_bool(&Value::Integer(2.into())));
+        assert!(!value_as_bool(&Value::Real(0.0)));
+        assert!(value_as_bool(&string("YES")));
+        assert!(value_as_bool(&string(" 007")));
+        assert!(!value_as_bool(&string("NO")));
+        assert!(!value_as_bool(&string("0")));
+        assert_eq!(value_as_integer(&Value::Real(-2.75)), -2);
+        assert_eq!(value_as_integer(&Value::Boolean(true)), 1);
+        assert_eq!(value_as_integer(&string("42 apples")), 42);
+        assert_eq!(value_as_double(&string("  1.5e2x")), 150.0);
+        assert_eq!(value_as_double(&string("1e")), 1.0);
+        assert_eq!(value_as_double(&string("apples")), 0.0);
+    }
+}
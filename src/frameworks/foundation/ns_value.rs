@@ -5,17 +5,91 @@
  */
 //! The `NSValue` class cluster, including `NSNumber`.
 
-use super::NSUInteger;
+use super::{
+    NSComparisonResult, NSInteger, NSOrderedAscending, NSOrderedDescending, NSOrderedSame,
+    NSUInteger,
+};
 use crate::mem::MutVoidPtr;
 use crate::objc::{
     autorelease, id, msg, msg_class, objc_classes, retain, Class, ClassExports, HostObject,
 };
+use std::cmp::Ordering;
 
-enum NSNumberHostObject {
+#[derive(Debug, Copy, Clone)]
+pub(super) enum NSNumberHostObject {
     Bool(bool),
+    LongLong(i64),
+    UnsignedLongLong(u64),
+    Float(f32),
+    Double(f64),
 }
 impl HostObject for NSNumberHostObject {}
 
+/// Generates methods that convert the value to some primitive type like a C
+/// cast would. Rust's float-to-integer casts saturate, which is also what the
+/// ARM instructions do.
+macro_rules! impl_cast {
+    ($($name:ident -> $type:ty),*) => {
+        $(
+            fn $name(self) -> $type {
+                match self {
+                    NSNumberHostObject::Bool(value) => value as u8 as $type,
+                    NSNumberHostObject::LongLong(value) => value as $type,
+                    NSNumberHostObject::UnsignedLongLong(value) => value as $type,
+                    NSNumberHostObject::Float(value) => value as $type,
+                    NSNumberHostObject::Double(value) => value as $type,
+                }
+            }
+        )*
+    }
+}
+
+impl NSNumberHostObject {
+    impl_cast!(
+        as_i32 -> i32,
+        as_u32 -> u32,
+        as_i64 -> i64,
+        as_u64 -> u64,
+        as_f32 -> f32,
+        as_f64 -> f64
+    );
+
+    fn as_bool(self) -> bool {
+        match self {
+            NSNumberHostObject::Bool(value) => value,
+            NSNumberHostObject::LongLong(value) => value != 0,
+            NSNumberHostObject::UnsignedLongLong(value) => value != 0,
+            NSNumberHostObject::Float(value) => value != 0.0,
+            NSNumberHostObject::Double(value) => value != 0.0,
+        }
+    }
+
+    /// Get the value as an integer if it has no fractional part, so that
+    /// numbers of different types can be compared exactly.
+    fn as_integer(self) -> Option<i128> {
+        match self {
+            NSNumberHostObject::Bool(value) => Some(value.into()),
+            NSNumberHostObject::LongLong(value) => Some(value.into()),
+            NSNumberHostObject::UnsignedLongLong(value) => Some(value.into()),
+            NSNumberHostObject::Float(_) | NSNumberHostObject::Double(_) => {
+                let value = self.as_f64();
+                (value.fract() == 0.0).then_some(value as i128)
+            }
+        }
+    }
+
+    fn compare(self, other: Self) -> Ordering {
+        match (self.as_integer(), other.as_integer()) {
+            (Some(a), Some(b)) => a.cmp(&b),
+            // TODO: Apple's handling of NaN is probably different.
+            _ => self
+                .as_f64()
+                .partial_cmp(&other.as_f64())
+                .unwrap_or(Ordering::Equal),
+        }
+    }
+}
+
 pub const CLASSES: ClassExports = objc_classes! {
 
 (env, this, _cmd);
@@ -46,8 +120,48 @@ pub const CLASSES: ClassExports = objc_classes! {
     let new: id = msg![env; new initWithBool:value];
     autorelease(env, new)
 }
++ (id)numberWithInt:(i32)value {
+    let new: id = msg![env; this alloc];
+    let new: id = msg![env; new initWithInt:value];
+    autorelease(env, new)
+}
++ (id)numberWithUnsignedInt:(u32)value {
+    let new: id = msg![env; this alloc];
+    let new: id = msg![env; new initWithUnsignedInt:value];
+    autorelease(env, new)
+}
++ (id)numberWithInteger:(NSInteger)value {
+    let new: id = msg![env; this alloc];
+    let new: id = msg![env; new initWithInteger:value];
+    autorelease(env, new)
+}
++ (id)numberWithUnsignedInteger:(NSUInteger)value {
+    let new: id = msg![env; this alloc];
+    let new: id = msg![env; new initWithUnsignedInteger:value];
+    autorelease(env, new)
+}
++ (id)numberWithLongLong:(i64)value {
+    let new: id = msg![env; this alloc];
+    let new: id = msg![env; new initWithLongLong:value];
+    autorelease(env, new)
+}
++ (id)numberWithUnsignedLongLong:(u64)value {
+    let new: id = msg![env; this alloc];
+    let new: id = msg![env; new initWithUnsignedLongLong:value];
+    autorelease(env, new)
+}
++ (id)numberWithFloat:(f32)value {
+    let new: id = msg![env; this alloc];
+    let new: id = msg![env; new initWithFloat:value];
+    autorelease(env, new)
+}
++ (id)numberWithDouble:(f64)value {
+    let new: id = msg![env; this alloc];
+    let new: id = msg![env; new initWithDouble:value];
+    autorelease(env, new)
+}
 
-// TODO: types other than booleans
+// TODO: char and short types
 
 - (id)initWithBool:(bool)value {
     *env.objc.borrow_mut::<NSNumberHostObject>(this) = NSNumberHostObject::Bool(
@@ -55,10 +169,78 @@ pub const CLASSES: ClassExports = objc_classes! {
     );
     this
 }
+- (id)initWithInt:(i32)value {
+    msg![env; this initWithLongLong:(value as i64)]
+}
+- (id)initWithUnsignedInt:(u32)value {
+    msg![env; this initWithLongLong:(value as i64)]
+}
+- (id)initWithInteger:(NSInteger)value {
+    msg![env; this initWithLongLong:(value as i64)]
+}
+- (id)initWithUnsignedInteger:(NSUInteger)value {
+    msg![env; this initWithLongLong:(value as i64)]
+}
+- (id)initWithLongLong:(i64)value {
+    *env.objc.borrow_mut::<NSNumberHostObject>(this) = NSNumberHostObject::LongLong(
+        value,
+    );
+    this
+}
+- (id)initWithUnsignedLongLong:(u64)value {
+    *env.objc.borrow_mut::<NSNumberHostObject>(this) = NSNumberHostObject::UnsignedLongLong(
+        value,
+    );
+    this
+}
+- (id)initWithFloat:(f32)value {
+    *env.objc.borrow_mut::<NSNumberHostObject>(this) = NSNumberHostObject::Float(
+        value,
+    );
+    this
+}
+- (id)initWithDouble:(f64)value {
+    *env.objc.borrow_mut::<NSNumberHostObject>(this) = NSNumberHostObject::Double(
+        value,
+    );
+    this
+}
+
+- (bool)boolValue {
+    env.objc.borrow::<NSNumberHostObject>(this).as_bool()
+}
+- (i32)intValue {
+    env.objc.borrow::<NSNumberHostObject>(this).as_i32()
+}
+- (u32)unsignedIntValue {
+    env.objc.borrow::<NSNumberHostObject>(this).as_u32()
+}
+- (NSInteger)integerValue {
+    env.objc.borrow::<NSNumberHostObject>(this).as_i32()
+}
+- (NSUInteger)unsignedIntegerValue {
+    env.objc.borrow::<NSNumberHostObject>(this).as_u32()
+}
+- (i64)longLongValue {
+    env.objc.borrow::<NSNumberHostObject>(this).as_i64()
+}
+- (u64)unsignedLongLongValue {
+    env.objc.borrow::<NSNumberHostObject>(this).as_u64()
+}
+- (f32)floatValue {
+    env.objc.borrow::<NSNumberHostObject>(this).as_f32()
+}
+- (f64)doubleValue {
+    env.objc.borrow::<NSNumberHostObject>(this).as_f64()
+}
 
 - (NSUInteger)hash {
-    let &NSNumberHostObject::Bool(value) = env.objc.borrow(this);
-    super::hash_helper(&value)
+    let &number = env.objc.borrow::<NSNumberHostObject>(this);
+    // Equal numbers must have equal hashes even if their types differ.
+    match number.as_integer() {
+        Some(value) => super::hash_helper(&value),
+        None => super::hash_helper(&number.as_f64().to_bits()),
+    }
 }
 - (bool)isEqualTo:(id)other {
     if this == other {
@@ -68,13 +250,64 @@ pub const CLASSES: ClassExports = objc_classes! {
     if !msg![env; other isKindOfClass:class] {
         return false;
     }
-    let &NSNumberHostObject::Bool(a) = env.objc.borrow(this);
-    let &NSNumberHostObject::Bool(b) = env.objc.borrow(other);
-    a == b
+    msg![env; this isEqualToNumber:other]
+}
+- (bool)isEqualToNumber:(id)other { // NSNumber*
+    let &a = env.objc.borrow::<NSNumberHostObject>(this);
+    let &b = env.objc.borrow::<NSNumberHostObject>(other);
+    a.compare(b) == Ordering::Equal
 }
 
-// TODO: accessors etc
+- (NSComparisonResult)compare:(id)other { // NSNumber*
+    let &a = env.objc.borrow::<NSNumberHostObject>(this);
+    let &b = env.objc.borrow::<NSNumberHostObject>(other);
+    match a.compare(b) {
+        Ordering::Less => NSOrderedAscending,
+        Ordering::Equal => NSOrderedSame,
+        Ordering::Greater => NSOrderedDescending,
+    }
+}
+
+// TODO: stringValue, description, char and short accessors
 
 @end
 
 };
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use NSNumberHostObject::*;
+
+    #[test]
+    fn conversions() {
+        assert_eq!(Double(-3.99).as_i32(), -3);
+        assert_eq!(Double(3.99).as_i64(), 3);
+        assert_eq!(Float(1e10).as_i32(), i32::MAX);
+        assert_eq!(Double(-1.0).as_u32(), 0);
+        assert_eq!(LongLong(0x1_0000_0002).as_i32(), 2);
+        assert_eq!(LongLong(-1).as_u64(), u64::MAX);
+        assert_eq!(UnsignedLongLong(u64::MAX).as_i64(), -1);
+        assert_eq!(LongLong(7).as_f32(), 7.0);
+        assert_eq!(Bool(true).as_f64(), 1.0);
+        assert_eq!(Double(0.1).as_f32(), 0.1f32);
+        assert!(Double(0.5).as_bool());
+        assert!(!LongLong(0).as_bool());
+        assert!(UnsignedLongLong(256).as_bool());
+    }
+
+    #[test]
+    fn comparison() {
+        assert_eq!(LongLong(2).compare(Double(2.0)), Ordering::Equal);
+        assert_eq!(Bool(true).compare(UnsignedLongLong(1)), Ordering::Equal);
+        assert_eq!(Float(0.5).compare(Double(0.5)), Ordering::Equal);
+        assert_eq!(Double(2.5).compare(LongLong(2)), Ordering::Greater);
+        assert_eq!(LongLong(-1).compare(UnsignedLongLong(0)), Ordering::Less);
+        // Too large to compare exactly as doubles.
+        assert_eq!(
+            UnsignedLongLong(u64::MAX).compare(UnsignedLongLong(u64::MAX - 1)),
+            Ordering::Greater
+        );
+        assert_eq!(Float(0.1).compare(Double(0.1)), Ordering::Greater);
+    }
+}
@@ -15,14 +15,20 @@ use crate::audio::openal as al;
 use crate::audio::openal::al_types::*;
 use crate::audio::openal::alc_types::*;
 use crate::dyld::{export_c_func, FunctionExports};
-use crate::mem::{ConstPtr, ConstVoidPtr, GuestUSize, MutPtr, Ptr, SafeWrite};
+use crate::mem::{ConstPtr, ConstVoidPtr, GuestUSize, Mem, MutPtr, Ptr, SafeWrite};
 use crate::Environment;
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 
 #[derive(Default)]
 pub struct State {
     devices: HashMap<MutPtr<GuestALCdevice>, *mut ALCdevice>,
     contexts: HashMap<MutPtr<GuestALCcontext>, *mut ALCcontext>,
+    /// Buffers queued on each source, oldest first. This mirrors OpenAL Soft's
+    /// queues so that unqueueing can be checked.
+    source_queues: HashMap<ALuint, VecDeque<ALuint>>,
+    /// Error taken from OpenAL Soft by [stash_error] that the app hasn't seen
+    /// yet. This is reported by the next `alGetError()` call.
+    error: Option<ALenum>,
 }
 impl State {
     fn get(env: &mut Environment) -> &mut Self {
@@ -154,7 +160,7 @@ fn alcGetProcAddress(
 
 // === al.h ===
 
-fn alGetError(_env: &mut Environment) -> i32 {
+fn alGetError(env: &mut Environment) -> i32 {
     // Super Monkey Ball tries to use this function (rather than alcGetError) to
     // figure out whether opening the device succeeded. This is not correct and
     // seems to be a bug. Presumably iPhone OS doesn't mind this, but OpenAL
@@ -165,11 +171,92 @@ fn alGetError(_env: &mut Environment) -> i32 {
         return al::AL_NO_ERROR;
     }
 
-    let res = unsafe { al::alGetError() };
+    let res = match State::get(env).error.take() {
+        Some(error) => error,
+        None => unsafe { al::alGetError() },
+    };
     log_dbg!("alGetError() => {:#x}", res);
     res
 }
 
+/// Check whether OpenAL Soft has recorded an error, e.g. to find out whether
+/// the last call succeeded. The error is kept in [State::error] so the app
+/// still gets it from `alGetError()`. Returns [true] if there was an error.
+fn stash_error(env: &mut Environment) -> bool {
+    let res = unsafe { al::alGetError() };
+    if res == al::AL_NO_ERROR {
+        return false;
+    }
+    // Like OpenAL, only keep the first error.
+    let error = &mut State::get(env).error;
+    if error.is_none() {
+        *error = Some(res);
+    }
+    true
+}
+
+fn alDistanceModel(_env: &mut Environment, distance_model: ALenum) {
+    unsafe { al::alDistanceModel(distance_model) };
+}
+
+fn alListenerf(_env: &mut Environment, param: ALenum, value: ALfloat) {
+    unsafe { al::alListenerf(param, value) };
+}
+fn alListener3f(
+    _env: &mut Environment,
+    param: ALenum,
+    value1: ALfloat,
+    value2: ALfloat,
+    value3: ALfloat,
+) {
+    unsafe { al::alListener3f(param, value1, value2, value3) };
+}
+fn alListenerfv(env: &mut Environment, param: ALenum, values: ConstPtr<ALfloat>) {
+    let host_values: [ALfloat; 6] = read_values(&env.mem, values, listener_param_count(param));
+    unsafe { al::alListenerfv(param, host_values.as_ptr()) };
+}
+fn alGetListenerf(env: &mut Environment, param: ALenum, value: MutPtr<ALfloat>) {
+    let mut res = 0.0;
+    unsafe { al::alGetListenerf(param, &mut res) };
+    env.mem.write(value, res);
+}
+fn alGetListenerfv(env: &mut Environment, param: ALenum, values: MutPtr<ALfloat>) {
+    let mut res = [0.0; 6];
+    unsafe { al::alGetListenerfv(param, res.as_mut_ptr()) };
+    let count = listener_param_count(param) as usize;
+    write_values(&mut env.mem, values, &res[..count]);
+}
+
+/// Number of values a vector listener parameter has.
+fn listener_param_count(param: ALenum) -> GuestUSize {
+    match param {
+        // "at" vector followed by "up" vector
+        al::AL_ORIENTATION => 6,
+        al::AL_POSITION | al::AL_VELOCITY => 3,
+        _ => 1,
+    }
+}
+
+/// Read the `count` values of a vector parameter from guest memory. The rest of
+/// the array is zeroed.
+fn read_values<const N: usize>(
+    mem: &Mem,
+    values: ConstPtr<ALfloat>,
+    count: GuestUSize,
+) -> [ALfloat; N] {
+    let mut host_values = [0.0; N];
+    for (i, value) in host_values[..count as usize].iter_mut().enumerate() {
+        *value = mem.read(values + i as GuestUSize);
+    }
+    host_values
+}
+/// Write the values of a vector parameter to guest memory.
+fn write_values(mem: &mut Mem, values: MutPtr<ALfloat>, host_values: &[ALfloat]) {
+    for (i, &value) in host_values.iter().enumerate() {
+        mem.write(values + i as GuestUSize, value);
+    }
+}
+
 fn alGenSources(env: &mut Environment, n: ALsizei, sources: MutPtr<ALuint>) {
     let n_usize: GuestUSize = n.try_into().unwrap();
     let sources = env.mem.ptr_at_mut(sources, n_usize);
@@ -177,6 +264,10 @@ fn alGenSources(env: &mut Environment, n: ALsizei, sources: MutPtr<ALuint>) {
 }
 fn alDeleteSources(env: &mut Environment, n: ALsizei, sources: ConstPtr<ALuint>) {
     let n_usize: GuestUSize = n.try_into().unwrap();
+    for i in 0..n_usize {
+        let source = env.mem.read(sources + i);
+        State::get(env).source_queues.remove(&source);
+    }
     let sources = env.mem.ptr_at(sources, n_usize);
     unsafe { al::alDeleteSources(n, sources) };
 }
@@ -184,11 +275,61 @@ fn alDeleteSources(env: &mut Environment, n: ALsizei, sources: ConstPtr<ALuint>)
 fn alSourcef(_env: &mut Environment, source: ALuint, param: ALenum, value: ALfloat) {
     unsafe { al::alSourcef(source, param, value) };
 }
-fn alSourcei(_env: &mut Environment, source: ALuint, param: ALenum, value: ALint) {
+fn alSource3f(
+    _env: &mut Environment,
+    source: ALuint,
+    param: ALenum,
+    value1: ALfloat,
+    value2: ALfloat,
+    value3: ALfloat,
+) {
+    unsafe { al::alSource3f(source, param, value1, value2, value3) };
+}
+fn alSourcefv(env: &mut Environment, source: ALuint, param: ALenum, values: ConstPtr<ALfloat>) {
+    let host_values: [ALfloat; 3] = read_values(&env.mem, values, source_param_count(param));
+    unsafe { al::alSourcefv(source, param, host_values.as_ptr()) };
+}
+fn alSourcei(env: &mut Environment, source: ALuint, param: ALenum, value: ALint) {
+    if param != al::AL_BUFFER {
+        unsafe { al::alSourcei(source, param, value) };
+        return;
+    }
+
+    stash_error(env);
     unsafe { al::alSourcei(source, param, value) };
+    // Setting a static buffer replaces the source's whole queue.
+    if !stash_error(env) {
+        let queue = State::get(env).source_queues.entry(source).or_default();
+        queue.clear();
+        if value != 0 {
+            queue.push_back(value as ALuint);
+        }
+    }
 }
 fn alGetSourcef(env: &mut Environment, source: ALuint, param: ALenum, value: MutPtr<ALfloat>) {
-    unsafe { al::alGetSourcef(source, param, env.mem.ptr_at_mut(value, 1)) };
+    let mut res = 0.0;
+    unsafe { al::alGetSourcef(source, param, &mut res) };
+    env.mem.write(value, res);
+}
+fn alGetSourcefv(env: &mut Environment, source: ALuint, param: ALenum, values: MutPtr<ALfloat>) {
+    let mut res = [0.0; 3];
+    unsafe { al::alGetSourcefv(source, param, res.as_mut_ptr()) };
+    let count = source_param_count(param) as usize;
+    write_values(&mut env.mem, values, &res[..count]);
+}
+fn alGetSource3f(
+    env: &mut Environment,
+    source: ALuint,
+    param: ALenum,
+    value1: MutPtr<ALfloat>,
+    value2: MutPtr<ALfloat>,
+    value3: MutPtr<ALfloat>,
+) {
+    let mut res = [0.0; 3];
+    unsafe { al::alGetSource3f(source, param, &mut res[0], &mut res[1], &mut res[2]) };
+    env.mem.write(value1, res[0]);
+    env.mem.write(value2, res[1]);
+    env.mem.write(value3, res[2]);
 }
 fn alGetSourcei(env: &mut Environment, source: ALuint, param: ALenum, value: MutPtr<ALint>) {
     // Game-specific hack: Super Monkey Ball has some code like:
@@ -201,21 +342,31 @@ fn alGetSourcei(env: &mut Environment, source: ALuint, param: ALenum, value: Mut
     // If we pretend AL_BUFFERS_PROCESSED was used, everything works.
     // TODO: Test on iPhone OS and figure out why Super Monkey Ball works there.
     // This might be hiding some bug in touchHLE.
-    if param == al::AL_BUFFERS_QUEUED && env.bundle.bundle_identifier() == "com.ooi.supermonkeyball"
+    let res = if param == al::AL_BUFFERS_QUEUED
+        && env.bundle.bundle_identifier() == "com.ooi.supermonkeyball"
     {
         log!("Applying game-specific hack for Super Monkey Ball: treating alGetSourcei(_, AL_BUFFERS_QUEUED, _) as alGetSourcei(_, AL_BUFFERS_PROCESSED, _)");
-        unsafe {
-            al::alGetSourcei(
-                source,
-                al::AL_BUFFERS_PROCESSED,
-                env.mem.ptr_at_mut(value, 1),
-            )
-        };
+        get_source_i(source, al::AL_BUFFERS_PROCESSED)
     } else {
-        unsafe { al::alGetSourcei(source, param, env.mem.ptr_at_mut(value, 1)) };
+        get_source_i(source, param)
+    };
+    env.mem.write(value, res);
+}
+
+/// Number of values a vector source parameter has.
+fn source_param_count(param: ALenum) -> GuestUSize {
+    match param {
+        al::AL_POSITION | al::AL_VELOCITY | al::AL_DIRECTION => 3,
+        _ => 1,
     }
 }
 
+fn get_source_i(source: ALuint, param: ALenum) -> ALint {
+    let mut res = 0;
+    unsafe { al::alGetSourcei(source, param, &mut res) };
+    res
+}
+
 fn alSourcePlay(_env: &mut Environment, source: ALuint) {
     unsafe { al::alSourcePlay(source) };
 }
@@ -230,8 +381,25 @@ fn alSourceQueueBuffers(
     buffers: ConstPtr<ALuint>,
 ) {
     let nb_usize: GuestUSize = nb.try_into().unwrap();
-    let buffers = env.mem.ptr_at(buffers, nb_usize);
-    unsafe { al::alSourceQueueBuffers(source, nb, buffers) }
+    stash_error(env);
+    unsafe { al::alSourceQueueBuffers(source, nb, env.mem.ptr_at(buffers, nb_usize)) };
+    if stash_error(env) {
+        log_dbg!(
+            "alSourceQueueBuffers({}, {}, {:?}) failed",
+            source,
+            nb,
+            buffers
+        );
+        return;
+    }
+    for i in 0..nb_usize {
+        let buffer = env.mem.read(buffers + i);
+        State::get(env)
+            .source_queues
+            .entry(source)
+            .or_default()
+            .push_back(buffer);
+    }
 }
 fn alSourceUnqueueBuffers(
     env: &mut Environment,
@@ -240,8 +408,72 @@ fn alSourceUnqueueBuffers(
     buffers: MutPtr<ALuint>,
 ) {
     let nb_usize: GuestUSize = nb.try_into().unwrap();
-    let buffers = env.mem.ptr_at_mut(buffers, nb_usize);
-    unsafe { al::alSourceUnqueueBuffers(source, nb, buffers) }
+
+    // OpenAL Soft refuses to unqueue anything if asked for more buffers than
+    // have been processed, which would leave apps that do this with no
+    // buffers to refill. Instead, unqueue as many as possible, and give the
+    // app zero (the null buffer) for the rest.
+    let processed = get_source_i(source, al::AL_BUFFERS_PROCESSED);
+    if nb > processed {
+        log!(
+            "Warning: alSourceUnqueueBuffers({}, {}, {:?}) called when only {} buffer(s) have been processed. Unqueueing only those.",
+            source,
+            nb,
+            buffers,
+            processed
+        );
+    }
+    let count = nb.min(processed).max(0);
+
+    let mut unqueued = vec![0; count as usize];
+    if count > 0 {
+        stash_error(env);
+        unsafe { al::alSourceUnqueueBuffers(source, count, unqueued.as_mut_ptr()) };
+        if stash_error(env) {
+            log_dbg!(
+                "alSourceUnqueueBuffers({}, {}, {:?}) failed",
+                source,
+                nb,
+                buffers
+            );
+            unqueued.fill(0);
+        } else {
+            let queue = State::get(env).source_queues.entry(source).or_default();
+            unqueue_from_mirror(source, queue, &unqueued);
+        }
+    }
+
+    for i in 0..nb_usize {
+        let buffer = unqueued.get(i as usize).copied().unwrap_or(0);
+        env.mem.write(buffers + i, buffer);
+    }
+}
+
+/// Remove buffers that OpenAL Soft unqueued from a source's mirrored queue.
+/// If the mirror has drifted, it is brought back in line with OpenAL Soft.
+fn unqueue_from_mirror(source: ALuint, queue: &mut VecDeque<ALuint>, unqueued: &[ALuint]) {
+    for &buffer in unqueued {
+        if queue.front() == Some(&buffer) {
+            queue.pop_front();
+            continue;
+        }
+        // Anything queued before the buffer must already be gone too.
+        if let Some(index) = queue.iter().position(|&queued| queued == buffer) {
+            log!(
+                "Warning: Source {} unqueued buffer {} but {:?} were queued before it, dropping those",
+                source,
+                buffer,
+                queue.range(..index).collect::<Vec<_>>()
+            );
+            queue.drain(..=index);
+        } else {
+            log!(
+                "Warning: Source {} unqueued buffer {} which wasn't known to be queued",
+                source,
+                buffer
+            );
+        }
+    }
 }
 
 fn alGenBuffers(env: &mut Environment, n: ALsizei, buffers: MutPtr<ALuint>) {
@@ -302,11 +534,21 @@ pub const FUNCTIONS: FunctionExports = &[
     export_c_func!(alcMakeContextCurrent(_)),
     export_c_func!(alcGetProcAddress(_, _)),
     export_c_func!(alGetError()),
+    export_c_func!(alDistanceModel(_)),
+    export_c_func!(alListenerf(_, _)),
+    export_c_func!(alListener3f(_, _, _, _)),
+    export_c_func!(alListenerfv(_, _)),
+    export_c_func!(alGetListenerf(_, _)),
+    export_c_func!(alGetListenerfv(_, _)),
     export_c_func!(alGenSources(_, _)),
     export_c_func!(alDeleteSources(_, _)),
     export_c_func!(alGetSourcef(_, _, _)),
+    export_c_func!(alGetSourcefv(_, _, _)),
+    export_c_func!(alGetSource3f(_, _, _, _, _)),
     export_c_func!(alGetSourcei(_, _, _)),
     export_c_func!(alSourcef(_, _, _)),
+    export_c_func!(alSource3f(_, _, _, _, _)),
+    export_c_func!(alSourcefv(_, _, _)),
     export_c_func!(alSourcei(_, _, _)),
     export_c_func!(alSourcePlay(_)),
     export_c_func!(alSourceStop(_)),
@@ -317,3 +559,46 @@ pub const FUNCTIONS: FunctionExports = &[
     export_c_func!(alBufferData(_, _, _, _, _)),
     export_c_func!(alBufferDataStatic(_, _, _, _, _)),
 ];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unqueueing_resyncs_mirror() {
+        let mut queue = VecDeque::from([1, 2, 3, 4]);
+        unqueue_from_mirror(1, &mut queue, &[1]);
+        assert_eq!(queue, [2, 3, 4]);
+        // Buffer 2 was unqueued without the mirror being updated.
+        unqueue_from_mirror(1, &mut queue, &[3]);
+        assert_eq!(queue, [4]);
+        // Unknown buffers are ignored.
+        unqueue_from_mirror(1, &mut queue, &[5, 4]);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn vector_parameters() {
+        assert_eq!(listener_param_count(al::AL_ORIENTATION), 6);
+        assert_eq!(listener_param_count(al::AL_POSITION), 3);
+        assert_eq!(listener_param_count(al::AL_GAIN), 1);
+        assert_eq!(source_param_count(al::AL_DIRECTION), 3);
+        assert_eq!(source_param_count(al::AL_REFERENCE_DISTANCE), 1);
+
+        let mut mem = Mem::new();
+        let values: MutPtr<ALfloat> = mem.alloc(8 * 4).cast();
+        for i in 0..8 {
+            mem.write(values + i, (i + 1) as ALfloat);
+        }
+
+        let orientation: [ALfloat; 6] = read_values(&mem, values.cast_const(), 6);
+        assert_eq!(orientation, [1.0, 2.0, 3.0, 4.0, 5.0, 6.0]);
+        let position: [ALfloat; 6] = read_values(&mem, values.cast_const(), 3);
+        assert_eq!(position, [1.0, 2.0, 3.0, 0.0, 0.0, 0.0]);
+
+        // Only as many values as the parameter has are written.
+        write_values(&mut mem, values, &[-4.0, 5.0, 6.0]);
+        let written: [ALfloat; 8] = read_values(&mem, values.cast_const(), 8);
+        assert_eq!(written, [-4.0, 5.0, 6.0, 4.0, 5.0, 6.0, 7.0, 8.0]);
+    }
+}
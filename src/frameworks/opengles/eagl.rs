@@ -5,14 +5,18 @@
  */
 //! EAGL.
 
+use super::gles_guest::apply_scale_hack;
 use super::{GLES1OnGL2, GLES};
 use crate::dyld::{ConstantExports, HostConstant};
+use crate::frameworks::core_graphics::{CGFloat, CGRect};
 use crate::frameworks::foundation::ns_string::get_static_str;
 use crate::frameworks::foundation::NSUInteger;
-use crate::objc::{id, msg, nil, objc_classes, release, retain, ClassExports, HostObject};
+use crate::objc::{
+    id, msg, msg_class, nil, objc_classes, release, retain, ClassExports, HostObject, ObjC,
+};
 use crate::window::gles11;
 use crate::window::Matrix;
-use crate::Environment; // for constants
+use crate::Environment;
 
 // These are used by the EAGLDrawable protocol implemented by CAEAGLayer.
 // Since these have the ABI of constant symbols rather than literal constants,
@@ -50,20 +54,46 @@ const kEAGLRenderingAPIOpenGLES3: EAGLRenderingAPI = 3;
 
 pub(super) struct EAGLContextHostObject {
     pub(super) gles_ctx: Option<Box<dyn GLES>>,
+    /// `EAGLSharegroup*`, retained.
+    sharegroup: id,
 }
 impl HostObject for EAGLContextHostObject {}
 
+struct EAGLSharegroupHostObject {
+    /// The contexts in this sharegroup (`EAGLContext*`, not retained, since
+    /// they retain the sharegroup). New contexts share objects with these.
+    contexts: Vec<id>,
+}
+impl HostObject for EAGLSharegroupHostObject {}
+
 pub const CLASSES: ClassExports = objc_classes! {
 
 (env, this, _cmd);
 
+@implementation EAGLSharegroup: NSObject
+
++ (id)alloc {
+    let host_object = Box::new(EAGLSharegroupHostObject { contexts: Vec::new() });
+    env.objc.alloc_object(this, host_object, &mut env.mem)
+}
+
+@end
+
 @implementation EAGLContext: NSObject
 
 + (id)alloc {
-    let host_object = Box::new(EAGLContextHostObject { gles_ctx: None });
+    let host_object = Box::new(EAGLContextHostObject {
+        gles_ctx: None,
+        sharegroup: nil,
+    });
     env.objc.alloc_object(this, host_object, &mut env.mem)
 }
 
++ (id)currentContext {
+    let current_ctx = env.framework_state.opengles.current_ctx_for_thread(env.current_thread);
+    current_ctx.unwrap_or(nil)
+}
+
 + (bool)setCurrentContext:(id)context { // EAGLContext*
     retain(env, context);
 
@@ -91,17 +121,47 @@ pub const CLASSES: ClassExports = objc_classes! {
 }
 
 - (id)initWithAPI:(EAGLRenderingAPI)api {
-    assert!(api == kEAGLRenderingAPIOpenGLES1);
+    msg![env; this initWithAPI:api sharegroup:nil]
+}
 
-    let gles1_ctx = Box::new(GLES1OnGL2::new(&mut env.window));
+- (id)initWithAPI:(EAGLRenderingAPI)api
+       sharegroup:(id)sharegroup { // EAGLSharegroup*
+    assert!(api == kEAGLRenderingAPIOpenGLES1);
 
-    *env.objc.borrow_mut(this) = EAGLContextHostObject {
-        gles_ctx: Some(gles1_ctx),
+    let sharegroup = if sharegroup == nil {
+        msg_class![env; EAGLSharegroup new]
+    } else {
+        retain(env, sharegroup)
     };
 
+    let share_with = context_to_share_with(&env.objc, sharegroup).map(|ctx| {
+        let host_obj = env.objc.borrow::<EAGLContextHostObject>(ctx);
+        host_obj.gles_ctx.as_deref().unwrap()
+    });
+    let gles1_ctx = Box::new(GLES1OnGL2::new(&mut env.window, share_with));
+
+    env.objc.borrow_mut::<EAGLContextHostObject>(this).gles_ctx = Some(gles1_ctx);
+    join_sharegroup(&mut env.objc, this, sharegroup);
+
     this
 }
 
+- (())dealloc {
+    let sharegroup = leave_sharegroup(&mut env.objc, this);
+    if sharegroup != nil {
+        release(env, sharegroup);
+    }
+    env.objc.dealloc_object(this, &mut env.mem)
+}
+
+- (EAGLRenderingAPI)API {
+    kEAGLRenderingAPIOpenGLES1
+}
+
+- (id)sharegroup {
+    env.objc.borrow::<EAGLContextHostObject>(this).sharegroup
+}
+
 - (bool)renderbufferStorage:(NSUInteger)target
                fromDrawable:(id)drawable { // EAGLDrawable (always CAEAGLayer*)
     assert!(target == gles11::RENDERBUFFER_OES);
@@ -121,14 +181,31 @@ pub const CLASSES: ClassExports = objc_classes! {
         gles11::RGBA8_OES
     };
 
-    // FIXME: get width and height from the layer!
-    let (width, height) = env.window.size_unrotated_scalehacked();
+    // The renderbuffer is the size of the layer in pixels. Apps usually
+    // call this method again if the layer is resized, e.g. on rotation.
+    let bounds: CGRect = msg![env; drawable bounds];
+    let contents_scale: CGFloat = msg![env; drawable contentsScale];
+    let (width, height) = drawable_size(bounds, contents_scale)
+        .unwrap_or_else(|| env.window.size_unrotated_unscaled());
+    let (width, height) = apply_scale_hack(
+        env,
+        width.try_into().unwrap(),
+        height.try_into().unwrap(),
+        /* is_renderbuffer: */ true,
+    );
+    log_dbg!(
+        "renderbufferStorage:{:#x} fromDrawable:{:?} => {}x{}",
+        target,
+        drawable,
+        width,
+        height
+    );
 
     // Unclear from documentation if this method requires an appropriate context
     // to already be active, but that seems to be the case in practice?
     let gles = super::sync_context(&mut env.framework_state.opengles, &mut env.objc, &mut env.window, env.current_thread);
     unsafe {
-        gles.RenderbufferStorageOES(target, internalformat, width.try_into().unwrap(), height.try_into().unwrap())
+        gles.RenderbufferStorageOES(target, internalformat, width, height)
     }
 
     true
@@ -151,6 +228,61 @@ pub const CLASSES: ClassExports = objc_classes! {
 
 };
 
+/// Find a context whose objects a new context in `sharegroup` should share.
+/// Any existing context in the sharegroup will do, since they all share the
+/// same objects.
+fn context_to_share_with(objc: &ObjC, sharegroup: id) -> Option<id> {
+    objc.borrow::<EAGLSharegroupHostObject>(sharegroup)
+        .contexts
+        .first()
+        .copied()
+}
+
+/// Add a context to a sharegroup. The context takes over the caller's
+/// reference to the sharegroup.
+fn join_sharegroup(objc: &mut ObjC, context: id, sharegroup: id) {
+    objc.borrow_mut::<EAGLSharegroupHostObject>(sharegroup)
+        .contexts
+        .push(context);
+    objc.borrow_mut::<EAGLContextHostObject>(context).sharegroup = sharegroup;
+}
+
+/// Remove a context from its sharegroup, if it has one. Returns the
+/// sharegroup, which the caller must release.
+fn leave_sharegroup(objc: &mut ObjC, context: id) -> id {
+    let sharegroup = std::mem::replace(
+        &mut objc.borrow_mut::<EAGLContextHostObject>(context).sharegroup,
+        nil,
+    );
+    if sharegroup != nil {
+        objc.borrow_mut::<EAGLSharegroupHostObject>(sharegroup)
+            .contexts
+            .retain(|&ctx| ctx != context);
+    }
+    sharegroup
+}
+
+/// Get the size in pixels of a drawable with the given bounds and contents
+/// scale, or [None] if it is empty (e.g. the layer hasn't been laid out yet).
+fn drawable_size(bounds: CGRect, contents_scale: CGFloat) -> Option<(u32, u32)> {
+    let width = (bounds.size.width * contents_scale).round();
+    let height = (bounds.size.height * contents_scale).round();
+    if width >= 1.0 && height >= 1.0 {
+        Some((width as u32, height as u32))
+    } else {
+        None
+    }
+}
+
+/// Whether a renderbuffer needs to be rotated to appear upright in a viewport
+/// of the given size. An app that resized its layer to the rotated screen
+/// size will already be drawing in that orientation.
+fn renderbuffer_needs_rotation(size: (i32, i32), viewport_size: (u32, u32)) -> bool {
+    let is_landscape = size.0 > size.1;
+    let viewport_is_landscape = viewport_size.0 > viewport_size.1;
+    is_landscape != viewport_is_landscape
+}
+
 /// Copies the renderbuffer provided by the app to the window's framebuffer,
 /// rotated if necessary, and presents that framebuffer.
 unsafe fn present_renderbuffer(env: &mut Environment) {
@@ -295,7 +427,12 @@ unsafe fn present_renderbuffer(env: &mut Environment) {
     let tex_coords: [f32; 12] = [0.0, 0.0, 0.0, 1.0, 1.0, 0.0, 1.0, 0.0, 0.0, 1.0, 1.0, 1.0];
     gl::EnableClientState(gl::TEXTURE_COORD_ARRAY);
     gl::TexCoordPointer(2, gl::FLOAT, 0, tex_coords.as_ptr() as *const GLvoid);
-    let matrix = Matrix::<4>::from(&env.window.output_rotation_matrix());
+    let matrix = if renderbuffer_needs_rotation((width, height), viewport_size) {
+        env.window.output_rotation_matrix()
+    } else {
+        Matrix::identity()
+    };
+    let matrix = Matrix::<4>::from(&matrix);
     gl::MatrixMode(gl::TEXTURE);
     gl::LoadMatrixf(matrix.columns().as_ptr() as *const _);
     gl::Enable(gl::TEXTURE_2D);
@@ -345,3 +482,76 @@ unsafe fn present_renderbuffer(env: &mut Environment) {
 
     //{ let err = gl::GetError(); if err != 0 { panic!("{:#x}", err); } }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frameworks::core_graphics::{CGPoint, CGSize};
+    use crate::mem::Mem;
+
+    fn rect(width: CGFloat, height: CGFloat) -> CGRect {
+        CGRect {
+            origin: CGPoint { x: 0.0, y: 0.0 },
+            size: CGSize { width, height },
+        }
+    }
+
+    #[test]
+    fn drawable_sizes() {
+        assert_eq!(drawable_size(rect(320.0, 480.0), 1.0), Some((320, 480)));
+        assert_eq!(drawable_size(rect(480.0, 320.0), 1.0), Some((480, 320)));
+        assert_eq!(drawable_size(rect(320.0, 480.0), 2.0), Some((640, 960)));
+        assert_eq!(drawable_size(rect(0.0, 0.0), 1.0), None);
+    }
+
+    #[test]
+    fn rotation_on_present() {
+        // A portrait renderbuffer is rotated to fit a landscape window...
+        assert!(renderbuffer_needs_rotation((320, 480), (480, 320)));
+        // ...but not if the app already resized it for the new orientation.
+        assert!(!renderbuffer_needs_rotation((480, 320), (480, 320)));
+        assert!(!renderbuffer_needs_rotation((640, 960), (640, 960)));
+    }
+
+    #[test]
+    fn sharegroup_bookkeeping() {
+        let mut objc = ObjC::new();
+        let mut mem = Mem::new();
+
+        // This only covers which contexts are in which sharegroup. Whether
+        // objects like textures are actually shared is up to GLES1OnGL2::new()
+        // and the host driver, which need a window to test.
+        let class = objc.get_known_class("EAGLSharegroup", &mut mem);
+        let host_object = Box::new(EAGLSharegroupHostObject {
+            contexts: Vec::new(),
+        });
+        let sharegroup = objc.alloc_object(class, host_object, &mut mem);
+        let class = objc.get_known_class("EAGLContext", &mut mem);
+        let [first, second] = [(); 2].map(|_| {
+            let host_object = Box::new(EAGLContextHostObject {
+                gles_ctx: None,
+                sharegroup: nil,
+            });
+            objc.alloc_object(class, host_object, &mut mem)
+        });
+
+        // The first context has nothing to share with, the second one shares
+        // the first one's objects.
+        assert_eq!(context_to_share_with(&objc, sharegroup), None);
+        join_sharegroup(&mut objc, first, sharegroup);
+        assert_eq!(context_to_share_with(&objc, sharegroup), Some(first));
+        join_sharegroup(&mut objc, second, sharegroup);
+        for context in [first, second] {
+            let host_object = objc.borrow::<EAGLContextHostObject>(context);
+            assert_eq!(host_object.sharegroup, sharegroup);
+        }
+
+        // Each context hands back its reference to the sharegroup on dealloc,
+        // and the objects stay available to the remaining context.
+        assert_eq!(leave_sharegroup(&mut objc, first), sharegroup);
+        assert_eq!(context_to_share_with(&objc, sharegroup), Some(second));
+        assert_eq!(leave_sharegroup(&mut objc, second), sharegroup);
+        assert_eq!(context_to_share_with(&objc, sharegroup), None);
+        assert_eq!(leave_sharegroup(&mut objc, second), nil);
+    }
+}
@@ -76,18 +76,44 @@ pub(super) const CAPABILITIES: &[GLenum] = &[
     gl21::TEXTURE_2D,
 ];
 
+/// List of `glPushAttrib` mask bits that we support, i.e. the attribute groups
+/// which cover state that exists in OpenGL ES 1.1.
+///
+/// OpenGL ES 1.1 doesn't have `glPushAttrib`/`glPopAttrib`, but some apps and
+/// middleware call them anyway (presumably they're shared with desktop OpenGL
+/// code). Other bits, e.g. `GL_ACCUM_BUFFER_BIT` or `GL_EVAL_BIT`, refer to
+/// state the app can't have modified, so it's harmless to ignore them.
+const ATTRIB_BITS: &[GLbitfield] = &[
+    gl21::COLOR_BUFFER_BIT,
+    gl21::CURRENT_BIT,
+    gl21::DEPTH_BUFFER_BIT,
+    gl21::ENABLE_BIT,
+    gl21::FOG_BIT,
+    gl21::HINT_BIT,
+    gl21::LIGHTING_BIT,
+    gl21::LINE_BIT,
+    gl21::MULTISAMPLE_BIT,
+    gl21::POINT_BIT,
+    gl21::POLYGON_BIT,
+    gl21::SCISSOR_BIT,
+    gl21::STENCIL_BUFFER_BIT,
+    gl21::TEXTURE_BIT,
+    gl21::TRANSFORM_BIT,
+    gl21::VIEWPORT_BIT,
+];
+
 pub(super) struct ArrayInfo {
     /// Enum used by `glEnableClientState`, `glDisableClientState` and
     /// `glGetBoolean`.
     pub(super) name: GLenum,
     /// Buffer binding enum for `glGetInteger`.
-    buffer_binding: GLenum,
+    pub(super) buffer_binding: GLenum,
     /// Size enum for `glGetInteger`.
     size: Option<GLenum>,
     /// Stride enum for `glGetInteger`.
     stride: GLenum,
     /// Pointer enum for `glGetPointer`.
-    pointer: GLenum,
+    pub(super) pointer: GLenum,
 }
 
 struct ArrayStateBackup {
@@ -145,6 +171,39 @@ pub(super) const LIGHT_PARAMS: &[(GLenum, u8)] = &[
     (gl21::QUADRATIC_ATTENUATION, 1),
 ];
 
+/// List of `glTexParameter*` and `glGetTexParameter*` parameters shared by
+/// OpenGL ES 1.1 and OpenGL 2.1. All of them have a single value.
+const TEX_PARAMS: &[GLenum] = &[
+    gl21::TEXTURE_MIN_FILTER,
+    gl21::TEXTURE_MAG_FILTER,
+    gl21::TEXTURE_WRAP_S,
+    gl21::TEXTURE_WRAP_T,
+    gl21::GENERATE_MIPMAP,
+];
+
+/// List of primitive types shared by OpenGL ES 1.1 and OpenGL 2.1.
+const PRIMITIVE_MODES: &[GLenum] = &[
+    gl21::POINTS,
+    gl21::LINE_STRIP,
+    gl21::LINE_LOOP,
+    gl21::LINES,
+    gl21::TRIANGLE_STRIP,
+    gl21::TRIANGLE_FAN,
+    gl21::TRIANGLES,
+];
+
+/// Check the parameters shared by `glDrawArrays` and `glDrawElements`.
+/// Passing these through would only produce a GL error, which is easy to miss,
+/// so it's better to fail loudly.
+fn validate_draw_call(mode: GLenum, count: GLsizei) {
+    assert!(
+        PRIMITIVE_MODES.contains(&mode),
+        "Invalid primitive mode {:#x} for draw call",
+        mode
+    );
+    assert!(count >= 0, "Invalid negative count {} for draw call", count);
+}
+
 pub struct GLES1OnGL2 {
     gl_ctx: GLContext,
     pointer_is_fixed_point: [bool; ARRAYS.len()],
@@ -281,9 +340,10 @@ impl GLES1OnGL2 {
     }
 }
 impl GLES for GLES1OnGL2 {
-    fn new(window: &mut Window) -> Self {
+    fn new(window: &mut Window, share_with: Option<&dyn GLES>) -> Self {
+        let share_with = share_with.map(|gles| gles.gl_context());
         Self {
-            gl_ctx: window.create_gl_context(GLVersion::GL21Compat),
+            gl_ctx: window.create_gl_context(GLVersion::GL21Compat, share_with),
             pointer_is_fixed_point: [false; ARRAYS.len()],
             fixed_point_translation_buffers: [Vec::new(), Vec::new(), Vec::new(), Vec::new()],
         }
@@ -293,6 +353,10 @@ impl GLES for GLES1OnGL2 {
         window.make_gl_context_current(&self.gl_ctx);
     }
 
+    fn gl_context(&self) -> &GLContext {
+        &self.gl_ctx
+    }
+
     // Generic state manipulation
     unsafe fn GetError(&mut self) -> GLenum {
         gl21::GetError()
@@ -316,15 +380,49 @@ impl GLES for GLES1OnGL2 {
     unsafe fn GetIntegerv(&mut self, pname: GLenum, params: *mut GLint) {
         // This function family can return a huge number of things.
         // TODO: support more possible values.
-        assert!([
-            gl21::ARRAY_BUFFER_BINDING,
-            gl21::ELEMENT_ARRAY_BUFFER_BINDING,
-            gl21::MATRIX_MODE,
-            gl21::TEXTURE_BINDING_2D
-        ]
-        .contains(&pname));
+        let is_array_buffer_binding = ARRAYS
+            .iter()
+            .any(|&ArrayInfo { buffer_binding, .. }| buffer_binding == pname);
+        assert!(
+            is_array_buffer_binding
+                || [
+                    gl21::ARRAY_BUFFER_BINDING,
+                    gl21::ELEMENT_ARRAY_BUFFER_BINDING,
+                    gl21::MATRIX_MODE,
+                    gl21::TEXTURE_BINDING_2D
+                ]
+                .contains(&pname)
+        );
         gl21::GetIntegerv(pname, params);
     }
+    unsafe fn GetPointerv(&mut self, pname: GLenum, params: *mut *const GLvoid) {
+        assert!(ARRAYS
+            .iter()
+            .any(|&ArrayInfo { pointer, .. }| pointer == pname));
+        // gl_generator generates the wrong signature for glGetPointerv by
+        // mistake, hence the cast. See
+        // https://github.com/brendanzab/gl-rs/issues/541
+        gl21::GetPointerv(pname, params.cast());
+    }
+    unsafe fn PushAttrib(&mut self, mask: GLbitfield) {
+        let supported_mask = ATTRIB_BITS.iter().fold(0, |acc, &bit| acc | bit);
+        // GL_ALL_ATTRIB_BITS is common and not worth warning about.
+        if mask & !supported_mask != 0 && mask != gl21::ALL_ATTRIB_BITS {
+            log!(
+                "Warning: glPushAttrib() mask {:#x} has unsupported bits {:#x}, ignoring them",
+                mask,
+                mask & !supported_mask
+            );
+        }
+        // The host OpenGL 2.1 context has its own attribute stack, so it can
+        // do the actual saving and restoring. The attribute stack used by
+        // `present_renderbuffer` in `eagl.rs` is always balanced, so sharing it
+        // with the app is fine.
+        gl21::PushAttrib(mask & supported_mask);
+    }
+    unsafe fn PopAttrib(&mut self) {
+        gl21::PopAttrib();
+    }
 
     // Other state manipulation
     unsafe fn AlphaFunc(&mut self, func: GLenum, ref_: GLclampf) {
@@ -373,6 +471,24 @@ impl GLES for GLES1OnGL2 {
     unsafe fn DepthMask(&mut self, flag: GLboolean) {
         gl21::DepthMask(flag)
     }
+    unsafe fn SampleCoverage(&mut self, value: GLclampf, invert: GLboolean) {
+        // The context we create doesn't necessarily have multisample buffers,
+        // in which case the sample coverage state has no effect anyway.
+        let mut sample_buffers = 0;
+        gl21::GetIntegerv(gl21::SAMPLE_BUFFERS, &mut sample_buffers);
+        if sample_buffers == 0 {
+            log!(
+                "Warning: glSampleCoverage({}, {}) has no effect, there are no multisample buffers",
+                value,
+                invert
+            );
+            return;
+        }
+        gl21::SampleCoverage(value, invert)
+    }
+    unsafe fn SampleCoveragex(&mut self, value: GLclampx, invert: GLboolean) {
+        self.SampleCoverage(fixed_to_float(value), invert)
+    }
     unsafe fn ShadeModel(&mut self, mode: GLenum) {
         assert!(mode == gl21::FLAT || mode == gl21::SMOOTH);
         gl21::ShadeModel(mode);
@@ -435,6 +551,9 @@ impl GLES for GLES1OnGL2 {
             fixed_to_float(alpha),
         )
     }
+    unsafe fn Color4ub(&mut self, red: GLubyte, green: GLubyte, blue: GLubyte, alpha: GLubyte) {
+        gl21::Color4ub(red, green, blue, alpha)
+    }
 
     // Pointers
     unsafe fn ColorPointer(
@@ -445,6 +564,8 @@ impl GLES for GLES1OnGL2 {
         pointer: *const GLvoid,
     ) {
         assert!(size == 4);
+        // GL_UNSIGNED_BYTE (packed RGBA) is the most common type here and can
+        // be passed straight through, as can GL_FLOAT.
         if type_ == gles11::FIXED {
             // Translation deferred until draw call
             self.pointer_is_fixed_point[0] = true;
@@ -507,16 +628,7 @@ impl GLES for GLES1OnGL2 {
 
     // Drawing
     unsafe fn DrawArrays(&mut self, mode: GLenum, first: GLint, count: GLsizei) {
-        assert!([
-            gl21::POINTS,
-            gl21::LINE_STRIP,
-            gl21::LINE_LOOP,
-            gl21::LINES,
-            gl21::TRIANGLE_STRIP,
-            gl21::TRIANGLE_FAN,
-            gl21::TRIANGLES
-        ]
-        .contains(&mode));
+        validate_draw_call(mode, count);
 
         let state_backup = self.translate_fixed_point_arrays(first, count);
 
@@ -531,16 +643,7 @@ impl GLES for GLES1OnGL2 {
         type_: GLenum,
         indices: *const GLvoid,
     ) {
-        assert!([
-            gl21::POINTS,
-            gl21::LINE_STRIP,
-            gl21::LINE_LOOP,
-            gl21::LINES,
-            gl21::TRIANGLE_STRIP,
-            gl21::TRIANGLE_FAN,
-            gl21::TRIANGLES
-        ]
-        .contains(&mode));
+        validate_draw_call(mode, count);
         assert!(type_ == gl21::UNSIGNED_BYTE || type_ == gl21::UNSIGNED_SHORT);
 
         let state_backup = if self.pointer_is_fixed_point.iter().any(|&is_fixed| is_fixed) {
@@ -559,7 +662,6 @@ impl GLES for GLES1OnGL2 {
 
             let mut first = usize::MAX;
             let mut last = usize::MIN;
-            assert!(count >= 0);
             match type_ {
                 gl21::UNSIGNED_BYTE => {
                     let indices_ptr: *const GLubyte = indices.cast();
@@ -656,15 +758,19 @@ impl GLES for GLES1OnGL2 {
     }
     unsafe fn TexParameteri(&mut self, target: GLenum, pname: GLenum, param: GLint) {
         assert!(target == gl21::TEXTURE_2D);
-        assert!(
-            pname == gl21::TEXTURE_MIN_FILTER
-                || pname == gl21::TEXTURE_MAG_FILTER
-                || pname == gl21::TEXTURE_WRAP_S
-                || pname == gl21::TEXTURE_WRAP_T
-                || pname == gl21::GENERATE_MIPMAP
-        );
+        assert!(TEX_PARAMS.contains(&pname));
         gl21::TexParameteri(target, pname, param);
     }
+    unsafe fn GetTexParameteriv(&mut self, target: GLenum, pname: GLenum, params: *mut GLint) {
+        assert!(target == gl21::TEXTURE_2D);
+        assert!(TEX_PARAMS.contains(&pname));
+        gl21::GetTexParameteriv(target, pname, params);
+    }
+    unsafe fn GetTexParameterfv(&mut self, target: GLenum, pname: GLenum, params: *mut GLfloat) {
+        assert!(target == gl21::TEXTURE_2D);
+        assert!(TEX_PARAMS.contains(&pname));
+        gl21::GetTexParameterfv(target, pname, params);
+    }
     unsafe fn TexImage2D(
         &mut self,
         target: GLenum,
@@ -14,10 +14,15 @@ use crate::window::gles11::types::*;
 /// Trait representing an OpenGL ES implementation and context.
 #[allow(clippy::upper_case_acronyms)]
 pub trait GLES {
-    fn new(window: &mut crate::window::Window) -> Self
+    /// Create a new context. If `share_with` is provided, the new context will
+    /// share objects (textures, buffers etc) with it, like contexts in the
+    /// same `EAGLSharegroup`.
+    fn new(window: &mut crate::window::Window, share_with: Option<&dyn GLES>) -> Self
     where
         Self: Sized;
     fn make_current(&self, window: &mut crate::window::Window);
+    /// The underlying OpenGL context, for sharing with new contexts.
+    fn gl_context(&self) -> &crate::window::GLContext;
 
     // Generic state manipulation
     unsafe fn GetError(&mut self) -> GLenum;
@@ -26,12 +31,17 @@ pub trait GLES {
     unsafe fn EnableClientState(&mut self, array: GLenum);
     unsafe fn DisableClientState(&mut self, array: GLenum);
     unsafe fn GetIntegerv(&mut self, pname: GLenum, params: *mut GLint);
+    unsafe fn GetPointerv(&mut self, pname: GLenum, params: *mut *const GLvoid);
+    unsafe fn PushAttrib(&mut self, mask: GLbitfield);
+    unsafe fn PopAttrib(&mut self);
 
     // Other state manipulation
     unsafe fn AlphaFunc(&mut self, func: GLenum, ref_: GLclampf);
     unsafe fn AlphaFuncx(&mut self, func: GLenum, ref_: GLclampx);
     unsafe fn BlendFunc(&mut self, sfactor: GLenum, dfactor: GLenum);
     unsafe fn DepthMask(&mut self, flag: GLboolean);
+    unsafe fn SampleCoverage(&mut self, value: GLclampf, invert: GLboolean);
+    unsafe fn SampleCoveragex(&mut self, value: GLclampx, invert: GLboolean);
     unsafe fn ShadeModel(&mut self, mode: GLenum);
     unsafe fn Scissor(&mut self, x: GLint, y: GLint, width: GLsizei, height: GLsizei);
     unsafe fn Viewport(&mut self, x: GLint, y: GLint, width: GLsizei, height: GLsizei);
@@ -50,6 +60,7 @@ pub trait GLES {
     // Non-pointers
     unsafe fn Color4f(&mut self, red: GLfloat, green: GLfloat, blue: GLfloat, alpha: GLfloat);
     unsafe fn Color4x(&mut self, red: GLfixed, green: GLfixed, blue: GLfixed, alpha: GLfixed);
+    unsafe fn Color4ub(&mut self, red: GLubyte, green: GLubyte, blue: GLubyte, alpha: GLubyte);
 
     // Pointers
     unsafe fn ColorPointer(
@@ -110,6 +121,8 @@ pub trait GLES {
     unsafe fn DeleteTextures(&mut self, n: GLsizei, textures: *const GLuint);
     unsafe fn BindTexture(&mut self, target: GLenum, texture: GLuint);
     unsafe fn TexParameteri(&mut self, target: GLenum, pname: GLenum, param: GLint);
+    unsafe fn GetTexParameteriv(&mut self, target: GLenum, pname: GLenum, params: *mut GLint);
+    unsafe fn GetTexParameterfv(&mut self, target: GLenum, pname: GLenum, params: *mut GLfloat);
     unsafe fn TexImage2D(
         &mut self,
         target: GLenum,
@@ -7,10 +7,10 @@
 
 use super::GLES;
 use crate::dyld::{export_c_func, FunctionExports};
-use crate::mem::{ConstPtr, ConstVoidPtr, GuestUSize, Mem, MutPtr};
+use crate::mem::{ConstPtr, ConstVoidPtr, GuestUSize, Mem, MutPtr, MutVoidPtr, Ptr};
 use crate::window::gles11;
 use crate::window::gles11::types::*;
-use crate::Environment;
+use crate::{Environment, ScaleHackMode};
 
 fn with_ctx_and_mem<T, U>(env: &mut Environment, f: T) -> U
 where
@@ -89,6 +89,12 @@ fn glGetIntegerv(env: &mut Environment, pname: GLenum, params: MutPtr<GLint>) {
         unsafe { gles.GetIntegerv(pname, params) };
     });
 }
+fn glPushAttrib(env: &mut Environment, mask: GLbitfield) {
+    with_ctx_and_mem(env, |gles, _mem| unsafe { gles.PushAttrib(mask) })
+}
+fn glPopAttrib(env: &mut Environment) {
+    with_ctx_and_mem(env, |gles, _mem| unsafe { gles.PopAttrib() })
+}
 
 // Other state manipulation
 fn glAlphaFunc(env: &mut Environment, func: GLenum, ref_: GLclampf) {
@@ -105,17 +111,68 @@ fn glBlendFunc(env: &mut Environment, sfactor: GLenum, dfactor: GLenum) {
 fn glDepthMask(env: &mut Environment, flag: GLboolean) {
     with_ctx_and_mem(env, |gles, _mem| unsafe { gles.DepthMask(flag) })
 }
+fn glSampleCoverage(env: &mut Environment, value: GLclampf, invert: GLboolean) {
+    with_ctx_and_mem(env, |gles, _mem| unsafe {
+        gles.SampleCoverage(value, invert)
+    })
+}
+fn glSampleCoveragex(env: &mut Environment, value: GLclampx, invert: GLboolean) {
+    with_ctx_and_mem(env, |gles, _mem| unsafe {
+        gles.SampleCoveragex(value, invert)
+    })
+}
 fn glShadeModel(env: &mut Environment, mode: GLenum) {
     with_ctx_and_mem(env, |gles, _mem| unsafe { gles.ShadeModel(mode) })
 }
+
+/// If `size` is the size of the screen, get the size with the scale hack
+/// applied. `unscaled` and `scalehacked` are the unrotated screen sizes.
+///
+/// Apps using a landscape orientation will usually use the screen size with
+/// the width and height swapped, so that is also recognized.
+fn scale_hack_size(
+    size: (u32, u32),
+    unscaled: (u32, u32),
+    scalehacked: (u32, u32),
+) -> Option<(u32, u32)> {
+    if size == unscaled {
+        Some(scalehacked)
+    } else if size == (unscaled.1, unscaled.0) {
+        Some((scalehacked.1, scalehacked.0))
+    } else {
+        None
+    }
+}
+
+/// Apply the scale hack (see `--scale-hack` and `--scale-hack-mode`) to a size,
+/// if it's the size of the screen.
+pub(super) fn apply_scale_hack(
+    env: &Environment,
+    width: GLsizei,
+    height: GLsizei,
+    is_renderbuffer: bool,
+) -> (GLsizei, GLsizei) {
+    let enabled = match env.options.scale_hack_mode {
+        ScaleHackMode::Full => true,
+        ScaleHackMode::RenderbufferOnly => is_renderbuffer,
+        ScaleHackMode::Off => false,
+    };
+    if !enabled || width < 0 || height < 0 {
+        return (width, height);
+    }
+    match scale_hack_size(
+        (width as u32, height as u32),
+        env.window.size_unrotated_unscaled(),
+        env.window.size_unrotated_scalehacked(),
+    ) {
+        Some((width, height)) => (width as GLsizei, height as GLsizei),
+        None => (width, height),
+    }
+}
+
 fn glScissor(env: &mut Environment, x: GLint, y: GLint, width: GLsizei, height: GLsizei) {
-    // apply scale hack
-    let (width, height) = if x == 0
-        && y == 0
-        && (width as u32, height as u32) == env.window.size_unrotated_unscaled()
-    {
-        let (width, height) = env.window.size_unrotated_scalehacked();
-        (width as GLsizei, height as GLsizei)
+    let (width, height) = if x == 0 && y == 0 {
+        apply_scale_hack(env, width, height, /* is_renderbuffer: */ false)
     } else {
         (width, height)
     };
@@ -124,13 +181,8 @@ fn glScissor(env: &mut Environment, x: GLint, y: GLint, width: GLsizei, height:
     })
 }
 fn glViewport(env: &mut Environment, x: GLint, y: GLint, width: GLsizei, height: GLsizei) {
-    // apply scale hack
-    let (width, height) = if x == 0
-        && y == 0
-        && (width as u32, height as u32) == env.window.size_unrotated_unscaled()
-    {
-        let (width, height) = env.window.size_unrotated_scalehacked();
-        (width as GLsizei, height as GLsizei)
+    let (width, height) = if x == 0 && y == 0 {
+        apply_scale_hack(env, width, height, /* is_renderbuffer: */ false)
     } else {
         (width, height)
     };
@@ -201,6 +253,11 @@ fn glColor4x(env: &mut Environment, red: GLfixed, green: GLfixed, blue: GLfixed,
         gles.Color4x(red, green, blue, alpha)
     })
 }
+fn glColor4ub(env: &mut Environment, red: GLubyte, green: GLubyte, blue: GLubyte, alpha: GLubyte) {
+    with_ctx_and_mem(env, |gles, _mem| unsafe {
+        gles.Color4ub(red, green, blue, alpha)
+    })
+}
 
 // Pointers
 
@@ -224,6 +281,29 @@ unsafe fn translate_pointer_or_offset(
     }
 }
 
+fn glGetPointerv(env: &mut Environment, pname: GLenum, params: MutPtr<MutVoidPtr>) {
+    with_ctx_and_mem(env, |gles, mem| {
+        let Some(array_info) = super::gles1_on_gl2::ARRAYS
+            .iter()
+            .find(|array_info| array_info.pointer == pname)
+        else {
+            unimplemented!("pname value {:#x}", pname);
+        };
+        let mut host_pointer: *const GLvoid = std::ptr::null();
+        unsafe { gles.GetPointerv(pname, &mut host_pointer) };
+        // This is the inverse of translate_pointer_or_offset(). Whether the
+        // value is a pointer or an offset depends on the buffer binding at the
+        // time the pointer was set, which is remembered per-array.
+        let mut buffer_binding = 0;
+        unsafe { gles.GetIntegerv(array_info.buffer_binding, &mut buffer_binding) };
+        let pointer_or_offset: MutVoidPtr = if buffer_binding != 0 || host_pointer.is_null() {
+            Ptr::from_bits((host_pointer as usize).try_into().unwrap())
+        } else {
+            mem.guest_ptr_from_host_ptr(host_pointer).cast_mut()
+        };
+        mem.write(params, pointer_or_offset);
+    })
+}
 fn glColorPointer(
     env: &mut Environment,
     size: GLint,
@@ -476,6 +556,29 @@ fn glTexParameteri(env: &mut Environment, target: GLenum, pname: GLenum, param:
         gles.TexParameteri(target, pname, param)
     })
 }
+fn glGetTexParameteriv(
+    env: &mut Environment,
+    target: GLenum,
+    pname: GLenum,
+    params: MutPtr<GLint>,
+) {
+    with_ctx_and_mem(env, |gles, mem| {
+        // All the OpenGL ES 1.1 texture parameters have a single value.
+        let params = mem.ptr_at_mut(params, 1);
+        unsafe { gles.GetTexParameteriv(target, pname, params) }
+    })
+}
+fn glGetTexParameterfv(
+    env: &mut Environment,
+    target: GLenum,
+    pname: GLenum,
+    params: MutPtr<GLfloat>,
+) {
+    with_ctx_and_mem(env, |gles, mem| {
+        let params = mem.ptr_at_mut(params, 1);
+        unsafe { gles.GetTexParameterfv(target, pname, params) }
+    })
+}
 fn glTexImage2D(
     env: &mut Environment,
     target: GLenum,
@@ -553,13 +656,7 @@ fn glRenderbufferStorageOES(
     width: GLsizei,
     height: GLsizei,
 ) {
-    // apply scale hack
-    let (width, height) = if (width as u32, height as u32) == env.window.size_unrotated_unscaled() {
-        let (width, height) = env.window.size_unrotated_scalehacked();
-        (width as GLsizei, height as GLsizei)
-    } else {
-        (width, height)
-    };
+    let (width, height) = apply_scale_hack(env, width, height, /* is_renderbuffer: */ true);
     with_ctx_and_mem(env, |gles, _mem| unsafe {
         gles.RenderbufferStorageOES(target, internalformat, width, height)
     })
@@ -600,11 +697,15 @@ pub const FUNCTIONS: FunctionExports = &[
     export_c_func!(glEnableClientState(_)),
     export_c_func!(glDisableClientState(_)),
     export_c_func!(glGetIntegerv(_, _)),
+    export_c_func!(glPushAttrib(_)),
+    export_c_func!(glPopAttrib()),
     // Other state manipulation
     export_c_func!(glAlphaFunc(_, _)),
     export_c_func!(glAlphaFuncx(_, _)),
     export_c_func!(glBlendFunc(_, _)),
     export_c_func!(glDepthMask(_)),
+    export_c_func!(glSampleCoverage(_, _)),
+    export_c_func!(glSampleCoveragex(_, _)),
     export_c_func!(glShadeModel(_)),
     export_c_func!(glScissor(_, _, _, _)),
     export_c_func!(glViewport(_, _, _, _)),
@@ -620,7 +721,9 @@ pub const FUNCTIONS: FunctionExports = &[
     // Non-pointers
     export_c_func!(glColor4f(_, _, _, _)),
     export_c_func!(glColor4x(_, _, _, _)),
+    export_c_func!(glColor4ub(_, _, _, _)),
     // Pointers
+    export_c_func!(glGetPointerv(_, _)),
     export_c_func!(glColorPointer(_, _, _, _)),
     export_c_func!(glNormalPointer(_, _, _)),
     export_c_func!(glTexCoordPointer(_, _, _, _)),
@@ -659,6 +762,8 @@ pub const FUNCTIONS: FunctionExports = &[
     export_c_func!(glDeleteTextures(_, _)),
     export_c_func!(glBindTexture(_, _)),
     export_c_func!(glTexParameteri(_, _, _)),
+    export_c_func!(glGetTexParameteriv(_, _, _)),
+    export_c_func!(glGetTexParameterfv(_, _, _)),
     export_c_func!(glTexImage2D(_, _, _, _, _, _, _, _, _)),
     // OES_framebuffer_object
     export_c_func!(glGenFramebuffersOES(_, _)),
@@ -670,3 +775,43 @@ pub const FUNCTIONS: FunctionExports = &[
     export_c_func!(glGetRenderbufferParameterivOES(_, _, _)),
     export_c_func!(glCheckFramebufferStatusOES(_)),
 ];
+
+#[cfg(test)]
+mod scale_hack_tests {
+    use super::scale_hack_size;
+    use crate::window::{size_for_orientation, DeviceOrientation};
+    use std::num::NonZeroU32;
+
+    const UNSCALED: (u32, u32) = (320, 480);
+
+    #[test]
+    fn orientations() {
+        let unscaled = NonZeroU32::new(1).unwrap();
+        for scale in 1..=4 {
+            let scale = NonZeroU32::new(scale).unwrap();
+            let scalehacked = (320 * scale.get(), 480 * scale.get());
+            for orientation in [
+                DeviceOrientation::Portrait,
+                DeviceOrientation::LandscapeLeft,
+            ] {
+                // An app uses the screen size for its current orientation.
+                let size = size_for_orientation(orientation, unscaled);
+                assert_eq!(
+                    scale_hack_size(size, UNSCALED, scalehacked),
+                    Some(size_for_orientation(orientation, scale)),
+                    "{:?} at scale {}",
+                    orientation,
+                    scale
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn other_sizes_are_unaffected() {
+        let scalehacked = (640, 960);
+        for size in [(0, 0), (320, 320), (480, 480), (256, 256), (640, 960)] {
+            assert_eq!(scale_hack_size(size, UNSCALED, scalehacked), None);
+        }
+    }
+}
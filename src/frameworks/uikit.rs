@@ -9,14 +9,20 @@
 //! likely to use UIKit in very simple and limited ways, so this implementation
 //! will probably take a lot of shortcuts.
 
+use super::core_animation;
+use crate::objc::{id, msg};
 use crate::Environment;
 
 pub mod ui_accelerometer;
 pub mod ui_application;
+pub mod ui_color;
 pub mod ui_device;
 pub mod ui_event;
 pub mod ui_font;
 pub mod ui_graphics;
+pub mod ui_image;
+pub mod ui_image_view;
+pub mod ui_label;
 pub mod ui_nib;
 pub mod ui_responder;
 pub mod ui_screen;
@@ -30,17 +36,28 @@ pub struct State {
     ui_application: ui_application::State,
     ui_font: ui_font::State,
     ui_graphics: ui_graphics::State,
+    ui_image: ui_image::State,
     ui_screen: ui_screen::State,
     ui_touch: ui_touch::State,
     ui_view: ui_view::State,
+    ui_window: ui_window::State,
 }
 
 /// For use by `NSRunLoop`: handles any events that have queued up.
 pub fn handle_events(env: &mut Environment) {
     use crate::window::Event;
 
+    if env.input_recording.is_some() {
+        let now = crate::libc::time::monotonic_since_startup(env);
+        env.input_recording.as_mut().unwrap().begin_tick(now);
+    }
+
     loop {
-        let Some(event) = env.window.pop_event() else {
+        let event = match &mut env.input_recording {
+            Some(recording) => recording.next_event(&mut env.window),
+            None => env.window.pop_event(),
+        };
+        let Some(event) = event else {
             break;
         };
 
@@ -52,8 +69,23 @@ pub fn handle_events(env: &mut Environment) {
             Event::TouchDown(..) | Event::TouchMove(..) | Event::TouchUp(..) => {
                 ui_touch::handle_event(env, event)
             }
+            Event::FocusLost
+            | Event::FocusGained
+            | Event::EnterBackground
+            | Event::EnterForeground => ui_application::handle_lifecycle_event(env, event),
         }
     }
 
     ui_accelerometer::handle_accelerometer(env);
 }
+
+/// For use by `NSRunLoop`: composites the visible windows for display, if
+/// necessary.
+pub fn recomposite_if_necessary(env: &mut Environment) {
+    let windows = env.framework_state.uikit.ui_window.visible_windows.clone();
+    let layers: Vec<id> = windows
+        .into_iter()
+        .map(|window| msg![env; window layer])
+        .collect();
+    core_animation::composition::recomposite_if_necessary(env, &layers);
+}
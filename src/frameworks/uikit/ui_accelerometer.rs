@@ -8,6 +8,7 @@
 //! Useful resources:
 //! - [Apple's documentation for UIAcceleration](https://developer.apple.com/documentation/uikit/uiacceleration) has a really nice diagram of how the accelerometer axes relate to an iPhone.
 
+use crate::frameworks::foundation::ns_timer::reschedule;
 use crate::frameworks::foundation::NSTimeInterval;
 use crate::mem::MutVoidPtr;
 use crate::objc::{
@@ -27,6 +28,13 @@ pub struct State {
     due_by: Option<Instant>,
 }
 
+/// The real default doesn't seem to be documented anywhere, so this is a
+/// guess. Apps usually set their own interval anyway.
+const DEFAULT_UPDATE_INTERVAL: NSTimeInterval = 1.0 / 10.0;
+/// The iPhone's accelerometer can't be polled faster than 100Hz, and requests
+/// for a shorter interval are clamped to this.
+const MIN_UPDATE_INTERVAL: NSTimeInterval = 1.0 / 100.0;
+
 type UIAccelerationValue = f64;
 
 struct UIAccelerationHostObject {
@@ -77,11 +85,22 @@ pub const CLASSES: ClassExports = objc_classes! {
 }
 
 - (NSTimeInterval)updateInterval {
-    // TODO: return some reasonable default value
-    env.framework_state.uikit.ui_accelerometer.update_interval.unwrap()
+    env.framework_state
+        .uikit
+        .ui_accelerometer
+        .update_interval
+        .unwrap_or(DEFAULT_UPDATE_INTERVAL)
 }
 - (())setUpdateInterval:(NSTimeInterval)interval {
-    env.framework_state.uikit.ui_accelerometer.update_interval = Some(interval);
+    let interval = if interval.is_finite() {
+        interval.max(MIN_UPDATE_INTERVAL)
+    } else {
+        DEFAULT_UPDATE_INTERVAL
+    };
+    let state = &mut env.framework_state.uikit.ui_accelerometer;
+    state.update_interval = Some(interval);
+    // Start the new schedule straight away.
+    state.due_by = None;
 }
 
 @end
@@ -124,37 +143,24 @@ pub(super) fn handle_accelerometer(env: &mut Environment) {
         return;
     };
 
-    // TODO: use some reasonable default value
-    let ns_interval = state.update_interval.unwrap();
-    let rust_interval = Duration::from_secs_f64(ns_interval);
+    let interval = state.update_interval.unwrap_or(DEFAULT_UPDATE_INTERVAL);
+    let interval = Duration::from_secs_f64(interval);
 
-    let now = Instant::now();
-    if let Some(due_by) = state.due_by {
-        if due_by > now {
-            return;
-        }
-
-        // See NSTimer implementation for a discussion of what this does.
-        // I don't know if iPhone OS uses this approach for accelerometer
-        // updates, but there's no obvious reason not to.
-        let overdue_by = now.duration_since(due_by);
-        // TODO: Use `.div_duration_f64()` once that is stabilized.
-        let advance_by = (overdue_by.as_secs_f64() / ns_interval).max(1.0).ceil();
-        assert!(advance_by == (advance_by as u32) as f64);
-        let advance_by = advance_by as u32;
-        if advance_by > 1 {
-            log!("Warning: Accelerometer is lagging. It is overdue by {}s and has missed {} interval(s)!", overdue_by.as_secs_f64(), advance_by - 1);
-        }
-        let advance_by = rust_interval.checked_mul(advance_by).unwrap();
-        state.due_by = Some(due_by.checked_add(advance_by).unwrap());
-    } else {
-        state.due_by = Some(now.checked_add(rust_interval).unwrap());
-    }
+    let mut live = || {
+        update_is_due(&mut state.due_by, Instant::now(), interval)
+            .then(|| env.window.get_acceleration(&env.options))
+    };
+    let acceleration = match &mut env.input_recording {
+        Some(recording) => recording.acceleration(live),
+        None => live(),
+    };
+    let Some((x, y, z)) = acceleration else {
+        return;
+    };
 
     // UIKit creates and drains autorelease pools when handling events.
     let pool: id = msg_class![env; NSAutoreleasePool new];
 
-    let (x, y, z) = env.window.get_acceleration(&env.options);
     let timestamp: NSTimeInterval = msg_class![env; NSProcessInfo systemUptime];
     let acceleration: id = msg_class![env; UIAcceleration alloc];
     *env.objc.borrow_mut(acceleration) = UIAccelerationHostObject {
@@ -178,3 +184,68 @@ pub(super) fn handle_accelerometer(env: &mut Environment) {
 
     release(env, pool);
 }
+
+/// Work out whether an accelerometer update should be sent at `now`, and if so,
+/// when the next one will be due.
+fn update_is_due(due_by: &mut Option<Instant>, now: Instant, interval: Duration) -> bool {
+    if let Some(old_due_by) = *due_by {
+        if old_due_by > now {
+            return false;
+        }
+
+        // See NSTimer implementation for a discussion of what this does.
+        // I don't know if iPhone OS uses this approach for accelerometer
+        // updates, but there's no obvious reason not to.
+        let (next, missed) = reschedule(old_due_by, now, interval);
+        if missed > 0 {
+            log!(
+                "Warning: Accelerometer is lagging. It is overdue by {}s and has missed {} interval(s)!",
+                now.duration_since(old_due_by).as_secs_f64(),
+                missed
+            );
+        }
+        *due_by = Some(next);
+    } else {
+        *due_by = Some(now.checked_add(interval).unwrap());
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::update_is_due;
+    use std::time::{Duration, Instant};
+
+    /// Simulate a run loop polling the accelerometer at regular intervals,
+    /// and return the times (in ms since the start) at which updates are sent.
+    fn update_times(interval_ms: u64, tick_ms: u64, total_ms: u64) -> Vec<u64> {
+        let start = Instant::now();
+        let interval = Duration::from_millis(interval_ms);
+        let mut due_by = None;
+        let mut times = Vec::new();
+        let mut elapsed = 0;
+        while elapsed <= total_ms {
+            if update_is_due(
+                &mut due_by,
+                start + Duration::from_millis(elapsed),
+                interval,
+            ) {
+                times.push(elapsed);
+            }
+            elapsed += tick_ms;
+        }
+        times
+    }
+
+    #[test]
+    fn update_timing() {
+        // The first update is sent immediately.
+        assert_eq!(update_times(100, 100, 500), [0, 100, 200, 300, 400, 500]);
+        // Polled at roughly 60Hz: updates are sent at the first opportunity
+        // after they're due, without drifting.
+        assert_eq!(update_times(50, 16, 200), [0, 64, 112, 160]);
+        // Polled more slowly than the update rate: missed updates are not
+        // caught up on.
+        assert_eq!(update_times(10, 25, 100), [0, 25, 50, 75, 100]);
+    }
+}
@@ -6,20 +6,75 @@
 //! `UIApplication` and `UIApplicationMain`.
 
 use super::ui_device::*;
-use crate::dyld::{export_c_func, FunctionExports};
-use crate::frameworks::foundation::ns_string;
+use crate::dyld::{export_c_func, ConstantExports, FunctionExports, HostConstant};
+use crate::frameworks::foundation::{ns_notification_center, ns_string, ns_user_defaults};
 use crate::frameworks::uikit::ui_nib::load_main_nib_file;
 use crate::mem::{MutPtr, MutVoidPtr};
-use crate::objc::{id, msg, msg_class, nil, objc_classes, retain, ClassExports, HostObject};
-use crate::window::DeviceOrientation;
+use crate::objc::{
+    id, msg, msg_class, msg_send, nil, objc_classes, retain, ClassExports, HostObject,
+};
+use crate::window::{DeviceOrientation, Event};
 use crate::Environment;
 
 #[derive(Default)]
 pub struct State {
     /// [UIApplication sharedApplication]
     shared_application: Option<id>,
+    app_state: AppState,
+}
+
+/// The app's state, as far as its delegate is concerned.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+enum AppState {
+    /// Running in the foreground, but not receiving events (this is also the
+    /// state while launching).
+    #[default]
+    Inactive,
+    /// Running in the foreground and receiving events.
+    Active,
+    /// Not visible. touchHLE keeps running the app, but it should have paused
+    /// itself.
+    Background,
 }
 
+// Notification names (NSString*)
+const UIApplicationDidFinishLaunchingNotification: &str =
+    "UIApplicationDidFinishLaunchingNotification";
+const UIApplicationDidBecomeActiveNotification: &str = "UIApplicationDidBecomeActiveNotification";
+const UIApplicationWillResignActiveNotification: &str = "UIApplicationWillResignActiveNotification";
+const UIApplicationDidEnterBackgroundNotification: &str =
+    "UIApplicationDidEnterBackgroundNotification";
+const UIApplicationWillEnterForegroundNotification: &str =
+    "UIApplicationWillEnterForegroundNotification";
+const UIApplicationWillTerminateNotification: &str = "UIApplicationWillTerminateNotification";
+
+pub const CONSTANTS: ConstantExports = &[
+    (
+        "_UIApplicationDidFinishLaunchingNotification",
+        HostConstant::NSString(UIApplicationDidFinishLaunchingNotification),
+    ),
+    (
+        "_UIApplicationDidBecomeActiveNotification",
+        HostConstant::NSString(UIApplicationDidBecomeActiveNotification),
+    ),
+    (
+        "_UIApplicationWillResignActiveNotification",
+        HostConstant::NSString(UIApplicationWillResignActiveNotification),
+    ),
+    (
+        "_UIApplicationDidEnterBackgroundNotification",
+        HostConstant::NSString(UIApplicationDidEnterBackgroundNotification),
+    ),
+    (
+        "_UIApplicationWillEnterForegroundNotification",
+        HostConstant::NSString(UIApplicationWillEnterForegroundNotification),
+    ),
+    (
+        "_UIApplicationWillTerminateNotification",
+        HostConstant::NSString(UIApplicationWillTerminateNotification),
+    ),
+];
+
 struct UIApplicationHostObject {
     delegate: id,
 }
@@ -99,10 +154,23 @@ pub const CLASSES: ClassExports = objc_classes! {
     env.window.set_screen_saver_enabled(!disabled);
 }
 
+- (bool)canOpenURL:(id)url { // NSURL
+    let ns_string = msg![env; url absoluteString];
+    let url_string = ns_string::to_rust_string(env, ns_string);
+    can_open_url_on_host(&url_string)
+}
+
 - (bool)openURL:(id)url { // NSURL
-    let ns_string = msg![env; url absoluteURL];
+    let ns_string = msg![env; url absoluteString];
     let url_string = ns_string::to_rust_string(env, ns_string);
-    crate::window::open_url(&url_string);
+    if !can_open_url_on_host(&url_string) {
+        // This is probably a URL for some other app, which we can't launch.
+        log!("App tried to open URL {:?} with an unsupported scheme, ignoring.", url_string);
+        return false;
+    }
+    if !crate::window::open_url(&url_string) {
+        log!("Warning: couldn't open URL {:?} on the host", url_string);
+    }
 
     // iPhone OS doesn't really do multitasking, so the app expects to close
     // when a URL is opened, e.g. Super Monkey Ball keeps opening the URL every
@@ -156,12 +224,23 @@ pub(super) fn UIApplicationMain(
 
     {
         let pool: id = msg_class![env; NSAutoreleasePool new];
-        () = msg![env; delegate applicationDidFinishLaunching:ui_application];
+        if delegate_responds(env, delegate, "application:didFinishLaunchingWithOptions:") {
+            // TODO: launch options
+            let _: bool = msg![env; delegate application:ui_application
+                                   didFinishLaunchingWithOptions:nil];
+        } else if delegate_responds(env, delegate, "applicationDidFinishLaunching:") {
+            () = msg![env; delegate applicationDidFinishLaunching:ui_application];
+        }
+        ns_notification_center::post_notification(
+            env,
+            UIApplicationDidFinishLaunchingNotification,
+            ui_application,
+        );
         let _: () = msg![env; pool drain];
     }
 
-    // FIXME: There are more messages we should send.
-    // TODO: Send UIApplicationDidFinishLaunchingNotification?
+    env.framework_state.uikit.ui_application.app_state = AppState::Active;
+    send_to_delegate(env, "applicationDidBecomeActive:");
 
     // TODO: It might be nicer to return from this function (even though it's
     // conceptually noreturn) and set some global flag that changes how the
@@ -174,18 +253,156 @@ pub(super) fn UIApplicationMain(
 
 /// Tell the app it's about to quit and then exit.
 pub(super) fn exit(env: &mut Environment) {
+    send_to_delegate(env, "applicationWillTerminate:");
+    ns_user_defaults::synchronize_standard_user_defaults(env);
+
+    std::process::exit(0);
+}
+
+/// For use by [super::handle_events]: update the app's state in response to
+/// the window losing or regaining focus or being hidden or shown, and tell the
+/// delegate about it.
+pub(super) fn handle_lifecycle_event(env: &mut Environment, event: Event) {
+    let old_state = env.framework_state.uikit.ui_application.app_state;
+    let (new_state, messages) = lifecycle_transition(old_state, &event);
+    if new_state == old_state {
+        return;
+    }
+    log_dbg!("App state change: {:?} => {:?}", old_state, new_state);
+    env.framework_state.uikit.ui_application.app_state = new_state;
+    for &selector in messages {
+        send_to_delegate(env, selector);
+    }
+}
+
+/// Work out the app's new state after an event, and which delegate methods
+/// should be called (in order) to tell it about the change.
+fn lifecycle_transition(state: AppState, event: &Event) -> (AppState, &'static [&'static str]) {
+    match (state, event) {
+        (AppState::Active, Event::FocusLost) => {
+            (AppState::Inactive, &["applicationWillResignActive:"])
+        }
+        (AppState::Inactive, Event::FocusGained) => {
+            (AppState::Active, &["applicationDidBecomeActive:"])
+        }
+        (AppState::Active, Event::EnterBackground) => (
+            AppState::Background,
+            &[
+                "applicationWillResignActive:",
+                "applicationDidEnterBackground:",
+            ],
+        ),
+        (AppState::Inactive, Event::EnterBackground) => {
+            (AppState::Background, &["applicationDidEnterBackground:"])
+        }
+        // The window might not regain focus when it's restored, but the app
+        // probably won't resume unless it becomes active.
+        (AppState::Background, Event::EnterForeground) => (
+            AppState::Active,
+            &[
+                "applicationWillEnterForeground:",
+                "applicationDidBecomeActive:",
+            ],
+        ),
+        _ => (state, &[]),
+    }
+}
+
+/// Check whether the app delegate implements an optional
+/// `UIApplicationDelegate` method.
+fn delegate_responds(env: &mut Environment, delegate: id, selector: &str) -> bool {
+    let Some(sel) = env.objc.lookup_selector(selector) else {
+        return false;
+    };
+    delegate != nil && msg![env; delegate respondsToSelector:sel]
+}
+
+/// Send a `UIApplicationDelegate` message that takes the `UIApplication` as
+/// its only argument, if the delegate implements it, and then post the
+/// corresponding notification.
+fn send_to_delegate(env: &mut Environment, selector: &str) {
     let ui_application: id = msg_class![env; UIApplication sharedApplication];
     let delegate: id = msg![env; ui_application delegate];
 
-    // FIXME: There are more messages we should send.
+    let pool: id = msg_class![env; NSAutoreleasePool new];
+    if delegate_responds(env, delegate, selector) {
+        let sel = env.objc.lookup_selector(selector).unwrap();
+        let _: () = msg_send(env, (delegate, sel, ui_application));
+    }
+    if let Some(name) = notification_for_delegate_message(selector) {
+        ns_notification_center::post_notification(env, name, ui_application);
+    }
+    let _: () = msg![env; pool drain];
+}
 
-    {
-        let pool: id = msg_class![env; NSAutoreleasePool new];
-        () = msg![env; delegate applicationWillTerminate:ui_application];
-        let _: () = msg![env; pool drain];
+/// The notification posted after each `UIApplicationDelegate` message.
+fn notification_for_delegate_message(selector: &str) -> Option<&'static str> {
+    match selector {
+        "applicationDidBecomeActive:" => Some(UIApplicationDidBecomeActiveNotification),
+        "applicationWillResignActive:" => Some(UIApplicationWillResignActiveNotification),
+        "applicationDidEnterBackground:" => Some(UIApplicationDidEnterBackgroundNotification),
+        "applicationWillEnterForeground:" => Some(UIApplicationWillEnterForegroundNotification),
+        "applicationWillTerminate:" => Some(UIApplicationWillTerminateNotification),
+        _ => None,
     }
+}
 
-    std::process::exit(0);
+/// Check whether a URL can be handed off to the host OS. URLs with other
+/// schemes are probably meant for other iPhone OS apps.
+fn can_open_url_on_host(url: &str) -> bool {
+    let Some((scheme, _)) = url.split_once(':') else {
+        return false;
+    };
+    ["http", "https", "mailto"]
+        .iter()
+        .any(|supported| scheme.eq_ignore_ascii_case(supported))
 }
 
 pub const FUNCTIONS: FunctionExports = &[export_c_func!(UIApplicationMain(_, _, _, _))];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn url_schemes() {
+        assert!(can_open_url_on_host("http://example.com/"));
+        assert!(can_open_url_on_host("HTTPS://example.com/"));
+        assert!(can_open_url_on_host("mailto:someone@example.com"));
+        assert!(!can_open_url_on_host("itms-apps://example.com/"));
+        assert!(!can_open_url_on_host("example.com"));
+    }
+
+    #[test]
+    fn lifecycle() {
+        let mut state = AppState::Active;
+        let mut send = |event| {
+            let (new_state, messages) = lifecycle_transition(state, &event);
+            state = new_state;
+            messages
+        };
+        assert_eq!(send(Event::FocusLost), ["applicationWillResignActive:"]);
+        assert_eq!(send(Event::FocusLost), [] as [&str; 0]);
+        assert_eq!(
+            send(Event::EnterBackground),
+            ["applicationDidEnterBackground:"]
+        );
+        assert_eq!(send(Event::FocusGained), [] as [&str; 0]);
+        assert_eq!(
+            send(Event::EnterForeground),
+            [
+                "applicationWillEnterForeground:",
+                "applicationDidBecomeActive:"
+            ]
+        );
+        assert_eq!(send(Event::FocusGained), [] as [&str; 0]);
+        assert_eq!(
+            send(Event::EnterBackground),
+            [
+                "applicationWillResignActive:",
+                "applicationDidEnterBackground:"
+            ]
+        );
+        assert_eq!(state, AppState::Background);
+    }
+}
@@ -0,0 +1,229 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! `UIColor`.
+
+use super::ui_graphics::UIGraphicsGetCurrentContext;
+use crate::frameworks::core_graphics::cg_color::{
+    self, CGColorCreateGenericRGB, CGColorRef, CGColorRelease, CGColorRetain,
+};
+use crate::frameworks::core_graphics::cg_context::{
+    self, CGContextRef, CGContextSetStrokeColorWithColor,
+};
+use crate::frameworks::core_graphics::CGFloat;
+use crate::mem::{Mem, MutPtr, MutVoidPtr};
+use crate::objc::{autorelease, id, msg, nil, objc_classes, Class, ClassExports, HostObject, ObjC};
+use crate::Environment;
+
+struct UIColorHostObject {
+    cg_color: CGColorRef,
+}
+impl HostObject for UIColorHostObject {}
+
+pub const CLASSES: ClassExports = objc_classes! {
+
+(env, this, _cmd);
+
+@implementation UIColor: NSObject
+
++ (id)allocWithZone:(MutVoidPtr)_zone {
+    let host_object = Box::new(UIColorHostObject { cg_color: nil });
+    env.objc.alloc_object(this, host_object, &mut env.mem)
+}
+
++ (id)colorWithRed:(CGFloat)red green:(CGFloat)green blue:(CGFloat)blue alpha:(CGFloat)alpha {
+    let new: id = msg![env; this alloc];
+    let new: id = msg![env; new initWithRed:red green:green blue:blue alpha:alpha];
+    autorelease(env, new)
+}
+
++ (id)colorWithWhite:(CGFloat)white alpha:(CGFloat)alpha {
+    let new: id = msg![env; this alloc];
+    let new: id = msg![env; new initWithWhite:white alpha:alpha];
+    autorelease(env, new)
+}
++ (id)colorWithCGColor:(CGColorRef)cg_color {
+    let new: id = msg![env; this alloc];
+    let new: id = msg![env; new initWithCGColor:cg_color];
+    autorelease(env, new)
+}
+
+// Standard colors. The non-grayscale ones are defined in RGB.
++ (id)blackColor {
+    color_with_rgba(env, this, (0.0, 0.0, 0.0, 1.0))
+}
++ (id)darkGrayColor {
+    color_with_rgba(env, this, (1.0 / 3.0, 1.0 / 3.0, 1.0 / 3.0, 1.0))
+}
++ (id)grayColor {
+    color_with_rgba(env, this, (0.5, 0.5, 0.5, 1.0))
+}
++ (id)lightGrayColor {
+    color_with_rgba(env, this, (2.0 / 3.0, 2.0 / 3.0, 2.0 / 3.0, 1.0))
+}
++ (id)whiteColor {
+    color_with_rgba(env, this, (1.0, 1.0, 1.0, 1.0))
+}
++ (id)clearColor {
+    color_with_rgba(env, this, (0.0, 0.0, 0.0, 0.0))
+}
++ (id)redColor {
+    color_with_rgba(env, this, (1.0, 0.0, 0.0, 1.0))
+}
++ (id)greenColor {
+    color_with_rgba(env, this, (0.0, 1.0, 0.0, 1.0))
+}
++ (id)blueColor {
+    color_with_rgba(env, this, (0.0, 0.0, 1.0, 1.0))
+}
++ (id)cyanColor {
+    color_with_rgba(env, this, (0.0, 1.0, 1.0, 1.0))
+}
++ (id)yellowColor {
+    color_with_rgba(env, this, (1.0, 1.0, 0.0, 1.0))
+}
++ (id)magentaColor {
+    color_with_rgba(env, this, (1.0, 0.0, 1.0, 1.0))
+}
++ (id)orangeColor {
+    color_with_rgba(env, this, (1.0, 0.5, 0.0, 1.0))
+}
++ (id)purpleColor {
+    color_with_rgba(env, this, (0.5, 0.0, 0.5, 1.0))
+}
++ (id)brownColor {
+    color_with_rgba(env, this, (0.6, 0.4, 0.2, 1.0))
+}
+
+- (id)initWithRed:(CGFloat)red green:(CGFloat)green blue:(CGFloat)blue alpha:(CGFloat)alpha {
+    let cg_color = CGColorCreateGenericRGB(env, red, green, blue, alpha);
+    env.objc.borrow_mut::<UIColorHostObject>(this).cg_color = cg_color;
+    this
+}
+- (id)initWithWhite:(CGFloat)white alpha:(CGFloat)alpha {
+    // Only RGB colors are supported for now.
+    msg![env; this initWithRed:white green:white blue:white alpha:alpha]
+}
+- (id)initWithCGColor:(CGColorRef)cg_color {
+    let cg_color = CGColorRetain(env, cg_color);
+    env.objc.borrow_mut::<UIColorHostObject>(this).cg_color = cg_color;
+    this
+}
+
+- (())dealloc {
+    let cg_color = env.objc.borrow::<UIColorHostObject>(this).cg_color;
+    CGColorRelease(env, cg_color);
+    env.objc.dealloc_object(this, &mut env.mem)
+}
+
+- (CGColorRef)CGColor {
+    env.objc.borrow::<UIColorHostObject>(this).cg_color
+}
+
+- (bool)getRed:(MutPtr<CGFloat>)red
+         green:(MutPtr<CGFloat>)green
+          blue:(MutPtr<CGFloat>)blue
+         alpha:(MutPtr<CGFloat>)alpha {
+    let (r, g, b, a) = get_rgba(&env.objc, &env.mem, this);
+    for (ptr, value) in [(red, r), (green, g), (blue, b), (alpha, a)] {
+        if !ptr.is_null() {
+            env.mem.write(ptr, value);
+        }
+    }
+    true
+}
+
+- (id)colorWithAlphaComponent:(CGFloat)alpha {
+    let (r, g, b, _) = get_rgba(&env.objc, &env.mem, this);
+    let class: Class = msg![env; this class];
+    color_with_rgba(env, class, (r, g, b, alpha))
+}
+
+// These configure the current graphics context.
+- (())set {
+    () = msg![env; this setFill];
+    () = msg![env; this setStroke];
+}
+- (())setFill {
+    let context = UIGraphicsGetCurrentContext(env);
+    if context == nil {
+        log!("Warning: [(UIColor*){:?} setFill] with no current context", this);
+        return;
+    }
+    set_fill(&mut env.objc, &env.mem, this, context);
+}
+- (())setStroke {
+    let context = UIGraphicsGetCurrentContext(env);
+    if context == nil {
+        log!("Warning: [(UIColor*){:?} setStroke] with no current context", this);
+        return;
+    }
+    let cg_color = env.objc.borrow::<UIColorHostObject>(this).cg_color;
+    CGContextSetStrokeColorWithColor(env, context, cg_color);
+}
+
+@end
+
+};
+
+fn color_with_rgba(
+    env: &mut Environment,
+    class: Class,
+    (red, green, blue, alpha): (CGFloat, CGFloat, CGFloat, CGFloat),
+) -> id {
+    msg![env; class colorWithRed:red green:green blue:blue alpha:alpha]
+}
+
+fn get_rgba(objc: &ObjC, mem: &Mem, color: id) -> (CGFloat, CGFloat, CGFloat, CGFloat) {
+    let cg_color = objc.borrow::<UIColorHostObject>(color).cg_color;
+    cg_color::get_rgba(objc, mem, cg_color)
+}
+
+/// The part of `-setFill` that doesn't need the current context.
+fn set_fill(objc: &mut ObjC, mem: &Mem, color: id, context: CGContextRef) {
+    let cg_color = objc.borrow::<UIColorHostObject>(color).cg_color;
+    cg_context::set_fill_color(objc, mem, context, cg_color);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frameworks::core_graphics::cg_bitmap_context::{self, CGBitmapContextDrawer};
+    use crate::frameworks::core_graphics::cg_color_space::{
+        create_with_name, kCGColorSpaceGenericRGB,
+    };
+    use crate::frameworks::core_graphics::cg_image::kCGImageAlphaPremultipliedLast;
+    use crate::mem::Ptr;
+
+    #[test]
+    fn components_and_fill() {
+        let mut objc = ObjC::new();
+        let mut mem = Mem::new();
+        let rgba = (1.0, 0.5, 0.25, 0.75);
+
+        // What -initWithRed:green:blue:alpha: does.
+        let cg_color = cg_color::create_generic_rgb(&mut objc, &mut mem, rgba);
+        let class = objc.get_known_class("UIColor", &mut mem);
+        let color = objc.alloc_object(class, Box::new(UIColorHostObject { cg_color }), &mut mem);
+        assert_eq!(get_rgba(&objc, &mem, color), rgba);
+
+        // -set sends -setFill, which does this with the current context.
+        let color_space = create_with_name(&mut objc, &mut mem, kCGColorSpaceGenericRGB);
+        let context = cg_bitmap_context::create(
+            &mut objc,
+            &mut mem,
+            Ptr::null(),
+            1,
+            1,
+            8,
+            0,
+            color_space,
+            kCGImageAlphaPremultipliedLast,
+        );
+        set_fill(&mut objc, &mem, color, context);
+        let drawer = CGBitmapContextDrawer::new(&objc, &mut mem, context);
+        assert_eq!(drawer.rgb_fill_color(), rgba);
+    }
+}
@@ -5,14 +5,79 @@
  */
 //! `UIEvent`.
 
-use crate::objc::{objc_classes, ClassExports};
+use crate::frameworks::foundation::{ns_set, NSTimeInterval};
+use crate::mem::MutVoidPtr;
+use crate::objc::{
+    autorelease, id, msg, msg_class, objc_classes, release, ClassExports, HostObject,
+};
+use crate::Environment;
+
+struct UIEventHostObject {
+    /// `UITouch*` strong references. Only touch events are supported.
+    touches: Vec<id>,
+    timestamp: NSTimeInterval,
+}
+impl HostObject for UIEventHostObject {}
 
 pub const CLASSES: ClassExports = objc_classes! {
 
 (env, this, _cmd);
 
 @implementation UIEvent: NSObject
-// TODO
+
++ (id)allocWithZone:(MutVoidPtr)_zone {
+    let host_object = Box::new(UIEventHostObject {
+        touches: Vec::new(),
+        timestamp: 0.0,
+    });
+    env.objc.alloc_object(this, host_object, &mut env.mem)
+}
+
+- (())dealloc {
+    let touches = std::mem::take(&mut env.objc.borrow_mut::<UIEventHostObject>(this).touches);
+    for touch in touches {
+        release(env, touch);
+    }
+    env.objc.dealloc_object(this, &mut env.mem)
+}
+
+- (NSTimeInterval)timestamp {
+    env.objc.borrow::<UIEventHostObject>(this).timestamp
+}
+
+- (id)allTouches {
+    let touches = env.objc.borrow::<UIEventHostObject>(this).touches.clone();
+    let set = ns_set::from_vec(env, touches);
+    autorelease(env, set)
+}
+
+- (id)touchesForView:(id)view { // UIView*
+    let touches = env.objc.borrow::<UIEventHostObject>(this).touches.clone();
+    let mut touches_for_view = Vec::new();
+    for touch in touches {
+        let touch_view: id = msg![env; touch view];
+        if touch_view == view {
+            touches_for_view.push(touch);
+        }
+    }
+    let set = ns_set::from_vec(env, touches_for_view);
+    autorelease(env, set)
+}
+
+// TODO: touchesForWindow:, event types, motion events
+
 @end
 
 };
+
+/// For use by `UITouch`: create a new touch event with the given touches
+/// (which should already be retained by the `Vec`).
+pub(super) fn new_touch_event(
+    env: &mut Environment,
+    touches: Vec<id>,
+    timestamp: NSTimeInterval,
+) -> id {
+    let event: id = msg_class![env; UIEvent new];
+    *env.objc.borrow_mut(event) = UIEventHostObject { touches, timestamp };
+    event
+}
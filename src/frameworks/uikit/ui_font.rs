@@ -6,11 +6,12 @@
 //! `UIFont`.
 
 use super::ui_graphics::UIGraphicsGetCurrentContext;
-use crate::font::{Font, TextAlignment, WrapMode};
+use crate::font::{read_font_names, Font, FontNames, TextAlignment, WrapMode};
 use crate::frameworks::core_graphics::cg_bitmap_context::CGBitmapContextDrawer;
-use crate::frameworks::core_graphics::{CGFloat, CGRect, CGSize};
+use crate::frameworks::core_graphics::{CGFloat, CGPoint, CGRect, CGSize};
+use crate::frameworks::foundation::ns_string::{from_rust_string, to_rust_string};
 use crate::frameworks::foundation::NSInteger;
-use crate::objc::{autorelease, id, objc_classes, ClassExports, HostObject};
+use crate::objc::{autorelease, id, msg, objc_classes, Class, ClassExports, HostObject};
 use crate::Environment;
 
 #[derive(Default)]
@@ -20,13 +21,17 @@ pub(super) struct State {
     italic: Option<Font>,
     regular_ja: Option<Font>,
     bold_ja: Option<Font>,
+    /// Fonts listed in the app's `UIAppFonts`, loaded when first needed.
+    app_fonts: Option<Vec<(FontNames, Font)>>,
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 enum FontKind {
     Regular,
     Bold,
     Italic,
+    /// Index into [State::app_fonts].
+    App(usize),
 }
 
 struct UIFontHostObject {
@@ -42,13 +47,9 @@ impl HostObject for UIFontHostObject {}
 pub type UILineBreakMode = NSInteger;
 pub const UILineBreakModeWordWrap: UILineBreakMode = 0;
 pub const UILineBreakModeCharacterWrap: UILineBreakMode = 1;
-#[allow(dead_code)]
 pub const UILineBreakModeClip: UILineBreakMode = 2;
-#[allow(dead_code)]
 pub const UILineBreakModeHeadTruncation: UILineBreakMode = 3;
-#[allow(dead_code)]
 pub const UILineBreakModeTailTruncation: UILineBreakMode = 4;
-#[allow(dead_code)]
 pub const UILineBreakModeMiddleTruncation: UILineBreakMode = 5;
 
 /// Text alignment.
@@ -67,56 +68,168 @@ pub const CLASSES: ClassExports = objc_classes! {
 @implementation UIFont: NSObject
 
 + (id)systemFontOfSize:(CGFloat)size {
-    // Cache for later use
-    if env.framework_state.uikit.ui_font.regular.is_none() {
-        env.framework_state.uikit.ui_font.regular = Some(Font::sans_regular());
-    }
-    let host_object = UIFontHostObject {
-        size,
-        kind: FontKind::Regular,
-    };
-    let new = env.objc.alloc_object(this, Box::new(host_object), &mut env.mem);
-    autorelease(env, new)
+    new_font(env, this, size, FontKind::Regular)
 }
 + (id)boldSystemFontOfSize:(CGFloat)size {
-    // Cache for later use
-    if env.framework_state.uikit.ui_font.bold.is_none() {
-        env.framework_state.uikit.ui_font.bold = Some(Font::sans_bold());
-    }
-    let host_object = UIFontHostObject {
-        size,
-        kind: FontKind::Bold,
-    };
-    let new = env.objc.alloc_object(this, Box::new(host_object), &mut env.mem);
-    autorelease(env, new)
+    new_font(env, this, size, FontKind::Bold)
 }
 + (id)italicSystemFontOfSize:(CGFloat)size {
-    // Cache for later use
-    if env.framework_state.uikit.ui_font.italic.is_none() {
-        env.framework_state.uikit.ui_font.italic = Some(Font::sans_italic());
-    }
-    let host_object = UIFontHostObject {
-        size,
-        kind: FontKind::Italic,
+    new_font(env, this, size, FontKind::Italic)
+}
++ (id)fontWithName:(id)name // NSString*
+              size:(CGFloat)size {
+    let name = to_rust_string(env, name).into_owned();
+    let app_font = app_fonts(env).iter().position(|(names, _)| names.postscript == name);
+    let kind = if let Some(index) = app_font {
+        FontKind::App(index)
+    } else {
+        let kind = system_font_kind(&name);
+        if !name.starts_with("Helvetica") {
+            log!("Warning: substituting {:?} for font {:?}", font_name(env, kind), name);
+        }
+        kind
     };
-    let new = env.objc.alloc_object(this, Box::new(host_object), &mut env.mem);
-    autorelease(env, new)
+    new_font(env, this, size, kind)
+}
+
+- (id)fontWithSize:(CGFloat)size {
+    let kind = env.objc.borrow::<UIFontHostObject>(this).kind;
+    let class: Class = msg![env; this class];
+    new_font(env, class, size, kind)
+}
+
+- (CGFloat)pointSize {
+    env.objc.borrow::<UIFontHostObject>(this).size
+}
+
+- (id)fontName {
+    let kind = env.objc.borrow::<UIFontHostObject>(this).kind;
+    let name = font_name(env, kind).to_string();
+    let name = from_rust_string(env, name);
+    autorelease(env, name)
+}
+- (id)familyName {
+    let name = match env.objc.borrow::<UIFontHostObject>(this).kind {
+        FontKind::App(index) => app_fonts(env)[index].0.family.clone(),
+        _ => "Helvetica".to_string(),
+    };
+    let name = from_rust_string(env, name);
+    autorelease(env, name)
+}
+
+- (CGFloat)ascender {
+    let (font, size) = font_and_size(env, this);
+    font.ascent(size)
+}
+- (CGFloat)descender {
+    let (font, size) = font_and_size(env, this);
+    font.descent(size)
+}
+- (CGFloat)lineHeight {
+    let (font, size) = font_and_size(env, this);
+    font.line_height(size)
 }
 
 @end
 
 };
 
+fn new_font(env: &mut Environment, class: Class, size: CGFloat, kind: FontKind) -> id {
+    // Cache for later use
+    let state = &mut env.framework_state.uikit.ui_font;
+    match kind {
+        FontKind::Regular => _ = state.regular.get_or_insert_with(Font::sans_regular),
+        FontKind::Bold => _ = state.bold.get_or_insert_with(Font::sans_bold),
+        FontKind::Italic => _ = state.italic.get_or_insert_with(Font::sans_italic),
+        // Already loaded by app_fonts()
+        FontKind::App(_) => (),
+    }
+    let host_object = UIFontHostObject { size, kind };
+    let new = env
+        .objc
+        .alloc_object(class, Box::new(host_object), &mut env.mem);
+    autorelease(env, new)
+}
+
+/// Get the fonts listed in the app's `Info.plist` under `UIAppFonts`, loading
+/// them if this is the first time.
+fn app_fonts(env: &mut Environment) -> &[(FontNames, Font)] {
+    if env.framework_state.uikit.ui_font.app_fonts.is_none() {
+        let mut app_fonts = Vec::new();
+        for path in env.bundle.app_font_paths() {
+            let Ok(bytes) = env.fs.read(&path) else {
+                log!("Warning: couldn't read app font {:?}", path.as_str());
+                continue;
+            };
+            let names = read_font_names(&bytes);
+            match (names, Font::from_bytes(bytes)) {
+                (Some(names), Some(font)) => app_fonts.push((names, font)),
+                _ => log!("Warning: couldn't load app font {:?}", path.as_str()),
+            }
+        }
+        env.framework_state.uikit.ui_font.app_fonts = Some(app_fonts);
+    }
+    env.framework_state
+        .uikit
+        .ui_font
+        .app_fonts
+        .as_ref()
+        .unwrap()
+}
+
+/// Pick the closest of the system fonts for a font name that isn't one of the
+/// app's own fonts. The system fonts stand in for Helvetica.
+fn system_font_kind(name: &str) -> FontKind {
+    let style = name.split_once('-').map_or("", |(_family, style)| style);
+    if style.contains("Bold") {
+        FontKind::Bold
+    } else if style.contains("Oblique") || style.contains("Italic") {
+        FontKind::Italic
+    } else {
+        FontKind::Regular
+    }
+}
+
+fn font_name(env: &mut Environment, kind: FontKind) -> &str {
+    match kind {
+        FontKind::Regular => "Helvetica",
+        FontKind::Bold => "Helvetica-Bold",
+        FontKind::Italic => "Helvetica-Oblique",
+        FontKind::App(index) => &app_fonts(env)[index].0.postscript,
+    }
+}
+
+/// Get the font used for Latin text by a `UIFont`, and its size.
+fn font_and_size(env: &mut Environment, font: id) -> (&Font, CGFloat) {
+    let &UIFontHostObject { size, kind } = env.objc.borrow(font);
+    (
+        get_font(&mut env.framework_state.uikit.ui_font, kind, ""),
+        size,
+    )
+}
+
 fn convert_line_break_mode(ui_mode: UILineBreakMode) -> WrapMode {
     match ui_mode {
         UILineBreakModeWordWrap => WrapMode::Word,
         UILineBreakModeCharacterWrap => WrapMode::Char,
+        // Text is wrapped at word boundaries in the other modes too. They
+        // only affect what happens when the text doesn't fit.
+        UILineBreakModeClip
+        | UILineBreakModeHeadTruncation
+        | UILineBreakModeTailTruncation
+        | UILineBreakModeMiddleTruncation => WrapMode::Word,
         _ => unimplemented!("TODO: line break mode {}", ui_mode),
     }
 }
 
 #[rustfmt::skip]
 fn get_font<'a>(state: &'a mut State, kind: FontKind, text: &str) -> &'a Font {
+    // App fonts are used as-is, since they were presumably chosen for the
+    // text they're used with.
+    if let FontKind::App(index) = kind {
+        return &state.app_fonts.as_ref().unwrap()[index].1;
+    }
+
     // The default fonts (see font.rs) are the Liberation family, which are a
     // good substitute for Helvetica, the iPhone OS system font. Unfortunately,
     // there is no CJK support in these fonts. To support Super Monkey Ball in
@@ -143,6 +256,7 @@ fn get_font<'a>(state: &'a mut State, kind: FontKind, text: &str) -> &'a Font {
                     }
                     return state.bold_ja.as_ref().unwrap();
                 },
+                FontKind::App(_) => unreachable!(),
             }
         }
     }
@@ -151,6 +265,7 @@ fn get_font<'a>(state: &'a mut State, kind: FontKind, text: &str) -> &'a Font {
         FontKind::Regular => state.regular.as_ref().unwrap(),
         FontKind::Bold => state.bold.as_ref().unwrap(),
         FontKind::Italic => state.italic.as_ref().unwrap(),
+        FontKind::App(_) => unreachable!(),
     }
 }
 
@@ -176,6 +291,134 @@ pub fn size_with_font(
     CGSize { width, height }
 }
 
+/// Called by `UILabel` to fit its text within its bounds. Returns the lines
+/// that fit, joined by newlines, with the last one truncated according to the
+/// line break mode if necessary. A `max_lines` of 0 means there is no limit
+/// other than the height.
+pub fn fit_text(
+    env: &mut Environment,
+    font: id,
+    text: &str,
+    size: CGSize,
+    max_lines: NSInteger,
+    line_break_mode: UILineBreakMode,
+) -> String {
+    let host_object = env.objc.borrow::<UIFontHostObject>(font);
+    let font_size = host_object.size;
+    let font = get_font(
+        &mut env.framework_state.uikit.ui_font,
+        host_object.kind,
+        text,
+    );
+
+    // A single-line label never wraps its text.
+    let wrap = (max_lines != 1).then(|| (size.width, convert_line_break_mode(line_break_mode)));
+    let lines: Vec<&str> = font
+        .break_lines(font_size, text, wrap)
+        .into_iter()
+        .map(|(_width, line)| line)
+        .collect();
+
+    // Always show at least one line, even if it doesn't fit vertically.
+    let mut line_count = 1;
+    while line_count < lines.len()
+        && (max_lines <= 0 || line_count < max_lines as usize)
+        && font
+            .calculate_text_size(font_size, &lines[..line_count + 1].join("\n"), None)
+            .1
+            <= size.height
+    {
+        line_count += 1;
+    }
+
+    fit_lines(&lines, line_count, size.width, line_break_mode, |line| {
+        font.calculate_text_size(font_size, line, None).0
+    })
+}
+
+/// Keep the first `line_count` lines and make the last of them fit `width`,
+/// either by clipping it or by truncating it with an ellipsis, depending on the
+/// line break mode. The `measure` callback returns the width of a line.
+fn fit_lines<F: Fn(&str) -> CGFloat>(
+    lines: &[&str],
+    line_count: usize,
+    width: CGFloat,
+    line_break_mode: UILineBreakMode,
+    measure: F,
+) -> String {
+    let Some((&last, rest)) = lines[..line_count.min(lines.len())].split_last() else {
+        return String::new();
+    };
+    let overflowed = line_count < lines.len();
+    let ellipsize = matches!(
+        line_break_mode,
+        UILineBreakModeHeadTruncation
+            | UILineBreakModeTailTruncation
+            | UILineBreakModeMiddleTruncation
+    );
+
+    let last = if !overflowed && measure(last) <= width {
+        last.to_string()
+    } else if !ellipsize {
+        // Clip to the characters that fit.
+        let end = char_boundaries(last)
+            .rev()
+            .find(|&end| measure(&last[..end]) <= width)
+            .unwrap_or(0);
+        last[..end].to_string()
+    } else {
+        // When lines were dropped, the last line gets an ellipsis even if it
+        // would otherwise fit.
+        truncate_line(last.trim_end(), width, line_break_mode, measure)
+    };
+
+    let mut fitted: Vec<&str> = rest.to_vec();
+    fitted.push(&last);
+    fitted.join("\n")
+}
+
+fn char_boundaries(text: &str) -> impl DoubleEndedIterator<Item = usize> + '_ {
+    text.char_indices()
+        .map(|(i, _)| i)
+        .chain(std::iter::once(text.len()))
+}
+
+/// Truncate a line with an ellipsis so that it fits `width`.
+fn truncate_line<F: Fn(&str) -> CGFloat>(
+    line: &str,
+    width: CGFloat,
+    line_break_mode: UILineBreakMode,
+    measure: F,
+) -> String {
+    const ELLIPSIS: &str = "\u{2026}";
+    let boundaries: Vec<usize> = char_boundaries(line).collect();
+    let char_count = boundaries.len() - 1;
+    // Try keeping fewer and fewer characters until the result fits.
+    for kept in (0..=char_count).rev() {
+        let candidate = match line_break_mode {
+            UILineBreakModeHeadTruncation => {
+                let start = boundaries[char_count - kept];
+                format!("{}{}", ELLIPSIS, line[start..].trim_start())
+            }
+            UILineBreakModeMiddleTruncation => {
+                let head_end = boundaries[kept.div_ceil(2)];
+                let tail_start = boundaries[char_count - kept / 2];
+                format!(
+                    "{}{}{}",
+                    line[..head_end].trim_end(),
+                    ELLIPSIS,
+                    line[tail_start..].trim_start()
+                )
+            }
+            _ => format!("{}{}", line[..boundaries[kept]].trim_end(), ELLIPSIS),
+        };
+        if kept == 0 || measure(&candidate) <= width {
+            return candidate;
+        }
+    }
+    unreachable!()
+}
+
 /// Called by the `drawInRect:` method family on `NSString`.
 pub fn draw_in_rect(
     env: &mut Environment,
@@ -184,10 +427,37 @@ pub fn draw_in_rect(
     rect: CGRect,
     line_break_mode: UILineBreakMode,
     alignment: UITextAlignment,
+) -> CGSize {
+    draw(env, font, text, rect, Some(line_break_mode), alignment)
+}
+
+/// Called by the `drawAtPoint:` method family on `NSString`. The text is not
+/// wrapped.
+pub fn draw_at_point(env: &mut Environment, font: id, text: &str, point: CGPoint) -> CGSize {
+    let size = size_with_font(env, font, text, None);
+    let rect = CGRect {
+        origin: point,
+        size,
+    };
+    draw(env, font, text, rect, None, UITextAlignmentLeft)
+}
+
+fn draw(
+    env: &mut Environment,
+    font: id,
+    text: &str,
+    rect: CGRect,
+    line_break_mode: Option<UILineBreakMode>,
+    alignment: UITextAlignment,
 ) -> CGSize {
     let context = UIGraphicsGetCurrentContext(env);
 
-    let text_size = size_with_font(env, font, text, Some((rect.size, line_break_mode)));
+    let text_size = size_with_font(
+        env,
+        font,
+        text,
+        line_break_mode.map(|mode| (rect.size, mode)),
+    );
 
     let host_object = env.objc.borrow::<UIFontHostObject>(font);
 
@@ -200,6 +470,10 @@ pub fn draw_in_rect(
     let mut drawer = CGBitmapContextDrawer::new(&env.objc, &mut env.mem, context);
 
     let fill_color = drawer.rgb_fill_color();
+    let transform = drawer.user_to_pixel_transform();
+    // The text is rendered with y pointing up, but UIKit's coordinate system
+    // has y pointing down, so it must be reflected within its bounds.
+    let reflect_y = 2.0 * rect.origin.y + text_size.height;
 
     let (origin_x_offset, alignment) = match alignment {
         UITextAlignmentLeft => (0.0, TextAlignment::Left),
@@ -212,14 +486,60 @@ pub fn draw_in_rect(
         host_object.size,
         text,
         (rect.origin.x + origin_x_offset, rect.origin.y),
-        Some((rect.size.width, convert_line_break_mode(line_break_mode))),
+        line_break_mode.map(|mode| (rect.size.width, convert_line_break_mode(mode))),
         alignment,
         |(x, y), coverage| {
             let (r, g, b, a) = fill_color;
             let (r, g, b, a) = (r * coverage, g * coverage, b * coverage, a * coverage);
-            drawer.put_pixel((x, y), (r, g, b, a));
+            let point = (x as CGFloat + 0.5, reflect_y - (y as CGFloat + 0.5));
+            let (x, y) = transform.apply_to_point(point);
+            drawer.put_pixel((x.floor() as i32, y.floor() as i32), (r, g, b, a));
         },
     );
 
     text_size
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn system_font_substitution() {
+        assert_eq!(system_font_kind("Helvetica"), FontKind::Regular);
+        assert_eq!(system_font_kind("Helvetica-BoldOblique"), FontKind::Bold);
+        assert_eq!(system_font_kind("Arial-ItalicMT"), FontKind::Italic);
+        assert_eq!(system_font_kind("MarkerFelt-Thin"), FontKind::Regular);
+        assert_eq!(system_font_kind("BoldFamilyName"), FontKind::Regular);
+    }
+
+    /// Every character is 1 unit wide.
+    fn measure(text: &str) -> CGFloat {
+        text.chars().count() as CGFloat
+    }
+
+    #[test]
+    fn fit_label_lines() {
+        let lines = ["The quick ", "brown fox ", "jumps"];
+
+        // Everything fits.
+        let fitted = fit_lines(&lines, 3, 10.0, UILineBreakModeTailTruncation, measure);
+        assert_eq!(fitted, "The quick \nbrown fox \njumps");
+
+        // Dropping lines adds an ellipsis to the last one kept.
+        let fitted = fit_lines(&lines, 2, 10.0, UILineBreakModeTailTruncation, measure);
+        assert_eq!(fitted, "The quick \nbrown fox\u{2026}");
+        let fitted = fit_lines(&lines, 2, 10.0, UILineBreakModeWordWrap, measure);
+        assert_eq!(fitted, "The quick \nbrown fox ");
+
+        // A single line that is too wide.
+        let line = ["Hello, world"];
+        let fit = |mode| fit_lines(&line, 1, 8.0, mode, measure);
+        assert_eq!(fit(UILineBreakModeTailTruncation), "Hello,\u{2026}");
+        assert_eq!(fit(UILineBreakModeHeadTruncation), "\u{2026}world");
+        assert_eq!(fit(UILineBreakModeMiddleTruncation), "Hell\u{2026}rld");
+        assert_eq!(fit(UILineBreakModeClip), "Hello, w");
+
+        assert_eq!(fit_lines(&[], 1, 8.0, UILineBreakModeClip, measure), "");
+    }
+}
@@ -5,19 +5,34 @@
  */
 //! `UIGraphics.h`
 
+use super::ui_image::UIImageHostObject;
 use crate::dyld::{export_c_func, FunctionExports};
+use crate::frameworks::core_graphics::cg_bitmap_context::{
+    CGBitmapContextCreate, CGBitmapContextCreateImage,
+};
+use crate::frameworks::core_graphics::cg_color_space::{
+    create_with_name, kCGColorSpaceGenericRGB, CGColorSpaceRelease,
+};
 use crate::frameworks::core_graphics::cg_context::{
-    CGContextRef, CGContextRelease, CGContextRetain,
+    CGContextRef, CGContextRelease, CGContextRetain, CGContextScaleCTM, CGContextTranslateCTM,
+};
+use crate::frameworks::core_graphics::cg_image::{
+    kCGImageAlphaNoneSkipLast, kCGImageAlphaPremultipliedLast, CGImageRelease,
 };
-use crate::objc::nil;
+use crate::frameworks::core_graphics::{CGFloat, CGSize};
+use crate::mem::{GuestUSize, Ptr};
+use crate::objc::{autorelease, id, msg, msg_class, nil};
 use crate::Environment;
 
 #[derive(Default)]
 pub(super) struct State {
     pub(super) context_stack: Vec<CGContextRef>,
+    /// Contexts created by `UIGraphicsBeginImageContext` and friends, which
+    /// are also on the context stack, with their scale factors.
+    image_contexts: Vec<(CGContextRef, CGFloat)>,
 }
 
-fn UIGraphicsPushContext(env: &mut Environment, context: CGContextRef) {
+pub(super) fn UIGraphicsPushContext(env: &mut Environment, context: CGContextRef) {
     CGContextRetain(env, context);
     env.framework_state
         .uikit
@@ -25,7 +40,7 @@ fn UIGraphicsPushContext(env: &mut Environment, context: CGContextRef) {
         .context_stack
         .push(context);
 }
-fn UIGraphicsPopContext(env: &mut Environment) {
+pub(super) fn UIGraphicsPopContext(env: &mut Environment) {
     let context = env.framework_state.uikit.ui_graphics.context_stack.pop();
     CGContextRelease(env, context.unwrap());
 }
@@ -39,8 +54,104 @@ pub(super) fn UIGraphicsGetCurrentContext(env: &mut Environment) -> CGContextRef
         .unwrap_or(nil)
 }
 
+/// Size in pixels of the bitmap for an image context.
+fn pixel_size(size: CGSize, scale: CGFloat) -> (GuestUSize, GuestUSize) {
+    let width = (size.width * scale).ceil().max(0.0) as GuestUSize;
+    let height = (size.height * scale).ceil().max(0.0) as GuestUSize;
+    (width, height)
+}
+
+fn UIGraphicsBeginImageContext(env: &mut Environment, size: CGSize) {
+    UIGraphicsBeginImageContextWithOptions(env, size, false, 1.0)
+}
+fn UIGraphicsBeginImageContextWithOptions(
+    env: &mut Environment,
+    size: CGSize,
+    opaque: bool,
+    scale: CGFloat,
+) {
+    // A scale of 0 means the scale of the main screen. touchHLE emulates a
+    // device with a non-Retina display, so that is always 1.
+    let scale = if scale == 0.0 { 1.0 } else { scale };
+    let (width, height) = pixel_size(size, scale);
+    assert!(width != 0 && height != 0);
+
+    let color_space = create_with_name(&mut env.objc, &mut env.mem, kCGColorSpaceGenericRGB);
+    let alpha_info = if opaque {
+        kCGImageAlphaNoneSkipLast
+    } else {
+        kCGImageAlphaPremultipliedLast
+    };
+    let context = CGBitmapContextCreate(
+        env,
+        Ptr::null(),
+        width,
+        height,
+        8,
+        0,
+        color_space,
+        alpha_info,
+    );
+    CGColorSpaceRelease(env, color_space);
+    // UIKit's co-ordinate system has the origin at the top-left, so the
+    // context must be flipped, and it is in points rather than pixels.
+    CGContextTranslateCTM(env, context, 0.0, height as CGFloat);
+    CGContextScaleCTM(env, context, scale, -scale);
+
+    UIGraphicsPushContext(env, context);
+    // The context stack now holds the only reference that's needed.
+    CGContextRelease(env, context);
+    env.framework_state
+        .uikit
+        .ui_graphics
+        .image_contexts
+        .push((context, scale));
+}
+fn UIGraphicsGetImageFromCurrentImageContext(env: &mut Environment) -> id {
+    let state = &env.framework_state.uikit.ui_graphics;
+    let current_context = state.context_stack.last().copied();
+    let Some(&(context, scale)) = state.image_contexts.last() else {
+        return nil;
+    };
+    if current_context != Some(context) {
+        return nil;
+    }
+
+    let cg_image = CGBitmapContextCreateImage(env, context);
+    let image: id = msg_class![env; UIImage alloc];
+    let image: id = msg![env; image initWithCGImage:cg_image];
+    CGImageRelease(env, cg_image);
+    env.objc.borrow_mut::<UIImageHostObject>(image).scale = scale;
+    autorelease(env, image)
+}
+fn UIGraphicsEndImageContext(env: &mut Environment) {
+    let state = &mut env.framework_state.uikit.ui_graphics;
+    let (context, _scale) = state.image_contexts.pop().unwrap();
+    assert!(state.context_stack.last() == Some(&context));
+    UIGraphicsPopContext(env);
+}
+
 pub const FUNCTIONS: FunctionExports = &[
     export_c_func!(UIGraphicsPushContext(_)),
     export_c_func!(UIGraphicsPopContext()),
     export_c_func!(UIGraphicsGetCurrentContext()),
+    export_c_func!(UIGraphicsBeginImageContext(_)),
+    export_c_func!(UIGraphicsBeginImageContextWithOptions(_, _, _)),
+    export_c_func!(UIGraphicsGetImageFromCurrentImageContext()),
+    export_c_func!(UIGraphicsEndImageContext()),
 ];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn image_context_pixel_size() {
+        let size = CGSize {
+            width: 10.0,
+            height: 2.5,
+        };
+        assert_eq!(pixel_size(size, 1.0), (10, 3));
+        assert_eq!(pixel_size(size, 2.0), (20, 5));
+    }
+}
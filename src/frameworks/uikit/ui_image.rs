@@ -0,0 +1,154 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! `UIImage`.
+
+use crate::frameworks::core_graphics::cg_image::{
+    self, CGImageGetHeight, CGImageGetWidth, CGImageRef, CGImageRelease, CGImageRetain,
+};
+use crate::frameworks::core_graphics::{CGFloat, CGSize};
+use crate::frameworks::foundation::ns_string::to_rust_string;
+use crate::fs::{GuestPath, GuestPathBuf};
+use crate::image::Image;
+use crate::mem::MutVoidPtr;
+use crate::objc::{autorelease, id, msg, nil, objc_classes, release, ClassExports, HostObject};
+use crate::Environment;
+use std::collections::HashMap;
+
+#[derive(Default)]
+pub struct State {
+    /// Images returned by `imageNamed:`, which are kept forever.
+    named_images: HashMap<String, id>,
+}
+
+pub(super) struct UIImageHostObject {
+    pub(super) cg_image: CGImageRef,
+    pub(super) scale: CGFloat,
+}
+impl HostObject for UIImageHostObject {}
+
+pub const CLASSES: ClassExports = objc_classes! {
+
+(env, this, _cmd);
+
+@implementation UIImage: NSObject
+
++ (id)allocWithZone:(MutVoidPtr)_zone {
+    let host_object = Box::new(UIImageHostObject {
+        cg_image: nil,
+        scale: 1.0,
+    });
+    env.objc.alloc_object(this, host_object, &mut env.mem)
+}
+
++ (id)imageNamed:(id)name { // NSString*
+    let name = to_rust_string(env, name).into_owned();
+    if let Some(&existing) = env.framework_state.uikit.ui_image.named_images.get(&name) {
+        return existing;
+    }
+
+    let Some((path, scale)) = find_named_image(env, &name) else {
+        log!("Warning: [UIImage imageNamed:{:?}] => nil (no such image)", name);
+        return nil;
+    };
+    let new: id = msg![env; this alloc];
+    let new = init_with_path(env, new, &path, scale);
+    if new != nil {
+        env.framework_state.uikit.ui_image.named_images.insert(name, new);
+    }
+    new
+}
+
++ (id)imageWithContentsOfFile:(id)path { // NSString*
+    let new: id = msg![env; this alloc];
+    let new: id = msg![env; new initWithContentsOfFile:path];
+    autorelease(env, new)
+}
+
++ (id)imageWithCGImage:(CGImageRef)cg_image {
+    let new: id = msg![env; this alloc];
+    let new: id = msg![env; new initWithCGImage:cg_image];
+    autorelease(env, new)
+}
+
+- (id)initWithContentsOfFile:(id)path { // NSString*
+    let path = GuestPathBuf::from(to_rust_string(env, path).into_owned());
+    // @2x images are only loaded when explicitly asked for by path
+    let scale = if path.as_str().contains("@2x.") { 2.0 } else { 1.0 };
+    init_with_path(env, this, &path, scale)
+}
+
+- (id)initWithCGImage:(CGImageRef)cg_image {
+    let cg_image = CGImageRetain(env, cg_image);
+    env.objc.borrow_mut::<UIImageHostObject>(this).cg_image = cg_image;
+    this
+}
+
+- (())dealloc {
+    let &UIImageHostObject { cg_image, .. } = env.objc.borrow(this);
+    CGImageRelease(env, cg_image);
+    env.objc.dealloc_object(this, &mut env.mem)
+}
+
+- (CGImageRef)CGImage {
+    env.objc.borrow::<UIImageHostObject>(this).cg_image
+}
+
+- (CGFloat)scale {
+    env.objc.borrow::<UIImageHostObject>(this).scale
+}
+
+- (CGSize)size {
+    let &UIImageHostObject { cg_image, scale } = env.objc.borrow(this);
+    let width = CGImageGetWidth(env, cg_image) as CGFloat;
+    let height = CGImageGetHeight(env, cg_image) as CGFloat;
+    CGSize {
+        width: width / scale,
+        height: height / scale,
+    }
+}
+
+@end
+
+};
+
+/// Find the file for `+[UIImage imageNamed:]` in the app bundle, returning the
+/// path and the image's scale.
+fn find_named_image(env: &mut Environment, name: &str) -> Option<(GuestPathBuf, CGFloat)> {
+    // The extension is optional and defaults to PNG.
+    let (stem, extension) = match name.rsplit_once('.') {
+        Some((stem, extension)) if !extension.contains('/') => (stem, extension),
+        _ => (name, "png"),
+    };
+    let stem = stem.strip_suffix("@2x").unwrap_or(stem);
+
+    // touchHLE emulates a device with a non-Retina display, so the normal image
+    // is preferred, but some apps only include the @2x version.
+    let bundle_path = env.bundle.bundle_path();
+    [
+        (format!("{}.{}", stem, extension), 1.0),
+        (format!("{}@2x.{}", stem, extension), 2.0),
+    ]
+    .into_iter()
+    .map(|(file, scale)| (bundle_path.join(file), scale))
+    .find(|(path, _)| env.fs.is_file(path))
+}
+
+fn init_with_path(env: &mut Environment, this: id, path: &GuestPath, scale: CGFloat) -> id {
+    let Ok(bytes) = env.fs.read(path) else {
+        release(env, this);
+        return nil;
+    };
+    let Ok(image) = Image::from_bytes(&bytes) else {
+        log!("Warning: couldn't decode image {:?}", path.as_str());
+        release(env, this);
+        return nil;
+    };
+    let cg_image = cg_image::from_image(env, image);
+    let host_object = env.objc.borrow_mut::<UIImageHostObject>(this);
+    host_object.cg_image = cg_image;
+    host_object.scale = scale;
+    this
+}
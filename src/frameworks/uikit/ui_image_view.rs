@@ -0,0 +1,58 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! `UIImageView`.
+
+use super::ui_view::UIViewHostObject;
+use crate::frameworks::core_graphics::cg_image::CGImageRef;
+use crate::frameworks::core_graphics::{CGFloat, CGPoint, CGRect, CGSize};
+use crate::objc::{id, msg, nil, objc_classes, release, retain, ClassExports};
+
+pub const CLASSES: ClassExports = objc_classes! {
+
+(env, this, _cmd);
+
+@implementation UIImageView: UIView
+
+- (id)initWithImage:(id)image { // UIImage*
+    let size = if image != nil {
+        msg![env; image size]
+    } else {
+        CGSize { width: 0.0, height: 0.0 }
+    };
+    let frame = CGRect {
+        origin: CGPoint { x: 0.0, y: 0.0 },
+        size,
+    };
+    let this: id = msg![env; this initWithFrame:frame];
+    () = msg![env; this setImage:image];
+    this
+}
+
+- (id)image {
+    env.objc.borrow::<UIViewHostObject>(this).image
+}
+- (())setImage:(id)image { // UIImage*
+    retain(env, image);
+    let host_object = env.objc.borrow_mut::<UIViewHostObject>(this);
+    let old_image = std::mem::replace(&mut host_object.image, image);
+    release(env, old_image);
+
+    // The image is displayed by making it the contents of the layer.
+    let (cg_image, scale): (CGImageRef, CGFloat) = if image != nil {
+        (msg![env; image CGImage], msg![env; image scale])
+    } else {
+        (nil, 1.0)
+    };
+    let layer: id = msg![env; this layer];
+    () = msg![env; layer setContents:cg_image];
+    () = msg![env; layer setContentsScale:scale];
+}
+
+// TODO: highlighted images, animation
+
+@end
+
+};
@@ -0,0 +1,208 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! `UILabel`.
+
+use super::ui_font::{
+    self, UILineBreakMode, UILineBreakModeTailTruncation, UILineBreakModeWordWrap, UITextAlignment,
+    UITextAlignmentLeft,
+};
+use super::ui_graphics::UIGraphicsGetCurrentContext;
+use super::ui_view::UIViewHostObject;
+use crate::frameworks::core_graphics::cg_color::CGColorRef;
+use crate::frameworks::core_graphics::cg_context::CGContextSetFillColorWithColor;
+use crate::frameworks::core_graphics::{CGFloat, CGPoint, CGRect, CGSize};
+use crate::frameworks::foundation::ns_string::to_rust_string;
+use crate::frameworks::foundation::NSInteger;
+use crate::objc::{id, msg, msg_class, nil, objc_classes, release, retain, ClassExports};
+use crate::Environment;
+
+/// `UILabel`'s properties. These are stored in [UIViewHostObject], since a
+/// subclass can't have a host object of its own.
+pub(super) struct UILabelProperties {
+    /// `NSString*`, possibly nil. Strong reference.
+    text: id,
+    /// `UIFont*`, nil until first needed. Strong reference.
+    font: id,
+    /// `UIColor*`, nil until first needed. Strong reference.
+    text_color: id,
+    text_alignment: UITextAlignment,
+    number_of_lines: NSInteger,
+    line_break_mode: UILineBreakMode,
+}
+impl Default for UILabelProperties {
+    fn default() -> Self {
+        UILabelProperties {
+            text: nil,
+            font: nil,
+            text_color: nil,
+            text_alignment: UITextAlignmentLeft,
+            number_of_lines: 1,
+            line_break_mode: UILineBreakModeTailTruncation,
+        }
+    }
+}
+impl UILabelProperties {
+    /// For use by `UIView`'s destructor.
+    pub(super) fn release(self, env: &mut Environment) {
+        release(env, self.text);
+        release(env, self.font);
+        release(env, self.text_color);
+    }
+}
+
+fn properties(env: &mut Environment, label: id) -> &mut UILabelProperties {
+    &mut env.objc.borrow_mut::<UIViewHostObject>(label).label
+}
+
+/// Replace one of the label's object properties, and redraw the label.
+fn set_object(
+    env: &mut Environment,
+    this: id,
+    value: id,
+    field: fn(&mut UILabelProperties) -> &mut id,
+) {
+    let old = std::mem::replace(field(properties(env, this)), value);
+    release(env, old);
+    () = msg![env; this setNeedsDisplay];
+}
+
+pub const CLASSES: ClassExports = objc_classes! {
+
+(env, this, _cmd);
+
+@implementation UILabel: UIView
+
+- (id)text {
+    properties(env, this).text
+}
+- (())setText:(id)text { // NSString*
+    let text: id = msg![env; text copy];
+    set_object(env, this, text, |properties| &mut properties.text);
+}
+
+- (id)font {
+    let font = properties(env, this).font;
+    if font != nil {
+        return font;
+    }
+    let size: CGFloat = 17.0;
+    let font: id = msg_class![env; UIFont systemFontOfSize:size];
+    properties(env, this).font = retain(env, font);
+    font
+}
+- (())setFont:(id)font { // UIFont*
+    retain(env, font);
+    set_object(env, this, font, |properties| &mut properties.font);
+}
+
+- (id)textColor {
+    let text_color = properties(env, this).text_color;
+    if text_color != nil {
+        return text_color;
+    }
+    let text_color: id = msg_class![env; UIColor blackColor];
+    properties(env, this).text_color = retain(env, text_color);
+    text_color
+}
+- (())setTextColor:(id)text_color { // UIColor*
+    retain(env, text_color);
+    set_object(env, this, text_color, |properties| &mut properties.text_color);
+}
+
+- (UITextAlignment)textAlignment {
+    properties(env, this).text_alignment
+}
+- (())setTextAlignment:(UITextAlignment)text_alignment {
+    properties(env, this).text_alignment = text_alignment;
+    () = msg![env; this setNeedsDisplay];
+}
+
+- (NSInteger)numberOfLines {
+    properties(env, this).number_of_lines
+}
+- (())setNumberOfLines:(NSInteger)number_of_lines {
+    properties(env, this).number_of_lines = number_of_lines;
+    () = msg![env; this setNeedsDisplay];
+}
+
+- (UILineBreakMode)lineBreakMode {
+    properties(env, this).line_break_mode
+}
+- (())setLineBreakMode:(UILineBreakMode)line_break_mode {
+    properties(env, this).line_break_mode = line_break_mode;
+    () = msg![env; this setNeedsDisplay];
+}
+
+- (CGSize)sizeThatFits:(CGSize)size {
+    let text = properties(env, this).text;
+    if text == nil {
+        return CGSize { width: 0.0, height: 0.0 };
+    }
+    let font: id = msg![env; this font];
+    let &mut UILabelProperties { number_of_lines, line_break_mode, .. } = properties(env, this);
+    let text = to_rust_string(env, text); // TODO: avoid copy
+    if text.is_empty() {
+        return CGSize { width: 0.0, height: 0.0 };
+    }
+    // A single-line label's text is never wrapped or truncated.
+    let width = if number_of_lines == 1 { CGFloat::INFINITY } else { size.width };
+    let size = CGSize { width, height: CGFloat::INFINITY };
+    let text = ui_font::fit_text(env, font, &text, size, number_of_lines, line_break_mode);
+    ui_font::size_with_font(env, font, &text, None)
+}
+- (())sizeToFit {
+    let mut frame: CGRect = msg![env; this frame];
+    let size = frame.size;
+    frame.size = msg![env; this sizeThatFits:size];
+    () = msg![env; this setFrame:frame];
+}
+
+- (())drawRect:(CGRect)_rect {
+    let bounds: CGRect = msg![env; this bounds];
+    () = msg![env; this drawTextInRect:bounds];
+}
+- (())drawTextInRect:(CGRect)rect {
+    let text = properties(env, this).text;
+    if text == nil {
+        return;
+    }
+    let text = to_rust_string(env, text); // TODO: avoid copy
+    if text.is_empty() {
+        return;
+    }
+    let font: id = msg![env; this font];
+    let text_color: id = msg![env; this textColor];
+    let &mut UILabelProperties {
+        text_alignment,
+        number_of_lines,
+        line_break_mode,
+        ..
+    } = properties(env, this);
+
+    let text = ui_font::fit_text(env, font, &text, rect.size, number_of_lines, line_break_mode);
+    // The text is centered vertically.
+    let text_size = ui_font::size_with_font(env, font, &text, None);
+    let text_rect = CGRect {
+        origin: CGPoint {
+            x: rect.origin.x,
+            y: rect.origin.y + (rect.size.height - text_size.height) / 2.0,
+        },
+        size: CGSize {
+            width: rect.size.width,
+            height: text_size.height,
+        },
+    };
+
+    let context = UIGraphicsGetCurrentContext(env);
+    let cg_color: CGColorRef = msg![env; text_color CGColor];
+    CGContextSetFillColorWithColor(env, context, cg_color);
+    // The lines already fit, so this won't re-wrap them.
+    ui_font::draw_in_rect(env, font, &text, text_rect, UILineBreakModeWordWrap, text_alignment);
+}
+
+@end
+
+};
@@ -5,7 +5,7 @@
  */
 //! `UIResponder`.
 
-use crate::objc::{id, objc_classes, ClassExports};
+use crate::objc::{id, msg, nil, objc_classes, ClassExports};
 
 pub const CLASSES: ClassExports = objc_classes! {
 
@@ -13,40 +13,73 @@ pub const CLASSES: ClassExports = objc_classes! {
 
 @implementation UIResponder: NSObject
 
-// TODO: real responder implementation etc
+- (id)nextResponder {
+    nil
+}
 
-// These methods print debug logs because they are only likely to get called if
-// a subclass didn't override them, which might mean we delivered the event to
-// the wrong object or it is unhandled.
+// The default implementations of these methods pass the event along the
+// responder chain. They print debug logs if they reach the end of it, since
+// that might mean we delivered the event to the wrong object or it is
+// unhandled.
 
 - (())touchesBegan:(id)touches // NSSet* of UITouch*
          withEvent:(id)event { // UIEvent*
-    log_dbg!(
-        "[{:?} touchesBegan:{:?} withEvent:{:?}] (probably unhandled)",
-        this,
-        touches,
-        event,
-    );
+    let next: id = msg![env; this nextResponder];
+    if next != nil {
+        msg![env; next touchesBegan:touches withEvent:event]
+    } else {
+        log_dbg!(
+            "[{:?} touchesBegan:{:?} withEvent:{:?}] (probably unhandled)",
+            this,
+            touches,
+            event,
+        );
+    }
 }
 
 - (())touchesMoved:(id)touches // NSSet* of UITouch*
          withEvent:(id)event { // UIEvent*
-    log_dbg!(
-        "[{:?} touchesMoved:{:?} withEvent:{:?}] (probably unhandled)",
-        this,
-        touches,
-        event,
-    );
+    let next: id = msg![env; this nextResponder];
+    if next != nil {
+        msg![env; next touchesMoved:touches withEvent:event]
+    } else {
+        log_dbg!(
+            "[{:?} touchesMoved:{:?} withEvent:{:?}] (probably unhandled)",
+            this,
+            touches,
+            event,
+        );
+    }
 }
 
 - (())touchesEnded:(id)touches // NSSet* of UITouch*
          withEvent:(id)event { // UIEvent*
-    log_dbg!(
-        "[{:?} touchesEnded:{:?} withEvent:{:?}] (probably unhandled)",
-        this,
-        touches,
-        event,
-    );
+    let next: id = msg![env; this nextResponder];
+    if next != nil {
+        msg![env; next touchesEnded:touches withEvent:event]
+    } else {
+        log_dbg!(
+            "[{:?} touchesEnded:{:?} withEvent:{:?}] (probably unhandled)",
+            this,
+            touches,
+            event,
+        );
+    }
+}
+
+- (())touchesCancelled:(id)touches // NSSet* of UITouch*
+             withEvent:(id)event { // UIEvent*
+    let next: id = msg![env; this nextResponder];
+    if next != nil {
+        msg![env; next touchesCancelled:touches withEvent:event]
+    } else {
+        log_dbg!(
+            "[{:?} touchesCancelled:{:?} withEvent:{:?}] (probably unhandled)",
+            this,
+            touches,
+            event,
+        );
+    }
 }
 
 @end
@@ -5,27 +5,39 @@
  */
 //! `UITouch`.
 
-use super::ui_view::UIViewHostObject;
-use crate::frameworks::core_graphics::{CGFloat, CGPoint};
-use crate::frameworks::foundation::{NSTimeInterval, NSUInteger};
+use super::ui_event;
+use crate::frameworks::core_graphics::{CGFloat, CGPoint, CGRect};
+use crate::frameworks::foundation::{NSInteger, NSTimeInterval, NSUInteger};
 use crate::mem::MutVoidPtr;
 use crate::objc::{
     autorelease, id, msg, msg_class, nil, objc_classes, release, retain, ClassExports, HostObject,
 };
-use crate::window::Event;
+use crate::window::{Event, FingerId};
 use crate::Environment;
 
 #[derive(Default)]
 pub struct State {
-    current_touch: Option<id>,
+    /// `UITouch*` strong references.
+    touches: ActiveTouches<id>,
 }
 
+pub type UITouchPhase = NSInteger;
+pub const UITouchPhaseBegan: UITouchPhase = 0;
+pub const UITouchPhaseMoved: UITouchPhase = 1;
+pub const UITouchPhaseStationary: UITouchPhase = 2;
+pub const UITouchPhaseEnded: UITouchPhase = 3;
+#[allow(dead_code)]
+pub const UITouchPhaseCancelled: UITouchPhase = 4;
+
 struct UITouchHostObject {
     /// Strong reference to the `UIView`
     view: id,
     /// Relative to screen
     location: CGPoint,
+    /// Relative to screen
+    previous_location: CGPoint,
     timestamp: NSTimeInterval,
+    phase: UITouchPhase,
 }
 impl HostObject for UITouchHostObject {}
 
@@ -39,7 +51,9 @@ pub const CLASSES: ClassExports = objc_classes! {
     let host_object = Box::new(UITouchHostObject {
         view: nil,
         location: CGPoint { x: 0.0, y: 0.0 },
+        previous_location: CGPoint { x: 0.0, y: 0.0 },
         timestamp: 0.0,
+        phase: UITouchPhaseBegan,
     });
     env.objc.alloc_object(this, host_object, &mut env.mem)
 }
@@ -52,12 +66,13 @@ pub const CLASSES: ClassExports = objc_classes! {
 
 - (CGPoint)locationInView:(id)that_view { // UIView*
     let &UITouchHostObject { location, .. } = env.objc.borrow(this);
-    if that_view == nil {
-        location
-    } else {
-        // FIXME, see below
-        resolve_point_in_view(env, that_view, location).unwrap()
-    }
+    resolve_point_in_view(env, that_view, location)
+}
+- (CGPoint)previousLocationInView:(id)that_view { // UIView*
+    let &UITouchHostObject {
+        previous_location, ..
+    } = env.objc.borrow(this);
+    resolve_point_in_view(env, that_view, previous_location)
 }
 
 - (id)view {
@@ -68,6 +83,10 @@ pub const CLASSES: ClassExports = objc_classes! {
     env.objc.borrow::<UITouchHostObject>(this).timestamp
 }
 
+- (UITouchPhase)phase {
+    env.objc.borrow::<UITouchHostObject>(this).phase
+}
+
 - (NSUInteger)tapCount {
     1 // TODO: support double-taps etc
 }
@@ -76,30 +95,24 @@ pub const CLASSES: ClassExports = objc_classes! {
 
 };
 
-fn resolve_point_in_view(env: &mut Environment, view: id, point: CGPoint) -> Option<CGPoint> {
-    let (expected_width, expected_height) = env.window.size_unrotated_unscaled();
-    let expected_width = expected_width as CGFloat;
-    let expected_height = expected_height as CGFloat;
-
-    let &UIViewHostObject { bounds, center, .. } = env.objc.borrow(view);
-
-    if bounds.size.width != expected_width || bounds.size.height != expected_height {
-        return None;
-    }
-    if center.x != expected_width / 2.0 || center.y != expected_height / 2.0 {
-        return None;
+/// Convert a point relative to the screen to be relative to a view (or leave
+/// it alone, for `nil`).
+fn resolve_point_in_view(env: &mut Environment, view: id, point: CGPoint) -> CGPoint {
+    if view == nil {
+        point
+    } else {
+        // TODO: This assumes windows cover the whole screen.
+        msg![env; view convertPoint:point fromView:nil]
     }
-
-    Some(CGPoint {
-        x: point.x - bounds.origin.x,
-        y: point.y - bounds.origin.y,
-    })
 }
 
-fn find_view_for_touch(env: &mut Environment, point: CGPoint) -> Option<id> {
-    // FIXME: This is a massive hack that is only going to work for apps that
-    // have a single view which handles all touch inputs. We should eventually
-    // implement the proper responder chain.
+/// Old heuristic for finding a view to send touches to, for apps that never
+/// make a window visible: it assumes there is a single view with the same size
+/// as the screen that handles all touch inputs.
+fn find_fullscreen_view(env: &mut Environment) -> Option<id> {
+    let (expected_width, expected_height) = env.window.size_unrotated_unscaled();
+    let expected_width = expected_width as CGFloat;
+    let expected_height = expected_height as CGFloat;
 
     let ui_window_class = env.objc.get_known_class("UIWindow", &mut env.mem);
     // TODO: Can we avoid copying this somehow?
@@ -111,67 +124,211 @@ fn find_view_for_touch(env: &mut Environment, point: CGPoint) -> Option<id> {
             continue;
         }
 
-        // FIXME: This is an even bigger hack, it is assuming there is a single
-        // view with the same size as the screen, and can't account for
-        // the view hierarchy's effects on the co-ordinate system!
-        if resolve_point_in_view(env, view, point).is_none() {
-            continue;
+        let bounds: CGRect = msg![env; view bounds];
+        let center: CGPoint = msg![env; view center];
+        if bounds.size.width == expected_width
+            && bounds.size.height == expected_height
+            && center.x == expected_width / 2.0
+            && center.y == expected_height / 2.0
+        {
+            return Some(view);
+        }
+    }
+    None
+}
+
+/// Find the view a new touch should be delivered to, by hit-testing the
+/// visible windows from front to back.
+fn find_view_for_touch(env: &mut Environment, point: CGPoint) -> Option<id> {
+    let windows = env.framework_state.uikit.ui_window.visible_windows.clone();
+    if windows.is_empty() {
+        let view = find_fullscreen_view(env);
+        if view.is_none() {
+            log!(
+                "Warning: touch event ignored, no window is visible and there's no fullscreen view"
+            );
         }
+        return view;
+    }
 
-        log_dbg!("Picked view {:?} for touch event", view);
-        return Some(view);
+    for window in windows.into_iter().rev() {
+        let point: CGPoint = msg![env; window convertPoint:point fromView:nil];
+        let view: id = msg![env; window hitTest:point withEvent:nil];
+        if view != nil {
+            log_dbg!("Picked view {:?} for touch event", view);
+            return Some(view);
+        }
     }
 
-    log!("Warning: touch event ignored, can't find appropriate view (FIXME)");
+    log!(
+        "Warning: touch event at {:?} ignored, no view accepted it",
+        point
+    );
     None
 }
 
-/// [super::handle_events] will forward touch events to this function.
-pub fn handle_event(env: &mut Environment, event: Event) {
-    match event {
-        Event::TouchDown(coords) => {
-            if env.framework_state.uikit.ui_touch.current_touch.is_some() {
+/// The touches currently in progress, each belonging to a finger, in the order
+/// they began. Generic so it can be tested without creating objects.
+struct ActiveTouches<T> {
+    touches: Vec<(FingerId, T)>,
+}
+impl<T> Default for ActiveTouches<T> {
+    fn default() -> Self {
+        ActiveTouches {
+            touches: Vec::new(),
+        }
+    }
+}
+impl<T: Copy> ActiveTouches<T> {
+    /// Work out what phase of a touch an event for a finger corresponds to,
+    /// taking into account whether that finger already has a touch in
+    /// progress. Returns [None] if the event should be ignored.
+    fn phase_for(&self, finger: FingerId, phase: UITouchPhase) -> Option<UITouchPhase> {
+        let in_progress = self.get(finger).is_some();
+        match phase {
+            UITouchPhaseBegan if in_progress => {
                 log!("Warning: New touch initiated but current touch did not end yet, treating as movement.");
-                return handle_event(env, Event::TouchMove(coords));
+                Some(UITouchPhaseMoved)
             }
+            UITouchPhaseBegan => Some(UITouchPhaseBegan),
+            _ if in_progress => Some(phase),
+            _ => None,
+        }
+    }
 
-            log_dbg!("Touch down: {:?}", coords);
+    fn get(&self, finger: FingerId) -> Option<T> {
+        self.touches
+            .iter()
+            .find(|&&(f, _)| f == finger)
+            .map(|&(_, touch)| touch)
+    }
+
+    fn begin(&mut self, finger: FingerId, touch: T) {
+        assert!(self.get(finger).is_none());
+        self.touches.push((finger, touch));
+    }
+
+    fn end(&mut self, finger: FingerId) -> T {
+        let idx = self.touches.iter().position(|&(f, _)| f == finger).unwrap();
+        self.touches.remove(idx).1
+    }
 
-            let location = CGPoint {
-                x: coords.0,
-                y: coords.1,
-            };
+    fn iter(&self) -> impl Iterator<Item = T> + '_ {
+        self.touches.iter().map(|&(_, touch)| touch)
+    }
+}
 
-            let Some(view) = find_view_for_touch(env, location) else {
-                return;
-            };
+/// Check whether a view can receive a new touch, considering its
+/// `multipleTouchEnabled` property.
+fn accepts_new_touch(env: &mut Environment, view: id) -> bool {
+    if msg![env; view isMultipleTouchEnabled] {
+        return true;
+    }
+    let touches: Vec<id> = env.framework_state.uikit.ui_touch.touches.iter().collect();
+    touches
+        .into_iter()
+        .all(|touch| env.objc.borrow::<UITouchHostObject>(touch).view != view)
+}
 
-            // UIKit creates and drains autorelease pools when handling events.
-            let pool: id = msg_class![env; NSAutoreleasePool new];
+/// [super::handle_events] will forward touch events to this function.
+pub fn handle_event(env: &mut Environment, event: Event) {
+    log_dbg!("Touch event: {:?}", event);
 
-            // Note: if the emulator is heavily lagging, this timestamp is going
-            // to be far off from the truth, since it should represent the
-            // time when the event actually happened, not the time when the
-            // event was dispatched. Maybe we'll need to fix this eventually.
-            let timestamp: NSTimeInterval = msg_class![env; NSProcessInfo systemUptime];
+    let (finger, coords, phase) = match event {
+        Event::TouchDown(finger, coords) => (finger, coords, UITouchPhaseBegan),
+        Event::TouchMove(finger, coords) => (finger, coords, UITouchPhaseMoved),
+        Event::TouchUp(finger, coords) => (finger, coords, UITouchPhaseEnded),
+        _ => unreachable!(),
+    };
+
+    let active_touches = &env.framework_state.uikit.ui_touch.touches;
+    let Some(phase) = active_touches.phase_for(finger, phase) else {
+        log_dbg!("No touch in progress for {:?}, ignoring event.", finger);
+        return;
+    };
+
+    let location = CGPoint {
+        x: coords.0,
+        y: coords.1,
+    };
+
+    // Note: if the emulator is heavily lagging, this timestamp is going
+    // to be far off from the truth, since it should represent the
+    // time when the event actually happened, not the time when the
+    // event was dispatched. Maybe we'll need to fix this eventually.
+    let timestamp: NSTimeInterval = msg_class![env; NSProcessInfo systemUptime];
+
+    // UIKit creates and drains autorelease pools when handling events.
+    let pool: id = msg_class![env; NSAutoreleasePool new];
+
+    let touch = if phase == UITouchPhaseBegan {
+        let Some(view) = find_view_for_touch(env, location) else {
+            release(env, pool);
+            return;
+        };
+        if !accepts_new_touch(env, view) {
+            log_dbg!(
+                "{:?} already has a touch and isn't multi-touch enabled, ignoring new touch.",
+                view
+            );
+            release(env, pool);
+            return;
+        }
 
-            let new_touch: id = msg_class![env; UITouch alloc];
-            retain(env, view);
-            *env.objc.borrow_mut(new_touch) = UITouchHostObject {
-                view,
-                location,
-                timestamp,
-            };
-            autorelease(env, new_touch);
+        let new_touch: id = msg_class![env; UITouch alloc];
+        retain(env, view);
+        *env.objc.borrow_mut(new_touch) = UITouchHostObject {
+            view,
+            location,
+            previous_location: location,
+            timestamp,
+            phase,
+        };
+        // The state owns this reference until the touch ends.
+        env.framework_state
+            .uikit
+            .ui_touch
+            .touches
+            .begin(finger, new_touch);
+        new_touch
+    } else {
+        let touch = env
+            .framework_state
+            .uikit
+            .ui_touch
+            .touches
+            .get(finger)
+            .unwrap();
+        let host_object = env.objc.borrow_mut::<UITouchHostObject>(touch);
+        host_object.previous_location = host_object.location;
+        host_object.location = location;
+        host_object.timestamp = timestamp;
+        host_object.phase = phase;
+        touch
+    };
+
+    // Any other touches in progress haven't moved since their last event.
+    let all_touches: Vec<id> = env.framework_state.uikit.ui_touch.touches.iter().collect();
+    for &other_touch in &all_touches {
+        if other_touch != touch {
+            env.objc.borrow_mut::<UITouchHostObject>(other_touch).phase = UITouchPhaseStationary;
+        }
+        retain(env, other_touch);
+    }
+    let event = ui_event::new_touch_event(env, all_touches, timestamp);
+    autorelease(env, event);
 
-            env.framework_state.uikit.ui_touch.current_touch = Some(new_touch);
-            retain(env, new_touch);
+    let touches: id = msg_class![env; NSSet setWithObject:touch];
 
-            let touches: id = msg_class![env; NSSet setWithObject:new_touch];
-            // TODO: populate event object (not all apps care about it)
-            let event: id = msg_class![env; UIEvent new];
-            autorelease(env, event);
+    if phase == UITouchPhaseEnded {
+        env.framework_state.uikit.ui_touch.touches.end(finger);
+        // The set and event now own this touch.
+        release(env, touch);
+    }
 
+    let view = env.objc.borrow::<UITouchHostObject>(touch).view;
+    match phase {
+        UITouchPhaseBegan => {
             log_dbg!(
                 "Sending [{:?} touchesBegan:{:?} withEvent:{:?}]",
                 view,
@@ -179,36 +336,8 @@ pub fn handle_event(env: &mut Environment, event: Event) {
                 event
             );
             let _: () = msg![env; view touchesBegan:touches withEvent:event];
-
-            release(env, pool);
         }
-        Event::TouchMove(coords) => {
-            let Some(touch) = env.framework_state.uikit.ui_touch.current_touch else {
-                log!("Warning: Touch move event received but no current touch, ignoring.");
-                return;
-            };
-
-            log_dbg!("Touch move: {:?}", coords);
-
-            let location = CGPoint {
-                x: coords.0,
-                y: coords.1,
-            };
-
-            let timestamp: NSTimeInterval = msg_class![env; NSProcessInfo systemUptime];
-
-            let view = env.objc.borrow::<UITouchHostObject>(touch).view;
-            let host_object = env.objc.borrow_mut::<UITouchHostObject>(touch);
-            host_object.location = location;
-            host_object.timestamp = timestamp;
-
-            let pool: id = msg_class![env; NSAutoreleasePool new];
-
-            let touches: id = msg_class![env; NSSet setWithObject:touch];
-            // TODO: populate event object (not all apps care about it)
-            let event: id = msg_class![env; UIEvent new];
-            autorelease(env, event);
-
+        UITouchPhaseMoved => {
             log_dbg!(
                 "Sending [{:?} touchesMoved:{:?} withEvent:{:?}]",
                 view,
@@ -216,39 +345,8 @@ pub fn handle_event(env: &mut Environment, event: Event) {
                 event
             );
             let _: () = msg![env; view touchesMoved:touches withEvent:event];
-
-            release(env, pool);
         }
-        Event::TouchUp(coords) => {
-            let Some(touch) = env.framework_state.uikit.ui_touch.current_touch else {
-                log!("Warning: Touch up event received but no current touch, ignoring.");
-                return;
-            };
-
-            log_dbg!("Touch up: {:?}", coords);
-
-            let location = CGPoint {
-                x: coords.0,
-                y: coords.1,
-            };
-
-            let timestamp: NSTimeInterval = msg_class![env; NSProcessInfo systemUptime];
-
-            let view = env.objc.borrow::<UITouchHostObject>(touch).view;
-            let host_object = env.objc.borrow_mut::<UITouchHostObject>(touch);
-            host_object.location = location;
-            host_object.timestamp = timestamp;
-
-            let pool: id = msg_class![env; NSAutoreleasePool new];
-
-            let touches: id = msg_class![env; NSSet setWithObject:touch];
-            // TODO: populate event object (not all apps care about it)
-            let event: id = msg_class![env; UIEvent new];
-            autorelease(env, event);
-
-            env.framework_state.uikit.ui_touch.current_touch = None;
-            release(env, touch); // only owner now should be the NSSet
-
+        UITouchPhaseEnded => {
             log_dbg!(
                 "Sending [{:?} touchesEnded:{:?} withEvent:{:?}]",
                 view,
@@ -256,9 +354,71 @@ pub fn handle_event(env: &mut Environment, event: Event) {
                 event
             );
             let _: () = msg![env; view touchesEnded:touches withEvent:event];
-
-            release(env, pool);
         }
         _ => unreachable!(),
     }
+
+    release(env, pool);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn touch_sequence() {
+        // The touches are represented by the view they began in, which is
+        // where every event for that touch should be delivered.
+        let mut touches = ActiveTouches::<char>::default();
+        let finger = FingerId::Finger {
+            touch_id: 1,
+            finger_id: 7,
+        };
+
+        // The mouse touches view a.
+        assert_eq!(
+            touches.phase_for(FingerId::Mouse, UITouchPhaseBegan),
+            Some(UITouchPhaseBegan)
+        );
+        touches.begin(FingerId::Mouse, 'a');
+
+        // A finger touches view b while the first touch is still in progress.
+        assert_eq!(
+            touches.phase_for(finger, UITouchPhaseBegan),
+            Some(UITouchPhaseBegan)
+        );
+        touches.begin(finger, 'b');
+        assert_eq!(touches.iter().collect::<String>(), "ab");
+
+        // The mouse moves over view b, but its touch still belongs to a.
+        assert_eq!(
+            touches.phase_for(FingerId::Mouse, UITouchPhaseMoved),
+            Some(UITouchPhaseMoved)
+        );
+        assert_eq!(touches.get(FingerId::Mouse), Some('a'));
+
+        // The finger is lifted.
+        assert_eq!(
+            touches.phase_for(finger, UITouchPhaseEnded),
+            Some(UITouchPhaseEnded)
+        );
+        assert_eq!(touches.end(finger), 'b');
+
+        // Stray events for the lifted finger are ignored.
+        assert_eq!(touches.phase_for(finger, UITouchPhaseMoved), None);
+        assert_eq!(touches.phase_for(finger, UITouchPhaseEnded), None);
+
+        // A repeated touch down is treated as movement of the existing touch.
+        assert_eq!(
+            touches.phase_for(FingerId::Mouse, UITouchPhaseBegan),
+            Some(UITouchPhaseMoved)
+        );
+
+        assert_eq!(
+            touches.phase_for(FingerId::Mouse, UITouchPhaseEnded),
+            Some(UITouchPhaseEnded)
+        );
+        assert_eq!(touches.end(FingerId::Mouse), 'a');
+        assert_eq!(touches.iter().count(), 0);
+    }
 }
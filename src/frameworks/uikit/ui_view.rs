@@ -5,10 +5,22 @@
  */
 //! `UIView`.
 
-use crate::frameworks::core_graphics::{CGPoint, CGRect, CGSize};
+use super::ui_graphics::{UIGraphicsPopContext, UIGraphicsPushContext};
+use super::ui_label::UILabelProperties;
+use crate::frameworks::core_animation::ca_layer::{
+    kCAGravityBottom, kCAGravityBottomLeft, kCAGravityBottomRight, kCAGravityCenter,
+    kCAGravityLeft, kCAGravityResize, kCAGravityResizeAspect, kCAGravityResizeAspectFill,
+    kCAGravityRight, kCAGravityTop, kCAGravityTopLeft, kCAGravityTopRight,
+};
+use crate::frameworks::core_graphics::cg_context::CGContextRef;
+use crate::frameworks::core_graphics::{CGFloat, CGPoint, CGRect, CGSize};
 use crate::frameworks::foundation::ns_string::{get_static_str, to_rust_string};
+use crate::frameworks::foundation::{ns_array, NSInteger};
 use crate::mem::MutVoidPtr;
-use crate::objc::{id, msg, objc_classes, release, Class, ClassExports, HostObject};
+use crate::objc::{
+    autorelease, id, msg, nil, objc_classes, release, retain, Class, ClassExports, HostObject, ObjC,
+};
+use crate::Environment;
 
 #[derive(Default)]
 pub struct State {
@@ -16,13 +28,37 @@ pub struct State {
 }
 
 pub(super) struct UIViewHostObject {
-    pub(super) bounds: CGRect,
-    pub(super) center: CGPoint,
-    /// CALayer or subclass.
+    /// CALayer or subclass. The view's geometry is stored by the layer.
     layer: id,
+    /// Strong references, in back-to-front order.
+    pub(super) subviews: Vec<id>,
+    /// Possibly nil. This is a weak reference.
+    pub(super) superview: id,
+    content_mode: UIViewContentMode,
+    user_interaction_enabled: bool,
+    multiple_touch_enabled: bool,
+    /// For UIImageView only. `UIImage*`, possibly nil. Strong reference.
+    pub(super) image: id,
+    /// For UILabel only.
+    pub(super) label: UILabelProperties,
 }
 impl HostObject for UIViewHostObject {}
 
+pub type UIViewContentMode = NSInteger;
+pub const UIViewContentModeScaleToFill: UIViewContentMode = 0;
+pub const UIViewContentModeScaleAspectFit: UIViewContentMode = 1;
+pub const UIViewContentModeScaleAspectFill: UIViewContentMode = 2;
+pub const UIViewContentModeRedraw: UIViewContentMode = 3;
+pub const UIViewContentModeCenter: UIViewContentMode = 4;
+pub const UIViewContentModeTop: UIViewContentMode = 5;
+pub const UIViewContentModeBottom: UIViewContentMode = 6;
+pub const UIViewContentModeLeft: UIViewContentMode = 7;
+pub const UIViewContentModeRight: UIViewContentMode = 8;
+pub const UIViewContentModeTopLeft: UIViewContentMode = 9;
+pub const UIViewContentModeTopRight: UIViewContentMode = 10;
+pub const UIViewContentModeBottomLeft: UIViewContentMode = 11;
+pub const UIViewContentModeBottomRight: UIViewContentMode = 12;
+
 fn parse_tuple(string: &str) -> Option<(f32, f32)> {
     let (a, b) = string.split_once(", ")?;
     Some((a.parse().ok()?, b.parse().ok()?))
@@ -52,13 +88,19 @@ pub const CLASSES: ClassExports = objc_classes! {
     let layer_class: Class = msg![env; this layerClass];
     let layer: id = msg![env; layer_class layer];
 
+    // Image views are the only kind of view that ignore touches by default.
+    let image_view_class = env.objc.get_known_class("UIImageView", &mut env.mem);
+    let user_interaction_enabled = !env.objc.class_is_subclass_of(this, image_view_class);
+
     let host_object = Box::new(UIViewHostObject {
-        bounds: CGRect {
-            origin: CGPoint { x: 0.0, y: 0.0 },
-            size: CGSize { width: 0.0, height: 0.0 }
-        },
-        center: CGPoint { x: 0.0, y: 0.0 },
         layer,
+        subviews: Vec::new(),
+        superview: nil,
+        content_mode: UIViewContentModeScaleToFill,
+        user_interaction_enabled,
+        multiple_touch_enabled: false,
+        image: nil,
+        label: Default::default(),
     });
     env.objc.alloc_object(this, host_object, &mut env.mem)
 }
@@ -67,7 +109,20 @@ pub const CLASSES: ClassExports = objc_classes! {
     env.objc.get_known_class("CALayer", &mut env.mem)
 }
 
-// TODO: initWithFrame:, accessors, etc
+- (id)init {
+    let frame = CGRect {
+        origin: CGPoint { x: 0.0, y: 0.0 },
+        size: CGSize { width: 0.0, height: 0.0 },
+    };
+    msg![env; this initWithFrame:frame]
+}
+
+- (id)initWithFrame:(CGRect)frame {
+    log_dbg!("[(UIView*){:?} initWithFrame:{:?}]", this, frame);
+
+    () = msg![env; this setFrame:frame];
+    init_common(env, this)
+}
 
 // NSCoding implementation
 - (id)initWithCoder:(id)coder {
@@ -84,9 +139,8 @@ pub const CLASSES: ClassExports = objc_classes! {
     let value = msg![env; coder decodeObjectForKey:key_ns_string];
     let center = parse_point(&to_rust_string(env, value)).unwrap();
 
-    let host_object: &mut UIViewHostObject = env.objc.borrow_mut(this);
-    host_object.bounds = bounds;
-    host_object.center = center;
+    () = msg![env; this setBounds:bounds];
+    () = msg![env; this setCenter:center];
 
     log_dbg!(
         "[(UIView*){:?} initWithCoder:{:?}] => bounds {:?}, center {:?}",
@@ -96,21 +150,26 @@ pub const CLASSES: ClassExports = objc_classes! {
         center
     );
 
-    let layer = host_object.layer;
-    () = msg![env; layer setDelegate:this];
-
-    env.framework_state.uikit.ui_view.views.push(this);
-
-    this
+    init_common(env, this)
 }
 
 - (())dealloc {
-    let &mut UIViewHostObject { layer, .. } = env.objc.borrow_mut(this);
+    let host_object = env.objc.borrow_mut::<UIViewHostObject>(this);
+    let subviews = std::mem::take(&mut host_object.subviews);
+    let label = std::mem::take(&mut host_object.label);
+    let &mut UIViewHostObject { layer, image, .. } = host_object;
+    for subview in subviews {
+        env.objc.borrow_mut::<UIViewHostObject>(subview).superview = nil;
+        release(env, subview);
+    }
+    release(env, image);
+    label.release(env);
     release(env, layer);
 
     env.framework_state.uikit.ui_view.views.swap_remove(
         env.framework_state.uikit.ui_view.views.iter().position(|&v| v == this).unwrap()
     );
+    env.framework_state.uikit.ui_window.visible_windows.retain(|&w| w != this);
 
     // FIXME: this should do a super-call instead
     env.objc.dealloc_object(this, &mut env.mem);
@@ -120,6 +179,368 @@ pub const CLASSES: ClassExports = objc_classes! {
     env.objc.borrow_mut::<UIViewHostObject>(this).layer
 }
 
+- (CGRect)bounds {
+    let layer = env.objc.borrow::<UIViewHostObject>(this).layer;
+    msg![env; layer bounds]
+}
+- (())setBounds:(CGRect)bounds {
+    let layer = env.objc.borrow::<UIViewHostObject>(this).layer;
+    msg![env; layer setBounds:bounds]
+}
+- (CGPoint)center {
+    let layer = env.objc.borrow::<UIViewHostObject>(this).layer;
+    msg![env; layer position]
+}
+- (())setCenter:(CGPoint)center {
+    let layer = env.objc.borrow::<UIViewHostObject>(this).layer;
+    msg![env; layer setPosition:center]
+}
+- (CGRect)frame {
+    let layer = env.objc.borrow::<UIViewHostObject>(this).layer;
+    msg![env; layer frame]
+}
+- (())setFrame:(CGRect)frame {
+    let layer = env.objc.borrow::<UIViewHostObject>(this).layer;
+    msg![env; layer setFrame:frame]
+}
+
+- (id)superview {
+    env.objc.borrow::<UIViewHostObject>(this).superview
+}
+- (id)subviews {
+    let subviews = env.objc.borrow::<UIViewHostObject>(this).subviews.clone();
+    for &subview in &subviews {
+        retain(env, subview);
+    }
+    let array = ns_array::from_vec(env, subviews);
+    autorelease(env, array)
+}
+
+- (())addSubview:(id)view {
+    log_dbg!("[(UIView*){:?} addSubview:{:?}]", this, view);
+    insert_subview(env, this, view, SubviewPosition::Front);
+}
+- (())insertSubview:(id)view atIndex:(NSInteger)index {
+    // Out-of-range indices are clamped.
+    let index = index.try_into().unwrap_or(0);
+    insert_subview(env, this, view, SubviewPosition::Index(index));
+}
+- (())insertSubview:(id)view aboveSubview:(id)sibling {
+    insert_subview(env, this, view, SubviewPosition::Above(sibling));
+}
+- (())insertSubview:(id)view belowSubview:(id)sibling {
+    insert_subview(env, this, view, SubviewPosition::Below(sibling));
+}
+- (())bringSubviewToFront:(id)view {
+    // This does nothing if the view isn't a subview.
+    if view == nil || env.objc.borrow::<UIViewHostObject>(view).superview != this {
+        return;
+    }
+    insert_subview(env, this, view, SubviewPosition::Front);
+}
+- (())sendSubviewToBack:(id)view {
+    if view == nil || env.objc.borrow::<UIViewHostObject>(view).superview != this {
+        return;
+    }
+    insert_subview(env, this, view, SubviewPosition::Index(0));
+}
+
+- (())removeFromSuperview {
+    let superview = env.objc.borrow::<UIViewHostObject>(this).superview;
+    if superview == nil {
+        return;
+    }
+    env.objc
+        .borrow_mut::<UIViewHostObject>(superview)
+        .subviews
+        .retain(|&v| v != this);
+    let host_object = env.objc.borrow_mut::<UIViewHostObject>(this);
+    host_object.superview = nil;
+    let layer = host_object.layer;
+    () = msg![env; layer removeFromSuperlayer];
+    release(env, this);
+}
+
+- (bool)isDescendantOfView:(id)view {
+    let mut current = this;
+    while current != nil {
+        if current == view {
+            return true;
+        }
+        current = env.objc.borrow::<UIViewHostObject>(current).superview;
+    }
+    false
+}
+
+- (CGPoint)convertPoint:(CGPoint)point toView:(id)view {
+    let layer = env.objc.borrow::<UIViewHostObject>(this).layer;
+    let other_layer = view_layer(&env.objc, view);
+    msg![env; layer convertPoint:point toLayer:other_layer]
+}
+- (CGPoint)convertPoint:(CGPoint)point fromView:(id)view {
+    let layer = env.objc.borrow::<UIViewHostObject>(this).layer;
+    let other_layer = view_layer(&env.objc, view);
+    msg![env; layer convertPoint:point fromLayer:other_layer]
+}
+- (CGRect)convertRect:(CGRect)rect toView:(id)view {
+    let layer = env.objc.borrow::<UIViewHostObject>(this).layer;
+    let other_layer = view_layer(&env.objc, view);
+    msg![env; layer convertRect:rect toLayer:other_layer]
+}
+- (CGRect)convertRect:(CGRect)rect fromView:(id)view {
+    let layer = env.objc.borrow::<UIViewHostObject>(this).layer;
+    let other_layer = view_layer(&env.objc, view);
+    msg![env; layer convertRect:rect fromLayer:other_layer]
+}
+
+- (bool)isHidden {
+    let layer = env.objc.borrow::<UIViewHostObject>(this).layer;
+    msg![env; layer isHidden]
+}
+- (())setHidden:(bool)hidden {
+    let layer = env.objc.borrow::<UIViewHostObject>(this).layer;
+    msg![env; layer setHidden:hidden]
+}
+- (CGFloat)alpha {
+    let layer = env.objc.borrow::<UIViewHostObject>(this).layer;
+    msg![env; layer opacity]
+}
+- (())setAlpha:(CGFloat)alpha {
+    let layer = env.objc.borrow::<UIViewHostObject>(this).layer;
+    msg![env; layer setOpacity:alpha]
+}
+
+- (bool)isUserInteractionEnabled {
+    env.objc.borrow::<UIViewHostObject>(this).user_interaction_enabled
+}
+- (())setUserInteractionEnabled:(bool)enabled {
+    env.objc.borrow_mut::<UIViewHostObject>(this).user_interaction_enabled = enabled;
+}
+
+- (bool)isMultipleTouchEnabled {
+    env.objc.borrow::<UIViewHostObject>(this).multiple_touch_enabled
+}
+- (())setMultipleTouchEnabled:(bool)enabled {
+    env.objc.borrow_mut::<UIViewHostObject>(this).multiple_touch_enabled = enabled;
+}
+
+// UIResponder implementation
+- (id)nextResponder {
+    // TODO: view controllers
+    env.objc.borrow::<UIViewHostObject>(this).superview
+}
+
+- (bool)pointInside:(CGPoint)point withEvent:(id)_event { // UIEvent* (possibly nil)
+    let layer = env.objc.borrow::<UIViewHostObject>(this).layer;
+    msg![env; layer containsPoint:point]
+}
+- (id)hitTest:(CGPoint)point withEvent:(id)event { // UIEvent* (possibly nil)
+    let user_interaction_enabled = env
+        .objc
+        .borrow::<UIViewHostObject>(this)
+        .user_interaction_enabled;
+    let hidden: bool = msg![env; this isHidden];
+    let alpha: CGFloat = msg![env; this alpha];
+    if !user_interaction_enabled || hidden || alpha < 0.01 {
+        return nil;
+    }
+    if !msg![env; this pointInside:point withEvent:event] {
+        return nil;
+    }
+    // Subviews are in back-to-front order, so the frontmost gets to go first.
+    let subviews = env.objc.borrow::<UIViewHostObject>(this).subviews.clone();
+    for subview in subviews.into_iter().rev() {
+        let point: CGPoint = msg![env; this convertPoint:point toView:subview];
+        let hit: id = msg![env; subview hitTest:point withEvent:event];
+        if hit != nil {
+            return hit;
+        }
+    }
+    this
+}
+
+- (())setNeedsDisplay {
+    // Views that don't draw their own content don't need to be redrawn, and
+    // redrawing them would replace their layer's existing contents.
+    if overrides_draw_rect(env, this) {
+        let layer = env.objc.borrow::<UIViewHostObject>(this).layer;
+        () = msg![env; layer setNeedsDisplay];
+    }
+}
+- (())setNeedsDisplayInRect:(CGRect)rect {
+    if overrides_draw_rect(env, this) {
+        let layer = env.objc.borrow::<UIViewHostObject>(this).layer;
+        () = msg![env; layer setNeedsDisplayInRect:rect];
+    }
+}
+- (())drawRect:(CGRect)_rect {
+    // Subclasses can override this.
+}
+// CALayerDelegate implementation
+- (())drawLayer:(id)_layer inContext:(CGContextRef)context {
+    let bounds: CGRect = msg![env; this bounds];
+    UIGraphicsPushContext(env, context);
+    () = msg![env; this drawRect:bounds];
+    UIGraphicsPopContext(env);
+}
+
+- (())setNeedsLayout {
+    let layer = env.objc.borrow::<UIViewHostObject>(this).layer;
+    msg![env; layer setNeedsLayout]
+}
+- (())layoutIfNeeded {
+    let layer = env.objc.borrow::<UIViewHostObject>(this).layer;
+    msg![env; layer layoutIfNeeded]
+}
+- (())layoutSubviews {
+    // Subclasses can override this.
+}
+// CALayerDelegate implementation
+- (())layoutSublayersOfLayer:(id)_layer {
+    () = msg![env; this layoutSubviews];
+}
+
+- (UIViewContentMode)contentMode {
+    env.objc.borrow::<UIViewHostObject>(this).content_mode
+}
+- (())setContentMode:(UIViewContentMode)content_mode {
+    // Core Animation's gravity names assume the y axis points up, so top and
+    // bottom are swapped.
+    let gravity = match content_mode {
+        UIViewContentModeScaleToFill | UIViewContentModeRedraw => kCAGravityResize,
+        UIViewContentModeScaleAspectFit => kCAGravityResizeAspect,
+        UIViewContentModeScaleAspectFill => kCAGravityResizeAspectFill,
+        UIViewContentModeCenter => kCAGravityCenter,
+        UIViewContentModeTop => kCAGravityBottom,
+        UIViewContentModeBottom => kCAGravityTop,
+        UIViewContentModeLeft => kCAGravityLeft,
+        UIViewContentModeRight => kCAGravityRight,
+        UIViewContentModeTopLeft => kCAGravityBottomLeft,
+        UIViewContentModeTopRight => kCAGravityBottomRight,
+        UIViewContentModeBottomLeft => kCAGravityTopLeft,
+        UIViewContentModeBottomRight => kCAGravityTopRight,
+        _ => unimplemented!("UIViewContentMode {}", content_mode),
+    };
+    let host_object = env.objc.borrow_mut::<UIViewHostObject>(this);
+    host_object.content_mode = content_mode;
+    let layer = host_object.layer;
+    let gravity = get_static_str(env, gravity);
+    () = msg![env; layer setContentsGravity:gravity];
+}
+
 @end
 
 };
+
+/// Shared part of the `init` methods.
+fn init_common(env: &mut Environment, this: id) -> id {
+    let layer = env.objc.borrow::<UIViewHostObject>(this).layer;
+    () = msg![env; layer setDelegate:this];
+
+    env.framework_state.uikit.ui_view.views.push(this);
+
+    // Views that draw their own content need to be drawn at least once.
+    if overrides_draw_rect(env, this) {
+        () = msg![env; layer setNeedsDisplay];
+    }
+
+    this
+}
+
+fn overrides_draw_rect(env: &mut Environment, view: id) -> bool {
+    let ui_view_class = env.objc.get_known_class("UIView", &mut env.mem);
+    let class = ObjC::read_isa(view, &env.mem);
+    let sel = env.objc.lookup_selector("drawRect:").unwrap();
+    env.objc.class_overrides_method(class, sel, ui_view_class)
+}
+
+/// Get a view's layer, or `nil` for a `nil` view.
+fn view_layer(objc: &ObjC, view: id) -> id {
+    if view == nil {
+        nil
+    } else {
+        objc.borrow::<UIViewHostObject>(view).layer
+    }
+}
+
+/// Where to put a subview among its siblings.
+#[derive(Debug, Copy, Clone)]
+enum SubviewPosition<T> {
+    /// In front of all the other subviews.
+    Front,
+    Index(usize),
+    Above(T),
+    Below(T),
+}
+
+/// Insert an item into a back-to-front list of subviews, returning the index
+/// it ended up at. Out-of-range indices are clamped.
+fn insert_into_list<T: Copy + PartialEq>(
+    list: &mut Vec<T>,
+    item: T,
+    position: SubviewPosition<T>,
+) -> usize {
+    let sibling_index = |sibling| list.iter().position(|&i| i == sibling).unwrap();
+    let index = match position {
+        SubviewPosition::Front => list.len(),
+        SubviewPosition::Index(index) => index.min(list.len()),
+        SubviewPosition::Above(sibling) => sibling_index(sibling) + 1,
+        SubviewPosition::Below(sibling) => sibling_index(sibling),
+    };
+    list.insert(index, item);
+    index
+}
+
+/// Shared implementation of `addSubview:` and the other methods that add or
+/// move subviews.
+fn insert_subview(env: &mut Environment, this: id, view: id, position: SubviewPosition<id>) {
+    if view == nil {
+        log!("Warning: attempt to add nil subview to {:?} ignored", this);
+        return;
+    }
+
+    // The view might already be a subview of this or another view. Retain it
+    // first so removing it doesn't free it.
+    retain(env, view);
+    () = msg![env; view removeFromSuperview];
+
+    let subviews = &mut env.objc.borrow_mut::<UIViewHostObject>(this).subviews;
+    let index = insert_into_list(subviews, view, position);
+    let next_sibling = subviews.get(index + 1).copied();
+    env.objc.borrow_mut::<UIViewHostObject>(view).superview = this;
+
+    // Keep the layers in the same order. The layer may have sublayers that
+    // don't belong to a view, so this can't just use the same index.
+    let layer = env.objc.borrow::<UIViewHostObject>(this).layer;
+    let sublayer = env.objc.borrow::<UIViewHostObject>(view).layer;
+    if let Some(next_sibling) = next_sibling {
+        let sibling_layer = env.objc.borrow::<UIViewHostObject>(next_sibling).layer;
+        () = msg![env; layer insertSublayer:sublayer below:sibling_layer];
+    } else {
+        () = msg![env; layer addSublayer:sublayer];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn insert(list: &str, item: char, position: SubviewPosition<char>) -> String {
+        let mut list: Vec<char> = list.chars().collect();
+        insert_into_list(&mut list, item, position);
+        list.into_iter().collect()
+    }
+
+    #[test]
+    fn subview_ordering() {
+        assert_eq!(insert("", 'a', SubviewPosition::Front), "a");
+        assert_eq!(insert("ab", 'c', SubviewPosition::Front), "abc");
+        assert_eq!(insert("abc", 'd', SubviewPosition::Index(0)), "dabc");
+        assert_eq!(insert("abc", 'd', SubviewPosition::Index(2)), "abdc");
+        assert_eq!(insert("abc", 'd', SubviewPosition::Index(10)), "abcd");
+        assert_eq!(insert("abc", 'd', SubviewPosition::Above('a')), "adbc");
+        assert_eq!(insert("abc", 'd', SubviewPosition::Above('c')), "abcd");
+        assert_eq!(insert("abc", 'd', SubviewPosition::Below('a')), "dabc");
+        assert_eq!(insert("abc", 'd', SubviewPosition::Below('c')), "abdc");
+    }
+}
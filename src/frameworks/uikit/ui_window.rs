@@ -5,7 +5,14 @@
  */
 //! `UIWindow`.
 
-use crate::objc::{objc_classes, ClassExports};
+use crate::objc::{id, msg_class, objc_classes, ClassExports};
+
+#[derive(Default)]
+pub struct State {
+    /// Windows that have been made visible, in back-to-front order. These are
+    /// weak references.
+    pub(super) visible_windows: Vec<id>,
+}
 
 pub const CLASSES: ClassExports = objc_classes! {
 
@@ -13,6 +20,21 @@ pub const CLASSES: ClassExports = objc_classes! {
 
 @implementation UIWindow: UIView
 
+- (())makeKeyAndVisible {
+    log_dbg!("[(UIWindow*){:?} makeKeyAndVisible]", this);
+
+    // TODO: track the key window
+    let visible_windows = &mut env.framework_state.uikit.ui_window.visible_windows;
+    if !visible_windows.contains(&this) {
+        visible_windows.push(this);
+    }
+}
+
+// UIResponder implementation
+- (id)nextResponder {
+    msg_class![env; UIApplication sharedApplication]
+}
+
 // TODO
 
 @end
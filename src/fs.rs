@@ -8,9 +8,9 @@
 //! This lets us put files and directories where the guest app expects them to
 //! be, without constraining the layout of the host filesystem.
 //!
-//! Currently the filesystem layout is frozen at the point of creation. Except
-//! for creating new files in existing directories, no nodes can be created,
-//! deleted, renamed or moved.
+//! Currently the filesystem layout is mostly frozen at the point of creation.
+//! Files and directories can only be created or deleted within writeable
+//! directories, and no nodes can be renamed or moved.
 //!
 //! All files in the guest filesystem have a corresponding file in the host
 //! filesystem. Accessing a file requires traversing the guest filesystem's
@@ -276,6 +276,23 @@ fn handle_open_err<T>(open_result: std::io::Result<T>, host_path: &Path) -> T {
     }
 }
 
+/// Like [std::fs::Metadata] but for the guest filesystem, and with only the
+/// information touchHLE currently needs.
+#[derive(Debug)]
+pub struct GuestMetadata {
+    pub is_dir: bool,
+    /// Size in bytes. Always zero for directories.
+    pub size: u64,
+    /// [None] if not available, e.g. for read-only directories.
+    pub modified: Option<std::time::SystemTime>,
+}
+
+/// Get the host path of the sandbox directory for the app with a particular
+/// bundle ID. See [Fs::new].
+pub fn sandbox_host_path(bundle_id: &str) -> PathBuf {
+    Path::new("touchHLE_sandbox").join(bundle_id)
+}
+
 /// The type that owns the guest filesystem and provides accessors for it.
 #[derive(Debug)]
 pub struct Fs {
@@ -285,8 +302,8 @@ pub struct Fs {
 }
 impl Fs {
     /// Construct a filesystem containing a home directory for the app, its
-    /// bundle and documents, and the bundled shared libraries. Returns the new
-    /// filesystem and the guest path of the bundle.
+    /// bundle, documents and preferences, and the bundled shared libraries.
+    /// Returns the new filesystem and the guest path of the bundle.
     ///
     /// The `bundle_dir_name` argument will be used as the name of the bundle
     /// directory in the guest filesystem, and must end in `.app`.
@@ -296,8 +313,8 @@ impl Fs {
     ///
     /// The `bundle_id` argument should be some value that uniquely identifies
     /// the app. This will be used to construct the host path for the app's
-    /// sandbox directory, where documents can be stored. A directory will be
-    /// created at that path if it does not already exist.
+    /// sandbox directory, where documents and preferences can be stored.
+    /// Directories will be created at that path if they do not already exist.
     pub fn new(
         bundle_host_path: &Path,
         bundle_dir_name: String,
@@ -310,15 +327,21 @@ impl Fs {
 
         let bundle_guest_path = home_directory.join(&bundle_dir_name);
 
-        let documents_host_path = Path::new("touchHLE_sandbox")
-            .join(bundle_id)
-            .join("Documents");
+        let sandbox_host_path = sandbox_host_path(bundle_id);
+        let documents_host_path = sandbox_host_path.join("Documents");
         if let Err(e) = std::fs::create_dir_all(&documents_host_path) {
             panic!(
                 "Could not create documents directory for app at {:?}: {:?}",
                 documents_host_path, e
             );
         }
+        let preferences_host_path = sandbox_host_path.join("Library").join("Preferences");
+        if let Err(e) = std::fs::create_dir_all(&preferences_host_path) {
+            panic!(
+                "Could not create preferences directory for app at {:?}: {:?}",
+                preferences_host_path, e
+            );
+        }
 
         // Some Free Software libraries are bundled with touchHLE.
         let dylibs_host_path = Path::new("touchHLE_dylibs");
@@ -360,6 +383,16 @@ impl Fs {
                                         /* writeable: */ true,
                                     ),
                                 ),
+                                (
+                                    "Library".to_string(),
+                                    FsNode::dir().with_child(
+                                        "Preferences",
+                                        FsNode::from_host_dir(
+                                            &preferences_host_path,
+                                            /* writeable: */ true,
+                                        ),
+                                    ),
+                                ),
                             ]),
                             writeable: None,
                         },
@@ -416,11 +449,162 @@ impl Fs {
         Some((parent, final_component.to_string()))
     }
 
+    /// Like [std::path::Path::exists] but for the guest filesystem.
+    pub fn exists(&self, path: &GuestPath) -> bool {
+        self.lookup_node(path).is_some()
+    }
+
     /// Like [std::path::Path::is_file] but for the guest filesystem.
     pub fn is_file(&self, path: &GuestPath) -> bool {
         matches!(self.lookup_node(path), Some(FsNode::File { .. }))
     }
 
+    /// Like [std::path::Path::is_dir] but for the guest filesystem.
+    pub fn is_dir(&self, path: &GuestPath) -> bool {
+        matches!(self.lookup_node(path), Some(FsNode::Directory { .. }))
+    }
+
+    /// Get the names of the children of a directory, sorted alphabetically.
+    pub fn read_dir(&self, path: &GuestPath) -> Result<Vec<String>, ()> {
+        let Some(FsNode::Directory { children, writeable: _ }) = self.lookup_node(path) else {
+            return Err(());
+        };
+        let mut names: Vec<String> = children.keys().cloned().collect();
+        names.sort();
+        Ok(names)
+    }
+
+    /// Like [std::fs::metadata] but for the guest filesystem.
+    pub fn metadata(&self, path: &GuestPath) -> Result<GuestMetadata, ()> {
+        let (host_path, is_dir) = match self.lookup_node(path).ok_or(())? {
+            FsNode::File {
+                host_path,
+                writeable: _,
+            } => (Some(host_path), false),
+            FsNode::Directory {
+                children: _,
+                writeable: host_path,
+            } => (host_path.as_ref(), true),
+        };
+        // Read-only directories have no host directory, and so no metadata.
+        let Some(host_path) = host_path else {
+            return Ok(GuestMetadata {
+                is_dir,
+                size: 0,
+                modified: None,
+            });
+        };
+        let metadata = handle_open_err(std::fs::metadata(host_path), host_path);
+        Ok(GuestMetadata {
+            is_dir,
+            size: if is_dir { 0 } else { metadata.len() },
+            modified: metadata.modified().ok(),
+        })
+    }
+
+    /// Like [std::fs::create_dir] but for the guest filesystem. The parent
+    /// directory must be writeable.
+    pub fn create_dir(&mut self, path: &GuestPath) -> Result<(), ()> {
+        let (parent_node, new_dirname) = self.lookup_parent_node(path).ok_or(())?;
+        let FsNode::Directory {
+            children,
+            writeable: dir_host_path,
+        } = parent_node else {
+            return Err(());
+        };
+
+        if children.contains_key(&new_dirname) {
+            return Err(());
+        }
+
+        let Some(dir_host_path) = dir_host_path else {
+            log!("Warning: attempt to create directory at path {:?}, but parent directory is read-only", path);
+            return Err(());
+        };
+
+        // The guest path has already been resolved, so this is the only way
+        // the new host path could end up outside the parent directory.
+        if new_dirname.chars().any(std::path::is_separator) {
+            log!("Warning: attempt to create directory at path {:?}, but name contains a path separator character", path);
+            return Err(());
+        }
+
+        let host_path = dir_host_path.join(&new_dirname);
+        std::fs::create_dir(&host_path).map_err(|e| {
+            log!(
+                "Warning: couldn't create directory at real path {:?}: {}",
+                host_path,
+                e
+            );
+        })?;
+        log_dbg!(
+            "Created directory at path {:?} (host path: {:?})",
+            path,
+            host_path
+        );
+        children.insert(
+            new_dirname,
+            FsNode::Directory {
+                children: HashMap::new(),
+                writeable: Some(host_path),
+            },
+        );
+        Ok(())
+    }
+
+    /// Like [std::fs::create_dir_all] but for the guest filesystem.
+    pub fn create_dir_all(&mut self, path: &GuestPath) -> Result<(), ()> {
+        let components: Vec<String> = resolve_path(path, Some(&self.current_directory))
+            .into_iter()
+            .map(String::from)
+            .collect();
+        let mut ancestor = String::new();
+        for component in components {
+            ancestor.push('/');
+            ancestor.push_str(&component);
+            let ancestor = GuestPath::new(&ancestor);
+            if !self.is_dir(ancestor) {
+                self.create_dir(ancestor)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove a file, or a directory and all its contents, like
+    /// [std::fs::remove_file] and [std::fs::remove_dir_all]. Both the node and
+    /// its parent directory must be writeable.
+    pub fn remove(&mut self, path: &GuestPath) -> Result<(), ()> {
+        let (parent_node, name) = self.lookup_parent_node(path).ok_or(())?;
+        let FsNode::Directory {
+            children,
+            writeable: Some(_),
+        } = parent_node else {
+            log!("Warning: attempt to remove {:?}, but parent directory is read-only or missing", path);
+            return Err(());
+        };
+
+        let result = match children.get(&name).ok_or(())? {
+            FsNode::File {
+                host_path,
+                writeable: true,
+            } => std::fs::remove_file(host_path),
+            FsNode::Directory {
+                children: _,
+                writeable: Some(host_path),
+            } => std::fs::remove_dir_all(host_path),
+            _ => {
+                log!("Warning: attempt to remove read-only {:?}", path);
+                return Err(());
+            }
+        };
+        if let Err(e) = result {
+            log!("Warning: couldn't remove {:?}: {}", path, e);
+            return Err(());
+        }
+        children.remove(&name);
+        Ok(())
+    }
+
     /// Like [std::fs::read] but for the guest filesystem.
     pub fn read<P: AsRef<GuestPath>>(&self, path: P) -> Result<Vec<u8>, ()> {
         let node = self.lookup_node(path.as_ref()).ok_or(())?;
@@ -540,3 +724,91 @@ impl Fs {
         Ok(file)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Create a filesystem with a read-only `/Bundle.app` directory and a
+    /// writeable `/Documents` directory backed by a new temporary directory,
+    /// which is returned too.
+    fn temp_sandbox(name: &str) -> (Fs, PathBuf) {
+        let host_dir =
+            std::env::temp_dir().join(format!("touchHLE_test_{}_{}", name, std::process::id()));
+        let documents = host_dir.join("Documents");
+        let bundle = host_dir.join("Bundle.app");
+        std::fs::create_dir_all(&documents).unwrap();
+        std::fs::create_dir_all(&bundle).unwrap();
+        std::fs::write(bundle.join("Info.plist"), b"").unwrap();
+
+        let root = FsNode::dir()
+            .with_child("Bundle.app", FsNode::from_host_dir(&bundle, false))
+            .with_child("Documents", FsNode::from_host_dir(&documents, true));
+        let fs = Fs {
+            root,
+            current_directory: GuestPathBuf::from("/Documents".to_string()),
+            home_directory: GuestPathBuf::from("/".to_string()),
+        };
+        (fs, host_dir)
+    }
+
+    #[test]
+    fn directories() {
+        let (mut fs, host_dir) = temp_sandbox("directories");
+        let path = |s: &'static str| -> &'static GuestPath { s.as_ref() };
+
+        assert!(fs.create_dir_all(path("/Documents/Saves/Slot1")).is_ok());
+        assert!(host_dir.join("Documents/Saves/Slot1").is_dir());
+        assert!(fs.is_dir(path("Saves/Slot1")));
+        assert!(fs.create_dir(path("/Documents/Saves")).is_err());
+
+        let mut options = GuestOpenOptions::new();
+        options.write().create();
+        let mut file = fs
+            .open_with_options(path("/Documents/Saves/Slot1/save.dat"), options)
+            .unwrap();
+        std::io::Write::write_all(&mut file, b"12345").unwrap();
+        drop(file);
+
+        assert_eq!(fs.read_dir(path("/Documents/Saves")).unwrap(), ["Slot1"]);
+        assert!(fs.read_dir(path("/Documents/Saves/Slot1/save.dat")).is_err());
+        let metadata = fs.metadata(path("/Documents/Saves/Slot1/save.dat")).unwrap();
+        assert!(!metadata.is_dir && metadata.size == 5);
+        assert!(metadata.modified.is_some());
+        assert!(fs.metadata(path("/Documents/Saves")).unwrap().is_dir);
+
+        assert!(fs.remove(path("/Documents/Saves")).is_ok());
+        assert!(!fs.exists(path("/Documents/Saves/Slot1/save.dat")));
+        assert!(!host_dir.join("Documents/Saves").exists());
+        assert!(fs.read_dir(path("/Documents")).unwrap().is_empty());
+
+        std::fs::remove_dir_all(host_dir).unwrap();
+    }
+
+    #[test]
+    fn sandbox_escapes() {
+        let (mut fs, host_dir) = temp_sandbox("sandbox_escapes");
+        let path = |s: &'static str| -> &'static GuestPath { s.as_ref() };
+
+        // Guest paths can't go above the root, so this is just /Escape, which
+        // is in a read-only directory.
+        assert!(fs.create_dir(path("/Documents/../../../Escape")).is_err());
+        assert!(fs.create_dir_all(path("../../Escape/Deeper")).is_err());
+        assert!(!host_dir.join("Escape").exists());
+        assert!(!host_dir.parent().unwrap().join("Escape").exists());
+
+        // Read-only nodes and the sandbox directories themselves can't be
+        // removed.
+        assert!(fs.remove(path("/Bundle.app/Info.plist")).is_err());
+        assert!(fs.remove(path("/Bundle.app")).is_err());
+        assert!(fs.remove(path("/Documents")).is_err());
+        assert!(fs.remove(path("/")).is_err());
+        assert!(host_dir.join("Bundle.app/Info.plist").is_file());
+        assert!(host_dir.join("Documents").is_dir());
+
+        // Nor can directories be created in them.
+        assert!(fs.create_dir(path("/Bundle.app/New")).is_err());
+
+        std::fs::remove_dir_all(host_dir).unwrap();
+    }
+}
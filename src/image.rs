@@ -3,17 +3,18 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at https://mozilla.org/MPL/2.0/.
  */
-//! Image decoding. Currently only supports PNG.
+//! Image decoding and encoding. Decoding currently supports PNG and JPEG,
+//! encoding only supports PNG.
 //!
 //! Implemented as a wrapper around the C library stb_image, since it supports
 //! "CgBI" PNG files (an Apple proprietary extension used in iPhone OS apps).
 
-use std::ffi::{c_int, c_uchar};
+use std::ffi::c_int;
 
 use touchHLE_stb_image_wrapper::*;
 
 pub struct Image {
-    pixels: *mut c_uchar,
+    pixels: Vec<u8>,
     dimensions: (u32, u32),
 }
 
@@ -44,10 +45,18 @@ impl Image {
         let width: u32 = x.try_into().unwrap();
         let height: u32 = y.try_into().unwrap();
 
-        Ok(Image {
-            pixels,
-            dimensions: (width, height),
-        })
+        let size = width as usize * height as usize * 4;
+        let pixels_vec = unsafe { std::slice::from_raw_parts(pixels, size) }.to_vec();
+        unsafe { stbi_image_free(pixels.cast()) };
+
+        Ok(Self::from_pixels(pixels_vec, (width, height)))
+    }
+
+    /// Create an image from pixel data in the same format as [Self::pixels]
+    /// returns.
+    pub fn from_pixels(pixels: Vec<u8>, dimensions: (u32, u32)) -> Image {
+        assert!(pixels.len() == dimensions.0 as usize * dimensions.1 as usize * 4);
+        Image { pixels, dimensions }
     }
 
     pub fn dimensions(&self) -> (u32, u32) {
@@ -56,17 +65,149 @@ impl Image {
 
     /// Get image data as bytes (8 bits per channel RGBA)
     pub fn pixels(&self) -> &[u8] {
-        unsafe {
-            std::slice::from_raw_parts(
-                self.pixels,
-                self.dimensions.0 as usize * self.dimensions.1 as usize * 4,
-            )
+        &self.pixels
+    }
+
+    /// Shrink the image by an integer factor in each dimension, averaging each
+    /// `factor`×`factor` block of pixels.
+    pub fn downscaled(&self, factor: u32) -> Image {
+        assert!(factor != 0);
+        let (width, height) = self.dimensions;
+        let (new_width, new_height) = (width / factor, height / factor);
+        let mut pixels = Vec::with_capacity(new_width as usize * new_height as usize * 4);
+        for y in 0..new_height {
+            for x in 0..new_width {
+                let mut sums = [0u32; 4];
+                for src_y in (y * factor)..((y + 1) * factor) {
+                    for src_x in (x * factor)..((x + 1) * factor) {
+                        let idx = (src_y as usize * width as usize + src_x as usize) * 4;
+                        for (sum, &channel) in sums.iter_mut().zip(&self.pixels[idx..idx + 4]) {
+                            *sum += channel as u32;
+                        }
+                    }
+                }
+                pixels.extend(sums.map(|sum| (sum / (factor * factor)) as u8));
+            }
         }
+        Image::from_pixels(pixels, (new_width, new_height))
     }
+
+    /// Encode the image as a PNG file, e.g. for frame captures (see
+    /// `--capture-frame=`). The image data is stored without compression,
+    /// since touchHLE has no DEFLATE implementation.
+    pub fn to_png(&self) -> Vec<u8> {
+        fn chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+            out.extend_from_slice(&u32::try_from(data.len()).unwrap().to_be_bytes());
+            let start = out.len();
+            out.extend_from_slice(kind);
+            out.extend_from_slice(data);
+            let crc = crc32(&out[start..]);
+            out.extend_from_slice(&crc.to_be_bytes());
+        }
+
+        let (width, height) = self.dimensions;
+
+        let mut header = Vec::new();
+        header.extend_from_slice(&width.to_be_bytes());
+        header.extend_from_slice(&height.to_be_bytes());
+        // 8 bits per channel, RGBA, default compression/filter, no interlacing
+        header.extend_from_slice(&[8, 6, 0, 0, 0]);
+
+        // Each scanline is preceded by its filter type, which is always 0
+        // (none) here.
+        let mut scanlines = Vec::with_capacity(self.pixels.len() + height as usize);
+        for row in self.pixels.chunks(width as usize * 4) {
+            scanlines.push(0);
+            scanlines.extend_from_slice(row);
+        }
+
+        // zlib stream using only "stored" (uncompressed) DEFLATE blocks.
+        let mut zlib = vec![0x78, 0x01];
+        let mut blocks = scanlines.chunks(0xFFFF).peekable();
+        if blocks.peek().is_none() {
+            zlib.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+        }
+        while let Some(block) = blocks.next() {
+            let is_final = blocks.peek().is_none();
+            let len = block.len() as u16;
+            zlib.push(is_final as u8);
+            zlib.extend_from_slice(&len.to_le_bytes());
+            zlib.extend_from_slice(&(!len).to_le_bytes());
+            zlib.extend_from_slice(block);
+        }
+        zlib.extend_from_slice(&adler32(&scanlines).to_be_bytes());
+
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        chunk(&mut png, b"IHDR", &header);
+        chunk(&mut png, b"IDAT", &zlib);
+        chunk(&mut png, b"IEND", &[]);
+        png
+    }
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB88320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
 }
 
-impl Drop for Image {
-    fn drop(&mut self) {
-        unsafe { stbi_image_free(self.pixels.cast()) }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checksums() {
+        assert_eq!(crc32(b"123456789"), 0xCBF43926);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E60398);
+    }
+
+    #[test]
+    fn downscaling() {
+        #[rustfmt::skip]
+        let image = Image::from_pixels(
+            vec![
+                0, 0, 0, 255,   4, 8, 0, 255,   1, 1, 1, 1,
+                8, 0, 0, 255,   0, 0, 4, 255,   1, 1, 1, 1,
+            ],
+            (3, 2),
+        );
+        let downscaled = image.downscaled(2);
+        assert_eq!(downscaled.dimensions(), (1, 1));
+        assert_eq!(downscaled.pixels(), [3, 2, 1, 255]);
+        assert_eq!(image.downscaled(1).pixels(), image.pixels());
+    }
+
+    #[test]
+    fn png_round_trip() {
+        let (width, height) = (300, 250);
+        let mut pixels = Vec::new();
+        for y in 0..height {
+            for x in 0..width {
+                pixels.extend_from_slice(&[x as u8, y as u8, (x ^ y) as u8, 255]);
+            }
+        }
+        let image = Image::from_pixels(pixels.clone(), (width, height));
+
+        let decoded = Image::from_bytes(&image.to_png()).unwrap();
+        assert_eq!(decoded.dimensions(), (width, height));
+        assert_eq!(decoded.pixels(), pixels);
     }
 }
@@ -0,0 +1,427 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! Recording and replaying of user input (`--record-input=` and
+//! `--replay-input=`), for reproducing bugs and regression-testing apps.
+//!
+//! Input is recorded where UIKit consumes it: the window events handled by
+//! [crate::frameworks::uikit::handle_events], and the accelerometer samples
+//! sent to the app. Each time UIKit handles events counts as one "tick", and
+//! every recorded item is tagged with the tick it happened on. On replay, the
+//! items are fed back on the same ticks rather than at the same wall-clock
+//! times, so that the app sees the same sequence of input even if the host is
+//! faster or slower. Combined with `--rng-seed=`, this makes runs reproducible.
+//!
+//! While replaying, live input is ignored, except for quitting. Once the
+//! recording runs out, live input is accepted again.
+//!
+//! # File format
+//!
+//! All integers and floats are little-endian.
+//!
+//! The file starts with a header:
+//! - The 8-byte magic number `tHLEinpt`.
+//! - A `u32` format version, currently [VERSION].
+//!
+//! The header is followed by any number of records, each consisting of:
+//! - A `u64` tick number. Ticks are counted from one and records are in tick
+//!   order.
+//! - A `u64` timestamp: nanoseconds since the emulator started, according to
+//!   [crate::libc::time::monotonic_since_startup]. This is informative only
+//!   and is not used for replay.
+//! - A `u8` record kind, followed by the kind's payload:
+//!   - `0` touch down, `1` touch move, `2` touch up: a finger ID, then the
+//!     `f32` X and Y coordinates.
+//!   - `3` focus lost, `4` focus gained, `5` enter background,
+//!     `6` enter foreground: no payload.
+//!   - `7` accelerometer sample: `f32` X, Y and Z acceleration.
+//!
+//! A finger ID is a `u8`: `0` for the mouse, `1` for the controller's virtual
+//! cursor, or `2` for a touch device finger, which is followed by the `i64`
+//! touch device ID and `i64` finger ID.
+
+use crate::window::{Event, FingerId, Window};
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::time::Duration;
+
+const MAGIC: &[u8; 8] = b"tHLEinpt";
+/// Current version of the file format.
+const VERSION: u32 = 1;
+
+/// Something that can happen during a tick.
+#[derive(Debug, PartialEq)]
+enum Item {
+    Event(Event),
+    Acceleration((f32, f32, f32)),
+}
+
+#[derive(Debug, PartialEq)]
+struct Record {
+    tick: u64,
+    timestamp: Duration,
+    item: Item,
+}
+
+fn encode_header(out: &mut Vec<u8>) {
+    out.extend_from_slice(MAGIC);
+    out.extend_from_slice(&VERSION.to_le_bytes());
+}
+
+fn encode_record(out: &mut Vec<u8>, record: &Record) {
+    fn encode_touch(out: &mut Vec<u8>, kind: u8, finger: FingerId, (x, y): (f32, f32)) {
+        out.push(kind);
+        match finger {
+            FingerId::Mouse => out.push(0),
+            FingerId::VirtualCursor => out.push(1),
+            FingerId::Finger {
+                touch_id,
+                finger_id,
+            } => {
+                out.push(2);
+                out.extend_from_slice(&touch_id.to_le_bytes());
+                out.extend_from_slice(&finger_id.to_le_bytes());
+            }
+        }
+        out.extend_from_slice(&x.to_le_bytes());
+        out.extend_from_slice(&y.to_le_bytes());
+    }
+
+    out.extend_from_slice(&record.tick.to_le_bytes());
+    out.extend_from_slice(&(record.timestamp.as_nanos() as u64).to_le_bytes());
+    match record.item {
+        Item::Event(Event::TouchDown(finger, coords)) => encode_touch(out, 0, finger, coords),
+        Item::Event(Event::TouchMove(finger, coords)) => encode_touch(out, 1, finger, coords),
+        Item::Event(Event::TouchUp(finger, coords)) => encode_touch(out, 2, finger, coords),
+        Item::Event(Event::FocusLost) => out.push(3),
+        Item::Event(Event::FocusGained) => out.push(4),
+        Item::Event(Event::EnterBackground) => out.push(5),
+        Item::Event(Event::EnterForeground) => out.push(6),
+        Item::Event(Event::Quit) => panic!("Quit events are not recorded"),
+        Item::Acceleration((x, y, z)) => {
+            out.push(7);
+            out.extend_from_slice(&x.to_le_bytes());
+            out.extend_from_slice(&y.to_le_bytes());
+            out.extend_from_slice(&z.to_le_bytes());
+        }
+    }
+}
+
+/// Cursor for reading the little-endian values of the file format.
+struct Reader<'a> {
+    data: &'a [u8],
+}
+impl<'a> Reader<'a> {
+    fn bytes<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        if self.data.len() < N {
+            return Err("Input recording is truncated".to_string());
+        }
+        let (bytes, rest) = self.data.split_at(N);
+        self.data = rest;
+        Ok(bytes.try_into().unwrap())
+    }
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.bytes::<1>()?[0])
+    }
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.bytes()?))
+    }
+    fn u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_le_bytes(self.bytes()?))
+    }
+    fn i64(&mut self) -> Result<i64, String> {
+        Ok(i64::from_le_bytes(self.bytes()?))
+    }
+    fn f32(&mut self) -> Result<f32, String> {
+        Ok(f32::from_le_bytes(self.bytes()?))
+    }
+}
+
+fn decode(data: &[u8]) -> Result<Vec<Record>, String> {
+    let mut reader = Reader { data };
+
+    if reader.bytes::<8>().ok().as_ref() != Some(MAGIC) {
+        return Err("File is not a touchHLE input recording".to_string());
+    }
+    let version = reader.u32()?;
+    if version != VERSION {
+        return Err(format!(
+            "Input recording has unsupported format version {} (expected {})",
+            version, VERSION
+        ));
+    }
+
+    let mut records = Vec::new();
+    while !reader.data.is_empty() {
+        let tick = reader.u64()?;
+        let timestamp = Duration::from_nanos(reader.u64()?);
+        let item = match reader.u8()? {
+            kind @ 0..=2 => {
+                let finger = match reader.u8()? {
+                    0 => FingerId::Mouse,
+                    1 => FingerId::VirtualCursor,
+                    2 => FingerId::Finger {
+                        touch_id: reader.i64()?,
+                        finger_id: reader.i64()?,
+                    },
+                    other => return Err(format!("Invalid finger ID kind {}", other)),
+                };
+                let coords = (reader.f32()?, reader.f32()?);
+                Item::Event(match kind {
+                    0 => Event::TouchDown(finger, coords),
+                    1 => Event::TouchMove(finger, coords),
+                    _ => Event::TouchUp(finger, coords),
+                })
+            }
+            3 => Item::Event(Event::FocusLost),
+            4 => Item::Event(Event::FocusGained),
+            5 => Item::Event(Event::EnterBackground),
+            6 => Item::Event(Event::EnterForeground),
+            7 => Item::Acceleration((reader.f32()?, reader.f32()?, reader.f32()?)),
+            other => return Err(format!("Invalid record kind {}", other)),
+        };
+        if records
+            .last()
+            .map_or(false, |last: &Record| last.tick > tick)
+        {
+            return Err("Input recording is not in tick order".to_string());
+        }
+        records.push(Record {
+            tick,
+            timestamp,
+            item,
+        });
+    }
+    Ok(records)
+}
+
+pub struct Recorder {
+    file: BufWriter<File>,
+    tick: u64,
+    timestamp: Duration,
+}
+impl Recorder {
+    fn record(&mut self, item: Item) {
+        let mut bytes = Vec::new();
+        encode_record(
+            &mut bytes,
+            &Record {
+                tick: self.tick,
+                timestamp: self.timestamp,
+                item,
+            },
+        );
+        // Flushing immediately means the recording is still useful if the
+        // emulator crashes, which is when it's most likely to be wanted.
+        self.file
+            .write_all(&bytes)
+            .and_then(|()| self.file.flush())
+            .expect("Could not write to input recording");
+    }
+}
+
+pub struct Replayer {
+    /// Remaining records, in reverse order.
+    records: Vec<Record>,
+    tick: u64,
+    /// Events for the current tick that haven't been handled yet.
+    events: VecDeque<Event>,
+    /// Accelerometer sample for the current tick, if it hasn't been used yet.
+    acceleration: Option<(f32, f32, f32)>,
+    finished: bool,
+}
+
+pub enum InputRecording {
+    Record(Recorder),
+    Replay(Replayer),
+}
+impl InputRecording {
+    pub fn record_to(path: &Path) -> Result<InputRecording, String> {
+        let mut file = File::create(path)
+            .map(BufWriter::new)
+            .map_err(|e| format!("Could not create input recording {:?}: {}", path, e))?;
+        let mut header = Vec::new();
+        encode_header(&mut header);
+        file.write_all(&header)
+            .and_then(|()| file.flush())
+            .map_err(|e| format!("Could not write input recording {:?}: {}", path, e))?;
+        log!("Recording input to {:?}.", path);
+        Ok(InputRecording::Record(Recorder {
+            file,
+            tick: 0,
+            timestamp: Duration::ZERO,
+        }))
+    }
+
+    pub fn replay_from(path: &Path) -> Result<InputRecording, String> {
+        let data = std::fs::read(path)
+            .map_err(|e| format!("Could not read input recording {:?}: {}", path, e))?;
+        let mut records = decode(&data)
+            .map_err(|e| format!("Could not parse input recording {:?}: {}", path, e))?;
+        records.reverse();
+        log!(
+            "Replaying {} input record(s) from {:?}. Live input will be ignored until the replay finishes.",
+            records.len(),
+            path
+        );
+        Ok(InputRecording::Replay(Replayer {
+            records,
+            tick: 0,
+            events: VecDeque::new(),
+            acceleration: None,
+            finished: false,
+        }))
+    }
+
+    /// Start a new tick. This must be called each time UIKit handles events,
+    /// before anything else is done with the recording.
+    pub fn begin_tick(&mut self, timestamp: Duration) {
+        match self {
+            InputRecording::Record(recorder) => {
+                recorder.tick += 1;
+                recorder.timestamp = timestamp;
+            }
+            InputRecording::Replay(replayer) => {
+                replayer.tick += 1;
+                // Anything from the previous tick that wasn't used is dropped,
+                // so that the replay doesn't get out of step.
+                replayer.events.clear();
+                replayer.acceleration = None;
+                while replayer
+                    .records
+                    .last()
+                    .map_or(false, |record| record.tick <= replayer.tick)
+                {
+                    let record = replayer.records.pop().unwrap();
+                    match record.item {
+                        Item::Event(event) => replayer.events.push_back(event),
+                        Item::Acceleration(acceleration) => {
+                            replayer.acceleration = Some(acceleration)
+                        }
+                    }
+                }
+                if replayer.records.is_empty() && !replayer.finished {
+                    replayer.finished = true;
+                    log!("Input replay finished, live input will now be used.");
+                }
+            }
+        }
+    }
+
+    /// Get the next event to handle, either from the window or from the
+    /// recording.
+    pub fn next_event(&mut self, window: &mut Window) -> Option<Event> {
+        match self {
+            InputRecording::Record(recorder) => {
+                let event = window.pop_event()?;
+                if !matches!(event, Event::Quit) {
+                    recorder.record(Item::Event(event.clone()));
+                }
+                Some(event)
+            }
+            InputRecording::Replay(replayer) => {
+                if let Some(event) = replayer.events.pop_front() {
+                    return Some(event);
+                }
+                loop {
+                    let event = window.pop_event()?;
+                    if replayer.finished || matches!(event, Event::Quit) {
+                        return Some(event);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Get the accelerometer sample to send to the app this tick, if any.
+    /// `live` is called to get a sample from the host when one is due, but
+    /// during a replay, the recorded samples are used instead.
+    pub fn acceleration(
+        &mut self,
+        live: impl FnOnce() -> Option<(f32, f32, f32)>,
+    ) -> Option<(f32, f32, f32)> {
+        match self {
+            InputRecording::Record(recorder) => {
+                let acceleration = live()?;
+                recorder.record(Item::Acceleration(acceleration));
+                Some(acceleration)
+            }
+            InputRecording::Replay(replayer) if !replayer.finished => replayer.acceleration.take(),
+            InputRecording::Replay(_) => live(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let records = vec![
+            Record {
+                tick: 1,
+                timestamp: Duration::from_millis(16),
+                item: Item::Event(Event::TouchDown(FingerId::Mouse, (10.0, 20.5))),
+            },
+            Record {
+                tick: 1,
+                timestamp: Duration::from_millis(16),
+                item: Item::Acceleration((0.0, -1.0, 0.25)),
+            },
+            Record {
+                tick: 3,
+                timestamp: Duration::from_millis(50),
+                item: Item::Event(Event::TouchMove(
+                    FingerId::Finger {
+                        touch_id: -5,
+                        finger_id: 1 << 40,
+                    },
+                    (319.0, 0.0),
+                )),
+            },
+            Record {
+                tick: 4,
+                timestamp: Duration::from_millis(66),
+                item: Item::Event(Event::TouchUp(FingerId::VirtualCursor, (1.0, 2.0))),
+            },
+            Record {
+                tick: 9,
+                timestamp: Duration::from_secs(1),
+                item: Item::Event(Event::EnterBackground),
+            },
+        ];
+
+        let mut data = Vec::new();
+        encode_header(&mut data);
+        for record in &records {
+            encode_record(&mut data, record);
+        }
+        assert_eq!(decode(&data), Ok(records));
+    }
+
+    #[test]
+    fn invalid_files() {
+        assert!(decode(b"").is_err());
+        assert!(decode(b"notinput\x01\0\0\0").is_err());
+        assert!(decode(b"tHLEinpt\x02\0\0\0").is_err());
+        assert_eq!(decode(b"tHLEinpt\x01\0\0\0"), Ok(Vec::new()));
+
+        let mut data = Vec::new();
+        encode_header(&mut data);
+        encode_record(
+            &mut data,
+            &Record {
+                tick: 1,
+                timestamp: Duration::ZERO,
+                item: Item::Event(Event::FocusLost),
+            },
+        );
+        data.pop();
+        assert!(decode(&data).is_err());
+    }
+}
@@ -22,10 +22,12 @@ pub mod stdio;
 pub mod stdlib;
 pub mod string;
 pub mod time;
+pub mod unistd;
 
 /// Container for state of various child modules
 #[derive(Default)]
 pub struct State {
+    dlfcn: dlfcn::State,
     keymgr: keymgr::State,
     pthread: pthread::State,
     stdio: stdio::State,
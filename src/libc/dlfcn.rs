@@ -5,47 +5,257 @@
  */
 //! `dlfcn.h` (`dlopen()` and friends)
 
-use crate::dyld::{export_c_func, FunctionExports};
-use crate::mem::{ConstPtr, MutVoidPtr, Ptr};
+use crate::cpu::Cpu;
+use crate::dyld::{export_c_func, Dyld, FunctionExports};
+use crate::mach_o::MachO;
+use crate::mem::{ConstPtr, Mem, MutPtr, MutVoidPtr, Ptr};
 use crate::Environment;
+use std::collections::HashMap;
+
+#[derive(Default)]
+pub struct State {
+    /// Error message for the most recent failure, if it hasn't been retrieved
+    /// with `dlerror()` yet.
+    last_error: Option<String>,
+    /// Buffer for the string most recently returned by `dlerror()`.
+    error_buffer: Option<MutPtr<u8>>,
+    /// Handles returned by `dlopen()` for libraries, with the library's path
+    /// and the number of times it has been opened.
+    handles: HashMap<MutVoidPtr, (String, u32)>,
+    /// Handle returned by `dlopen(NULL, ...)`, i.e. for the main program.
+    main_program_handle: Option<MutVoidPtr>,
+}
+impl State {
+    fn get(env: &mut Environment) -> &mut Self {
+        &mut env.libc_state.dlfcn
+    }
+}
+
+/// Whether a library has a host implementation, in which case `dlopen()` can
+/// succeed without loading anything.
+fn is_host_library(path: &str) -> bool {
+    path == "/usr/lib/libSystem.B.dylib"
+        || path == "/usr/lib/libobjc.A.dylib"
+        || path.starts_with("/System/Library/Frameworks/")
+}
 
 fn dlopen(env: &mut Environment, path: ConstPtr<u8>, _mode: i32) -> MutVoidPtr {
-    // TODO: dlopen() support for real dynamic libraries, and support for all
-    // libraries with host implementations.
-    assert_eq!(env.mem.cstr_at_utf8(path), "/usr/lib/libSystem.B.dylib");
-    // For convenience, use the path as the handle.
+    let path = if path.is_null() {
+        None
+    } else {
+        Some(env.mem.cstr_at_utf8(path).to_string())
+    };
+    let handle = open_library(&mut env.libc_state.dlfcn, &mut env.mem, path.as_deref());
+    log_dbg!("dlopen({:?}) => {:?}", path, handle);
+    handle
+}
+
+/// The bookkeeping part of `dlopen()`. `path` is [None] for the main program.
+fn open_library(state: &mut State, mem: &mut Mem, path: Option<&str>) -> MutVoidPtr {
+    let Some(path) = path else {
+        // The handle isn't used for anything, it just needs to be unique.
+        return *state
+            .main_program_handle
+            .get_or_insert_with(|| mem.alloc(1));
+    };
+
+    // TODO: dlopen() support for real dynamic libraries.
+    if !is_host_library(path) {
+        log!("Warning: dlopen() for unsupported library {:?}", path);
+        state.last_error = Some(format!("dlopen({}): image not found", path));
+        return Ptr::null();
+    }
+
+    // Opening a library again gives the same handle.
     // TODO: Find out whether the handle is truly opaque on iPhone OS, and if
     // not, where it points.
-    path.cast_mut().cast()
+    if let Some((&handle, (_, count))) = state
+        .handles
+        .iter_mut()
+        .find(|(_, (handle_path, _))| handle_path == path)
+    {
+        *count += 1;
+        return handle;
+    }
+    // For convenience, the handle is a copy of the path. The app's string
+    // can't be used, because it might be freed or overwritten.
+    let handle = mem.alloc_and_write_cstr(path.as_bytes()).cast();
+    state.handles.insert(handle, (path.to_string(), 1));
+    handle
 }
 
 fn dlsym(env: &mut Environment, handle: MutVoidPtr, symbol: ConstPtr<u8>) -> MutVoidPtr {
-    assert_eq!(
-        env.mem.cstr_at_utf8(handle.cast()),
-        "/usr/lib/libSystem.B.dylib"
-    );
+    // All host-implemented libraries and loaded binaries currently share a
+    // single namespace, so the handle (which might also be a special value like
+    // RTLD_DEFAULT) is not used.
     // For some reason, the symbols passed to dlsym() don't have the leading _.
     let symbol = format!("_{}", env.mem.cstr_at_utf8(symbol));
-    // TODO: error handling. dlsym() should just return NULL in this case, but
-    // currently it's probably more useful to have the emulator crash if there's
-    // no symbol found, since it most likely indicates a missing host function.
-    let addr = env
-        .dyld
-        .create_proc_address(&mut env.mem, &mut env.cpu, &symbol)
-        .unwrap_or_else(|_| panic!("dlsym() for unimplemented function {}", symbol));
-    Ptr::from_bits(addr.addr_with_thumb_bit())
+    log_dbg!("dlsym({:?}, {:?})", handle, symbol);
+
+    match find_symbol(
+        &env.bins,
+        &mut env.dyld,
+        &mut env.mem,
+        &mut env.cpu,
+        &symbol,
+    ) {
+        Ok(addr) => addr,
+        Err(error) => {
+            log!("Warning: dlsym() for unimplemented function {}", symbol);
+            State::get(env).last_error = Some(format!("dlsym({:?}, {})", handle, error));
+            Ptr::null()
+        }
+    }
+}
+
+/// Find a symbol (with the leading _) exported by a loaded binary or
+/// implemented by the host. The error is a message for `dlerror()`.
+fn find_symbol(
+    bins: &[MachO],
+    dyld: &mut Dyld,
+    mem: &mut Mem,
+    cpu: &mut Cpu,
+    symbol: &str,
+) -> Result<MutVoidPtr, String> {
+    if let Some(&addr) = bins.iter().find_map(|bin| bin.exported_symbols.get(symbol)) {
+        return Ok(Ptr::from_bits(addr));
+    }
+
+    match dyld.create_proc_address(mem, cpu, symbol) {
+        Ok(function) => Ok(Ptr::from_bits(function.addr_with_thumb_bit())),
+        Err(()) => Err(format!("{}: symbol not found", &symbol[1..])),
+    }
+}
+
+fn dlerror(env: &mut Environment) -> MutPtr<u8> {
+    take_error(&mut env.libc_state.dlfcn, &mut env.mem)
+}
+
+/// The bookkeeping part of `dlerror()`.
+fn take_error(state: &mut State, mem: &mut Mem) -> MutPtr<u8> {
+    let Some(error) = state.last_error.take() else {
+        return Ptr::null();
+    };
+    // The string returned by the previous call is no longer valid.
+    if let Some(old_buffer) = state.error_buffer.take() {
+        mem.free(old_buffer.cast());
+    }
+    let buffer = mem.alloc_and_write_cstr(error.as_bytes());
+    state.error_buffer = Some(buffer);
+    buffer
 }
 
 fn dlclose(env: &mut Environment, handle: MutVoidPtr) -> i32 {
-    assert_eq!(
-        env.mem.cstr_at_utf8(handle.cast()),
-        "/usr/lib/libSystem.B.dylib"
-    );
+    close_library(&mut env.libc_state.dlfcn, &mut env.mem, handle)
+}
+
+/// The bookkeeping part of `dlclose()`.
+fn close_library(state: &mut State, mem: &mut Mem, handle: MutVoidPtr) -> i32 {
+    if Some(handle) == state.main_program_handle {
+        return 0; // success
+    }
+    let Some((path, count)) = state.handles.get_mut(&handle) else {
+        log!("Warning: dlclose() with invalid handle {:?}", handle);
+        state.last_error = Some(format!("dlclose({:?}): invalid handle", handle));
+        return -1;
+    };
+    *count -= 1;
+    if *count == 0 {
+        log_dbg!("dlclose(): {:?} is no longer open", path);
+        state.handles.remove(&handle);
+        mem.free(handle);
+    }
     0 // success
 }
 
 pub const FUNCTIONS: FunctionExports = &[
     export_c_func!(dlopen(_, _)),
     export_c_func!(dlsym(_, _)),
+    export_c_func!(dlerror()),
     export_c_func!(dlclose(_)),
 ];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn open_and_close() {
+        let mut state = State::default();
+        let mut mem = Mem::new();
+        let path = "/usr/lib/libSystem.B.dylib";
+
+        let handle = open_library(&mut state, &mut mem, Some(path));
+        assert!(!handle.is_null());
+        assert_eq!(mem.cstr_at_utf8(handle.cast::<u8>()), path);
+        assert_eq!(open_library(&mut state, &mut mem, Some(path)), handle);
+        assert_eq!(close_library(&mut state, &mut mem, handle), 0);
+        assert_eq!(close_library(&mut state, &mut mem, handle), 0);
+        assert!(state.handles.is_empty());
+
+        // Closing it once too often is an error, as is a null handle.
+        assert_eq!(close_library(&mut state, &mut mem, handle), -1);
+        assert!(!take_error(&mut state, &mut mem).is_null());
+        assert_eq!(close_library(&mut state, &mut mem, Ptr::null()), -1);
+
+        // The main program always has the same handle.
+        let main = open_library(&mut state, &mut mem, None);
+        assert!(!main.is_null());
+        assert_eq!(open_library(&mut state, &mut mem, None), main);
+        assert_eq!(close_library(&mut state, &mut mem, main), 0);
+    }
+
+    #[test]
+    fn unsupported_library() {
+        let mut state = State::default();
+        let mut mem = Mem::new();
+
+        let handle = open_library(&mut state, &mut mem, Some("/usr/lib/libfoo.dylib"));
+        assert!(handle.is_null());
+        let error = take_error(&mut state, &mut mem);
+        assert_eq!(
+            mem.cstr_at_utf8(error),
+            "dlopen(/usr/lib/libfoo.dylib): image not found"
+        );
+        // The error is only reported once.
+        assert!(take_error(&mut state, &mut mem).is_null());
+    }
+
+    #[test]
+    fn symbols() {
+        let mut dyld = Dyld::new();
+        let mut mem = Mem::new();
+        let mut cpu = Cpu::new();
+
+        // Host functions are found without any binary exporting them. The
+        // pointer is to a guest function that calls the host function with an
+        // SVC, so check that the instruction there leads to a host function.
+        // Executing it would need a whole Environment.
+        let malloc = find_symbol(&[], &mut dyld, &mut mem, &mut cpu, "_malloc").unwrap();
+        let instruction = mem.read(malloc.cast::<u32>());
+        assert_eq!(instruction & 0xff000000, 0xef000000);
+        let svc = instruction & 0x00ffffff;
+        assert!(dyld
+            .get_svc_handler(&[], &mut mem, &mut cpu, malloc.to_bits(), svc)
+            .is_some());
+        assert_eq!(
+            find_symbol(&[], &mut dyld, &mut mem, &mut cpu, "_malloc"),
+            Ok(malloc)
+        );
+
+        let error = find_symbol(&[], &mut dyld, &mut mem, &mut cpu, "_notARealFunction");
+        assert_eq!(error, Err("notARealFunction: symbol not found".to_string()));
+
+        // As dlsym() would report it. The error is only reported once.
+        let mut state = State {
+            last_error: error.err(),
+            ..Default::default()
+        };
+        let error = take_error(&mut state, &mut mem);
+        assert_eq!(
+            mem.cstr_at_utf8(error),
+            "notARealFunction: symbol not found"
+        );
+        assert!(take_error(&mut state, &mut mem).is_null());
+    }
+}
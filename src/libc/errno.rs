@@ -4,7 +4,50 @@
  * file, You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 //! `errno.h`
+//!
+//! On Darwin, `errno` is a macro that expands to `(*__error())`, where
+//! `__error()` returns a pointer to the current thread's `errno` value. Each
+//! thread's value lives in guest memory, allocated on first use.
+
+use crate::dyld::{export_c_func, FunctionExports};
+use crate::mem::MutPtr;
+use crate::Environment;
 
 pub const EPERM: i32 = 1;
+pub const ENOENT: i32 = 2;
+pub const ESRCH: i32 = 3;
+pub const EIO: i32 = 5;
 pub const EDEADLK: i32 = 11;
+pub const EBUSY: i32 = 16;
 pub const EINVAL: i32 = 22;
+pub const EDOM: i32 = 33;
+pub const ERANGE: i32 = 34;
+
+/// Get the pointer to the current thread's `errno`, allocating it if needed.
+fn errno_ptr(env: &mut Environment) -> MutPtr<i32> {
+    let thread = &mut env.threads[env.current_thread];
+    if let Some(ptr) = thread.errno {
+        return ptr;
+    }
+    let ptr = env.mem.alloc_and_write(0i32);
+    thread.errno = Some(ptr);
+    ptr
+}
+
+/// Set the current thread's `errno`, for use by host implementations of
+/// functions that do so.
+pub fn set_errno(env: &mut Environment, value: i32) {
+    log_dbg!(
+        "Setting errno to {} on thread {}",
+        value,
+        env.current_thread
+    );
+    let ptr = errno_ptr(env);
+    env.mem.write(ptr, value);
+}
+
+fn __error(env: &mut Environment) -> MutPtr<i32> {
+    errno_ptr(env)
+}
+
+pub const FUNCTIONS: FunctionExports = &[export_c_func!(__error())];
@@ -6,9 +6,10 @@
 //! `mach_time.h`
 
 use crate::dyld::{export_c_func, FunctionExports};
+use crate::libc::time::monotonic_since_startup;
 use crate::mem::{MutPtr, SafeRead};
 use crate::Environment;
-use std::time::Instant;
+use std::time::Duration;
 
 #[repr(C, packed)]
 struct struct_mach_timebase_info {
@@ -35,15 +36,16 @@ fn mach_timebase_info(
     KERN_SUCCESS
 }
 
+/// Convert a duration to the units of [mach_absolute_time].
+pub fn ticks_from_duration(duration: Duration) -> u64 {
+    duration.as_nanos().try_into().unwrap()
+}
+
 /// The result of this function, multiplied by the constant from
 /// [mach_timebase_info], should be the absolute time in nanoseconds.
 /// The absolute time is a monotonic clock with an arbitrary starting point.
 fn mach_absolute_time(env: &mut Environment) -> u64 {
-    let now = Instant::now();
-    now.duration_since(env.startup_time)
-        .as_nanos()
-        .try_into()
-        .unwrap()
+    ticks_from_duration(monotonic_since_startup(env))
 }
 
 pub const FUNCTIONS: FunctionExports = &[
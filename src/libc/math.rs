@@ -6,23 +6,63 @@
 //! `math.h`
 
 use crate::dyld::{export_c_func, FunctionExports};
+use crate::libc::errno::{set_errno, EDOM, ERANGE};
 use crate::Environment;
 
-// Exponential functions
-// FIXME: These should theoretically set errno, though it's unlikely apps
-// actually check it.
-// TODO: implement the rest
-fn sqrt(_env: &mut Environment, arg: f64) -> f64 {
-    arg.sqrt()
+/// Set `errno` if a function's result indicates an error: a NaN result from
+/// non-NaN arguments is a domain error (`EDOM`), and an infinite result from
+/// finite arguments is an overflow or pole error (`ERANGE`).
+///
+/// C99 lets the implementation choose whether to report errors this way, so
+/// it's unlikely apps rely on it, but it doesn't hurt.
+fn check_errors<T: Into<f64> + Copy>(env: &mut Environment, args: &[T], result: T) -> T {
+    let result_f64: f64 = result.into();
+    if result_f64.is_nan() && !args.iter().any(|&arg| arg.into().is_nan()) {
+        set_errno(env, EDOM);
+    } else if result_f64.is_infinite() && args.iter().all(|&arg| arg.into().is_finite()) {
+        set_errno(env, ERANGE);
+    }
+    result
 }
-fn sqrtf(_env: &mut Environment, arg: f32) -> f32 {
-    arg.sqrt()
+
+// Exponential and logarithmic functions
+
+fn exp(env: &mut Environment, arg: f64) -> f64 {
+    check_errors(env, &[arg], arg.exp())
+}
+fn expf(env: &mut Environment, arg: f32) -> f32 {
+    check_errors(env, &[arg], arg.exp())
+}
+fn log(env: &mut Environment, arg: f64) -> f64 {
+    check_errors(env, &[arg], arg.ln())
+}
+fn logf(env: &mut Environment, arg: f32) -> f32 {
+    check_errors(env, &[arg], arg.ln())
+}
+fn log10(env: &mut Environment, arg: f64) -> f64 {
+    check_errors(env, &[arg], arg.log10())
+}
+fn log10f(env: &mut Environment, arg: f32) -> f32 {
+    check_errors(env, &[arg], arg.log10())
+}
+
+// Power functions
+
+fn pow(env: &mut Environment, base: f64, exponent: f64) -> f64 {
+    check_errors(env, &[base, exponent], base.powf(exponent))
+}
+fn powf(env: &mut Environment, base: f32, exponent: f32) -> f32 {
+    check_errors(env, &[base, exponent], base.powf(exponent))
+}
+fn sqrt(env: &mut Environment, arg: f64) -> f64 {
+    check_errors(env, &[arg], arg.sqrt())
+}
+fn sqrtf(env: &mut Environment, arg: f32) -> f32 {
+    check_errors(env, &[arg], arg.sqrt())
 }
 
 // Trigonometric functions
 
-// FIXME: These should theoretically set errno, though it's unlikely apps
-// actually check it.
 // TODO: These should also have `long double` variants, which can probably just
 // alias the `double` ones.
 
@@ -45,17 +85,17 @@ fn tanf(_env: &mut Environment, arg: f32) -> f32 {
     arg.tan()
 }
 
-fn asin(_env: &mut Environment, arg: f64) -> f64 {
-    arg.asin()
+fn asin(env: &mut Environment, arg: f64) -> f64 {
+    check_errors(env, &[arg], arg.asin())
 }
-fn asinf(_env: &mut Environment, arg: f32) -> f32 {
-    arg.asin()
+fn asinf(env: &mut Environment, arg: f32) -> f32 {
+    check_errors(env, &[arg], arg.asin())
 }
-fn acos(_env: &mut Environment, arg: f64) -> f64 {
-    arg.acos()
+fn acos(env: &mut Environment, arg: f64) -> f64 {
+    check_errors(env, &[arg], arg.acos())
 }
-fn acosf(_env: &mut Environment, arg: f32) -> f32 {
-    arg.acos()
+fn acosf(env: &mut Environment, arg: f32) -> f32 {
+    check_errors(env, &[arg], arg.acos())
 }
 fn atan(_env: &mut Environment, arg: f64) -> f64 {
     arg.atan()
@@ -98,21 +138,71 @@ fn asinh(_env: &mut Environment, arg: f64) -> f64 {
 fn asinhf(_env: &mut Environment, arg: f32) -> f32 {
     arg.asinh()
 }
-fn acosh(_env: &mut Environment, arg: f64) -> f64 {
-    arg.acosh()
+fn acosh(env: &mut Environment, arg: f64) -> f64 {
+    check_errors(env, &[arg], arg.acosh())
+}
+fn acoshf(env: &mut Environment, arg: f32) -> f32 {
+    check_errors(env, &[arg], arg.acosh())
+}
+fn atanh(env: &mut Environment, arg: f64) -> f64 {
+    check_errors(env, &[arg], arg.atanh())
+}
+fn atanhf(env: &mut Environment, arg: f32) -> f32 {
+    check_errors(env, &[arg], arg.atanh())
+}
+
+// Nearest integer functions
+
+fn floor(_env: &mut Environment, arg: f64) -> f64 {
+    arg.floor()
+}
+fn floorf(_env: &mut Environment, arg: f32) -> f32 {
+    arg.floor()
+}
+fn ceil(_env: &mut Environment, arg: f64) -> f64 {
+    arg.ceil()
+}
+fn ceilf(_env: &mut Environment, arg: f32) -> f32 {
+    arg.ceil()
+}
+// Rust's round() rounds halfway cases away from zero, like C's.
+fn round(_env: &mut Environment, arg: f64) -> f64 {
+    arg.round()
+}
+fn roundf(_env: &mut Environment, arg: f32) -> f32 {
+    arg.round()
+}
+
+// Remainder functions
+
+// Rust's % operator on floats has the same semantics as C's fmod().
+fn fmod(env: &mut Environment, x: f64, y: f64) -> f64 {
+    check_errors(env, &[x, y], x % y)
 }
-fn acoshf(_env: &mut Environment, arg: f32) -> f32 {
-    arg.acosh()
+fn fmodf(env: &mut Environment, x: f32, y: f32) -> f32 {
+    check_errors(env, &[x, y], x % y)
 }
-fn atanh(_env: &mut Environment, arg: f64) -> f64 {
-    arg.atanh()
+
+// Absolute value functions
+
+fn fabs(_env: &mut Environment, arg: f64) -> f64 {
+    arg.abs()
 }
-fn atanhf(_env: &mut Environment, arg: f32) -> f32 {
-    arg.atanh()
+fn fabsf(_env: &mut Environment, arg: f32) -> f32 {
+    arg.abs()
 }
 
 pub const FUNCTIONS: FunctionExports = &[
-    // Exponential functions
+    // Exponential and logarithmic functions
+    export_c_func!(exp(_)),
+    export_c_func!(expf(_)),
+    export_c_func!(log(_)),
+    export_c_func!(logf(_)),
+    export_c_func!(log10(_)),
+    export_c_func!(log10f(_)),
+    // Power functions
+    export_c_func!(pow(_, _)),
+    export_c_func!(powf(_, _)),
     export_c_func!(sqrt(_)),
     export_c_func!(sqrtf(_)),
     // Trigonometric functions
@@ -143,4 +233,86 @@ pub const FUNCTIONS: FunctionExports = &[
     export_c_func!(acoshf(_)),
     export_c_func!(atanh(_)),
     export_c_func!(atanhf(_)),
+    // Nearest integer functions
+    export_c_func!(floor(_)),
+    export_c_func!(floorf(_)),
+    export_c_func!(ceil(_)),
+    export_c_func!(ceilf(_)),
+    export_c_func!(round(_)),
+    export_c_func!(roundf(_)),
+    // Remainder functions
+    export_c_func!(fmod(_, _)),
+    export_c_func!(fmodf(_, _)),
+    // Absolute value functions
+    export_c_func!(fabs(_)),
+    export_c_func!(fabsf(_)),
 ];
+
+#[cfg(test)]
+mod tests {
+    use crate::abi::{GuestArg, GuestRet};
+
+    /// Pass arguments and the result through registers the way a guest call
+    /// would, and compare with calling the host function directly. This
+    /// checks e.g. that a `double` uses a pair of registers.
+    fn check_via_regs<T>(args: &[T], f: impl Fn(&[T]) -> T)
+    where
+        T: GuestArg + GuestRet + Copy + Into<f64>,
+    {
+        let mut regs = [0u32; 4];
+        let mut offset = 0;
+        for &arg in args {
+            GuestArg::to_regs(arg, &mut regs[offset..offset + T::REG_COUNT]);
+            offset += T::REG_COUNT;
+        }
+
+        let mut offset = 0;
+        let guest_args: Vec<T> = args
+            .iter()
+            .map(|_| {
+                let arg = <T as GuestArg>::from_regs(&regs[offset..offset + T::REG_COUNT]);
+                offset += T::REG_COUNT;
+                arg
+            })
+            .collect();
+        let mut ret_regs = [0u32; 2];
+        GuestRet::to_regs(f(&guest_args), &mut ret_regs);
+        let guest_result: f64 = <T as GuestRet>::from_regs(&ret_regs).into();
+
+        let host_result: f64 = f(args).into();
+        assert!(
+            guest_result.to_bits() == host_result.to_bits(),
+            "{} != {}",
+            guest_result,
+            host_result
+        );
+    }
+
+    #[test]
+    fn float_functions() {
+        check_via_regs(&[2.0f32, 10.0], |a| a[0].powf(a[1]));
+        check_via_regs(&[-8.0f32, 1.0 / 3.0], |a| a[0].powf(a[1])); // NaN
+        check_via_regs(&[1.5f32], |a| a[0].sin());
+        check_via_regs(&[0.5f32], |a| a[0].ln());
+        check_via_regs(&[0.0f32], |a| a[0].ln()); // -inf
+        check_via_regs(&[100.0f32], |a| a[0].exp()); // inf
+        check_via_regs(&[2.5f32], |a| a[0].round());
+        check_via_regs(&[-2.5f32], |a| a[0].floor());
+        check_via_regs(&[-7.5f32, 2.0], |a| a[0] % a[1]);
+        check_via_regs(&[1.0f32, -0.0], |a| a[0].atan2(a[1]));
+    }
+
+    #[test]
+    fn double_functions() {
+        check_via_regs(&[2.0f64, 0.5], |a| a[0].powf(a[1]));
+        check_via_regs(&[f64::NAN, 0.0], |a| a[0].powf(a[1])); // 1
+        check_via_regs(&[1000.0f64], |a| a[0].exp()); // inf
+        check_via_regs(&[-1.0f64], |a| a[0].sqrt()); // NaN
+        check_via_regs(&[std::f64::consts::PI], |a| a[0].cos());
+        check_via_regs(&[1000.0f64], |a| a[0].log10());
+        check_via_regs(&[-0.5f64], |a| a[0].round());
+        check_via_regs(&[f64::INFINITY], |a| a[0].ceil());
+        check_via_regs(&[5.0f64, 0.0], |a| a[0] % a[1]); // NaN
+        check_via_regs(&[-3.25f64], |a| a[0].abs());
+    }
+}
@@ -31,11 +31,13 @@ macro_rules! check_magic {
 pub mod key;
 pub mod mutex;
 pub mod once;
+pub mod rwlock;
 pub mod thread;
 
 #[derive(Default)]
 pub struct State {
     key: key::State,
     mutex: mutex::State,
+    rwlock: rwlock::State,
     thread: thread::State,
 }
@@ -5,7 +5,7 @@
  */
 //! Thread-specific data keys.
 
-use crate::abi::GuestFunction;
+use crate::abi::{CallFromHost, GuestFunction};
 use crate::dyld::{export_c_func, FunctionExports};
 use crate::mem::{ConstVoidPtr, MutPtr, MutVoidPtr, Ptr};
 use crate::{Environment, ThreadID};
@@ -25,6 +25,10 @@ fn get_state(env: &mut Environment) -> &mut State {
 
 type pthread_key_t = u32;
 
+/// Maximum number of times destructors are run for a thread, in case they set
+/// new values. This is Apple's value.
+const PTHREAD_DESTRUCTOR_ITERATIONS: usize = 4;
+
 fn pthread_key_create(
     env: &mut Environment,
     key_ptr: MutPtr<pthread_key_t>,
@@ -58,8 +62,84 @@ fn pthread_setspecific(env: &mut Environment, key: pthread_key_t, value: ConstVo
     0 // success
 }
 
+/// Remove the current values of a thread's keys that have destructors, and
+/// return the destructor calls that should be made for them.
+fn take_destructor_calls(state: &mut State, thread: ThreadID) -> Vec<(GuestFunction, MutVoidPtr)> {
+    let mut calls = Vec::new();
+    for (values, destructor) in state.keys.iter_mut() {
+        if destructor.addr_with_thumb_bit() == 0 {
+            continue;
+        }
+        match values.remove(&thread) {
+            Some(value) if !value.is_null() => calls.push((*destructor, value)),
+            _ => (),
+        }
+    }
+    calls
+}
+
+/// For use by [super::thread]: Call the destructors for a thread's
+/// thread-specific data when it exits.
+pub fn run_destructors(env: &mut Environment, thread: ThreadID) {
+    for _ in 0..PTHREAD_DESTRUCTOR_ITERATIONS {
+        let calls = take_destructor_calls(get_state(env), thread);
+        if calls.is_empty() {
+            break;
+        }
+        for (destructor, value) in calls {
+            log_dbg!(
+                "Thread {} calling thread-specific data destructor {:?} with {:?}",
+                thread,
+                destructor,
+                value
+            );
+            let () = destructor.call_from_host(env, (value,));
+        }
+    }
+    // The data is no longer accessible, so there's no point keeping it.
+    for (values, _) in get_state(env).keys.iter_mut() {
+        values.remove(&thread);
+    }
+}
+
 pub const FUNCTIONS: FunctionExports = &[
     export_c_func!(pthread_key_create(_, _)),
     export_c_func!(pthread_getspecific(_)),
     export_c_func!(pthread_setspecific(_, _)),
 ];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn destructor_receives_value() {
+        let destructor = GuestFunction::from_addr_with_thumb_bit(0x2001);
+        let no_destructor = GuestFunction::from_addr_with_thumb_bit(0);
+        let value: MutVoidPtr = Ptr::from_bits(0x3000);
+        let mut state = State::default();
+        state.keys.push((HashMap::new(), destructor));
+        state.keys.push((HashMap::new(), no_destructor));
+        state.keys[0].0.insert(1, value);
+        state.keys[0].0.insert(2, Ptr::from_bits(0x4000));
+        state.keys[1].0.insert(1, value);
+
+        let calls = take_destructor_calls(&mut state, 1);
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].0.addr_with_thumb_bit(), 0x2001);
+        assert_eq!(calls[0].1, value);
+        // The value is cleared before the destructor is called, and other
+        // threads' values are unaffected.
+        assert!(take_destructor_calls(&mut state, 1).is_empty());
+        assert_eq!(state.keys[0].0.get(&2), Some(&Ptr::from_bits(0x4000)));
+    }
+
+    #[test]
+    fn null_value_has_no_destructor_call() {
+        let destructor = GuestFunction::from_addr_with_thumb_bit(0x2001);
+        let mut state = State::default();
+        state.keys.push((HashMap::new(), destructor));
+        state.keys[0].0.insert(1, Ptr::null());
+        assert!(take_destructor_calls(&mut state, 1).is_empty());
+    }
+}
@@ -0,0 +1,302 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! Read-write locks.
+
+use crate::dyld::{export_c_func, FunctionExports};
+use crate::libc::errno::{EBUSY, EDEADLK, EPERM};
+use crate::mem::{ConstVoidPtr, MutPtr, SafeRead};
+use crate::{Environment, ThreadID};
+use std::collections::{HashMap, VecDeque};
+
+#[derive(Default)]
+pub struct State {
+    rwlocks: HashMap<MutPtr<pthread_rwlock_t>, RwLockHostObject>,
+}
+impl State {
+    fn get(env: &mut Environment) -> &mut Self {
+        &mut env.libc_state.pthread.rwlock
+    }
+}
+
+/// Apple's implementation is a 4-byte magic number followed by a 124-byte
+/// opaque region. We will store the actual data on the host instead.
+#[repr(C, packed)]
+struct pthread_rwlock_t {
+    /// Magic number (must be [MAGIC_RWLOCK])
+    magic: u32,
+}
+unsafe impl SafeRead for pthread_rwlock_t {}
+
+/// Arbitrarily-chosen magic number for `pthread_rwlock_t` (not Apple's).
+const MAGIC_RWLOCK: u32 = u32::from_be_bytes(*b"RWLK");
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum LockKind {
+    Read,
+    Write,
+}
+
+#[derive(Default)]
+struct RwLockHostObject {
+    /// Threads holding a read lock. A thread appears once for each read lock
+    /// it holds.
+    readers: Vec<ThreadID>,
+    writer: Option<ThreadID>,
+    /// Threads blocked waiting for the lock, in the order they will get it.
+    waiting: VecDeque<(ThreadID, LockKind)>,
+}
+impl RwLockHostObject {
+    fn is_held_by(&self, thread: ThreadID) -> bool {
+        self.writer == Some(thread) || self.readers.contains(&thread)
+    }
+
+    /// Whether a lock of some kind could be granted right now without making
+    /// any waiting threads wait longer.
+    fn can_grant(&self, kind: LockKind) -> bool {
+        match kind {
+            LockKind::Read => self.writer.is_none() && self.waiting.is_empty(),
+            LockKind::Write => self.writer.is_none() && self.readers.is_empty(),
+        }
+    }
+
+    fn grant(&mut self, thread: ThreadID, kind: LockKind) {
+        match kind {
+            LockKind::Read => self.readers.push(thread),
+            LockKind::Write => self.writer = Some(thread),
+        }
+    }
+
+    /// Try to take a lock without waiting. Returns [false] if the lock is
+    /// unavailable.
+    fn try_lock(&mut self, thread: ThreadID, kind: LockKind) -> bool {
+        if self.can_grant(kind) {
+            self.grant(thread, kind);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Take a lock, or add the thread to the queue of waiting threads if it is
+    /// unavailable. Returns [false] if the thread must wait.
+    fn lock(&mut self, thread: ThreadID, kind: LockKind) -> bool {
+        if self.try_lock(thread, kind) {
+            true
+        } else {
+            self.waiting.push_back((thread, kind));
+            false
+        }
+    }
+
+    /// Release one of the thread's locks and hand the lock over to waiting
+    /// threads where possible. Returns the threads that now hold the lock and
+    /// should stop waiting.
+    fn unlock(&mut self, thread: ThreadID) -> Vec<ThreadID> {
+        if self.writer == Some(thread) {
+            self.writer = None;
+        } else {
+            let idx = self.readers.iter().position(|&t| t == thread).unwrap();
+            self.readers.swap_remove(idx);
+        }
+
+        let mut woken = Vec::new();
+        while let Some(&(waiting_thread, kind)) = self.waiting.front() {
+            let available = match kind {
+                LockKind::Read => self.writer.is_none(),
+                LockKind::Write => self.writer.is_none() && self.readers.is_empty(),
+            };
+            if !available {
+                break;
+            }
+            self.waiting.pop_front();
+            self.grant(waiting_thread, kind);
+            woken.push(waiting_thread);
+        }
+        woken
+    }
+}
+
+fn pthread_rwlock_init(
+    env: &mut Environment,
+    rwlock: MutPtr<pthread_rwlock_t>,
+    // The only attribute is whether the lock is shared between processes,
+    // which doesn't matter here.
+    _attr: ConstVoidPtr, // const pthread_rwlockattr_t*
+) -> i32 {
+    env.mem.write(
+        rwlock,
+        pthread_rwlock_t {
+            magic: MAGIC_RWLOCK,
+        },
+    );
+
+    assert!(!State::get(env).rwlocks.contains_key(&rwlock));
+    State::get(env)
+        .rwlocks
+        .insert(rwlock, RwLockHostObject::default());
+
+    0 // success
+}
+
+fn lock_common(
+    env: &mut Environment,
+    rwlock: MutPtr<pthread_rwlock_t>,
+    kind: LockKind,
+    wait: bool,
+) -> i32 {
+    check_magic!(env, rwlock, MAGIC_RWLOCK);
+    let current_thread = env.current_thread;
+    let host_object = State::get(env).rwlocks.get_mut(&rwlock).unwrap();
+
+    if kind == LockKind::Write && host_object.is_held_by(current_thread) {
+        log_dbg!(
+            "Thread {} attempted to write-lock rwlock {:?} it already holds! Returning EDEADLK.",
+            current_thread,
+            rwlock,
+        );
+        return EDEADLK;
+    }
+    if kind == LockKind::Read && host_object.writer == Some(current_thread) {
+        log_dbg!(
+            "Thread {} attempted to read-lock rwlock {:?} it holds for writing! Returning EDEADLK.",
+            current_thread,
+            rwlock,
+        );
+        return EDEADLK;
+    }
+
+    if !wait {
+        return if host_object.try_lock(current_thread, kind) {
+            log_dbg!(
+                "Thread {} took {:?} lock on {:?}.",
+                current_thread,
+                kind,
+                rwlock
+            );
+            0 // success
+        } else {
+            EBUSY
+        };
+    }
+
+    if host_object.lock(current_thread, kind) {
+        log_dbg!(
+            "Thread {} took {:?} lock on {:?}.",
+            current_thread,
+            kind,
+            rwlock
+        );
+    } else {
+        // The lock will be handed over by pthread_rwlock_unlock(), which will
+        // then unblock this thread.
+        log_dbg!(
+            "Thread {} is waiting for {:?} lock on {:?}.",
+            current_thread,
+            kind,
+            rwlock
+        );
+        env.block_current_thread();
+    }
+    0 // success
+}
+
+fn pthread_rwlock_rdlock(env: &mut Environment, rwlock: MutPtr<pthread_rwlock_t>) -> i32 {
+    lock_common(env, rwlock, LockKind::Read, true)
+}
+fn pthread_rwlock_wrlock(env: &mut Environment, rwlock: MutPtr<pthread_rwlock_t>) -> i32 {
+    lock_common(env, rwlock, LockKind::Write, true)
+}
+fn pthread_rwlock_tryrdlock(env: &mut Environment, rwlock: MutPtr<pthread_rwlock_t>) -> i32 {
+    lock_common(env, rwlock, LockKind::Read, false)
+}
+fn pthread_rwlock_trywrlock(env: &mut Environment, rwlock: MutPtr<pthread_rwlock_t>) -> i32 {
+    lock_common(env, rwlock, LockKind::Write, false)
+}
+
+fn pthread_rwlock_unlock(env: &mut Environment, rwlock: MutPtr<pthread_rwlock_t>) -> i32 {
+    check_magic!(env, rwlock, MAGIC_RWLOCK);
+    let current_thread = env.current_thread;
+    let host_object = State::get(env).rwlocks.get_mut(&rwlock).unwrap();
+
+    if !host_object.is_held_by(current_thread) {
+        log_dbg!(
+            "Thread {} attempted to unlock rwlock {:?} it doesn't hold! Returning EPERM.",
+            current_thread,
+            rwlock,
+        );
+        return EPERM;
+    }
+
+    log_dbg!("Thread {} unlocked rwlock {:?}.", current_thread, rwlock);
+    for thread in host_object.unlock(current_thread) {
+        log_dbg!("Handing over rwlock {:?} to thread {}.", rwlock, thread);
+        env.unblock_thread(thread);
+    }
+    0 // success
+}
+
+fn pthread_rwlock_destroy(env: &mut Environment, rwlock: MutPtr<pthread_rwlock_t>) -> i32 {
+    check_magic!(env, rwlock, MAGIC_RWLOCK);
+    let host_object = State::get(env).rwlocks.remove(&rwlock).unwrap();
+    assert!(host_object.writer.is_none() && host_object.readers.is_empty()); // should be EBUSY
+    env.mem.write(rwlock, pthread_rwlock_t { magic: 0 });
+    0 // success
+}
+
+pub const FUNCTIONS: FunctionExports = &[
+    export_c_func!(pthread_rwlock_init(_, _)),
+    export_c_func!(pthread_rwlock_rdlock(_)),
+    export_c_func!(pthread_rwlock_wrlock(_)),
+    export_c_func!(pthread_rwlock_tryrdlock(_)),
+    export_c_func!(pthread_rwlock_trywrlock(_)),
+    export_c_func!(pthread_rwlock_unlock(_)),
+    export_c_func!(pthread_rwlock_destroy(_)),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn concurrent_readers_and_blocked_writer() {
+        let mut lock = RwLockHostObject::default();
+
+        assert!(lock.lock(1, LockKind::Read));
+        assert!(lock.lock(2, LockKind::Read));
+        assert!(!lock.try_lock(3, LockKind::Write));
+        assert!(!lock.lock(3, LockKind::Write));
+        // A waiting writer keeps new readers out, so it doesn't starve.
+        assert!(!lock.try_lock(4, LockKind::Read));
+        assert!(!lock.lock(4, LockKind::Read));
+
+        assert_eq!(lock.unlock(1), vec![]);
+        assert_eq!(lock.unlock(2), vec![3]);
+        assert_eq!(lock.writer, Some(3));
+
+        assert_eq!(lock.unlock(3), vec![4]);
+        assert_eq!(lock.readers, vec![4]);
+        assert!(lock.try_lock(5, LockKind::Read));
+    }
+
+    #[test]
+    fn writers_are_exclusive() {
+        let mut lock = RwLockHostObject::default();
+
+        assert!(lock.lock(1, LockKind::Write));
+        assert!(!lock.try_lock(2, LockKind::Write));
+        assert!(!lock.lock(2, LockKind::Read));
+        assert!(!lock.lock(3, LockKind::Read));
+        assert!(!lock.lock(4, LockKind::Write));
+
+        // Consecutive waiting readers are woken together, but not the writer
+        // queued after them.
+        assert_eq!(lock.unlock(1), vec![2, 3]);
+        assert_eq!(lock.unlock(3), vec![]);
+        assert_eq!(lock.unlock(2), vec![4]);
+        assert_eq!(lock.unlock(4), vec![]);
+        assert!(lock.try_lock(5, LockKind::Write));
+    }
+}
@@ -5,9 +5,10 @@
  */
 //! Threads.
 
-use crate::abi::GuestFunction;
+use crate::abi::{CallFromHost, GuestFunction};
 use crate::dyld::{export_c_func, FunctionExports};
-use crate::mem::{ConstPtr, MutPtr, MutVoidPtr, SafeRead};
+use crate::libc::errno::{EDEADLK, EINVAL, ESRCH};
+use crate::mem::{ConstPtr, Mem, MutPtr, MutVoidPtr, Ptr, SafeRead};
 use crate::{Environment, ThreadID};
 use std::collections::HashMap;
 
@@ -40,20 +41,75 @@ const DEFAULT_ATTR: pthread_attr_t = pthread_attr_t {
     _unused: [0; 8],
 };
 
-/// Apple's implementation is a 4-byte magic number followed by a massive
-/// (>4KiB) opaque region. We will store the actual data on the host instead.
+/// Apple's implementation is a 4-byte magic number and a cleanup handler stack
+/// pointer followed by a massive (>4KiB) opaque region. We will store the
+/// other data on the host instead.
 #[repr(C, packed)]
 struct OpaqueThread {
     /// Magic number (must be [MAGIC_THREAD])
     magic: u32,
+    /// Top of the stack of cleanup handlers. The `pthread_cleanup_push()` and
+    /// `pthread_cleanup_pop()` macros in Apple's headers access this directly.
+    cleanup_stack: MutPtr<darwin_pthread_handler_rec>,
 }
 unsafe impl SafeRead for OpaqueThread {}
 
+/// Cleanup handler record, allocated on the stack by
+/// `pthread_cleanup_push()`.
+#[repr(C, packed)]
+struct darwin_pthread_handler_rec {
+    routine: GuestFunction, // void (*)(void *)
+    arg: MutVoidPtr,
+    next: MutPtr<darwin_pthread_handler_rec>,
+}
+unsafe impl SafeRead for darwin_pthread_handler_rec {}
+
 type pthread_t = MutPtr<OpaqueThread>;
 
 struct ThreadHostObject {
     thread_id: ThreadID,
     _attr: pthread_attr_t,
+    join_state: JoinState,
+    /// Set by `pthread_cancel()`. Cancellation is deferred until the thread
+    /// reaches a cancellation point.
+    cancel_requested: bool,
+}
+
+/// What should happen with a thread's exit value.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum JoinState {
+    /// The thread is still running and nobody is waiting for it.
+    Joinable,
+    /// A thread is blocked in `pthread_join()` waiting for this one to exit.
+    JoinedBy {
+        thread_id: ThreadID,
+        value_ptr: MutPtr<MutVoidPtr>,
+    },
+    /// The thread has exited, but its exit value hasn't been collected yet.
+    Exited(MutVoidPtr),
+    /// Nobody will collect the exit value, so the thread's resources can be
+    /// freed as soon as it exits.
+    Detached,
+}
+impl JoinState {
+    /// Update the state for a thread that exited with `exit_value`. Returns
+    /// the thread that was waiting in `pthread_join()` and where to write the
+    /// exit value, if any. If the new state is [None], the thread's object
+    /// should be freed.
+    fn exit(
+        self,
+        exit_value: MutVoidPtr,
+    ) -> (Option<JoinState>, Option<(ThreadID, MutPtr<MutVoidPtr>)>) {
+        match self {
+            JoinState::Joinable => (Some(JoinState::Exited(exit_value)), None),
+            JoinState::JoinedBy {
+                thread_id,
+                value_ptr,
+            } => (None, Some((thread_id, value_ptr))),
+            JoinState::Detached => (None, None),
+            JoinState::Exited(_) => panic!("Thread exited twice"),
+        }
+    }
 }
 
 /// Arbitrarily-chosen magic number for `pthread_attr_t` (not Apple's).
@@ -66,6 +122,9 @@ type DetachState = i32;
 const PTHREAD_CREATE_JOINABLE: DetachState = 1;
 const PTHREAD_CREATE_DETACHED: DetachState = 2;
 
+/// Exit value of a cancelled thread.
+const PTHREAD_CANCELED: MutVoidPtr = Ptr::from_bits(1);
+
 fn pthread_attr_init(env: &mut Environment, attr: MutPtr<pthread_attr_t>) -> i32 {
     env.mem.write(attr, DEFAULT_ATTR);
     0 // success
@@ -111,19 +170,13 @@ fn pthread_create(
 
     let thread_id = env.new_thread(start_routine, user_data);
 
-    let opaque = env.mem.alloc_and_write(OpaqueThread {
-        magic: MAGIC_THREAD,
-    });
-    env.mem.write(thread, opaque);
-
-    assert!(!State::get(env).threads.contains_key(&opaque));
-    State::get(env).threads.insert(
-        opaque,
-        ThreadHostObject {
-            thread_id,
-            _attr: attr,
-        },
+    let opaque = new_thread_object(
+        &mut env.libc_state.pthread.thread,
+        &mut env.mem,
+        thread_id,
+        attr,
     );
+    env.mem.write(thread, opaque);
 
     log_dbg!("pthread_create({:?}, {:?}, {:?}, {:?}) => 0 (success), created new pthread_t {:?} (thread ID: {})", thread, attr, start_routine, user_data, opaque, thread_id);
 
@@ -138,17 +191,11 @@ fn pthread_self(env: &mut Environment) -> pthread_t {
     if current_thread == 0 && !State::get(env).main_thread_object_created {
         State::get(env).main_thread_object_created = true;
 
-        let opaque = env.mem.alloc_and_write(OpaqueThread {
-            magic: MAGIC_THREAD,
-        });
-
-        assert!(!State::get(env).threads.contains_key(&opaque));
-        State::get(env).threads.insert(
-            opaque,
-            ThreadHostObject {
-                thread_id: 0,
-                _attr: DEFAULT_ATTR,
-            },
+        let opaque = new_thread_object(
+            &mut env.libc_state.pthread.thread,
+            &mut env.mem,
+            0,
+            DEFAULT_ATTR,
         );
         log_dbg!(
             "pthread_self: created pthread object {:?} for main thread",
@@ -164,6 +211,286 @@ fn pthread_self(env: &mut Environment) -> pthread_t {
     ptr
 }
 
+/// Allocate a `pthread_t` for a thread and set up its host object.
+fn new_thread_object(
+    state: &mut State,
+    mem: &mut Mem,
+    thread_id: ThreadID,
+    attr: pthread_attr_t,
+) -> pthread_t {
+    let opaque = mem.alloc_and_write(OpaqueThread {
+        magic: MAGIC_THREAD,
+        cleanup_stack: Ptr::null(),
+    });
+
+    assert!(!state.threads.contains_key(&opaque));
+    let join_state = if attr.detachstate == PTHREAD_CREATE_DETACHED {
+        JoinState::Detached
+    } else {
+        JoinState::Joinable
+    };
+    state.threads.insert(
+        opaque,
+        ThreadHostObject {
+            thread_id,
+            _attr: attr,
+            join_state,
+            cancel_requested: false,
+        },
+    );
+    opaque
+}
+
+fn free_thread_object(state: &mut State, mem: &mut Mem, thread: pthread_t) {
+    state.threads.remove(&thread);
+    mem.write(
+        thread,
+        OpaqueThread {
+            magic: 0,
+            cleanup_stack: Ptr::null(),
+        },
+    );
+    mem.free(thread.cast());
+}
+
+fn pthread_join(env: &mut Environment, thread: pthread_t, value_ptr: MutPtr<MutVoidPtr>) -> i32 {
+    check_magic!(env, thread, MAGIC_THREAD);
+    if test_cancel(env) {
+        return 0;
+    }
+    let current_thread = env.current_thread;
+    match join_thread(
+        &mut env.libc_state.pthread.thread,
+        &mut env.mem,
+        thread,
+        current_thread,
+        value_ptr,
+    ) {
+        // thread_exited() will write the value and unblock this thread.
+        Ok(true) => env.block_current_thread(),
+        Ok(false) => (),
+        Err(err) => return err,
+    }
+    0 // success
+}
+
+/// The bookkeeping part of `pthread_join()`. If the thread has already exited,
+/// its exit value is written and its object is freed. Otherwise, this returns
+/// [true] and the joining thread must block until the thread exits (see
+/// [deliver_exit_value]).
+fn join_thread(
+    state: &mut State,
+    mem: &mut Mem,
+    thread: pthread_t,
+    joiner: ThreadID,
+    value_ptr: MutPtr<MutVoidPtr>,
+) -> Result<bool, i32> {
+    let Some(host_object) = state.threads.get_mut(&thread) else {
+        return Err(ESRCH);
+    };
+    if host_object.thread_id == joiner {
+        return Err(EDEADLK);
+    }
+
+    let join_state = host_object.join_state;
+    match join_state {
+        JoinState::Joinable => {
+            log_dbg!(
+                "Thread {} is waiting to join thread {}",
+                joiner,
+                host_object.thread_id
+            );
+            host_object.join_state = JoinState::JoinedBy {
+                thread_id: joiner,
+                value_ptr,
+            };
+            Ok(true)
+        }
+        JoinState::Exited(exit_value) => {
+            log_dbg!(
+                "Thread {} joined already-exited thread {}",
+                joiner,
+                host_object.thread_id
+            );
+            if !value_ptr.is_null() {
+                mem.write(value_ptr, exit_value);
+            }
+            free_thread_object(state, mem, thread);
+            Ok(false)
+        }
+        // Already being joined by another thread, or not joinable.
+        JoinState::JoinedBy { .. } | JoinState::Detached => Err(EINVAL),
+    }
+}
+
+fn pthread_detach(env: &mut Environment, thread: pthread_t) -> i32 {
+    check_magic!(env, thread, MAGIC_THREAD);
+    let Some(host_object) = State::get(env).threads.get_mut(&thread) else {
+        return ESRCH;
+    };
+    let join_state = host_object.join_state;
+    match join_state {
+        JoinState::Joinable => host_object.join_state = JoinState::Detached,
+        JoinState::Exited(_) => {
+            free_thread_object(&mut env.libc_state.pthread.thread, &mut env.mem, thread)
+        }
+        JoinState::JoinedBy { .. } | JoinState::Detached => return EINVAL,
+    }
+    0 // success
+}
+
+fn pthread_cancel(env: &mut Environment, thread: pthread_t) -> i32 {
+    check_magic!(env, thread, MAGIC_THREAD);
+    let Some(host_object) = State::get(env).threads.get_mut(&thread) else {
+        return ESRCH;
+    };
+    let thread_id = host_object.thread_id;
+    if thread_id == 0 {
+        // The main thread can't exit without exiting the app.
+        log!("TODO: pthread_cancel() of the main thread, ignoring");
+        return 0;
+    }
+    host_object.cancel_requested = true;
+    log_dbg!("Cancellation requested for thread {}", thread_id);
+
+    // Sleeping and pthread_join() are cancellation points, so a thread blocked
+    // in either can be cancelled right away. Otherwise, the thread will notice
+    // the cancellation when it reaches a cancellation point.
+    if thread_id != env.current_thread {
+        if env.is_thread_sleeping(thread_id) {
+            env.request_thread_exit(thread_id, PTHREAD_CANCELED);
+        } else if abandon_join(State::get(env), thread_id) {
+            env.unblock_thread(thread_id);
+            env.request_thread_exit(thread_id, PTHREAD_CANCELED);
+        }
+    }
+    0 // success
+}
+
+/// If `joiner` is blocked in `pthread_join()`, make the thread it is joining
+/// forget about it, so that the exit value isn't delivered to a thread that
+/// was cancelled. Returns [true] if `joiner` was joining a thread.
+fn abandon_join(state: &mut State, joiner: ThreadID) -> bool {
+    let Some(host_object) = state.threads.values_mut().find(|host_object| {
+        matches!(
+            host_object.join_state,
+            JoinState::JoinedBy { thread_id, .. } if thread_id == joiner
+        )
+    }) else {
+        return false;
+    };
+    log_dbg!(
+        "Thread {} is no longer waiting to join thread {}",
+        joiner,
+        host_object.thread_id
+    );
+    host_object.join_state = JoinState::Joinable;
+    true
+}
+
+fn pthread_testcancel(env: &mut Environment) {
+    test_cancel(env);
+}
+
+/// Check for a pending cancellation of the current thread. This should be
+/// called by functions that are cancellation points, before they block. If
+/// this returns [true], the thread will exit once the current host function
+/// returns, so the function should return without doing anything.
+pub fn test_cancel(env: &mut Environment) -> bool {
+    let current_thread = env.current_thread;
+    let cancel_requested = State::get(env)
+        .threads
+        .values()
+        .any(|host_object| host_object.thread_id == current_thread && host_object.cancel_requested);
+    if cancel_requested {
+        log_dbg!("Thread {} reached a cancellation point", current_thread);
+        env.request_thread_exit(current_thread, PTHREAD_CANCELED);
+    }
+    cancel_requested
+}
+
+/// Remove the most recently pushed cleanup handler from a thread's stack.
+fn pop_cleanup_handler(mem: &mut Mem, thread: pthread_t) -> Option<(GuestFunction, MutVoidPtr)> {
+    let mut opaque = mem.read(thread);
+    if opaque.cleanup_stack.is_null() {
+        return None;
+    }
+    let darwin_pthread_handler_rec { routine, arg, next } = mem.read(opaque.cleanup_stack);
+    opaque.cleanup_stack = next;
+    mem.write(thread, opaque);
+    Some((routine, arg))
+}
+
+/// For use by [crate::Environment]: Handle a thread exiting (its start routine
+/// returned or it was cancelled). This runs any remaining cleanup handlers and
+/// thread-specific data destructors, then delivers the exit value to
+/// `pthread_join()` or frees the thread's resources if it was detached.
+pub fn thread_exited(env: &mut Environment, thread_id: ThreadID, exit_value: MutVoidPtr) {
+    // Threads not created by pthread_create() (e.g. by NSThread) have no
+    // pthread object.
+    let thread = State::get(env)
+        .threads
+        .iter()
+        .find(|(_, host_object)| host_object.thread_id == thread_id)
+        .map(|(&thread, _)| thread);
+
+    if let Some(thread) = thread {
+        // Handlers are only left on the stack if the thread was cancelled.
+        while let Some((routine, arg)) = pop_cleanup_handler(&mut env.mem, thread) {
+            log_dbg!(
+                "Thread {} calling cleanup handler {:?} with {:?}",
+                thread_id,
+                routine,
+                arg
+            );
+            let () = routine.call_from_host(env, (arg,));
+        }
+    }
+
+    super::key::run_destructors(env, thread_id);
+
+    let Some(thread) = thread else {
+        return;
+    };
+    if let Some(joiner) = deliver_exit_value(
+        &mut env.libc_state.pthread.thread,
+        &mut env.mem,
+        thread,
+        exit_value,
+    ) {
+        env.unblock_thread(joiner);
+    }
+}
+
+/// The bookkeeping part of [thread_exited]. If a thread is waiting in
+/// `pthread_join()`, the exit value is written for it and its ID is returned
+/// so it can be unblocked.
+fn deliver_exit_value(
+    state: &mut State,
+    mem: &mut Mem,
+    thread: pthread_t,
+    exit_value: MutVoidPtr,
+) -> Option<ThreadID> {
+    let host_object = state.threads.get_mut(&thread).unwrap();
+    let thread_id = host_object.thread_id;
+    let (new_state, joiner) = host_object.join_state.exit(exit_value);
+    match new_state {
+        Some(new_state) => host_object.join_state = new_state,
+        None => free_thread_object(state, mem, thread),
+    }
+    let (joiner, value_ptr) = joiner?;
+    log_dbg!(
+        "Thread {} exited, delivering {:?} to thread {}",
+        thread_id,
+        exit_value,
+        joiner
+    );
+    if !value_ptr.is_null() {
+        mem.write(value_ptr, exit_value);
+    }
+    Some(joiner)
+}
+
 type mach_port_t = u32;
 
 /// Undocumented Darwin function that returns a `mach_port_t`, which in practice
@@ -179,5 +506,200 @@ pub const FUNCTIONS: FunctionExports = &[
     export_c_func!(pthread_attr_destroy(_)),
     export_c_func!(pthread_create(_, _, _, _)),
     export_c_func!(pthread_self()),
+    export_c_func!(pthread_join(_, _)),
+    export_c_func!(pthread_detach(_)),
+    export_c_func!(pthread_cancel(_)),
+    export_c_func!(pthread_testcancel()),
     export_c_func!(pthread_mach_thread_np(_)),
 ];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::thread as thread_for_test;
+    use crate::{find_next_thread, ThreadBlock};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn create_and_join() {
+        let mut state = State::default();
+        let mut mem = Mem::new();
+        let value_ptr: MutPtr<MutVoidPtr> = mem.alloc_and_write(Ptr::null());
+        let exit_value: MutVoidPtr = Ptr::from_bits(0xCAFE);
+
+        // Joined before exiting: the joiner blocks, then is woken with the
+        // value.
+        let thread = new_thread_object(&mut state, &mut mem, 1, DEFAULT_ATTR);
+        assert_eq!(
+            join_thread(&mut state, &mut mem, thread, 0, value_ptr),
+            Ok(true)
+        );
+        assert_eq!(
+            deliver_exit_value(&mut state, &mut mem, thread, exit_value),
+            Some(0)
+        );
+        assert_eq!(mem.read(value_ptr), exit_value);
+        assert!(state.threads.is_empty());
+
+        // Exiting before being joined: the value is kept for pthread_join().
+        mem.write(value_ptr, Ptr::null());
+        let thread = new_thread_object(&mut state, &mut mem, 2, DEFAULT_ATTR);
+        assert_eq!(
+            deliver_exit_value(&mut state, &mut mem, thread, exit_value),
+            None
+        );
+        assert_eq!(
+            join_thread(&mut state, &mut mem, thread, 0, value_ptr),
+            Ok(false)
+        );
+        assert_eq!(mem.read(value_ptr), exit_value);
+        assert!(state.threads.is_empty());
+    }
+
+    #[test]
+    fn invalid_joins() {
+        let mut state = State::default();
+        let mut mem = Mem::new();
+        let thread = new_thread_object(&mut state, &mut mem, 1, DEFAULT_ATTR);
+        assert_eq!(
+            join_thread(&mut state, &mut mem, thread, 1, Ptr::null()),
+            Err(EDEADLK)
+        );
+        assert_eq!(
+            join_thread(&mut state, &mut mem, thread, 0, Ptr::null()),
+            Ok(true)
+        );
+        assert_eq!(
+            join_thread(&mut state, &mut mem, thread, 2, Ptr::null()),
+            Err(EINVAL)
+        );
+
+        let detached = pthread_attr_t {
+            detachstate: PTHREAD_CREATE_DETACHED,
+            ..DEFAULT_ATTR
+        };
+        let thread = new_thread_object(&mut state, &mut mem, 3, detached);
+        assert_eq!(
+            join_thread(&mut state, &mut mem, thread, 0, Ptr::null()),
+            Err(EINVAL)
+        );
+        // Nobody is waiting, so the object is freed right away.
+        assert_eq!(
+            deliver_exit_value(&mut state, &mut mem, thread, Ptr::null()),
+            None
+        );
+        assert!(!state.threads.contains_key(&thread));
+    }
+
+    #[test]
+    fn cleanup_handlers_run_in_reverse_order() {
+        let mut mem = Mem::new();
+        let thread = mem.alloc_and_write(OpaqueThread {
+            magic: MAGIC_THREAD,
+            cleanup_stack: Ptr::null(),
+        });
+        // Imitate the pthread_cleanup_push() macro.
+        for i in 1..=2 {
+            let mut opaque = mem.read(thread);
+            opaque.cleanup_stack = mem.alloc_and_write(darwin_pthread_handler_rec {
+                routine: GuestFunction::from_addr_with_thumb_bit(0x2000 + i),
+                arg: Ptr::from_bits(0x3000 + i),
+                next: opaque.cleanup_stack,
+            });
+            mem.write(thread, opaque);
+        }
+
+        let (routine, arg) = pop_cleanup_handler(&mut mem, thread).unwrap();
+        assert_eq!(routine.addr_with_thumb_bit(), 0x2002);
+        assert_eq!(arg.to_bits(), 0x3002);
+        let (routine, arg) = pop_cleanup_handler(&mut mem, thread).unwrap();
+        assert_eq!(routine.addr_with_thumb_bit(), 0x2001);
+        assert_eq!(arg.to_bits(), 0x3001);
+        assert!(pop_cleanup_handler(&mut mem, thread).is_none());
+    }
+
+    #[test]
+    fn cancel_sleeping_thread() {
+        let mut state = State::default();
+        let mut mem = Mem::new();
+        let value_ptr: MutPtr<MutVoidPtr> = mem.alloc_and_write(Ptr::null());
+        let now = Instant::now();
+
+        // Thread 1 pushed a cleanup handler and went to sleep. The main thread
+        // is waiting to join it.
+        let thread = new_thread_object(&mut state, &mut mem, 1, DEFAULT_ATTR);
+        let mut opaque = mem.read(thread);
+        opaque.cleanup_stack = mem.alloc_and_write(darwin_pthread_handler_rec {
+            routine: GuestFunction::from_addr_with_thumb_bit(0x2001),
+            arg: Ptr::from_bits(0x3001),
+            next: Ptr::null(),
+        });
+        mem.write(thread, opaque);
+        assert_eq!(
+            join_thread(&mut state, &mut mem, thread, 0, value_ptr),
+            Ok(true)
+        );
+        let wakeup = now + Duration::from_secs(60);
+        let mut threads = vec![
+            thread_for_test(ThreadBlock::Waiting),
+            thread_for_test(ThreadBlock::Sleeping(wakeup)),
+        ];
+        threads[0].in_start_routine = false;
+
+        // pthread_cancel() wakes it up to exit...
+        threads[1].request_exit(PTHREAD_CANCELED);
+        assert_eq!(find_next_thread(&mut threads, 0, now), Ok(1));
+        assert_eq!(threads[1].exit_requested, Some(PTHREAD_CANCELED));
+
+        // ...which runs its cleanup handler (see thread_exited())...
+        let (routine, arg) = pop_cleanup_handler(&mut mem, thread).unwrap();
+        assert_eq!(routine.addr_with_thumb_bit(), 0x2001);
+        assert_eq!(arg.to_bits(), 0x3001);
+        assert!(pop_cleanup_handler(&mut mem, thread).is_none());
+
+        // ...and the joiner gets PTHREAD_CANCELED.
+        assert_eq!(
+            deliver_exit_value(&mut state, &mut mem, thread, PTHREAD_CANCELED),
+            Some(0)
+        );
+        assert_eq!(mem.read(value_ptr), PTHREAD_CANCELED);
+        assert!(state.threads.is_empty());
+    }
+
+    #[test]
+    fn cancel_joining_thread() {
+        let mut state = State::default();
+        let mut mem = Mem::new();
+        let value_ptr: MutPtr<MutVoidPtr> = mem.alloc_and_write(Ptr::null());
+        let exit_value: MutVoidPtr = Ptr::from_bits(0xCAFE);
+
+        // Thread 1 is cancelled while joining thread 2.
+        let thread = new_thread_object(&mut state, &mut mem, 2, DEFAULT_ATTR);
+        assert_eq!(
+            join_thread(&mut state, &mut mem, thread, 1, value_ptr),
+            Ok(true)
+        );
+        assert!(!abandon_join(&mut state, 3));
+        assert!(abandon_join(&mut state, 1));
+        assert!(!abandon_join(&mut state, 1));
+
+        // Thread 2 exiting doesn't write to the cancelled thread's variable or
+        // try to wake it, and can still be joined by someone else.
+        assert_eq!(
+            deliver_exit_value(&mut state, &mut mem, thread, exit_value),
+            None
+        );
+        assert!(mem.read(value_ptr).is_null());
+        let other_value_ptr: MutPtr<MutVoidPtr> = mem.alloc_and_write(Ptr::null());
+        assert_eq!(
+            join_thread(&mut state, &mut mem, thread, 0, other_value_ptr),
+            Ok(false)
+        );
+        assert_eq!(mem.read(other_value_ptr), exit_value);
+    }
+
+    #[test]
+    fn detached_thread_is_freed_on_exit() {
+        assert_eq!(JoinState::Detached.exit(Ptr::null()), (None, None));
+    }
+}
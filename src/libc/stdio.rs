@@ -7,12 +7,14 @@
 
 use crate::dyld::{export_c_func, FunctionExports};
 use crate::fs::{GuestOpenOptions, GuestPath};
+use crate::libc::errno::{set_errno, EINVAL, EIO, ENOENT};
 use crate::mem::{ConstPtr, ConstVoidPtr, GuestUSize, MutPtr, MutVoidPtr, Ptr, SafeRead};
 use crate::Environment;
 use std::collections::HashMap;
-use std::io::{Read, Seek, SeekFrom, Write};
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Write};
 
 pub mod printf;
+pub mod scanf;
 
 #[derive(Default)]
 pub struct State {
@@ -28,7 +30,100 @@ struct FILE {
 unsafe impl SafeRead for FILE {}
 
 struct FileHostObject {
-    file: std::fs::File,
+    file: BufferedFile<std::fs::File>,
+}
+
+/// Buffering and end-of-file state for a `FILE`. Without buffering, functions
+/// like `fgets()` that read a little at a time would be very slow.
+///
+/// This is generic so it can be tested without a real file.
+struct BufferedFile<F> {
+    inner: BufReader<F>,
+    eof: bool,
+}
+impl<F: Read + Write + Seek> BufferedFile<F> {
+    fn new(file: F) -> Self {
+        BufferedFile {
+            inner: BufReader::new(file),
+            eof: false,
+        }
+    }
+
+    fn get_ref(&self) -> &F {
+        self.inner.get_ref()
+    }
+
+    /// Read as much of `buffer` as possible, stopping early only at the end of
+    /// the file or on an error.
+    fn read(&mut self, buffer: &mut [u8]) -> usize {
+        let mut bytes_read = 0;
+        while bytes_read < buffer.len() {
+            match self.inner.read(&mut buffer[bytes_read..]) {
+                Ok(0) => {
+                    self.eof = true;
+                    break;
+                }
+                Ok(n) => bytes_read += n,
+                Err(_) => break,
+            }
+        }
+        bytes_read
+    }
+
+    /// Read up to and including the next newline, but no more than `max_len`
+    /// bytes.
+    fn read_line(&mut self, max_len: usize) -> Vec<u8> {
+        let mut line = Vec::new();
+        while line.len() < max_len {
+            let Ok(available) = self.inner.fill_buf() else {
+                break;
+            };
+            if available.is_empty() {
+                self.eof = true;
+                break;
+            }
+            let available = &available[..available.len().min(max_len - line.len())];
+            let (used, found_newline) = match available.iter().position(|&c| c == b'\n') {
+                Some(newline_idx) => (newline_idx + 1, true),
+                None => (available.len(), false),
+            };
+            line.extend_from_slice(&available[..used]);
+            self.inner.consume(used);
+            if found_newline {
+                break;
+            }
+        }
+        line
+    }
+
+    fn write(&mut self, buffer: &[u8]) -> usize {
+        // Whatever has been read ahead into the buffer must be discarded so the
+        // write happens at the position the guest expects. Seeking does that.
+        let Ok(position) = self.inner.stream_position() else {
+            return 0;
+        };
+        if self.inner.seek(SeekFrom::Start(position)).is_err() {
+            return 0;
+        }
+        let mut bytes_written = 0;
+        while bytes_written < buffer.len() {
+            match self.inner.get_mut().write(&buffer[bytes_written..]) {
+                Ok(0) | Err(_) => break,
+                Ok(n) => bytes_written += n,
+            }
+        }
+        bytes_written
+    }
+
+    fn seek(&mut self, from: SeekFrom) -> std::io::Result<u64> {
+        self.eof = false;
+        self.inner.seek(from)
+    }
+
+    fn position(&mut self) -> std::io::Result<u64> {
+        // BufReader accounts for the buffered data here.
+        self.inner.stream_position()
+    }
 }
 
 fn fopen(env: &mut Environment, filename: ConstPtr<u8>, mode: ConstPtr<u8>) -> MutPtr<FILE> {
@@ -51,14 +146,17 @@ fn fopen(env: &mut Environment, filename: ConstPtr<u8>, mode: ConstPtr<u8>) -> M
         .open_with_options(GuestPath::new(&env.mem.cstr_at_utf8(filename)), options)
     {
         Ok(file) => {
-            let host_object = FileHostObject { file };
+            let host_object = FileHostObject {
+                file: BufferedFile::new(file),
+            };
             let file_ptr = env.mem.alloc_and_write(FILE { _filler: 0 });
             env.libc_state.stdio.files.insert(file_ptr, host_object);
             log_dbg!("fopen({:?}, {:?}) => {:?}", filename, mode, file_ptr);
             file_ptr
         }
         Err(()) => {
-            // TODO: set errno
+            // TODO: distinguish other errors, e.g. EACCES
+            set_errno(env, ENOENT);
             log!(
                 "Warning: fopen({:?}, {:?}) failed, returning NULL",
                 filename,
@@ -83,12 +181,15 @@ fn fread(
     // and most implementations provide. There's no requirement that partial
     // objects should not be written to the buffer, and perhaps some app will
     // rely on that. The file position also does not need to be rewound!
-    let bytes_read = file.file.read(buffer_slice).unwrap_or(0);
+    let bytes_read = file.file.read(buffer_slice);
     let items_read: GuestUSize = (bytes_read / usize::try_from(item_size).unwrap())
         .try_into()
         .unwrap();
     if bytes_read < buffer_slice.len() {
-        // TODO: set errno
+        // Reaching the end of the file isn't an error.
+        if !file.file.eof {
+            set_errno(env, EIO);
+        }
         log!(
             "Warning: fread({:?}, {:#x}, {:#x}, {:?}) read only {:#x} of requested {:#x} bytes",
             buffer,
@@ -122,12 +223,12 @@ fn fwrite(
     let total_size = item_size.checked_mul(n_items).unwrap();
     let buffer_slice = env.mem.bytes_at(buffer.cast(), total_size);
     // Remarks in fread() apply here too.
-    let bytes_written = file.file.write(buffer_slice).unwrap_or(0);
+    let bytes_written = file.file.write(buffer_slice);
     let items_written: GuestUSize = (bytes_written / usize::try_from(item_size).unwrap())
         .try_into()
         .unwrap();
     if bytes_written < buffer_slice.len() {
-        // TODO: set errno
+        set_errno(env, EIO);
         log!(
             "Warning: fwrite({:?}, {:#x}, {:#x}, {:?}) wrote only {:#x} of requested {:#x} bytes",
             buffer,
@@ -167,8 +268,10 @@ fn fseek(env: &mut Environment, file_ptr: MutPtr<FILE>, offset: i32, whence: i32
 
     let res = match file.file.seek(from) {
         Ok(_) => 0,
-        // TODO: set errno
-        Err(_) => -1,
+        Err(_) => {
+            set_errno(env, EINVAL);
+            -1
+        }
     };
     log_dbg!(
         "fseek({:?}, {:#x}, {}) => {}",
@@ -183,11 +286,13 @@ fn fseek(env: &mut Environment, file_ptr: MutPtr<FILE>, offset: i32, whence: i32
 fn ftell(env: &mut Environment, file_ptr: MutPtr<FILE>) -> i32 {
     let file = env.libc_state.stdio.files.get_mut(&file_ptr).unwrap();
 
-    let res = match file.file.stream_position() {
+    let res = match file.file.position() {
         // TODO: What's the correct behaviour if the position is beyond 2GiB?
         Ok(pos) => pos.try_into().unwrap(),
-        // TODO: set errno
-        Err(_) => -1,
+        Err(_) => {
+            set_errno(env, EIO);
+            -1
+        }
     };
     log_dbg!("ftell({:?}) => {:?}", file_ptr, res);
     res
@@ -198,19 +303,53 @@ fn fclose(env: &mut Environment, file_ptr: MutPtr<FILE>) -> i32 {
 
     // The actual closing of the file happens implicitly when `file` falls out
     // of scope. The return value is about whether flushing succeeds.
-    match file.file.sync_all() {
+    match file.file.get_ref().sync_all() {
         Ok(()) => {
             log_dbg!("fclose({:?}) => 0", file_ptr);
             0
         }
         Err(_) => {
-            // TODO: set errno
+            set_errno(env, EIO);
             log!("Warning: fclose({:?}) failed, returning EOF", file_ptr);
             EOF
         }
     }
 }
 
+fn feof(env: &mut Environment, file_ptr: MutPtr<FILE>) -> i32 {
+    let file = env.libc_state.stdio.files.get_mut(&file_ptr).unwrap();
+    let res = file.file.eof.into();
+    log_dbg!("feof({:?}) => {}", file_ptr, res);
+    res
+}
+
+fn fgets(env: &mut Environment, str: MutPtr<u8>, size: i32, file_ptr: MutPtr<FILE>) -> MutPtr<u8> {
+    let file = env.libc_state.stdio.files.get_mut(&file_ptr).unwrap();
+    if size <= 0 {
+        return Ptr::null();
+    }
+    // One byte is reserved for the null terminator.
+    let max_len = usize::try_from(size).unwrap() - 1;
+    let line = file.file.read_line(max_len);
+    if line.is_empty() && max_len > 0 {
+        log_dbg!("fgets({:?}, {}, {:?}) => NULL", str, size, file_ptr);
+        return Ptr::null();
+    }
+    let str_slice = env
+        .mem
+        .bytes_at_mut(str, (line.len() + 1).try_into().unwrap());
+    str_slice[..line.len()].copy_from_slice(&line);
+    str_slice[line.len()] = b'\0';
+    log_dbg!(
+        "fgets({:?}, {}, {:?}) => {:?}",
+        str,
+        size,
+        file_ptr,
+        String::from_utf8_lossy(&line)
+    );
+    str
+}
+
 fn puts(env: &mut Environment, s: ConstPtr<u8>) -> i32 {
     let _ = std::io::stdout().write_all(env.mem.cstr_at(s));
     let _ = std::io::stdout().write_all(b"\n");
@@ -226,5 +365,71 @@ pub const FUNCTIONS: FunctionExports = &[
     export_c_func!(fseek(_, _, _)),
     export_c_func!(ftell(_)),
     export_c_func!(fclose(_)),
+    export_c_func!(feof(_)),
+    export_c_func!(fgets(_, _, _)),
     export_c_func!(puts(_)),
 ];
+
+#[cfg(test)]
+mod tests {
+    use super::BufferedFile;
+    use crate::fs::{Fs, GuestOpenOptions};
+    use std::io::{Cursor, SeekFrom};
+
+    #[test]
+    fn read_from_bundle() {
+        let bundle_host_path = std::env::temp_dir().join("touchHLE_stdio_test.app");
+        std::fs::create_dir_all(&bundle_host_path).unwrap();
+        std::fs::write(
+            bundle_host_path.join("data.txt"),
+            b"first line\nsecond\nend",
+        )
+        .unwrap();
+
+        let (mut fs, bundle_guest_path) = Fs::new(
+            &bundle_host_path,
+            "Test.app".to_string(),
+            "org.touchhle.stdio-test",
+        );
+        let mut options = GuestOpenOptions::new();
+        options.read();
+        let file = fs
+            .open_with_options(bundle_guest_path.join("data.txt"), options)
+            .unwrap();
+        let mut file = BufferedFile::new(file);
+
+        assert_eq!(file.read_line(100), b"first line\n");
+        assert_eq!(file.position().unwrap(), 11);
+        // Limited by the maximum length
+        assert_eq!(file.read_line(3), b"sec");
+        assert_eq!(file.read_line(100), b"ond\n");
+        assert!(!file.eof);
+        let mut buffer = [0u8; 10];
+        assert_eq!(file.read(&mut buffer), 3);
+        assert_eq!(&buffer[..3], b"end");
+        assert!(file.eof);
+        assert_eq!(file.read_line(100), b"");
+
+        // Seeking clears the end-of-file state.
+        file.seek(SeekFrom::Start(6)).unwrap();
+        assert!(!file.eof);
+        assert_eq!(file.read(&mut buffer[..4]), 4);
+        assert_eq!(&buffer[..4], b"line");
+
+        std::fs::remove_dir_all(&bundle_host_path).unwrap();
+    }
+
+    #[test]
+    fn write_after_read() {
+        let mut file = BufferedFile::new(Cursor::new(b"abcdefgh".to_vec()));
+        let mut buffer = [0u8; 2];
+        assert_eq!(file.read(&mut buffer), 2);
+        // The rest of the data has already been buffered, but the write must
+        // still happen at the guest-visible position.
+        assert_eq!(file.write(b"XY"), 2);
+        assert_eq!(file.position().unwrap(), 4);
+        assert_eq!(file.read(&mut buffer), 2);
+        assert_eq!(&buffer, b"ef");
+        assert_eq!(file.get_ref().get_ref(), b"abXYefgh");
+    }
+}
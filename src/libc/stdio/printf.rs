@@ -4,90 +4,644 @@
  * file, You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 //! `printf` function family.
+//!
+//! The formatting itself is done by [format], which is independent of where
+//! the arguments come from (see [ArgSource]) so that it can be shared by the
+//! various `printf` variants and by `NSString`'s `stringWithFormat:` family,
+//! and tested without a guest app.
+//!
+//! Resources:
+//! - [C99 standard, section 7.19.6.1](https://www.open-std.org/jtc1/sc22/wg14/www/docs/n1256.pdf)
+//! - [POSIX `printf`](https://pubs.opengroup.org/onlinepubs/9699919799/functions/printf.html),
+//!   which adds positional arguments (`%1$d`).
 
 use crate::abi::VAList;
 use crate::dyld::{export_c_func, FunctionExports};
-use crate::mem::{ConstPtr, MutPtr};
+use crate::mem::{ConstPtr, ConstVoidPtr, GuestUSize, MutPtr, Ptr};
 use crate::Environment;
 use std::io::Write;
 
-fn printf_inner(env: &mut Environment, format: ConstPtr<u8>, mut args: VAList) -> Vec<u8> {
-    log_dbg!(
-        "Processing format string {:?}",
-        env.mem.cstr_at_utf8(format)
-    );
+/// Arbitrary limit on the length of a format string. A longer one is probably
+/// garbage, e.g. a missing null terminator or a bad pointer.
+const MAX_FORMAT_LEN: GuestUSize = 64 * 1024;
 
-    let mut res = Vec::<u8>::new();
+/// Provides the arguments for [format].
+///
+/// Arguments are accessed by the index of their first 32-bit word (as they
+/// would be laid out in a `va_list`), because positional arguments (`%1$d`)
+/// require random access. 64-bit arguments use two words.
+pub trait ArgSource {
+    fn word(&mut self, index: usize) -> u32;
+    /// Read the C string at `ptr`, stopping after `max_len` bytes if provided.
+    /// `ptr` is never null.
+    fn c_string(&mut self, ptr: ConstPtr<u8>, max_len: Option<usize>) -> Vec<u8>;
+    /// Get the UTF-8 description of an Objective-C object, for `%@`. `object`
+    /// is never `nil`. Only Foundation's format strings support this.
+    fn object_description(&mut self, object: u32) -> Vec<u8> {
+        unimplemented!("%@ with object {:#x} in a C format string", object);
+    }
+}
+
+/// [ArgSource] for the variable arguments of a function called by the guest.
+struct VAListArgs<'a> {
+    env: &'a Environment,
+    args: VAList,
+}
+impl ArgSource for VAListArgs<'_> {
+    fn word(&mut self, index: usize) -> u32 {
+        self.args.peek_word(self.env, index)
+    }
+    fn c_string(&mut self, ptr: ConstPtr<u8>, max_len: Option<usize>) -> Vec<u8> {
+        read_c_string(self.env, ptr, max_len)
+    }
+}
+
+/// [ArgSource] for a guest `va_list`, as passed to `vsnprintf()` etc. On this
+/// platform it is just a pointer to the arguments' words on the stack.
+struct GuestVaListArgs<'a> {
+    env: &'a Environment,
+    va_list: ConstPtr<u32>,
+}
+impl ArgSource for GuestVaListArgs<'_> {
+    fn word(&mut self, index: usize) -> u32 {
+        self.env.mem.read(self.va_list + index.try_into().unwrap())
+    }
+    fn c_string(&mut self, ptr: ConstPtr<u8>, max_len: Option<usize>) -> Vec<u8> {
+        read_c_string(self.env, ptr, max_len)
+    }
+}
+
+pub fn read_c_string(env: &Environment, ptr: ConstPtr<u8>, max_len: Option<usize>) -> Vec<u8> {
+    match max_len {
+        // The string doesn't have to be null-terminated in this case, so we
+        // must not read past the limit.
+        Some(max_len) => (0..max_len)
+            .map(|i| env.mem.read(ptr + i.try_into().unwrap()))
+            .take_while(|&c| c != b'\0')
+            .collect(),
+        None => env.mem.cstr_at(ptr).to_vec(),
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Length {
+    /// `hh`
+    Char,
+    /// `h`
+    Short,
+    /// No length modifier. `l`, `z` and `t` are also treated as this, since
+    /// `long`, `size_t` and `ptrdiff_t` are 32-bit.
+    Default,
+    /// `ll`, `q` or `j`. (`intmax_t` is `long long`.)
+    LongLong,
+}
+
+/// A field width or precision.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Count {
+    Fixed(usize),
+    /// `*` (`None`) or `*n$` (`Some(n)`)
+    FromArg(Option<usize>),
+}
+
+#[derive(Debug, Default, Copy, Clone)]
+struct Flags {
+    left_justify: bool,
+    plus_sign: bool,
+    space_sign: bool,
+    alternate_form: bool,
+    zero_pad: bool,
+}
+
+#[derive(Debug)]
+struct Spec {
+    /// `n` for `%n$...`, 1-based.
+    position: Option<usize>,
+    flags: Flags,
+    width: Option<Count>,
+    precision: Option<Count>,
+    length: Length,
+    conversion: u8,
+}
+
+enum Piece<'a> {
+    Literal(&'a [u8]),
+    Spec(Spec),
+}
+
+/// Number of 32-bit words used by an argument for a conversion.
+fn arg_words(conversion: u8, length: Length) -> usize {
+    match conversion {
+        b'e' | b'E' | b'f' | b'F' | b'g' | b'G' => 2, // always double
+        b'd' | b'i' | b'u' | b'o' | b'x' | b'X' if length == Length::LongLong => 2,
+        _ => 1,
+    }
+}
+
+fn parse_number(format: &[u8], i: &mut usize) -> Option<usize> {
+    let start = *i;
+    let mut number = 0usize;
+    while let Some(&c @ b'0'..=b'9') = format.get(*i) {
+        number = number.checked_mul(10)?.checked_add((c - b'0') as usize)?;
+        *i += 1;
+    }
+    (*i != start).then_some(number)
+}
 
-    let mut current_format = format;
+/// Parse `n$` if present, rewinding if not.
+fn parse_position(format: &[u8], i: &mut usize) -> Option<usize> {
+    let start = *i;
+    match parse_number(format, i) {
+        Some(n) if format.get(*i) == Some(&b'$') && n != 0 => {
+            *i += 1;
+            Some(n)
+        }
+        _ => {
+            *i = start;
+            None
+        }
+    }
+}
 
-    loop {
-        let c = env.mem.read(current_format);
-        current_format += 1;
+fn parse_count(format: &[u8], i: &mut usize) -> Option<Count> {
+    if format.get(*i) == Some(&b'*') {
+        *i += 1;
+        Some(Count::FromArg(parse_position(format, i)))
+    } else {
+        parse_number(format, i).map(Count::Fixed)
+    }
+}
 
-        if c == b'\0' {
+fn parse_format(format: &[u8]) -> Vec<Piece<'_>> {
+    let mut pieces = Vec::new();
+    let mut i = 0;
+    while i < format.len() {
+        let literal_start = i;
+        while i < format.len() && format[i] != b'%' {
+            i += 1;
+        }
+        if i > literal_start {
+            pieces.push(Piece::Literal(&format[literal_start..i]));
+        }
+        if i == format.len() {
             break;
         }
-        if c != b'%' {
-            res.push(c);
-            continue;
+        i += 1; // skip %
+
+        let position = parse_position(format, &mut i);
+
+        let mut flags = Flags::default();
+        loop {
+            match format.get(i) {
+                Some(b'-') => flags.left_justify = true,
+                Some(b'+') => flags.plus_sign = true,
+                Some(b' ') => flags.space_sign = true,
+                Some(b'#') => flags.alternate_form = true,
+                Some(b'0') => flags.zero_pad = true,
+                _ => break,
+            }
+            i += 1;
         }
 
-        let pad_char = if env.mem.read(current_format) == b'0' {
-            current_format += 1;
-            '0'
+        let width = parse_count(format, &mut i);
+        let precision = if format.get(i) == Some(&b'.') {
+            i += 1;
+            // A lone '.' means a precision of zero.
+            Some(parse_count(format, &mut i).unwrap_or(Count::Fixed(0)))
         } else {
-            ' '
+            None
         };
-        let pad_width = {
-            let mut pad_width = 0;
-            while let c @ b'0'..=b'9' = env.mem.read(current_format) {
-                pad_width = pad_width * 10 + (c - b'0') as usize;
-                current_format += 1;
+
+        let length = match format.get(i..).unwrap_or_default() {
+            [b'h', b'h', ..] => {
+                i += 2;
+                Length::Char
+            }
+            [b'l', b'l', ..] => {
+                i += 2;
+                Length::LongLong
+            }
+            [b'h', ..] => {
+                i += 1;
+                Length::Short
             }
-            pad_width
+            [b'q' | b'j', ..] => {
+                i += 1;
+                Length::LongLong
+            }
+            // `L` is for long double, which is the same as double.
+            [b'l' | b'z' | b't' | b'L', ..] => {
+                i += 1;
+                Length::Default
+            }
+            _ => Length::Default,
         };
 
-        let specifier = env.mem.read(current_format);
-        current_format += 1;
+        let Some(&conversion) = format.get(i) else {
+            panic!("Incomplete format specifier at end of format string");
+        };
+        i += 1;
 
-        assert!(specifier != b'\0');
-        if specifier == b'%' {
-            res.push(b'%');
+        if conversion == b'%' {
+            pieces.push(Piece::Literal(b"%"));
             continue;
         }
 
-        match specifier {
-            b's' => {
-                let c_string: ConstPtr<u8> = args.next(env);
-                assert!(pad_char == ' ' && pad_width == 0); // TODO
-                res.extend_from_slice(env.mem.cstr_at(c_string));
+        pieces.push(Piece::Spec(Spec {
+            position,
+            flags,
+            width,
+            precision,
+            length,
+            conversion,
+        }));
+    }
+    pieces
+}
+
+/// Keeps track of which argument is next, or where each argument is if
+/// positional arguments are used.
+struct ArgCursor {
+    next_word: usize,
+    /// Word index of each argument, if positional arguments are in use.
+    positional_offsets: Option<Vec<usize>>,
+}
+impl ArgCursor {
+    fn new(pieces: &[Piece]) -> ArgCursor {
+        // Positional arguments can be used in any order, so to find where
+        // each one is, the types of all the arguments must be known first.
+        let mut sizes: Vec<Option<usize>> = Vec::new();
+        let mut record = |position: usize, words: usize| {
+            if sizes.len() < position {
+                sizes.resize(position, None);
             }
-            b'd' | b'i' => {
-                let int: i32 = args.next(env);
-                // TODO: avoid copy?
-                if pad_width > 0 {
-                    if pad_char == '0' {
-                        res.extend_from_slice(format!("{:01$}", int, pad_width).as_bytes());
-                    } else {
-                        res.extend_from_slice(format!("{:1$}", int, pad_width).as_bytes());
-                    }
-                } else {
-                    res.extend_from_slice(format!("{}", int).as_bytes());
+            let size = &mut sizes[position - 1];
+            assert!(
+                size.is_none() || *size == Some(words),
+                "Positional argument {} used with inconsistent types",
+                position
+            );
+            *size = Some(words);
+        };
+        let mut any_positional = false;
+        for piece in pieces {
+            let Piece::Spec(spec) = piece else {
+                continue;
+            };
+            let Some(position) = spec.position else {
+                continue;
+            };
+            any_positional = true;
+            for count in [spec.width, spec.precision] {
+                if let Some(Count::FromArg(count_position)) = count {
+                    let count_position =
+                        count_position.expect("Mixing positional and non-positional arguments");
+                    record(count_position, 1);
                 }
             }
-            // TODO: more specifiers
-            _ => unimplemented!("Format character '{}'", specifier as char),
+            record(position, arg_words(spec.conversion, spec.length));
         }
+
+        let positional_offsets = any_positional.then(|| {
+            let mut offset = 0;
+            sizes
+                .iter()
+                .enumerate()
+                .map(|(i, &size)| {
+                    let Some(size) = size else {
+                        // There's no way to know this argument's size.
+                        panic!("Positional argument {} is not used", i + 1);
+                    };
+                    let this_offset = offset;
+                    offset += size;
+                    this_offset
+                })
+                .collect()
+        });
+
+        ArgCursor {
+            next_word: 0,
+            positional_offsets,
+        }
+    }
+
+    /// Get the next argument (or the argument at `position`) as a 64-bit
+    /// value, from one or two words.
+    fn next(&mut self, src: &mut impl ArgSource, position: Option<usize>, words: usize) -> u64 {
+        let index = match (&self.positional_offsets, position) {
+            (Some(offsets), Some(position)) => offsets[position - 1],
+            (None, None) => {
+                let index = self.next_word;
+                self.next_word += words;
+                index
+            }
+            _ => panic!("Mixing positional and non-positional arguments"),
+        };
+        let low = src.word(index);
+        let high = if words == 2 { src.word(index + 1) } else { 0 };
+        (u64::from(high) << 32) | u64::from(low)
     }
 
+    /// Resolve a width or precision. A negative value is returned as-is.
+    fn count(&mut self, src: &mut impl ArgSource, count: Count) -> i64 {
+        match count {
+            Count::Fixed(n) => n.try_into().unwrap(),
+            Count::FromArg(position) => self.next(src, position, 1) as u32 as i32 as i64,
+        }
+    }
+}
+
+/// Pad `body` (which is preceded by `prefix`, e.g. a sign) to the field width.
+fn pad(res: &mut Vec<u8>, flags: &Flags, width: usize, prefix: &[u8], body: &[u8], zero_ok: bool) {
+    let padding = width.saturating_sub(prefix.len() + body.len());
+    if flags.left_justify {
+        res.extend_from_slice(prefix);
+        res.extend_from_slice(body);
+        res.resize(res.len() + padding, b' ');
+    } else if flags.zero_pad && zero_ok {
+        res.extend_from_slice(prefix);
+        res.resize(res.len() + padding, b'0');
+        res.extend_from_slice(body);
+    } else {
+        res.resize(res.len() + padding, b' ');
+        res.extend_from_slice(prefix);
+        res.extend_from_slice(body);
+    }
+}
+
+fn sign_prefix(negative: bool, flags: &Flags) -> &'static [u8] {
+    if negative {
+        b"-"
+    } else if flags.plus_sign {
+        b"+"
+    } else if flags.space_sign {
+        b" "
+    } else {
+        b""
+    }
+}
+
+/// Split a non-negative finite number into the mantissa (with `precision`
+/// decimal places) and exponent, as used by `%e`. The exponent is determined
+/// after rounding.
+fn split_exponent(value: f64, precision: usize) -> (String, i32) {
+    let formatted = format!("{:.*e}", precision, value);
+    let (mantissa, exponent) = formatted.split_once('e').unwrap();
+    (mantissa.to_string(), exponent.parse().unwrap())
+}
+
+/// `%e`-style formatting, e.g. `1.5e-07`.
+fn join_exponent(mantissa: &str, exponent: i32) -> String {
+    let sign = if exponent < 0 { '-' } else { '+' };
+    format!("{}e{}{:02}", mantissa, sign, exponent.abs())
+}
+
+fn strip_trailing_zeros(digits: &str) -> &str {
+    if digits.contains('.') {
+        digits.trim_end_matches('0').trim_end_matches('.')
+    } else {
+        digits
+    }
+}
+
+fn format_float(value: f64, conversion: u8, precision: Option<usize>, flags: &Flags) -> String {
+    let uppercase = conversion.is_ascii_uppercase();
+    let precision = precision.unwrap_or(6);
+    let value = value.abs();
+
+    let mut body = match conversion.to_ascii_lowercase() {
+        b'f' => format!("{:.*}", precision, value),
+        b'e' => {
+            let (mantissa, exponent) = split_exponent(value, precision);
+            join_exponent(&mantissa, exponent)
+        }
+        b'g' => {
+            // The precision is the number of significant digits here, and
+            // the style depends on the exponent.
+            let precision = precision.max(1);
+            let (mantissa, exponent) = split_exponent(value, precision - 1);
+            let (digits, exponent) = if exponent < -4 || exponent >= precision as i32 {
+                (mantissa, Some(exponent))
+            } else {
+                let decimals = (precision as i32 - 1 - exponent) as usize;
+                (format!("{:.*}", decimals, value), None)
+            };
+            // Trailing zeros are removed unless '#' is used.
+            let digits = if flags.alternate_form {
+                &digits
+            } else {
+                strip_trailing_zeros(&digits)
+            };
+            match exponent {
+                Some(exponent) => join_exponent(digits, exponent),
+                None => digits.to_string(),
+            }
+        }
+        _ => unreachable!(),
+    };
+    if flags.alternate_form && !body.contains('.') {
+        // '#' means the decimal point is always present.
+        match body.find('e') {
+            Some(e) => body.insert(e, '.'),
+            None => body.push('.'),
+        }
+    }
+    if uppercase {
+        body.make_ascii_uppercase();
+    }
+    body
+}
+
+fn format_spec(res: &mut Vec<u8>, spec: &Spec, cursor: &mut ArgCursor, src: &mut impl ArgSource) {
+    let mut flags = spec.flags;
+    let width = spec.width.map_or(0, |width| {
+        let width = cursor.count(src, width);
+        // A negative width from an argument means left-justification.
+        if width < 0 {
+            flags.left_justify = true;
+        }
+        width.unsigned_abs() as usize
+    });
+    let precision = spec.precision.and_then(|precision| {
+        let precision = cursor.count(src, precision);
+        // A negative precision from an argument is treated as if omitted.
+        (precision >= 0).then_some(precision as usize)
+    });
+
+    let words = arg_words(spec.conversion, spec.length);
+    let arg = cursor.next(src, spec.position, words);
+
+    match spec.conversion {
+        b'd' | b'i' => {
+            let value: i64 = match spec.length {
+                Length::Char => arg as i8 as i64,
+                Length::Short => arg as i16 as i64,
+                Length::Default => arg as i32 as i64,
+                Length::LongLong => arg as i64,
+            };
+            let mut digits = value.unsigned_abs().to_string().into_bytes();
+            if let Some(precision) = precision {
+                if precision == 0 && value == 0 {
+                    digits.clear();
+                }
+                while digits.len() < precision {
+                    digits.insert(0, b'0');
+                }
+            }
+            let prefix = sign_prefix(value < 0, &flags);
+            pad(res, &flags, width, prefix, &digits, precision.is_none());
+        }
+        b'u' | b'o' | b'x' | b'X' | b'p' => {
+            let value: u64 = match spec.length {
+                Length::Char => arg as u8 as u64,
+                Length::Short => arg as u16 as u64,
+                Length::Default => arg as u32 as u64,
+                Length::LongLong => arg,
+            };
+            let mut digits = match spec.conversion {
+                b'u' => value.to_string(),
+                b'o' => format!("{:o}", value),
+                b'x' | b'p' => format!("{:x}", value),
+                b'X' => format!("{:X}", value),
+                _ => unreachable!(),
+            }
+            .into_bytes();
+            if let Some(precision) = precision {
+                if precision == 0 && value == 0 {
+                    digits.clear();
+                }
+                while digits.len() < precision {
+                    digits.insert(0, b'0');
+                }
+            }
+            let prefix: &[u8] = match spec.conversion {
+                b'p' => b"0x",
+                b'x' if flags.alternate_form && value != 0 => b"0x",
+                b'X' if flags.alternate_form && value != 0 => b"0X",
+                b'o' if flags.alternate_form && digits.first() != Some(&b'0') => b"0",
+                _ => b"",
+            };
+            pad(res, &flags, width, prefix, &digits, precision.is_none());
+        }
+        b'e' | b'E' | b'f' | b'F' | b'g' | b'G' => {
+            let value = f64::from_bits(arg);
+            let prefix = sign_prefix(value.is_sign_negative() && !value.is_nan(), &flags);
+            let body = if value.is_finite() {
+                format_float(value, spec.conversion, precision, &flags)
+            } else {
+                let body = if value.is_nan() { "nan" } else { "inf" };
+                if spec.conversion.is_ascii_uppercase() {
+                    body.to_ascii_uppercase()
+                } else {
+                    body.to_string()
+                }
+            };
+            pad(
+                res,
+                &flags,
+                width,
+                prefix,
+                body.as_bytes(),
+                value.is_finite(),
+            );
+        }
+        b'c' => {
+            pad(res, &flags, width, b"", &[arg as u8], false);
+        }
+        b'C' => {
+            // A `unichar` (UTF-16 code unit), or a `wint_t` in C.
+            let c = char::from_u32(arg as u32).unwrap_or(char::REPLACEMENT_CHARACTER);
+            let mut buffer = [0u8; 4];
+            let utf8 = c.encode_utf8(&mut buffer).as_bytes();
+            pad(res, &flags, width, b"", utf8, false);
+        }
+        b'@' => {
+            let object = arg as u32;
+            let description = if object == 0 {
+                b"(null)".to_vec()
+            } else {
+                src.object_description(object)
+            };
+            pad(res, &flags, width, b"", &description, false);
+        }
+        b's' => {
+            let ptr: ConstPtr<u8> = Ptr::from_bits(arg as u32);
+            let string = if ptr.is_null() {
+                let mut string = b"(null)".to_vec();
+                string.truncate(precision.unwrap_or(string.len()));
+                string
+            } else {
+                src.c_string(ptr, precision)
+            };
+            pad(res, &flags, width, b"", &string, false);
+        }
+        b'n' => {
+            // Writing to an arbitrary pointer is a well-known source of
+            // security problems, so this is never honored. Apple's libc also
+            // stopped supporting it eventually.
+            log!("Warning: ignoring %n in format string");
+        }
+        _ => unimplemented!("Format character '{}'", spec.conversion as char),
+    }
+}
+
+/// Format a string like `printf` would.
+pub fn format(format: &[u8], src: &mut impl ArgSource) -> Vec<u8> {
+    let pieces = parse_format(format);
+    let mut cursor = ArgCursor::new(&pieces);
+
+    let mut res = Vec::<u8>::new();
+    for piece in &pieces {
+        match piece {
+            Piece::Literal(literal) => res.extend_from_slice(literal),
+            Piece::Spec(spec) => format_spec(&mut res, spec, &mut cursor, src),
+        }
+    }
+    res
+}
+
+fn printf_inner(env: &Environment, format: ConstPtr<u8>, args: &mut impl ArgSource) -> Vec<u8> {
+    let Some(format_str) = env.mem.cstr_at_bounded(format, MAX_FORMAT_LEN) else {
+        panic!(
+            "Format string at {:?} has no null terminator within {} bytes",
+            format, MAX_FORMAT_LEN
+        );
+    };
+    log_dbg!(
+        "Processing format string {:?}",
+        String::from_utf8_lossy(format_str)
+    );
+
+    let res = self::format(format_str, args);
+
     log_dbg!("=> {:?}", std::str::from_utf8(&res));
 
     res
 }
 
+/// Get the bytes `snprintf()` should write into a buffer of `size` bytes:
+/// as much of `res` as fits, plus a null terminator, or nothing at all if
+/// `size` is zero.
+fn truncate_for_buffer(res: &[u8], size: GuestUSize) -> Vec<u8> {
+    let Some(max_len) = (size as usize).checked_sub(1) else {
+        return Vec::new();
+    };
+    let mut truncated = res[..res.len().min(max_len)].to_vec();
+    truncated.push(b'\0');
+    truncated
+}
+
+fn write_to_buffer(env: &mut Environment, dest: MutPtr<u8>, bytes: &[u8]) {
+    if bytes.is_empty() {
+        return;
+    }
+    env.mem
+        .bytes_at_mut(dest, bytes.len().try_into().unwrap())
+        .copy_from_slice(bytes);
+}
+
 fn sprintf(env: &mut Environment, dest: MutPtr<u8>, format: ConstPtr<u8>, args: VAList) -> i32 {
-    let res = printf_inner(env, format, args);
+    let res = printf_inner(env, format, &mut VAListArgs { env, args });
 
     log_dbg!("sprintf({:?}, {:?}, ...)", dest, format);
 
@@ -101,8 +655,55 @@ fn sprintf(env: &mut Environment, dest: MutPtr<u8>, format: ConstPtr<u8>, args:
     res.len().try_into().unwrap()
 }
 
+fn snprintf(
+    env: &mut Environment,
+    dest: MutPtr<u8>,
+    size: GuestUSize,
+    format: ConstPtr<u8>,
+    args: VAList,
+) -> i32 {
+    let res = printf_inner(env, format, &mut VAListArgs { env, args });
+
+    log_dbg!("snprintf({:?}, {:?}, {:?}, ...)", dest, size, format);
+
+    write_to_buffer(env, dest, &truncate_for_buffer(&res, size));
+
+    // The return value is the length the string would have had if the buffer
+    // were big enough, so the caller can detect truncation.
+    res.len().try_into().unwrap()
+}
+
+fn vsnprintf(
+    env: &mut Environment,
+    dest: MutPtr<u8>,
+    size: GuestUSize,
+    format: ConstPtr<u8>,
+    va_list: ConstVoidPtr,
+) -> i32 {
+    let res = printf_inner(
+        env,
+        format,
+        &mut GuestVaListArgs {
+            env,
+            va_list: va_list.cast(),
+        },
+    );
+
+    log_dbg!(
+        "vsnprintf({:?}, {:?}, {:?}, {:?})",
+        dest,
+        size,
+        format,
+        va_list
+    );
+
+    write_to_buffer(env, dest, &truncate_for_buffer(&res, size));
+
+    res.len().try_into().unwrap()
+}
+
 fn printf(env: &mut Environment, format: ConstPtr<u8>, args: VAList) -> i32 {
-    let res = printf_inner(env, format, args);
+    let res = printf_inner(env, format, &mut VAListArgs { env, args });
     // TODO: I/O error handling
     let _ = std::io::stdout().write_all(&res);
     res.len().try_into().unwrap()
@@ -112,5 +713,231 @@ fn printf(env: &mut Environment, format: ConstPtr<u8>, args: VAList) -> i32 {
 
 pub const FUNCTIONS: FunctionExports = &[
     export_c_func!(sprintf(_, _, _)),
+    export_c_func!(snprintf(_, _, _, _)),
+    export_c_func!(vsnprintf(_, _, _, _)),
     export_c_func!(printf(_, _)),
 ];
+
+#[cfg(test)]
+mod tests {
+    use super::{format, truncate_for_buffer, ArgSource, ConstPtr};
+    use std::collections::HashMap;
+
+    /// Arguments for a test, as they'd be laid out in a `va_list`.
+    #[derive(Default)]
+    struct TestArgs {
+        words: Vec<u32>,
+        strings: HashMap<u32, &'static [u8]>,
+        /// Descriptions of objects
+        objects: HashMap<u32, &'static [u8]>,
+    }
+    impl TestArgs {
+        fn int(mut self, value: i32) -> Self {
+            self.words.push(value as u32);
+            self
+        }
+        fn long_long(mut self, value: i64) -> Self {
+            self.words.push(value as u64 as u32);
+            self.words.push((value as u64 >> 32) as u32);
+            self
+        }
+        fn double(mut self, value: f64) -> Self {
+            let bits = value.to_bits();
+            self.words.push(bits as u32);
+            self.words.push((bits >> 32) as u32);
+            self
+        }
+        fn string(mut self, value: &'static [u8]) -> Self {
+            // Arbitrary fake address
+            let addr = 0x1000 * (self.strings.len() as u32 + 1);
+            self.strings.insert(addr, value);
+            self.words.push(addr);
+            self
+        }
+        fn object(mut self, description: &'static [u8]) -> Self {
+            // Arbitrary fake address
+            let addr = 0x10000 * (self.objects.len() as u32 + 1);
+            self.objects.insert(addr, description);
+            self.words.push(addr);
+            self
+        }
+    }
+    impl ArgSource for TestArgs {
+        fn word(&mut self, index: usize) -> u32 {
+            self.words[index]
+        }
+        fn c_string(&mut self, ptr: ConstPtr<u8>, max_len: Option<usize>) -> Vec<u8> {
+            let string = self.strings[&ptr.to_bits()];
+            string[..string.len().min(max_len.unwrap_or(usize::MAX))].to_vec()
+        }
+        fn object_description(&mut self, object: u32) -> Vec<u8> {
+            self.objects[&object].to_vec()
+        }
+    }
+
+    fn new() -> TestArgs {
+        TestArgs::default()
+    }
+
+    #[test]
+    fn table() {
+        let cases: Vec<(&str, TestArgs, &str)> = vec![
+            ("plain text", new(), "plain text"),
+            ("100%%", new(), "100%"),
+            // integers
+            ("%d", new().int(-42), "-42"),
+            (
+                "%i|%5d|%-5d|%05d",
+                new().int(7).int(7).int(7).int(-7),
+                "7|    7|7    |-0007",
+            ),
+            ("%+d % d", new().int(3).int(3), "+3  3"),
+            (
+                "%.3d|%.0d|%8.3d",
+                new().int(5).int(0).int(-5),
+                "005||    -005",
+            ),
+            ("%u", new().int(-1), "4294967295"),
+            (
+                "%x %X %#x %#o %o",
+                new().int(255).int(255).int(255).int(8).int(0),
+                "ff FF 0xff 010 0",
+            ),
+            ("%hhd %hd", new().int(0x1ff).int(0x18000), "-1 -32768"),
+            ("%ld %zu", new().int(-5).int(5), "-5 5"),
+            ("%lld", new().long_long(-1234567890123), "-1234567890123"),
+            (
+                "%llx %qu",
+                new().long_long(0x123456789a).long_long(1),
+                "123456789a 1",
+            ),
+            ("%p %p", new().int(0xdead).int(0), "0xdead 0x0"),
+            ("%c%c", new().int(b'o' as i32).int(b'k' as i32), "ok"),
+            // floats
+            ("%f", new().double(1.5), "1.500000"),
+            (
+                "%.2f|%8.3f|%-8.1f|%08.2f",
+                new().double(1.23456).double(-2.5).double(2.3).double(-1.5),
+                "1.23|  -2.500|2.3     |-0001.50",
+            ),
+            ("%.0f %#.0f", new().double(2.25).double(2.0), "2 2."),
+            ("%e", new().double(12345.678), "1.234568e+04"),
+            ("%.2E", new().double(0.000123), "1.23E-04"),
+            (
+                "%g %g %g",
+                new().double(100000.0).double(1000000.0).double(0.0001),
+                "100000 1e+06 0.0001",
+            ),
+            (
+                "%g %G",
+                new().double(0.00001234).double(1.5e-10),
+                "1.234e-05 1.5E-10",
+            ),
+            (
+                "%.3g %#g",
+                new().double(1.23456).double(1.0),
+                "1.23 1.00000",
+            ),
+            (
+                "%f %F %f",
+                new()
+                    .double(f64::INFINITY)
+                    .double(f64::NAN)
+                    .double(f64::NEG_INFINITY),
+                "inf NAN -inf",
+            ),
+            // strings
+            ("%s!", new().string(b"hello"), "hello!"),
+            (
+                "[%8s][%-8s]",
+                new().string(b"abc").string(b"abc"),
+                "[     abc][abc     ]",
+            ),
+            ("%.2s", new().string(b"hello"), "he"),
+            ("%s", new().int(0), "(null)"),
+            // widths and precisions from arguments
+            (
+                "%*d|%-*d|%*d",
+                new().int(4).int(1).int(4).int(2).int(-4).int(3),
+                "   1|2   |3   ",
+            ),
+            ("%.*f", new().int(1).double(2.26), "2.3"),
+            ("%.*s", new().int(3).string(b"abcdef"), "abc"),
+            ("%*.*s|", new().int(6).int(2).string(b"abcdef"), "    ab|"),
+            ("%.*d", new().int(-1).int(5), "5"),
+            // positional arguments
+            (
+                "%2$s %1$s",
+                new().string(b"world").string(b"hello"),
+                "hello world",
+            ),
+            ("%2$d %1$.2f %2$d", new().double(0.5).int(9), "9 0.50 9"),
+            ("%1$*2$d", new().int(7).int(3), "  7"),
+        ];
+        for (format_str, mut args, expected) in cases {
+            let res = format(format_str.as_bytes(), &mut args);
+            assert_eq!(
+                std::str::from_utf8(&res).unwrap(),
+                expected,
+                "format string {:?}",
+                format_str
+            );
+        }
+    }
+
+    #[test]
+    fn foundation_extensions() {
+        let cases: Vec<(&str, TestArgs, &str)> = vec![
+            ("%@", new().object(b"Level 1"), "Level 1"),
+            (
+                "%@ %@",
+                new().int(0).object("Ünder".as_bytes()),
+                "(null) Ünder",
+            ),
+            ("[%8@]", new().object(b"abc"), "[     abc]"),
+            ("%C%C", new().int(0x48).int(0x20ac), "H€"),
+            ("%C", new().int(0xd800), "\u{fffd}"),
+            (
+                "%d%% of %@: %.2f (%C)",
+                new()
+                    .int(50)
+                    .object(b"<Player: 0x1234>")
+                    .double(2.0 / 3.0)
+                    .int(0x2713),
+                "50% of <Player: 0x1234>: 0.67 (✓)",
+            ),
+            ("%2$@ %1$d", new().int(3).object(b"lives:"), "lives: 3"),
+        ];
+        for (format_str, mut args, expected) in cases {
+            let res = format(format_str.as_bytes(), &mut args);
+            assert_eq!(
+                std::str::from_utf8(&res).unwrap(),
+                expected,
+                "format string {:?}",
+                format_str
+            );
+        }
+    }
+
+    #[test]
+    fn n_is_ignored() {
+        let mut args = new().int(5).int(0x2000).int(6);
+        let res = format(b"%d%n%d", &mut args);
+        assert_eq!(res, b"56");
+    }
+
+    #[test]
+    fn snprintf_truncation() {
+        // Plenty of space
+        assert_eq!(truncate_for_buffer(b"hello", 10), b"hello\0");
+        // Exact fit, including the null terminator
+        assert_eq!(truncate_for_buffer(b"hello", 6), b"hello\0");
+        // One byte too small
+        assert_eq!(truncate_for_buffer(b"hello", 5), b"hell\0");
+        assert_eq!(truncate_for_buffer(b"hello", 1), b"\0");
+        // Nothing is written, not even a null terminator
+        assert_eq!(truncate_for_buffer(b"hello", 0), b"");
+        assert_eq!(truncate_for_buffer(b"", 0), b"");
+        assert_eq!(truncate_for_buffer(b"", 1), b"\0");
+    }
+}
@@ -0,0 +1,457 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! `scanf` function family.
+//!
+//! Like for `printf`, the parsing is done by a function ([scan]) that doesn't
+//! touch guest memory, and the results are written back afterwards.
+//!
+//! Resources:
+//! - [C99 standard, section 7.19.6.2](https://www.open-std.org/jtc1/sc22/wg14/www/docs/n1256.pdf)
+
+use crate::abi::VAList;
+use crate::dyld::{export_c_func, FunctionExports};
+use crate::mem::{ConstPtr, ConstVoidPtr, MutPtr, MutVoidPtr};
+use crate::Environment;
+
+const EOF: i32 = -1;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Length {
+    /// `hh`
+    Char,
+    /// `h`
+    Short,
+    /// No length modifier, or `z`/`t` (`size_t` and `ptrdiff_t` are 32-bit).
+    Default,
+    /// `l`. This is 32-bit for integers, but means `double` for floats.
+    Long,
+    /// `ll`, `q` or `j`
+    LongLong,
+}
+
+/// A value parsed by [scan], to be written to the next pointer argument.
+#[derive(Debug, PartialEq)]
+enum Value {
+    Int(i64, Length),
+    Float(f64, Length),
+    /// For `%s` this includes the null terminator, for `%c` it doesn't.
+    Bytes(Vec<u8>),
+    /// Number of bytes consumed so far, for `%n`. This doesn't count as an
+    /// assignment.
+    Count(i64, Length),
+}
+
+/// Match the input against the format string. Returns the values to assign,
+/// or [None] if the input ended before the first conversion (in which case
+/// `scanf()` returns `EOF`).
+fn scan(input: &[u8], format: &[u8]) -> Option<Vec<Value>> {
+    let mut values = Vec::new();
+    let mut in_i = 0;
+    let mut fmt_i = 0;
+    // Whether any conversion has been attempted with input available.
+    let mut any_conversion = false;
+
+    let skip_whitespace = |in_i: &mut usize| {
+        while matches!(input.get(*in_i), Some(c) if c.is_ascii_whitespace()) {
+            *in_i += 1;
+        }
+    };
+
+    while let Some(&c) = format.get(fmt_i) {
+        fmt_i += 1;
+
+        // Whitespace in the format string matches any amount of whitespace,
+        // including none.
+        if c.is_ascii_whitespace() {
+            skip_whitespace(&mut in_i);
+            continue;
+        }
+
+        if c != b'%' || format.get(fmt_i) == Some(&b'%') {
+            if c == b'%' {
+                fmt_i += 1;
+                skip_whitespace(&mut in_i);
+            }
+            // Ordinary characters must match exactly.
+            match input.get(in_i) {
+                Some(&i) if i == c => in_i += 1,
+                Some(_) => break,
+                None if any_conversion => break,
+                None => return None,
+            }
+            continue;
+        }
+
+        let suppress = format.get(fmt_i) == Some(&b'*');
+        if suppress {
+            fmt_i += 1;
+        }
+
+        let mut width: Option<usize> = None;
+        while let Some(&d @ b'0'..=b'9') = format.get(fmt_i) {
+            width = Some(width.unwrap_or(0) * 10 + (d - b'0') as usize);
+            fmt_i += 1;
+        }
+
+        let length = match format.get(fmt_i..).unwrap_or_default() {
+            [b'h', b'h', ..] => {
+                fmt_i += 2;
+                Length::Char
+            }
+            [b'l', b'l', ..] => {
+                fmt_i += 2;
+                Length::LongLong
+            }
+            [b'h', ..] => {
+                fmt_i += 1;
+                Length::Short
+            }
+            [b'l' | b'L', ..] => {
+                fmt_i += 1;
+                Length::Long
+            }
+            [b'q' | b'j', ..] => {
+                fmt_i += 1;
+                Length::LongLong
+            }
+            [b'z' | b't', ..] => {
+                fmt_i += 1;
+                Length::Default
+            }
+            _ => Length::Default,
+        };
+
+        let Some(&conversion) = format.get(fmt_i) else {
+            panic!("Incomplete format specifier at end of format string");
+        };
+        fmt_i += 1;
+
+        // All conversions except these skip leading whitespace.
+        if !matches!(conversion, b'c' | b'n') {
+            skip_whitespace(&mut in_i);
+        }
+        if in_i == input.len() && conversion != b'n' {
+            if any_conversion {
+                break;
+            } else {
+                return None;
+            }
+        }
+        any_conversion = true;
+
+        let width = width.unwrap_or(usize::MAX);
+        let available = &input[in_i..input.len().min(in_i.saturating_add(width))];
+
+        let value = match conversion {
+            b'd' | b'i' | b'u' | b'o' | b'x' | b'X' => {
+                let base = match conversion {
+                    b'd' | b'u' => Some(10),
+                    b'i' => None,
+                    b'o' => Some(8),
+                    _ => Some(16),
+                };
+                let Some((value, used)) = parse_int(available, base) else {
+                    break;
+                };
+                in_i += used;
+                Value::Int(value, length)
+            }
+            b'e' | b'E' | b'f' | b'F' | b'g' | b'G' | b'a' | b'A' => {
+                let Some((value, used)) = parse_float(available) else {
+                    break;
+                };
+                in_i += used;
+                Value::Float(value, length)
+            }
+            b's' => {
+                let used = available
+                    .iter()
+                    .position(u8::is_ascii_whitespace)
+                    .unwrap_or(available.len());
+                let mut bytes = available[..used].to_vec();
+                bytes.push(b'\0');
+                in_i += used;
+                Value::Bytes(bytes)
+            }
+            b'c' => {
+                // The default width is 1 in this case, and the full width
+                // must be available.
+                let count = if width == usize::MAX { 1 } else { width };
+                if available.len() < count {
+                    break;
+                }
+                in_i += count;
+                Value::Bytes(available[..count].to_vec())
+            }
+            b'n' => Value::Count(in_i.try_into().unwrap(), length),
+            _ => unimplemented!("Format character '{}'", conversion as char),
+        };
+        if !suppress {
+            values.push(value);
+        }
+    }
+
+    Some(values)
+}
+
+/// Parse an integer in the given base, or detect the base from the prefix
+/// if [None]. Returns the value and the number of bytes consumed. Overflow
+/// wraps, which is good enough to get the expected value for `%u` and `%x`
+/// with 32-bit values over `INT_MAX`.
+fn parse_int(input: &[u8], base: Option<u32>) -> Option<(i64, usize)> {
+    let mut i = 0;
+    let negative = match input.first() {
+        Some(b'-') => {
+            i += 1;
+            true
+        }
+        Some(b'+') => {
+            i += 1;
+            false
+        }
+        _ => false,
+    };
+
+    let has_hex_prefix = matches!(input.get(i..i + 2), Some([b'0', b'x' | b'X']))
+        && matches!(input.get(i + 2), Some(c) if c.is_ascii_hexdigit());
+    let base = match base {
+        Some(16) | None if has_hex_prefix => {
+            i += 2;
+            16
+        }
+        Some(base) => base,
+        None if input.get(i) == Some(&b'0') => 8,
+        None => 10,
+    };
+
+    let start = i;
+    let mut value: i64 = 0;
+    while let Some(digit) = input.get(i).and_then(|&c| (c as char).to_digit(base)) {
+        value = value.wrapping_mul(base.into()).wrapping_add(digit.into());
+        i += 1;
+    }
+    if i == start {
+        return None;
+    }
+
+    Some((
+        if negative {
+            value.wrapping_neg()
+        } else {
+            value
+        },
+        i,
+    ))
+}
+
+/// Parse a decimal floating-point number. Returns the value and the number of
+/// bytes consumed.
+fn parse_float(input: &[u8]) -> Option<(f64, usize)> {
+    let mut i = 0;
+    if matches!(input.first(), Some(b'-' | b'+')) {
+        i += 1;
+    }
+
+    let count_digits = |i: &mut usize| {
+        let start = *i;
+        while matches!(input.get(*i), Some(c) if c.is_ascii_digit()) {
+            *i += 1;
+        }
+        *i - start
+    };
+
+    let mut digits = count_digits(&mut i);
+    if input.get(i) == Some(&b'.') {
+        i += 1;
+        digits += count_digits(&mut i);
+    }
+    if digits == 0 {
+        return None;
+    }
+
+    // The exponent is only consumed if it's complete.
+    if matches!(input.get(i), Some(b'e' | b'E')) {
+        let mut j = i + 1;
+        if matches!(input.get(j), Some(b'-' | b'+')) {
+            j += 1;
+        }
+        if count_digits(&mut j) > 0 {
+            i = j;
+        }
+    }
+
+    let value = std::str::from_utf8(&input[..i]).unwrap().parse().unwrap();
+    Some((value, i))
+}
+
+fn scanf_inner(
+    env: &mut Environment,
+    input: ConstPtr<u8>,
+    format: ConstPtr<u8>,
+    mut next_ptr: impl FnMut(&mut Environment) -> MutVoidPtr,
+) -> i32 {
+    let input_str = env.mem.cstr_at(input);
+    let format_str = env.mem.cstr_at(format);
+    log_dbg!(
+        "Scanning {:?} with format string {:?}",
+        String::from_utf8_lossy(input_str),
+        String::from_utf8_lossy(format_str)
+    );
+
+    let Some(values) = scan(input_str, format_str) else {
+        return EOF;
+    };
+
+    log_dbg!("=> {:?}", values);
+
+    let mut assigned = 0;
+    for value in values {
+        let ptr = next_ptr(env);
+        match value {
+            Value::Int(value, length) | Value::Count(value, length) => match length {
+                Length::Char => env.mem.write(ptr.cast(), value as i8),
+                Length::Short => env.mem.write(ptr.cast(), value as i16),
+                Length::Default | Length::Long => env.mem.write(ptr.cast(), value as i32),
+                Length::LongLong => env.mem.write(ptr.cast(), value),
+            },
+            Value::Float(value, Length::Long | Length::LongLong) => {
+                env.mem.write(ptr.cast(), value)
+            }
+            Value::Float(value, _) => env.mem.write(ptr.cast(), value as f32),
+            Value::Bytes(bytes) => {
+                let ptr: MutPtr<u8> = ptr.cast();
+                env.mem
+                    .bytes_at_mut(ptr, bytes.len().try_into().unwrap())
+                    .copy_from_slice(&bytes);
+            }
+        }
+        if !matches!(value, Value::Count(..)) {
+            assigned += 1;
+        }
+    }
+    assigned
+}
+
+fn sscanf(
+    env: &mut Environment,
+    input: ConstPtr<u8>,
+    format: ConstPtr<u8>,
+    mut args: VAList,
+) -> i32 {
+    scanf_inner(env, input, format, |env| args.next(env))
+}
+
+fn vsscanf(
+    env: &mut Environment,
+    input: ConstPtr<u8>,
+    format: ConstPtr<u8>,
+    va_list: ConstVoidPtr,
+) -> i32 {
+    // On this platform, a va_list is just a pointer to the arguments.
+    let mut va_list: ConstPtr<MutVoidPtr> = va_list.cast();
+    scanf_inner(env, input, format, |env| {
+        let ptr = env.mem.read(va_list);
+        va_list += 1;
+        ptr
+    })
+}
+
+pub const FUNCTIONS: FunctionExports = &[
+    export_c_func!(sscanf(_, _, _)),
+    export_c_func!(vsscanf(_, _, _)),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::{scan, Length, Value};
+
+    fn int(value: i64) -> Value {
+        Value::Int(value, Length::Default)
+    }
+    fn string(value: &[u8]) -> Value {
+        let mut bytes = value.to_vec();
+        bytes.push(b'\0');
+        Value::Bytes(bytes)
+    }
+
+    #[test]
+    fn integers() {
+        assert_eq!(scan(b"10 -20", b"%d %d"), Some(vec![int(10), int(-20)]));
+        assert_eq!(scan(b"  42", b"%d"), Some(vec![int(42)]));
+        assert_eq!(scan(b"ff 0x1F", b"%x %X"), Some(vec![int(255), int(31)]));
+        assert_eq!(
+            scan(b"0x10 010 10", b"%i %i %i"),
+            Some(vec![int(16), int(8), int(10)])
+        );
+        assert_eq!(scan(b"12345", b"%3d%2d"), Some(vec![int(123), int(45)]));
+        assert_eq!(
+            scan(b"123", b"%lld%hd"),
+            Some(vec![Value::Int(123, Length::LongLong)])
+        );
+        assert_eq!(scan(b"4294967295", b"%u"), Some(vec![int(4294967295)]));
+    }
+
+    #[test]
+    fn floats() {
+        assert_eq!(
+            scan(b"1.5 -2e3 .25", b"%f %lf %g"),
+            Some(vec![
+                Value::Float(1.5, Length::Default),
+                Value::Float(-2000.0, Length::Long),
+                Value::Float(0.25, Length::Default),
+            ])
+        );
+        // An incomplete exponent isn't consumed
+        assert_eq!(
+            scan(b"3e+x", b"%f%s"),
+            Some(vec![Value::Float(3.0, Length::Default), string(b"e+x")])
+        );
+    }
+
+    #[test]
+    fn strings_and_chars() {
+        assert_eq!(
+            scan(b"hello world", b"%s %s"),
+            Some(vec![string(b"hello"), string(b"world")])
+        );
+        // The width limits how much is written
+        assert_eq!(
+            scan(b"abcdefgh", b"%3s%s"),
+            Some(vec![string(b"abc"), string(b"defgh")])
+        );
+        assert_eq!(
+            scan(b" xy", b"%c%2c"),
+            Some(vec![
+                Value::Bytes(b" ".to_vec()),
+                Value::Bytes(b"xy".to_vec())
+            ])
+        );
+    }
+
+    #[test]
+    fn config_line() {
+        assert_eq!(
+            scan(b"volume = 75, name: Player1", b"volume = %d, name: %15s"),
+            Some(vec![int(75), string(b"Player1")])
+        );
+        assert_eq!(
+            scan(b"pos=(3,4) 100%", b"pos=(%d,%*d) %d%%%n"),
+            Some(vec![int(3), int(100), Value::Count(14, Length::Default)])
+        );
+    }
+
+    #[test]
+    fn failures() {
+        // Matching failure: stops at the first mismatch
+        assert_eq!(scan(b"1 x 3", b"%d %d %d"), Some(vec![int(1)]));
+        assert_eq!(scan(b"a=1", b"b=%d"), Some(vec![]));
+        // Input failure before the first conversion
+        assert_eq!(scan(b"", b"%d"), None);
+        assert_eq!(scan(b"   ", b"%s"), None);
+        // Input failure after a conversion
+        assert_eq!(scan(b"5", b"%d %d"), Some(vec![int(5)]));
+    }
+}
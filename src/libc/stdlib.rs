@@ -7,13 +7,24 @@
 
 use crate::abi::GuestFunction;
 use crate::dyld::{export_c_func, FunctionExports};
-use crate::mem::{ConstPtr, GuestUSize, MutVoidPtr};
-use crate::Environment;
+use crate::libc::errno::{set_errno, EINVAL, ERANGE};
+use crate::mem::{ConstPtr, GuestUSize, MutPtr, MutVoidPtr, Ptr};
+use crate::{Environment, Options};
+use std::collections::HashMap;
 
 #[derive(Default)]
 pub struct State {
-    rand: u32,
-    random: u32,
+    rand: Prng,
+    random: Prng,
+    /// Stream for `arc4random()`. This is seeded on first use, see
+    /// [arc4random_prng].
+    arc4random: Option<Prng>,
+    /// Environment variables. This is initialized on first use, because the
+    /// defaults depend on the filesystem. See [env_vars].
+    env_vars: Option<HashMap<String, String>>,
+    /// Guest copies of environment variable values returned by `getenv()`, so
+    /// repeated calls for the same variable don't allocate again.
+    getenv_strings: HashMap<String, MutPtr<u8>>,
 }
 
 fn malloc(env: &mut Environment, size: GuestUSize) -> MutVoidPtr {
@@ -42,103 +53,424 @@ fn atexit(
     0 // success
 }
 
-fn skip_whitespace(env: &mut Environment, s: ConstPtr<u8>) -> ConstPtr<u8> {
-    let mut start = s;
-    loop {
-        let c = env.mem.read(start);
-        // Rust's definition of whitespace excludes vertical tab, unlike C's
-        if c.is_ascii_whitespace() || c == b'\x0b' {
-            start += 1;
+/// C's definition of whitespace. Rust's excludes vertical tab.
+fn is_c_whitespace(c: u8) -> bool {
+    c.is_ascii_whitespace() || c == b'\x0b'
+}
+
+/// Result of [parse_integer].
+#[derive(Debug, PartialEq, Eq)]
+struct ParsedInteger {
+    negative: bool,
+    /// This is wider than any C type so that overflow can be detected.
+    /// Saturates at [u128::MAX].
+    magnitude: u128,
+    /// Number of bytes consumed, including leading whitespace. Zero if there
+    /// is no number.
+    len: usize,
+}
+
+/// The parsing shared by `strtol()` and friends. `base` is 0 for automatic
+/// detection, or 2 to 36.
+fn parse_integer(s: &[u8], base: u32) -> ParsedInteger {
+    assert!(
+        base == 0 || (2..=36).contains(&base),
+        "Invalid base {}",
+        base
+    );
+
+    let mut i = 0;
+    while matches!(s.get(i), Some(&c) if is_c_whitespace(c)) {
+        i += 1;
+    }
+    let negative = match s.get(i) {
+        Some(b'-') => {
+            i += 1;
+            true
+        }
+        Some(b'+') => {
+            i += 1;
+            false
+        }
+        _ => false,
+    };
+
+    // The 0x prefix only counts if it's followed by a hex digit, otherwise
+    // just the 0 is parsed.
+    let has_hex_prefix = matches!(s.get(i..i + 2), Some([b'0', b'x' | b'X']))
+        && matches!(s.get(i + 2), Some(c) if c.is_ascii_hexdigit());
+    let base = match base {
+        0 | 16 if has_hex_prefix => {
+            i += 2;
+            16
+        }
+        0 if s.get(i) == Some(&b'0') => 8,
+        0 => 10,
+        base => base,
+    };
+
+    let digits_start = i;
+    let mut magnitude: u128 = 0;
+    while let Some(digit) = s.get(i).and_then(|&c| (c as char).to_digit(base)) {
+        magnitude = magnitude
+            .saturating_mul(base.into())
+            .saturating_add(digit.into());
+        i += 1;
+    }
+
+    ParsedInteger {
+        negative,
+        magnitude,
+        len: if i == digits_start { 0 } else { i },
+    }
+}
+
+/// Clamp a parsed integer to a signed range, like `strtol()` does on overflow.
+/// The clamped value is returned as an [Err] if it overflowed.
+fn clamp_signed(parsed: &ParsedInteger, min: i64, max: i64) -> Result<i64, i64> {
+    if parsed.negative {
+        if parsed.magnitude > min.unsigned_abs().into() {
+            Err(min)
         } else {
-            break;
+            Ok((parsed.magnitude as i64).wrapping_neg())
         }
+    } else if parsed.magnitude > max as u128 {
+        Err(max)
+    } else {
+        Ok(parsed.magnitude as i64)
     }
-    start
 }
 
-fn atoi(env: &mut Environment, s: ConstPtr<u8>) -> i32 {
-    // atoi() doesn't work with a null-terminated string, instead it stops
-    // once it hits something that's not a digit, so we have to do some parsing
-    // ourselves.
-    let start = skip_whitespace(env, s);
-    let mut len = 0;
-    let maybe_sign = env.mem.read(start + len);
-    if maybe_sign == b'+' || maybe_sign == b'-' || maybe_sign.is_ascii_digit() {
-        len += 1;
+/// Clamp a parsed integer to an unsigned range, like `strtoul()` does on
+/// overflow. Note that a minus sign negates the value rather than clamping.
+/// The clamped value is returned as an [Err] if it overflowed.
+fn clamp_unsigned(parsed: &ParsedInteger, max: u64) -> Result<u64, u64> {
+    if parsed.magnitude > max.into() {
+        Err(max)
+    } else if parsed.negative {
+        Ok((parsed.magnitude as u64).wrapping_neg() & max)
+    } else {
+        Ok(parsed.magnitude as u64)
     }
-    while env.mem.read(start + len).is_ascii_digit() {
-        len += 1;
+}
+
+/// Parse a floating-point number like `strtod()`. Returns the value and the
+/// number of bytes consumed, including leading whitespace (zero if there is no
+/// number).
+fn parse_float(s: &[u8]) -> (f64, usize) {
+    // FIXME: no C99 hexfloat support
+    let mut i = 0;
+    while matches!(s.get(i), Some(&c) if is_c_whitespace(c)) {
+        i += 1;
+    }
+    let start = i;
+    if matches!(s.get(i), Some(b'+' | b'-')) {
+        i += 1;
     }
 
-    let s = std::str::from_utf8(env.mem.bytes_at(start, len)).unwrap();
-    // conveniently, overflow is undefined, so 0 is as valid a result as any
-    s.parse().unwrap_or(0)
-}
+    // Infinity and NaN are case-insensitive.
+    let rest = s[i..].to_ascii_lowercase();
+    for (word, len) in [("infinity", 8), ("inf", 3), ("nan", 3)] {
+        if rest.starts_with(word.as_bytes()) {
+            let number = std::str::from_utf8(&s[start..i + len]).unwrap();
+            return (number.parse().unwrap(), i + len);
+        }
+    }
 
-fn atof(env: &mut Environment, s: ConstPtr<u8>) -> f64 {
-    // atof() is similar to atoi().
-    // FIXME: no C99 hexfloat, INF, NAN support
-    let start = skip_whitespace(env, s);
-    let mut len = 0;
-    let maybe_sign = env.mem.read(start + len);
-    if maybe_sign == b'+' || maybe_sign == b'-' || maybe_sign.is_ascii_digit() {
-        len += 1;
-    }
-    while env.mem.read(start + len).is_ascii_digit() {
-        len += 1;
-    }
-    if env.mem.read(start + len) == b'.' {
-        len += 1;
-        while env.mem.read(start + len).is_ascii_digit() {
-            len += 1;
+    let count_digits = |i: &mut usize| {
+        let digits_start = *i;
+        while matches!(s.get(*i), Some(c) if c.is_ascii_digit()) {
+            *i += 1;
+        }
+        *i - digits_start
+    };
+    let mut digits = count_digits(&mut i);
+    if s.get(i) == Some(&b'.') {
+        i += 1;
+        digits += count_digits(&mut i);
+    }
+    if digits == 0 {
+        return (0.0, 0);
+    }
+    // The exponent is only consumed if it's complete.
+    if matches!(s.get(i), Some(b'e' | b'E')) {
+        let mut j = i + 1;
+        if matches!(s.get(j), Some(b'+' | b'-')) {
+            j += 1;
+        }
+        if count_digits(&mut j) > 0 {
+            i = j;
         }
     }
-    if env.mem.read(start + len).to_ascii_lowercase() == b'e' {
-        len += 1;
-        let maybe_sign = env.mem.read(start + len);
-        if maybe_sign == b'+' || maybe_sign == b'-' || maybe_sign.is_ascii_digit() {
-            len += 1;
+
+    let number = std::str::from_utf8(&s[start..i]).unwrap();
+    // Overflow results in infinity, which matches HUGE_VAL.
+    (number.parse().unwrap(), i)
+}
+
+/// Write the end pointer for `strtol()` and friends, if requested.
+fn write_endptr(env: &mut Environment, endptr: MutPtr<ConstPtr<u8>>, s: ConstPtr<u8>, len: usize) {
+    if !endptr.is_null() {
+        env.mem
+            .write(endptr, s + GuestUSize::try_from(len).unwrap());
+    }
+}
+
+/// Common part of `strtol()` and friends.
+fn strtol_inner(
+    env: &mut Environment,
+    s: ConstPtr<u8>,
+    endptr: MutPtr<ConstPtr<u8>>,
+    base: i32,
+) -> ParsedInteger {
+    let parsed = match u32::try_from(base) {
+        Ok(base) if base == 0 || (2..=36).contains(&base) => {
+            parse_integer(env.mem.cstr_at(s), base)
         }
-        while env.mem.read(start + len).is_ascii_digit() {
-            len += 1;
+        _ => {
+            set_errno(env, EINVAL);
+            ParsedInteger {
+                negative: false,
+                magnitude: 0,
+                len: 0,
+            }
         }
+    };
+    write_endptr(env, endptr, s, parsed.len);
+    parsed
+}
+
+/// Set `errno` if a value was clamped by [clamp_signed] or [clamp_unsigned].
+fn check_range<T>(env: &mut Environment, result: Result<T, T>) -> T {
+    result.unwrap_or_else(|clamped| {
+        set_errno(env, ERANGE);
+        clamped
+    })
+}
+
+fn strtol(env: &mut Environment, s: ConstPtr<u8>, endptr: MutPtr<ConstPtr<u8>>, base: i32) -> i32 {
+    let parsed = strtol_inner(env, s, endptr, base);
+    check_range(env, clamp_signed(&parsed, i32::MIN.into(), i32::MAX.into())) as i32
+}
+
+fn strtoul(env: &mut Environment, s: ConstPtr<u8>, endptr: MutPtr<ConstPtr<u8>>, base: i32) -> u32 {
+    let parsed = strtol_inner(env, s, endptr, base);
+    check_range(env, clamp_unsigned(&parsed, u32::MAX.into())) as u32
+}
+
+fn strtoll(env: &mut Environment, s: ConstPtr<u8>, endptr: MutPtr<ConstPtr<u8>>, base: i32) -> i64 {
+    let parsed = strtol_inner(env, s, endptr, base);
+    check_range(env, clamp_signed(&parsed, i64::MIN, i64::MAX))
+}
+
+fn strtoull(
+    env: &mut Environment,
+    s: ConstPtr<u8>,
+    endptr: MutPtr<ConstPtr<u8>>,
+    base: i32,
+) -> u64 {
+    let parsed = strtol_inner(env, s, endptr, base);
+    check_range(env, clamp_unsigned(&parsed, u64::MAX))
+}
+
+fn strtod(env: &mut Environment, s: ConstPtr<u8>, endptr: MutPtr<ConstPtr<u8>>) -> f64 {
+    let s_bytes = env.mem.cstr_at(s);
+    let (value, len) = parse_float(s_bytes);
+    // An infinite result from a finite number means it overflowed.
+    let overflowed = value.is_infinite() && s_bytes[..len].iter().any(u8::is_ascii_digit);
+    write_endptr(env, endptr, s, len);
+    if overflowed {
+        set_errno(env, ERANGE);
     }
+    value
+}
+
+fn strtof(env: &mut Environment, s: ConstPtr<u8>, endptr: MutPtr<ConstPtr<u8>>) -> f32 {
+    strtod(env, s, endptr) as f32
+}
 
-    let s = std::str::from_utf8(env.mem.bytes_at(start, len)).unwrap();
-    s.parse().unwrap_or(0.0)
+fn atoi(env: &mut Environment, s: ConstPtr<u8>) -> i32 {
+    // Overflow is undefined for atoi(), so clamping is as valid as anything.
+    strtol(env, s, Ptr::null(), 10)
+}
+
+fn atol(env: &mut Environment, s: ConstPtr<u8>) -> i32 {
+    // long is 32-bit
+    strtol(env, s, Ptr::null(), 10)
 }
 
-fn prng(state: u32) -> u32 {
-    // The state must not be zero for this algorithm to work. This also makes
-    // the default seed be 1, which matches the C standard.
-    let mut state: u32 = state.max(1);
-    // https://en.wikipedia.org/wiki/Xorshift#Example_implementation
-    // xorshift32 is not a good random number generator, but it is cute one!
-    // It's not like anyone expects the C stdlib `rand()` to be good.
-    state ^= state << 13;
-    state ^= state >> 17;
-    state ^= state << 5;
-    state
+fn atoll(env: &mut Environment, s: ConstPtr<u8>) -> i64 {
+    strtoll(env, s, Ptr::null(), 10)
+}
+
+fn atof(env: &mut Environment, s: ConstPtr<u8>) -> f64 {
+    strtod(env, s, Ptr::null())
+}
+
+/// The pseudo-random number generator behind all of the guest's random number
+/// functions. Each function family has its own stream. `rand()` and `random()`
+/// are reproducible by default, and `--rng-seed=` makes `arc4random()`
+/// reproducible too.
+#[derive(Default)]
+struct Prng {
+    state: u32,
+}
+impl Prng {
+    fn new(seed: u32) -> Prng {
+        Prng { state: seed }
+    }
+
+    fn next(&mut self) -> u32 {
+        // The state must not be zero for this algorithm to work. This also
+        // makes the default seed be 1, which matches the C standard.
+        let mut state: u32 = self.state.max(1);
+        // https://en.wikipedia.org/wiki/Xorshift#Example_implementation
+        // xorshift32 is not a good random number generator, but it is cute one!
+        // It's not like anyone expects the C stdlib `rand()` to be good.
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        self.state = state;
+        state
+    }
+
+    /// Uniformly distributed number in the range `0..upper_bound`, without
+    /// modulo bias.
+    fn next_below(&mut self, upper_bound: u32) -> u32 {
+        if upper_bound < 2 {
+            return 0;
+        }
+        // Values below this would make the low results more likely.
+        let min = upper_bound.wrapping_neg() % upper_bound;
+        loop {
+            let value = self.next();
+            if value >= min {
+                return value % upper_bound;
+            }
+        }
+    }
 }
 
 const RAND_MAX: i32 = i32::MAX;
 
 fn srand(env: &mut Environment, seed: u32) {
-    env.libc_state.stdlib.rand = seed;
+    env.libc_state.stdlib.rand = Prng::new(seed);
 }
 fn rand(env: &mut Environment) -> i32 {
-    env.libc_state.stdlib.rand = prng(env.libc_state.stdlib.rand);
-    (env.libc_state.stdlib.rand as i32) & RAND_MAX
+    (env.libc_state.stdlib.rand.next() as i32) & RAND_MAX
 }
 
 // BSD's "better" random number generator, with an implementation that is not
 // actually better.
 fn srandom(env: &mut Environment, seed: u32) {
-    env.libc_state.stdlib.random = seed;
+    env.libc_state.stdlib.random = Prng::new(seed);
 }
 fn random(env: &mut Environment) -> i32 {
-    env.libc_state.stdlib.random = prng(env.libc_state.stdlib.random);
-    (env.libc_state.stdlib.random as i32) & RAND_MAX
+    (env.libc_state.stdlib.random.next() as i32) & RAND_MAX
+}
+
+/// Get the `arc4random()` stream, seeding it if necessary. Unlike `rand()` and
+/// `random()`, apps expect this to be different every run, so the seed comes
+/// from the host unless the user provided one with `--rng-seed=`.
+fn arc4random_prng(env: &mut Environment) -> &mut Prng {
+    arc4random_prng_for(&mut env.libc_state.stdlib, &env.options)
+}
+fn arc4random_prng_for<'a>(state: &'a mut State, options: &Options) -> &'a mut Prng {
+    let seed = options.rng_seed;
+    state.arc4random.get_or_insert_with(|| {
+        Prng::new(seed.unwrap_or_else(|| {
+            use std::hash::{BuildHasher, Hasher};
+            // The standard library's hasher is randomly keyed, which is a
+            // handy source of entropy that doesn't need another dependency.
+            std::collections::hash_map::RandomState::new()
+                .build_hasher()
+                .finish() as u32
+        }))
+    })
+}
+
+fn arc4random(env: &mut Environment) -> u32 {
+    arc4random_prng(env).next()
+}
+fn arc4random_uniform(env: &mut Environment, upper_bound: u32) -> u32 {
+    arc4random_prng(env).next_below(upper_bound)
+}
+
+/// Get the environment variables, initializing them with the defaults if
+/// necessary. These are based on what an app would see on iPhone OS.
+fn env_vars(env: &mut Environment) -> &mut HashMap<String, String> {
+    let home = env.fs.home_directory().as_str();
+    env.libc_state.stdlib.env_vars.get_or_insert_with(|| {
+        HashMap::from([
+            ("HOME".to_string(), home.to_string()),
+            ("CFFIXED_USER_HOME".to_string(), home.to_string()),
+            ("TMPDIR".to_string(), format!("{}/tmp/", home)),
+            ("USER".to_string(), "mobile".to_string()),
+            ("LOGNAME".to_string(), "mobile".to_string()),
+            ("SHELL".to_string(), "/bin/sh".to_string()),
+            (
+                "PATH".to_string(),
+                "/usr/bin:/bin:/usr/sbin:/sbin".to_string(),
+            ),
+        ])
+    })
+}
+
+/// Forget the guest copy of a variable's value after it changes. The old copy
+/// isn't freed, because the app might still be using it. Apple's libc has
+/// the same policy.
+fn invalidate_getenv_string(env: &mut Environment, name: &str) {
+    env.libc_state.stdlib.getenv_strings.remove(name);
+}
+
+fn getenv(env: &mut Environment, name: ConstPtr<u8>) -> MutPtr<u8> {
+    let name = env.mem.cstr_at_utf8(name).to_string();
+    let Some(value) = env_vars(env).get(&name).cloned() else {
+        log_dbg!("getenv({:?}) => NULL", name);
+        return Ptr::null();
+    };
+    log_dbg!("getenv({:?}) => {:?}", name, value);
+    if let Some(&ptr) = env.libc_state.stdlib.getenv_strings.get(&name) {
+        return ptr;
+    }
+    let ptr = env.mem.alloc_and_write_cstr(value.as_bytes());
+    env.libc_state.stdlib.getenv_strings.insert(name, ptr);
+    ptr
+}
+
+/// Check a variable name is valid for `setenv()` and `unsetenv()`.
+fn is_valid_env_var_name(name: &str) -> bool {
+    !name.is_empty() && !name.contains('=')
+}
+
+fn setenv(env: &mut Environment, name: ConstPtr<u8>, value: ConstPtr<u8>, overwrite: i32) -> i32 {
+    let name = env.mem.cstr_at_utf8(name).to_string();
+    let value = env.mem.cstr_at_utf8(value).to_string();
+    log_dbg!("setenv({:?}, {:?}, {})", name, value, overwrite);
+    if !is_valid_env_var_name(&name) {
+        set_errno(env, EINVAL);
+        log!("Warning: setenv() with invalid name {:?}", name);
+        return -1;
+    }
+    if overwrite == 0 && env_vars(env).contains_key(&name) {
+        return 0; // success
+    }
+    invalidate_getenv_string(env, &name);
+    env_vars(env).insert(name, value);
+    0 // success
+}
+
+fn unsetenv(env: &mut Environment, name: ConstPtr<u8>) -> i32 {
+    let name = env.mem.cstr_at_utf8(name).to_string();
+    log_dbg!("unsetenv({:?})", name);
+    if !is_valid_env_var_name(&name) {
+        set_errno(env, EINVAL);
+        log!("Warning: unsetenv() with invalid name {:?}", name);
+        return -1;
+    }
+    invalidate_getenv_string(env, &name);
+    env_vars(env).remove(&name);
+    0 // success
 }
 
 pub const FUNCTIONS: FunctionExports = &[
@@ -146,10 +478,153 @@ pub const FUNCTIONS: FunctionExports = &[
     export_c_func!(calloc(_, _)),
     export_c_func!(free(_)),
     export_c_func!(atexit(_)),
+    export_c_func!(strtol(_, _, _)),
+    export_c_func!(strtoul(_, _, _)),
+    export_c_func!(strtoll(_, _, _)),
+    export_c_func!(strtoull(_, _, _)),
+    export_c_func!(strtod(_, _)),
+    export_c_func!(strtof(_, _)),
     export_c_func!(atoi(_)),
+    export_c_func!(atol(_)),
+    export_c_func!(atoll(_)),
     export_c_func!(atof(_)),
     export_c_func!(srand(_)),
     export_c_func!(rand()),
     export_c_func!(srandom(_)),
     export_c_func!(random()),
+    export_c_func!(arc4random()),
+    export_c_func!(arc4random_uniform(_)),
+    export_c_func!(getenv(_)),
+    export_c_func!(setenv(_, _, _)),
+    export_c_func!(unsetenv(_)),
 ];
+
+#[cfg(test)]
+mod tests {
+    use super::{clamp_signed, clamp_unsigned, parse_float, parse_integer, Prng, State};
+
+    fn strtol(s: &str, base: u32) -> (i32, usize) {
+        let parsed = parse_integer(s.as_bytes(), base);
+        let value = clamp_signed(&parsed, i32::MIN.into(), i32::MAX.into());
+        let value = value.unwrap_or_else(|clamped| clamped) as i32;
+        (value, parsed.len)
+    }
+    fn strtoul(s: &str, base: u32) -> (u32, usize) {
+        let parsed = parse_integer(s.as_bytes(), base);
+        let value = clamp_unsigned(&parsed, u32::MAX.into());
+        let value = value.unwrap_or_else(|clamped| clamped) as u32;
+        (value, parsed.len)
+    }
+
+    #[test]
+    fn integers() {
+        assert_eq!(strtol("123", 10), (123, 3));
+        assert_eq!(strtol("-42", 10), (-42, 3));
+        assert_eq!(strtol(" \t\x0b\n+7", 10), (7, 6));
+        // Trailing garbage is not consumed
+        assert_eq!(strtol("99 bottles", 10), (99, 2));
+        assert_eq!(strtol("12abc", 10), (12, 2));
+        // No number at all
+        assert_eq!(strtol("  abc", 10), (0, 0));
+        assert_eq!(strtol("-", 10), (0, 0));
+        assert_eq!(strtol("", 0), (0, 0));
+    }
+
+    #[test]
+    fn bases() {
+        assert_eq!(strtol("0x1F", 16), (31, 4));
+        assert_eq!(strtol("1f", 16), (31, 2));
+        assert_eq!(strtol("0x1F", 0), (31, 4));
+        assert_eq!(strtol("-0XfF", 0), (-255, 5));
+        assert_eq!(strtol("0755", 0), (0o755, 4));
+        assert_eq!(strtol("0", 0), (0, 1));
+        assert_eq!(strtol("42", 0), (42, 2));
+        // A 0x prefix without digits is just a zero
+        assert_eq!(strtol("0xg", 16), (0, 1));
+        assert_eq!(strtol("0x", 0), (0, 1));
+        // In base 10 an x is garbage
+        assert_eq!(strtol("0x10", 10), (0, 1));
+        assert_eq!(strtol("1010", 2), (10, 4));
+        assert_eq!(strtol("zz", 36), (1295, 2));
+    }
+
+    #[test]
+    fn overflow() {
+        assert_eq!(strtol("2147483647", 10), (i32::MAX, 10));
+        assert_eq!(strtol("2147483648", 10), (i32::MAX, 10));
+        assert_eq!(strtol("-2147483648", 10), (i32::MIN, 11));
+        assert_eq!(strtol("-2147483649", 10), (i32::MIN, 11));
+        assert_eq!(strtol("99999999999999999999999", 10), (i32::MAX, 23));
+        assert_eq!(strtoul("4294967295", 10), (u32::MAX, 10));
+        assert_eq!(strtoul("4294967296", 10), (u32::MAX, 10));
+        assert_eq!(strtoul("0xffffffff", 0), (u32::MAX, 10));
+        // The minus sign negates the value for the unsigned variants
+        assert_eq!(strtoul("-1", 10), (u32::MAX, 2));
+        let parsed = parse_integer(b"-9223372036854775808", 10);
+        assert_eq!(clamp_signed(&parsed, i64::MIN, i64::MAX), Ok(i64::MIN));
+        let parsed = parse_integer(b"9223372036854775808", 10);
+        assert_eq!(clamp_signed(&parsed, i64::MIN, i64::MAX), Err(i64::MAX));
+        let parsed = parse_integer(b"-2147483649", 10);
+        assert_eq!(
+            clamp_signed(&parsed, i32::MIN.into(), i32::MAX.into()),
+            Err(i32::MIN.into())
+        );
+        let parsed = parse_integer(b"18446744073709551616", 10);
+        assert_eq!(clamp_unsigned(&parsed, u64::MAX), Err(u64::MAX));
+    }
+
+    #[test]
+    fn floats() {
+        assert_eq!(parse_float(b"1.5"), (1.5, 3));
+        assert_eq!(parse_float(b"  -2.5e3x"), (-2500.0, 8));
+        assert_eq!(parse_float(b".5"), (0.5, 2));
+        assert_eq!(parse_float(b"5."), (5.0, 2));
+        // An incomplete exponent isn't consumed
+        assert_eq!(parse_float(b"1e+"), (1.0, 1));
+        assert_eq!(parse_float(b"abc"), (0.0, 0));
+        assert_eq!(parse_float(b"."), (0.0, 0));
+        assert_eq!(parse_float(b"-INF"), (f64::NEG_INFINITY, 4));
+        assert_eq!(parse_float(b"Infinity!"), (f64::INFINITY, 8));
+        assert_eq!(parse_float(b"1e999"), (f64::INFINITY, 5));
+        let (value, len) = parse_float(b"nan");
+        assert!(value.is_nan() && len == 3);
+    }
+
+    #[test]
+    fn seeded_rng_is_repeatable() {
+        fn run(rng_seed: Option<u32>) -> Vec<u32> {
+            // The state a fresh Environment would have.
+            let mut state = State::default();
+            let options = Options {
+                rng_seed,
+                ..Default::default()
+            };
+
+            let mut values = Vec::new();
+            for i in 0..100 {
+                values.push(state.rand.next());
+                values.push(state.random.next());
+                let arc4random = arc4random_prng_for(&mut state, &options);
+                values.push(arc4random.next());
+                values.push(arc4random.next_below(i));
+            }
+            values
+        }
+
+        assert_eq!(run(Some(1234)), run(Some(1234)));
+        assert_ne!(run(Some(1234)), run(Some(5678)));
+        // Without a seed, arc4random() differs between runs.
+        assert_ne!(run(None), run(None));
+    }
+
+    #[test]
+    fn rng_uniform_range() {
+        let mut prng = Prng::new(42);
+        assert_eq!(prng.next_below(0), 0);
+        assert_eq!(prng.next_below(1), 0);
+        for _ in 0..1000 {
+            assert!(prng.next_below(10) < 10);
+            assert!(prng.next_below(u32::MAX) < u32::MAX);
+        }
+    }
+}
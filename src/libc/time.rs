@@ -3,35 +3,220 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at https://mozilla.org/MPL/2.0/.
  */
-//! `time.h`
+//! `time.h` and `sys/time.h`
+//!
+//! All the time functions in touchHLE, including `mach_absolute_time()` and
+//! `NSDate`, are derived from the single clock provided by
+//! [monotonic_since_startup] and [realtime_since_unix_epoch], so that they
+//! always agree with each other.
 
 use crate::dyld::{export_c_func, FunctionExports};
-use crate::mem::MutPtr;
+use crate::libc::errno::{set_errno, EINVAL};
+use crate::mem::{MutPtr, MutVoidPtr, SafeRead};
 use crate::Environment;
-use std::time::SystemTime;
+use std::time::{Duration, Instant, SystemTime};
 
 #[derive(Default)]
 pub struct State {
     y2k38_warned: bool,
+    /// The host's wall-clock time when the emulator started. This is only read
+    /// once, so that later adjustments to the host clock don't affect the app.
+    startup_since_unix_epoch: Option<Duration>,
 }
 
 #[allow(non_camel_case_types)]
 type time_t = i32;
+#[allow(non_camel_case_types)]
+type suseconds_t = i32;
+#[allow(non_camel_case_types)]
+type clockid_t = u32;
 
-fn time(env: &mut Environment, out: MutPtr<time_t>) -> time_t {
-    let time64 = SystemTime::now()
-        .duration_since(SystemTime::UNIX_EPOCH)
-        .unwrap()
-        .as_secs();
-    let time = time64 as time_t;
-    if !env.libc_state.time.y2k38_warned && time64 != time as u64 {
+const CLOCK_REALTIME: clockid_t = 0;
+const CLOCK_MONOTONIC_RAW: clockid_t = 4;
+const CLOCK_MONOTONIC: clockid_t = 6;
+
+#[allow(non_camel_case_types)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(C, packed)]
+struct timeval {
+    tv_sec: time_t,
+    tv_usec: suseconds_t,
+}
+unsafe impl SafeRead for timeval {}
+
+#[allow(non_camel_case_types)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(C, packed)]
+struct timespec {
+    tv_sec: time_t,
+    tv_nsec: i32,
+}
+unsafe impl SafeRead for timespec {}
+
+#[allow(non_camel_case_types)]
+#[repr(C, packed)]
+struct timezone {
+    tz_minuteswest: i32,
+    tz_dsttime: i32,
+}
+unsafe impl SafeRead for timezone {}
+
+/// Time elapsed since the emulator started, according to a monotonic clock.
+pub fn monotonic_since_startup(env: &Environment) -> Duration {
+    monotonic_since(env.startup_time)
+}
+
+fn monotonic_since(startup_time: Instant) -> Duration {
+    Instant::now().duration_since(startup_time)
+}
+
+/// Current wall-clock time. This advances with [monotonic_since_startup].
+pub fn realtime_since_unix_epoch(env: &mut Environment) -> Duration {
+    let since_startup = monotonic_since_startup(env);
+    realtime_from_monotonic(
+        &mut env.libc_state.time.startup_since_unix_epoch,
+        since_startup,
+    )
+}
+
+fn realtime_from_monotonic(
+    startup_since_unix_epoch: &mut Option<Duration>,
+    since_startup: Duration,
+) -> Duration {
+    let startup = *startup_since_unix_epoch.get_or_insert_with(|| {
+        SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .saturating_sub(since_startup)
+    });
+    startup + since_startup
+}
+
+/// [realtime_since_unix_epoch], but with a warning if it doesn't fit in a
+/// [time_t].
+fn realtime_checked(env: &mut Environment) -> Duration {
+    let now = realtime_since_unix_epoch(env);
+    if !env.libc_state.time.y2k38_warned && now.as_secs() != now.as_secs() as time_t as u64 {
         env.libc_state.time.y2k38_warned = true;
         log!("Warning: system clock is beyond Y2K38 and might confuse the app");
     }
+    now
+}
+
+fn timeval_from_duration(duration: Duration) -> timeval {
+    timeval {
+        tv_sec: duration.as_secs() as time_t,
+        tv_usec: duration.subsec_micros() as suseconds_t,
+    }
+}
+
+fn timespec_from_duration(duration: Duration) -> timespec {
+    timespec {
+        tv_sec: duration.as_secs() as time_t,
+        tv_nsec: duration.subsec_nanos() as i32,
+    }
+}
+
+fn time(env: &mut Environment, out: MutPtr<time_t>) -> time_t {
+    let time = realtime_checked(env).as_secs() as time_t;
     if !out.is_null() {
         env.mem.write(out, time);
     }
     time
 }
 
-pub const FUNCTIONS: FunctionExports = &[export_c_func!(time(_))];
+fn gettimeofday(env: &mut Environment, tv: MutPtr<timeval>, tz: MutVoidPtr) -> i32 {
+    if !tv.is_null() {
+        let now = realtime_checked(env);
+        env.mem.write(tv, timeval_from_duration(now));
+    }
+    if !tz.is_null() {
+        // The time zone argument is obsolete, and always UTC on the iPhone.
+        env.mem.write(
+            tz.cast(),
+            timezone {
+                tz_minuteswest: 0,
+                tz_dsttime: 0,
+            },
+        );
+    }
+    0 // success
+}
+
+fn clock_gettime(env: &mut Environment, clock_id: clockid_t, tp: MutPtr<timespec>) -> i32 {
+    let now = match clock_id {
+        CLOCK_REALTIME => realtime_checked(env),
+        CLOCK_MONOTONIC | CLOCK_MONOTONIC_RAW => monotonic_since_startup(env),
+        _ => {
+            log!(
+                "Warning: clock_gettime() with unsupported clock {}",
+                clock_id
+            );
+            set_errno(env, EINVAL);
+            return -1;
+        }
+    };
+    env.mem.write(tp, timespec_from_duration(now));
+    0 // success
+}
+
+pub const FUNCTIONS: FunctionExports = &[
+    export_c_func!(time(_)),
+    export_c_func!(gettimeofday(_, _)),
+    export_c_func!(clock_gettime(_, _)),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn struct_conversion() {
+        let duration = Duration::new(1234, 567_891_234);
+        assert_eq!(
+            timeval_from_duration(duration),
+            timeval {
+                tv_sec: 1234,
+                tv_usec: 567_891,
+            }
+        );
+        assert_eq!(
+            timespec_from_duration(duration),
+            timespec {
+                tv_sec: 1234,
+                tv_nsec: 567_891_234,
+            }
+        );
+    }
+
+    #[test]
+    fn clocks_track_together() {
+        let startup_time = Instant::now();
+        let mut startup_since_unix_epoch = None;
+        let mut previous = Duration::ZERO;
+        for _ in 0..1000 {
+            let now = monotonic_since(startup_time);
+            assert!(now >= previous);
+            previous = now;
+
+            // mach_absolute_time() uses a timebase of 1ns, so its ticks must
+            // be exactly what clock_gettime(CLOCK_MONOTONIC) reports.
+            let ticks = crate::libc::mach_time::ticks_from_duration(now);
+            let timespec { tv_sec, tv_nsec } = timespec_from_duration(now);
+            assert_eq!(ticks, tv_sec as u64 * 1_000_000_000 + tv_nsec as u64);
+
+            // The wall-clock time advances by exactly the same amount.
+            let realtime = realtime_from_monotonic(&mut startup_since_unix_epoch, now);
+            assert_eq!(realtime - now, startup_since_unix_epoch.unwrap());
+        }
+
+        // ...and started out as the host's wall-clock time.
+        let host_now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap();
+        let now = monotonic_since(startup_time);
+        let realtime = realtime_from_monotonic(&mut startup_since_unix_epoch, now);
+        let tolerance = Duration::from_secs(1);
+        assert!(realtime < host_now + tolerance && host_now < realtime + tolerance);
+    }
+}
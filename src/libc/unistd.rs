@@ -0,0 +1,34 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! `unistd.h`
+
+use crate::dyld::{export_c_func, FunctionExports};
+use crate::libc::pthread::thread::test_cancel;
+use crate::Environment;
+use std::time::Duration;
+
+#[allow(non_camel_case_types)]
+type useconds_t = u32;
+
+fn sleep(env: &mut Environment, seconds: u32) -> u32 {
+    // Sleeping is a cancellation point.
+    if test_cancel(env) {
+        return seconds;
+    }
+    env.sleep(Duration::from_secs(seconds.into()));
+    0 // full duration slept
+}
+
+fn usleep(env: &mut Environment, useconds: useconds_t) -> i32 {
+    // Sleeping is a cancellation point.
+    if test_cancel(env) {
+        return 0;
+    }
+    env.sleep(Duration::from_micros(useconds.into()));
+    0 // success
+}
+
+pub const FUNCTIONS: FunctionExports = &[export_c_func!(sleep(_)), export_c_func!(usleep(_))];
@@ -21,7 +21,7 @@
 use crate::fs::{Fs, GuestPath};
 use crate::mem::{Mem, Ptr};
 use mach_object::{DyLib, LoadCommand, MachCommand, OFile, Symbol, SymbolIter};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::io::{Cursor, Seek, SeekFrom};
 
 #[derive(Debug)]
@@ -35,9 +35,25 @@ pub struct MachO {
     /// Symbols exported by the binary. This is a hashmap so the dynamic linker
     /// can look things up quickly.
     pub exported_symbols: HashMap<String, u32>,
+    /// Addresses and names of all symbols defined by the binary, including
+    /// non-exported ones, sorted by address. This is used to symbolicate
+    /// addresses for debugging.
+    pub defined_symbols: Vec<(u32, String)>,
     /// List of addresses and names of external relocations for the dynamic
     /// linker to resolve.
     pub external_relocations: Vec<(u32, String)>,
+    /// List of addresses of pointers within the binary that refer to the
+    /// binary itself (internal relocations), and therefore must be rebased by
+    /// the dynamic linker if the binary is not loaded at its preferred address.
+    pub local_relocations: Vec<u32>,
+    /// Names of symbols that are imported weakly, i.e. which may legitimately
+    /// be missing at runtime. The dynamic linker links these to null if they
+    /// can't be found.
+    pub weak_imports: HashSet<String>,
+    /// Difference between the address the binary was loaded at and its
+    /// preferred address. Binaries are currently always loaded at their
+    /// preferred address, so this is always zero for now.
+    pub load_slide: u32,
 }
 
 #[derive(Debug)]
@@ -84,6 +100,30 @@ fn get_sym_by_idx<'a>(
     iter.next()
 }
 
+/// `N_WEAK_REF` from `/usr/include/mach-o/nlist.h`: the flag in `n_desc` for a
+/// weakly-imported undefined symbol.
+const N_WEAK_REF: u16 = 0x0040;
+
+/// Check whether a symbol has the [N_WEAK_REF] flag. The mach_object crate
+/// doesn't expose `n_desc`, so this reads the `nlist` entry directly.
+fn sym_is_weak_ref(
+    bytes: &[u8],
+    idx: u32,
+    (symoff, nsyms, _stroff, _strsize): (u32, u32, u32, u32),
+    is_bigend: bool,
+) -> bool {
+    assert!(!is_bigend);
+    if idx >= nsyms {
+        return false;
+    }
+    // struct nlist { n_strx: u32, n_type: u8, n_sect: u8, n_desc: u16, ... }
+    let desc_offset = (symoff + idx * 12 + 6) as usize;
+    let Some(desc) = bytes.get(desc_offset..desc_offset + 2) else {
+        return false;
+    };
+    u16::from_le_bytes(desc.try_into().unwrap()) & N_WEAK_REF != 0
+}
+
 /// Parsed relocation entry
 #[derive(Debug)]
 enum Reloc {
@@ -111,7 +151,38 @@ enum Reloc {
         type_: u32,
     },
 }
+
+/// `ARM_RELOC_VANILLA` from `/usr/include/mach-o/arm/reloc.h`, which has the
+/// same value as `GENERIC_RELOC_VANILLA`.
+const ARM_RELOC_VANILLA: u32 = 0;
+/// `ARM_RELOC_PB_LA_PTR` from `/usr/include/mach-o/arm/reloc.h`. Used for
+/// prebound lazy pointers.
+const ARM_RELOC_PB_LA_PTR: u32 = 4;
+
 impl Reloc {
+    /// For a local relocation entry, get the address of the pointer that needs
+    /// rebasing. `reloc_base` is the address that relocation addresses are
+    /// relative to, i.e. the address of the first segment.
+    fn local_reloc_addr(&self, reloc_base: u32) -> Option<u32> {
+        match *self {
+            Reloc::Local {
+                addr,
+                is_pc_relative: false,
+                size: 4,
+                type_: ARM_RELOC_VANILLA,
+                ..
+            } => Some(reloc_base + addr),
+            Reloc::Scattered {
+                offset,
+                is_pc_relative: false,
+                size: 4,
+                type_: ARM_RELOC_VANILLA | ARM_RELOC_PB_LA_PTR,
+                ..
+            } => Some(reloc_base + offset),
+            _ => None,
+        }
+    }
+
     fn parse(is_bigend: bool, entry: [u8; 8]) -> Self {
         assert!(!is_bigend);
 
@@ -205,13 +276,17 @@ impl MachO {
 
         // Info used while parsing file
         let mut all_sections = Vec::new();
+        let mut first_segment_addr: Option<u32> = None;
         let mut sym_tab_info: Option<(u32, u32, u32, u32)> = None;
 
         // Info used for the result
         let mut dynamic_libraries = Vec::new();
         let mut exported_symbols = HashMap::new();
+        let mut defined_symbols = Vec::new();
         let mut indirect_undef_symbols: Vec<Option<String>> = Vec::new();
         let mut external_relocations: Vec<(u32, String)> = Vec::new();
+        let mut local_relocations: Vec<u32> = Vec::new();
+        let mut weak_imports = HashSet::new();
 
         for MachCommand(command, _size) in commands {
             match command {
@@ -228,6 +303,8 @@ impl MachO {
                     let vmsize: u32 = vmsize.try_into().unwrap();
                     let filesize: u32 = filesize.try_into().unwrap();
 
+                    first_segment_addr.get_or_insert(vmaddr);
+
                     let load_me = match &*segname {
                         // Special linker data section, not meant to be loaded.
                         "__LINKEDIT" => false,
@@ -287,13 +364,16 @@ impl MachO {
                             }
                             if let Symbol::Defined {
                                 name: Some(name),
-                                external: true,
+                                external,
                                 entry,
                                 ..
                             } = symbol
                             {
                                 let entry: u32 = entry.try_into().unwrap();
-                                exported_symbols.insert(name.to_string(), entry);
+                                if external {
+                                    exported_symbols.insert(name.to_string(), entry);
+                                }
+                                defined_symbols.push((entry, name.to_string()));
                             };
                         }
                     }
@@ -303,6 +383,8 @@ impl MachO {
                     nindirectsyms,
                     extreloff,
                     nextrel,
+                    locreloff,
+                    nlocrel,
                     ..
                 } => {
                     let indirectsyms =
@@ -321,7 +403,12 @@ impl MachO {
                         );
                         indirect_undef_symbols.push(match sym {
                             // apparently used in apps?
-                            Some(Symbol::Undefined { name: Some(n), .. }) => Some(String::from(n)),
+                            Some(Symbol::Undefined { name: Some(n), .. }) => {
+                                if sym_is_weak_ref(bytes, idx, sym_tab_info.unwrap(), is_bigend) {
+                                    weak_imports.insert(String::from(n));
+                                }
+                                Some(String::from(n))
+                            }
                             // apparently used in libraries?
                             Some(Symbol::Prebound { name: Some(n), .. }) => Some(String::from(n)),
                             _ => None,
@@ -354,8 +441,25 @@ impl MachO {
                         let Some(Symbol::Undefined { name: Some(n), .. }) = sym else {
                             continue;
                         };
+                        if sym_is_weak_ref(bytes, sym_idx, sym_tab_info.unwrap(), is_bigend) {
+                            weak_imports.insert(String::from(n));
+                        }
                         external_relocations.push((addr, String::from(n)));
                     }
+
+                    // Relocation addresses are relative to the first segment.
+                    // (This isn't true if MH_SPLIT_SEGS is set, but that
+                    // shouldn't be the case for iPhone OS binaries.)
+                    let reloc_base = first_segment_addr.unwrap();
+                    let locrels = &bytes[locreloff as usize..][..nlocrel as usize * 8];
+                    for entry in locrels.chunks(8) {
+                        let reloc = Reloc::parse(is_bigend, entry.try_into().unwrap());
+                        let Some(addr) = reloc.local_reloc_addr(reloc_base) else {
+                            log!("Warning: Unhandled locrel: {:?}", reloc);
+                            continue;
+                        };
+                        local_relocations.push(addr);
+                    }
                 }
                 LoadCommand::EncryptionInfo { id, .. } => {
                     if id != 0 {
@@ -376,6 +480,8 @@ impl MachO {
             }
         }
 
+        defined_symbols.sort_by_key(|&(addr, _)| addr);
+
         let sections = all_sections
             .iter()
             .map(|section| {
@@ -419,7 +525,11 @@ impl MachO {
             dynamic_libraries,
             sections,
             exported_symbols,
+            defined_symbols,
             external_relocations,
+            local_relocations,
+            weak_imports,
+            load_slide: 0,
         })
     }
 
@@ -443,4 +553,98 @@ impl MachO {
     pub fn get_section(&self, name: &str) -> Option<&Section> {
         self.sections.iter().find(|s| s.name == name)
     }
+
+    /// Find the nearest symbol at or before an address, returning its name and
+    /// the offset of the address from it. Returns [None] if the address isn't
+    /// within one of the binary's sections. The Thumb bit should be cleared.
+    pub fn symbolicate(&self, addr: u32) -> Option<(&str, u32)> {
+        if !self
+            .sections
+            .iter()
+            .any(|s| (s.addr..(s.addr + s.size)).contains(&addr))
+        {
+            return None;
+        }
+        let idx = self
+            .defined_symbols
+            .partition_point(|&(sym_addr, _)| sym_addr <= addr);
+        let &(sym_addr, ref name) = self.defined_symbols.get(idx.checked_sub(1)?)?;
+        Some((name, addr - sym_addr))
+    }
+}
+
+#[cfg(test)]
+mod reloc_tests {
+    use super::{sym_is_weak_ref, Reloc, N_WEAK_REF};
+    use crate::dyld::rebase_pointers;
+    use crate::mem::{Mem, MutPtr};
+
+    fn entry(word1: u32, word2: u32) -> [u8; 8] {
+        let mut entry = [0u8; 8];
+        entry[..4].copy_from_slice(&word1.to_le_bytes());
+        entry[4..].copy_from_slice(&word2.to_le_bytes());
+        entry
+    }
+
+    #[test]
+    fn local_relocations() {
+        // Non-scattered, section 2, 4 bytes (log2: 2), vanilla
+        let reloc = Reloc::parse(false, entry(0x3000, 2 | (2 << 25)));
+        assert_eq!(reloc.local_reloc_addr(0x1000), Some(0x4000));
+
+        // Scattered, 4 bytes (log2: 2), vanilla
+        let reloc = Reloc::parse(false, entry(0x80000000 | (2 << 28) | 0x10, 0x5678));
+        assert_eq!(reloc.local_reloc_addr(0x1000), Some(0x1010));
+
+        // Scattered, 4 bytes (log2: 2), prebound lazy pointer
+        let reloc = Reloc::parse(false, entry(0x80000000 | (2 << 28) | (4 << 24) | 0x20, 0));
+        assert_eq!(reloc.local_reloc_addr(0x1000), Some(0x1020));
+
+        // PC-relative relocations don't need rebasing
+        let reloc = Reloc::parse(false, entry(0x3000, 2 | (1 << 24) | (2 << 25)));
+        assert_eq!(reloc.local_reloc_addr(0x1000), None);
+
+        // External relocations aren't local relocations
+        let reloc = Reloc::parse(false, entry(0x3000, 2 | (2 << 25) | (1 << 27)));
+        assert_eq!(reloc.local_reloc_addr(0x1000), None);
+    }
+
+    #[test]
+    fn local_relocations_with_slide() {
+        let mut mem = Mem::new();
+        let slide = 0x100;
+
+        // A table of two pointers into the binary, which was meant to be at
+        // `table - slide`, but was loaded at `table`.
+        let table: MutPtr<u32> = mem.alloc(8).cast();
+        let reloc_base = table.to_bits() - slide;
+        mem.write(table, reloc_base + 0x40);
+        mem.write(table + 1, reloc_base + 0x80);
+
+        // Non-scattered vanilla for the first, prebound lazy pointer for the
+        // second.
+        let vanilla = Reloc::parse(false, entry(0, 1 | (2 << 25)));
+        let pb_la_ptr = Reloc::parse(false, entry(0x80000000 | (2 << 28) | (4 << 24) | 4, 0));
+        let addrs = [vanilla, pb_la_ptr].map(|reloc| reloc.local_reloc_addr(reloc_base).unwrap());
+
+        rebase_pointers(&mut mem, &addrs, slide);
+        assert_eq!(mem.read(table), table.to_bits() + 0x40);
+        assert_eq!(mem.read(table + 1), table.to_bits() + 0x80);
+    }
+
+    #[test]
+    fn weak_refs() {
+        // Two nlist entries at offset 4: one strong, one weak.
+        let mut bytes = vec![0u8; 4];
+        for desc in [0u16, N_WEAK_REF] {
+            bytes.extend_from_slice(&0u32.to_le_bytes()); // n_strx
+            bytes.extend_from_slice(&[1, 0]); // n_type (N_EXT), n_sect
+            bytes.extend_from_slice(&desc.to_le_bytes());
+            bytes.extend_from_slice(&0u32.to_le_bytes()); // n_value
+        }
+        let sym_tab_info = (4, 2, 0, 0);
+        assert!(!sym_is_weak_ref(&bytes, 0, sym_tab_info, false));
+        assert!(sym_is_weak_ref(&bytes, 1, sym_tab_info, false));
+        assert!(!sym_is_weak_ref(&bytes, 2, sym_tab_info, false));
+    }
 }
@@ -30,7 +30,9 @@ mod dyld;
 mod font;
 mod frameworks;
 mod fs;
+mod http;
 mod image;
+mod input_recording;
 mod libc;
 mod licenses;
 mod mach_o;
@@ -44,6 +46,9 @@ use std::path::PathBuf;
 /// Current version. See `build.rs` for how this is generated.
 const VERSION: &str = include_str!(concat!(env!("OUT_DIR"), "/version.txt"));
 
+/// Where the report is saved if the emulator crashes, see `--no-crash-report`.
+const CRASH_REPORT_PATH: &str = "touchHLE_crash_report.txt";
+
 const USAGE: &str = "\
 Usage:
     touchHLE path/to/some.app
@@ -66,6 +71,25 @@ View options:
 
         This is a natural number that is at least 1.
 
+    --scale-hack-mode=...
+        Set which OpenGL ES calls the scale hack is applied to. The scale hack
+        works by detecting calls that use the size of the screen, and
+        replacing that size with the scaled-up size.
+
+        The default is 'full', which applies the scale hack to the main
+        renderbuffer's size, the viewport and the scissor rectangle.
+
+        Some apps set a viewport or scissor rectangle that happens to be the
+        size of the screen for some other purpose, e.g. when rendering to a
+        texture, and will look wrong in that mode. 'renderbuffer-only' applies
+        the scale hack only to the renderbuffer's size, which avoids this
+        problem, but means the app will only draw to part of the renderbuffer
+        unless it derives its viewport from the renderbuffer's size.
+
+        'off' disables the scale hack for OpenGL ES entirely, but the window
+        will still be scaled up. This may be useful for 2D games with sharp
+        pixel art, which can look worse at a higher internal resolution.
+
 Game controller options:
     --deadzone=...
         Configures the size of the \"dead zone\" for analog stick inputs.
@@ -109,11 +133,21 @@ Game controller options:
         This is a floating-point (decimal) number of degrees, without a degree
         symbol. It may be negative.
 
+Network options:
+    --allow-network-access
+        Let the app make HTTP requests (e.g. for leaderboards or downloadable
+        content) using NSURLConnection. Without this option, all requests fail
+        as if the device were offline. Only plain HTTP is supported, not HTTPS.
+
+        This is off by default because the app could send information about
+        you or your computer to anywhere on the internet.
+
 Debugging options:
     --breakpoint=...
         This option sets a primitive breakpoint at a provided memory address.
         The target instruction will be overwritten shortly after the binary is
-        loaded, and executing the instruction will cause touchHLE to panic.
+        loaded. When the instruction is executed, touchHLE will print the
+        registers and a stack trace, then continue execution.
 
         The address is hexadecimal and can have an optional '0x' prefix.
         If the target instruction is a Thumb instruction, either the lowest bit
@@ -121,16 +155,121 @@ Debugging options:
         e.g. 'T0xF00' or 'TF00'.
 
         To set multiple breakpoints, use several '--breakpoint=' arguments.
+
+    --no-crash-report
+        Don't save a crash report file if touchHLE crashes. By default, the
+        information printed when touchHLE crashes (register state, stack trace,
+        list of loaded binaries, etc) is also saved to the file
+        touchHLE_crash_report.txt in the current directory, so that it can be
+        attached to bug reports. The report contains nothing that isn't also
+        printed to the console.
+
+    --trace-instructions
+        Print every instruction executed by the app, together with any changes
+        to the registers. This is extremely slow and produces a huge amount of
+        output, so it's only useful for diagnosing very specific problems.
+
+    --headless
+        Run without showing a window. The app still renders as usual, so this
+        is mostly useful together with '--capture-frame=', e.g. for automated
+        testing. On a machine without a display, a virtual one (e.g. Xvfb) is
+        still required.
+
+    --capture-frame=...
+        Save a frame presented by the app as a PNG file. The value is the
+        frame number (counting from 1) and the path to save to, separated by
+        a colon, e.g. '--capture-frame=120:frame.png'. Both OpenGL ES frames
+        and frames of the app's UI count. With '--headless', touchHLE quits
+        after capturing the frame.
+
+    --screenshot-dir=...
+        Set the directory where screenshots are saved. Pressing F12 saves a
+        screenshot of the next frame presented by the app. By default,
+        screenshots are saved in the 'Screenshots' directory in the app's
+        sandbox directory (e.g. 'touchHLE_sandbox/com.example.app/').
+
+    --screenshot-unscaled
+        Save screenshots at the app's own resolution rather than the window's,
+        undoing the effect of '--scale-hack='. This shows what the app itself
+        rendered, which can be useful for bug reports.
+
+    --rng-seed=...
+        Seed the random number generator behind arc4random() and
+        arc4random_uniform() with a fixed number, so that the app sees the
+        same random numbers every time. Combined with the same input, this
+        makes runs reproducible, which helps with investigating bugs.
+
+        The seed is a decimal number between 0 and 4294967295. Without this
+        option, a different seed is chosen each time. This option doesn't
+        affect rand() and random(), which always produce the same numbers
+        unless the app seeds them itself.
+
+    --record-input=...
+        Record all touch input, accelerometer input and app lifecycle events
+        (e.g. focus changes) to the file at the provided path.
+
+    --replay-input=...
+        Replay the input recorded by '--record-input=' from the file at the
+        provided path. Live input is ignored until the replay has finished,
+        except for quitting.
+
+        Input is replayed with the same timing relative to the app's event
+        handling, rather than real time. Use the same '--rng-seed=' that the
+        recording was made with to reproduce a run as closely as possible.
 ";
 
+/// Which OpenGL ES calls the scale hack is applied to, see `--scale-hack-mode`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ScaleHackMode {
+    Full,
+    RenderbufferOnly,
+    Off,
+}
+
 pub struct Options {
     scale_hack: std::num::NonZeroU32,
+    scale_hack_mode: ScaleHackMode,
     deadzone: f32,
     x_tilt_range: f32,
     y_tilt_range: f32,
     x_tilt_offset: f32,
     y_tilt_offset: f32,
+    network_access: bool,
     breakpoints: Vec<u32>,
+    trace_instructions: bool,
+    headless: bool,
+    capture_frame: Option<(u64, PathBuf)>,
+    screenshot_dir: Option<PathBuf>,
+    screenshot_unscaled: bool,
+    rng_seed: Option<u32>,
+    crash_report: bool,
+    record_input: Option<PathBuf>,
+    replay_input: Option<PathBuf>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            scale_hack: std::num::NonZeroU32::new(1).unwrap(),
+            scale_hack_mode: ScaleHackMode::Full,
+            deadzone: 0.1,
+            x_tilt_range: 60.0,
+            y_tilt_range: 60.0,
+            x_tilt_offset: 0.0,
+            y_tilt_offset: 0.0,
+            network_access: false,
+            breakpoints: Vec::new(),
+            trace_instructions: false,
+            headless: false,
+            capture_frame: None,
+            screenshot_dir: None,
+            screenshot_unscaled: false,
+            rng_seed: None,
+            crash_report: true,
+            record_input: None,
+            replay_input: None,
+        }
+    }
 }
 
 fn main() -> Result<(), String> {
@@ -150,15 +289,7 @@ fn main() -> Result<(), String> {
     let mut args = std::env::args();
     let _ = args.next().unwrap(); // skip argv[0]
 
-    let mut options = Options {
-        scale_hack: std::num::NonZeroU32::new(1).unwrap(),
-        deadzone: 0.1,
-        x_tilt_range: 60.0,
-        y_tilt_range: 60.0,
-        x_tilt_offset: 0.0,
-        y_tilt_offset: 0.0,
-        breakpoints: Vec::new(),
-    };
+    let mut options = Options::default();
 
     let mut bundle_path: Option<PathBuf> = None;
     for arg in args {
@@ -174,6 +305,13 @@ fn main() -> Result<(), String> {
             options.scale_hack = value
                 .parse()
                 .map_err(|_| "Invalid scale hack factor".to_string())?;
+        } else if let Some(value) = arg.strip_prefix("--scale-hack-mode=") {
+            options.scale_hack_mode = match value {
+                "full" => ScaleHackMode::Full,
+                "renderbuffer-only" => ScaleHackMode::RenderbufferOnly,
+                "off" => ScaleHackMode::Off,
+                _ => return Err("Invalid scale hack mode".to_string()),
+            };
         } else if let Some(value) = arg.strip_prefix("--deadzone=") {
             options.deadzone = parse_degrees(value, "deadzone")?;
         } else if let Some(value) = arg.strip_prefix("--x-tilt-range=") {
@@ -184,6 +322,36 @@ fn main() -> Result<(), String> {
             options.x_tilt_offset = parse_degrees(value, "X tilt offset")?;
         } else if let Some(value) = arg.strip_prefix("--y-tilt-offset=") {
             options.y_tilt_offset = parse_degrees(value, "Y tilt offset")?;
+        } else if arg == "--allow-network-access" {
+            options.network_access = true;
+        } else if arg == "--no-crash-report" {
+            options.crash_report = false;
+        } else if arg == "--trace-instructions" {
+            options.trace_instructions = true;
+        } else if arg == "--headless" {
+            options.headless = true;
+        } else if let Some(value) = arg.strip_prefix("--capture-frame=") {
+            let (frame, path) = value
+                .split_once(':')
+                .ok_or_else(|| "Incorrect frame capture syntax".to_string())?;
+            let frame: u64 = frame
+                .parse()
+                .map_err(|_| "Invalid frame number to capture".to_string())?;
+            options.capture_frame = Some((frame, PathBuf::from(path)));
+        } else if let Some(path) = arg.strip_prefix("--screenshot-dir=") {
+            options.screenshot_dir = Some(PathBuf::from(path));
+        } else if arg == "--screenshot-unscaled" {
+            options.screenshot_unscaled = true;
+        } else if let Some(value) = arg.strip_prefix("--rng-seed=") {
+            options.rng_seed = Some(
+                value
+                    .parse()
+                    .map_err(|_| "Invalid random number seed".to_string())?,
+            );
+        } else if let Some(path) = arg.strip_prefix("--record-input=") {
+            options.record_input = Some(PathBuf::from(path));
+        } else if let Some(path) = arg.strip_prefix("--replay-input=") {
+            options.replay_input = Some(PathBuf::from(path));
         } else if let Some(addr) = arg.strip_prefix("--breakpoint=") {
             let is_thumb = addr.starts_with('T');
             let addr = addr.strip_prefix('T').unwrap_or(addr);
@@ -204,6 +372,10 @@ fn main() -> Result<(), String> {
         return Err("Path to bundle must be specified".to_string());
     };
 
+    if options.record_input.is_some() && options.replay_input.is_some() {
+        return Err("Input can't be recorded and replayed at the same time".to_string());
+    }
+
     // When PowerShell does tab-completion on a directory, for some reason it
     // expands it to `'..\My Bundle.app\'` and that trailing \ seems to
     // get interpreted as escaping a double quotation mark?
@@ -220,6 +392,17 @@ fn main() -> Result<(), String> {
 /// Index into the [Vec] of threads. Thread 0 is always the main thread.
 type ThreadID = usize;
 
+/// Reason for a thread not being able to execute right now.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum ThreadBlock {
+    NotBlocked,
+    /// The thread is sleeping until the specified time.
+    Sleeping(std::time::Instant),
+    /// The thread is waiting for some host code to call
+    /// [Environment::unblock_thread].
+    Waiting,
+}
+
 /// Bookkeeping for a thread.
 struct Thread {
     /// Once a thread finishes, this is set to false.
@@ -244,6 +427,9 @@ struct Thread {
     /// frame" of the thread is a host function, not whether there are any host
     /// functions at all.
     in_host_function: bool,
+    /// Whether the thread is blocked (e.g. sleeping). A blocked thread will
+    /// not be scheduled by [Environment::run_inner] until it is unblocked.
+    blocked_by: ThreadBlock,
     /// Context object containing the CPU state for this thread.
     ///
     /// There should always be `(threads.len() - 1)` contexts in existence.
@@ -254,8 +440,28 @@ struct Thread {
     /// Address range of this thread's stack, used to check if addresses are in
     /// range while producing a stack trace.
     stack: Option<std::ops::RangeInclusive<u32>>,
+    /// This thread's `errno` value in guest memory, if it has been used yet.
+    /// See [libc::errno].
+    errno: Option<mem::MutPtr<i32>>,
+    /// If set, the thread should exit with this value the next time it would
+    /// run. See [Environment::request_thread_exit].
+    exit_requested: Option<mem::MutVoidPtr>,
 }
 
+impl Thread {
+    /// See [Environment::request_thread_exit].
+    fn request_exit(&mut self, exit_value: mem::MutVoidPtr) {
+        self.exit_requested = Some(exit_value);
+        if let ThreadBlock::Sleeping(_) = self.blocked_by {
+            self.blocked_by = ThreadBlock::NotBlocked;
+        }
+    }
+}
+
+/// Callback for each instruction executed, see
+/// [Environment::set_trace_callback].
+pub type TraceCallback = Box<dyn FnMut(&Environment, &cpu::TraceEntry)>;
+
 /// The struct containing the entire emulator state.
 pub struct Environment {
     /// Reference point for various timing functions.
@@ -275,6 +481,11 @@ pub struct Environment {
     libc_state: libc::State,
     framework_state: frameworks::State,
     options: Options,
+    /// Set if `--record-input=` or `--replay-input=` was used.
+    input_recording: Option<input_recording::InputRecording>,
+    /// If this is set, the CPU is single-stepped and this is called after
+    /// every instruction. This is slow, so it's [None] normally.
+    trace_callback: Option<TraceCallback>,
 }
 
 impl Environment {
@@ -300,10 +511,15 @@ impl Environment {
             .ok()
             .and_then(|bytes| image::Image::from_bytes(&bytes).ok());
 
+        let screenshot_dir = options.screenshot_dir.clone().unwrap_or_else(|| {
+            fs::sandbox_host_path(bundle.bundle_identifier()).join("Screenshots")
+        });
+
         let window = window::Window::new(
             &format!("{} (touchHLE {})", bundle.display_name(), VERSION),
             icon,
             launch_image,
+            screenshot_dir,
             &options,
         );
 
@@ -357,14 +573,25 @@ impl Environment {
             dyld.set_breakpoint(&mut mem, breakpoint);
         }
 
+        let input_recording = if let Some(path) = &options.record_input {
+            Some(input_recording::InputRecording::record_to(path)?)
+        } else if let Some(path) = &options.replay_input {
+            Some(input_recording::InputRecording::replay_from(path)?)
+        } else {
+            None
+        };
+
         let cpu = cpu::Cpu::new();
 
         let main_thread = Thread {
             active: true,
             in_start_routine: false, // main thread never terminates
             in_host_function: false,
+            blocked_by: ThreadBlock::NotBlocked,
             context: None,
             stack: Some(mem::Mem::MAIN_THREAD_STACK_LOW_END..=0u32.wrapping_sub(1)),
+            errno: None,
+            exit_requested: None,
         };
 
         let mut env = Environment {
@@ -382,8 +609,16 @@ impl Environment {
             libc_state: Default::default(),
             framework_state: Default::default(),
             options,
+            input_recording,
+            trace_callback: None,
         };
 
+        if env.options.trace_instructions {
+            env.set_trace_callback(Some(Box::new(|env, entry| {
+                eprintln!("[thread {}] {}", env.current_thread, entry);
+            })));
+        }
+
         dyld::Dyld::do_late_linking(&mut env);
 
         {
@@ -400,54 +635,158 @@ impl Environment {
 
         env.cpu.set_cpsr(cpu::Cpu::CPSR_USER_MODE);
 
-        // FIXME: call library static initializers too
-        if let Some(mod_init_func) = env.bins[0].get_section("__mod_init_func") {
-            log_dbg!("Calling static initializers for {:?}", env.bins[0].name);
-            assert!(mod_init_func.size % 4 == 0);
-            let base: mem::ConstPtr<abi::GuestFunction> = mem::Ptr::from_bits(mod_init_func.addr);
-            let count = mod_init_func.size / 4;
-            for i in 0..count {
-                let func = env.mem.read(base + i);
-                func.call(&mut env);
-            }
-            log_dbg!("Static initialization done");
-        }
+        dyld::Dyld::run_initializers(&mut env);
 
         env.cpu.branch(entry_point_addr);
 
         Ok(env)
     }
 
+    /// Describe a code address for debugging purposes, e.g. `0x1234 (_foo+0x10
+    /// in Foo)`, using the symbols of the loaded binaries.
+    fn describe_code_addr(&self, addr: u32) -> String {
+        let addr_without_thumb_bit = addr & !1;
+        for bin in &self.bins {
+            if let Some((symbol, offset)) = bin.symbolicate(addr_without_thumb_bit) {
+                return format!("{:#x} ({}+{:#x} in {})", addr, symbol, offset, bin.name);
+            }
+        }
+        format!("{:#x}", addr)
+    }
+
     fn stack_trace(&self) {
+        eprint!("{}", self.format_stack_trace());
+    }
+
+    fn format_stack_trace(&self) -> String {
+        use std::fmt::Write;
+
+        let mut out = String::new();
         let stack_range = self.threads[self.current_thread].stack.clone().unwrap();
-        eprintln!(
-            " 0. {:#x} (PC)",
-            self.cpu.pc_with_thumb_bit().addr_with_thumb_bit()
-        );
+        writeln!(
+            out,
+            " 0. {} (PC)",
+            self.describe_code_addr(self.cpu.pc_with_thumb_bit().addr_with_thumb_bit())
+        )
+        .unwrap();
         let regs = self.cpu.regs();
         let mut lr = regs[cpu::Cpu::LR];
         let return_to_host_routine_addr = self.dyld.return_to_host_routine().addr_with_thumb_bit();
         if lr == return_to_host_routine_addr {
-            eprintln!(" 1. [host function] (LR)");
+            writeln!(out, " 1. [host function] (LR)").unwrap();
         } else {
-            eprintln!(" 1. {:#x} (LR)", lr);
+            writeln!(out, " 1. {} (LR)", self.describe_code_addr(lr)).unwrap();
         }
         let mut i = 2;
         let mut fp: mem::ConstPtr<u8> = mem::Ptr::from_bits(regs[abi::FRAME_POINTER]);
         loop {
             if !stack_range.contains(&fp.to_bits()) {
-                eprintln!("Next FP ({:?}) is outside the stack.", fp);
+                writeln!(out, "Next FP ({:?}) is outside the stack.", fp).unwrap();
                 break;
             }
             lr = self.mem.read((fp + 4).cast());
             fp = self.mem.read(fp.cast());
             if lr == return_to_host_routine_addr {
-                eprintln!("{:2}. [host function]", i);
+                writeln!(out, "{:2}. [host function]", i).unwrap();
             } else {
-                eprintln!("{:2}. {:#x}", i, lr);
+                writeln!(out, "{:2}. {}", i, self.describe_code_addr(lr)).unwrap();
             }
             i += 1;
         }
+        out
+    }
+
+    /// Produce the report printed when the emulator panics, describing the
+    /// state of the app at the time. See `--no-crash-report`.
+    fn crash_report(&self, panic_message: &str) -> String {
+        use std::fmt::Write;
+
+        let mut out = String::new();
+        writeln!(out, "touchHLE {} crash report", VERSION).unwrap();
+        writeln!(
+            out,
+            "App: {} ({})",
+            self.bundle.display_name(),
+            self.bundle.bundle_identifier()
+        )
+        .unwrap();
+        writeln!(out, "Panic message: {}", panic_message).unwrap();
+
+        writeln!(out, "Register state immediately after panic:").unwrap();
+        out.push_str(&self.cpu.format_regs());
+
+        // Only try to disassemble code that is actually in a binary, since
+        // reading from a bad PC would just cause another panic.
+        let pc = self.cpu.pc_with_thumb_bit();
+        let pc_addr = pc.addr_without_thumb_bit();
+        let pc_in_binary = self.bins.iter().any(|bin| {
+            bin.sections
+                .iter()
+                .any(|section| (section.addr..section.addr + section.size).contains(&pc_addr))
+        });
+        if pc_in_binary {
+            writeln!(
+                out,
+                "Instruction at PC: {}",
+                cpu::Cpu::disassemble(&self.mem, pc)
+            )
+            .unwrap();
+        } else {
+            writeln!(out, "PC is not within a loaded binary.").unwrap();
+        }
+
+        if self.current_thread == 0 {
+            writeln!(out, "Attempting to produce stack trace for main thread:").unwrap();
+        } else {
+            writeln!(
+                out,
+                "Attempting to produce stack trace for thread {}:",
+                self.current_thread
+            )
+            .unwrap();
+        }
+        out.push_str(&self.format_stack_trace());
+
+        writeln!(out, "Loaded binaries:").unwrap();
+        for bin in &self.bins {
+            let start = bin.sections.iter().map(|s| s.addr).min().unwrap_or(0);
+            let end = bin
+                .sections
+                .iter()
+                .map(|s| s.addr + s.size)
+                .max()
+                .unwrap_or(0);
+            writeln!(
+                out,
+                "  {:#010x}-{:#010x} {} (slide {:#x})",
+                start, end, bin.name, bin.load_slide
+            )
+            .unwrap();
+        }
+        out
+    }
+
+    /// Set or clear a callback that will be called after every instruction the
+    /// CPU executes. Execution is much slower while this is set.
+    pub fn set_trace_callback(&mut self, callback: Option<TraceCallback>) {
+        self.trace_callback = callback;
+    }
+
+    /// Execute exactly one instruction on the current thread and describe what
+    /// it did. If there is a trace callback, it gets called too.
+    ///
+    /// This is for debugging purposes. The caller is responsible for handling
+    /// the returned [cpu::CpuState], e.g. an SVC.
+    pub fn step_instruction(&mut self) -> (cpu::CpuState, cpu::TraceEntry) {
+        let (state, entry) = self.cpu.step_traced(&mut self.mem);
+        if let Some(mut callback) = self.trace_callback.take() {
+            callback(self, &entry);
+            // The callback might have replaced itself.
+            if self.trace_callback.is_none() {
+                self.trace_callback = Some(callback);
+            }
+        }
+        (state, entry)
     }
 
     /// Create a new thread and return its ID. The `start_routine` and
@@ -467,8 +806,11 @@ impl Environment {
             active: true,
             in_start_routine: true,
             in_host_function: false,
+            blocked_by: ThreadBlock::NotBlocked,
             context: Some(cpu::CpuContext::new()),
             stack: Some(stack_alloc.to_bits()..=(stack_high_addr - 1)),
+            errno: None,
+            exit_requested: None,
         });
         let new_thread_id = self.threads.len() - 1;
 
@@ -490,6 +832,81 @@ impl Environment {
         new_thread_id
     }
 
+    /// Put the current thread to sleep for some duration, running other
+    /// threads in the meantime.
+    ///
+    /// This is meant to be called from a host function called by the guest,
+    /// and only takes effect once that host function returns. Calling guest
+    /// code after this has been called is not allowed.
+    pub fn sleep(&mut self, duration: std::time::Duration) {
+        let until = std::time::Instant::now() + duration;
+        let thread = &mut self.threads[self.current_thread];
+        assert!(thread.blocked_by == ThreadBlock::NotBlocked);
+        log_dbg!(
+            "Thread {} is going to sleep for {:?}",
+            self.current_thread,
+            duration
+        );
+        thread.blocked_by = ThreadBlock::Sleeping(until);
+    }
+
+    /// Block the current thread until [Environment::unblock_thread] is called
+    /// for it, running other threads in the meantime.
+    ///
+    /// Like [Environment::sleep], this only takes effect once the current host
+    /// function returns to the guest.
+    pub fn block_current_thread(&mut self) {
+        let thread = &mut self.threads[self.current_thread];
+        assert!(thread.blocked_by == ThreadBlock::NotBlocked);
+        log_dbg!("Thread {} is now blocked", self.current_thread);
+        thread.blocked_by = ThreadBlock::Waiting;
+    }
+
+    /// Unblock a thread blocked with [Environment::block_current_thread].
+    pub fn unblock_thread(&mut self, thread_id: ThreadID) {
+        let thread = &mut self.threads[thread_id];
+        assert!(thread.blocked_by == ThreadBlock::Waiting);
+        log_dbg!("Thread {} is no longer blocked", thread_id);
+        thread.blocked_by = ThreadBlock::NotBlocked;
+    }
+
+    /// Check if a thread is sleeping (see [Environment::sleep]).
+    pub fn is_thread_sleeping(&self, thread_id: ThreadID) -> bool {
+        matches!(self.threads[thread_id].blocked_by, ThreadBlock::Sleeping(_))
+    }
+
+    /// Make a secondary thread exit the next time it would run, as if its
+    /// start routine had returned `exit_value`. If the thread is sleeping, it
+    /// is woken up for this.
+    ///
+    /// This must only be used when the thread is somewhere it can stop without
+    /// leaving things in an inconsistent state, e.g. a pthread cancellation
+    /// point. If this is called for the current thread, it only takes effect
+    /// once the current host function returns to the guest.
+    pub fn request_thread_exit(&mut self, thread_id: ThreadID, exit_value: mem::MutVoidPtr) {
+        let thread = &mut self.threads[thread_id];
+        assert!(thread.in_start_routine); // the main thread can't exit
+        log_dbg!("Thread {} will exit with {:?}", thread_id, exit_value);
+        thread.request_exit(exit_value);
+    }
+
+    /// Mark the current thread as finished and free its stack. The thread must
+    /// not be scheduled afterwards.
+    fn exit_current_thread(&mut self, exit_value: mem::MutVoidPtr) {
+        let thread_id = self.current_thread;
+        assert!(self.threads[thread_id].in_start_routine);
+        // This might run guest code (pthread cleanup handlers), so the thread
+        // must still be active.
+        libc::pthread::thread::thread_exited(self, thread_id, exit_value);
+        frameworks::foundation::ns_thread::thread_exited(self, thread_id);
+        log_dbg!("Thread {} became inactive", thread_id);
+        self.threads[thread_id].active = false;
+        let stack = self.threads[thread_id].stack.take().unwrap();
+        let stack: mem::MutVoidPtr = mem::Ptr::from_bits(*stack.start());
+        log_dbg!("Freeing thread {} stack {:?}", thread_id, stack);
+        self.mem.free(stack);
+    }
+
     /// Run the emulator. This is the main loop and won't return until app exit.
     /// Only `main.rs` should call this.
     fn run(&mut self) {
@@ -497,17 +914,27 @@ impl Environment {
         // the emulator will crash anyway, maybe this is okay.
         let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| self.run_inner(true)));
         if let Err(e) = res {
-            eprintln!("Register state immediately after panic:");
-            self.cpu.dump_regs();
-            if self.current_thread == 0 {
-                eprintln!("Attempting to produce stack trace for main thread:");
+            let panic_message = if let Some(message) = e.downcast_ref::<&str>() {
+                message
+            } else if let Some(message) = e.downcast_ref::<String>() {
+                message.as_str()
             } else {
-                eprintln!(
-                    "Attempting to produce stack trace for thread {}:",
-                    self.current_thread
-                );
+                "(unknown)"
+            };
+            let report = self.crash_report(panic_message);
+            eprint!("{}", report);
+            if self.options.crash_report {
+                match std::fs::write(CRASH_REPORT_PATH, &report) {
+                    Ok(()) => eprintln!(
+                        "This crash report has been saved to {:?}. Please include it if you report this crash.",
+                        CRASH_REPORT_PATH
+                    ),
+                    Err(e) => eprintln!(
+                        "Warning: Could not save crash report to {:?}: {}",
+                        CRASH_REPORT_PATH, e
+                    ),
+                }
             }
-            self.stack_trace();
             std::panic::resume_unwind(e);
         }
     }
@@ -552,86 +979,268 @@ impl Environment {
             // 100,000 ticks is an arbitrary number.
             self.window.poll_for_events(&self.options);
 
-            let mut ticks = 100_000;
-            while ticks > 0 {
-                match self.cpu.run(&mut self.mem, &mut ticks) {
-                    cpu::CpuState::Normal => (),
-                    cpu::CpuState::Svc(svc) => {
-                        // the program counter is pointing at the
-                        // instruction after the SVC, but we want the
-                        // address of the SVC itself
-                        let svc_pc = self.cpu.regs()[cpu::Cpu::PC] - 4;
-                        if svc == dyld::Dyld::SVC_RETURN_TO_HOST {
-                            assert!(
-                                svc_pc
-                                    == self.dyld.return_to_host_routine().addr_without_thumb_bit()
-                            );
-                            assert!(!root);
-                            // FIXME/TODO: How do we handle a return-to-host on
-                            // the wrong thread? Defer it somehow?
-                            if !root && self.current_thread == initial_thread {
-                                // Normal return from host-to-guest call
-                                return;
-                            } else if self.threads[self.current_thread].in_start_routine {
-                                // Secondary thread finished starting
-                                // TODO: Having two meanings for this SVC is
-                                // dangerous, use a different SVC for this case.
-                                log_dbg!(
-                                    "Thread {} finished start routine and became inactive",
-                                    self.current_thread
-                                );
-                                self.threads[self.current_thread].active = false;
-                                let stack = self.threads[self.current_thread].stack.take().unwrap();
-                                let stack: mem::MutVoidPtr = mem::Ptr::from_bits(*stack.start());
-                                log_dbg!(
-                                    "Freeing thread {} stack {:?}",
-                                    self.current_thread,
-                                    stack
-                                );
-                                self.mem.free(stack);
-                                break;
-                            } else {
-                                panic!("Unexpected return-to-host!");
-                            }
+            if self.run_slice(root, initial_thread) {
+                return;
+            }
+
+            // Find next thread to execute.
+            loop {
+                match self.find_next_thread() {
+                    Ok(next) => {
+                        if next != self.current_thread {
+                            self.switch_thread(next);
                         }
+                        break;
+                    }
+                    // Every thread is blocked. If some thread is sleeping, the
+                    // host can sleep too until that thread wakes up.
+                    Err(Some(next_wakeup)) => {
+                        log_dbg!("All threads are blocked, sleeping until {:?}", next_wakeup);
+                        std::thread::sleep(
+                            next_wakeup.saturating_duration_since(std::time::Instant::now()),
+                        );
+                    }
+                    Err(None) => panic!("No thread can run, the app has deadlocked!"),
+                }
+            }
+        }
+    }
 
-                        if let Some(f) = self.dyld.get_svc_handler(
-                            &self.bins,
-                            &mut self.mem,
-                            &mut self.cpu,
-                            svc_pc,
-                            svc,
-                        ) {
-                            let was_in_host_function =
-                                self.threads[self.current_thread].in_host_function;
-                            self.threads[self.current_thread].in_host_function = true;
-                            f.call_from_guest(self);
-                            self.threads[self.current_thread].in_host_function =
-                                was_in_host_function;
+    /// Run the current thread for a while (100,000 ticks, or until it finishes
+    /// or becomes blocked). Returns [true] if the guest returned to the host on
+    /// `initial_thread`, i.e. a host-to-guest call completed.
+    fn run_slice(&mut self, root: bool, initial_thread: ThreadID) -> bool {
+        if let Some(exit_value) = self.threads[self.current_thread].exit_requested.take() {
+            // TODO: Unwind host-to-guest calls on the exiting thread?
+            assert!(
+                root || self.current_thread != initial_thread,
+                "Thread {} can't exit during a host-to-guest call",
+                self.current_thread
+            );
+            self.exit_current_thread(exit_value);
+            return false;
+        }
+
+        let mut ticks = 100_000;
+        while ticks > 0 {
+            let state = if self.trace_callback.is_some() {
+                ticks -= 1;
+                self.step_instruction().0
+            } else {
+                self.cpu.run(&mut self.mem, &mut ticks)
+            };
+            match state {
+                cpu::CpuState::Normal => (),
+                cpu::CpuState::Svc(svc) => {
+                    let svc = if svc == dyld::Dyld::SVC_BREAKPOINT {
+                        match dyld::Dyld::handle_breakpoint(self) {
+                            Some(svc) => svc,
+                            None => continue,
+                        }
+                    } else {
+                        svc
+                    };
+
+                    // the program counter is pointing at the
+                    // instruction after the SVC, but we want the
+                    // address of the SVC itself
+                    let svc_pc = self.cpu.regs()[cpu::Cpu::PC] - 4;
+                    if svc == dyld::Dyld::SVC_RETURN_TO_HOST {
+                        assert!(
+                            svc_pc == self.dyld.return_to_host_routine().addr_without_thumb_bit()
+                        );
+                        // FIXME/TODO: How do we handle a return-to-host on
+                        // the wrong thread? Defer it somehow?
+                        if !root && self.current_thread == initial_thread {
+                            // Normal return from host-to-guest call
+                            return true;
+                        } else if self.threads[self.current_thread].in_start_routine {
+                            // Secondary thread finished starting
+                            // TODO: Having two meanings for this SVC is
+                            // dangerous, use a different SVC for this case.
+                            log_dbg!("Thread {} finished start routine", self.current_thread);
+                            let exit_value = mem::Ptr::from_bits(self.cpu.regs()[0]);
+                            self.exit_current_thread(exit_value);
+                            break;
                         } else {
-                            self.cpu.regs_mut()[cpu::Cpu::PC] = svc_pc;
+                            panic!("Unexpected return-to-host!");
                         }
                     }
+
+                    if let Some(f) = self.dyld.get_svc_handler(
+                        &self.bins,
+                        &mut self.mem,
+                        &mut self.cpu,
+                        svc_pc,
+                        svc,
+                    ) {
+                        let was_in_host_function =
+                            self.threads[self.current_thread].in_host_function;
+                        self.threads[self.current_thread].in_host_function = true;
+                        f.call_from_guest(self);
+                        self.threads[self.current_thread].in_host_function = was_in_host_function;
+                        // The host function might have blocked the thread
+                        // (e.g. put it to sleep) or asked for it to exit, in
+                        // which case another thread should run.
+                        if self.threads[self.current_thread].blocked_by != ThreadBlock::NotBlocked
+                            || self.threads[self.current_thread].exit_requested.is_some()
+                        {
+                            break;
+                        }
+                    } else {
+                        self.cpu.regs_mut()[cpu::Cpu::PC] = svc_pc;
+                    }
                 }
             }
+        }
+        false
+    }
 
-            // Find next thread to execute
-            let mut next = self.current_thread;
-            loop {
-                next = (next + 1) % self.threads.len();
-                // Back where we started: couldn't find a suitable new
-                // thread.
-                if next == self.current_thread {
-                    assert!(self.threads[self.current_thread].active);
-                    break;
-                }
-                if !self.threads[next].active || self.threads[next].in_host_function {
+    /// Find the next thread that can be executed. See [find_next_thread].
+    fn find_next_thread(&mut self) -> Result<ThreadID, Option<std::time::Instant>> {
+        find_next_thread(
+            &mut self.threads,
+            self.current_thread,
+            std::time::Instant::now(),
+        )
+    }
+
+    /// Execute guest code on threads other than the current one until
+    /// `deadline`, or until none of them can run. This is meant for host code
+    /// that is waiting for something to happen, e.g. a run loop, so that other
+    /// threads aren't starved in the meantime.
+    pub fn run_other_threads(&mut self, deadline: std::time::Instant) {
+        let initial_thread = self.current_thread;
+        // The current thread must be excluded from scheduling.
+        assert!(self.threads[initial_thread].in_host_function);
+
+        while std::time::Instant::now() < deadline {
+            let Ok(next) = self.find_next_thread() else {
+                break;
+            };
+            if next != self.current_thread {
+                self.switch_thread(next);
+            }
+            let returned = self.run_slice(/* root: */ false, initial_thread);
+            assert!(!returned);
+        }
+
+        if self.current_thread != initial_thread {
+            self.switch_thread(initial_thread);
+        }
+    }
+}
+
+/// Find the next thread that can be executed, waking up any sleeping threads
+/// whose time has come. The current thread is considered last, so that it only
+/// continues if no other thread can run.
+///
+/// If no thread can run, the error value is the earliest time that a sleeping
+/// thread will wake up, if any.
+fn find_next_thread(
+    threads: &mut [Thread],
+    current_thread: ThreadID,
+    now: std::time::Instant,
+) -> Result<ThreadID, Option<std::time::Instant>> {
+    let mut next = current_thread;
+    let mut next_wakeup: Option<std::time::Instant> = None;
+    for _ in 0..threads.len() {
+        next = (next + 1) % threads.len();
+        let thread = &mut threads[next];
+        if !thread.active || thread.in_host_function {
+            continue;
+        }
+        match thread.blocked_by {
+            ThreadBlock::NotBlocked => (),
+            ThreadBlock::Sleeping(until) => {
+                if until > now {
+                    next_wakeup = Some(next_wakeup.map_or(until, |w| w.min(until)));
                     continue;
                 }
-                // Candidate found, switch to it.
-                self.switch_thread(next);
-                break;
+                log_dbg!("Thread {} woke up", next);
+                thread.blocked_by = ThreadBlock::NotBlocked;
             }
+            ThreadBlock::Waiting => continue,
+        }
+        return Ok(next);
+    }
+    Err(next_wakeup)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    pub(crate) fn thread(blocked_by: ThreadBlock) -> Thread {
+        Thread {
+            active: true,
+            in_start_routine: true,
+            in_host_function: false,
+            blocked_by,
+            context: None,
+            stack: None,
+            errno: None,
+            exit_requested: None,
         }
     }
+
+    #[test]
+    fn threads_are_scheduled_round_robin() {
+        let now = Instant::now();
+        let mut threads = vec![
+            thread(ThreadBlock::NotBlocked),
+            thread(ThreadBlock::NotBlocked),
+            thread(ThreadBlock::NotBlocked),
+        ];
+        assert_eq!(find_next_thread(&mut threads, 0, now), Ok(1));
+        assert_eq!(find_next_thread(&mut threads, 1, now), Ok(2));
+        assert_eq!(find_next_thread(&mut threads, 2, now), Ok(0));
+
+        // The current thread only continues if nothing else can run.
+        threads[1].active = false;
+        threads[2].in_host_function = true;
+        assert_eq!(find_next_thread(&mut threads, 0, now), Ok(0));
+    }
+
+    #[test]
+    fn blocked_threads_are_skipped() {
+        let now = Instant::now();
+        let soon = now + Duration::from_millis(10);
+        let later = now + Duration::from_millis(20);
+        let mut threads = vec![
+            thread(ThreadBlock::Waiting),
+            thread(ThreadBlock::Sleeping(later)),
+            thread(ThreadBlock::Sleeping(soon)),
+        ];
+        // Nothing can run yet: the earliest wakeup is reported.
+        assert_eq!(find_next_thread(&mut threads, 0, now), Err(Some(soon)));
+
+        // Once its time has come, a sleeping thread is woken up.
+        assert_eq!(find_next_thread(&mut threads, 0, soon), Ok(2));
+        assert_eq!(threads[2].blocked_by, ThreadBlock::NotBlocked);
+        assert_eq!(threads[1].blocked_by, ThreadBlock::Sleeping(later));
+
+        // A thread waiting for the host is never woken up by the scheduler.
+        let mut threads = vec![thread(ThreadBlock::Waiting)];
+        assert_eq!(find_next_thread(&mut threads, 0, later), Err(None));
+    }
+
+    #[test]
+    fn sleeping_thread_is_woken_to_exit() {
+        let now = Instant::now();
+        let wakeup = now + Duration::from_secs(60);
+        let mut threads = vec![
+            thread(ThreadBlock::Waiting),
+            thread(ThreadBlock::Sleeping(wakeup)),
+        ];
+        threads[0].in_start_routine = false;
+        assert_eq!(find_next_thread(&mut threads, 0, now), Err(Some(wakeup)));
+
+        // e.g. pthread_cancel() of a thread blocked in sleep()
+        let exit_value: mem::MutVoidPtr = mem::Ptr::from_bits(1);
+        threads[1].request_exit(exit_value);
+        assert_eq!(find_next_thread(&mut threads, 0, now), Ok(1));
+        assert_eq!(threads[1].exit_requested, Some(exit_value));
+    }
 }
@@ -77,10 +77,10 @@ impl<T, const MUT: bool> Ptr<T, MUT> {
         Ptr(0, std::marker::PhantomData)
     }
 
-    pub fn to_bits(self) -> VAddr {
+    pub const fn to_bits(self) -> VAddr {
         self.0
     }
-    pub fn from_bits(bits: VAddr) -> Self {
+    pub const fn from_bits(bits: VAddr) -> Self {
         Ptr(bits, std::marker::PhantomData)
     }
 
@@ -320,6 +320,17 @@ impl Mem {
         self.bytes_at_mut(ptr.cast(), size).as_mut_ptr().cast()
     }
 
+    /// Get the guest address corresponding to a host pointer into guest memory,
+    /// i.e. the inverse of [Self::ptr_at]. This is only useful for interfacing
+    /// with unsafe C-like APIs that give back pointers we passed to them.
+    ///
+    /// Panics if the pointer does not point into guest memory.
+    pub fn guest_ptr_from_host_ptr<T>(&self, host_ptr: *const T) -> ConstPtr<T> {
+        let base = self.bytes as *const u8 as usize;
+        let offset = (host_ptr as usize).checked_sub(base).unwrap();
+        Ptr::from_bits(offset.try_into().unwrap())
+    }
+
     /// Read a value for memory. This is the preferred way to read memory in
     /// most cases.
     pub fn read<T, const MUT: bool>(&self, ptr: Ptr<T, MUT>) -> T
@@ -392,12 +403,56 @@ impl Mem {
         self.bytes_at(ptr, len)
     }
 
+    /// Like [Self::cstr_at], but gives up after examining `max_len` bytes
+    /// without finding the null terminator, in which case [None] is returned.
+    /// Use this when the string might not be properly terminated, e.g. when
+    /// parsing untrusted guest data.
+    pub fn cstr_at_bounded<const MUT: bool>(
+        &self,
+        ptr: Ptr<u8, MUT>,
+        max_len: GuestUSize,
+    ) -> Option<&[u8]> {
+        let len = (0..max_len).find(|&len| self.read(ptr + len) == b'\0')?;
+        Some(self.bytes_at(ptr, len))
+    }
+
     /// Get a C string (null-terminated) as a string slice, panicking if it is
     /// not UTF-8. The null terminator is not included in the slice.
     pub fn cstr_at_utf8<const MUT: bool>(&self, ptr: Ptr<u8, MUT>) -> &str {
         std::str::from_utf8(self.cstr_at(ptr)).unwrap()
     }
 
+    /// Read `len` UTF-16 code units (not bytes). Guest memory is assumed to be
+    /// little-endian, see the module documentation.
+    pub fn utf16_at<const MUT: bool>(&self, ptr: Ptr<u16, MUT>, len: GuestUSize) -> Vec<u16> {
+        let size = len.checked_mul(guest_size_of::<u16>()).unwrap();
+        self.bytes_at(ptr.cast(), size)
+            .chunks_exact(2)
+            .map(|code_unit| u16::from_le_bytes(code_unit.try_into().unwrap()))
+            .collect()
+    }
+
+    /// Read a null-terminated sequence of UTF-16 code units. The null
+    /// terminator is not included in the result.
+    #[allow(dead_code)]
+    pub fn utf16_cstr_at<const MUT: bool>(&self, ptr: Ptr<u16, MUT>) -> Vec<u16> {
+        let mut len = 0;
+        while self.read(ptr + len) != 0 {
+            len += 1;
+        }
+        self.utf16_at(ptr, len)
+    }
+
+    /// Write a sequence of UTF-16 code units. No null terminator is written.
+    pub fn write_utf16(&mut self, ptr: MutPtr<u16>, code_units: &[u16]) {
+        let len: GuestUSize = code_units.len().try_into().unwrap();
+        let size = len.checked_mul(guest_size_of::<u16>()).unwrap();
+        let bytes = self.bytes_at_mut(ptr.cast(), size);
+        for (dest, &code_unit) in bytes.chunks_exact_mut(2).zip(code_units) {
+            dest.copy_from_slice(&code_unit.to_le_bytes());
+        }
+    }
+
     /// Permanently mark a region of address space as being unusable to the
     /// memory allocator.
     pub fn reserve(&mut self, base: VAddr, size: GuestUSize) {
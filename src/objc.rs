@@ -20,8 +20,9 @@
 
 use crate::dyld::{export_c_func, FunctionExports};
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
+mod associated_objects;
 mod classes;
 mod messages;
 mod methods;
@@ -29,15 +30,30 @@ mod objects;
 mod properties;
 mod selectors;
 
+pub use associated_objects::remove_associated_objects;
 pub use classes::{objc_classes, Class, ClassExports, ClassTemplate};
-pub use messages::{autorelease, msg, msg_class, msg_send, release, retain};
+pub use messages::{
+    autorelease, msg, msg_class, msg_send, msg_send_with_guest_args, release, retain,
+};
 pub use methods::{GuestIMP, HostIMP, IMP};
 pub use objects::{id, nil, AnyHostObject, HostObject, TrivialHostObject};
 pub use selectors::{selector, SEL};
 
-use classes::{ClassHostObject, UnimplementedClass, CLASS_LISTS};
-use messages::{objc_msgSend, objc_msgSendSuper2, objc_msgSend_stret};
-use methods::method_list_t;
+use associated_objects::{
+    objc_getAssociatedObject, objc_removeAssociatedObjects, objc_setAssociatedObject, Associations,
+};
+use classes::{
+    class_addIvar, objc_allocateClassPair, objc_registerClassPair, ClassHostObject,
+    UnimplementedClass, CLASS_LISTS,
+};
+use messages::{
+    objc_msgSend, objc_msgSendSuper, objc_msgSendSuper2, objc_msgSendSuper2_stret,
+    objc_msgSendSuper_stret, objc_msgSend_stret,
+};
+use methods::{
+    class_addMethod, class_getClassMethod, class_getInstanceMethod, method_exchangeImplementations,
+    method_getImplementation, method_list_t, method_setImplementation, Method,
+};
 use objects::{objc_object, HostObjectEntry};
 use properties::objc_setProperty;
 
@@ -55,6 +71,24 @@ pub struct ObjC {
     ///
     /// Look at the `isa` to get the metaclass for a class.
     classes: HashMap<String, Class>,
+
+    /// Classes created by `objc_allocateClassPair` that haven't been
+    /// registered yet.
+    unregistered_classes: HashSet<Class>,
+
+    /// Classes and selectors of the [Method]s given to the guest, see
+    /// `methods.rs`.
+    method_handles: HashMap<Method, (Class, SEL)>,
+
+    /// Guest functions created for host method implementations, keyed by the
+    /// address of the [HostIMP].
+    host_imp_functions: HashMap<*const (), GuestIMP>,
+
+    /// Objects attached to other objects with `objc_setAssociatedObject`.
+    associations: Associations,
+
+    /// Classes that have been sent `+initialize` (or didn't need it).
+    initialized_classes: HashSet<Class>,
 }
 
 impl ObjC {
@@ -63,6 +97,11 @@ impl ObjC {
             selectors: HashMap::new(),
             objects: HashMap::new(),
             classes: HashMap::new(),
+            unregistered_classes: HashSet::new(),
+            method_handles: HashMap::new(),
+            host_imp_functions: HashMap::new(),
+            associations: HashMap::new(),
+            initialized_classes: HashSet::new(),
         }
     }
 }
@@ -70,6 +109,21 @@ impl ObjC {
 pub const FUNCTIONS: FunctionExports = &[
     export_c_func!(objc_msgSend(_, _)),
     export_c_func!(objc_msgSend_stret(_, _, _)),
+    export_c_func!(objc_msgSendSuper(_, _)),
+    export_c_func!(objc_msgSendSuper_stret(_, _, _)),
     export_c_func!(objc_msgSendSuper2(_, _)),
+    export_c_func!(objc_msgSendSuper2_stret(_, _, _)),
     export_c_func!(objc_setProperty(_, _, _, _, _, _)),
+    export_c_func!(objc_allocateClassPair(_, _, _)),
+    export_c_func!(objc_registerClassPair(_)),
+    export_c_func!(class_addIvar(_, _, _, _, _)),
+    export_c_func!(class_addMethod(_, _, _, _)),
+    export_c_func!(class_getInstanceMethod(_, _)),
+    export_c_func!(class_getClassMethod(_, _)),
+    export_c_func!(method_getImplementation(_)),
+    export_c_func!(method_setImplementation(_, _)),
+    export_c_func!(method_exchangeImplementations(_, _)),
+    export_c_func!(objc_setAssociatedObject(_, _, _, _)),
+    export_c_func!(objc_getAssociatedObject(_, _)),
+    export_c_func!(objc_removeAssociatedObjects(_)),
 ];
@@ -0,0 +1,206 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! Associated objects (`objc_setAssociatedObject` and friends).
+//!
+//! These let code attach arbitrary objects to other objects, which is often
+//! used by categories to fake instance variables.
+//!
+//! Resources:
+//! - [Apple's documentation of `objc_setAssociatedObject`](https://developer.apple.com/documentation/objectivec/1418509-objc_setassociatedobject)
+//! - [Apple's documentation of `objc_AssociationPolicy`](https://developer.apple.com/documentation/objectivec/objc_associationpolicy)
+
+use super::{id, msg, nil, release, retain, ObjC};
+use crate::mem::ConstVoidPtr;
+use crate::Environment;
+use std::collections::HashMap;
+
+#[allow(non_camel_case_types)]
+type objc_AssociationPolicy = u32;
+
+const OBJC_ASSOCIATION_ASSIGN: objc_AssociationPolicy = 0;
+const OBJC_ASSOCIATION_RETAIN_NONATOMIC: objc_AssociationPolicy = 1;
+const OBJC_ASSOCIATION_COPY_NONATOMIC: objc_AssociationPolicy = 3;
+const OBJC_ASSOCIATION_RETAIN: objc_AssociationPolicy = 0o1401;
+const OBJC_ASSOCIATION_COPY: objc_AssociationPolicy = 0o1403;
+
+/// How the value of an association is kept alive. Whether access is atomic
+/// doesn't matter for us, because host code can't be interrupted by other
+/// guest threads.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Ownership {
+    Assign,
+    Retain,
+    Copy,
+}
+
+fn ownership_from_policy(policy: objc_AssociationPolicy) -> Ownership {
+    match policy {
+        OBJC_ASSOCIATION_ASSIGN => Ownership::Assign,
+        OBJC_ASSOCIATION_RETAIN_NONATOMIC | OBJC_ASSOCIATION_RETAIN => Ownership::Retain,
+        OBJC_ASSOCIATION_COPY_NONATOMIC | OBJC_ASSOCIATION_COPY => Ownership::Copy,
+        _ => panic!("Unknown association policy {:#o}", policy),
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(super) struct Association {
+    value: id,
+    /// Whether the association holds a reference to the value that it must
+    /// release.
+    owned: bool,
+}
+
+/// Associations of each object, keyed by the key pointer.
+pub(super) type Associations = HashMap<id, HashMap<ConstVoidPtr, Association>>;
+
+impl ObjC {
+    /// Replace or remove an association, returning the previous one.
+    fn set_association(
+        &mut self,
+        object: id,
+        key: ConstVoidPtr,
+        association: Option<Association>,
+    ) -> Option<Association> {
+        if let Some(association) = association {
+            self.associations
+                .entry(object)
+                .or_default()
+                .insert(key, association)
+        } else {
+            let object_associations = self.associations.get_mut(&object)?;
+            let old = object_associations.remove(&key);
+            if object_associations.is_empty() {
+                self.associations.remove(&object);
+            }
+            old
+        }
+    }
+
+    fn get_association(&self, object: id, key: ConstVoidPtr) -> Option<Association> {
+        self.associations.get(&object)?.get(&key).copied()
+    }
+
+    fn take_associations(&mut self, object: id) -> Vec<Association> {
+        self.associations
+            .remove(&object)
+            .map(|associations| associations.into_values().collect())
+            .unwrap_or_default()
+    }
+}
+
+#[allow(non_snake_case)]
+pub(super) fn objc_setAssociatedObject(
+    env: &mut Environment,
+    object: id,
+    key: ConstVoidPtr,
+    value: id,
+    policy: objc_AssociationPolicy,
+) {
+    let association = (value != nil).then(|| match ownership_from_policy(policy) {
+        Ownership::Assign => Association {
+            value,
+            owned: false,
+        },
+        Ownership::Retain => Association {
+            value: retain(env, value),
+            owned: true,
+        },
+        Ownership::Copy => Association {
+            value: msg![env; value copy],
+            owned: true,
+        },
+    });
+    log_dbg!(
+        "objc_setAssociatedObject({:?}, {:?}, {:?}, {:#o}) => {:?}",
+        object,
+        key,
+        value,
+        policy,
+        association
+    );
+    let old = env.objc.set_association(object, key, association);
+    if let Some(Association { value, owned: true }) = old {
+        release(env, value);
+    }
+}
+
+#[allow(non_snake_case)]
+pub(super) fn objc_getAssociatedObject(env: &mut Environment, object: id, key: ConstVoidPtr) -> id {
+    env.objc
+        .get_association(object, key)
+        .map_or(nil, |association| association.value)
+}
+
+#[allow(non_snake_case)]
+pub(super) fn objc_removeAssociatedObjects(env: &mut Environment, object: id) {
+    remove_associated_objects(env, object)
+}
+
+/// Remove all of an object's associations and release their values where
+/// needed. This is called when an object is deallocated.
+pub fn remove_associated_objects(env: &mut Environment, object: id) {
+    for association in env.objc.take_associations(object) {
+        if association.owned {
+            release(env, association.value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mem::Ptr;
+
+    #[test]
+    fn policies() {
+        assert_eq!(ownership_from_policy(0), Ownership::Assign);
+        assert_eq!(ownership_from_policy(1), Ownership::Retain);
+        assert_eq!(ownership_from_policy(3), Ownership::Copy);
+        assert_eq!(ownership_from_policy(0x301), Ownership::Retain);
+        assert_eq!(ownership_from_policy(0x303), Ownership::Copy);
+    }
+
+    #[test]
+    fn association_bookkeeping() {
+        let mut objc = ObjC::new();
+        let object: id = Ptr::from_bits(0x1000);
+        let other_object: id = Ptr::from_bits(0x2000);
+        let [key1, key2]: [ConstVoidPtr; 2] = [0x10, 0x20].map(Ptr::from_bits);
+        let assigned = Association {
+            value: Ptr::from_bits(0x3000),
+            owned: false,
+        };
+        let retained = Association {
+            value: Ptr::from_bits(0x4000),
+            owned: true,
+        };
+
+        assert_eq!(objc.set_association(object, key1, Some(assigned)), None);
+        assert_eq!(objc.set_association(object, key2, Some(assigned)), None);
+        assert_eq!(objc.get_association(object, key1), Some(assigned));
+        assert_eq!(objc.get_association(other_object, key1), None);
+
+        // Replacing or clearing a value hands back the old one, so that it
+        // can be released if it was owned.
+        assert_eq!(
+            objc.set_association(object, key2, Some(retained)),
+            Some(assigned)
+        );
+        assert_eq!(objc.set_association(object, key1, None), Some(assigned));
+        assert_eq!(objc.get_association(object, key1), None);
+        assert_eq!(objc.set_association(object, key1, None), None);
+
+        // Deallocation takes all the remaining associations.
+        assert_eq!(
+            objc.set_association(other_object, key1, Some(retained)),
+            None
+        );
+        assert_eq!(objc.take_associations(object), vec![retained]);
+        assert_eq!(objc.get_association(object, key2), None);
+        assert_eq!(objc.take_associations(object), vec![]);
+        assert_eq!(objc.get_association(other_object, key1), Some(retained));
+    }
+}
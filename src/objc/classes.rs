@@ -13,11 +13,14 @@
 mod class_lists;
 pub(super) use class_lists::CLASS_LISTS;
 
+use super::methods::find_bin_method;
 use super::{
-    id, method_list_t, nil, objc_object, AnyHostObject, HostIMP, HostObject, ObjC, IMP, SEL,
+    id, method_list_t, nil, objc_object, AnyHostObject, GuestIMP, HostIMP, HostObject, ObjC, IMP,
+    SEL,
 };
 use crate::mach_o::MachO;
 use crate::mem::{guest_size_of, ConstPtr, ConstVoidPtr, GuestUSize, Mem, Ptr, SafeRead};
+use crate::Environment;
 use std::collections::HashMap;
 
 /// Generic pointer to an Objective-C class or metaclass.
@@ -38,6 +41,9 @@ pub(super) struct ClassHostObject {
     pub(super) is_metaclass: bool,
     pub(super) superclass: Class,
     pub(super) methods: HashMap<SEL, IMP>,
+    /// Type encoding strings for methods, where known. Only methods from the
+    /// app binary or added at runtime have these.
+    pub(super) method_types: HashMap<SEL, ConstPtr<u8>>,
     /// Offset into the allocated memory for the object where the ivars of
     /// instances of this class or metaclass (respectively: normal objects or
     /// classes) should live. This is always >= the value in the superclass.
@@ -98,7 +104,7 @@ struct category_t {
     name: ConstPtr<u8>,
     class: Class,
     _instance_methods: ConstPtr<method_list_t>,
-    _class_methods: ConstPtr<method_list_t>,
+    class_methods: ConstPtr<method_list_t>,
     _protocols: ConstVoidPtr,     // protocol list (TODO)
     _property_list: ConstVoidPtr, // property list (TODO)
 }
@@ -160,7 +166,7 @@ macro_rules! _objc_method {
             #[allow(unused_variables)]
             $_cmd: $crate::objc::SEL,
             $($arg: $ty,)*
-            $(mut $va_arg: $va_type,)?
+            $(#[allow(unused_mut)] mut $va_arg: $va_type,)?
         | -> $retty {$block}) as fn(
             &mut $crate::Environment,
             $crate::objc::id,
@@ -329,6 +335,7 @@ impl ClassHostObject {
                     (objc.selectors[name], IMP::Host(host_imp))
                 }),
             ),
+            method_types: HashMap::new(),
             // maybe this should be 0 for NSObject? does it matter?
             _instance_start: size,
             instance_size: size,
@@ -354,6 +361,7 @@ impl ClassHostObject {
             is_metaclass,
             superclass,
             methods: HashMap::new(),
+            method_types: HashMap::new(),
             _instance_start: instance_start,
             instance_size,
         };
@@ -490,8 +498,8 @@ impl ObjC {
         }
     }
 
-    /// For use by [crate::dyld]: register all the classes from the application
-    /// binary.
+    /// For use by [crate::dyld]: register all the classes from a binary.
+    /// Superclasses in other binaries must already have been linked.
     pub fn register_bin_classes(&mut self, bin: &MachO, mem: &mut Mem) {
         let Some(list) = bin.get_section("__objc_classlist") else { return; };
 
@@ -518,8 +526,77 @@ impl ObjC {
         }
     }
 
-    /// For use by [crate::dyld]: register all the categories from the
-    /// application binary.
+    /// For use by [crate::dyld]: get the `+load` methods of the classes from a
+    /// binary that have their own, together with the class and the selector to
+    /// call them with, in the order they should be called. As in Apple's
+    /// runtime, superclasses come before their subclasses.
+    /// The classes must already have been registered.
+    ///
+    /// The methods must be called directly rather than via a message send, so
+    /// that `+initialize` isn't sent first.
+    pub fn bin_class_load_methods(&self, bin: &MachO, mem: &Mem) -> Vec<(Class, SEL, GuestIMP)> {
+        let Some(list) = bin.get_section("__objc_classlist") else { return Vec::new(); };
+        // If no binary uses the selector, no class can implement it.
+        let Some(load_sel) = self.lookup_selector("load") else { return Vec::new(); };
+
+        assert!(list.size % 4 == 0);
+        let base: ConstPtr<Class> = Ptr::from_bits(list.addr);
+        let classes: Vec<Class> = (0..(list.size / 4)).map(|i| mem.read(base + i)).collect();
+
+        let mut ordered = Vec::new();
+        for &class in &classes {
+            // Superclasses from other binaries have already been handled.
+            let mut ancestry = Vec::new();
+            let mut current = class;
+            while current != nil && classes.contains(&current) {
+                ancestry.push(current);
+                current = self.borrow::<ClassHostObject>(current).superclass;
+            }
+            for class in ancestry.into_iter().rev() {
+                if ordered.iter().any(|&(ordered, _, _)| ordered == class) {
+                    continue;
+                }
+                let metaclass = Self::read_isa(class, mem);
+                let metaclass_host_object = self.borrow::<ClassHostObject>(metaclass);
+                if let Some(&IMP::Guest(imp)) = metaclass_host_object.methods.get(&load_sel) {
+                    ordered.push((class, load_sel, imp));
+                }
+            }
+        }
+        ordered
+    }
+
+    /// For use by [crate::dyld]: get the `+load` methods of the categories
+    /// from a binary, together with the class each category belongs to and
+    /// the selector to call them with.
+    pub fn bin_category_load_methods(
+        &mut self,
+        bin: &MachO,
+        mem: &Mem,
+    ) -> Vec<(Class, SEL, GuestIMP)> {
+        let Some(list) = bin.get_section("__objc_catlist") else { return Vec::new(); };
+
+        assert!(list.size % 4 == 0);
+        let base: ConstPtr<ConstPtr<category_t>> = Ptr::from_bits(list.addr);
+        let mut methods = Vec::new();
+        for i in 0..(list.size / 4) {
+            let category_t {
+                class,
+                class_methods,
+                ..
+            } = mem.read(mem.read(base + i));
+            if class_methods.is_null() {
+                continue;
+            }
+            if let Some((name, imp)) = find_bin_method(class_methods, "load", mem) {
+                let sel = self.register_bin_selector(name, mem);
+                methods.push((class, sel, imp));
+            }
+        }
+        methods
+    }
+
+    /// For use by [crate::dyld]: register all the categories from a binary.
     pub fn register_bin_categories(&mut self, bin: &MachO, mem: &mut Mem) {
         let Some(list) = bin.get_section("__objc_catlist") else { return; };
 
@@ -543,6 +620,155 @@ impl ObjC {
         }
     }
 
+    /// For use by [super::messages]: mark a class and its superclasses as
+    /// initialized if they weren't already, and return the ones that must now
+    /// be sent `+initialize`, superclasses first. As in Apple's runtime, this
+    /// includes classes that only inherit an `+initialize` method.
+    pub(super) fn classes_to_initialize(&mut self, class: Class, mem: &Mem) -> Vec<Class> {
+        let mut newly_initialized = Vec::new();
+        let mut current = class;
+        while current != nil && !self.initialized_classes.contains(&current) {
+            let superclass = match self
+                .get_host_object(current)
+                .and_then(|host_object| host_object.as_any().downcast_ref())
+            {
+                Some(&ClassHostObject { superclass, .. }) => superclass,
+                None => break,
+            };
+            self.initialized_classes.insert(current);
+            newly_initialized.push(current);
+            current = superclass;
+        }
+        if newly_initialized.is_empty() {
+            return newly_initialized;
+        }
+
+        // If no binary uses the selector, no class can implement it.
+        let Some(initialize_sel) = self.lookup_selector("initialize") else { return Vec::new(); };
+        // Like class_has_method(), but tolerating unimplemented superclasses.
+        let responds = |class: Class| {
+            let mut metaclass = Self::read_isa(class, mem);
+            while let Some(&ClassHostObject {
+                superclass,
+                ref methods,
+                ..
+            }) = self
+                .get_host_object(metaclass)
+                .and_then(|host_object| host_object.as_any().downcast_ref())
+            {
+                if methods.contains_key(&initialize_sel) {
+                    return true;
+                }
+                metaclass = superclass;
+            }
+            false
+        };
+        newly_initialized
+            .into_iter()
+            .rev()
+            .filter(|&class| responds(class))
+            .collect()
+    }
+
+    /// Create a new class and metaclass at runtime (`objc_allocateClassPair`).
+    /// The class can't be found by name until [Self::register_class_pair] is
+    /// called. Returns [nil] if the name is already in use.
+    pub fn allocate_class_pair(&mut self, superclass: Class, name: &str, mem: &mut Mem) -> Class {
+        if self.classes.contains_key(name)
+            || self
+                .unregistered_classes
+                .iter()
+                .any(|&class| self.get_class_name(class) == name)
+        {
+            return nil;
+        }
+
+        let size = guest_size_of::<objc_object>();
+        let (meta_superclass, instance_size, meta_instance_size) = if superclass == nil {
+            (nil, size, size)
+        } else {
+            let meta_superclass = Self::read_isa(superclass, mem);
+            let &ClassHostObject { instance_size, .. } = self.borrow(superclass);
+            let &ClassHostObject {
+                instance_size: meta_instance_size,
+                ..
+            } = self.borrow(meta_superclass);
+            (meta_superclass, instance_size, meta_instance_size)
+        };
+        let new_host_object = |is_metaclass, superclass, instance_size| {
+            Box::new(ClassHostObject {
+                name: name.to_string(),
+                is_metaclass,
+                superclass,
+                methods: HashMap::new(),
+                method_types: HashMap::new(),
+                _instance_start: instance_size,
+                instance_size,
+            })
+        };
+        let class_host_object = new_host_object(false, superclass, instance_size);
+        let metaclass_host_object = new_host_object(true, meta_superclass, meta_instance_size);
+
+        // The isa of every metaclass is the root metaclass.
+        let metaclass = if superclass == nil {
+            let metaclass = mem.alloc_and_write(objc_object { isa: nil });
+            mem.write(metaclass, objc_object { isa: metaclass });
+            self.register_static_object(metaclass, metaclass_host_object);
+            metaclass
+        } else {
+            let root_metaclass = Self::read_isa(meta_superclass, mem);
+            self.alloc_static_object(root_metaclass, metaclass_host_object, mem)
+        };
+        let class = self.alloc_static_object(metaclass, class_host_object, mem);
+
+        self.unregistered_classes.insert(class);
+        class
+    }
+
+    /// Make a class created by [Self::allocate_class_pair] available by name
+    /// (`objc_registerClassPair`).
+    pub fn register_class_pair(&mut self, class: Class) {
+        assert!(
+            self.unregistered_classes.remove(&class),
+            "{:?} is not a class waiting to be registered",
+            class
+        );
+        let name = self.get_class_name(class).to_string();
+        self.classes.insert(name, class);
+    }
+
+    /// Add an instance variable to a class created by
+    /// [Self::allocate_class_pair] that hasn't been registered yet
+    /// (`class_addIvar`). Returns the ivar's offset, or [None] if the class
+    /// can't be changed.
+    pub fn add_ivar(
+        &mut self,
+        class: Class,
+        size: GuestUSize,
+        alignment_log2: u8,
+    ) -> Option<GuestUSize> {
+        if !self.unregistered_classes.contains(&class) {
+            return None;
+        }
+        let host_object = self.borrow_mut::<ClassHostObject>(class);
+        let alignment = 1 << alignment_log2;
+        let offset = (host_object.instance_size + alignment - 1) / alignment * alignment;
+        host_object.instance_size = offset + size;
+        Some(offset)
+    }
+
+    /// Get the name of a class or metaclass.
+    pub fn get_class_name(&self, class: Class) -> &str {
+        let host_object = self.get_host_object(class).unwrap();
+        if let Some(ClassHostObject { name, .. }) = host_object.as_any().downcast_ref() {
+            name
+        } else if let Some(UnimplementedClass { name, .. }) = host_object.as_any().downcast_ref() {
+            name
+        } else {
+            panic!("{:?} is not a class", class);
+        }
+    }
+
     pub fn class_is_subclass_of(&self, class: Class, superclass: Class) -> bool {
         if class == superclass {
             return true;
@@ -563,3 +789,239 @@ impl ObjC {
         }
     }
 }
+
+#[allow(non_snake_case)]
+pub(super) fn objc_allocateClassPair(
+    env: &mut Environment,
+    superclass: Class,
+    name: ConstPtr<u8>,
+    extra_bytes: GuestUSize,
+) -> Class {
+    assert!(extra_bytes == 0); // TODO
+    let name = env.mem.cstr_at_utf8(name).to_string();
+    let class = env
+        .objc
+        .allocate_class_pair(superclass, &name, &mut env.mem);
+    log_dbg!(
+        "objc_allocateClassPair({:?}, {:?}, 0) => {:?}",
+        superclass,
+        name,
+        class
+    );
+    class
+}
+
+#[allow(non_snake_case)]
+pub(super) fn objc_registerClassPair(env: &mut Environment, class: Class) {
+    log_dbg!("objc_registerClassPair({:?})", class);
+    env.objc.register_class_pair(class)
+}
+
+#[allow(non_snake_case)]
+pub(super) fn class_addIvar(
+    env: &mut Environment,
+    class: Class,
+    name: ConstPtr<u8>,
+    size: GuestUSize,
+    alignment_log2: u8,
+    _types: ConstPtr<u8>,
+) -> bool {
+    let offset = env.objc.add_ivar(class, size, alignment_log2);
+    // TODO: keep the names, once there are functions to look ivars up.
+    log_dbg!(
+        "class_addIvar({:?}, {:?}, {}, {}) => offset {:?}",
+        class,
+        env.mem.cstr_at_utf8(name),
+        size,
+        alignment_log2,
+        offset
+    );
+    offset.is_some()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mach_o::Section;
+    use crate::mem::MutPtr;
+    use crate::objc::TrivialHostObject;
+    use std::collections::HashSet;
+
+    fn alloc_words(mem: &mut Mem, words: &[u32]) -> u32 {
+        let ptr: MutPtr<u32> = mem.alloc(words.len() as GuestUSize * 4).cast();
+        for (i, &word) in words.iter().enumerate() {
+            mem.write(ptr + i as GuestUSize, word);
+        }
+        ptr.to_bits()
+    }
+
+    /// Create a method list with a single method.
+    fn method_list(mem: &mut Mem, name: &str, imp: u32) -> u32 {
+        let name = mem.alloc_and_write_cstr(name.as_bytes()).to_bits();
+        let types = mem.alloc_and_write_cstr(b"v8@0:4").to_bits();
+        alloc_words(mem, &[12, 1, name, types, imp])
+    }
+
+    /// Create a class and metaclass as they would appear in a binary. Returns
+    /// the addresses of both.
+    fn bin_class(
+        mem: &mut Mem,
+        name: &str,
+        superclass: Option<(u32, u32)>,
+        class_methods: u32,
+    ) -> (u32, u32) {
+        let name = mem.alloc_and_write_cstr(name.as_bytes()).to_bits();
+        let data = alloc_words(mem, &[0, 4, 4, 0, name, 0, 0, 0, 0, 0]);
+        let metadata = alloc_words(mem, &[0, 4, 4, 0, name, class_methods, 0, 0, 0, 0]);
+        let (superclass, supermetaclass) = superclass.unwrap_or((0, 0));
+        let metaclass = alloc_words(mem, &[0, supermetaclass, 0, 0, metadata]);
+        let class = alloc_words(mem, &[metaclass, superclass, 0, 0, data]);
+        (class, metaclass)
+    }
+
+    fn bin_with_sections(sections: Vec<Section>) -> MachO {
+        MachO {
+            name: "App".to_string(),
+            dynamic_libraries: Vec::new(),
+            sections,
+            exported_symbols: HashMap::new(),
+            defined_symbols: Vec::new(),
+            external_relocations: Vec::new(),
+            local_relocations: Vec::new(),
+            weak_imports: HashSet::new(),
+            load_slide: 0,
+        }
+    }
+
+    #[test]
+    fn initialize_once_superclasses_first() {
+        let mut mem = Mem::new();
+        let mut objc = ObjC::new();
+
+        let initialize = method_list(&mut mem, "initialize", 0x1000);
+        let root = bin_class(&mut mem, "Root", None, initialize);
+        let middle = bin_class(&mut mem, "Middle", Some(root), 0);
+        let leaf = bin_class(&mut mem, "Leaf", Some(middle), 0);
+        let class_list = alloc_words(&mut mem, &[leaf.0, middle.0, root.0]);
+        let bin = bin_with_sections(vec![Section {
+            name: "__objc_classlist".to_string(),
+            addr: class_list,
+            size: 12,
+            dyld_indirect_symbol_info: None,
+        }]);
+        objc.register_bin_classes(&bin, &mut mem);
+
+        let [root, middle, leaf]: [Class; 3] =
+            [root, middle, leaf].map(|(class, _)| Ptr::from_bits(class));
+        // A class that only inherits +initialize still gets sent it.
+        assert_eq!(objc.classes_to_initialize(middle, &mem), vec![root, middle]);
+        assert_eq!(objc.classes_to_initialize(leaf, &mem), vec![leaf]);
+        assert_eq!(objc.classes_to_initialize(leaf, &mem), vec![]);
+        assert_eq!(objc.classes_to_initialize(root, &mem), vec![]);
+    }
+
+    #[test]
+    fn load_before_initialize() {
+        let mut mem = Mem::new();
+        let mut objc = ObjC::new();
+
+        let load = mem.alloc_and_write_cstr(b"load").to_bits();
+        let initialize = mem.alloc_and_write_cstr(b"initialize").to_bits();
+        let types = mem.alloc_and_write_cstr(b"v8@0:4").to_bits();
+        let methods = alloc_words(
+            &mut mem,
+            &[12, 2, initialize, types, 0x1000, load, types, 0x2000],
+        );
+        let (class, _) = bin_class(&mut mem, "SomeClass", None, methods);
+        let class_list = alloc_words(&mut mem, &[class]);
+        let bin = bin_with_sections(vec![Section {
+            name: "__objc_classlist".to_string(),
+            addr: class_list,
+            size: 4,
+            dyld_indirect_symbol_info: None,
+        }]);
+        objc.register_bin_classes(&bin, &mut mem);
+
+        let class: Class = Ptr::from_bits(class);
+        let methods = objc.bin_class_load_methods(&bin, &mem);
+        assert_eq!(methods.len(), 1);
+        let (load_class, load_sel, load_imp) = methods[0];
+        assert_eq!(load_class, class);
+        assert_eq!(load_sel.as_str(&mem), "load");
+        assert_eq!(load_imp.addr_with_thumb_bit(), 0x2000);
+        // Getting the +load method doesn't count as using the class, so it
+        // still gets +initialize when it receives its first message.
+        assert_eq!(objc.classes_to_initialize(class, &mem), vec![class]);
+    }
+
+    #[test]
+    fn category_load_methods() {
+        let mut mem = Mem::new();
+        let mut objc = ObjC::new();
+
+        let class = bin_class(&mut mem, "SomeClass", None, 0).0;
+        let name = mem.alloc_and_write_cstr(b"Loading").to_bits();
+        let load = method_list(&mut mem, "load", 0x2001);
+        let other = method_list(&mut mem, "other", 0x3000);
+        // category_t: name, class, instance methods, class methods, protocols,
+        // properties.
+        let category = alloc_words(&mut mem, &[name, class, 0, load, 0, 0]);
+        let other_category = alloc_words(&mut mem, &[name, class, load, other, 0, 0]);
+        let category_list = alloc_words(&mut mem, &[category, other_category]);
+        let bin = bin_with_sections(vec![Section {
+            name: "__objc_catlist".to_string(),
+            addr: category_list,
+            size: 8,
+            dyld_indirect_symbol_info: None,
+        }]);
+
+        let methods = objc.bin_category_load_methods(&bin, &mem);
+        assert_eq!(methods.len(), 1);
+        let (load_class, load_sel, load_imp) = methods[0];
+        assert_eq!(load_class, Ptr::from_bits(class));
+        assert_eq!(load_sel.as_str(&mem), "load");
+        assert_eq!(objc.lookup_selector("load"), Some(load_sel));
+        assert_eq!(load_imp.addr_with_thumb_bit(), 0x2001);
+    }
+
+    #[test]
+    fn runtime_class_creation() {
+        let mut mem = Mem::new();
+        let mut objc = ObjC::new();
+        objc.register_host_selectors(&mut mem);
+        let ns_object = objc.get_known_class("NSObject", &mut mem);
+
+        let class = objc.allocate_class_pair(ns_object, "RuntimeClass", &mut mem);
+        assert!(class != nil);
+        let metaclass = ObjC::read_isa(class, &mem);
+        assert!(objc.class_is_subclass_of(class, ns_object));
+        assert!(objc.class_is_subclass_of(metaclass, ObjC::read_isa(ns_object, &mem)));
+        // The name is reserved, but can't be looked up until registration.
+        assert!(objc.allocate_class_pair(ns_object, "RuntimeClass", &mut mem) == nil);
+        assert!(objc.get_class("RuntimeClass", false, &mem).is_none());
+
+        assert_eq!(objc.add_ivar(class, 1, 0), Some(4));
+        assert_eq!(objc.add_ivar(class, 4, 2), Some(8));
+        assert_eq!(objc.add_ivar(metaclass, 4, 2), None);
+
+        let sel = objc.lookup_selector("description").unwrap();
+        let imp = GuestIMP::from_addr_with_thumb_bit(0x1001);
+        assert!(objc.add_method(class, sel, IMP::Guest(imp)));
+        assert!(!objc.add_method(class, sel, IMP::Guest(imp)));
+
+        objc.register_class_pair(class);
+        assert_eq!(objc.get_known_class("RuntimeClass", &mut mem), class);
+        assert_eq!(objc.add_ivar(class, 4, 2), None);
+
+        // Instances get the space for the ivars, and the added method.
+        let instance = objc.alloc_object(class, Box::new(TrivialHostObject), &mut mem);
+        assert_eq!(ObjC::read_isa(instance, &mem), class);
+        let &ClassHostObject { instance_size, .. } = objc.borrow(class);
+        assert_eq!(instance_size, 12);
+        assert!(objc.class_has_method(class, sel));
+        let &ClassHostObject { ref methods, .. } = objc.borrow(class);
+        assert!(
+            matches!(methods[&sel], IMP::Guest(found) if found.addr_with_thumb_bit() == 0x1001)
+        );
+    }
+}
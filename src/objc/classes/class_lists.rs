@@ -10,34 +10,52 @@ use crate::frameworks::{core_animation, core_graphics, foundation, opengles, uik
 
 /// All the lists of classes that the runtime should search through.
 pub const CLASS_LISTS: &[super::ClassExports] = &[
+    core_animation::ca_display_link::CLASSES,
     core_animation::ca_eagl_layer::CLASSES,
     core_animation::ca_layer::CLASSES,
+    core_graphics::cg_color::CLASSES,
     core_graphics::cg_color_space::CLASSES,
     core_graphics::cg_context::CLASSES,
+    core_graphics::cg_data_provider::CLASSES,
+    core_graphics::cg_image::CLASSES,
     foundation::ns_array::CLASSES,
     foundation::ns_autorelease_pool::CLASSES,
     foundation::ns_bundle::CLASSES,
     foundation::ns_character_set::CLASSES,
     foundation::ns_coder::CLASSES,
     foundation::ns_data::CLASSES,
+    foundation::ns_date::CLASSES,
     foundation::ns_dictionary::CLASSES,
+    foundation::ns_file_manager::CLASSES,
+    foundation::ns_invocation::CLASSES,
     foundation::ns_keyed_unarchiver::CLASSES,
     foundation::ns_locale::CLASSES,
+    foundation::ns_method_signature::CLASSES,
+    foundation::ns_notification_center::CLASSES,
     foundation::ns_null::CLASSES,
     foundation::ns_object::CLASSES,
     foundation::ns_process_info::CLASSES,
     foundation::ns_run_loop::CLASSES,
+    foundation::ns_scanner::CLASSES,
     foundation::ns_set::CLASSES,
     foundation::ns_string::CLASSES,
     foundation::ns_thread::CLASSES,
     foundation::ns_timer::CLASSES,
     foundation::ns_url::CLASSES,
+    foundation::ns_url_connection::CLASSES,
+    foundation::ns_url_request::CLASSES,
+    foundation::ns_url_response::CLASSES,
+    foundation::ns_user_defaults::CLASSES,
     foundation::ns_value::CLASSES,
     opengles::eagl::CLASSES,
     uikit::ui_accelerometer::CLASSES,
     uikit::ui_application::CLASSES,
+    uikit::ui_color::CLASSES,
     uikit::ui_event::CLASSES,
     uikit::ui_font::CLASSES,
+    uikit::ui_image::CLASSES,
+    uikit::ui_image_view::CLASSES,
+    uikit::ui_label::CLASSES,
     uikit::ui_nib::CLASSES,
     uikit::ui_responder::CLASSES,
     uikit::ui_screen::CLASSES,
@@ -12,8 +12,8 @@
 //! - Peter Steinberger's [Calling Super at Runtime in Swift](https://steipete.com/posts/calling-super-at-runtime/) explains `objc_msgSendSuper2`
 
 use super::{id, nil, Class, ObjC, IMP, SEL};
-use crate::abi::CallFromHost;
-use crate::mem::{ConstPtr, MutVoidPtr, SafeRead};
+use crate::abi::{CallFromHost, GuestArg};
+use crate::mem::{ConstPtr, Mem, MutVoidPtr, SafeRead};
 use crate::Environment;
 
 /// The core implementation of `objc_msgSend`, the main function of Objective-C.
@@ -29,7 +29,12 @@ use crate::Environment;
 /// by the method implementation. We are relying on CallFromGuest not
 /// overwriting it.
 #[allow(non_snake_case)]
-fn objc_msgSend_inner(env: &mut Environment, receiver: id, selector: SEL, super2: Option<Class>) {
+fn objc_msgSend_inner(
+    env: &mut Environment,
+    receiver: id,
+    selector: SEL,
+    super_class: Option<Class>,
+) {
     if receiver == nil {
         // https://developer.apple.com/library/archive/documentation/Cocoa/Conceptual/ObjectiveC/Chapters/ocObjectsClasses.html#//apple_ref/doc/uid/TP30001163-CH11-SW7
         log_dbg!("[nil {}]", selector.as_str(&env.mem));
@@ -37,40 +42,46 @@ fn objc_msgSend_inner(env: &mut Environment, receiver: id, selector: SEL, super2
         return;
     } // TODO: nil handling
 
-    let orig_class = super2.unwrap_or_else(|| ObjC::read_isa(receiver, &env.mem));
+    // For super-calls, the lookup starts at the class given by the caller
+    // rather than the receiver's own class.
+    let orig_class = super_class.unwrap_or_else(|| ObjC::read_isa(receiver, &env.mem));
     assert!(orig_class != nil);
 
-    // Traverse the chain of superclasses to find the method implementation.
+    // A super-call can only happen once the class has been used already.
+    if super_class.is_none() {
+        ensure_initialized(env, receiver, orig_class);
+    }
+
+    let is_super = super_class.is_some();
+    let imp = lookup_method(
+        &env.objc, &env.mem, receiver, orig_class, selector, is_super,
+    );
+    match imp {
+        Some(IMP::Host(host_imp)) => host_imp.call_from_guest(env),
+        Some(IMP::Guest(guest_imp)) => guest_imp.call(env),
+        None => forward_message(env, receiver, orig_class, selector, is_super),
+    }
+}
 
+/// Traverse the chain of superclasses, starting at `orig_class`, to find the
+/// implementation of a method. `receiver` is only used for error messages.
+/// Returns [None] if no class in the chain implements the method.
+fn lookup_method(
+    objc: &ObjC,
+    mem: &Mem,
+    receiver: id,
+    orig_class: Class,
+    selector: SEL,
+    is_super: bool,
+) -> Option<IMP> {
     let mut class = orig_class;
     loop {
         if class == nil {
             assert!(class != orig_class);
-
-            let class_host_object = env.objc.get_host_object(orig_class).unwrap();
-            let &super::ClassHostObject {
-                ref name,
-                is_metaclass,
-                ..
-            } = class_host_object.as_any().downcast_ref().unwrap();
-
-            panic!(
-                "{} {:?} ({}class \"{}\", {:?}){} does not respond to selector \"{}\"!",
-                if is_metaclass { "Class" } else { "Object" },
-                receiver,
-                if is_metaclass { "meta" } else { "" },
-                name,
-                orig_class,
-                if super2.is_some() {
-                    "'s superclass"
-                } else {
-                    ""
-                },
-                selector.as_str(&env.mem),
-            );
+            return None;
         }
 
-        let host_object = env.objc.get_host_object(class).unwrap();
+        let host_object = objc.get_host_object(class).unwrap();
 
         if let Some(&super::ClassHostObject {
             superclass,
@@ -78,19 +89,8 @@ fn objc_msgSend_inner(env: &mut Environment, receiver: id, selector: SEL, super2
             ..
         }) = host_object.as_any().downcast_ref()
         {
-            // Skip method lookup on first iteration if this is the super-call
-            // variant of objc_msgSend (look up the superclass first)
-            if super2.is_some() && class == orig_class {
-                class = superclass;
-                continue;
-            }
-
-            if let Some(imp) = methods.get(&selector) {
-                match imp {
-                    IMP::Host(host_imp) => host_imp.call_from_guest(env),
-                    IMP::Guest(guest_imp) => guest_imp.call(env),
-                }
-                return;
+            if let Some(&imp) = methods.get(&selector) {
+                return Some(imp);
             } else {
                 class = superclass;
             }
@@ -100,11 +100,12 @@ fn objc_msgSend_inner(env: &mut Environment, receiver: id, selector: SEL, super2
         }) = host_object.as_any().downcast_ref()
         {
             panic!(
-                "Class \"{}\" ({:?}) is unimplemented. Call to {} method \"{}\".",
+                "Class \"{}\" ({:?}) is unimplemented. Call to {} method \"{}\".{}",
                 name,
                 class,
                 if is_metaclass { "class" } else { "instance" },
-                selector.as_str(&env.mem),
+                selector.as_str(mem),
+                if is_super { " (super-call)" } else { "" },
             );
         } else {
             panic!(
@@ -115,10 +116,109 @@ fn objc_msgSend_inner(env: &mut Environment, receiver: id, selector: SEL, super2
     }
 }
 
+/// Handle a message that the receiver has no method for. Like Apple's runtime,
+/// we ask the receiver for the method's signature, package the message as an
+/// `NSInvocation` and send it to the receiver with `forwardInvocation:`. This
+/// is what proxy objects rely on.
+fn forward_message(
+    env: &mut Environment,
+    receiver: id,
+    orig_class: Class,
+    selector: SEL,
+    is_super: bool,
+) {
+    // Objects that don't inherit from NSObject can't take part in this.
+    let signature_sel = env
+        .objc
+        .lookup_selector("methodSignatureForSelector:")
+        .unwrap();
+    let isa = ObjC::read_isa(receiver, &env.mem);
+    if !env.objc.class_has_method(isa, signature_sel) {
+        unrecognized_selector(
+            &env.objc, &env.mem, receiver, orig_class, selector, is_super,
+        );
+    }
+
+    // The arguments of the message being sent are still in the registers and
+    // must survive the nested message send. Stack arguments are safe.
+    let saved_args: [u32; 4] = env.cpu.regs()[0..4].try_into().unwrap();
+    let signature: id = msg_send(env, (receiver, signature_sel, selector));
+    env.cpu.regs_mut()[0..4].copy_from_slice(&saved_args);
+
+    if signature == nil {
+        unrecognized_selector(
+            &env.objc, &env.mem, receiver, orig_class, selector, is_super,
+        );
+    }
+    log_dbg!(
+        "Forwarding {:?} message to {:?}",
+        selector.as_str(&env.mem),
+        receiver
+    );
+    crate::frameworks::foundation::ns_invocation::forward_invocation(env, receiver, signature)
+}
+
+fn unrecognized_selector(
+    objc: &ObjC,
+    mem: &Mem,
+    receiver: id,
+    orig_class: Class,
+    selector: SEL,
+    is_super: bool,
+) -> ! {
+    let class_host_object = objc.get_host_object(orig_class).unwrap();
+    let &super::ClassHostObject {
+        ref name,
+        is_metaclass,
+        ..
+    } = class_host_object.as_any().downcast_ref().unwrap();
+
+    panic!(
+        "{} {:?} ({}class \"{}\", {:?}){} does not respond to selector \"{}\"!",
+        if is_metaclass { "Class" } else { "Object" },
+        receiver,
+        if is_metaclass { "meta" } else { "" },
+        name,
+        orig_class,
+        if is_super { " (super-call)" } else { "" },
+        selector.as_str(mem),
+    );
+}
+
+/// Send `+initialize` to the class of a message's receiver (and to its
+/// superclasses) if this is the first message it or its instances received.
+/// Many apps set up singletons and other global state this way.
+fn ensure_initialized(env: &mut Environment, receiver: id, isa: Class) {
+    let host_object = env.objc.get_host_object(isa).unwrap();
+    let class = match host_object.as_any().downcast_ref() {
+        Some(&super::ClassHostObject {
+            is_metaclass: true, ..
+        }) => receiver,
+        Some(_) => isa,
+        // Unimplemented classes are reported by the caller.
+        None => return,
+    };
+
+    let to_initialize = env.objc.classes_to_initialize(class, &env.mem);
+    if to_initialize.is_empty() {
+        return;
+    }
+
+    // The arguments of the message being sent are still in the registers and
+    // must survive the nested message sends. Stack arguments are safe.
+    let saved_args: [u32; 4] = env.cpu.regs()[0..4].try_into().unwrap();
+    let initialize_sel = env.objc.lookup_selector("initialize").unwrap();
+    for class in to_initialize {
+        log_dbg!("Sending +initialize to {:?}", class);
+        let _: () = msg_send(env, (class, initialize_sel));
+    }
+    env.cpu.regs_mut()[0..4].copy_from_slice(&saved_args);
+}
+
 /// Standard variant of `objc_msgSend`. See [objc_msgSend_inner].
 #[allow(non_snake_case)]
 pub(super) fn objc_msgSend(env: &mut Environment, receiver: id, selector: SEL) {
-    objc_msgSend_inner(env, receiver, selector, /* super2: */ None)
+    objc_msgSend_inner(env, receiver, selector, /* super_class: */ None)
 }
 
 /// Variant of `objc_msgSend` for methods that return a struct via a pointer.
@@ -136,20 +236,51 @@ pub(super) fn objc_msgSend_stret(
     receiver: id,
     selector: SEL,
 ) {
-    objc_msgSend_inner(env, receiver, selector, /* super2: */ None)
+    objc_msgSend_inner(env, receiver, selector, /* super_class: */ None)
 }
 
 #[repr(C, packed)]
 pub(super) struct objc_super {
     receiver: id,
-    /// If this is used with `objc_msgSendSuper` (not implemented here, TODO),
-    /// this is a pointer to the superclass to look up the method on.
+    /// If this is used with `objc_msgSendSuper`, this is a pointer to the
+    /// superclass to look up the method on.
     /// If this is used with `objc_msgSendSuper2`, this is a pointer to a class
     /// and the superclass will be looked up from it.
     class: Class,
 }
 unsafe impl SafeRead for objc_super {}
 
+/// Shared part of the `objc_msgSendSuper` variants. `arg_offset` is the
+/// argument slot of the [objc_super] pointer.
+fn msg_send_super_common(
+    env: &mut Environment,
+    arg_offset: usize,
+    super_ptr: ConstPtr<objc_super>,
+    selector: SEL,
+    is_super2: bool,
+) {
+    let objc_super { receiver, class } = env.mem.read(super_ptr);
+
+    let super_class = if is_super2 {
+        let &super::ClassHostObject { superclass, .. } = env.objc.borrow(class);
+        assert!(
+            superclass != nil,
+            "Super-call of \"{}\" in root class {:?}",
+            selector.as_str(&env.mem),
+            class
+        );
+        superclass
+    } else {
+        class
+    };
+
+    // Rewrite the pointer argument to match the normal ABI.
+    let mut arg_offset = arg_offset;
+    crate::abi::write_next_arg(&mut arg_offset, env.cpu.regs_mut(), &mut env.mem, receiver);
+
+    objc_msgSend_inner(env, receiver, selector, Some(super_class))
+}
+
 /// Variant of `objc_msgSend` for supercalls. See [objc_msgSend_inner].
 ///
 /// This variant has a weird ABI because it needs to receive an additional piece
@@ -163,12 +294,54 @@ pub(super) fn objc_msgSendSuper2(
     super_ptr: ConstPtr<objc_super>,
     selector: SEL,
 ) {
-    let objc_super { receiver, class } = env.mem.read(super_ptr);
+    msg_send_super_common(env, 0, super_ptr, selector, /* is_super2: */ true)
+}
 
-    // Rewrite first argument to match the normal ABI.
-    crate::abi::write_next_arg(&mut 0, env.cpu.regs_mut(), &mut env.mem, receiver);
+/// Older variant of [objc_msgSendSuper2], where the [objc_super] contains the
+/// superclass itself rather than the class of the calling method.
+#[allow(non_snake_case)]
+pub(super) fn objc_msgSendSuper(
+    env: &mut Environment,
+    super_ptr: ConstPtr<objc_super>,
+    selector: SEL,
+) {
+    msg_send_super_common(env, 0, super_ptr, selector, /* is_super2: */ false)
+}
+
+/// Variant of [objc_msgSendSuper2] for methods that return a struct via a
+/// pointer. See [objc_msgSend_stret].
+#[allow(non_snake_case)]
+pub(super) fn objc_msgSendSuper2_stret(
+    env: &mut Environment,
+    _stret: MutVoidPtr,
+    super_ptr: ConstPtr<objc_super>,
+    selector: SEL,
+) {
+    msg_send_super_common(env, 1, super_ptr, selector, /* is_super2: */ true)
+}
+
+/// Variant of [objc_msgSendSuper] for methods that return a struct via a
+/// pointer. See [objc_msgSend_stret].
+#[allow(non_snake_case)]
+pub(super) fn objc_msgSendSuper_stret(
+    env: &mut Environment,
+    _stret: MutVoidPtr,
+    super_ptr: ConstPtr<objc_super>,
+    selector: SEL,
+) {
+    msg_send_super_common(env, 1, super_ptr, selector, /* is_super2: */ false)
+}
 
-    objc_msgSend_inner(env, receiver, selector, /* super2: */ Some(class))
+/// Send a message whose arguments have already been written to the registers
+/// and stack, e.g. by `NSInvocation`. If `stret` is set, the first argument is
+/// the pointer for the struct return, as with [objc_msgSend_stret]. The return
+/// value is left in the registers (or the struct return buffer).
+pub fn msg_send_with_guest_args(env: &mut Environment, stret: bool) {
+    let regs = env.cpu.regs();
+    let first_arg = stret as usize;
+    let receiver: id = GuestArg::from_regs(&regs[first_arg..][..1]);
+    let selector: SEL = GuestArg::from_regs(&regs[first_arg + 1..][..1]);
+    objc_msgSend_inner(env, receiver, selector, /* super_class: */ None)
 }
 
 /// Wrapper around [objc_msgSend] which, together with [msg], makes it easy to
@@ -276,3 +449,90 @@ pub fn autorelease(env: &mut Environment, object: id) -> id {
     }
     msg![env; object autorelease]
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::objc::{ClassHostObject, GuestIMP, HostIMP};
+
+    fn host_imp_addr(imp: IMP) -> *const () {
+        match imp {
+            IMP::Host(host_imp) => host_imp as *const dyn HostIMP as *const (),
+            IMP::Guest(_) => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn super_call_lookup() {
+        let mut mem = Mem::new();
+        let mut objc = ObjC::new();
+        objc.register_host_selectors(&mut mem);
+
+        let subclass = objc.get_known_class("NSAutoreleasePool", &mut mem);
+        let superclass = objc.get_known_class("NSObject", &mut mem);
+        let init = objc.lookup_selector("init").unwrap();
+        let retain = objc.lookup_selector("retain").unwrap();
+        let lookup = |class, sel, is_super| {
+            host_imp_addr(lookup_method(&objc, &mem, nil, class, sel, is_super).unwrap())
+        };
+
+        // The subclass overrides -init, and calling super from the override
+        // must reach the superclass's implementation rather than itself.
+        let overridden = lookup(subclass, init, false);
+        let original = lookup(superclass, init, true);
+        assert!(overridden != original);
+        assert_eq!(original, lookup(superclass, init, false));
+
+        // Methods that aren't overridden are found on the superclass anyway.
+        assert_eq!(
+            lookup(subclass, retain, false),
+            lookup(superclass, retain, true)
+        );
+    }
+
+    #[test]
+    fn swizzled_dispatch() {
+        let mut mem = Mem::new();
+        let mut objc = ObjC::new();
+        objc.register_host_selectors(&mut mem);
+
+        let superclass = objc.get_known_class("NSObject", &mut mem);
+        let class = objc.allocate_class_pair(superclass, "Swizzled", &mut mem);
+        let subclass = objc.allocate_class_pair(class, "SwizzledSubclass", &mut mem);
+        let [foo, bar] = ["init", "description"].map(|name| objc.lookup_selector(name).unwrap());
+        let [foo_imp, bar_imp] = [0x1000, 0x2000].map(GuestIMP::from_addr_with_thumb_bit);
+        assert!(objc.add_method(class, foo, IMP::Guest(foo_imp)));
+        assert!(objc.add_method(class, bar, IMP::Guest(bar_imp)));
+
+        let dispatch = |objc: &ObjC, class, sel| {
+            let imp = lookup_method(objc, &mem, nil, class, sel, false).unwrap();
+            match imp {
+                IMP::Guest(imp) => imp.addr_with_thumb_bit(),
+                IMP::Host(_) => unreachable!(),
+            }
+        };
+        assert_eq!(dispatch(&objc, subclass, foo), 0x1000);
+        assert_eq!(dispatch(&objc, subclass, bar), 0x2000);
+
+        // What method_exchangeImplementations() does with the methods found
+        // by class_getInstanceMethod() on the subclass.
+        let exchange = |objc: &mut ObjC| {
+            let foo_owner = objc.find_method_owner(subclass, foo).unwrap();
+            let bar_owner = objc.find_method_owner(subclass, bar).unwrap();
+            assert_eq!([foo_owner, bar_owner], [class, class]);
+            let &ClassHostObject { ref methods, .. } = objc.borrow(class);
+            let (foo_imp, bar_imp) = (methods[&foo], methods[&bar]);
+            objc.set_method_imp(foo_owner, foo, bar_imp);
+            objc.set_method_imp(bar_owner, bar, foo_imp);
+        };
+
+        exchange(&mut objc);
+        assert_eq!(dispatch(&objc, subclass, foo), 0x2000);
+        assert_eq!(dispatch(&objc, subclass, bar), 0x1000);
+        assert_eq!(dispatch(&objc, class, foo), 0x2000);
+
+        exchange(&mut objc);
+        assert_eq!(dispatch(&objc, subclass, foo), 0x1000);
+        assert_eq!(dispatch(&objc, subclass, bar), 0x2000);
+    }
+}
@@ -10,7 +10,7 @@
 
 use super::{id, nil, Class, ClassHostObject, ObjC, SEL};
 use crate::abi::{CallFromGuest, GuestArg, GuestFunction, GuestRet, VAList};
-use crate::mem::{guest_size_of, ConstPtr, GuestUSize, Mem, Ptr, SafeRead};
+use crate::mem::{guest_size_of, ConstPtr, GuestUSize, Mem, MutPtr, Ptr, SafeRead};
 use crate::Environment;
 
 /// Type for any function implementating a method.
@@ -21,6 +21,7 @@ use crate::Environment;
 /// "guest methods" (functions in the guest app). Either way, the function needs
 /// to conform to the same ABI: [id] and [SEL] must be its first two parameters.
 #[allow(clippy::upper_case_acronyms)]
+#[derive(Copy, Clone)]
 pub enum IMP {
     Host(&'static dyn HostIMP),
     Guest(GuestIMP),
@@ -130,13 +131,51 @@ unsafe impl SafeRead for method_list_t {}
 ///
 /// The name, field names and field layout are based on what Ghidra outputs.
 #[repr(C, packed)]
-struct method_t {
+pub(super) struct method_t {
     name: ConstPtr<u8>,
     types: ConstPtr<u8>,
     imp: GuestIMP,
 }
 unsafe impl SafeRead for method_t {}
 
+/// Opaque type for a method of a particular class, as used by runtime
+/// functions like `class_getInstanceMethod`.
+///
+/// The name is standard Objective-C. In Apple's runtime it points to a
+/// [method_t]. We create these on demand and keep the [method_t] up to date,
+/// but the real method table is the one in the [ClassHostObject].
+pub(super) type Method = MutPtr<method_t>;
+
+/// Wrapper that lets a [HostIMP] be called via [crate::dyld] like any other
+/// host function, so it can be given a guest function pointer.
+struct HostIMPFunction(&'static dyn HostIMP);
+impl CallFromGuest for HostIMPFunction {
+    fn call_from_guest(&self, env: &mut Environment) {
+        self.0.call_from_guest(env)
+    }
+}
+
+/// Find a method by name in a method list from an app binary, without
+/// registering any selectors. Returns the method's name string (which can be
+/// used to register the selector) and its implementation.
+pub(super) fn find_bin_method(
+    method_list_ptr: ConstPtr<method_list_t>,
+    name: &str,
+    mem: &Mem,
+) -> Option<(ConstPtr<u8>, GuestIMP)> {
+    let method_list_t { entsize, count } = mem.read(method_list_ptr);
+    assert!(entsize >= guest_size_of::<method_t>());
+
+    let methods_base_ptr: ConstPtr<method_t> = (method_list_ptr + 1).cast();
+
+    (0..count).find_map(|i| {
+        let method_ptr: ConstPtr<method_t> =
+            Ptr::from_bits(methods_base_ptr.to_bits() + i * entsize);
+        let method = mem.read(method_ptr);
+        (mem.cstr_at(method.name) == name.as_bytes()).then_some((method.name, method.imp))
+    })
+}
+
 impl ClassHostObject {
     // See classes.rs for host method parsing
 
@@ -155,22 +194,66 @@ impl ClassHostObject {
             let method_ptr: ConstPtr<method_t> =
                 Ptr::from_bits(methods_base_ptr.to_bits() + i * entsize);
 
-            // TODO: support type strings
-            let method_t {
-                name,
-                types: _,
-                imp,
-            } = mem.read(method_ptr);
+            let method_t { name, types, imp } = mem.read(method_ptr);
 
             // There is no guarantee this string is unique or known.
             // We must deduplicate it like any other.
             let sel = objc.register_bin_selector(name, mem);
             self.methods.insert(sel, IMP::Guest(imp));
+            if !types.is_null() {
+                self.method_types.insert(sel, types);
+            }
         }
     }
 }
 
 impl ObjC {
+    /// Find the class or superclass that has its own implementation of a
+    /// method, i.e. the class whose method table `objc_msgSend` would use.
+    pub fn find_method_owner(&self, class: Class, sel: SEL) -> Option<Class> {
+        let mut class = class;
+        while class != nil {
+            let &ClassHostObject {
+                superclass,
+                ref methods,
+                ..
+            } = self.borrow(class);
+            if methods.contains_key(&sel) {
+                return Some(class);
+            }
+            class = superclass;
+        }
+        None
+    }
+
+    /// Replace a class's own implementation of a method, returning the old
+    /// one. The class must already have an implementation.
+    pub fn set_method_imp(&mut self, class: Class, sel: SEL, imp: IMP) -> IMP {
+        let host_object = self.borrow_mut::<ClassHostObject>(class);
+        host_object.methods.insert(sel, imp).unwrap()
+    }
+
+    /// Add a method to a class or metaclass, unless the class already has its
+    /// own implementation of it, in which case [false] is returned.
+    pub fn add_method(&mut self, class: Class, sel: SEL, imp: IMP) -> bool {
+        let host_object = self.borrow_mut::<ClassHostObject>(class);
+        if host_object.methods.contains_key(&sel) {
+            return false;
+        }
+        host_object.methods.insert(sel, imp);
+        true
+    }
+
+    /// Get the type encoding string of the method that would be used for a
+    /// selector, if it is known. For use by `methodSignatureForSelector:`.
+    pub fn get_method_types(&self, class: Class, sel: SEL) -> Option<ConstPtr<u8>> {
+        let owner = self.find_method_owner(class, sel)?;
+        let &ClassHostObject {
+            ref method_types, ..
+        } = self.borrow(owner);
+        method_types.get(&sel).copied()
+    }
+
     /// For use by NSObject's getter/setter search methods.
     pub fn class_has_method(&self, class: Class, sel: SEL) -> bool {
         let mut class = class;
@@ -189,4 +272,152 @@ impl ObjC {
             }
         }
     }
+    /// Check whether a class overrides a method it inherits from `base_class`,
+    /// i.e. whether it or any superclass below `base_class` has its own
+    /// implementation. For use by frameworks that should avoid doing extra work
+    /// when an app hasn't provided a method, e.g. UIKit with `drawRect:`.
+    pub fn class_overrides_method(&self, class: Class, sel: SEL, base_class: Class) -> bool {
+        let mut class = class;
+        while class != base_class && class != nil {
+            let &ClassHostObject {
+                superclass,
+                ref methods,
+                ..
+            } = self.borrow(class);
+            if methods.contains_key(&sel) {
+                return true;
+            }
+            class = superclass;
+        }
+        false
+    }
+}
+
+#[allow(non_snake_case)]
+pub(super) fn class_addMethod(
+    env: &mut Environment,
+    class: Class,
+    sel: SEL,
+    imp: GuestIMP,
+    types: ConstPtr<u8>,
+) -> bool {
+    log_dbg!(
+        "class_addMethod({:?}, {:?}, {:?}, {:?})",
+        class,
+        sel.as_str(&env.mem),
+        imp,
+        types
+    );
+    if !env.objc.add_method(class, sel, IMP::Guest(imp)) {
+        return false;
+    }
+    if !types.is_null() {
+        let host_object = env.objc.borrow_mut::<ClassHostObject>(class);
+        host_object.method_types.insert(sel, types);
+    }
+    true
+}
+
+/// Get a guest function pointer for an [IMP]. Host implementations get a
+/// guest function that calls them, created once and then reused.
+fn imp_to_guest_function(env: &mut Environment, imp: IMP) -> GuestIMP {
+    let host_imp = match imp {
+        IMP::Guest(guest_imp) => return guest_imp,
+        IMP::Host(host_imp) => host_imp,
+    };
+    let key = host_imp as *const dyn HostIMP as *const ();
+    if let Some(&function) = env.objc.host_imp_functions.get(&key) {
+        return function;
+    }
+    // This is leaked, but there's only one per host method.
+    let wrapper: &'static HostIMPFunction = Box::leak(Box::new(HostIMPFunction(host_imp)));
+    let function = env
+        .dyld
+        .create_guest_function(&mut env.mem, &mut env.cpu, wrapper);
+    env.objc.host_imp_functions.insert(key, function);
+    function
+}
+
+/// Get the [Method] for a class's own implementation of a method.
+fn method_for(env: &mut Environment, class: Class, sel: SEL) -> Method {
+    if let Some((&method, _)) = env
+        .objc
+        .method_handles
+        .iter()
+        .find(|&(_, &owner)| owner == (class, sel))
+    {
+        return method;
+    }
+    let &ClassHostObject {
+        ref methods,
+        ref method_types,
+        ..
+    } = env.objc.borrow(class);
+    let imp = methods[&sel];
+    let types = method_types.get(&sel).copied().unwrap_or(Ptr::null());
+    let imp = imp_to_guest_function(env, imp);
+    let method = env.mem.alloc_and_write(method_t {
+        name: sel.as_ptr(),
+        types,
+        imp,
+    });
+    env.objc.method_handles.insert(method, (class, sel));
+    method
+}
+
+#[allow(non_snake_case)]
+pub(super) fn class_getInstanceMethod(env: &mut Environment, class: Class, sel: SEL) -> Method {
+    if class == nil {
+        return Ptr::null();
+    }
+    match env.objc.find_method_owner(class, sel) {
+        Some(owner) => method_for(env, owner, sel),
+        None => Ptr::null(),
+    }
+}
+
+#[allow(non_snake_case)]
+pub(super) fn class_getClassMethod(env: &mut Environment, class: Class, sel: SEL) -> Method {
+    if class == nil {
+        return Ptr::null();
+    }
+    let metaclass = ObjC::read_isa(class, &env.mem);
+    class_getInstanceMethod(env, metaclass, sel)
+}
+
+#[allow(non_snake_case)]
+pub(super) fn method_getImplementation(env: &mut Environment, method: Method) -> GuestIMP {
+    let (class, sel) = env.objc.method_handles[&method];
+    let &ClassHostObject { ref methods, .. } = env.objc.borrow(class);
+    let imp = methods[&sel];
+    imp_to_guest_function(env, imp)
+}
+
+#[allow(non_snake_case)]
+pub(super) fn method_setImplementation(
+    env: &mut Environment,
+    method: Method,
+    imp: GuestIMP,
+) -> GuestIMP {
+    let old_imp = method_getImplementation(env, method);
+    let (class, sel) = env.objc.method_handles[&method];
+    log_dbg!(
+        "method_setImplementation({:?} {:?}, {:?}) (was {:?})",
+        class,
+        sel.as_str(&env.mem),
+        imp,
+        old_imp
+    );
+    env.objc.set_method_imp(class, sel, IMP::Guest(imp));
+    let imp_ptr: MutPtr<GuestIMP> = Ptr::from_bits(method.to_bits() + 8);
+    env.mem.write(imp_ptr, imp);
+    old_imp
+}
+
+#[allow(non_snake_case)]
+pub(super) fn method_exchangeImplementations(env: &mut Environment, m1: Method, m2: Method) {
+    let imp1 = method_getImplementation(env, m1);
+    let imp2 = method_getImplementation(env, m2);
+    method_setImplementation(env, m1, imp2);
+    method_setImplementation(env, m2, imp1);
 }
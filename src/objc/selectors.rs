@@ -53,6 +53,11 @@ impl SEL {
         // selectors are probably always UTF-8 but this hasn't been verified
         mem.cstr_at_utf8(self.0)
     }
+
+    /// Get the string pointer, for storing the selector in guest structs.
+    pub(super) fn as_ptr(self) -> ConstPtr<u8> {
+        self.0
+    }
 }
 
 impl ObjC {
@@ -96,7 +101,7 @@ impl ObjC {
     }
 
     /// For use by [crate::dyld]: register and deduplicate all the selectors
-    /// referenced in the application binary.
+    /// referenced in a binary.
     pub fn register_bin_selectors(&mut self, bin: &MachO, mem: &mut Mem) {
         let Some(selrefs) = bin.get_section("__objc_selrefs") else { return; };
 
@@ -21,19 +21,24 @@ pub use matrix::Matrix;
 
 use crate::image::Image;
 use crate::Options;
+use sdl2::event::WindowEvent;
+use sdl2::keyboard::Scancode;
 use sdl2::mouse::MouseButton;
 use sdl2::pixels::PixelFormatEnum;
+use sdl2::sensor::{Sensor, SensorData, SensorType};
 use sdl2::surface::Surface;
 use std::collections::VecDeque;
 use std::f32::consts::FRAC_PI_2;
 use std::num::NonZeroU32;
+use std::path::PathBuf;
+use std::time::SystemTime;
 
-#[derive(Copy, Clone, Eq, PartialEq)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum DeviceOrientation {
     Portrait,
     LandscapeLeft,
 }
-fn size_for_orientation(orientation: DeviceOrientation, scale_hack: NonZeroU32) -> (u32, u32) {
+pub fn size_for_orientation(orientation: DeviceOrientation, scale_hack: NonZeroU32) -> (u32, u32) {
     let scale_hack = scale_hack.get();
     match orientation {
         DeviceOrientation::Portrait => (320 * scale_hack, 480 * scale_hack),
@@ -41,12 +46,92 @@ fn size_for_orientation(orientation: DeviceOrientation, scale_hack: NonZeroU32)
     }
 }
 
-#[derive(Debug)]
+/// Identifies the source of a touch, so that several touches can be in
+/// progress at once.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum FingerId {
+    /// The mouse (left button) simulating a touch.
+    Mouse,
+    /// The controller's virtual cursor simulating a touch.
+    VirtualCursor,
+    /// A finger on a real touch device.
+    Finger { touch_id: i64, finger_id: i64 },
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Event {
     Quit,
-    TouchDown((f32, f32)),
-    TouchMove((f32, f32)),
-    TouchUp((f32, f32)),
+    TouchDown(FingerId, (f32, f32)),
+    TouchMove(FingerId, (f32, f32)),
+    TouchUp(FingerId, (f32, f32)),
+    /// The window lost input focus, e.g. because the user switched to another
+    /// window.
+    FocusLost,
+    /// The window regained input focus.
+    FocusGained,
+    /// The window was minimized, or the host OS is about to suspend the app.
+    EnterBackground,
+    /// The window was restored, or the host OS resumed the app.
+    EnterForeground,
+}
+
+/// Key that takes a screenshot, see [Window::request_screenshot].
+const SCREENSHOT_KEY: Scancode = Scancode::F12;
+
+/// SDL synthesizes mouse events from touch events and uses this ID for the
+/// "mouse" in them (`SDL_TOUCH_MOUSEID`). They need to be ignored, since the
+/// touch events are already handled.
+const SDL_TOUCH_MOUSEID: u32 = u32::MAX;
+
+/// Find and open the host device's accelerometer, if it has one (e.g. if it's
+/// a phone or tablet).
+fn open_accelerometer(sensor_ctx: sdl2::SensorSubsystem) -> Option<Sensor> {
+    let count = sensor_ctx.num_sensors().ok()?;
+    (0..count)
+        .filter_map(|idx| sensor_ctx.open(idx).ok())
+        .find(|sensor| sensor.sensor_type() == SensorType::Accelerometer)
+}
+
+/// Convert an SDL accelerometer reading to the iPhone OS convention. The axes
+/// are the same, but SDL uses m/s² and reports gravity as pointing away from
+/// the ground, whereas iPhone OS uses multiples of g and reports gravity as
+/// pointing towards the ground.
+fn acceleration_from_sensor([x, y, z]: [f32; 3]) -> (f32, f32, f32) {
+    const STANDARD_GRAVITY: f32 = 9.80665; // SDL_STANDARD_GRAVITY
+    (
+        -x / STANDARD_GRAVITY,
+        -y / STANDARD_GRAVITY,
+        -z / STANDARD_GRAVITY,
+    )
+}
+
+/// Simulate tilting the device, given analog stick-like input with a range of
+/// [-1, 1] on each axis. The angles are in radians.
+fn simulate_tilt(
+    (x, y): (f32, f32),
+    (neutral_x, neutral_y): (f32, f32),
+    (x_rotation_range, y_rotation_range): (f32, f32),
+) -> (f32, f32, f32) {
+    // If an iPhone is lying flat on its back, level with the ground, and it
+    // is on Earth, the accelerometer will report approximately (0, 0, -1).
+    // The acceleration x and y axes are aligned with the screen's x and y
+    // axes. +x points to the right of the screen, +y points to the top of
+    // the screen, and +z points away from the screen. In the example
+    // scenario, the z axis is parallel to gravity.
+
+    let gravity: [f32; 3] = [0.0, 0.0, -1.0];
+
+    // (x, y) are swapped and inverted because the controller Y axis usually
+    // corresponds to forward/backward movement, but rotating about the Y
+    // axis means tilting the device left/right, and gravity points in the
+    // opposite direction of the device's tilt.
+    let x_rotation = neutral_x - x_rotation_range * y;
+    let y_rotation = neutral_y - y_rotation_range * x;
+
+    let matrix = Matrix::<3>::y_rotation(y_rotation).multiply(&Matrix::<3>::x_rotation(x_rotation));
+    let [x, y, z] = matrix.transform(gravity);
+
+    (x, y, z)
 }
 
 fn surface_from_image(image: &Image) -> Surface {
@@ -82,14 +167,39 @@ pub struct Window {
     viewport_y_offset: u32,
     scale_hack: NonZeroU32,
     splash_image_and_gl_ctx: Option<(Image, GLContext)>,
+    /// Used by [Self::present_image].
+    image_gl_ctx: Option<GLContext>,
     device_orientation: DeviceOrientation,
     app_gl_ctx_no_longer_current: bool,
     controller_ctx: sdl2::GameControllerSubsystem,
     controllers: Vec<sdl2::controller::GameController>,
     virtual_cursor_last: Option<(f32, f32, bool, bool)>,
+    /// The host device's real accelerometer, if any.
+    accelerometer: Option<Sensor>,
+    /// See `--headless`.
+    headless: bool,
+    /// Number of frames presented so far, counting both OpenGL ES frames and
+    /// composited UI frames.
+    frames_presented: u64,
+    /// Frame number and path for `--capture-frame=`, if it hasn't been
+    /// captured yet.
+    frame_capture: Option<(u64, PathBuf)>,
+    /// Directory where screenshots are saved, see [Self::request_screenshot].
+    screenshot_dir: PathBuf,
+    /// See `--screenshot-unscaled`.
+    screenshot_unscaled: bool,
+    screenshot_requested: bool,
+    /// Used to give screenshots taken in the same second different names.
+    screenshot_count: u32,
 }
 impl Window {
-    pub fn new(title: &str, icon: Image, launch_image: Option<Image>, options: &Options) -> Window {
+    pub fn new(
+        title: &str,
+        icon: Image,
+        launch_image: Option<Image>,
+        screenshot_dir: PathBuf,
+        options: &Options,
+    ) -> Window {
         let sdl_ctx = sdl2::init().unwrap();
         let video_ctx = sdl_ctx.video().unwrap();
 
@@ -105,12 +215,14 @@ impl Window {
         let device_orientation = DeviceOrientation::Portrait;
 
         let (width, height) = size_for_orientation(device_orientation, scale_hack);
-        let mut window = video_ctx
-            .window(title, width, height)
-            .position_centered()
-            .opengl()
-            .build()
-            .unwrap();
+        let mut window_builder = video_ctx.window(title, width, height);
+        window_builder.position_centered().opengl();
+        if options.headless {
+            // The window still exists, because SDL needs one to create OpenGL
+            // contexts, but rendering to a hidden window works just as well.
+            window_builder.hidden();
+        }
+        let mut window = window_builder.build().unwrap();
 
         window.set_icon(surface_from_image(&icon));
 
@@ -123,7 +235,7 @@ impl Window {
             // contexts in this window, so let's use something relatively modern
             // and compatible. OpenGL 3.2 is the baseline version of OpenGL
             // available on macOS.
-            let gl_ctx = gl::create_gl_context(&video_ctx, &window, GLVersion::GL32Core);
+            let gl_ctx = gl::create_gl_context(&video_ctx, &window, GLVersion::GL32Core, None);
             Some((launch_image, gl_ctx))
         } else {
             None
@@ -131,6 +243,9 @@ impl Window {
 
         let controller_ctx = sdl_ctx.game_controller().unwrap();
 
+        // Most computers don't have an accelerometer, so this is optional.
+        let accelerometer = sdl_ctx.sensor().ok().and_then(open_accelerometer);
+
         let mut window = Window {
             _sdl_ctx: sdl_ctx,
             video_ctx,
@@ -143,11 +258,20 @@ impl Window {
             viewport_y_offset: 0,
             scale_hack,
             splash_image_and_gl_ctx,
+            image_gl_ctx: None,
             device_orientation: DeviceOrientation::Portrait,
             app_gl_ctx_no_longer_current: false,
             controller_ctx,
             controllers: Vec::new(),
             virtual_cursor_last: None,
+            accelerometer,
+            headless: options.headless,
+            frames_presented: 0,
+            frame_capture: options.capture_frame.clone(),
+            screenshot_dir,
+            screenshot_unscaled: options.screenshot_unscaled,
+            screenshot_requested: false,
+            screenshot_count: 0,
         };
         if window.splash_image_and_gl_ctx.is_some() {
             window.display_splash();
@@ -175,28 +299,95 @@ impl Window {
             (out_x, out_y)
         }
 
+        // Touch device co-ordinates are normalized to the window size.
+        fn transform_finger_coords(window: &Window, (in_x, in_y): (f32, f32)) -> (f32, f32) {
+            let (in_w, in_h) = window.size_in_current_orientation();
+            transform_input_coords(window, (in_x * in_w as f32, in_y * in_h as f32))
+        }
+
         while let Some(event) = self.event_pump.poll_event() {
             use sdl2::event::Event as E;
             self.event_queue.push_back(match event {
-                E::Quit { .. } => Event::Quit,
-                // TODO: support for real touch inputs and multi-touch
+                E::Quit { .. } | E::AppTerminating { .. } => Event::Quit,
+                E::Window { win_event, .. } => match win_event {
+                    WindowEvent::FocusLost => Event::FocusLost,
+                    WindowEvent::FocusGained => Event::FocusGained,
+                    WindowEvent::Minimized => Event::EnterBackground,
+                    WindowEvent::Restored => Event::EnterForeground,
+                    _ => continue,
+                },
+                // These are only sent on mobile host OSes.
+                E::AppWillEnterBackground { .. } => Event::EnterBackground,
+                E::AppDidEnterForeground { .. } => Event::EnterForeground,
                 E::MouseButtonDown {
+                    which,
                     x,
                     y,
                     mouse_btn: MouseButton::Left,
                     ..
-                } => Event::TouchDown(transform_input_coords(self, (x as f32, y as f32))),
+                } if which != SDL_TOUCH_MOUSEID => Event::TouchDown(
+                    FingerId::Mouse,
+                    transform_input_coords(self, (x as f32, y as f32)),
+                ),
                 E::MouseMotion {
-                    x, y, mousestate, ..
-                } if mousestate.left() => {
-                    Event::TouchMove(transform_input_coords(self, (x as f32, y as f32)))
-                }
+                    which,
+                    x,
+                    y,
+                    mousestate,
+                    ..
+                } if which != SDL_TOUCH_MOUSEID && mousestate.left() => Event::TouchMove(
+                    FingerId::Mouse,
+                    transform_input_coords(self, (x as f32, y as f32)),
+                ),
                 E::MouseButtonUp {
+                    which,
                     x,
                     y,
                     mouse_btn: MouseButton::Left,
                     ..
-                } => Event::TouchUp(transform_input_coords(self, (x as f32, y as f32))),
+                } if which != SDL_TOUCH_MOUSEID => Event::TouchUp(
+                    FingerId::Mouse,
+                    transform_input_coords(self, (x as f32, y as f32)),
+                ),
+                E::FingerDown {
+                    touch_id,
+                    finger_id,
+                    x,
+                    y,
+                    ..
+                } => Event::TouchDown(
+                    FingerId::Finger {
+                        touch_id,
+                        finger_id,
+                    },
+                    transform_finger_coords(self, (x, y)),
+                ),
+                E::FingerMotion {
+                    touch_id,
+                    finger_id,
+                    x,
+                    y,
+                    ..
+                } => Event::TouchMove(
+                    FingerId::Finger {
+                        touch_id,
+                        finger_id,
+                    },
+                    transform_finger_coords(self, (x, y)),
+                ),
+                E::FingerUp {
+                    touch_id,
+                    finger_id,
+                    x,
+                    y,
+                    ..
+                } => Event::TouchUp(
+                    FingerId::Finger {
+                        touch_id,
+                        finger_id,
+                    },
+                    transform_finger_coords(self, (x, y)),
+                ),
                 E::ControllerDeviceAdded { which, .. } => {
                     self.controller_added(which);
                     continue;
@@ -207,6 +398,14 @@ impl Window {
                 }
                 // Virtual cursor handling only. Accelerometer handling uses
                 // polling.
+                E::KeyDown {
+                    scancode: Some(SCREENSHOT_KEY),
+                    repeat: false,
+                    ..
+                } => {
+                    self.request_screenshot();
+                    continue;
+                }
                 E::ControllerButtonUp { .. }
                 | E::ControllerButtonDown { .. }
                 | E::ControllerAxisMotion { .. } => {
@@ -215,15 +414,18 @@ impl Window {
                         self.virtual_cursor_last.unwrap_or_default();
                     self.virtual_cursor_last = Some((new_x, new_y, new_pressed, visible));
                     match (old_pressed, new_pressed) {
-                        (false, true) => {
-                            Event::TouchDown(transform_input_coords(self, (new_x, new_y)))
-                        }
-                        (true, false) => {
-                            Event::TouchUp(transform_input_coords(self, (new_x, new_y)))
-                        }
-                        _ if (new_x, new_y) != (old_x, old_y) && new_pressed => {
-                            Event::TouchMove(transform_input_coords(self, (new_x, new_y)))
-                        }
+                        (false, true) => Event::TouchDown(
+                            FingerId::VirtualCursor,
+                            transform_input_coords(self, (new_x, new_y)),
+                        ),
+                        (true, false) => Event::TouchUp(
+                            FingerId::VirtualCursor,
+                            transform_input_coords(self, (new_x, new_y)),
+                        ),
+                        _ if (new_x, new_y) != (old_x, old_y) && new_pressed => Event::TouchMove(
+                            FingerId::VirtualCursor,
+                            transform_input_coords(self, (new_x, new_y)),
+                        ),
                         _ => continue,
                     }
                 }
@@ -257,50 +459,65 @@ impl Window {
     }
     pub fn print_accelerometer_notice(&self) {
         log!("This app uses the accelerometer.");
-        if self.controllers.is_empty() {
-            log!("Connect a controller to get accelerometer simulation.");
+        if let Some(ref accelerometer) = self.accelerometer {
+            log!(
+                "Your device's accelerometer ({}) will be used.",
+                accelerometer.name()
+            );
+        } else if self.controllers.is_empty() {
+            log!("Use the arrow keys or connect a controller to get accelerometer simulation.");
         } else {
-            log!("Your connected controller's left analog stick will be used for accelerometer simulation.");
+            log!("Your connected controller's left analog stick (or the arrow keys) will be used for accelerometer simulation.");
         }
     }
 
-    /// Get the real (TODO) or simulated accelerometer output.
+    /// Get the real or simulated accelerometer output.
     /// See also [crate::frameworks::uikit::ui_accelerometer].
     pub fn get_acceleration(&self, options: &Options) -> (f32, f32, f32) {
+        if let Some(ref accelerometer) = self.accelerometer {
+            match accelerometer.get_data() {
+                Ok(SensorData::Accel(data)) => return acceleration_from_sensor(data),
+                other => log_dbg!("Couldn't read accelerometer: {:?}", other),
+            }
+        }
+
         // Get left analog stick input. The range is [-1, 1] on each axis.
-        let (x, y, _) = self.get_controller_stick(options, true);
+        let (stick_x, stick_y, _) = self.get_controller_stick(options, true);
+        // The arrow keys are treated like a digital stick.
+        let (key_x, key_y) = self.get_arrow_keys();
+        let (x, y) = (stick_x + key_x, stick_y + key_y);
 
         // Correct for window rotation
         let [x, y] = self.input_rotation_matrix().transform([x, y]);
         let (x, y) = (x.clamp(-1.0, 1.0), y.clamp(-1.0, 1.0)); // just in case
 
         // Let's simulate tilting the device based on the analog stick inputs.
-        //
-        // If an iPhone is lying flat on its back, level with the ground, and it
-        // is on Earth, the accelerometer will report approximately (0, 0, -1).
-        // The acceleration x and y axes are aligned with the screen's x and y
-        // axes. +x points to the right of the screen, +y points to the top of
-        // the screen, and +z points away from the screen. In the example
-        // scenario, the z axis is parallel to gravity.
-
-        let gravity: [f32; 3] = [0.0, 0.0, -1.0];
-
-        let neutral_x = options.x_tilt_offset.to_radians();
-        let neutral_y = options.y_tilt_offset.to_radians();
-        let x_rotation_range = options.x_tilt_range.to_radians() / 2.0;
-        let y_rotation_range = options.y_tilt_range.to_radians() / 2.0;
-        // (x, y) are swapped and inverted because the controller Y axis usually
-        // corresponds to forward/backward movement, but rotating about the Y
-        // axis means tilting the device left/right, and gravity points in the
-        // opposite direction of the device's tilt.
-        let x_rotation = neutral_x - x_rotation_range * y;
-        let y_rotation = neutral_y - y_rotation_range * x;
-
-        let matrix =
-            Matrix::<3>::y_rotation(y_rotation).multiply(&Matrix::<3>::x_rotation(x_rotation));
-        let [x, y, z] = matrix.transform(gravity);
-
-        (x, y, z)
+        simulate_tilt(
+            (x, y),
+            (
+                options.x_tilt_offset.to_radians(),
+                options.y_tilt_offset.to_radians(),
+            ),
+            (
+                options.x_tilt_range.to_radians() / 2.0,
+                options.y_tilt_range.to_radians() / 2.0,
+            ),
+        )
+    }
+
+    /// Get the state of the arrow keys as analog stick-like input, with
+    /// up being -1 on the Y axis.
+    fn get_arrow_keys(&self) -> (f32, f32) {
+        let keyboard = self.event_pump.keyboard_state();
+        let axis = |negative, positive| {
+            let negative = keyboard.is_scancode_pressed(negative);
+            let positive = keyboard.is_scancode_pressed(positive);
+            (positive as i32 - negative as i32) as f32
+        };
+        (
+            axis(Scancode::Left, Scancode::Right),
+            axis(Scancode::Up, Scancode::Down),
+        )
     }
 
     /// For use when redrawing the screen: Get the cached on-screen position and
@@ -396,8 +613,16 @@ impl Window {
         (x, y, pressed)
     }
 
-    pub fn create_gl_context(&mut self, version: GLVersion) -> GLContext {
-        gl::create_gl_context(&self.video_ctx, &self.window, version)
+    /// Create a new OpenGL context. This changes the current context, so the
+    /// app's context will need to be restored afterwards. If `share_with` is
+    /// provided, the new context will share objects like textures with it.
+    pub fn create_gl_context(
+        &mut self,
+        version: GLVersion,
+        share_with: Option<&GLContext>,
+    ) -> GLContext {
+        self.app_gl_ctx_no_longer_current = true;
+        gl::create_gl_context(&self.video_ctx, &self.window, version, share_with)
     }
 
     pub fn make_gl_context_current(&mut self, gl_ctx: &GLContext) {
@@ -434,12 +659,118 @@ impl Window {
         // onto image so we can rotate later if necessary
     }
 
+    /// Display and present an image, e.g. one produced by compositing the
+    /// app's UI (as opposed to rendering with OpenGL ES). This replaces the
+    /// splash screen, if any.
+    pub fn present_image(&mut self, image: &Image) {
+        if self.image_gl_ctx.is_none() {
+            // See the comment about the splash screen in Self::new()
+            let gl_ctx = match self.splash_image_and_gl_ctx.take() {
+                Some((_image, gl_ctx)) => gl_ctx,
+                None => {
+                    gl::create_gl_context(&self.video_ctx, &self.window, GLVersion::GL32Core, None)
+                }
+            };
+            self.image_gl_ctx = Some(gl_ctx);
+        }
+
+        let matrix = self.output_rotation_matrix();
+        let viewport_size = self.size_in_current_orientation();
+        let viewport_offset = (0, self.viewport_y_offset());
+
+        self.app_gl_ctx_no_longer_current = true;
+
+        let gl_ctx = self.image_gl_ctx.as_ref().unwrap();
+        gl::make_gl_context_current(&self.video_ctx, &self.window, gl_ctx);
+        unsafe { gl::display_image(image, viewport_offset, viewport_size, &matrix) };
+        self.frame_presented();
+        self.window.gl_swap_window();
+    }
+
     /// Swap front-buffer and back-buffer so the result of OpenGL rendering is
     /// presented.
     pub fn swap_window(&mut self) {
+        self.frame_presented();
         self.window.gl_swap_window();
     }
 
+    /// Count a frame that is about to be presented, and capture it if
+    /// requested with `--capture-frame=`. This must be called before swapping,
+    /// while the frame is still in the back buffer.
+    fn frame_presented(&mut self) {
+        self.frames_presented += 1;
+
+        if std::mem::take(&mut self.screenshot_requested) {
+            self.save_screenshot();
+        }
+
+        let Some((frame, path)) = &self.frame_capture else {
+            return;
+        };
+        if self.frames_presented != *frame {
+            return;
+        }
+
+        let image = self.read_frame();
+        match std::fs::write(path, image.to_png()) {
+            Ok(()) => log!("Captured frame {} to {:?}.", frame, path),
+            Err(e) => log!(
+                "Warning: Could not write frame {} to {:?}: {}",
+                frame,
+                path,
+                e
+            ),
+        }
+        self.frame_capture = None;
+
+        if self.headless {
+            // There's nobody to close the window, and a headless run is
+            // presumably over once it has produced what it was asked for.
+            self.event_queue.push_back(Event::Quit);
+        }
+    }
+
+    /// Read back the frame that is about to be presented. Since the window is
+    /// rotated to match the device orientation (see [Self::rotate_device]),
+    /// this is always upright.
+    fn read_frame(&self) -> Image {
+        let viewport_size = self.size_in_current_orientation();
+        let viewport_offset = (0, self.viewport_y_offset());
+        unsafe { gl::read_pixels(&self.video_ctx, viewport_offset, viewport_size) }
+    }
+
+    /// Take a screenshot of the next frame the app presents, and save it as a
+    /// PNG file in the screenshot directory. The user can also do this by
+    /// pressing [SCREENSHOT_KEY].
+    pub fn request_screenshot(&mut self) {
+        self.screenshot_requested = true;
+    }
+
+    fn save_screenshot(&mut self) {
+        let mut image = self.read_frame();
+        if self.screenshot_unscaled {
+            // Match the resolution the app thinks it is rendering at.
+            image = image.downscaled(self.scale_hack.get());
+        }
+
+        let timestamp = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        self.screenshot_count += 1;
+        let path = self.screenshot_dir.join(format!(
+            "screenshot_{}_{}.png",
+            timestamp, self.screenshot_count
+        ));
+
+        let result = std::fs::create_dir_all(&self.screenshot_dir)
+            .and_then(|()| std::fs::write(&path, image.to_png()));
+        match result {
+            Ok(()) => log!("Saved screenshot to {:?}.", path),
+            Err(e) => log!("Warning: Could not save screenshot to {:?}: {}", path, e),
+        }
+    }
+
     /// Consider the emulated device to be rotated to a particular orientation.
     ///
     /// On a PC or laptop, this will make the window be rotated so the app
@@ -529,6 +860,59 @@ impl Window {
     }
 }
 
-pub fn open_url(url: &str) {
-    let _ = sdl2::url::open_url(url);
+/// Open a URL in the host's default handler (e.g. a web browser), returning
+/// `false` if this failed.
+pub fn open_url(url: &str) -> bool {
+    sdl2::url::open_url(url).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_approx_eq(a: (f32, f32, f32), b: (f32, f32, f32)) {
+        let close = |a: f32, b: f32| (a - b).abs() < 0.001;
+        assert!(
+            close(a.0, b.0) && close(a.1, b.1) && close(a.2, b.2),
+            "{:?} != {:?}",
+            a,
+            b
+        );
+    }
+
+    #[test]
+    fn sensor_acceleration() {
+        // Lying flat on its back
+        assert_approx_eq(
+            acceleration_from_sensor([0.0, 0.0, 9.80665]),
+            (0.0, 0.0, -1.0),
+        );
+        // Held upright in portrait orientation
+        assert_approx_eq(
+            acceleration_from_sensor([0.0, 9.80665, 0.0]),
+            (0.0, -1.0, 0.0),
+        );
+    }
+
+    #[test]
+    fn tilt_simulation() {
+        let range = (45f32.to_radians(), 45f32.to_radians());
+        let no_offset = (0.0, 0.0);
+        let half = std::f32::consts::FRAC_1_SQRT_2;
+        // Neutral: lying flat
+        assert_approx_eq(
+            simulate_tilt((0.0, 0.0), no_offset, range),
+            (0.0, 0.0, -1.0),
+        );
+        // Stick fully right: tilted 45° to the right
+        assert_approx_eq(
+            simulate_tilt((1.0, 0.0), no_offset, range),
+            (half, 0.0, -half),
+        );
+        // Stick fully down: tilted 45° towards the user
+        assert_approx_eq(
+            simulate_tilt((0.0, 1.0), no_offset, range),
+            (0.0, -half, -half),
+        );
+    }
 }
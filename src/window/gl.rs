@@ -30,8 +30,15 @@ pub fn create_gl_context(
     video_ctx: &sdl2::VideoSubsystem,
     window: &sdl2::video::Window,
     version: GLVersion,
+    share_with: Option<&GLContext>,
 ) -> GLContext {
+    // SDL can only share objects with the current context.
+    if let Some(share_with) = share_with {
+        window.gl_make_current(&share_with.gl_ctx).unwrap();
+    }
+
     let attr = video_ctx.gl_attr();
+    attr.set_share_with_current_context(share_with.is_some());
     match version {
         GLVersion::GLES11 => {
             attr.set_context_version(1, 1);
@@ -200,3 +207,42 @@ gl_FragColor = texture2D(tex, texCoord);
 
     assert!(gl::GetError() == 0);
 }
+
+/// Read back part of the current context's framebuffer (from the back buffer,
+/// i.e. before the window is swapped). This looks up `glReadPixels` itself
+/// rather than using one of the bindings, so it works regardless of which
+/// version of OpenGL the context uses.
+pub unsafe fn read_pixels(
+    video_ctx: &sdl2::VideoSubsystem,
+    offset: (u32, u32),
+    size: (u32, u32),
+) -> Image {
+    use gl32core::types::{GLenum, GLint, GLsizei, GLvoid};
+    type ReadPixels =
+        extern "system" fn(GLint, GLint, GLsizei, GLsizei, GLenum, GLenum, *mut GLvoid);
+
+    let read_pixels = video_ctx.gl_get_proc_address("glReadPixels");
+    assert!(!read_pixels.is_null());
+    let read_pixels: ReadPixels = std::mem::transmute(read_pixels);
+
+    let (width, height) = size;
+    let row_size = width as usize * 4;
+    let mut pixels = vec![0u8; row_size * height as usize];
+    // The default pack alignment of 4 is fine for RGBA data.
+    read_pixels(
+        offset.0.try_into().unwrap(),
+        offset.1.try_into().unwrap(),
+        width.try_into().unwrap(),
+        height.try_into().unwrap(),
+        gl32core::RGBA,
+        gl32core::UNSIGNED_BYTE,
+        pixels.as_mut_ptr() as *mut _,
+    );
+
+    // OpenGL's origin is the bottom-left corner, but images are top-down.
+    let mut flipped = Vec::with_capacity(pixels.len());
+    for row in pixels.chunks(row_size).rev() {
+        flipped.extend_from_slice(row);
+    }
+    Image::from_pixels(flipped, size)
+}